@@ -0,0 +1,189 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::history::CommandHistory;
+use crate::policy::PolicyViolation;
+use crate::redact;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalDecision {
+    Approved,
+    Denied,
+}
+
+impl std::fmt::Display for ApprovalDecision {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApprovalDecision::Approved => write!(f, "approved"),
+            ApprovalDecision::Denied => write!(f, "denied"),
+        }
+    }
+}
+
+/// How the human was asked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalChannel {
+    McpElicitation,
+    CliPrompt,
+}
+
+/// One approval decision for a command that matched a deny rule.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApprovalRecord {
+    pub timestamp_ms: u64,
+    pub host: String,
+    pub command: String,
+    pub rule: String,
+    pub decision: ApprovalDecision,
+    pub channel: ApprovalChannel,
+    /// Name given by the approving human, if any.
+    pub approver: Option<String>,
+    /// MCP client name, or the local user in CLI mode.
+    pub client: Option<String>,
+    pub comment: Option<String>,
+}
+
+impl ApprovalRecord {
+    pub fn new(
+        host: &str,
+        violation: &PolicyViolation,
+        decision: ApprovalDecision,
+        channel: ApprovalChannel,
+    ) -> Self {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        Self {
+            timestamp_ms,
+            host: host.to_string(),
+            command: violation.command.clone(),
+            rule: violation.rule.clone(),
+            decision,
+            channel,
+            approver: None,
+            client: None,
+            comment: None,
+        }
+    }
+}
+
+/// Append-only JSON Lines audit trail of approval decisions, and the
+/// history of the commands that ran.
+///
+/// Records are always emitted as `audit` tracing events; with a file they are
+/// also appended there, one JSON object per line.
+#[derive(Default)]
+pub struct AuditLog {
+    file: Option<Mutex<File>>,
+    history: Arc<CommandHistory>,
+}
+
+impl AuditLog {
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+        Ok(Self {
+            file: Some(Mutex::new(file)),
+            history: Arc::default(),
+        })
+    }
+
+    pub fn with_history(mut self, history: CommandHistory) -> Self {
+        self.history = Arc::new(history);
+        self
+    }
+
+    pub fn history(&self) -> &Arc<CommandHistory> {
+        &self.history
+    }
+
+    /// Logs and appends `record`, with secrets in the command masked.
+    pub fn record(&self, record: &ApprovalRecord) {
+        let record = &ApprovalRecord {
+            command: redact::for_logs(&record.command).into_owned(),
+            ..record.clone()
+        };
+        tracing::info!(
+            target: "audit",
+            host = %record.host,
+            rule = %record.rule,
+            decision = %record.decision,
+            approver = record.approver.as_deref().unwrap_or("-"),
+            client = record.client.as_deref().unwrap_or("-"),
+            command = %record.command,
+            "Approval decision"
+        );
+
+        let Some(ref file) = self.file else {
+            return;
+        };
+        let line = match serde_json::to_string(record) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to serialize audit record");
+                return;
+            }
+        };
+        let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+            tracing::error!(error = %e, "Failed to write audit record");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::CommandPolicy;
+
+    #[test]
+    fn test_records_are_appended_as_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let violation = CommandPolicy::new().check("rm -rf /tmp/x").unwrap_err();
+
+        let log = AuditLog::open(&path).unwrap();
+        let mut approved = ApprovalRecord::new(
+            "web-1",
+            &violation,
+            ApprovalDecision::Approved,
+            ApprovalChannel::McpElicitation,
+        );
+        approved.approver = Some("alice".to_string());
+        log.record(&approved);
+        log.record(&ApprovalRecord::new(
+            "web-1",
+            &violation,
+            ApprovalDecision::Denied,
+            ApprovalChannel::CliPrompt,
+        ));
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["decision"], "approved");
+        assert_eq!(lines[0]["approver"], "alice");
+        assert_eq!(lines[0]["rule"], "recursive-rm");
+        assert_eq!(lines[0]["channel"], "mcp_elicitation");
+        assert_eq!(lines[1]["decision"], "denied");
+        assert!(lines[1]["approver"].is_null());
+    }
+}
@@ -1,7 +1,364 @@
 use anyhow::{Context, Result};
-use std::io::{self, BufRead, BufReader, Write};
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Config, Editor, Helper};
+use std::io::{self, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+use crate::audit::{ApprovalChannel, ApprovalDecision, ApprovalRecord, AuditLog};
+use crate::policy::{CommandPolicy, PolicyViolation};
 use crate::ssh::SessionManager;
+use crate::ssh::channel::{ChannelOptions, CommandOutput, SudoError};
+use crate::ssh::config::list_ssh_hosts;
+use crate::ssh::passphrase::ChainPassphraseProvider;
+use crate::terminal::{self, RawTerminal, Resizes};
+
+async fn execute_with_sudo_prompt(
+    manager: &SessionManager,
+    alias: &str,
+    command: &str,
+) -> Result<CommandOutput> {
+    match manager.execute_command(alias, command, None).await {
+        Err(e) if e.downcast_ref::<SudoError>() == Some(&SudoError::PasswordRequired) => {
+            let password = rpassword::prompt_password(format!("[sudo] password on {}: ", alias))?;
+            manager
+                .execute_command(alias, command, Some(&password))
+                .await
+        }
+        other => other,
+    }
+}
+
+/// Words the CLI handles itself rather than sending to the host.
+const KEYWORDS: &[&str] = &[
+    "connect",
+    "disconnect",
+    "exit",
+    "quit",
+    "sessions",
+    "shell",
+    "switch",
+];
+
+type LineEditor = Editor<CliHelper, DefaultHistory>;
+
+/// Tab completion of CLI keywords, host aliases and `@alias` targets.
+#[derive(Default)]
+struct CliHelper {
+    /// Aliases from ~/.ssh/config.
+    config_hosts: Vec<String>,
+    /// Aliases connected in this CLI session.
+    connected: Vec<String>,
+}
+
+impl CliHelper {
+    fn load() -> Self {
+        let config_hosts = match list_ssh_hosts() {
+            Ok(hosts) => hosts.into_iter().map(|h| h.host).collect(),
+            Err(e) => {
+                tracing::debug!(error = %e, "No host aliases for completion");
+                Vec::new()
+            }
+        };
+        Self {
+            config_hosts,
+            connected: Vec::new(),
+        }
+    }
+
+    /// Start of the word ending at `pos` and the candidates for it.
+    fn complete_line(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let before = &line[..pos];
+        let start = before.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+        let prefix = &before[start..];
+        let previous: Vec<&str> = before[..start].split_whitespace().collect();
+        let matching = |hosts: &[String], sigil: &str| -> Vec<String> {
+            hosts
+                .iter()
+                .map(|h| format!("{}{}", sigil, h))
+                .filter(|h| h.starts_with(prefix))
+                .collect()
+        };
+
+        let mut candidates: Vec<String> = match previous.as_slice() {
+            [] if prefix.starts_with('@') => matching(&self.connected, "@"),
+            [] => KEYWORDS
+                .iter()
+                .filter(|k| k.starts_with(prefix))
+                .map(|k| k.to_string())
+                .collect(),
+            ["connect"] => {
+                let mut hosts = matching(&self.config_hosts, "");
+                hosts.extend(matching(&self.connected, ""));
+                hosts
+            }
+            ["switch"] | ["disconnect"] | ["shell"] => matching(&self.connected, ""),
+            _ => Vec::new(),
+        };
+        candidates.sort();
+        candidates.dedup();
+        (start, candidates)
+    }
+}
+
+/// `shell`: bridges the local terminal to a new remote PTY shell until it exits.
+/// The session and its command shell stay open.
+async fn run_passthrough(manager: &SessionManager, alias: &str) -> Result<()> {
+    if !io::stdin().is_terminal() {
+        anyhow::bail!("'shell' needs an interactive terminal");
+    }
+    let (cols, rows) = terminal::size().unwrap_or((80, 24));
+    let term = std::env::var("TERM").unwrap_or_else(|_| "xterm".to_string());
+    let mut channel = manager.open_pty_shell(alias, &term, cols, rows).await?;
+    eprintln!(
+        "Interactive shell on {}; exit it to return to the prompt.",
+        alias
+    );
+
+    let mut resizes = Resizes::new()?;
+    let raw = RawTerminal::enable()?;
+    let mut stdout = tokio::io::stdout();
+    let mut input = [0u8; 4096];
+    let mut output = [0u8; 16384];
+
+    let result: Result<()> = async {
+        loop {
+            tokio::select! {
+                n = raw.read(&mut input) => {
+                    let n = n?;
+                    if n == 0 {
+                        break;
+                    }
+                    channel.write_all(&input[..n]).await?;
+                    channel.flush().await?;
+                }
+                n = channel.read(&mut output) => {
+                    let n = n?;
+                    if n == 0 {
+                        break;
+                    }
+                    stdout.write_all(&output[..n]).await?;
+                    stdout.flush().await?;
+                }
+                _ = resizes.recv() => {
+                    if let Some((cols, rows)) = terminal::size() {
+                        let _ = channel.resize(cols, rows).await;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    .await;
+
+    drop(raw);
+    let _ = channel.close().await;
+    eprintln!();
+    result
+}
+
+/// Splits `@alias command` into the target alias and the command.
+fn split_target(line: &str) -> Option<(&str, &str)> {
+    let rest = line.strip_prefix('@')?;
+    let (alias, command) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    Some((alias, command.trim()))
+}
+
+impl Completer for CliHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.complete_line(line, pos))
+    }
+}
+
+impl Hinter for CliHelper {
+    type Hint = String;
+}
+
+impl Highlighter for CliHelper {}
+
+impl Validator for CliHelper {}
+
+impl Helper for CliHelper {}
+
+/// Asks whether to run a command that matched a deny rule and audits the answer.
+fn confirm_on_stderr(
+    editor: &mut LineEditor,
+    audit: &AuditLog,
+    alias: &str,
+    violation: &PolicyViolation,
+) -> Result<bool> {
+    eprintln!(
+        "Command matches deny rule '{}' ({}).",
+        violation.rule, violation.reason
+    );
+
+    // Ctrl-C or Ctrl-D at this prompt counts as "no".
+    let answer = match editor.readline(&format!("Type 'yes' to run it on {}: ", alias)) {
+        Ok(answer) => answer,
+        Err(ReadlineError::Interrupted | ReadlineError::Eof) => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let approved = answer.trim().eq_ignore_ascii_case("yes");
+
+    let decision = if approved {
+        ApprovalDecision::Approved
+    } else {
+        ApprovalDecision::Denied
+    };
+    let mut record = ApprovalRecord::new(alias, violation, decision, ApprovalChannel::CliPrompt);
+    record.approver = std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .ok();
+    record.client = Some("cli".to_string());
+    audit.record(&record);
+
+    Ok(approved)
+}
+
+/// Most lines kept in the CLI history file.
+const MAX_HISTORY: usize = 1000;
+
+/// `$XDG_DATA_HOME/ssh-liaison/history`, falling back to `~/.local/share`.
+pub fn default_history_path() -> Option<PathBuf> {
+    let data_dir = std::env::var_os("XDG_DATA_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".local").join("share"))
+        })?;
+    Some(data_dir.join("ssh-liaison").join("history"))
+}
+
+/// `connect <user> <host> <password>` lines carry a password and are not saved.
+fn keep_in_history(line: &str) -> bool {
+    match line.strip_prefix("connect ") {
+        Some(args) => args.split_whitespace().count() < 3,
+        None => true,
+    }
+}
+
+fn save_history(editor: &mut LineEditor, path: Option<&Path>) {
+    let Some(path) = path else { return };
+    if let Some(dir) = path.parent()
+        && let Err(e) = std::fs::create_dir_all(dir)
+    {
+        tracing::debug!(error = %e, "Failed to create history directory");
+        return;
+    }
+    if let Err(e) = editor.save_history(path) {
+        tracing::debug!(error = %e, path = %path.display(), "Failed to save history");
+    }
+}
+
+fn print_output(output: &CommandOutput) {
+    if !output.stdout.trim().is_empty() {
+        print!("{}", output.stdout.trim_end());
+        if !output.stdout.trim_end().ends_with('\n') {
+            println!();
+        }
+    }
+    if !output.stderr.trim().is_empty() {
+        eprint!("{}", output.stderr.trim_end());
+        if !output.stderr.trim_end().ends_with('\n') {
+            eprintln!();
+        }
+    }
+}
+
+/// `cli --script`: commands are read from `path` (`-` for stdin) instead of the prompt.
+pub struct ScriptOptions {
+    pub path: PathBuf,
+    pub continue_on_error: bool,
+}
+
+fn read_script(path: &Path) -> Result<String> {
+    if path == Path::new("-") {
+        let mut text = String::new();
+        io::stdin()
+            .read_to_string(&mut text)
+            .context("Failed to read script from stdin")?;
+        return Ok(text);
+    }
+    std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read script {}", path.display()))
+}
+
+/// Script lines to run: blank lines and `#` comments are skipped.
+fn script_commands(text: &str) -> Vec<&str> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+}
+
+/// Runs each script command in turn; a command fails on a policy violation,
+/// an SSH error or a non-zero exit code.
+async fn run_script(
+    manager: &SessionManager,
+    policy: &CommandPolicy,
+    alias: &str,
+    options: &ScriptOptions,
+) -> Result<()> {
+    let text = read_script(&options.path)?;
+    let commands = script_commands(&text);
+    let (mut succeeded, mut failed) = (0, 0);
+
+    for (index, command) in commands.iter().enumerate() {
+        eprintln!("+ {}", command);
+        let error = if let Err(violation) = policy.check(command) {
+            Some(format!(
+                "matches deny rule '{}' ({})",
+                violation.rule, violation.reason
+            ))
+        } else {
+            match execute_with_sudo_prompt(manager, alias, command).await {
+                Ok(output) => {
+                    print_output(&output);
+                    match output.exit_code {
+                        Some(0) => None,
+                        Some(code) => Some(format!("exited with status {}", code)),
+                        None => Some("exit status unknown".to_string()),
+                    }
+                }
+                Err(e) => Some(format!("{:#}", e)),
+            }
+        };
+
+        match error {
+            None => succeeded += 1,
+            Some(reason) => {
+                failed += 1;
+                eprintln!("Line {} failed: {}", index + 1, reason);
+                if !options.continue_on_error {
+                    break;
+                }
+            }
+        }
+    }
+
+    let skipped = commands.len() - succeeded - failed;
+    eprintln!(
+        "Script finished: {} succeeded, {} failed, {} skipped",
+        succeeded, failed, skipped
+    );
+    if failed > 0 {
+        anyhow::bail!("{} of {} script commands failed", failed, commands.len());
+    }
+    Ok(())
+}
 
 pub async fn run_cli_mode(
     host_alias: Option<String>,
@@ -9,8 +366,15 @@ pub async fn run_cli_mode(
     hostname: Option<String>,
     password: Option<String>,
     port: u16,
+    audit_log: Option<PathBuf>,
+    script: Option<ScriptOptions>,
 ) -> Result<()> {
-    let manager = SessionManager::new();
+    let policy = CommandPolicy::new().require_approval(true);
+    let audit = match audit_log {
+        Some(ref path) => AuditLog::open(path)?,
+        None => AuditLog::disabled(),
+    };
+    let manager = interactive_manager()?;
     let mut current_host: Option<String> = None;
 
     if let (Some(user), Some(hostname)) = (user, hostname) {
@@ -18,7 +382,7 @@ pub async fn run_cli_mode(
         tracing::info!(user = %user, hostname = %hostname, port = %port, "Connecting");
 
         match manager
-            .connect_direct(alias, &user, &hostname, Some(port))
+            .connect_direct(alias, &user, &hostname, Some(port), None)
             .await
         {
             Ok(()) => {
@@ -79,19 +443,55 @@ pub async fn run_cli_mode(
         current_host = Some(alias.clone());
     }
 
-    let stdin = io::stdin();
-    let mut reader = BufReader::new(stdin.lock());
+    if let Some(ref options) = script {
+        let Some(ref alias) = current_host else {
+            anyhow::bail!("--script needs a host: pass --host or --user with --hostname");
+        };
+        let result = run_script(&manager, &policy, alias, options).await;
+        let _ = manager.disconnect(alias).await;
+        return result;
+    }
+
+    let config = Config::builder()
+        .max_history_size(MAX_HISTORY)?
+        .history_ignore_dups(true)?
+        .history_ignore_space(true)
+        .build();
+    let mut editor = LineEditor::with_config(config)?;
+    editor.set_helper(Some(CliHelper::load()));
+    let history_path = default_history_path();
+    if let Some(ref path) = history_path
+        && path.exists()
+        && let Err(e) = editor.load_history(path)
+    {
+        tracing::debug!(error = %e, path = %path.display(), "Failed to load history");
+    }
 
     loop {
-        if let Some(ref alias) = current_host {
-            print!("[{}]> ", alias);
-        } else {
-            print!("ssh> ");
+        let connected: Vec<String> = manager
+            .list_connections()
+            .await
+            .into_iter()
+            .map(|c| c.alias)
+            .collect();
+        if let Some(helper) = editor.helper_mut() {
+            helper.connected = connected.clone();
         }
-        io::stdout().flush()?;
+        let prompt = match current_host {
+            Some(ref alias) => format!("[{}]> ", alias),
+            None => "ssh> ".to_string(),
+        };
 
-        let mut input = String::new();
-        reader.read_line(&mut input)?;
+        let input = match editor.readline(&prompt) {
+            Ok(line) => line,
+            // Ctrl-C only clears the line; the session stays connected.
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if keep_in_history(&input) {
+            let _ = editor.add_history_entry(input.as_str());
+        }
         let command = input.trim();
 
         if command.is_empty() {
@@ -99,19 +499,65 @@ pub async fn run_cli_mode(
         }
 
         match command {
-            "exit" | "quit" => {
-                if let Some(ref alias) = current_host {
-                    let _ = manager.disconnect(alias).await;
+            "exit" | "quit" => break,
+            "sessions" => {
+                if connected.is_empty() {
+                    eprintln!("No open sessions");
+                }
+                for alias in &connected {
+                    let marker = if current_host.as_ref() == Some(alias) {
+                        "*"
+                    } else {
+                        " "
+                    };
+                    println!("{} {}", marker, alias);
                 }
-                break;
+                continue;
             }
-            "disconnect" => {
-                if let Some(ref alias) = current_host {
-                    manager.disconnect(alias).await?;
-                    tracing::info!(host = %alias, "Disconnected");
-                    current_host = None;
-                } else {
+            cmd if cmd == "disconnect" || cmd.starts_with("disconnect ") => {
+                let Some(alias) = cmd[10..]
+                    .split_whitespace()
+                    .next()
+                    .or(current_host.as_deref())
+                else {
                     eprintln!("Not connected to any host");
+                    continue;
+                };
+                if !connected.iter().any(|c| c == alias) {
+                    eprintln!("Not connected to '{}'", alias);
+                    continue;
+                }
+                let alias = alias.to_string();
+                manager.disconnect(&alias).await?;
+                tracing::info!(host = %alias, "Disconnected");
+                if current_host.as_ref() == Some(&alias) {
+                    current_host = connected.iter().find(|c| **c != alias).cloned();
+                }
+                continue;
+            }
+            cmd if cmd == "shell" || cmd.starts_with("shell ") => {
+                let Some(alias) = cmd[5..]
+                    .split_whitespace()
+                    .next()
+                    .or(current_host.as_deref())
+                else {
+                    eprintln!("Not connected to any host");
+                    continue;
+                };
+                if let Err(e) = run_passthrough(&manager, alias).await {
+                    tracing::error!(error = %e, "Interactive shell failed");
+                }
+                continue;
+            }
+            cmd if cmd.starts_with("switch ") => {
+                let alias = cmd[7..].trim();
+                if connected.iter().any(|c| c == alias) {
+                    current_host = Some(alias.to_string());
+                } else {
+                    eprintln!(
+                        "Not connected to '{}'. Use 'connect {}' first.",
+                        alias, alias
+                    );
                 }
                 continue;
             }
@@ -123,10 +569,6 @@ pub async fn run_cli_mode(
                     continue;
                 }
 
-                if let Some(ref old_alias) = current_host {
-                    let _ = manager.disconnect(old_alias).await;
-                }
-
                 if args.len() >= 2 {
                     let user = args[0];
                     let hostname = args[1];
@@ -140,7 +582,7 @@ pub async fn run_cli_mode(
                     let alias = format!("{}_{}", user, hostname);
 
                     match manager
-                        .connect_direct(&alias, user, hostname, Some(port))
+                        .connect_direct(&alias, user, hostname, Some(port), None)
                         .await
                     {
                         Ok(()) => {
@@ -200,22 +642,29 @@ pub async fn run_cli_mode(
             _ => {}
         }
 
-        if let Some(ref alias) = current_host {
-            match manager.execute_command(alias, command, None).await {
-                Ok(output) => {
-                    if !output.stdout.trim().is_empty() {
-                        print!("{}", output.stdout.trim_end());
-                        if !output.stdout.trim_end().ends_with('\n') {
-                            println!();
-                        }
-                    }
-                    if !output.stderr.trim().is_empty() {
-                        eprint!("{}", output.stderr.trim_end());
-                        if !output.stderr.trim_end().ends_with('\n') {
-                            eprintln!();
-                        }
-                    }
-                }
+        let (target, command) = match split_target(command) {
+            Some((alias, _)) if !connected.iter().any(|c| c == alias) => {
+                eprintln!("Not connected to '{}'", alias);
+                continue;
+            }
+            Some((_, "")) => {
+                eprintln!("Usage: @<alias> <command>");
+                continue;
+            }
+            Some((alias, command)) => (Some(alias.to_string()), command),
+            None => (current_host.clone(), command),
+        };
+
+        if let Some(ref alias) = target {
+            if let Err(violation) = policy.check(command)
+                && !confirm_on_stderr(&mut editor, &audit, alias, &violation)?
+            {
+                eprintln!("Command not run");
+                continue;
+            }
+
+            match execute_with_sudo_prompt(&manager, alias, command).await {
+                Ok(output) => print_output(&output),
                 Err(e) => {
                     tracing::error!(error = %e, "Command execution failed");
                 }
@@ -225,5 +674,132 @@ pub async fn run_cli_mode(
         }
     }
 
+    for connection in manager.list_connections().await {
+        let _ = manager.disconnect(&connection.alias).await;
+    }
+    save_history(&mut editor, history_path.as_deref());
     Ok(())
 }
+
+/// Exit status when the remote command's status couldn't be read, as with `ssh`.
+const UNKNOWN_EXIT_CODE: i32 = 255;
+
+/// `exec <host> -- <command>`: runs one command and returns its exit code.
+pub async fn run_exec(host_alias: &str, command: &str, force: bool) -> Result<i32> {
+    if let Err(violation) = CommandPolicy::new().check(command)
+        && !force
+    {
+        anyhow::bail!(
+            "Command matches deny rule '{}' ({}); pass --force to run it anyway",
+            violation.rule,
+            violation.reason
+        );
+    }
+
+    let manager = interactive_manager()?;
+    manager
+        .connect_by_alias(host_alias)
+        .await
+        .with_context(|| format!("Failed to connect to '{}'", host_alias))?;
+
+    let result = execute_with_sudo_prompt(&manager, host_alias, command).await;
+    let _ = manager.disconnect(host_alias).await;
+    let output = result?;
+
+    print!("{}", output.stdout);
+    io::stdout().flush()?;
+    eprint!("{}", output.stderr);
+    if output.truncated {
+        tracing::warn!("Output exceeded the capture limit; the middle was dropped");
+    }
+    Ok(output.exit_code.unwrap_or(UNKNOWN_EXIT_CODE))
+}
+
+/// Manager that asks for key passphrases on the terminal, with channel
+/// options from the `SSH_LIAISON_*` environment variables.
+fn interactive_manager() -> Result<SessionManager> {
+    Ok(
+        SessionManager::with_passphrase_provider(Arc::new(ChainPassphraseProvider::interactive()))
+            .with_channel_options(ChannelOptions::default().with_env()?),
+    )
+}
+
+/// Legacy `connect <user> <host>` mode: a bare prompt on a single direct session.
+pub async fn run_legacy_connect(user: String, host: String, port: u16) -> Result<()> {
+    let manager = interactive_manager()?;
+    tracing::info!(user = %user, host = %host, port = %port, "Connecting");
+    manager
+        .connect_direct("direct", &user, &host, Some(port), None)
+        .await
+        .with_context(|| format!("Failed to connect to {}@{}:{}", user, host, port))?;
+    tracing::info!("Connected successfully");
+
+    loop {
+        print!("ssh> ");
+        io::stdout().flush()?;
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+        let command = input.trim();
+
+        if command.is_empty() {
+            continue;
+        }
+
+        if command == "exit" || command == "quit" {
+            break;
+        }
+
+        match manager.execute_command("direct", command, None).await {
+            Ok(output) => print_output(&output),
+            Err(e) => {
+                tracing::error!(error = %e, "Command execution failed");
+            }
+        }
+    }
+
+    manager.disconnect("direct").await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completes_keywords_and_hosts() {
+        let helper = CliHelper {
+            config_hosts: vec!["prod-1".to_string(), "prod-2".to_string()],
+            connected: vec!["dev".to_string(), "prod-1".to_string()],
+        };
+        let complete = |line: &str| helper.complete_line(line, line.len());
+        assert_eq!(complete("dis"), (0, vec!["disconnect".to_string()]));
+        assert_eq!(
+            complete("connect pr"),
+            (8, vec!["prod-1".to_string(), "prod-2".to_string()])
+        );
+        assert_eq!(complete("switch pr").1, vec!["prod-1".to_string()]);
+        assert_eq!(complete("@d"), (0, vec!["@dev".to_string()]));
+        assert!(complete("ls pr").1.is_empty());
+        assert!(complete("connect pi pr").1.is_empty());
+    }
+
+    #[test]
+    fn test_split_target() {
+        assert_eq!(split_target("@web-1 uptime"), Some(("web-1", "uptime")));
+        assert_eq!(split_target("@web-1"), Some(("web-1", "")));
+        assert_eq!(split_target("uptime"), None);
+    }
+
+    #[test]
+    fn test_password_connects_stay_out_of_history() {
+        assert!(keep_in_history("uname -a"));
+        assert!(keep_in_history("connect prod"));
+        assert!(keep_in_history("connect pi 192.168.1.100"));
+        assert!(!keep_in_history("connect pi 192.168.1.100 hunter2"));
+    }
+
+    #[test]
+    fn test_script_commands_skip_blanks_and_comments() {
+        let script = "# provision\nuname -a\n\n   \n  df -h /  \n#apt update\n";
+        assert_eq!(script_commands(script), vec!["uname -a", "df -h /"]);
+    }
+}
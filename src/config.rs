@@ -0,0 +1,632 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::inventory::Inventory;
+use crate::mcp::parsers::OutputParser;
+use crate::mcp::templates::CommandTemplate;
+use crate::ssh::ansi::AnsiMode;
+use crate::ssh::auth::AuthMethod;
+use crate::ssh::auth::vault::VaultConfig;
+use crate::ssh::backend::Backend;
+use crate::ssh::channel::{ChannelOptions, ChannelTuning};
+use crate::ssh::health::{DEFAULT_HEALTH_INTERVAL_SECS, HealthMonitor, HealthProbe, ProbeCheck};
+use crate::ssh::transport::{AwsTarget, TeleportTarget, Transport, TransportKind};
+
+/// Server settings loaded from `config.toml`; command-line flags win over them.
+///
+/// ```toml
+/// audit_log = "/var/log/ssh-liaison/audit.jsonl"
+/// keepalive_interval = 60
+/// backend = "russh"
+/// max_sessions = 32
+/// auto_connect = true
+/// ansi = "convert"
+/// cache_ttl = 30
+/// transfer_rate_limit = 10485760
+/// allowed_hosts = ["staging-*", "!staging-db"]
+/// auth_order = ["agent", "identity-file", "external"]
+///
+/// [tags]
+/// prod = ["prod-*", "10.0.1.*"]
+///
+/// [policy]
+/// deny_commands = ["^kubectl delete"]
+///
+/// [policy.tags.prod]
+/// read_only = true
+///
+/// [reconnect]
+/// attempts = 5
+///
+/// [redaction]
+/// patterns = ["session=(?P<secret>\\w+)"]
+///
+/// [recording]
+/// enabled = true
+/// directory = "/var/log/ssh-liaison/casts"
+///
+/// [sessions]
+/// restore = true
+///
+/// [history]
+/// file = "/var/log/ssh-liaison/history.jsonl"
+///
+/// [health]
+/// interval_secs = 60
+///
+/// [[health.probes]]
+/// hosts = ["@web"]
+/// kind = "disk"
+/// path = "/"
+/// max_used_percent = 90
+///
+/// [rate_limit]
+/// per_host = 30
+/// global = 120
+///
+/// [templates.restart_app]
+/// command = "sudo systemctl restart {unit}"
+/// params.unit.allowed = ["api.service", "worker.service"]
+///
+/// [plugins]
+/// executables = ["/usr/local/bin/cmdb-tools"]
+///
+/// [vault]
+/// address = "https://vault.corp:8200"
+///
+/// [vault.hosts.prod-web]
+/// role = "ops"
+///
+/// [channel]
+/// command_timeout_secs = 60
+///
+/// [hosts.sat-link]
+/// command_timeout_secs = 300
+/// resync_timeout_ms = 20000
+///
+/// [hosts.prod-db]
+/// transport = "ssm"
+/// instance_id = "i-0abc123def456"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    /// JSON Lines file receiving approval decisions.
+    pub audit_log: Option<PathBuf>,
+    /// Host group inventory file.
+    pub inventory: Option<PathBuf>,
+    /// Serve streamable HTTP on this address instead of stdio.
+    pub http: Option<SocketAddr>,
+    /// Bearer token HTTP clients must send.
+    pub http_token: Option<String>,
+    /// Keepalive seconds for hosts without `ServerAliveInterval` (0 disables).
+    pub keepalive_interval: Option<u32>,
+    /// SSH library new sessions use (default libssh2).
+    pub backend: Option<Backend>,
+    /// Most sessions open at once (0 is unlimited).
+    pub max_sessions: Option<usize>,
+    /// Connect `~/.ssh/config` aliases on their first command.
+    #[serde(default)]
+    pub auto_connect: bool,
+    /// Only report what `ssh_run_command` would run.
+    #[serde(default)]
+    pub dry_run: bool,
+    /// What `ssh_run_command` does with colors by default.
+    pub ansi: Option<AnsiMode>,
+    /// `ssh_run_command` calls that may wait behind the running one per shell.
+    pub queue_depth: Option<usize>,
+    /// Seconds read-only command results are reused (0 disables the cache).
+    pub cache_ttl: Option<u64>,
+    /// Bytes per second shared by all file transfers.
+    pub transfer_rate_limit: Option<u64>,
+    /// Aliases or hostnames the server may connect to (`Host`-style patterns).
+    #[serde(default)]
+    pub allowed_hosts: Vec<String>,
+    /// Auth methods in the order they are tried (default: external, agent,
+    /// identity-file, default-keys, password, keyboard-interactive).
+    pub auth_order: Option<Vec<AuthMethod>>,
+    /// Tags given to aliases or hostnames matching their patterns.
+    #[serde(default)]
+    pub tags: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub policy: PolicyConfig,
+    #[serde(default)]
+    pub reconnect: ReconnectConfig,
+    #[serde(default)]
+    pub redaction: RedactionConfig,
+    #[serde(default)]
+    pub recording: RecordingConfig,
+    #[serde(default)]
+    pub sessions: SessionsConfig,
+    #[serde(default)]
+    pub history: HistoryConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    /// Commands served as tools of their own, by tool name.
+    #[serde(default)]
+    pub templates: BTreeMap<String, TemplateConfig>,
+    #[serde(default)]
+    pub plugins: PluginsConfig,
+    /// Hosts that get signed certificates or OTPs from Vault.
+    #[serde(default)]
+    pub vault: VaultConfig,
+    /// Channel timeouts and output limits for every host.
+    #[serde(default)]
+    pub channel: ChannelTuning,
+    /// Channel settings and transports of single host aliases.
+    #[serde(default)]
+    pub hosts: BTreeMap<String, HostSettings>,
+    /// File the config was loaded from.
+    #[serde(skip)]
+    pub source: Option<PathBuf>,
+}
+
+/// `[hosts.<alias>]`: channel settings over `[channel]`, and how to reach the host.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HostSettings {
+    /// `direct`, `ssm`, `instance-connect` or `teleport`; unset uses the host's `ProxyCommand`.
+    pub transport: Option<TransportKind>,
+    #[serde(flatten)]
+    pub aws: AwsTarget,
+    #[serde(flatten)]
+    pub teleport: TeleportTarget,
+    #[serde(flatten)]
+    pub channel: ChannelTuning,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PolicyConfig {
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub require_approval: bool,
+    /// Allowlist regexes; when given, every command segment must match one.
+    #[serde(default)]
+    pub allow_commands: Vec<String>,
+    /// Deny regexes on top of the built-in rules.
+    #[serde(default)]
+    pub deny_commands: Vec<String>,
+    /// Stricter rules for sessions with a tag.
+    #[serde(default)]
+    pub tags: BTreeMap<String, TagPolicyConfig>,
+}
+
+/// `[policy.tags.<tag>]`: settings added to the policy for tagged sessions.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TagPolicyConfig {
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub require_approval: bool,
+    #[serde(default)]
+    pub deny_commands: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReconnectConfig {
+    pub enabled: Option<bool>,
+    pub attempts: Option<u32>,
+    pub backoff_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedactionConfig {
+    /// `false` turns off the built-in patterns too.
+    pub enabled: Option<bool>,
+    /// Regexes masked on top of the built-in patterns.
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// Mask tokens with at least this many bits of entropy per character.
+    pub entropy_threshold: Option<f64>,
+    pub entropy_min_length: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RecordingConfig {
+    /// Record every session unless a connect turns it off.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Where `.cast` files go (default: `recordings` under the config directory).
+    pub directory: Option<PathBuf>,
+}
+
+/// `[sessions]`: saving open sessions so they can be re-opened after a restart.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionsConfig {
+    /// Save open sessions (default: true).
+    pub persist: Option<bool>,
+    /// Where they are saved (default: `sessions.json` under the config directory).
+    pub file: Option<PathBuf>,
+    /// Re-open the saved sessions at startup.
+    #[serde(default)]
+    pub restore: bool,
+}
+
+/// `[history]`: where the commands that ran are recorded for `ssh_history`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Append the history to a file (default: true).
+    pub persist: Option<bool>,
+    /// The file (default: `history.jsonl` under the config directory).
+    pub file: Option<PathBuf>,
+}
+
+/// `[rate_limit]`: commands per minute, refused beyond with `rate_limited`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Per host alias (0 is unlimited).
+    pub per_host: Option<u32>,
+    /// Across all hosts and clients (0 is unlimited).
+    pub global: Option<u32>,
+}
+
+/// `[templates.<name>]`: a command with parameters, served as the tool `<name>`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateConfig {
+    /// Tool description; the command is appended to it.
+    pub description: Option<String>,
+    /// The command, with `{param}` where a parameter's quoted value goes.
+    pub command: String,
+    /// Host aliases or `@group`s it may run on (default: any host).
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Annotate the tool as read-only instead of destructive.
+    #[serde(default)]
+    pub read_only: bool,
+    #[serde(default)]
+    pub params: BTreeMap<String, TemplateParamConfig>,
+    /// Parses stdout into JSON returned as `parsed`.
+    pub output: Option<OutputParser>,
+}
+
+/// `[templates.<name>.params.<param>]`: what a parameter may be.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateParamConfig {
+    pub description: Option<String>,
+    /// The values it may take.
+    #[serde(default)]
+    pub allowed: Vec<String>,
+    /// Regex the whole value must match.
+    pub pattern: Option<String>,
+    /// Used when the call leaves it out; without one it is required.
+    pub default: Option<String>,
+}
+
+/// `[plugins]`: more tools, from template files and external executables.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginsConfig {
+    /// Directory whose `*.toml` files hold more `[templates.<name>]`
+    /// (default: `plugins` under the config directory, if it exists).
+    pub directory: Option<PathBuf>,
+    /// Executables serving tools over the plugin protocol.
+    #[serde(default)]
+    pub executables: Vec<PathBuf>,
+}
+
+/// A `*.toml` file in the plugin directory.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PluginFile {
+    #[serde(default)]
+    templates: BTreeMap<String, TemplateConfig>,
+}
+
+impl PluginsConfig {
+    /// Templates of the plugin directory's files, by name.
+    pub fn templates(&self) -> Result<BTreeMap<String, TemplateConfig>> {
+        let dir = match self.directory {
+            Some(ref dir) => dir.clone(),
+            None => match config_dir() {
+                Some(dir) if dir.join(PLUGIN_DIR).is_dir() => dir.join(PLUGIN_DIR),
+                _ => return Ok(BTreeMap::new()),
+            },
+        };
+        let entries = std::fs::read_dir(&dir)
+            .with_context(|| format!("Failed to read plugin directory {}", dir.display()))?;
+        let mut files: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+            .collect();
+        files.sort();
+
+        let mut templates = BTreeMap::new();
+        for path in files {
+            let content = std::fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read plugin {}", path.display()))?;
+            let file: PluginFile = toml::from_str(&content)
+                .with_context(|| format!("Invalid plugin file {}", path.display()))?;
+            for (name, template) in file.templates {
+                if templates.insert(name.clone(), template).is_some() {
+                    anyhow::bail!("Template '{}' is defined twice in {}", name, dir.display());
+                }
+            }
+        }
+        Ok(templates)
+    }
+}
+
+/// `[health]`: probes run on an interval against inventory hosts.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HealthConfig {
+    /// Seconds between runs (default: 60).
+    pub interval_secs: Option<u64>,
+    #[serde(default)]
+    pub probes: Vec<ProbeConfig>,
+}
+
+/// `[[health.probes]]`: a `tcp`, `command` or `disk` check of each of `hosts`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProbeConfig {
+    /// Shown in reports (default: derived from the check).
+    pub name: Option<String>,
+    /// Host aliases, or `@group` for an inventory group.
+    pub hosts: Vec<String>,
+    #[serde(flatten)]
+    pub check: ProbeCheck,
+}
+
+impl HealthConfig {
+    /// A monitor for the probes of every host, with groups expanded from `inventory`.
+    pub fn monitor(&self, inventory: &Inventory) -> Result<HealthMonitor> {
+        let mut probes = Vec::new();
+        for probe in &self.probes {
+            if probe.hosts.is_empty() {
+                anyhow::bail!("health probes must name at least one host");
+            }
+            for host in inventory.expand_targets(&probe.hosts)? {
+                probes.push(HealthProbe::new(
+                    host,
+                    probe.name.clone(),
+                    probe.check.clone(),
+                )?);
+            }
+        }
+        let interval = self.interval_secs.unwrap_or(DEFAULT_HEALTH_INTERVAL_SECS);
+        HealthMonitor::new(Duration::from_secs(interval), probes)
+    }
+}
+
+impl ServerConfig {
+    pub fn parse(content: &str) -> Result<Self> {
+        let config: Self = toml::from_str(content).context("Invalid config file")?;
+        if config.auth_order.as_ref().is_some_and(Vec::is_empty) {
+            anyhow::bail!("auth_order must name at least one auth method");
+        }
+        Ok(config)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config {}", path.display()))?;
+        let mut config = Self::parse(&content).with_context(|| format!("In {}", path.display()))?;
+        config.source = Some(path.to_path_buf());
+        Ok(config)
+    }
+
+    /// Loads `path`, or the default location if it exists, or an empty config.
+    pub fn load_or_default(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => Self::load(path),
+            None => match config_dir() {
+                Some(dir) if dir.join(CONFIG_FILE).exists() => Self::load(&dir.join(CONFIG_FILE)),
+                _ => Ok(Self::default()),
+            },
+        }
+    }
+
+    /// Channel options for every host: built-in defaults, then `[channel]`,
+    /// then the `SSH_LIAISON_*` environment variables.
+    pub fn channel_options(&self) -> Result<ChannelOptions> {
+        self.channel.apply(ChannelOptions::default()).with_env()
+    }
+
+    /// Options for the aliases under `[hosts]`, over `base`.
+    pub fn host_channel_options(&self, base: ChannelOptions) -> HashMap<String, ChannelOptions> {
+        self.hosts
+            .iter()
+            .map(|(alias, host)| (alias.clone(), host.channel.apply(base)))
+            .collect()
+    }
+
+    /// The `[templates]` and those of the plugin directory, with their
+    /// `@group` hosts expanded from `inventory`.
+    pub fn command_templates(&self, inventory: &Inventory) -> Result<Vec<CommandTemplate>> {
+        let mut templates = self.templates.clone();
+        for (name, template) in self.plugins.templates()? {
+            if templates.insert(name.clone(), template).is_some() {
+                anyhow::bail!(
+                    "Template '{}' is defined in both the config and a plugin file",
+                    name
+                );
+            }
+        }
+        templates
+            .iter()
+            .map(|(name, template)| CommandTemplate::new(name, template, inventory))
+            .collect()
+    }
+
+    /// Transports of the aliases under `[hosts]` that set one.
+    pub fn host_transports(&self) -> HashMap<String, Transport> {
+        self.hosts
+            .iter()
+            .filter_map(|(alias, host)| {
+                let kind = host.transport?;
+                let transport = Transport::new(kind, host.aws.clone(), host.teleport.clone());
+                Some((alias.clone(), transport))
+            })
+            .collect()
+    }
+}
+
+const CONFIG_FILE: &str = "config.toml";
+const PLUGIN_DIR: &str = "plugins";
+
+/// `$XDG_CONFIG_HOME/ssh-liaison`, falling back to `~/.config`.
+pub fn config_dir() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))?;
+    Some(config_dir.join("ssh-liaison"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"
+audit_log = "/tmp/audit.jsonl"
+http = "127.0.0.1:8765"
+http_token = "s3cret"
+ansi = "keep"
+backend = "russh"
+allowed_hosts = ["staging-*"]
+
+[tags]
+prod = ["prod-*"]
+
+[policy]
+read_only = true
+deny_commands = ["^kubectl delete"]
+
+[policy.tags.prod]
+require_approval = true
+deny_commands = ["^systemctl"]
+
+[reconnect]
+attempts = 5
+
+[rate_limit]
+per_host = 30
+
+[channel]
+command_timeout_secs = 60
+
+[hosts.sat-link]
+resync_timeout_ms = 20000
+charset = "koi8-r"
+
+[hosts.prod-db]
+transport = "ssm"
+region = "eu-west-1"
+
+[health]
+interval_secs = 30
+
+[[health.probes]]
+hosts = ["web1", "db1"]
+kind = "tcp"
+port = 22
+
+[[health.probes]]
+name = "nginx"
+hosts = ["web1"]
+kind = "command"
+command = "systemctl is-active nginx"
+
+[templates.restart_app]
+description = "Restart an app unit."
+command = "sudo systemctl restart {unit}"
+hosts = ["web1"]
+
+[templates.restart_app.params.unit]
+allowed = ["api.service", "worker.service"]
+"#;
+
+    #[test]
+    fn test_plugin_templates() {
+        let dir = tempfile::tempdir().unwrap();
+        let plugin = "[templates.unit_status]\ncommand = \"systemctl status {unit} --no-pager\"\nread_only = true\n\n[templates.unit_status.params.unit]\npattern = \"[a-z-]+\"\n";
+        std::fs::write(dir.path().join("units.toml"), plugin).unwrap();
+        std::fs::write(dir.path().join("README.md"), "not a plugin").unwrap();
+
+        let mut config = ServerConfig::parse(&format!(
+            "[plugins]\ndirectory = {:?}\n",
+            dir.path().display().to_string()
+        ))
+        .unwrap();
+        let templates = config.command_templates(&Inventory::default()).unwrap();
+        assert_eq!(templates.len(), 1);
+        assert_eq!(templates[0].name, "unit_status");
+        assert!(templates[0].read_only);
+
+        config.templates = config.plugins.templates().unwrap();
+        assert!(config.command_templates(&Inventory::default()).is_err());
+
+        std::fs::write(
+            dir.path().join("bad.toml"),
+            "[templates.x]\ncomand = \"ls\"\n",
+        )
+        .unwrap();
+        assert!(config.plugins.templates().is_err());
+    }
+
+    #[test]
+    fn test_parse_server_config() {
+        let config = ServerConfig::parse(SAMPLE).unwrap();
+        assert_eq!(config.audit_log, Some(PathBuf::from("/tmp/audit.jsonl")));
+        assert_eq!(config.http, Some("127.0.0.1:8765".parse().unwrap()));
+        assert_eq!(config.http_token.as_deref(), Some("s3cret"));
+        assert!(config.policy.read_only);
+        assert_eq!(config.ansi, Some(AnsiMode::Keep));
+        assert_eq!(config.backend, Some(Backend::Russh));
+        assert_eq!(config.allowed_hosts, ["staging-*"]);
+        assert_eq!(config.tags["prod"], ["prod-*"]);
+        assert!(config.policy.tags["prod"].require_approval);
+        assert_eq!(config.reconnect.attempts, Some(5));
+        assert_eq!(config.rate_limit.per_host, Some(30));
+        assert_eq!(config.rate_limit.global, None);
+
+        let base = config.channel.apply(ChannelOptions::default());
+        assert_eq!(base.command_timeout_secs, 60);
+        let hosts = config.host_channel_options(base);
+        assert_eq!(hosts["sat-link"].command_timeout_secs, 60);
+        assert_eq!(hosts["sat-link"].resync_timeout_ms, 20000);
+        assert_eq!(hosts["sat-link"].charset.to_string(), "KOI8-R");
+        assert_eq!(base.charset, crate::ssh::charset::Charset::default());
+
+        let transports = config.host_transports();
+        assert_eq!(transports.len(), 1);
+        assert!(matches!(
+            transports["prod-db"],
+            Transport::Ssm(AwsTarget { ref region, .. }) if region.as_deref() == Some("eu-west-1")
+        ));
+
+        let health = config.health.monitor(&Inventory::default()).unwrap();
+        assert_eq!(health.interval().as_secs(), 30);
+        assert_eq!(health.hosts(), ["db1", "web1"]);
+        let web1 = health.host("web1").unwrap();
+        assert_eq!(web1.probes[0].probe, "tcp:22");
+        assert_eq!(web1.probes[1].probe, "nginx");
+
+        let templates = config.command_templates(&Inventory::default()).unwrap();
+        assert_eq!(templates.len(), 1);
+        assert_eq!(templates[0].name, "restart_app");
+        assert_eq!(templates[0].params["unit"].allowed.len(), 2);
+
+        assert!(ServerConfig::parse("read_onyl = true").is_err());
+        let unknown_kind = "[[health.probes]]\nhosts = [\"web1\"]\nkind = \"ping\"\n";
+        assert!(ServerConfig::parse(unknown_kind).is_err());
+        assert!(ServerConfig::parse("auth_order = []").is_err());
+        assert!(ServerConfig::parse(r#"auth_order = ["kerberos"]"#).is_err());
+    }
+}
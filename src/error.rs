@@ -0,0 +1,138 @@
+use std::time::Duration;
+
+use crate::ssh::interactive::InteractiveCommand;
+
+/// Failure categories callers may want to tell apart.
+///
+/// Functions still return `anyhow::Result`; these errors are carried inside it and
+/// recovered with [`SshLiaisonError::find`], which also looks through added context.
+#[derive(Debug, thiserror::Error)]
+pub enum SshLiaisonError {
+    /// Unreadable or incomplete SSH configuration.
+    #[error("{0}")]
+    Config(String),
+    #[error("Host '{0}' not found in SSH config")]
+    HostNotFound(String),
+    /// The host allowlist doesn't cover the alias or hostname.
+    #[error("Host '{0}' is not in the allowed hosts list")]
+    HostNotAllowed(String),
+    /// Name resolution, TCP connect, or SSH handshake failed.
+    #[error("Failed to connect to {host}: {reason}")]
+    Connect { host: String, reason: String },
+    #[error("{0}")]
+    Auth(String),
+    #[error("Not connected to host '{0}'")]
+    NotConnected(String),
+    /// Opening a session would exceed the cap and no idle session can be closed.
+    #[error(
+        "Session limit of {0} reached and every session is busy or has tunnels or watches open"
+    )]
+    SessionLimit(usize),
+    /// The session died; `reconnected` tells whether it was re-established.
+    #[error("Connection to '{host}' lost ({})", lost_hint(*.reconnected))]
+    ConnectionLost { host: String, reconnected: bool },
+    /// Opening or using the shell channel failed.
+    #[error("{0}")]
+    Channel(String),
+    /// The command was interrupted with Ctrl-C; `recovered` tells whether the
+    /// shell answered again or is replaced before the next command.
+    #[error("Command timeout after {after:?}, interrupted ({})", interrupt_hint(*.recovered))]
+    Timeout { after: Duration, recovered: bool },
+    /// The command would wait for a terminal or run forever; refused, or
+    /// interrupted once it switched to the alternate screen.
+    #[error("{0}")]
+    Interactive(InteractiveCommand),
+    /// Interrupted with `ssh_cancel_command`, which got the partial output.
+    #[error("Command {0} was cancelled with ssh_cancel_command")]
+    Cancelled(u64),
+    /// A transferred file's SHA-256 differs from its source's.
+    #[error("Checksum mismatch for {path}: source {expected}, copy {actual}")]
+    ChecksumMismatch {
+        path: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl SshLiaisonError {
+    /// Stable snake_case name, used as the `type` of MCP error data.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SshLiaisonError::Config(_) => "config",
+            SshLiaisonError::HostNotFound(_) => "host_not_found",
+            SshLiaisonError::HostNotAllowed(_) => "host_not_allowed",
+            SshLiaisonError::Connect { .. } => "connect",
+            SshLiaisonError::Auth(_) => "auth",
+            SshLiaisonError::NotConnected(_) => "not_connected",
+            SshLiaisonError::SessionLimit(_) => "session_limit",
+            SshLiaisonError::ConnectionLost { .. } => "connection_lost",
+            SshLiaisonError::Channel(_) => "channel",
+            SshLiaisonError::Timeout { .. } => "timeout",
+            SshLiaisonError::Interactive(_) => "interactive",
+            SshLiaisonError::Cancelled(_) => "cancelled",
+            SshLiaisonError::ChecksumMismatch { .. } => "checksum_mismatch",
+        }
+    }
+
+    /// The outermost `SshLiaisonError` in an error, including ones attached as context.
+    pub fn find(err: &anyhow::Error) -> Option<&SshLiaisonError> {
+        err.downcast_ref::<SshLiaisonError>().or_else(|| {
+            err.chain()
+                .find_map(|e| e.downcast_ref::<SshLiaisonError>())
+        })
+    }
+}
+
+fn interrupt_hint(recovered: bool) -> &'static str {
+    if recovered {
+        "the shell is ready for the next command"
+    } else {
+        "the shell didn't recover and is reopened, losing its directory and variables"
+    }
+}
+
+fn lost_hint(reconnected: bool) -> &'static str {
+    if reconnected {
+        "reconnected; the command may not have completed, re-run it if needed"
+    } else {
+        "reconnect with ssh_connect"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_find_through_context() {
+        let err: anyhow::Result<()> = Err(SshLiaisonError::NotConnected("web".into()).into());
+        let err = err.context("While running uptime").unwrap_err();
+        assert_eq!(SshLiaisonError::find(&err).unwrap().kind(), "not_connected");
+
+        let io = std::io::Error::from(std::io::ErrorKind::BrokenPipe);
+        let lost = anyhow::Error::from(io).context(SshLiaisonError::ConnectionLost {
+            host: "web".into(),
+            reconnected: true,
+        });
+        assert_eq!(
+            SshLiaisonError::find(&lost).unwrap().kind(),
+            "connection_lost"
+        );
+
+        let plain = anyhow::anyhow!("something else");
+        assert!(SshLiaisonError::find(&plain).is_none());
+    }
+
+    #[test]
+    fn test_timeout_reports_interrupt() {
+        let timeout = SshLiaisonError::Timeout {
+            after: Duration::from_secs(30),
+            recovered: true,
+        };
+        assert_eq!(
+            timeout.to_string(),
+            "Command timeout after 30s, interrupted (the shell is ready for the next command)"
+        );
+    }
+}
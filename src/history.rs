@@ -0,0 +1,384 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::redact;
+use crate::ssh::channel::CommandOutput;
+
+/// Entries kept in memory for `ssh_history`; the file keeps all of them.
+const MAX_HISTORY_ENTRIES: usize = 1000;
+/// Latest entries whose output is kept in memory for `ssh_rerun`.
+const MAX_KEPT_OUTPUTS: usize = 32;
+/// Longest `summary`, in chars.
+const MAX_SUMMARY_CHARS: usize = 160;
+
+/// One command run on a host, with a summary of its result.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// Increases across restarts when the history is saved to a file.
+    pub id: u64,
+    pub timestamp_ms: u64,
+    pub host: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    /// The command as given, with secrets masked; for scripts, the
+    /// interpreter and length.
+    pub command: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub script: bool,
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+    pub stdout_bytes: usize,
+    pub stderr_bytes: usize,
+    pub truncated: bool,
+    /// Last non-empty line of the output, or the error.
+    pub summary: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// HTTP session namespace the command ran in.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+}
+
+impl std::fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{} [{}] {}", self.id, self.host, self.command)?;
+        match (&self.error, self.exit_code) {
+            (Some(error), _) => write!(f, " -> error: {}", error),
+            (None, Some(code)) => write!(f, " -> exit {}: {}", code, self.summary),
+            (None, None) => write!(f, " -> timed out: {}", self.summary),
+        }
+    }
+}
+
+/// Last non-empty line of `text`, shortened to `MAX_SUMMARY_CHARS`.
+fn summarize(text: &str) -> String {
+    let line = text
+        .lines()
+        .rev()
+        .map(str::trim)
+        .find(|l| !l.is_empty())
+        .unwrap_or_default();
+    match line.char_indices().nth(MAX_SUMMARY_CHARS) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line.to_string(),
+    }
+}
+
+/// A recorded entry with what `ssh_rerun` needs, which isn't saved.
+#[derive(Debug, Clone)]
+pub struct RecordedRun {
+    pub entry: HistoryEntry,
+    /// The command as it ran; `None` for scripts and for commands read
+    /// back from the file with secrets masked.
+    pub command: Option<String>,
+    /// The output as returned, for the latest `MAX_KEPT_OUTPUTS` entries.
+    pub output: Option<CommandOutput>,
+}
+
+/// Commands run through the server, newest last, optionally appended to a
+/// JSON Lines file that is read back at startup.
+#[derive(Default)]
+pub struct CommandHistory {
+    entries: Mutex<VecDeque<RecordedRun>>,
+    file: Option<(PathBuf, Mutex<File>)>,
+}
+
+impl CommandHistory {
+    /// A history kept in memory only.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `history.jsonl` under the config directory.
+    pub fn default_path() -> PathBuf {
+        crate::config::config_dir()
+            .map(|dir| dir.join("history.jsonl"))
+            .unwrap_or_else(|| PathBuf::from("history.jsonl"))
+    }
+
+    /// A history saved to `path`, starting with the entries already in it.
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut entries = VecDeque::new();
+        match std::fs::read_to_string(path) {
+            Ok(content) => {
+                let mut invalid = 0;
+                for line in content.lines().filter(|l| !l.trim().is_empty()) {
+                    match serde_json::from_str::<HistoryEntry>(line) {
+                        Ok(entry) => {
+                            if entries.len() >= MAX_HISTORY_ENTRIES {
+                                entries.pop_front();
+                            }
+                            let command = (!entry.script && !entry.command.contains("[REDACTED:"))
+                                .then(|| entry.command.clone());
+                            entries.push_back(RecordedRun {
+                                entry,
+                                command,
+                                output: None,
+                            });
+                        }
+                        Err(_) => invalid += 1,
+                    }
+                }
+                if invalid > 0 {
+                    tracing::warn!(path = %path.display(), invalid, "Skipped invalid history lines");
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read history {}", path.display()));
+            }
+        }
+
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options
+            .open(path)
+            .with_context(|| format!("Failed to open history {}", path.display()))?;
+        Ok(Self {
+            entries: Mutex::new(entries),
+            file: Some((path.to_path_buf(), Mutex::new(file))),
+        })
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.file.as_ref().map(|(path, _)| path.as_path())
+    }
+
+    /// Records `command` and its result with secrets masked, returning the
+    /// entry's id.
+    pub fn record(
+        &self,
+        namespace: Option<&str>,
+        host: &str,
+        channel: Option<&str>,
+        command: &str,
+        result: std::result::Result<&CommandOutput, &str>,
+    ) -> u64 {
+        let mut entry = new_entry(namespace, host, command, result);
+        entry.channel = channel.map(str::to_string);
+        self.push(RecordedRun {
+            entry,
+            command: Some(command.to_string()),
+            output: result.ok().cloned(),
+        })
+    }
+
+    /// Records a script run by `interpreter`; only its length is kept.
+    pub fn record_script(
+        &self,
+        namespace: Option<&str>,
+        host: &str,
+        interpreter: &str,
+        script: &str,
+        result: std::result::Result<&CommandOutput, &str>,
+    ) -> u64 {
+        let description = format!("{} script ({} lines)", interpreter, script.lines().count());
+        let mut entry = new_entry(namespace, host, &description, result);
+        entry.script = true;
+        self.push(RecordedRun {
+            entry,
+            command: None,
+            output: result.ok().cloned(),
+        })
+    }
+
+    fn push(&self, mut run: RecordedRun) -> u64 {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        run.entry.id = entries.back().map_or(1, |last| last.entry.id + 1);
+        if entries.len() >= MAX_HISTORY_ENTRIES {
+            entries.pop_front();
+        }
+        if let Some(index) = (entries.len() + 1).checked_sub(MAX_KEPT_OUTPUTS + 1)
+            && let Some(old) = entries.get_mut(index)
+        {
+            old.output = None;
+        }
+        let id = run.entry.id;
+        // Written under the entries lock so the file stays in id order.
+        if let Some((_, ref file)) = self.file {
+            let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+            let written = serde_json::to_string(&run.entry)
+                .map_err(std::io::Error::other)
+                .and_then(|line| writeln!(file, "{}", line))
+                .and_then(|_| file.flush());
+            if let Err(e) = written {
+                tracing::error!(error = %e, "Failed to write history entry");
+            }
+        }
+        entries.push_back(run);
+        id
+    }
+
+    /// Up to `limit` entries of `namespace`, newest first, optionally only
+    /// those on `host` or with a command matching `pattern`.
+    pub fn query(
+        &self,
+        namespace: Option<&str>,
+        host: Option<&str>,
+        pattern: Option<&Regex>,
+        limit: usize,
+    ) -> Vec<HistoryEntry> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries
+            .iter()
+            .rev()
+            .map(|run| &run.entry)
+            .filter(|e| e.namespace.as_deref() == namespace)
+            .filter(|e| host.is_none_or(|host| e.host == host))
+            .filter(|e| pattern.is_none_or(|p| p.is_match(&e.command)))
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Entry `id` of `namespace`, if it's still in memory.
+    pub fn get(&self, namespace: Option<&str>, id: u64) -> Option<RecordedRun> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries
+            .iter()
+            .find(|run| run.entry.id == id && run.entry.namespace.as_deref() == namespace)
+            .cloned()
+    }
+}
+
+/// The entry for `command` with its result summarized and secrets masked;
+/// the id is set when it's recorded.
+fn new_entry(
+    namespace: Option<&str>,
+    host: &str,
+    command: &str,
+    result: std::result::Result<&CommandOutput, &str>,
+) -> HistoryEntry {
+    let timestamp_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    let mut entry = HistoryEntry {
+        id: 0,
+        timestamp_ms,
+        host: host.to_string(),
+        channel: None,
+        command: redact::for_logs(command).into_owned(),
+        script: false,
+        exit_code: None,
+        duration_ms: 0,
+        stdout_bytes: 0,
+        stderr_bytes: 0,
+        truncated: false,
+        summary: String::new(),
+        error: None,
+        namespace: namespace.map(str::to_string),
+    };
+    match result {
+        Ok(output) => {
+            entry.exit_code = output.exit_code;
+            entry.duration_ms = output.duration_ms;
+            entry.stdout_bytes = output.stdout.len();
+            entry.stderr_bytes = output.stderr.len();
+            entry.truncated = output.truncated;
+            // Whole streams are masked, so multi-line secrets are found.
+            entry.summary = match summarize(&redact::for_logs(&output.stdout)) {
+                summary if summary.is_empty() => summarize(&redact::for_logs(&output.stderr)),
+                summary => summary,
+            };
+        }
+        Err(error) => {
+            let error = redact::for_logs(error);
+            entry.summary = summarize(&error);
+            entry.error = Some(error.into_owned());
+        }
+    }
+    entry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(stdout: &str, exit_code: i32) -> CommandOutput {
+        CommandOutput {
+            stdout: stdout.to_string(),
+            exit_code: Some(exit_code),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_history_is_saved_and_queried() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        let history = CommandHistory::open(&path).unwrap();
+        history.record(None, "web", None, "uptime", Ok(&output("up 3 days\n\n", 0)));
+        history.record(None, "db", Some("ops"), "df -h", Ok(&output("", 1)));
+        history.record(Some("client-2"), "web", None, "id", Ok(&output("uid=0", 0)));
+        history.record(
+            None,
+            "web",
+            None,
+            "systemctl status nginx",
+            Err("timed out"),
+        );
+
+        let web = history.query(None, Some("web"), None, 10);
+        assert_eq!(
+            web.iter().map(|e| e.id).collect::<Vec<_>>(),
+            [4, 1],
+            "newest first, other namespaces hidden"
+        );
+        assert_eq!(web[0].error.as_deref(), Some("timed out"));
+        assert_eq!(web[1].summary, "up 3 days");
+
+        let pattern = Regex::new("^df").unwrap();
+        let found = history.query(None, None, Some(&pattern), 10);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].channel.as_deref(), Some("ops"));
+        assert_eq!(history.query(None, None, None, 1).len(), 1);
+
+        let script = history.record_script(None, "web", "bash", "a\nb", Ok(&output("ok", 0)));
+        let run = history.get(None, script).unwrap();
+        assert_eq!(run.entry.command, "bash script (2 lines)");
+        assert_eq!(run.command, None);
+        let run = history.get(None, 1).unwrap();
+        assert_eq!(run.command.as_deref(), Some("uptime"));
+        assert_eq!(run.output.unwrap().stdout, "up 3 days\n\n");
+        assert!(history.get(None, 3).is_none(), "other namespace");
+
+        drop(history);
+        let reopened = CommandHistory::open(&path).unwrap();
+        assert_eq!(reopened.query(None, None, None, 10).len(), 4);
+        let run = reopened.get(None, 1).unwrap();
+        assert_eq!(run.command.as_deref(), Some("uptime"));
+        assert!(run.output.is_none());
+        assert_eq!(reopened.get(None, script).unwrap().command, None);
+        let id = reopened.record(None, "web", None, "w", Ok(&output("", 0)));
+        assert_eq!(id, 6);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 6);
+    }
+
+    #[test]
+    fn test_summarize() {
+        assert_eq!(summarize("a\nlast line  \n\n"), "last line");
+        assert_eq!(summarize(""), "");
+        let long = "é".repeat(MAX_SUMMARY_CHARS + 5);
+        assert_eq!(summarize(&long).chars().count(), MAX_SUMMARY_CHARS + 3);
+    }
+}
@@ -0,0 +1,166 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use crate::config::config_dir;
+
+/// Prefix marking a group reference inside a host list (`@web`).
+const GROUP_PREFIX: char = '@';
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct HostGroup {
+    /// Host aliases, or `@other-group` to include another group.
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+/// Named host groups loaded from `inventory.toml`:
+///
+/// ```toml
+/// [groups.web]
+/// description = "Frontend servers"
+/// hosts = ["web1", "web2"]
+///
+/// [groups.prod]
+/// hosts = ["@web", "db1"]
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Inventory {
+    #[serde(default)]
+    pub groups: BTreeMap<String, HostGroup>,
+}
+
+impl Inventory {
+    pub fn parse(content: &str) -> Result<Self> {
+        let inventory: Inventory = toml::from_str(content).context("Invalid inventory file")?;
+        for name in inventory.groups.keys() {
+            inventory.resolve(name)?;
+        }
+        Ok(inventory)
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read inventory {}", path.display()))?;
+        Self::parse(&content).with_context(|| format!("In {}", path.display()))
+    }
+
+    /// Loads `path`, or the default location if it exists, or an empty inventory.
+    pub fn load_or_default(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => Self::load(path),
+            None => match default_inventory_path() {
+                Some(path) if path.exists() => Self::load(&path),
+                _ => Ok(Self::default()),
+            },
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Host aliases of a group, with nested groups expanded, in first-seen order.
+    pub fn resolve(&self, group: &str) -> Result<Vec<String>> {
+        let mut hosts = Vec::new();
+        self.expand(group, &mut Vec::new(), &mut hosts)?;
+        Ok(hosts)
+    }
+
+    fn expand(&self, group: &str, stack: &mut Vec<String>, hosts: &mut Vec<String>) -> Result<()> {
+        if stack.iter().any(|g| g == group) {
+            anyhow::bail!(
+                "Group cycle in inventory: {} -> {}",
+                stack.join(" -> "),
+                group
+            );
+        }
+        let entry = self
+            .groups
+            .get(group)
+            .ok_or_else(|| anyhow::anyhow!("Unknown host group '{}'", group))?;
+
+        stack.push(group.to_string());
+        for host in &entry.hosts {
+            match host.strip_prefix(GROUP_PREFIX) {
+                Some(nested) => self.expand(nested, stack, hosts)?,
+                None if !hosts.contains(host) => hosts.push(host.clone()),
+                None => {}
+            }
+        }
+        stack.pop();
+        Ok(())
+    }
+
+    /// Expands a list of targets where `@name` entries refer to groups.
+    pub fn expand_targets(&self, targets: &[String]) -> Result<Vec<String>> {
+        let mut hosts = Vec::new();
+        for target in targets {
+            let resolved = match target.strip_prefix(GROUP_PREFIX) {
+                Some(group) => self.resolve(group)?,
+                None => vec![target.clone()],
+            };
+            for host in resolved {
+                if !hosts.contains(&host) {
+                    hosts.push(host);
+                }
+            }
+        }
+        Ok(hosts)
+    }
+}
+
+/// `$XDG_CONFIG_HOME/ssh-liaison/inventory.toml`, falling back to `~/.config`.
+pub fn default_inventory_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("inventory.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = r#"
+[groups.web]
+description = "Frontend"
+hosts = ["web1", "web2"]
+
+[groups.db]
+hosts = ["db1"]
+
+[groups.prod]
+hosts = ["@web", "@db", "web1", "cache1"]
+"#;
+
+    #[test]
+    fn test_resolve_nested_groups() {
+        let inventory = Inventory::parse(SAMPLE).unwrap();
+        assert_eq!(inventory.resolve("web").unwrap(), vec!["web1", "web2"]);
+        assert_eq!(
+            inventory.resolve("prod").unwrap(),
+            vec!["web1", "web2", "db1", "cache1"]
+        );
+        assert!(inventory.resolve("missing").is_err());
+    }
+
+    #[test]
+    fn test_expand_targets_mixes_hosts_and_groups() {
+        let inventory = Inventory::parse(SAMPLE).unwrap();
+        let targets = vec!["db1".to_string(), "@web".to_string(), "other".to_string()];
+        assert_eq!(
+            inventory.expand_targets(&targets).unwrap(),
+            vec!["db1", "web1", "web2", "other"]
+        );
+    }
+
+    #[test]
+    fn test_cycles_and_unknown_refs_are_rejected() {
+        let cyclic = "[groups.a]\nhosts = [\"@b\"]\n[groups.b]\nhosts = [\"@a\"]\n";
+        assert!(Inventory::parse(cyclic).is_err());
+
+        let dangling = "[groups.a]\nhosts = [\"@nope\"]\n";
+        assert!(Inventory::parse(dangling).is_err());
+    }
+}
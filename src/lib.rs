@@ -1,3 +1,45 @@
+//! SSH session management and an MCP server exposing it.
+//!
+//! The building blocks can be used on their own, e.g. to embed SSH sessions in
+//! another MCP server:
+//!
+//! - [`SessionManager`] owns persistent shell sessions keyed by host alias and runs
+//!   commands in them ([`CommandOutput`]).
+//! - [`parse_ssh_config`] resolves a `~/.ssh/config` alias the way `ssh -G` does.
+//! - [`SshMcpServer`] is the rmcp server handler with all tools; [`run_mcp_server`]
+//!   serves it over stdio or HTTP.
+//! - [`CommandPolicy`] decides which commands may run.
+//!
+//! ```no_run
+//! # async fn demo() -> anyhow::Result<()> {
+//! use ssh_liaison_mcp::SessionManager;
+//!
+//! let manager = SessionManager::new();
+//! manager.connect_by_alias("web-1").await?;
+//! let output = manager.execute_command("web-1", "uptime", None).await?;
+//! println!("{}", output.stdout);
+//! manager.disconnect("web-1").await?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod audit;
 pub mod cli;
+pub mod config;
+pub mod error;
+pub mod history;
+pub mod inventory;
 pub mod mcp;
+pub mod policy;
+pub mod redact;
 pub mod ssh;
+#[cfg(feature = "otel")]
+pub mod telemetry;
+pub mod terminal;
+
+pub use error::SshLiaisonError;
+pub use mcp::{ServerOptions, SshMcpServer, run_mcp_server};
+pub use policy::CommandPolicy;
+pub use ssh::SessionManager;
+pub use ssh::channel::CommandOutput;
+pub use ssh::config::{SshHostConfig, parse_ssh_config};
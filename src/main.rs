@@ -1,11 +1,19 @@
-use anyhow::{Context, Result};
+use anyhow::Result;
 use clap::{Parser, Subcommand};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
 use tracing::Level;
+use tracing_subscriber::registry::LookupSpan;
 use tracing_subscriber::{EnvFilter, fmt, prelude::*};
 
-mod cli;
-mod mcp;
-mod ssh;
+use ssh_liaison_mcp::history::CommandHistory;
+use ssh_liaison_mcp::ssh::ansi::AnsiMode;
+use ssh_liaison_mcp::ssh::backend::Backend;
+use ssh_liaison_mcp::ssh::reconnect::ReconnectPolicy;
+use ssh_liaison_mcp::ssh::recording::RecordingOptions;
+use ssh_liaison_mcp::ssh::store::SessionStore;
+use ssh_liaison_mcp::{cli, config, inventory, mcp, policy, redact};
 
 #[derive(Parser)]
 #[command(name = "ssh-liaison-mcp")]
@@ -15,14 +23,125 @@ struct Cli {
     #[arg(short = 'v', long, action = clap::ArgAction::Count, global = true)]
     verbose: u8,
 
+    /// Export spans to this OTLP/gRPC collector, e.g. http://localhost:4317
+    /// (default: $OTEL_EXPORTER_OTLP_ENDPOINT)
+    #[cfg(feature = "otel")]
+    #[arg(long, global = true, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+// Parsed once at startup, so the size of `Serve` doesn't matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 enum Commands {
     /// Run as MCP server (for Cursor/Claude integration)
-    Serve,
+    Serve {
+        /// Server config file (default: ~/.config/ssh-liaison/config.toml if present)
+        #[arg(long, value_name = "PATH")]
+        config: Option<PathBuf>,
+        /// Only connect to aliases or hostnames matching this pattern, e.g. 'staging-*' (repeatable)
+        #[arg(long = "allow-host", value_name = "PATTERN")]
+        allow_hosts: Vec<String>,
+        /// Only allow commands classified as read-only
+        #[arg(long)]
+        read_only: bool,
+        /// Allowlist regex; when given, every command segment must match one (repeatable)
+        #[arg(long = "allow-command", value_name = "REGEX")]
+        allow_commands: Vec<String>,
+        /// Extra deny regex on top of the built-in rules (repeatable)
+        #[arg(long = "deny-command", value_name = "REGEX")]
+        deny_commands: Vec<String>,
+        /// Ask the client to approve commands matching deny rules instead of blocking them
+        #[arg(long)]
+        require_approval: bool,
+        /// Append approval decisions to this JSON Lines file
+        #[arg(long, value_name = "PATH")]
+        audit_log: Option<PathBuf>,
+        /// Extra regex masked in tool output; a `secret` group limits it to that part (repeatable)
+        #[arg(long = "redact-pattern", value_name = "REGEX")]
+        redact_patterns: Vec<String>,
+        /// Also mask long tokens with at least this entropy in bits per character (e.g. 4.5)
+        #[arg(long, value_name = "BITS")]
+        redact_entropy: Option<f64>,
+        /// Record every session as asciicast files (connects can still opt out)
+        #[arg(long)]
+        record: bool,
+        /// Directory for session recordings (default: ~/.config/ssh-liaison/recordings)
+        #[arg(long, value_name = "PATH")]
+        record_dir: Option<PathBuf>,
+        /// Don't mask secrets in tool output
+        #[arg(long, conflicts_with_all = ["redact_patterns", "redact_entropy"])]
+        no_redaction: bool,
+        /// Serve MCP over streamable HTTP on this address (e.g. 127.0.0.1:8765) instead of stdio
+        #[arg(long, value_name = "ADDR")]
+        http: Option<SocketAddr>,
+        /// Bearer token HTTP clients must send (or SSH_LIAISON_HTTP_TOKEN); required unless --http is on loopback
+        #[arg(long, value_name = "TOKEN")]
+        http_token: Option<String>,
+        /// Host group inventory (default: ~/.config/ssh-liaison/inventory.toml if present)
+        #[arg(long, value_name = "PATH")]
+        inventory: Option<PathBuf>,
+        /// Don't re-establish dropped sessions automatically
+        #[arg(long)]
+        no_reconnect: bool,
+        /// Re-open the sessions saved by the last run (stdio only)
+        #[arg(long)]
+        restore_sessions: bool,
+        /// Don't save open sessions for --restore-sessions and ssh_restore_sessions
+        #[arg(long, conflicts_with = "restore_sessions")]
+        no_session_store: bool,
+        /// Append the commands that ran to this JSON Lines file (default: history.jsonl in the config directory)
+        #[arg(long, value_name = "PATH")]
+        history_file: Option<PathBuf>,
+        /// Keep the command history in memory only
+        #[arg(long, conflicts_with = "history_file")]
+        no_history_file: bool,
+        /// Connection attempts when re-establishing a dropped session (default 3)
+        #[arg(long, value_name = "N")]
+        reconnect_attempts: Option<u32>,
+        /// Delay before the second reconnect attempt, doubled after each failure (default 1000)
+        #[arg(long, value_name = "MS")]
+        reconnect_backoff_ms: Option<u64>,
+        /// Keepalive interval in seconds for hosts without ServerAliveInterval (0 disables)
+        #[arg(long, value_name = "SECS")]
+        keepalive_interval: Option<u32>,
+        /// SSH library for new sessions: libssh2, or russh in builds with the russh feature (default libssh2)
+        #[arg(long, value_name = "BACKEND")]
+        backend: Option<Backend>,
+        /// Most SSH sessions open at once; idle ones are closed to make room (default 16, 0 is unlimited)
+        #[arg(long, value_name = "N")]
+        max_sessions: Option<usize>,
+        /// Connect ~/.ssh/config aliases on their first ssh_run_command instead of failing
+        #[arg(long)]
+        auto_connect: bool,
+        /// Make ssh_run_command, templates and plugin commands report what they would run instead of running it
+        #[arg(long)]
+        dry_run: bool,
+        /// What ssh_run_command does with colors: strip, keep the ANSI codes, or convert them to markdown (default strip)
+        #[arg(long, value_name = "MODE")]
+        ansi: Option<AnsiMode>,
+        /// Commands per minute allowed on one host (default: unlimited)
+        #[arg(long, value_name = "N")]
+        host_rate_limit: Option<u32>,
+        /// Commands per minute allowed across all hosts (default: unlimited)
+        #[arg(long, value_name = "N")]
+        global_rate_limit: Option<u32>,
+        /// ssh_run_command calls that may wait behind the running one per shell; more are refused (default 8)
+        #[arg(long, value_name = "N")]
+        queue_depth: Option<usize>,
+        /// Reuse results of read-only commands for this many seconds (default 0, no cache)
+        #[arg(long, value_name = "SECS")]
+        cache_ttl: Option<u64>,
+        /// Output kept per command; beyond it the middle is dropped (default 1 MiB)
+        #[arg(long, value_name = "BYTES")]
+        max_output_bytes: Option<usize>,
+        /// Bytes per second shared by all file transfers (default: unlimited)
+        #[arg(long, value_name = "BYTES")]
+        transfer_rate_limit: Option<u64>,
+    },
     /// Interactive CLI mode for standalone testing
     Cli {
         /// Host alias from ~/.ssh/config to connect to immediately
@@ -40,6 +159,26 @@ enum Commands {
         /// SSH port (default: 22)
         #[arg(short = 'P', long, default_value = "22")]
         port: u16,
+        /// Append approval decisions to this JSON Lines file
+        #[arg(long, value_name = "PATH")]
+        audit_log: Option<PathBuf>,
+        /// Run the commands in this file (`-` for stdin) instead of prompting
+        #[arg(long, value_name = "FILE")]
+        script: Option<PathBuf>,
+        /// Keep running the script after a command fails
+        #[arg(long, requires = "script")]
+        continue_on_error: bool,
+    },
+    /// Run one command on a host and exit with its exit code
+    Exec {
+        /// Host alias from ~/.ssh/config
+        host: String,
+        /// Run the command even if it matches a deny rule
+        #[arg(long)]
+        force: bool,
+        /// Command to run, after `--`
+        #[arg(last = true, required = true, value_name = "COMMAND")]
+        command: Vec<String>,
     },
     /// Legacy direct connect mode (for backward compatibility)
     Connect {
@@ -53,7 +192,8 @@ enum Commands {
     },
 }
 
-fn init_tracing(verbose: u8) {
+/// Log output on stderr, filtered by `-v` or `RUST_LOG`.
+fn stderr_registry(verbose: u8) -> impl tracing::Subscriber + for<'a> LookupSpan<'a> {
     let level = match verbose {
         0 => Level::WARN,
         1 => Level::INFO,
@@ -63,22 +203,216 @@ fn init_tracing(verbose: u8) {
 
     let filter =
         EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level.as_str()));
+    tracing_subscriber::registry().with(
+        fmt::layer()
+            .with_writer(std::io::stderr)
+            .with_filter(filter),
+    )
+}
+
+#[cfg(not(feature = "otel"))]
+fn init_tracing(cli: &Cli) {
+    stderr_registry(cli.verbose).init();
+}
 
-    tracing_subscriber::registry()
-        .with(fmt::layer().with_writer(std::io::stderr))
-        .with(filter)
-        .init();
+/// Also exports spans when an OTLP endpoint is configured. The returned
+/// guard flushes them when dropped.
+#[cfg(feature = "otel")]
+fn init_tracing(cli: &Cli) -> Result<Option<ssh_liaison_mcp::telemetry::OtlpGuard>> {
+    use ssh_liaison_mcp::telemetry;
+    let registry = stderr_registry(cli.verbose);
+    match telemetry::otlp_layer(cli.otlp_endpoint.as_deref())? {
+        Some(otlp) => {
+            registry.with(otlp.layer.with_filter(otlp.filter)).init();
+            Ok(Some(otlp.guard))
+        }
+        None => {
+            registry.init();
+            Ok(None)
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
 
-    init_tracing(cli.verbose);
+    #[cfg(feature = "otel")]
+    let telemetry = init_tracing(&cli)?;
+    #[cfg(not(feature = "otel"))]
+    init_tracing(&cli);
 
     match cli.command {
-        Commands::Serve => {
-            mcp::run_mcp_server().await?;
+        Commands::Serve {
+            config,
+            allow_hosts,
+            read_only,
+            allow_commands,
+            deny_commands,
+            require_approval,
+            audit_log,
+            redact_patterns,
+            redact_entropy,
+            no_redaction,
+            record,
+            record_dir,
+            http,
+            http_token,
+            inventory,
+            no_reconnect,
+            restore_sessions,
+            no_session_store,
+            history_file,
+            no_history_file,
+            reconnect_attempts,
+            reconnect_backoff_ms,
+            keepalive_interval,
+            backend,
+            max_sessions,
+            auto_connect,
+            dry_run,
+            ansi,
+            host_rate_limit,
+            global_rate_limit,
+            queue_depth,
+            cache_ttl,
+            max_output_bytes,
+            transfer_rate_limit,
+        } => {
+            let config = config::ServerConfig::load_or_default(config.as_deref())?;
+            let mut policy = policy::CommandPolicy::new()
+                .read_only(read_only || config.policy.read_only)
+                .require_approval(require_approval || config.policy.require_approval);
+            for pattern in config.policy.allow_commands.iter().chain(&allow_commands) {
+                policy = policy.allow_pattern(pattern)?;
+            }
+            for pattern in config.policy.deny_commands.iter().chain(&deny_commands) {
+                policy = policy.deny_pattern(pattern)?;
+            }
+            for (tag, settings) in &config.policy.tags {
+                let mut rules = policy::TagRules::new()
+                    .read_only(settings.read_only)
+                    .require_approval(settings.require_approval);
+                for pattern in &settings.deny_commands {
+                    rules = rules.deny_pattern(pattern)?;
+                }
+                policy = policy.tag_rules(tag, rules);
+            }
+            let redactor = if no_redaction || config.redaction.enabled == Some(false) {
+                redact::Redactor::disabled()
+            } else {
+                let mut redactor = redact::Redactor::new();
+                for pattern in config.redaction.patterns.iter().chain(&redact_patterns) {
+                    redactor = redactor.pattern(pattern)?;
+                }
+                match redact_entropy.or(config.redaction.entropy_threshold) {
+                    Some(threshold) => redactor.entropy(redact::EntropyRule {
+                        threshold,
+                        min_length: config
+                            .redaction
+                            .entropy_min_length
+                            .unwrap_or(redact::DEFAULT_ENTROPY_MIN_LENGTH),
+                    }),
+                    None => redactor,
+                }
+            };
+            let defaults = RecordingOptions::default();
+            let recording = RecordingOptions {
+                dir: record_dir
+                    .or(config.recording.directory.clone())
+                    .unwrap_or(defaults.dir),
+                all_sessions: record || config.recording.enabled,
+            };
+            let inventory = inventory::Inventory::load_or_default(
+                inventory.or(config.inventory.clone()).as_deref(),
+            )?;
+            let health = config.health.monitor(&inventory)?;
+            let templates = config.command_templates(&inventory)?;
+            let plugins = mcp::plugins::load(&config.plugins.executables).await?;
+            let reconnect = if no_reconnect || config.reconnect.enabled == Some(false) {
+                ReconnectPolicy::disabled()
+            } else {
+                let defaults = ReconnectPolicy::default();
+                ReconnectPolicy {
+                    max_attempts: reconnect_attempts
+                        .or(config.reconnect.attempts)
+                        .unwrap_or(defaults.max_attempts),
+                    initial_backoff: reconnect_backoff_ms
+                        .or(config.reconnect.backoff_ms)
+                        .map_or(defaults.initial_backoff, Duration::from_millis),
+                    ..defaults
+                }
+            };
+            let session_file = if no_session_store || config.sessions.persist == Some(false) {
+                None
+            } else {
+                Some(
+                    config
+                        .sessions
+                        .file
+                        .clone()
+                        .unwrap_or_else(SessionStore::default_path),
+                )
+            };
+            let history_file = if no_history_file || config.history.persist == Some(false) {
+                None
+            } else {
+                Some(
+                    history_file
+                        .or(config.history.file.clone())
+                        .unwrap_or_else(CommandHistory::default_path),
+                )
+            };
+            let mut channel = config.channel_options()?;
+            if let Some(max_output_bytes) = max_output_bytes {
+                channel.max_output_bytes = max_output_bytes;
+            }
+            mcp::run_mcp_server(mcp::ServerOptions {
+                policy,
+                audit_log: audit_log.or(config.audit_log.clone()),
+                http: http.or(config.http),
+                http_token: http_token
+                    .or_else(|| std::env::var(mcp::HTTP_TOKEN_ENV).ok())
+                    .or(config.http_token.clone())
+                    .filter(|token| !token.is_empty()),
+                inventory,
+                reconnect,
+                keepalive_interval: keepalive_interval.or(config.keepalive_interval),
+                backend: backend.or(config.backend),
+                max_sessions: max_sessions.or(config.max_sessions),
+                auto_connect: auto_connect || config.auto_connect,
+                dry_run: dry_run || config.dry_run,
+                ansi: ansi.or(config.ansi).unwrap_or_default(),
+                rate_limits: mcp::rate_limit::RateLimits {
+                    per_host: host_rate_limit.or(config.rate_limit.per_host),
+                    global: global_rate_limit.or(config.rate_limit.global),
+                },
+                queue_depth: queue_depth.or(config.queue_depth),
+                cache_ttl: cache_ttl.or(config.cache_ttl),
+                channel,
+                allowed_hosts: config
+                    .allowed_hosts
+                    .iter()
+                    .cloned()
+                    .chain(allow_hosts)
+                    .collect(),
+                host_channels: config.host_channel_options(channel),
+                host_transports: config.host_transports(),
+                host_tags: config.tags,
+                config_file: config.source,
+                redactor,
+                recording,
+                vault: config.vault,
+                auth_order: config.auth_order,
+                transfer_rate_limit: transfer_rate_limit.or(config.transfer_rate_limit),
+                session_file,
+                history_file,
+                health,
+                templates,
+                plugins,
+                restore_sessions: restore_sessions || config.sessions.restore,
+            })
+            .await?;
         }
         Commands::Cli {
             host,
@@ -86,55 +420,29 @@ async fn main() -> Result<()> {
             hostname,
             password,
             port,
+            audit_log,
+            script,
+            continue_on_error,
+        } => {
+            let script = script.map(|path| cli::ScriptOptions {
+                path,
+                continue_on_error,
+            });
+            cli::run_cli_mode(host, user, hostname, password, port, audit_log, script).await?;
+        }
+        Commands::Exec {
+            host,
+            force,
+            command,
         } => {
-            cli::run_cli_mode(host, user, hostname, password, port).await?;
+            let code = cli::run_exec(&host, &command.join(" "), force).await?;
+            // `exit` skips destructors; flush the spans first.
+            #[cfg(feature = "otel")]
+            drop(telemetry);
+            std::process::exit(code);
         }
         Commands::Connect { user, host, port } => {
-            let manager = ssh::SessionManager::new();
-            tracing::info!(user = %user, host = %host, port = %port, "Connecting");
-            manager
-                .connect_direct("direct", &user, &host, Some(port))
-                .await
-                .with_context(|| format!("Failed to connect to {}@{}:{}", user, host, port))?;
-            tracing::info!("Connected successfully");
-
-            loop {
-                print!("ssh> ");
-                std::io::Write::flush(&mut std::io::stdout())?;
-                let mut input = String::new();
-                std::io::stdin().read_line(&mut input)?;
-                let command = input.trim();
-
-                if command.is_empty() {
-                    continue;
-                }
-
-                if command == "exit" || command == "quit" {
-                    break;
-                }
-
-                match manager.execute_command("direct", command, None).await {
-                    Ok(output) => {
-                        if !output.stdout.trim().is_empty() {
-                            print!("{}", output.stdout.trim_end());
-                            if !output.stdout.trim_end().ends_with('\n') {
-                                println!();
-                            }
-                        }
-                        if !output.stderr.trim().is_empty() {
-                            eprint!("{}", output.stderr.trim_end());
-                            if !output.stderr.trim_end().ends_with('\n') {
-                                eprintln!();
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        tracing::error!(error = %e, "Command execution failed");
-                    }
-                }
-            }
-
-            manager.disconnect("direct").await?;
+            cli::run_legacy_connect(user, host, port).await?;
         }
     }
 
@@ -0,0 +1,136 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::ssh::channel::CommandOutput;
+
+/// Results kept at most; expired ones are dropped first, then the oldest.
+const MAX_CACHED_RESULTS: usize = 256;
+
+/// What a cached result depends on: the command and the shell state it ran in.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub owner: Option<String>,
+    pub host: String,
+    pub channel: Option<String>,
+    pub command: String,
+    pub cwd: String,
+    pub env: BTreeMap<String, String>,
+}
+
+struct CachedResult {
+    stored_at: Instant,
+    output: CommandOutput,
+}
+
+/// Redacted results of read-only commands, reused until they are `ttl` old.
+/// A zero TTL disables the cache.
+#[derive(Default)]
+pub struct ResultCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<CacheKey, CachedResult>>,
+}
+
+impl ResultCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.ttl.is_zero()
+    }
+
+    /// The cached output for `key` and its age, unless it has expired.
+    pub fn get(&self, key: &CacheKey) -> Option<(CommandOutput, Duration)> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let age = entries.get(key)?.stored_at.elapsed();
+        if age >= self.ttl {
+            entries.remove(key);
+            return None;
+        }
+        entries.get(key).map(|cached| (cached.output.clone(), age))
+    }
+
+    pub fn insert(&self, key: CacheKey, output: CommandOutput) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() >= MAX_CACHED_RESULTS && !entries.contains_key(&key) {
+            entries.retain(|_, cached| cached.stored_at.elapsed() < self.ttl);
+            if entries.len() >= MAX_CACHED_RESULTS
+                && let Some(oldest) = entries
+                    .iter()
+                    .min_by_key(|(_, cached)| cached.stored_at)
+                    .map(|(key, _)| key.clone())
+            {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(
+            key,
+            CachedResult {
+                stored_at: Instant::now(),
+                output,
+            },
+        );
+    }
+
+    /// Drops the results for `host` in `owner`'s namespace, after a command
+    /// that may have changed what they show.
+    pub fn invalidate(&self, owner: Option<&str>, host: &str) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.retain(|key, _| key.owner.as_deref() != owner || key.host != host);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(host: &str, command: &str, cwd: &str) -> CacheKey {
+        CacheKey {
+            owner: None,
+            host: host.to_string(),
+            channel: None,
+            command: command.to_string(),
+            cwd: cwd.to_string(),
+            env: BTreeMap::new(),
+        }
+    }
+
+    fn output(stdout: &str) -> CommandOutput {
+        CommandOutput {
+            stdout: stdout.to_string(),
+            exit_code: Some(0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_result_cache() {
+        let cache = ResultCache::new(Duration::from_millis(200));
+        assert!(cache.is_enabled());
+        cache.insert(key("web", "ls", "/etc"), output("hosts\n"));
+        cache.insert(key("db", "uname", "/"), output("Linux\n"));
+
+        let (hit, _) = cache.get(&key("web", "ls", "/etc")).unwrap();
+        assert_eq!(hit.stdout, "hosts\n");
+        assert!(cache.get(&key("web", "ls", "/var")).is_none());
+
+        cache.invalidate(None, "web");
+        assert!(cache.get(&key("web", "ls", "/etc")).is_none());
+        assert!(cache.get(&key("db", "uname", "/")).is_some());
+
+        std::thread::sleep(Duration::from_millis(250));
+        assert!(cache.get(&key("db", "uname", "/")).is_none());
+
+        let disabled = ResultCache::new(Duration::ZERO);
+        assert!(!disabled.is_enabled());
+        disabled.insert(key("web", "ls", "/"), output("x"));
+        assert!(disabled.get(&key("web", "ls", "/")).is_none());
+    }
+}
@@ -0,0 +1,96 @@
+//! Argument completion (`completion/complete`) for host aliases, so clients
+//! filling in a prompt's `host` or a resource's `{alias}` can offer the names
+//! that actually exist instead of letting the model guess.
+
+use rmcp::ErrorData as McpError;
+use rmcp::model::{CompleteRequestParam, CompleteResult, CompletionInfo};
+
+use crate::ssh::SessionManager;
+use crate::ssh::config::list_ssh_hosts;
+
+/// Argument names completed with host aliases.
+const HOST_ARGUMENTS: &[&str] = &["host", "host_alias", "alias"];
+
+pub async fn complete(
+    session_manager: &SessionManager,
+    request: &CompleteRequestParam,
+) -> Result<CompleteResult, McpError> {
+    if !HOST_ARGUMENTS.contains(&request.argument.name.as_str()) {
+        return Ok(CompleteResult::default());
+    }
+
+    // Connected hosts come first: they are the likeliest targets.
+    let mut candidates: Vec<String> = session_manager
+        .list_connections()
+        .await
+        .into_iter()
+        .filter(|info| info.is_own)
+        .map(|info| info.alias)
+        .collect();
+    match tokio::task::spawn_blocking(list_ssh_hosts).await {
+        Ok(Ok(hosts)) => candidates.extend(
+            hosts
+                .into_iter()
+                .filter(|h| {
+                    session_manager.is_host_allowed(&[&h.host, h.hostname.as_deref().unwrap_or("")])
+                })
+                .map(|h| h.host),
+        ),
+        Ok(Err(e)) => tracing::debug!(error = %e, "No ~/.ssh/config hosts to complete"),
+        Err(e) => tracing::debug!(error = %e, "Reading ~/.ssh/config for completion failed"),
+    }
+
+    Ok(CompleteResult {
+        completion: completion_info(&request.argument.value, candidates),
+    })
+}
+
+/// Candidates starting with `value` (ignoring case), then those merely
+/// containing it, without duplicates and capped at the protocol's limit.
+fn completion_info(value: &str, candidates: Vec<String>) -> CompletionInfo {
+    let needle = value.to_lowercase();
+    let mut prefixed = Vec::new();
+    let mut containing = Vec::new();
+    for candidate in candidates {
+        if prefixed.contains(&candidate) || containing.contains(&candidate) {
+            continue;
+        }
+        let lower = candidate.to_lowercase();
+        if lower.starts_with(&needle) {
+            prefixed.push(candidate);
+        } else if lower.contains(&needle) {
+            containing.push(candidate);
+        }
+    }
+    prefixed.append(&mut containing);
+
+    let total = prefixed.len();
+    prefixed.truncate(CompletionInfo::MAX_VALUES);
+    CompletionInfo {
+        values: prefixed,
+        total: u32::try_from(total).ok(),
+        has_more: Some(total > CompletionInfo::MAX_VALUES),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completion_order() {
+        let candidates = ["db-1", "prod-web1", "Prod-db", "db-1", "staging"]
+            .map(String::from)
+            .to_vec();
+        let info = completion_info("prod", candidates.clone());
+        assert_eq!(info.values, ["prod-web1", "Prod-db"]);
+        assert_eq!(info.total, Some(2));
+
+        let info = completion_info("db", candidates.clone());
+        assert_eq!(info.values, ["db-1", "Prod-db"]);
+
+        let info = completion_info("", candidates);
+        assert_eq!(info.values.len(), 4);
+        assert_eq!(info.has_more, Some(false));
+    }
+}
@@ -1,22 +1,199 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use rmcp::{
-    ErrorData as McpError, ServiceExt,
-    handler::server::{router::tool::ToolRouter, wrapper::Parameters},
-    model::{CallToolResult, ServerCapabilities, ServerInfo},
-    tool, tool_handler, tool_router,
-    transport::stdio,
+    ErrorData as McpError, Peer, RoleServer, ServiceExt,
+    handler::server::{router::tool::ToolRouter, tool::ToolCallContext, wrapper::Parameters},
+    model::{
+        CallToolRequestParam, CallToolResult, CompleteRequestParam, CompleteResult,
+        GetPromptRequestParam, GetPromptResult, ListPromptsResult, ListResourceTemplatesResult,
+        ListResourcesResult, ListToolsResult, Meta, PaginatedRequestParam,
+        ReadResourceRequestParam, ReadResourceResult, ServerCapabilities, ServerInfo,
+        SubscribeRequestParam, UnsubscribeRequestParam,
+    },
+    service::{NotificationContext, RequestContext},
+    tool, tool_router,
+    transport::{
+        stdio,
+        streamable_http_server::{
+            StreamableHttpServerConfig, StreamableHttpService, session::local::LocalSessionManager,
+        },
+    },
 };
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument;
 
+use crate::audit::AuditLog;
+use crate::history::CommandHistory;
+use crate::inventory::Inventory;
+use crate::policy::CommandPolicy;
+use crate::redact::{self, Redactor};
 use crate::ssh::SessionManager;
+use crate::ssh::ansi::AnsiMode;
+use crate::ssh::auth::AuthMethod;
+use crate::ssh::auth::vault::VaultConfig;
+use crate::ssh::backend::Backend;
+use crate::ssh::channel::ChannelOptions;
+use crate::ssh::health::HealthMonitor;
+use crate::ssh::reconnect::ReconnectPolicy;
+use crate::ssh::recording::RecordingOptions;
+use crate::ssh::session::{DEFAULT_KEEPALIVE_SECS, DEFAULT_MAX_SESSIONS};
+use crate::ssh::store::SessionStore;
+use crate::ssh::transfer::RateLimiter;
+use crate::ssh::transport::Transport;
 
+pub mod cache;
+pub mod completion;
+pub mod output;
+pub mod parsers;
+pub mod plugins;
+pub mod prompts;
+pub mod queue;
+pub mod rate_limit;
+pub mod resources;
+pub mod templates;
 pub mod tools;
-use tools::{SshConnectDirectParams, SshConnectParams, SshReadLogParams, SshRunCommandParams};
+use output::OutputStore;
+use plugins::PluginTool;
+use queue::{CommandQueues, DEFAULT_QUEUE_DEPTH, QueueKey};
+use rate_limit::{CommandRateLimiter, RateLimits};
+use templates::CommandTemplate;
+use tools::{
+    SshCancelCommandParams, SshChecksumParams, SshCompareFilesParams, SshConnectDirectParams,
+    SshConnectParams, SshCopyBetweenHostsParams, SshCwdParams, SshDiskUsageParams,
+    SshDockerInspectParams, SshDockerLogsParams, SshDockerPsParams, SshDockerStatsParams,
+    SshEnvParams, SshFetchOutputParams, SshFindFilesParams, SshGenerateKeypairParams,
+    SshGrepParams, SshHealthReportParams, SshHistoryParams, SshInstallPublicKeyParams,
+    SshJournalctlParams, SshK8sNodeTriageParams, SshKeyscanParams, SshKnownHostsAddParams,
+    SshKnownHostsListParams, SshKnownHostsRemoveParams, SshListDirParams, SshNetworkInfoParams,
+    SshPackageQueryParams, SshPortCheckParams, SshProcessListParams, SshReadFileParams,
+    SshReadLogParams, SshRerunParams, SshResetSessionParams, SshRestoreSessionsParams,
+    SshRunCommandParams, SshRunOnHostsParams, SshRunScriptParams, SshScheduleCancelParams,
+    SshScheduleParams, SshSocksProxyParams, SshTransferParams, SshTunnelCloseParams,
+    SshTunnelOpenParams, SshWatchLogParams, SshWatchStopParams, SshWriteFileParams,
+};
+
+/// Settings for `run_mcp_server`, filled from the `serve` subcommand.
+#[derive(Default)]
+pub struct ServerOptions {
+    pub policy: CommandPolicy,
+    /// JSON Lines file receiving approval decisions.
+    pub audit_log: Option<PathBuf>,
+    /// Serve streamable HTTP on this address instead of stdio.
+    pub http: Option<SocketAddr>,
+    /// Bearer token HTTP clients must send; required off loopback.
+    pub http_token: Option<String>,
+    pub inventory: Inventory,
+    pub reconnect: ReconnectPolicy,
+    /// Keepalive seconds for hosts without `ServerAliveInterval` (default 30, 0 disables).
+    pub keepalive_interval: Option<u32>,
+    /// SSH library sessions are opened with (default libssh2).
+    pub backend: Option<Backend>,
+    /// Most sessions open at once (default 16, 0 is unlimited).
+    pub max_sessions: Option<usize>,
+    /// Connect `~/.ssh/config` aliases on their first `ssh_run_command`.
+    pub auto_connect: bool,
+    /// `ssh_run_command` and the tools built on it only report what they would run.
+    pub dry_run: bool,
+    /// What `ssh_run_command` does with colors when the call doesn't say.
+    pub ansi: AnsiMode,
+    /// Commands per minute allowed per host and across all hosts.
+    pub rate_limits: RateLimits,
+    /// Commands that may wait behind the running one on a shell (default 8).
+    pub queue_depth: Option<usize>,
+    /// Seconds read-only command results are reused; `None` or 0 disables the cache.
+    pub cache_ttl: Option<u64>,
+    /// Channel timeouts and buffer sizes for hosts that don't set their own.
+    pub channel: ChannelOptions,
+    /// Host patterns sessions may be opened to; empty allows any host.
+    pub allowed_hosts: Vec<String>,
+    /// Channel options of single host aliases.
+    pub host_channels: HashMap<String, ChannelOptions>,
+    /// SSM, EC2 Instance Connect, or direct TCP for single host aliases.
+    pub host_transports: HashMap<String, Transport>,
+    /// Host patterns of each tag, for tag-specific policy rules.
+    pub host_tags: BTreeMap<String, Vec<String>>,
+    /// Config file the options were read from, shown at startup.
+    pub config_file: Option<PathBuf>,
+    /// Masks secrets in tool output and logs.
+    pub redactor: Redactor,
+    /// Where session recordings go and whether every session is recorded.
+    pub recording: RecordingOptions,
+    /// Hosts whose credentials come from Vault's SSH secrets engine.
+    pub vault: VaultConfig,
+    /// Order auth methods are tried in; `None` keeps the default.
+    pub auth_order: Option<Vec<AuthMethod>>,
+    /// Bytes per second all file transfers share; `None` is unlimited.
+    pub transfer_rate_limit: Option<u64>,
+    /// File open sessions are saved to; `None` doesn't save them.
+    pub session_file: Option<PathBuf>,
+    /// Re-open the saved sessions at startup (stdio only).
+    pub restore_sessions: bool,
+    /// File the command history is appended to; `None` keeps it in memory.
+    pub history_file: Option<PathBuf>,
+    /// Probes run on an interval against inventory hosts.
+    pub health: HealthMonitor,
+    /// Commands served as tools of their own.
+    pub templates: Vec<CommandTemplate>,
+    /// Tools served by external executables.
+    pub plugins: Vec<PluginTool>,
+}
+
+/// Path the streamable HTTP endpoint is mounted at.
+const HTTP_MCP_PATH: &str = "/mcp";
 
+/// Environment variable with the HTTP bearer token, an alternative to
+/// `--http-token` that keeps it out of the process list.
+pub const HTTP_TOKEN_ENV: &str = "SSH_LIAISON_HTTP_TOKEN";
+
+/// How long shutdown waits for SSH sessions to close before exiting anyway.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+/// MCP server handler exposing the SSH tools. Clones share sessions and settings.
+#[derive(Clone)]
 pub struct SshMcpServer {
     session_manager: SessionManager,
+    policy: Arc<CommandPolicy>,
+    audit: Arc<AuditLog>,
+    redactor: Arc<Redactor>,
+    inventory: Arc<Inventory>,
+    outputs: Arc<OutputStore>,
+    health: HealthMonitor,
+    dry_run: bool,
+    ansi: AnsiMode,
+    rate_limiter: Arc<CommandRateLimiter>,
+    queues: Arc<CommandQueues>,
+    /// Set on scoped servers; the namespace is torn down with the last clone.
+    _namespace: Option<Arc<NamespaceGuard>>,
     tool_router: ToolRouter<Self>,
 }
 
+/// Closes a client's sessions, tunnels, watches and schedules when its MCP
+/// session ends and rmcp drops the handler.
+struct NamespaceGuard {
+    sessions: SessionManager,
+    queues: Arc<CommandQueues>,
+}
+
+impl Drop for NamespaceGuard {
+    fn drop(&mut self) {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let sessions = self.sessions.clone();
+        let queues = Arc::clone(&self.queues);
+        runtime.spawn(async move {
+            let closed = sessions.close_namespace().await;
+            queues.remove_namespace(sessions.namespace());
+            tracing::info!(namespace = ?sessions.namespace(), sessions = closed, "Client session ended");
+        });
+    }
+}
+
 impl Default for SshMcpServer {
     fn default() -> Self {
         Self::new()
@@ -25,19 +202,290 @@ impl Default for SshMcpServer {
 
 impl SshMcpServer {
     pub fn new() -> Self {
+        Self::with_policy(CommandPolicy::default(), AuditLog::disabled())
+    }
+
+    pub fn with_policy(policy: CommandPolicy, audit: AuditLog) -> Self {
         let session_manager = SessionManager::new();
         Self {
             session_manager,
+            policy: Arc::new(policy),
+            audit: Arc::new(audit),
+            redactor: Arc::new(Redactor::new()),
+            inventory: Arc::new(Inventory::default()),
+            outputs: Arc::new(OutputStore::new()),
+            health: HealthMonitor::default(),
+            dry_run: false,
+            ansi: AnsiMode::default(),
+            rate_limiter: Arc::new(CommandRateLimiter::default()),
+            queues: Arc::new(CommandQueues::default()),
+            _namespace: None,
             tool_router: Self::tool_router(),
         }
     }
+
+    pub fn with_redactor(mut self, redactor: Redactor) -> Self {
+        self.redactor = Arc::new(redactor);
+        self
+    }
+
+    pub fn with_inventory(mut self, inventory: Inventory) -> Self {
+        self.inventory = Arc::new(inventory);
+        self
+    }
+
+    /// Serves each template as a tool of its own.
+    pub fn with_templates(mut self, templates: Vec<CommandTemplate>) -> Self {
+        for template in templates {
+            self.tool_router.add_route(template.route());
+        }
+        self
+    }
+
+    /// Serves the tools of external executables.
+    pub fn with_plugins(mut self, plugins: Vec<PluginTool>) -> Self {
+        for plugin in plugins {
+            self.tool_router.add_route(plugin.route());
+        }
+        self
+    }
+
+    /// Reports the results of `health`'s probes; [`HealthMonitor::start`] runs them.
+    pub fn with_health(mut self, health: HealthMonitor) -> Self {
+        self.health = health;
+        self
+    }
+
+    /// Makes every `ssh_run_command` a dry run, whatever the call asks for.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Colors handling for `ssh_run_command` calls without an `ansi` of their own.
+    pub fn with_ansi(mut self, ansi: AnsiMode) -> Self {
+        self.ansi = ansi;
+        self
+    }
+
+    /// Refuses commands beyond `limits`, counted across every client.
+    pub fn with_rate_limits(mut self, limits: RateLimits) -> Self {
+        self.rate_limiter = Arc::new(CommandRateLimiter::new(limits));
+        self
+    }
+
+    /// Lets `depth` commands wait behind the running one on each shell.
+    pub fn with_queue_depth(mut self, depth: usize) -> Self {
+        self.queues = Arc::new(CommandQueues::new(depth));
+        self
+    }
+
+    /// Reuses results of read-only commands for `ttl`; zero disables the cache.
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.outputs = Arc::new(OutputStore::new().with_cache_ttl(ttl));
+        self
+    }
+
+    pub fn with_reconnect_policy(mut self, reconnect: ReconnectPolicy) -> Self {
+        self.session_manager = self.session_manager.with_reconnect_policy(reconnect);
+        self
+    }
+
+    pub fn with_keepalive_interval(mut self, secs: u32) -> Self {
+        self.session_manager = self.session_manager.with_keepalive_interval(secs);
+        self
+    }
+
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.session_manager = self.session_manager.with_backend(backend);
+        self
+    }
+
+    pub fn with_max_sessions(mut self, max_sessions: usize) -> Self {
+        self.session_manager = self.session_manager.with_max_sessions(max_sessions);
+        self
+    }
+
+    pub fn with_auto_connect(mut self, auto_connect: bool) -> Self {
+        self.session_manager = self.session_manager.with_auto_connect(auto_connect);
+        self
+    }
+
+    pub fn with_channel_options(mut self, options: ChannelOptions) -> Self {
+        self.session_manager = self.session_manager.with_channel_options(options);
+        self
+    }
+
+    /// Only connect to hosts matching `patterns`; empty allows any host.
+    pub fn with_allowed_hosts(mut self, patterns: Vec<String>) -> Self {
+        if !patterns.is_empty() {
+            self.session_manager = self.session_manager.with_allowed_hosts(patterns);
+        }
+        self
+    }
+
+    pub fn with_recording(mut self, recording: RecordingOptions) -> Self {
+        self.session_manager = self.session_manager.with_recording(recording);
+        self
+    }
+
+    pub fn with_vault(mut self, vault: VaultConfig) -> Self {
+        self.session_manager = self.session_manager.with_vault(vault);
+        self
+    }
+
+    pub fn with_transfer_rate_limit(mut self, limiter: RateLimiter) -> Self {
+        self.session_manager = self.session_manager.with_transfer_rate_limit(limiter);
+        self
+    }
+
+    pub fn with_session_store(mut self, store: SessionStore) -> Self {
+        self.session_manager = self.session_manager.with_session_store(store);
+        self
+    }
+
+    pub fn with_auth_order(mut self, order: Vec<AuthMethod>) -> Self {
+        self.session_manager = self.session_manager.with_auth_order(order);
+        self
+    }
+
+    pub fn with_host_channel_options(mut self, options: HashMap<String, ChannelOptions>) -> Self {
+        self.session_manager = self.session_manager.with_host_channel_options(options);
+        self
+    }
+
+    pub fn with_host_transports(mut self, transports: HashMap<String, Transport>) -> Self {
+        self.session_manager = self.session_manager.with_host_transports(transports);
+        self
+    }
+
+    pub fn with_host_tags(mut self, host_tags: BTreeMap<String, Vec<String>>) -> Self {
+        self.session_manager = self.session_manager.with_host_tags(host_tags);
+        self
+    }
+
+    /// Same server, with SSH sessions private to one client.
+    /// Once every clone of it is dropped, the namespace is closed.
+    pub fn scoped(&self, namespace: impl Into<String>) -> Self {
+        let session_manager = self.session_manager.scoped(namespace);
+        let guard = NamespaceGuard {
+            sessions: session_manager.clone(),
+            queues: Arc::clone(&self.queues),
+        };
+        Self {
+            session_manager,
+            _namespace: Some(Arc::new(guard)),
+            ..self.clone()
+        }
+    }
+
+    /// Runs a command built by a template or plugin like `ssh_run_command`.
+    async fn run_tool_command(
+        &self,
+        context: &RequestContext<RoleServer>,
+        host: String,
+        command: String,
+    ) -> Result<CallToolResult, McpError> {
+        let params = SshRunCommandParams {
+            host,
+            command,
+            // Output parsers expect plain text.
+            ansi: Some(AnsiMode::Strip),
+            ..Default::default()
+        };
+        self.run_command(context.peer.clone(), &context.meta, params)
+            .await
+    }
+
+    /// Runs `params` after the rate limit and the shell's queue let it;
+    /// dry runs skip both.
+    async fn run_command(
+        &self,
+        peer: Peer<RoleServer>,
+        meta: &Meta,
+        mut params: SshRunCommandParams,
+    ) -> Result<CallToolResult, McpError> {
+        if self.dry_run {
+            params.dry_run = Some(true);
+        }
+        params.ansi.get_or_insert(self.ansi);
+        let slot = if params.dry_run == Some(true) {
+            None
+        } else {
+            self.check_rate_limit(&[&params.host]).await?;
+            let key = QueueKey::new(
+                self.session_manager.namespace(),
+                &params.host,
+                params.channel.as_deref(),
+            );
+            let progress = tools::queue_progress(&peer, meta, &params.host);
+            let slot = self.queues.enter(key, progress).await.map_err(|full| {
+                tracing::warn!(host = %full.host, depth = full.depth, "Command queue full");
+                tools::queue_full_error(&full)
+            })?;
+            Some(slot)
+        };
+        let result = tools::ssh_run_command_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.audit,
+            &self.redactor,
+            &self.outputs,
+            peer,
+            Parameters(params),
+        )
+        .await;
+        match slot {
+            Some(slot) if slot.waited() >= Duration::from_millis(1) => {
+                result.map(|result| tools::with_queued(result, slot.waited()))
+            }
+            _ => result,
+        }
+    }
+
+    /// Counts a command on each of `hosts`, or fails with `rate_limited`.
+    async fn check_rate_limit(&self, hosts: &[&str]) -> Result<(), McpError> {
+        tools::check_rate_limit(&self.rate_limiter, &self.session_manager, hosts).await
+    }
+
+    async fn set_subscribed(&self, uri: &str, subscribed: bool) -> Result<(), McpError> {
+        let found = if let Some(id) = resources::watch_id(uri) {
+            self.session_manager
+                .set_watch_subscribed(id, subscribed)
+                .await
+        } else if let Some(id) = resources::schedule_id(uri) {
+            self.session_manager
+                .set_schedule_subscribed(id, subscribed)
+                .await
+        } else {
+            return Err(McpError::invalid_params(
+                format!(
+                    "{} can't be subscribed to; only log watches and schedules change",
+                    uri
+                ),
+                None,
+            ));
+        };
+        if !found {
+            return Err(McpError::resource_not_found(
+                format!("No resource at {}", uri),
+                None,
+            ));
+        }
+        Ok(())
+    }
 }
 
 #[tool_router]
 impl SshMcpServer {
     #[tool(
         name = "ssh_connect",
-        description = "Connect to a remote SSH server using host alias from ~/.ssh/config. Establishes a persistent shell session that preserves state between commands. WARNING: Only use for read-only operations unless explicitly authorized."
+        description = "Connect to a remote SSH server using host alias from ~/.ssh/config. Establishes a persistent shell session that preserves state between commands. WARNING: Only use for read-only operations unless explicitly authorized.",
+        annotations(
+            read_only_hint = false,
+            destructive_hint = false,
+            open_world_hint = true
+        )
     )]
     pub async fn ssh_connect(
         &self,
@@ -48,7 +496,12 @@ impl SshMcpServer {
 
     #[tool(
         name = "ssh_connect_direct",
-        description = "Connect to a remote SSH server directly using user, hostname/IP, optional password, and optional port. Establishes a persistent shell session that preserves state between commands. If password is not provided or fails, SSH key authentication will be attempted. WARNING: Only use for read-only operations unless explicitly authorized. Password is transmitted securely over SSH."
+        description = "Connect to a remote SSH server directly using user, hostname/IP, optional password, and optional port. Establishes a persistent shell session that preserves state between commands. If password is not provided or fails, SSH key authentication will be attempted. WARNING: Only use for read-only operations unless explicitly authorized. Password is transmitted securely over SSH.",
+        annotations(
+            read_only_hint = false,
+            destructive_hint = false,
+            open_world_hint = true
+        )
     )]
     pub async fn ssh_connect_direct(
         &self,
@@ -59,32 +512,1006 @@ impl SshMcpServer {
 
     #[tool(
         name = "ssh_run_command",
-        description = "Execute a command on a connected SSH host. Commands run in a persistent shell session, so state (like current directory) is preserved between commands. Use sudo_password for commands that require sudo. 30s timeout. IMPORTANT: Use --no-pager for systemctl, journalctl, git log, etc., otherwise output will hang. Avoid interactive/pager commands (less, vim, top, htop) — they will hang. For commands that produce no stdout (e.g. systemctl restart): if timeout occurs, append `&& echo` or `; echo` to force output. WARNING: Destructive operations (rm, mv, etc.) should be avoided. Prefer read-only commands."
+        description = "Execute a command on a connected SSH host. Commands run in a persistent shell session, so state (like current directory) is preserved between commands; the directory the shell is in afterwards is returned in _meta as ssh-liaison/cwd. When the server has a result cache, repeated read-only commands are answered from it with ssh-liaison/cacheHit and ssh-liaison/cacheAgeMs in _meta; pass no_cache=true for a fresh result. When the server runs with auto-connect, an alias from ~/.ssh/config that isn't connected yet is connected first and ssh-liaison/autoConnected is set in _meta. If sudo asks for a password and none is given, the client is asked for it (elicitation); set cache_sudo_password to reuse it for the session. Pass stdin to feed data to the command's standard input (e.g. SQL to psql, content to tee, a diff to patch). 30s timeout; a command that times out is interrupted with Ctrl-C and the shell is resynchronized (or reopened) before the next one. IMPORTANT: Use --no-pager for systemctl, journalctl, git log, etc., and batch flags (top -bn1, ping -c 4). Interactive or never-ending commands (editors, pagers, top, htop, tail -f, watch, mysql/psql without a query) are refused with an interactive error whose data has the program and a non-interactive suggestion; a command that still switches to full-screen mode is interrupted with the same error. For commands that produce no stdout (e.g. systemctl restart): if timeout occurs, append `&& echo` or `; echo` to force output. WARNING: Destructive operations (rm, mv, etc.) should be avoided. Prefer read-only commands. Commands are checked against a server-side policy (deny patterns, optional allowlist and read-only mode); blocked commands return a policy_violation error. If the server requires approval for destructive commands, the client is asked to confirm (elicitation) and the decision is audited. Secrets (keys, tokens, passwords) in the output are masked as [REDACTED:<rule>] unless redact=false. Pass dry_run=true to get the exact line that would be sent, the target host, the policy decision and the redactions that would apply without running anything; a server started with --dry-run treats every call this way. Calls for the same host and channel run one at a time in arrival order; a waiting call gets progress notifications with its position and ETA when it has a progress token, its result has ssh-liaison/queuedMs in _meta, and a call arriving at a full queue fails with a queue_full error (use a named channel to run in parallel). When the server limits commands per minute, a command over the limit fails with a rate_limited error carrying retry_after_secs. A running command is listed with its id under running in ssh_list_connections; ssh_cancel_command interrupts it, and the call running it then fails with a cancelled error. Colors are stripped from the output unless ansi='keep' (raw ANSI codes) or ansi='convert' (**bold**, *italic* and [red]...[/red] annotations, e.g. for a colored diff or grep --color=always). Binary output (cat image.png, tar cz) is returned as is with is_binary=true, mime_type and binary_bytes: up to 64 KiB as an image or base64 resource block, larger as a local file whose path is given.",
+        annotations(destructive_hint = true, open_world_hint = true)
     )]
     pub async fn ssh_run_command(
         &self,
+        peer: Peer<RoleServer>,
+        meta: Meta,
         params: Parameters<SshRunCommandParams>,
     ) -> Result<CallToolResult, McpError> {
-        tools::ssh_run_command_impl(&self.session_manager, params).await
+        self.run_command(peer, &meta, params.0).await
+    }
+
+    #[tool(
+        name = "ssh_cancel_command",
+        description = "Interrupt a running ssh_run_command with Ctrl-C and return the output it printed so far. Get the id from the running list of ssh_list_connections. The shell stays open and keeps its directory and variables.",
+        annotations(
+            destructive_hint = false,
+            idempotent_hint = true,
+            open_world_hint = true
+        )
+    )]
+    pub async fn ssh_cancel_command(
+        &self,
+        params: Parameters<SshCancelCommandParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_cancel_command_impl(&self.session_manager, &self.redactor, params).await
+    }
+
+    #[tool(
+        name = "ssh_reset_session",
+        description = "Bring a session's shell (or a named channel) back to a clean prompt when commands return stale or garbled output, e.g. after an interactive program took over the shell. Interrupts any command running in it, sends a newline and waits for a fresh marker; if the shell doesn't answer, or reopen=true, it is replaced with a new one, which resets its directory and variables. The SSH connection itself stays up.",
+        annotations(
+            destructive_hint = false,
+            idempotent_hint = true,
+            open_world_hint = true
+        )
+    )]
+    pub async fn ssh_reset_session(
+        &self,
+        params: Parameters<SshResetSessionParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_reset_session_impl(&self.session_manager, params).await
+    }
+
+    #[tool(
+        name = "ssh_fetch_output",
+        description = "Fetch the next page of a large ssh_run_command output. ssh_run_command returns at most max_bytes of stdout (default 64KB) and, when more remains, a next_cursor; pass it here to continue. Optional offset jumps to a byte position. Only the 16 most recent large outputs are kept.",
+        annotations(read_only_hint = true, open_world_hint = false)
+    )]
+    pub async fn ssh_fetch_output(
+        &self,
+        params: Parameters<SshFetchOutputParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_fetch_output_impl(&self.session_manager, &self.outputs, params).await
+    }
+
+    #[tool(
+        name = "ssh_cwd",
+        description = "Get or change the working directory of a session's persistent shell (or a named channel). Without path it returns the directory tracked for the shell, asking the host with pwd only when none is known yet; with path it cd's there and returns the resulting absolute directory. Later ssh_run_command calls in the same shell run there.",
+        annotations(
+            destructive_hint = false,
+            idempotent_hint = true,
+            open_world_hint = true
+        )
+    )]
+    pub async fn ssh_cwd(
+        &self,
+        params: Parameters<SshCwdParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_cwd_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.rate_limiter,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_env",
+        description = "Inspect or change environment variables of a session's persistent shell (or a named channel). set exports variables and unset removes them, for every later command in that shell; variables set here are tracked and always returned without a round trip. names reads the current values of other variables from the shell. Setting variables is checked by the server policy; values read are masked for secrets unless redact=false.",
+        annotations(
+            destructive_hint = false,
+            idempotent_hint = true,
+            open_world_hint = true
+        )
+    )]
+    pub async fn ssh_env(
+        &self,
+        peer: Peer<RoleServer>,
+        params: Parameters<SshEnvParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_env_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.rate_limiter,
+            &self.audit,
+            &self.redactor,
+            peer,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_run_on_hosts",
+        description = "Run the same command on several hosts concurrently (e.g. check disk space on web1..web10). Target hosts by alias and/or inventory group ('groups', or '@group' in 'hosts'). Hosts that aren't connected yet are connected via ~/.ssh/config. Returns a JSON object keyed by host alias with success, output or error for each host; one failing host doesn't fail the others. max_concurrency limits parallelism (default 8). The command is checked against the server policy once; sudo password prompts are not supported here.",
+        annotations(destructive_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_run_on_hosts(
+        &self,
+        peer: Peer<RoleServer>,
+        params: Parameters<SshRunOnHostsParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let targets: Vec<String> = params
+            .0
+            .hosts
+            .iter()
+            .cloned()
+            .chain(params.0.groups.iter().map(|g| format!("@{}", g)))
+            .collect();
+        // Bad targets are reported by the tool itself.
+        if let Ok(hosts) = self.inventory.expand_targets(&targets) {
+            let hosts: Vec<&str> = hosts.iter().map(String::as_str).collect();
+            self.check_rate_limit(&hosts).await?;
+        }
+        tools::ssh_run_on_hosts_impl(
+            &self.session_manager,
+            &self.inventory,
+            &self.policy,
+            &self.audit,
+            &self.redactor,
+            peer,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_list_hosts",
+        description = "List host aliases defined in ~/.ssh/config (wildcard patterns excluded) with their resolved user, hostname and port. Use it to find the right alias for ssh_connect.",
+        annotations(read_only_hint = true, open_world_hint = false)
+    )]
+    pub async fn ssh_list_hosts(&self) -> Result<CallToolResult, McpError> {
+        tools::ssh_list_hosts_impl().await
+    }
+
+    #[tool(
+        name = "ssh_list_groups",
+        description = "List host groups from the inventory file with their member host aliases. Use group names with ssh_run_on_hosts.",
+        annotations(read_only_hint = true, open_world_hint = false)
+    )]
+    pub async fn ssh_list_groups(&self) -> Result<CallToolResult, McpError> {
+        tools::ssh_list_groups_impl(&self.inventory).await
+    }
+
+    #[tool(
+        name = "ssh_list_connections",
+        description = "List active SSH sessions with their connect time, auth method, remote OS and tags, and the commands running in your sessions with the ids ssh_cancel_command takes. When several MCP clients share this server (HTTP mode), each client has its own sessions: yours are marked, others show the owning client and can't be used.",
+        annotations(read_only_hint = true, open_world_hint = false)
+    )]
+    pub async fn ssh_list_connections(&self) -> Result<CallToolResult, McpError> {
+        tools::ssh_list_connections_impl(&self.session_manager).await
+    }
+
+    #[tool(
+        name = "ssh_restore_sessions",
+        description = "Re-open the sessions that were open before the server restarted, with the same host settings and tags. Sessions opened with a password are reported as needs-password and must be connected again with ssh_connect_direct. With forget=true, removes saved sessions instead.",
+        annotations(
+            destructive_hint = false,
+            idempotent_hint = true,
+            open_world_hint = true
+        )
+    )]
+    pub async fn ssh_restore_sessions(
+        &self,
+        params: Parameters<SshRestoreSessionsParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_restore_sessions_impl(&self.session_manager, params).await
+    }
+
+    #[tool(
+        name = "ssh_history",
+        description = "List the commands already run on connected hosts through ssh_run_command, ssh_run_script and ssh_run_on_hosts, newest first, with their time, exit code, duration and last output line. Filter by host and by a regex on the command. Use it to check what was already done on a host before running something again.",
+        annotations(read_only_hint = true, open_world_hint = false)
+    )]
+    pub async fn ssh_history(
+        &self,
+        params: Parameters<SshHistoryParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_history_impl(&self.session_manager, &self.audit, params).await
+    }
+
+    #[tool(
+        name = "ssh_rerun",
+        description = "Run a command from ssh_history again, on the same host and channel, and return a unified diff of its output against the recorded run, e.g. to see whether an error count grew since the last check. The command is checked against the policy again. Outputs of the latest 32 history entries are kept for diffing; for older ones the new output is returned instead. Scripts can't be re-run.",
+        annotations(destructive_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_rerun(
+        &self,
+        peer: Peer<RoleServer>,
+        params: Parameters<SshRerunParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let namespace = self.session_manager.namespace();
+        if let Some(previous) = self.audit.history().get(namespace, params.0.id) {
+            self.check_rate_limit(&[&previous.entry.host]).await?;
+        }
+        tools::ssh_rerun_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.audit,
+            &self.redactor,
+            &self.outputs,
+            peer,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_read_file",
+        description = "Read a file on a connected SSH host without shell quoting issues. Uses SFTP when available, otherwise base64 over the shell. Supports a byte window (offset, length; at most 1MB per call) and a 1-based inclusive line range (start_line, end_line) within that window. Returns the content (utf-8, or base64 for binary data) and whether more data follows.",
+        annotations(read_only_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_read_file(
+        &self,
+        params: Parameters<SshReadFileParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_read_file_impl(&self.session_manager, &self.policy, &self.redactor, params).await
+    }
+
+    #[tool(
+        name = "ssh_run_script",
+        description = "Run a multi-line script on a connected SSH host. The script is uploaded to a private temp file, run with the given interpreter (default bash; e.g. 'bash -e', 'python3', 'sudo sh') and removed afterwards, so heredocs, quotes and long scripts work where ssh_run_command would break. Every line of a shell script is checked by the server policy, with backslash continuations joined; scripts for other interpreters (e.g. python3) can't be checked that way, so read-only and allowlist mode refuse them and approval mode asks a human first. Returns stdout, stderr, exit_code and duration_ms like ssh_run_command, with paging via ssh_fetch_output.",
+        annotations(destructive_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_run_script(
+        &self,
+        peer: Peer<RoleServer>,
+        params: Parameters<SshRunScriptParams>,
+    ) -> Result<CallToolResult, McpError> {
+        self.check_rate_limit(&[&params.0.host]).await?;
+        tools::ssh_run_script_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.audit,
+            &self.redactor,
+            &self.outputs,
+            peer,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_list_dir",
+        description = "List a directory on a connected SSH host. Returns structured entries (name, kind: file/directory/symlink/other, size, mtime as Unix seconds, mode and permissions) read over SFTP, so the result doesn't depend on the remote locale or ls flavor. Falls back to GNU find/stat over the shell when SFTP is unavailable. Hidden entries are included; '.' and '..' are not.",
+        annotations(read_only_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_list_dir(
+        &self,
+        params: Parameters<SshListDirParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_list_dir_impl(&self.session_manager, &self.policy, params).await
+    }
+
+    #[tool(
+        name = "ssh_write_file",
+        description = "Create or replace a file on a connected SSH host. The content is written to a temp file in the same directory and renamed over the target, so readers never see a partial file; an existing file keeps its permissions. Uses SFTP when available, otherwise base64 over the shell. Set base64=true for binary content. Subject to the server policy like a mutating command (blocked in read-only mode). WARNING: overwrites the file; read it first when editing.",
+        annotations(
+            destructive_hint = true,
+            idempotent_hint = true,
+            open_world_hint = true
+        )
+    )]
+    pub async fn ssh_write_file(
+        &self,
+        peer: Peer<RoleServer>,
+        params: Parameters<SshWriteFileParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_write_file_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.audit,
+            peer,
+            params,
+        )
+        .await
     }
 
     #[tool(
         name = "ssh_read_log",
-        description = "Read the last N lines from a log file on a connected SSH host. This is a read-only operation safe for log analysis."
+        description = "Read the last N lines from a log file on a connected SSH host. This is a read-only operation safe for log analysis.",
+        annotations(read_only_hint = true, open_world_hint = true)
     )]
     pub async fn ssh_read_log(
         &self,
         params: Parameters<SshReadLogParams>,
     ) -> Result<CallToolResult, McpError> {
-        tools::ssh_read_log_impl(&self.session_manager, params).await
+        tools::ssh_read_log_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.rate_limiter,
+            &self.redactor,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_journalctl",
+        description = "Read the systemd journal on a connected SSH host, filtered by unit, priority, time range (since/until, e.g. '-1h' or 'today') and message regex. Use it instead of ssh_read_log on journald-only systems where /var/log/syslog doesn't exist. Returns the newest entries as plain lines, or as structured JSON (timestamp, unit, identifier, pid, priority, message) with structured=true. Output is capped by lines and max_bytes, dropping older entries first.",
+        annotations(read_only_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_journalctl(
+        &self,
+        params: Parameters<SshJournalctlParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_journalctl_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.rate_limiter,
+            &self.redactor,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_process_list",
+        description = "List the top processes on a connected SSH host by CPU or memory, optionally filtered by command line text. Returns structured records (pid, ppid, user, cpu, mem, rss_kb, elapsed_secs, name, command) from a locale-independent ps call that works on Linux, BSD and macOS, so there is no need to guess ps flags.",
+        annotations(read_only_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_process_list(
+        &self,
+        params: Parameters<SshProcessListParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_process_list_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.rate_limiter,
+            &self.redactor,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_docker_ps",
+        description = "List Docker containers on a connected SSH host as structured records (id, name, image, command, state, status, ports, created_at). Running containers only unless all=true. Set sudo=true if the user can't reach the Docker socket.",
+        annotations(read_only_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_docker_ps(
+        &self,
+        params: Parameters<SshDockerPsParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_docker_ps_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.rate_limiter,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_docker_logs",
+        description = "Fetch the last N log lines (stdout and stderr) of a Docker container on a connected SSH host, optionally since a time ('10m', '2024-05-01T10:00:00') and with timestamps. Returns the lines as structured JSON, capped by max_bytes with older lines dropped first.",
+        annotations(read_only_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_docker_logs(
+        &self,
+        params: Parameters<SshDockerLogsParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_docker_logs_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.rate_limiter,
+            &self.redactor,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_docker_inspect",
+        description = "Return the full docker inspect JSON of a container on a connected SSH host: state, health, restart count, mounts, networks, config. Secrets in strings such as environment variables are masked unless redact=false.",
+        annotations(read_only_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_docker_inspect(
+        &self,
+        params: Parameters<SshDockerInspectParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_docker_inspect_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.rate_limiter,
+            &self.redactor,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_docker_stats",
+        description = "Take one resource usage sample of Docker containers on a connected SSH host: cpu_percent, mem_percent, mem_usage, net_io, block_io and pids per container, as structured JSON. Defaults to every running container.",
+        annotations(read_only_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_docker_stats(
+        &self,
+        params: Parameters<SshDockerStatsParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_docker_stats_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.rate_limiter,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_k8s_node_triage",
+        description = "Triage a Kubernetes node over a connected SSH session in one call: kubelet and container runtime (containerd/docker/crio) service state and restart counts, disk and inode usage of /, /var/lib/kubelet, the runtime's image store and /var/log, and recent kubelet errors from the journal. Returns a structured summary with a list of problems (empty when the node looks healthy). Reading the kubelet journal may need a user in the systemd-journal group.",
+        annotations(read_only_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_k8s_node_triage(
+        &self,
+        params: Parameters<SshK8sNodeTriageParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_k8s_node_triage_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.rate_limiter,
+            &self.redactor,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_copy_between_hosts",
+        description = "Copy a file from one connected SSH host to another. The data streams through this server over SFTP (no SSH trust between the hosts is needed), lands in a temp file next to the target and replaces it only after the SHA-256 of both ends match (verify=false skips the check). max_bytes_per_sec caps the rate; progress notifications are sent when the request has a progress token. An existing target keeps its permissions, a new one gets the source's. Needs SFTP on both hosts. Subject to the server policy like ssh_write_file on the target host. WARNING: overwrites the target file.",
+        annotations(
+            destructive_hint = true,
+            idempotent_hint = true,
+            open_world_hint = true
+        )
+    )]
+    pub async fn ssh_copy_between_hosts(
+        &self,
+        peer: Peer<RoleServer>,
+        meta: Meta,
+        params: Parameters<SshCopyBetweenHostsParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_copy_between_hosts_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.audit,
+            peer,
+            meta,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_upload",
+        description = "Upload a file or a whole directory from the machine running this server (local_path, absolute) to a connected SSH host over SFTP, keeping permissions and mtimes. A directory's contents go into remote_path, which is created if missing; include / exclude globs select files (e.g. include ['*.conf'], exclude ['.git', '*.log']). Returns files copied, skipped and failed with per-file results; one failed file doesn't stop the rest. resume=true continues partial remote files from where they stopped and verifies SHA-256 on both ends; a mismatch fails with type checksum_mismatch (re-run without resume to start over). max_bytes_per_sec caps the rate; progress notifications are sent when the request has a progress token. Subject to the server policy like ssh_write_file. WARNING: overwrites remote files.",
+        annotations(
+            destructive_hint = true,
+            idempotent_hint = true,
+            open_world_hint = true
+        )
+    )]
+    pub async fn ssh_upload(
+        &self,
+        peer: Peer<RoleServer>,
+        meta: Meta,
+        params: Parameters<SshTransferParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_upload_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.audit,
+            peer,
+            meta,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_download",
+        description = "Download a file or a whole directory from a connected SSH host to the machine running this server (local_path, absolute) over SFTP, keeping permissions and mtimes. A directory's contents go into local_path, which is created if missing; include / exclude globs select files. Returns files copied, skipped and failed with per-file results. resume=true continues partial local files and verifies SHA-256 on both ends. max_bytes_per_sec caps the rate; progress notifications are sent when the request has a progress token. WARNING: overwrites local files.",
+        annotations(
+            destructive_hint = true,
+            idempotent_hint = true,
+            open_world_hint = true
+        )
+    )]
+    pub async fn ssh_download(
+        &self,
+        peer: Peer<RoleServer>,
+        meta: Meta,
+        params: Parameters<SshTransferParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_download_impl(&self.session_manager, &self.policy, peer, meta, params).await
+    }
+
+    #[tool(
+        name = "ssh_install_public_key",
+        description = "Install an SSH public key for the login user of a connected host, like ssh-copy-id: creates ~/.ssh (700) and authorized_keys (600) if needed and appends the key unless it is already there, so repeating it is harmless. Typically used right after connecting with a password (ssh_connect_direct) to bootstrap key-based access, with a key from ssh_generate_keypair or an existing .pub file. Subject to the server policy like ssh_write_file.",
+        annotations(
+            destructive_hint = false,
+            idempotent_hint = true,
+            open_world_hint = true
+        )
+    )]
+    pub async fn ssh_install_public_key(
+        &self,
+        peer: Peer<RoleServer>,
+        params: Parameters<SshInstallPublicKeyParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_install_public_key_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.audit,
+            peer,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_generate_keypair",
+        description = "Generate a new ed25519 (default) or ecdsa keypair on the machine running this server with ssh-keygen, without a passphrase. The private key is written with mode 600 and the .pub file with 644; default path ~/.ssh/id_<type>, and existing files are never overwritten. Returns the public key line and its SHA256 fingerprint, ready for ssh_install_public_key.",
+        annotations(
+            destructive_hint = false,
+            idempotent_hint = false,
+            open_world_hint = false
+        )
+    )]
+    pub async fn ssh_generate_keypair(
+        &self,
+        params: Parameters<SshGenerateKeypairParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_generate_keypair_impl(params).await
+    }
+
+    #[tool(
+        name = "ssh_known_hosts_list",
+        description = "List entries of ~/.ssh/known_hosts on the machine running this server, with line number, host patterns ('(hashed)' for hashed entries), key type, SHA256 fingerprint and @revoked/@cert-authority markers. With host, only the entries that apply to it (its resolved HostName and Port), as ssh-keygen -F finds them.",
+        annotations(read_only_hint = true, open_world_hint = false)
+    )]
+    pub async fn ssh_known_hosts_list(
+        &self,
+        params: Parameters<SshKnownHostsListParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_known_hosts_list_impl(params).await
+    }
+
+    #[tool(
+        name = "ssh_keyscan",
+        description = "Fetch the host keys a server presents right now (ssh-keyscan from the machine running this server) with their SHA256 fingerprints, each marked known, new, changed (known_hosts has a different key of that type) or revoked. Use it to confirm a host was really reinstalled before replacing its known_hosts entry; compare fingerprints with the server console when in doubt. Hosts only reachable through ProxyCommand or a jump host can't be scanned.",
+        annotations(read_only_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_keyscan(
+        &self,
+        params: Parameters<SshKeyscanParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_keyscan_impl(params).await
+    }
+
+    #[tool(
+        name = "ssh_known_hosts_add",
+        description = "Trust host keys: append them to ~/.ssh/known_hosts under the host's resolved name ('host' or '[host]:port'). Pass public_keys (lines from ssh_keyscan) to pin exactly those keys; without them, whatever the host presents now is scanned and trusted. Keys already recorded are skipped.",
+        annotations(
+            destructive_hint = false,
+            idempotent_hint = true,
+            open_world_hint = true
+        )
+    )]
+    pub async fn ssh_known_hosts_add(
+        &self,
+        params: Parameters<SshKnownHostsAddParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_known_hosts_add_impl(params).await
+    }
+
+    #[tool(
+        name = "ssh_known_hosts_remove",
+        description = "Remove every ~/.ssh/known_hosts key recorded for a host (ssh-keygen -R, hashed entries included; the previous file is kept as known_hosts.old). For a host that was legitimately reinstalled: check ssh_keyscan, remove the stale keys, then ssh_known_hosts_add the new ones.",
+        annotations(
+            destructive_hint = true,
+            idempotent_hint = true,
+            open_world_hint = false
+        )
+    )]
+    pub async fn ssh_known_hosts_remove(
+        &self,
+        params: Parameters<SshKnownHostsRemoveParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_known_hosts_remove_impl(params).await
+    }
+
+    #[tool(
+        name = "ssh_checksum",
+        description = "Compute md5, sha1, sha256 (default) or sha512 checksums of one or more files on a connected SSH host. Each path gets its checksum or the error that prevented it (e.g. No such file or directory). Handy for checking that a deploy or a large file is the same everywhere.",
+        annotations(read_only_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_checksum(
+        &self,
+        params: Parameters<SshChecksumParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_checksum_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.rate_limiter,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_compare_files",
+        description = "Compare a file on a connected SSH host with the same or another path on another connected host (other_host / other_path), or with text given in content. Returns identical plus a unified diff from the first file to the second, e.g. to check whether the config on web1 matches web2. Files up to 1 MiB; use ssh_checksum for larger ones. Secrets in the diff are masked.",
+        annotations(read_only_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_compare_files(
+        &self,
+        params: Parameters<SshCompareFilesParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_compare_files_impl(&self.session_manager, &self.policy, &self.redactor, params)
+            .await
+    }
+
+    #[tool(
+        name = "ssh_find_files",
+        description = "Find files on a connected SSH host with safe bounds: by name glob, type, depth, modification window (newer_than_minutes / older_than_minutes) and minimum size. The search stops after timeout_secs (default 10) and returns at most limit entries (default 200) with path, kind, size and mtime, flagging truncated or timed-out results. Stays on one filesystem unless same_filesystem=false. Use it instead of a bare find in ssh_run_command.",
+        annotations(read_only_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_find_files(
+        &self,
+        params: Parameters<SshFindFilesParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_find_files_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.rate_limiter,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_grep",
+        description = "Search file contents on a connected SSH host with GNU grep and get structured matches: file, line number, text and optional before/after context lines. pattern is an extended regex (or literal with fixed_strings=true); paths may list several files or directories, searched recursively with recursive=true. Binary files are skipped unless skip_binary=false. Returns at most max_matches (default 100) and stops after timeout_secs (default 10), flagging truncated or timed-out results. Secrets in matched lines are masked.",
+        annotations(read_only_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_grep(
+        &self,
+        params: Parameters<SshGrepParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_grep_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.rate_limiter,
+            &self.redactor,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_disk_usage",
+        description = "Answer 'why is the disk full' in one call: usage and inode use of every real filesystem (tmpfs and overlays left out), fullest first, plus the top largest directories under path (default /) sized with du on that filesystem down to depth levels. du stops after timeout_secs (default 10) and reports what it sized so far, flagged as timed_out.",
+        annotations(read_only_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_disk_usage(
+        &self,
+        params: Parameters<SshDiskUsageParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_disk_usage_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.rate_limiter,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_network_info",
+        description = "Network overview of a connected SSH host as structured data: interfaces (state, MAC, MTU, addresses with prefix length), routes (destination, gateway, device, metric) and listening TCP/UDP sockets (address, port, process). Uses ip -j and ss, falling back to ifconfig and netstat on older systems; 'via' says which tools answered. Process names of other users' sockets need a root session.",
+        annotations(read_only_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_network_info(
+        &self,
+        params: Parameters<SshNetworkInfoParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_network_info_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.rate_limiter,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_port_check",
+        description = "Test TCP connectivity from a connected SSH host to target_host:port, e.g. whether an app server can reach its database. Returns status open, closed (refused), timeout (no answer, usually a firewall), unreachable or error, plus the connect latency measured on the host. Probes with nc, falling back to bash's /dev/tcp when nc is missing; the bash probe is refused in read-only mode.",
+        annotations(read_only_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_port_check(
+        &self,
+        params: Parameters<SshPortCheckParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_port_check_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.rate_limiter,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_package_query",
+        description = "Check whether packages are installed on a connected SSH host and at which version, detecting dpkg, rpm, apk or pacman automatically. With upgrades=true also lists upgrades the package database already knows of (apt list --upgradable, dnf/yum check-update -C, apk list --upgradable, pacman -Qu); indexes are never refreshed, so nothing on the host changes.",
+        annotations(read_only_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_package_query(
+        &self,
+        params: Parameters<SshPackageQueryParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_package_query_impl(
+            &self.session_manager,
+            &self.policy,
+            &self.rate_limiter,
+            params,
+        )
+        .await
+    }
+
+    #[tool(
+        name = "ssh_tunnel_open",
+        description = "Open a local port forward (like ssh -L) over a connected SSH session. Listens on 127.0.0.1:local_port and forwards connections to remote_host:remote_port as seen from the SSH server. Useful for reaching a remote database or admin UI locally. Returns the tunnel ID and bound local address.",
+        annotations(destructive_hint = false, open_world_hint = true)
+    )]
+    pub async fn ssh_tunnel_open(
+        &self,
+        params: Parameters<SshTunnelOpenParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_tunnel_open_impl(&self.session_manager, params).await
+    }
+
+    #[tool(
+        name = "ssh_socks_proxy",
+        description = "Open a local SOCKS5 proxy (like ssh -D) whose connections are made from the SSH server. Lets local tools reach an isolated network behind the host while the session is alive. Listens on 127.0.0.1 only; idle connections are closed and concurrent connections are capped. Close it with ssh_tunnel_close.",
+        annotations(destructive_hint = false, open_world_hint = true)
+    )]
+    pub async fn ssh_socks_proxy(
+        &self,
+        params: Parameters<SshSocksProxyParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_socks_proxy_impl(&self.session_manager, params).await
+    }
+
+    #[tool(
+        name = "ssh_tunnel_list",
+        description = "List open local port forwards with their local address, destination, and number of active connections.",
+        annotations(read_only_hint = true, open_world_hint = false)
+    )]
+    pub async fn ssh_tunnel_list(&self) -> Result<CallToolResult, McpError> {
+        tools::ssh_tunnel_list_impl(&self.session_manager).await
+    }
+
+    #[tool(
+        name = "ssh_tunnel_close",
+        description = "Close a local port forward by tunnel ID. Active forwarded connections are dropped.",
+        annotations(
+            destructive_hint = false,
+            idempotent_hint = true,
+            open_world_hint = false
+        )
+    )]
+    pub async fn ssh_tunnel_close(
+        &self,
+        params: Parameters<SshTunnelCloseParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_tunnel_close_impl(&self.session_manager, params).await
+    }
+
+    #[tool(
+        name = "ssh_watch_log",
+        description = "Watch a remote log file (tail -F) and keep the new lines matching a regular expression, e.g. 'tell me when ERROR appears in app.log' without polling ssh_read_log. Returns a watch ID and a resource URI (ssh://<host>/watches/<id>); read it for the matched lines, or subscribe to it to be notified as they arrive. Runs on its own channel, so the shell stays free. Stop it with ssh_watch_stop.",
+        annotations(read_only_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_watch_log(
+        &self,
+        peer: Peer<RoleServer>,
+        params: Parameters<SshWatchLogParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_watch_log_impl(&self.session_manager, &self.policy, peer, params).await
+    }
+
+    #[tool(
+        name = "ssh_watch_stop",
+        description = "Stop a log watch started with ssh_watch_log and return the lines it matched.",
+        annotations(
+            destructive_hint = false,
+            idempotent_hint = true,
+            open_world_hint = false
+        )
+    )]
+    pub async fn ssh_watch_stop(
+        &self,
+        peer: Peer<RoleServer>,
+        params: Parameters<SshWatchStopParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_watch_stop_impl(&self.session_manager, &self.redactor, peer, params).await
+    }
+
+    #[tool(
+        name = "ssh_schedule",
+        description = "Run a read-only command on a connected host every interval_secs (at least 10) on the server side, e.g. 'df -h /' or 'grep -c ERROR app.log', and keep the runs whose output changed (as a diff) or whose lines matching pattern changed. Returns a schedule ID and a resource URI (ssh://<host>/schedules/<id>); read it for the latest output and events, or subscribe to it to be notified of each event. Runs in its own channel, so the shell stays free. Cancel it with ssh_schedule_cancel.",
+        annotations(read_only_hint = true, open_world_hint = true)
+    )]
+    pub async fn ssh_schedule(
+        &self,
+        peer: Peer<RoleServer>,
+        params: Parameters<SshScheduleParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_schedule_impl(&self.session_manager, &self.policy, peer, params).await
+    }
+
+    #[tool(
+        name = "ssh_schedule_list",
+        description = "List scheduled commands with their interval, number of runs and events, and the last run's exit code or error.",
+        annotations(read_only_hint = true, open_world_hint = false)
+    )]
+    pub async fn ssh_schedule_list(&self) -> Result<CallToolResult, McpError> {
+        tools::ssh_schedule_list_impl(&self.session_manager).await
+    }
+
+    #[tool(
+        name = "ssh_schedule_cancel",
+        description = "Cancel a command scheduled with ssh_schedule and return its latest output and events.",
+        annotations(
+            destructive_hint = false,
+            idempotent_hint = true,
+            open_world_hint = false
+        )
+    )]
+    pub async fn ssh_schedule_cancel(
+        &self,
+        peer: Peer<RoleServer>,
+        params: Parameters<SshScheduleCancelParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_schedule_cancel_impl(&self.session_manager, &self.redactor, peer, params).await
+    }
+
+    #[tool(
+        name = "ssh_health_report",
+        description = "Latest results of the health probes configured for inventory hosts (TCP connect, command exit code, disk usage). Each host's status is its worst probe's: ok, pending, failing or error. Probes run in the background on an interval, so this doesn't contact the hosts.",
+        annotations(read_only_hint = true, open_world_hint = false)
+    )]
+    pub async fn ssh_health_report(
+        &self,
+        params: Parameters<SshHealthReportParams>,
+    ) -> Result<CallToolResult, McpError> {
+        tools::ssh_health_report_impl(&self.health, &self.redactor, params)
     }
 }
 
-#[tool_handler(router = self.tool_router)]
 impl rmcp::ServerHandler for SshMcpServer {
+    /// Every tool call runs in a `tool_call` span, parent of the SSH spans it causes.
+    async fn call_tool(
+        &self,
+        request: CallToolRequestParam,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let span = tracing::info_span!(
+            "tool_call",
+            tool = %request.name,
+            namespace = self.session_manager.namespace().unwrap_or("-"),
+            is_error = tracing::field::Empty,
+        );
+        async {
+            let tcc = ToolCallContext::new(self, request, context);
+            let result = self.tool_router.call(tcc).await;
+            let is_error = result
+                .as_ref()
+                .map_or(true, |r| r.is_error.unwrap_or(false));
+            tracing::Span::current().record("is_error", is_error);
+            result
+        }
+        .instrument(span)
+        .await
+    }
+
+    async fn list_tools(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListToolsResult, McpError> {
+        Ok(ListToolsResult {
+            tools: self.tool_router.list_all(),
+            meta: None,
+            next_cursor: None,
+        })
+    }
+
+    async fn list_resources(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourcesResult, McpError> {
+        let resources = resources::list(&self.session_manager, &self.outputs, &self.health).await;
+        Ok(ListResourcesResult::with_all_items(resources))
+    }
+
+    async fn list_resource_templates(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListResourceTemplatesResult, McpError> {
+        Ok(ListResourceTemplatesResult::with_all_items(
+            resources::templates(),
+        ))
+    }
+
+    async fn read_resource(
+        &self,
+        request: ReadResourceRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ReadResourceResult, McpError> {
+        resources::read(
+            &self.session_manager,
+            &self.outputs,
+            &self.health,
+            &self.redactor,
+            &request.uri,
+        )
+        .await
+    }
+
+    /// Only log watches and schedules change, so only they can be subscribed to.
+    async fn subscribe(
+        &self,
+        request: SubscribeRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<(), McpError> {
+        self.set_subscribed(&request.uri, true).await
+    }
+
+    async fn unsubscribe(
+        &self,
+        request: UnsubscribeRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<(), McpError> {
+        self.set_subscribed(&request.uri, false).await
+    }
+
+    async fn list_prompts(
+        &self,
+        _request: Option<PaginatedRequestParam>,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<ListPromptsResult, McpError> {
+        Ok(ListPromptsResult::with_all_items(prompts::list()))
+    }
+
+    async fn get_prompt(
+        &self,
+        request: GetPromptRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<GetPromptResult, McpError> {
+        prompts::get(&request.name, request.arguments.as_ref())
+    }
+
+    async fn complete(
+        &self,
+        request: CompleteRequestParam,
+        _context: RequestContext<RoleServer>,
+    ) -> Result<CompleteResult, McpError> {
+        completion::complete(&self.session_manager, &request).await
+    }
+
     fn get_info(&self) -> ServerInfo {
         ServerInfo {
-            capabilities: ServerCapabilities::builder().enable_tools().build(),
+            capabilities: ServerCapabilities::builder()
+                .enable_tools()
+                .enable_resources()
+                .enable_resources_subscribe()
+                .enable_resources_list_changed()
+                .enable_prompts()
+                .enable_completions()
+                .build(),
             instructions: Some(
                 "SSH Liaison MCP Server - Provides SSH connection and command execution tools. \
                 WARNING: Use with caution. Prefer read-only operations. Destructive commands should \
@@ -95,11 +1522,88 @@ impl rmcp::ServerHandler for SshMcpServer {
             ..Default::default()
         }
     }
+
+    async fn on_initialized(&self, context: NotificationContext<RoleServer>) {
+        if let Some(info) = context.peer.peer_info() {
+            tracing::info!(
+                client = %info.client_info.name,
+                namespace = self.session_manager.namespace().unwrap_or("-"),
+                "Client initialized"
+            );
+            self.session_manager.set_client_name(&info.client_info.name);
+        }
+    }
 }
 
-pub async fn run_mcp_server() -> Result<()> {
+pub async fn run_mcp_server(options: ServerOptions) -> Result<()> {
     use std::io::Write;
 
+    let ServerOptions {
+        policy,
+        audit_log,
+        http,
+        http_token,
+        inventory,
+        reconnect,
+        keepalive_interval,
+        backend,
+        max_sessions,
+        auto_connect,
+        dry_run,
+        ansi,
+        rate_limits,
+        queue_depth,
+        cache_ttl,
+        channel,
+        allowed_hosts,
+        host_channels,
+        host_transports,
+        host_tags,
+        config_file,
+        redactor,
+        recording,
+        vault,
+        auth_order,
+        transfer_rate_limit,
+        session_file,
+        restore_sessions,
+        history_file,
+        health,
+        templates,
+        plugins,
+    } = options;
+    if let Some(addr) = http {
+        check_http_bind(addr, http_token.as_deref())?;
+    }
+    let backend = backend.unwrap_or_default();
+    if !backend.is_available() {
+        anyhow::bail!(
+            "The {} SSH backend is not in this build; rebuild with `--features {}`",
+            backend,
+            backend
+        );
+    }
+    let mut tool_names: Vec<&str> = templates
+        .iter()
+        .map(|t| t.name.as_str())
+        .chain(plugins.iter().map(|p| p.name.as_str()))
+        .collect();
+    tool_names.sort_unstable();
+    if let Some(name) = tool_names.windows(2).find(|w| w[0] == w[1]) {
+        anyhow::bail!(
+            "Tool '{}' is defined by more than one template or plugin",
+            name[0]
+        );
+    }
+    let audit = match audit_log {
+        Some(ref path) => AuditLog::open(path)?,
+        None => AuditLog::disabled(),
+    };
+    let audit = match history_file {
+        Some(ref path) => audit.with_history(CommandHistory::open(path)?),
+        None => audit,
+    };
+
     let version = env!("CARGO_PKG_VERSION");
     let name = env!("CARGO_PKG_NAME");
 
@@ -107,20 +1611,355 @@ pub async fn run_mcp_server() -> Result<()> {
     eprintln!("{} v{}", name, version);
     eprintln!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
     eprintln!("MCP Server starting...");
+    if let Some(ref path) = config_file {
+        eprintln!("Config: {}", path.display());
+    }
     eprintln!();
     eprintln!("Available tools:");
     eprintln!("  - ssh_connect        Connect to SSH host via ~/.ssh/config");
     eprintln!("  - ssh_connect_direct Connect to SSH host directly");
+    eprintln!("  - ssh_list_hosts     List host aliases from ~/.ssh/config");
     eprintln!("  - ssh_run_command    Execute commands on connected host");
+    eprintln!("  - ssh_cancel_command Interrupt a running command, keeping its output");
+    eprintln!("  - ssh_reset_session  Resynchronize or reopen a shell that's out of step");
+    eprintln!("  - ssh_fetch_output   Page through large command output");
+    eprintln!("  - ssh_cwd            Get or change a shell's working directory");
+    eprintln!("  - ssh_env            Get, set or unset a shell's environment variables");
+    eprintln!("  - ssh_run_script     Upload and run a multi-line script");
+    eprintln!("  - ssh_run_on_hosts   Execute a command on several hosts at once");
+    eprintln!("  - ssh_list_groups    List inventory host groups");
+    eprintln!("  - ssh_list_connections List active sessions and their owners");
+    eprintln!("  - ssh_restore_sessions Re-open the sessions saved before a restart");
+    eprintln!("  - ssh_history        List commands already run, per host");
+    eprintln!("  - ssh_rerun          Run a recorded command again and diff its output");
+    eprintln!("  - ssh_read_file      Read a remote file (byte or line range)");
+    eprintln!("  - ssh_write_file     Write a remote file atomically");
+    eprintln!("  - ssh_copy_between_hosts Copy a file from one host to another");
+    eprintln!("  - ssh_upload         Upload a file or directory tree over SFTP");
+    eprintln!("  - ssh_download       Download a file or directory tree over SFTP");
+    eprintln!("  - ssh_install_public_key Add a key to authorized_keys (ssh-copy-id)");
+    eprintln!("  - ssh_generate_keypair Create a local ed25519/ecdsa keypair");
+    eprintln!("  - ssh_known_hosts_list List known_hosts entries and fingerprints");
+    eprintln!("  - ssh_keyscan        Fetch a host's current key fingerprints");
+    eprintln!("  - ssh_known_hosts_add Trust a host's keys in known_hosts");
+    eprintln!("  - ssh_known_hosts_remove Drop a host's known_hosts keys");
+    eprintln!("  - ssh_list_dir       List a remote directory as structured entries");
     eprintln!("  - ssh_read_log       Read log files from remote host");
+    eprintln!("  - ssh_journalctl     Read the systemd journal with unit/priority/time filters");
+    eprintln!("  - ssh_process_list   Top processes by CPU or memory");
+    eprintln!("  - ssh_docker_ps      List Docker containers");
+    eprintln!("  - ssh_docker_logs    Last log lines of a container");
+    eprintln!("  - ssh_docker_inspect Inspect a container");
+    eprintln!("  - ssh_docker_stats   Container CPU, memory and I/O usage");
+    eprintln!("  - ssh_k8s_node_triage Check kubelet, runtime, disk pressure and kubelet errors");
+    eprintln!("  - ssh_checksum       Checksums of remote files");
+    eprintln!("  - ssh_compare_files  Diff a file between hosts or against given content");
+    eprintln!("  - ssh_find_files     Find files with result and time limits");
+    eprintln!("  - ssh_grep           Search file contents with context lines");
+    eprintln!("  - ssh_disk_usage     Filesystem usage and largest directories");
+    eprintln!("  - ssh_network_info   Interfaces, routes and listening sockets");
+    eprintln!("  - ssh_port_check     Test TCP connectivity from a host to host:port");
+    eprintln!("  - ssh_package_query  Installed package versions and pending upgrades");
+    eprintln!("  - ssh_tunnel_open    Forward a local port through a session");
+    eprintln!("  - ssh_socks_proxy    Open a SOCKS5 proxy through a session");
+    eprintln!("  - ssh_tunnel_list    List open port forwards");
+    eprintln!("  - ssh_tunnel_close   Close a port forward");
+    eprintln!("  - ssh_watch_log      Watch a remote log for lines matching a pattern");
+    eprintln!("  - ssh_watch_stop     Stop a log watch");
+    eprintln!("  - ssh_schedule       Run a read-only command periodically, keeping changes");
+    eprintln!("  - ssh_schedule_list  List scheduled commands");
+    eprintln!("  - ssh_schedule_cancel Cancel a scheduled command");
+    eprintln!("  - ssh_health_report  Latest results of the configured health probes");
+    for template in &templates {
+        eprintln!("  - {:<18} Template: {}", template.name, template.command);
+    }
+    for plugin in &plugins {
+        eprintln!(
+            "  - {:<18} Plugin: {}",
+            plugin.name,
+            plugin.executable.display()
+        );
+    }
     eprintln!();
-    eprintln!("Security: Prefer read-only operations");
+    eprintln!(
+        "Resources: ssh://<alias>/info, ssh://<alias>/outputs/<id>, ssh://<alias>/watches/<id>, ssh://<alias>/schedules/<id>, ssh://<alias>/health"
+    );
+    eprintln!(
+        "Prompts: diagnose_high_cpu, investigate_failed_unit, check_disk_pressure, check_memory_pressure"
+    );
+    eprintln!();
+    if policy.is_read_only() {
+        eprintln!("Security: READ-ONLY mode, mutating commands are blocked");
+    } else {
+        eprintln!("Security: Prefer read-only operations");
+    }
+    if policy.is_allowlist() {
+        eprintln!("Security: Command allowlist enabled");
+    }
+    if policy.is_approval_required() {
+        eprintln!("Security: Destructive commands require client approval");
+    }
+    if !allowed_hosts.is_empty() {
+        eprintln!("Security: Only hosts matching {}", allowed_hosts.join(", "));
+    }
+    if redactor.is_enabled() {
+        eprintln!("Security: Secrets are masked in command output");
+    } else {
+        eprintln!("Security: Output redaction disabled");
+    }
+    if recording.all_sessions {
+        eprintln!("Recording: every session to {}", recording.dir.display());
+    } else {
+        eprintln!(
+            "Recording: on request (record=true) to {}",
+            recording.dir.display()
+        );
+    }
+    if !host_transports.is_empty() {
+        let mut aliases: Vec<&str> = host_transports.keys().map(String::as_str).collect();
+        aliases.sort();
+        eprintln!("Transports: configured for {}", aliases.join(", "));
+    }
+    if !host_tags.is_empty() {
+        let tags: Vec<&str> = host_tags.keys().map(String::as_str).collect();
+        eprintln!("Host tags: {}", tags.join(", "));
+    }
+    if !vault.is_empty() {
+        eprintln!(
+            "Vault: credentials for {} host(s) from the SSH secrets engine",
+            vault.hosts.len()
+        );
+    }
+    if let Some(ref order) = auth_order {
+        let names: Vec<&str> = order.iter().map(|m| m.as_str()).collect();
+        eprintln!("Auth order: {}", names.join(", "));
+    }
+    if let Some(ref path) = audit_log {
+        eprintln!("Audit log: {}", path.display());
+    }
+    if !inventory.is_empty() {
+        eprintln!("Inventory: {} host group(s)", inventory.groups.len());
+    }
+    if reconnect.is_enabled() {
+        eprintln!(
+            "Reconnect: up to {} attempt(s) when a session drops",
+            reconnect.max_attempts
+        );
+    } else {
+        eprintln!("Reconnect: disabled");
+    }
+    let keepalive_interval = keepalive_interval.unwrap_or(DEFAULT_KEEPALIVE_SECS);
+    if keepalive_interval > 0 {
+        eprintln!(
+            "Keepalive: every {}s unless ServerAliveInterval is set",
+            keepalive_interval
+        );
+    } else {
+        eprintln!("Keepalive: only for hosts with ServerAliveInterval");
+    }
+    eprintln!("SSH backend: {}", backend);
+    let max_sessions = max_sessions.unwrap_or(DEFAULT_MAX_SESSIONS);
+    if max_sessions > 0 {
+        eprintln!(
+            "Session limit: {}, least recently used idle sessions closed to make room",
+            max_sessions
+        );
+    } else {
+        eprintln!("Session limit: none");
+    }
+    if dry_run {
+        eprintln!(
+            "Dry run: ssh_run_command, templates and plugin commands report what they would run"
+        );
+    }
+    match ansi {
+        AnsiMode::Strip => {}
+        AnsiMode::Keep => eprintln!("Colors: ANSI codes kept in ssh_run_command output"),
+        AnsiMode::Convert => {
+            eprintln!("Colors: converted to markdown annotations in ssh_run_command output")
+        }
+    }
+    let rate_limiter = CommandRateLimiter::new(rate_limits);
+    match (rate_limiter.limits().per_host, rate_limiter.limits().global) {
+        (Some(per_host), Some(global)) => eprintln!(
+            "Rate limit: {} commands per minute per host, {} in total",
+            per_host, global
+        ),
+        (Some(per_host), None) => {
+            eprintln!("Rate limit: {} commands per minute per host", per_host)
+        }
+        (None, Some(global)) => eprintln!("Rate limit: {} commands per minute in total", global),
+        (None, None) => {}
+    }
+    if auto_connect {
+        eprintln!("Auto-connect: ~/.ssh/config aliases on their first command");
+    }
+    let cache_ttl = Duration::from_secs(cache_ttl.unwrap_or(0));
+    if !cache_ttl.is_zero() {
+        eprintln!(
+            "Result cache: read-only commands reused for {}s",
+            cache_ttl.as_secs()
+        );
+    }
+    eprintln!(
+        "Channels: {}s command timeout, {} ms resync timeout, {} output bytes kept",
+        channel.command_timeout_secs, channel.resync_timeout_ms, channel.max_output_bytes
+    );
+    let queue_depth = queue_depth.unwrap_or(DEFAULT_QUEUE_DEPTH);
+    eprintln!(
+        "Command queue: up to {} ssh_run_command calls wait per shell",
+        queue_depth
+    );
+    let transfer_limit = transfer_rate_limit.map(RateLimiter::new).transpose()?;
+    if let Some(limiter) = &transfer_limit {
+        eprintln!(
+            "Transfers: {} bytes/s shared by all transfers",
+            limiter.bytes_per_sec()
+        );
+    }
+    match session_file {
+        Some(ref path) if restore_sessions && http.is_none() => eprintln!(
+            "Sessions: saved to {}, re-opened at startup",
+            path.display()
+        ),
+        Some(ref path) => eprintln!("Sessions: saved to {}", path.display()),
+        None => eprintln!("Sessions: not saved"),
+    }
+    match audit.history().path() {
+        Some(path) => eprintln!("History: saved to {}", path.display()),
+        None => eprintln!("History: kept in memory"),
+    }
+    if !health.is_empty() {
+        eprintln!(
+            "Health: probing {} host(s) every {}s",
+            health.hosts().len(),
+            health.interval().as_secs()
+        );
+    }
+    match http {
+        Some(addr) => {
+            eprintln!(
+                "Transport: streamable HTTP on http://{}{}",
+                addr, HTTP_MCP_PATH
+            );
+            match http_token {
+                Some(_) => eprintln!("HTTP auth: bearer token"),
+                None => eprintln!("HTTP auth: none, loopback only"),
+            }
+        }
+        None => eprintln!("Transport: stdio"),
+    }
     eprintln!("━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━");
     eprintln!("Server ready, waiting for MCP requests...");
     eprintln!();
     std::io::stderr().flush()?;
 
-    let server = SshMcpServer::new();
+    redact::install_log_redactor(redactor.clone());
+    let server = SshMcpServer::with_policy(policy, audit)
+        .with_redactor(redactor)
+        .with_inventory(inventory)
+        .with_reconnect_policy(reconnect)
+        .with_keepalive_interval(keepalive_interval)
+        .with_backend(backend)
+        .with_max_sessions(max_sessions)
+        .with_auto_connect(auto_connect)
+        .with_dry_run(dry_run)
+        .with_ansi(ansi)
+        .with_rate_limits(rate_limiter.limits())
+        .with_queue_depth(queue_depth)
+        .with_cache_ttl(cache_ttl)
+        .with_health(health.clone())
+        .with_templates(templates)
+        .with_plugins(plugins)
+        .with_channel_options(channel)
+        .with_host_channel_options(host_channels)
+        .with_host_transports(host_transports)
+        .with_host_tags(host_tags)
+        .with_allowed_hosts(allowed_hosts)
+        .with_recording(recording)
+        .with_vault(vault);
+    let server = match auth_order {
+        Some(order) => server.with_auth_order(order),
+        None => server,
+    };
+    let server = match transfer_limit {
+        Some(limiter) => server.with_transfer_rate_limit(limiter),
+        None => server,
+    };
+    let server = match session_file {
+        Some(path) => server.with_session_store(SessionStore::new(path)),
+        None => server,
+    };
+    let sessions = server.session_manager.clone();
+    if restore_sessions {
+        match http {
+            // HTTP clients each get their own namespace, so they restore for themselves.
+            Some(_) => tracing::warn!(
+                "Sessions are only re-opened at startup over stdio; call ssh_restore_sessions instead"
+            ),
+            None => {
+                let sessions = sessions.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = sessions.restore_sessions(&[]).await {
+                        tracing::warn!(error = %e, "Failed to restore sessions");
+                    }
+                });
+            }
+        }
+    }
+    let probes = health.start(sessions.clone());
+    let served = match http {
+        Some(addr) => serve_http(server, addr, http_token).await,
+        None => serve_stdio(server).await,
+    };
+    if let Some(probes) = probes {
+        probes.abort();
+    }
+    close_sessions(&sessions).await;
+    served
+}
+
+/// Resolves on Ctrl-C (SIGINT) or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = terminate.recv() => {}
+                }
+                return;
+            }
+            Err(e) => tracing::warn!(error = %e, "Failed to install SIGTERM handler"),
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Closes every client's SSH sessions so remote shells don't linger until
+/// sshd times them out. Gives up after [`SHUTDOWN_GRACE`] or a second signal.
+async fn close_sessions(sessions: &SessionManager) {
+    tokio::select! {
+        count = sessions.close_all() => {
+            if count > 0 {
+                eprintln!("Closed {} SSH session(s)", count);
+            }
+        }
+        _ = tokio::time::sleep(SHUTDOWN_GRACE) => {
+            tracing::warn!(
+                grace_secs = SHUTDOWN_GRACE.as_secs(),
+                "SSH sessions still closing, exiting anyway"
+            );
+        }
+        _ = shutdown_signal() => {
+            tracing::warn!("Second signal, exiting without closing SSH sessions");
+        }
+    }
+}
+
+async fn serve_stdio(server: SshMcpServer) -> Result<()> {
     let service = match server.serve(stdio()).await {
         Ok(s) => s,
         Err(e) => {
@@ -129,7 +1968,19 @@ pub async fn run_mcp_server() -> Result<()> {
         }
     };
 
-    match service.waiting().await {
+    // A signal cancels the service, which then ends like a client disconnect.
+    let cancel = service.cancellation_token();
+    let waiting = service.waiting();
+    tokio::pin!(waiting);
+    let result = tokio::select! {
+        result = &mut waiting => result,
+        _ = shutdown_signal() => {
+            cancel.cancel();
+            waiting.await
+        }
+    };
+
+    match result {
         Ok(_) => {
             eprintln!();
             eprintln!("Server shutting down gracefully...");
@@ -148,3 +1999,120 @@ pub async fn run_mcp_server() -> Result<()> {
 
     Ok(())
 }
+
+/// Serves every MCP client over streamable HTTP (with SSE) until Ctrl-C or SIGTERM.
+///
+/// Each MCP session gets its own SSH session namespace (`client-N`), so clients
+/// can use the same aliases without interfering with each other.
+/// Refuses to serve HTTP without a token anywhere but on loopback, where
+/// only local users can reach it.
+fn check_http_bind(addr: SocketAddr, token: Option<&str>) -> Result<()> {
+    if token.is_none() && !addr.ip().is_loopback() {
+        anyhow::bail!(
+            "Refusing to serve HTTP on {} without authentication: set --http-token or {}, or bind to 127.0.0.1",
+            addr,
+            HTTP_TOKEN_ENV
+        );
+    }
+    Ok(())
+}
+
+/// Whether an `Authorization` header carries `token`. Compares digests so
+/// the time taken doesn't tell how much of the token was right.
+fn bearer_authorized(header: Option<&str>, token: &str) -> bool {
+    use sha2::{Digest, Sha256};
+    let Some(given) = header.and_then(|h| h.strip_prefix("Bearer ")) else {
+        return false;
+    };
+    let given = Sha256::digest(given.trim().as_bytes());
+    let expected = Sha256::digest(token.as_bytes());
+    given
+        .iter()
+        .zip(expected.iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+async fn require_bearer(
+    axum::extract::State(token): axum::extract::State<Arc<str>>,
+    request: axum::extract::Request,
+    next: axum::middleware::Next,
+) -> axum::response::Response {
+    use axum::http::{StatusCode, header};
+    use axum::response::IntoResponse;
+    let header = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if !bearer_authorized(header, &token) {
+        tracing::warn!("HTTP request without a valid bearer token");
+        return (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            "Missing or invalid bearer token",
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+async fn serve_http(server: SshMcpServer, addr: SocketAddr, token: Option<String>) -> Result<()> {
+    let ct = CancellationToken::new();
+    let next_client = AtomicU64::new(0);
+    let service = StreamableHttpService::new(
+        move || {
+            let id = next_client.fetch_add(1, Ordering::Relaxed) + 1;
+            Ok(server.scoped(format!("client-{}", id)))
+        },
+        Arc::new(LocalSessionManager::default()),
+        StreamableHttpServerConfig {
+            cancellation_token: ct.child_token(),
+            ..Default::default()
+        },
+    );
+    let router = axum::Router::new().nest_service(HTTP_MCP_PATH, service);
+    let router = match token {
+        Some(token) => router.layer(axum::middleware::from_fn_with_state(
+            Arc::<str>::from(token),
+            require_bearer,
+        )),
+        None => router,
+    };
+
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to bind {}", addr))?;
+    tracing::info!(addr = %addr, "HTTP transport listening");
+
+    let shutdown = ct.clone();
+    axum::serve(listener, router)
+        .with_graceful_shutdown(async move {
+            tokio::select! {
+                _ = shutdown_signal() => {}
+                _ = shutdown.cancelled() => {}
+            }
+            eprintln!();
+            eprintln!("Server shutting down gracefully...");
+            shutdown.cancel();
+        })
+        .await
+        .context("HTTP server failed")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connect_tools_are_not_read_only() {
+        let tools = SshMcpServer::tool_router().list_all();
+        for name in ["ssh_connect", "ssh_connect_direct"] {
+            let tool = tools.iter().find(|t| t.name == name).unwrap();
+            let annotations = tool.annotations.as_ref().unwrap();
+            assert_eq!(annotations.read_only_hint, Some(false), "{}", name);
+            assert_eq!(annotations.destructive_hint, Some(false), "{}", name);
+        }
+    }
+}
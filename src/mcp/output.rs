@@ -0,0 +1,329 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use super::cache::ResultCache;
+use crate::ssh::channel::CommandOutput;
+
+/// Default page size for `ssh_run_command` and `ssh_fetch_output`.
+pub const DEFAULT_PAGE_BYTES: usize = 64 * 1024;
+/// Outputs kept for paging; the oldest is evicted first.
+const MAX_STORED_OUTPUTS: usize = 16;
+/// Finished commands kept per store for the `ssh://<alias>/outputs/<id>` resources.
+const MAX_RECENT_OUTPUTS: usize = 32;
+
+/// One page of a command's stdout, with the rest of the result fields.
+#[derive(Debug, Serialize)]
+pub struct OutputPage {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+    /// The output was capped when captured, or more pages remain.
+    pub truncated: bool,
+    /// Byte offset of this page in the full stdout.
+    pub offset: usize,
+    pub total_bytes: usize,
+    /// Pass to `ssh_fetch_output` for the next page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_offset: Option<usize>,
+}
+
+impl OutputPage {
+    /// Plain text rendering, with a footer saying how to get the rest.
+    pub fn text(&self) -> String {
+        let mut text = CommandOutput {
+            stdout: self.stdout.clone(),
+            stderr: self.stderr.clone(),
+            ..Default::default()
+        }
+        .combined_with_stderr_label();
+        if let (Some(cursor), Some(next)) = (&self.next_cursor, self.next_offset) {
+            if !text.is_empty() && !text.ends_with('\n') {
+                text.push('\n');
+            }
+            text.push_str(&format!(
+                "[bytes {}-{} of {} shown; call ssh_fetch_output with cursor \"{}\" for more]",
+                self.offset, next, self.total_bytes, cursor
+            ));
+        }
+        text
+    }
+}
+
+/// Byte range of the page starting at `offset`, snapped to char boundaries.
+/// A page always advances, even when `max_bytes` is smaller than one char.
+fn page_bounds(text: &str, offset: usize, max_bytes: usize) -> (usize, usize) {
+    let floor = |mut i: usize| {
+        i = i.min(text.len());
+        while !text.is_char_boundary(i) {
+            i -= 1;
+        }
+        i
+    };
+    let start = floor(offset);
+    let mut end = floor(start.saturating_add(max_bytes));
+    if end == start && start < text.len() {
+        end = start + 1;
+        while !text.is_char_boundary(end) {
+            end += 1;
+        }
+    }
+    (start, end)
+}
+
+fn build_page(
+    output: &CommandOutput,
+    start: usize,
+    end: usize,
+    cursor: Option<String>,
+) -> OutputPage {
+    let has_more = end < output.stdout.len();
+    OutputPage {
+        stdout: output.stdout[start..end].to_string(),
+        stderr: output.stderr.clone(),
+        exit_code: output.exit_code,
+        duration_ms: output.duration_ms,
+        truncated: output.truncated || has_more || start > 0,
+        offset: start,
+        total_bytes: output.stdout.len(),
+        next_cursor: cursor.filter(|_| has_more),
+        next_offset: has_more.then_some(end),
+    }
+}
+
+/// A finished command, kept so it can be read again as a resource.
+#[derive(Debug, Clone, Serialize)]
+pub struct RecentOutput {
+    pub id: u64,
+    pub host: String,
+    pub command: String,
+    /// Unix seconds when the command finished.
+    pub finished_at: u64,
+    #[serde(skip)]
+    owner: Option<String>,
+    #[serde(flatten)]
+    pub output: CommandOutput,
+}
+
+struct StoredOutput {
+    cursor: String,
+    owner: Option<String>,
+    output: CommandOutput,
+    next_offset: usize,
+}
+
+/// Server-side remainder of outputs too large for one response.
+///
+/// Cursors are only visible to the session namespace that created them.
+#[derive(Default)]
+pub struct OutputStore {
+    entries: Mutex<VecDeque<StoredOutput>>,
+    next_id: AtomicU64,
+    recent: Mutex<VecDeque<RecentOutput>>,
+    next_recent_id: AtomicU64,
+    cache: ResultCache,
+}
+
+impl OutputStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Caches read-only command results for `ttl`; zero (the default) disables it.
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache = ResultCache::new(ttl);
+        self
+    }
+
+    pub fn cache(&self) -> &ResultCache {
+        &self.cache
+    }
+
+    /// The page of `output` at `offset`; if more follows, the output is kept
+    /// and the page carries a cursor for it.
+    pub fn first_page(
+        &self,
+        owner: Option<&str>,
+        output: CommandOutput,
+        offset: usize,
+        max_bytes: usize,
+    ) -> OutputPage {
+        let (start, end) = page_bounds(&output.stdout, offset, max_bytes);
+        if end >= output.stdout.len() {
+            return build_page(&output, start, end, None);
+        }
+
+        let cursor = format!("out-{}", self.next_id.fetch_add(1, Ordering::Relaxed) + 1);
+        let page = build_page(&output, start, end, Some(cursor.clone()));
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if entries.len() >= MAX_STORED_OUTPUTS {
+            entries.pop_front();
+        }
+        entries.push_back(StoredOutput {
+            cursor,
+            owner: owner.map(str::to_string),
+            output,
+            next_offset: end,
+        });
+        page
+    }
+
+    /// Keeps the (already redacted) output of `command` on `host` as a recent
+    /// output, returning its id.
+    pub fn record(
+        &self,
+        owner: Option<&str>,
+        host: &str,
+        command: &str,
+        output: &CommandOutput,
+    ) -> u64 {
+        let id = self.next_recent_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        if recent.len() >= MAX_RECENT_OUTPUTS {
+            recent.pop_front();
+        }
+        recent.push_back(RecentOutput {
+            id,
+            host: host.to_string(),
+            command: command.to_string(),
+            finished_at: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            owner: owner.map(str::to_string),
+            output: output.clone(),
+        });
+        id
+    }
+
+    /// Recent outputs of `owner`, newest first.
+    pub fn recent(&self, owner: Option<&str>) -> Vec<RecentOutput> {
+        let recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        recent
+            .iter()
+            .rev()
+            .filter(|r| r.owner.as_deref() == owner)
+            .cloned()
+            .collect()
+    }
+
+    pub fn recent_output(&self, owner: Option<&str>, host: &str, id: u64) -> Option<RecentOutput> {
+        let recent = self.recent.lock().unwrap_or_else(|e| e.into_inner());
+        recent
+            .iter()
+            .find(|r| r.id == id && r.host == host && r.owner.as_deref() == owner)
+            .cloned()
+    }
+
+    /// The page at `offset`, or right after the last page fetched.
+    pub fn fetch(
+        &self,
+        owner: Option<&str>,
+        cursor: &str,
+        offset: Option<usize>,
+        max_bytes: usize,
+    ) -> Result<OutputPage> {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = entries
+            .iter_mut()
+            .find(|e| e.cursor == cursor && e.owner.as_deref() == owner)
+            .ok_or_else(|| anyhow::anyhow!("Unknown or expired output cursor '{}'", cursor))?;
+
+        let (start, end) = page_bounds(
+            &entry.output.stdout,
+            offset.unwrap_or(entry.next_offset),
+            max_bytes,
+        );
+        entry.next_offset = end;
+        Ok(build_page(
+            &entry.output,
+            start,
+            end,
+            Some(cursor.to_string()),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(stdout: &str) -> CommandOutput {
+        CommandOutput {
+            stdout: stdout.to_string(),
+            exit_code: Some(0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_pages_through_stored_output() {
+        let store = OutputStore::new();
+        let small = store.first_page(None, output("hello"), 0, 10);
+        assert_eq!(small.stdout, "hello");
+        assert!(!small.truncated);
+        assert!(small.next_cursor.is_none());
+
+        let first = store.first_page(None, output("0123456789abcdef"), 0, 6);
+        assert_eq!(first.stdout, "012345");
+        assert!(first.truncated);
+        let cursor = first.next_cursor.clone().unwrap();
+        assert!(first.text().contains(&cursor));
+
+        let second = store.fetch(None, &cursor, None, 6).unwrap();
+        assert_eq!((second.stdout.as_str(), second.offset), ("6789ab", 6));
+        let last = store.fetch(None, &cursor, None, 6).unwrap();
+        assert_eq!(last.stdout, "cdef");
+        assert!(last.next_cursor.is_none());
+
+        let again = store.fetch(None, &cursor, Some(2), 3).unwrap();
+        assert_eq!(again.stdout, "234");
+
+        assert!(store.fetch(Some("client-2"), &cursor, None, 6).is_err());
+    }
+
+    #[test]
+    fn test_recent_outputs() {
+        let store = OutputStore::new();
+        let first = store.record(None, "web", "uptime", &output("up 3 days"));
+        let second = store.record(None, "db", "df -h", &output("/dev/sda1"));
+        store.record(Some("client-2"), "web", "id", &output("uid=0"));
+
+        let ids: Vec<u64> = store.recent(None).iter().map(|r| r.id).collect();
+        assert_eq!(ids, [second, first]);
+        assert_eq!(
+            store
+                .recent_output(None, "web", first)
+                .unwrap()
+                .output
+                .stdout,
+            "up 3 days"
+        );
+        assert!(store.recent_output(None, "db", first).is_none());
+        assert!(
+            store
+                .recent_output(Some("client-2"), "web", first)
+                .is_none()
+        );
+
+        for _ in 0..MAX_RECENT_OUTPUTS {
+            store.record(None, "web", "true", &output(""));
+        }
+        assert!(store.recent_output(None, "web", first).is_none());
+    }
+
+    #[test]
+    fn test_page_bounds_respect_char_boundaries() {
+        let text = "aé€b";
+        assert_eq!(page_bounds(text, 0, 2), (0, 1));
+        assert_eq!(page_bounds(text, 2, 1), (1, 3));
+        assert_eq!(page_bounds(text, 3, 10), (3, text.len()));
+        assert_eq!(page_bounds(text, 99, 10), (text.len(), text.len()));
+    }
+}
@@ -0,0 +1,189 @@
+//! Output parsers of command templates and plugins: turn a command's stdout
+//! into JSON returned as `parsed` next to it.
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use rmcp::model::CallToolResult;
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+
+/// How stdout is turned into JSON.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "format", rename_all = "lowercase", deny_unknown_fields)]
+pub enum OutputParser {
+    /// stdout is one JSON document.
+    Json,
+    /// One string per non-empty line.
+    Lines,
+    /// Whitespace-separated columns named by the first line, like `df` or
+    /// `ps`; the last column keeps the rest of the line.
+    Table,
+    /// One object per matching line, keyed by the pattern's named groups.
+    Regex {
+        #[serde(deserialize_with = "named_groups_regex")]
+        pattern: Regex,
+    },
+}
+
+impl OutputParser {
+    pub fn parse(&self, stdout: &str) -> Result<Value> {
+        let lines = || stdout.lines().filter(|line| !line.trim().is_empty());
+        Ok(match self {
+            Self::Json => serde_json::from_str(stdout).context("stdout is not JSON")?,
+            Self::Lines => lines().map(|line| Value::from(line.trim())).collect(),
+            Self::Table => {
+                let mut rows = lines();
+                let Some(header) = rows.next() else {
+                    return Ok(Value::Array(Vec::new()));
+                };
+                let columns: Vec<&str> = header.split_whitespace().collect();
+                rows.map(|row| {
+                    let cells = split_columns(row, columns.len());
+                    Value::Object(
+                        columns
+                            .iter()
+                            .zip(cells)
+                            .map(|(column, cell)| (column.to_string(), Value::from(cell)))
+                            .collect(),
+                    )
+                })
+                .collect()
+            }
+            Self::Regex { pattern } => lines()
+                .filter_map(|line| pattern.captures(line))
+                .map(|captures| {
+                    Value::Object(
+                        pattern
+                            .capture_names()
+                            .flatten()
+                            .map(|name| {
+                                let value = captures.name(name).map(|m| m.as_str());
+                                (name.to_string(), value.into())
+                            })
+                            .collect(),
+                    )
+                })
+                .collect(),
+        })
+    }
+}
+
+/// `row` split at whitespace into at most `count` cells.
+fn split_columns(row: &str, count: usize) -> Vec<&str> {
+    let mut cells = Vec::with_capacity(count);
+    let mut rest = row.trim();
+    while !rest.is_empty() {
+        if cells.len() + 1 == count {
+            cells.push(rest);
+            break;
+        }
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        cells.push(&rest[..end]);
+        rest = rest[end..].trim_start();
+    }
+    cells
+}
+
+fn named_groups_regex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Regex, D::Error> {
+    let pattern = String::deserialize(deserializer)?;
+    let regex = Regex::new(&pattern).map_err(serde::de::Error::custom)?;
+    if regex.capture_names().flatten().next().is_none() {
+        return Err(serde::de::Error::custom(
+            "pattern needs at least one named group, e.g. (?P<name>...)",
+        ));
+    }
+    Ok(regex)
+}
+
+/// `result` of a command with its stdout parsed into `parsed`, or the reason
+/// it couldn't be in `parse_error`.
+pub fn with_parsed(mut result: CallToolResult, parser: &OutputParser) -> CallToolResult {
+    let Some(Value::Object(ref mut page)) = result.structured_content else {
+        return result;
+    };
+    // A dry run has no output to parse.
+    if !page.contains_key("stdout") {
+        return result;
+    }
+    let parsed = if page.contains_key("next_cursor") {
+        Err("stdout is larger than one page; only complete output is parsed".to_string())
+    } else {
+        let stdout = page.get("stdout").and_then(Value::as_str).unwrap_or("");
+        parser.parse(stdout).map_err(|e| format!("{:#}", e))
+    };
+    match parsed {
+        Ok(value) => page.insert("parsed".to_string(), value),
+        Err(error) => page.insert("parse_error".to_string(), error.into()),
+    };
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parser(toml: &str) -> OutputParser {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn test_output_parsers() {
+        let df = "Filesystem Size Used Mounted on\n/dev/sda1 40G 35G /\n\n";
+        assert_eq!(
+            parser(r#"format = "table""#).parse(df).unwrap(),
+            json!([{ "Filesystem": "/dev/sda1", "Size": "40G", "Used": "35G", "Mounted": "/" }])
+        );
+        let ps = "PID COMMAND\n1 /sbin/init splash\n";
+        assert_eq!(
+            parser(r#"format = "table""#).parse(ps).unwrap(),
+            json!([{ "PID": "1", "COMMAND": "/sbin/init splash" }])
+        );
+
+        assert_eq!(
+            parser(r#"format = "lines""#).parse("a\n\n b \n").unwrap(),
+            json!(["a", "b"])
+        );
+        assert_eq!(
+            parser(r#"format = "json""#)
+                .parse(r#"{"ok": true}"#)
+                .unwrap(),
+            json!({ "ok": true })
+        );
+        assert!(parser(r#"format = "json""#).parse("ok").is_err());
+
+        let regex = parser(
+            r#"
+format = "regex"
+pattern = '^(?P<unit>\S+)\s+(?P<state>active|failed)(?:\s+(?P<note>.+))?$'
+"#,
+        );
+        assert_eq!(
+            regex
+                .parse("nginx.service active\nheader\ncron.service failed exit 1\n")
+                .unwrap(),
+            json!([
+                { "unit": "nginx.service", "state": "active", "note": null },
+                { "unit": "cron.service", "state": "failed", "note": "exit 1" },
+            ])
+        );
+        assert!(toml::from_str::<OutputParser>("format = \"regex\"\npattern = 'a+'").is_err());
+    }
+
+    #[test]
+    fn test_with_parsed() {
+        let lines = parser(r#"format = "lines""#);
+        let result = with_parsed(
+            CallToolResult::structured(json!({ "stdout": "a\nb\n", "exit_code": 0 })),
+            &lines,
+        );
+        assert_eq!(
+            result.structured_content.unwrap()["parsed"],
+            json!(["a", "b"])
+        );
+
+        let paged = json!({ "stdout": "a\n", "next_cursor": "out-1:2" });
+        let result = with_parsed(CallToolResult::structured(paged), &lines);
+        assert!(result.structured_content.unwrap()["parse_error"].is_string());
+    }
+}
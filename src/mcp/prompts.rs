@@ -0,0 +1,205 @@
+//! Built-in diagnostic playbooks offered as MCP prompts. Each one walks the
+//! model through read-only commands with the existing tools.
+
+use rmcp::ErrorData as McpError;
+use rmcp::model::{
+    GetPromptResult, JsonObject, Prompt, PromptArgument, PromptMessage, PromptMessageRole,
+};
+
+use crate::ssh::shell::quote;
+
+struct Argument {
+    name: &'static str,
+    description: &'static str,
+    /// Used when the argument is left out; `None` makes it required, and an
+    /// empty default leaves a `<name>` for the model to fill in.
+    default: Option<&'static str>,
+}
+
+struct Playbook {
+    name: &'static str,
+    title: &'static str,
+    description: &'static str,
+    arguments: &'static [Argument],
+    /// Steps, with `{name}` replaced by the shell-quoted argument.
+    steps: &'static str,
+}
+
+const HOST: Argument = Argument {
+    name: "host",
+    description: "Host alias from ~/.ssh/config",
+    default: None,
+};
+
+const PLAYBOOKS: &[Playbook] = &[
+    Playbook {
+        name: "diagnose_high_cpu",
+        title: "Diagnose high CPU",
+        description: "Find what is using the CPU on a host and whether it is abnormal",
+        arguments: &[HOST],
+        steps: "\
+1. `uptime` and `nproc`: compare the load averages with the CPU count.
+2. `top -b -n 1 -o %CPU | head -n 20`: the busiest processes.
+3. `ps -eo pid,ppid,user,etimes,%cpu,%mem,cmd --sort=-%cpu | head -n 15`: how long they have run and what started them.
+4. `vmstat 1 5`: split between user, system and iowait (`wa`); high `wa` points at the disks, not the CPU.
+5. For the top process, check its unit with `systemctl status <pid> --no-pager` and its recent log with `journalctl _PID=<pid> -n 50 --no-pager`.",
+    },
+    Playbook {
+        name: "investigate_failed_unit",
+        title: "Investigate failed systemd unit",
+        description: "Find out why a systemd unit failed and what it needs to start again",
+        arguments: &[
+            HOST,
+            Argument {
+                name: "unit",
+                description: "Unit to look at (default: every failed unit)",
+                default: Some(""),
+            },
+        ],
+        steps: "\
+1. `systemctl --failed --no-pager`: the failed units; without a unit named below, pick the one to look at.
+2. `systemctl status {unit} --no-pager -l`: state, exit code and the last log lines.
+3. `journalctl -u {unit} -n 100 --no-pager`: the log leading up to the failure.
+4. `systemctl cat {unit} --no-pager`: the unit file and drop-ins, for the command line, user and dependencies.
+5. Check what the log points at: missing files or permissions with `ls -l`, ports in use with `ss -ltnp`, failed dependencies with `systemctl list-dependencies {unit} --no-pager`.",
+    },
+    Playbook {
+        name: "check_disk_pressure",
+        title: "Check disk pressure",
+        description: "Find which filesystems are filling up and what is using the space",
+        arguments: &[
+            HOST,
+            Argument {
+                name: "path",
+                description: "Directory to break down (default: /)",
+                default: Some("/"),
+            },
+        ],
+        steps: "\
+1. `df -h -x tmpfs -x devtmpfs` and `df -i -x tmpfs -x devtmpfs`: space and inodes per filesystem.
+2. `du -xh --max-depth=2 {path} 2>/dev/null | sort -rh | head -n 20`: the largest directories under {path}.
+3. `find {path} -xdev -type f -size +500M -printf '%s %p\\n' 2>/dev/null | sort -rn | head -n 20`: the largest files.
+4. `lsof +L1 2>/dev/null | head -n 20`: deleted files still held open, which `du` doesn't count.
+5. `journalctl --disk-usage` and `ls -lhS /var/log | head`: logs that may need rotation.",
+    },
+    Playbook {
+        name: "check_memory_pressure",
+        title: "Check memory pressure",
+        description: "See whether a host is short of memory, swapping or killing processes",
+        arguments: &[HOST],
+        steps: "\
+1. `free -h` and `cat /proc/pressure/memory`: available memory, swap, and how long tasks stalled on memory.
+2. `ps -eo pid,user,rss,%mem,cmd --sort=-rss | head -n 15`: the largest processes.
+3. `vmstat 1 5`: swap activity in `si`/`so`.
+4. `journalctl -k --no-pager | grep -iE 'out of memory|oom-kill' | tail -n 20`: recent OOM kills.",
+    },
+];
+
+pub fn list() -> Vec<Prompt> {
+    PLAYBOOKS
+        .iter()
+        .map(|playbook| {
+            let arguments = playbook
+                .arguments
+                .iter()
+                .map(|arg| PromptArgument {
+                    name: arg.name.to_string(),
+                    title: None,
+                    description: Some(arg.description.to_string()),
+                    required: Some(arg.default.is_none()),
+                })
+                .collect();
+            let mut prompt =
+                Prompt::new(playbook.name, Some(playbook.description), Some(arguments));
+            prompt.title = Some(playbook.title.to_string());
+            prompt
+        })
+        .collect()
+}
+
+pub fn get(name: &str, arguments: Option<&JsonObject>) -> Result<GetPromptResult, McpError> {
+    let playbook = PLAYBOOKS
+        .iter()
+        .find(|p| p.name == name)
+        .ok_or_else(|| McpError::invalid_params(format!("Unknown prompt '{}'", name), None))?;
+
+    let mut steps = playbook.steps.to_string();
+    let mut host = String::new();
+    for arg in playbook.arguments {
+        let given = arguments
+            .and_then(|args| args.get(arg.name))
+            .and_then(|v| v.as_str())
+            .map(str::trim)
+            .filter(|v| !v.is_empty());
+        let value = match (given, arg.default) {
+            (Some(value), _) => value,
+            (None, Some(default)) => default,
+            (None, None) => {
+                return Err(McpError::invalid_params(
+                    format!("Prompt '{}' needs the '{}' argument", name, arg.name),
+                    None,
+                ));
+            }
+        };
+        if arg.name == HOST.name {
+            host = value.to_string();
+        }
+        let placeholder = format!("{{{}}}", arg.name);
+        let value = if value.is_empty() {
+            format!("<{}>", arg.name)
+        } else {
+            quote(value)
+        };
+        steps = steps.replace(&placeholder, &value);
+    }
+
+    let text = format!(
+        "{title} on '{host}'.\n\n\
+        Connect with ssh_connect (host_alias \"{host}\") unless a session is already open, \
+        then run these read-only commands with ssh_run_command, one at a time, adapting them \
+        to what you find:\n\n{steps}\n\n\
+        Don't change anything on the host. Finish with a short summary: the likely cause, \
+        the evidence for it, and the fix you would suggest.",
+        title = playbook.title,
+        host = host,
+        steps = steps,
+    );
+    Ok(GetPromptResult {
+        description: Some(playbook.description.to_string()),
+        messages: vec![PromptMessage::new_text(PromptMessageRole::User, text)],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmcp::model::PromptMessageContent;
+
+    fn text(result: &GetPromptResult) -> &str {
+        match result.messages[0].content {
+            PromptMessageContent::Text { ref text } => text,
+            _ => panic!("expected a text message"),
+        }
+    }
+
+    #[test]
+    fn test_playbook_arguments() {
+        let names: Vec<String> = list().into_iter().map(|p| p.name).collect();
+        assert!(names.contains(&"check_disk_pressure".to_string()));
+
+        let args = serde_json::json!({ "host": "web-1", "path": "/var lib" });
+        let result = get("check_disk_pressure", args.as_object()).unwrap();
+        assert!(text(&result).contains("on 'web-1'"));
+        assert!(text(&result).contains("du -xh --max-depth=2 '/var lib'"));
+
+        let args = serde_json::json!({ "host": "web-1" });
+        let result = get("check_disk_pressure", args.as_object()).unwrap();
+        assert!(text(&result).contains("du -xh --max-depth=2 / "));
+
+        let result = get("investigate_failed_unit", args.as_object()).unwrap();
+        assert!(text(&result).contains("journalctl -u <unit> -n 100"));
+
+        assert!(get("diagnose_high_cpu", None).is_err());
+        assert!(get("reboot_everything", args.as_object()).is_err());
+    }
+}
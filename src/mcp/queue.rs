@@ -0,0 +1,310 @@
+//! Per-session command queues. Commands for one shell run one at a time in
+//! the order they arrived; callers waiting their turn learn their position,
+//! and a full queue refuses new commands instead of piling them up.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedMutexGuard, watch};
+
+/// Commands that may wait behind the running one (default).
+pub const DEFAULT_QUEUE_DEPTH: usize = 8;
+
+/// Durations of recent commands the ETA is averaged over.
+const ETA_SAMPLES: usize = 8;
+
+/// One shell: client namespace, host alias and named channel.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct QueueKey {
+    pub namespace: Option<String>,
+    pub host: String,
+    pub channel: Option<String>,
+}
+
+impl QueueKey {
+    pub fn new(namespace: Option<&str>, host: &str, channel: Option<&str>) -> Self {
+        Self {
+            namespace: namespace.map(str::to_string),
+            host: host.to_string(),
+            channel: channel.map(str::to_string),
+        }
+    }
+}
+
+/// Where a waiting command stands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueStatus {
+    /// Commands that run before it, the running one included.
+    pub ahead: usize,
+    /// Estimate from the durations of recent commands; `None` before any finished.
+    pub eta: Option<Duration>,
+}
+
+impl QueueStatus {
+    pub fn message(&self, host: &str) -> String {
+        let mut message = format!("Queued behind {} command(s) on {}", self.ahead, host);
+        if let Some(eta) = self.eta {
+            message.push_str(&format!(", about {}s", eta.as_secs().max(1)));
+        }
+        message
+    }
+}
+
+/// The queue already holds `depth` waiting commands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueFull {
+    pub host: String,
+    pub channel: Option<String>,
+    pub depth: usize,
+}
+
+impl std::fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Command queue for '{}' is full ({} waiting behind the running command); retry when it finishes or use another channel",
+            self.host, self.depth
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    next_ticket: u64,
+    /// Tickets of the waiting commands, oldest first.
+    waiting: VecDeque<u64>,
+    running_since: Option<Instant>,
+    durations: VecDeque<Duration>,
+}
+
+impl QueueState {
+    fn status(&self, ticket: u64) -> QueueStatus {
+        let position = self.waiting.iter().position(|t| *t == ticket).unwrap_or(0);
+        let ahead = position + usize::from(self.running_since.is_some());
+        let eta = (!self.durations.is_empty()).then(|| {
+            let average = self.durations.iter().sum::<Duration>() / self.durations.len() as u32;
+            let elapsed = self.running_since.map_or(Duration::ZERO, |s| s.elapsed());
+            (average * ahead as u32).saturating_sub(elapsed)
+        });
+        QueueStatus { ahead, eta }
+    }
+}
+
+#[derive(Debug)]
+struct Queue {
+    /// Held by the running command; tokio hands it to waiters in FIFO order.
+    turn: Arc<tokio::sync::Mutex<()>>,
+    state: Mutex<QueueState>,
+    /// Bumped whenever positions move.
+    changed: watch::Sender<()>,
+}
+
+impl Queue {
+    fn state(&self) -> std::sync::MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// The queues of every shell, created on first use.
+#[derive(Debug)]
+pub struct CommandQueues {
+    depth: usize,
+    queues: Mutex<HashMap<QueueKey, Arc<Queue>>>,
+}
+
+impl Default for CommandQueues {
+    fn default() -> Self {
+        Self::new(DEFAULT_QUEUE_DEPTH)
+    }
+}
+
+impl CommandQueues {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            queues: Mutex::default(),
+        }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Drops the queues of a namespace's shells. Commands still holding a
+    /// slot keep their queue until they finish.
+    pub fn remove_namespace(&self, namespace: Option<&str>) {
+        self.queues
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|key, _| key.namespace.as_deref() != namespace);
+    }
+
+    /// Waits for the shell of `key` to be free and returns the slot holding
+    /// it. While waiting, `on_wait` gets the position each time it changes.
+    pub async fn enter(
+        &self,
+        key: QueueKey,
+        mut on_wait: impl FnMut(QueueStatus),
+    ) -> Result<QueueSlot, QueueFull> {
+        let queue = Arc::clone(
+            self.queues
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .entry(key.clone())
+                .or_insert_with(|| {
+                    Arc::new(Queue {
+                        turn: Arc::default(),
+                        state: Mutex::default(),
+                        changed: watch::Sender::new(()),
+                    })
+                }),
+        );
+
+        let ticket = {
+            let mut state = queue.state();
+            let busy = state.running_since.is_some() || !state.waiting.is_empty();
+            if busy && state.waiting.len() >= self.depth {
+                return Err(QueueFull {
+                    host: key.host,
+                    channel: key.channel,
+                    depth: self.depth,
+                });
+            }
+            let ticket = state.next_ticket;
+            state.next_ticket += 1;
+            state.waiting.push_back(ticket);
+            ticket
+        };
+        let waiter = Waiter {
+            queue: &queue,
+            ticket,
+        };
+
+        let arrived = Instant::now();
+        let mut changed = queue.changed.subscribe();
+        let turn = Arc::clone(&queue.turn).lock_owned();
+        tokio::pin!(turn);
+        let status = queue.state().status(ticket);
+        if status.ahead > 0 {
+            on_wait(status);
+        }
+        let guard = loop {
+            tokio::select! {
+                guard = &mut turn => break guard,
+                Ok(()) = changed.changed() => {
+                    let status = queue.state().status(ticket);
+                    if status.ahead > 0 {
+                        on_wait(status);
+                    }
+                }
+            }
+        };
+
+        {
+            let mut state = queue.state();
+            state.waiting.retain(|t| *t != ticket);
+            state.running_since = Some(Instant::now());
+        }
+        std::mem::forget(waiter);
+        Ok(QueueSlot {
+            waited: arrived.elapsed(),
+            queue: Arc::clone(&queue),
+            _turn: guard,
+        })
+    }
+}
+
+/// Takes a waiting command out of the queue if it's dropped before its turn.
+struct Waiter<'a> {
+    queue: &'a Queue,
+    ticket: u64,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        self.queue.state().waiting.retain(|t| *t != self.ticket);
+        self.queue.changed.send_replace(());
+    }
+}
+
+/// The turn of one command; the next one starts when it's dropped.
+#[derive(Debug)]
+pub struct QueueSlot {
+    waited: Duration,
+    queue: Arc<Queue>,
+    _turn: OwnedMutexGuard<()>,
+}
+
+impl QueueSlot {
+    /// How long the command waited for its turn.
+    pub fn waited(&self) -> Duration {
+        self.waited
+    }
+}
+
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        let mut state = self.queue.state();
+        if let Some(started) = state.running_since.take() {
+            if state.durations.len() == ETA_SAMPLES {
+                state.durations.pop_front();
+            }
+            state.durations.push_back(started.elapsed());
+        }
+        drop(state);
+        self.queue.changed.send_replace(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_command_queue() {
+        let queues = Arc::new(CommandQueues::new(1));
+        let key = QueueKey::new(None, "web1", None);
+
+        let first = queues.enter(key.clone(), |_| {}).await.unwrap();
+        assert!(first.waited() < Duration::from_secs(1));
+
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let second = tokio::spawn({
+            let queues = Arc::clone(&queues);
+            let key = key.clone();
+            async move {
+                let slot = queues
+                    .enter(key, move |status| tx.send(status).unwrap())
+                    .await
+                    .unwrap();
+                slot.waited()
+            }
+        });
+        let status = rx.recv().await.unwrap();
+        assert_eq!(
+            status,
+            QueueStatus {
+                ahead: 1,
+                eta: None
+            }
+        );
+        assert_eq!(status.message("web1"), "Queued behind 1 command(s) on web1");
+
+        let full = queues.enter(key.clone(), |_| {}).await.unwrap_err();
+        assert_eq!(full.depth, 1);
+        // Other channels have queues of their own.
+        let build = QueueKey::new(None, "web1", Some("build"));
+        drop(queues.enter(build, |_| {}).await.unwrap());
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        drop(first);
+        assert!(second.await.unwrap() >= Duration::from_millis(20));
+
+        // Free again, with the finished commands' durations for the ETA.
+        let slot = queues.enter(key.clone(), |_| {}).await.unwrap();
+        let state = queues.queues.lock().unwrap()[&key].state().status(u64::MAX);
+        assert_eq!(state.ahead, 1);
+        assert!(state.eta.is_some());
+        drop(slot);
+    }
+}
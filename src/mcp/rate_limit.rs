@@ -0,0 +1,204 @@
+//! Commands-per-minute limits, per host and across all hosts, so a looping
+//! agent can't flood a box with commands.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Window the limits count commands in.
+pub const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Commands per minute; `None` or 0 is unlimited.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RateLimits {
+    pub per_host: Option<u32>,
+    pub global: Option<u32>,
+}
+
+/// Why a command was refused, and when it may be tried again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimited {
+    /// The host whose limit was hit; `None` for the global limit.
+    pub host: Option<String>,
+    pub limit: u32,
+    pub retry_after: Duration,
+}
+
+impl RateLimited {
+    /// `retry_after` in whole seconds, rounded up.
+    pub fn retry_after_secs(&self) -> u64 {
+        self.retry_after.as_secs() + u64::from(self.retry_after.subsec_nanos() > 0)
+    }
+}
+
+impl std::fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.host {
+            Some(host) => write!(
+                f,
+                "Rate limited: at most {} commands per minute on '{}'",
+                self.limit, host
+            )?,
+            None => write!(
+                f,
+                "Rate limited: at most {} commands per minute across all hosts",
+                self.limit
+            )?,
+        }
+        write!(f, ", retry after {} seconds", self.retry_after_secs())
+    }
+}
+
+/// Start times of the commands of the last [`RATE_WINDOW`].
+#[derive(Debug, Default)]
+struct Windows {
+    global: VecDeque<Instant>,
+    hosts: HashMap<String, VecDeque<Instant>>,
+}
+
+/// Sliding-window limiter shared by every client of the server.
+#[derive(Debug, Default)]
+pub struct CommandRateLimiter {
+    limits: RateLimits,
+    windows: Mutex<Windows>,
+}
+
+impl CommandRateLimiter {
+    pub fn new(limits: RateLimits) -> Self {
+        Self {
+            limits: RateLimits {
+                per_host: limits.per_host.filter(|n| *n > 0),
+                global: limits.global.filter(|n| *n > 0),
+            },
+            windows: Mutex::default(),
+        }
+    }
+
+    pub fn limits(&self) -> RateLimits {
+        self.limits
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.limits.per_host.is_some() || self.limits.global.is_some()
+    }
+
+    /// Counts one command on each of `hosts`, or none of them if that would
+    /// exceed a limit.
+    pub fn acquire(&self, hosts: &[&str]) -> Result<(), RateLimited> {
+        self.acquire_at(hosts, Instant::now())
+    }
+
+    fn acquire_at(&self, hosts: &[&str], now: Instant) -> Result<(), RateLimited> {
+        if !self.is_enabled() {
+            return Ok(());
+        }
+        let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+        let windows = &mut *windows;
+        expire(&mut windows.global, now);
+        windows.hosts.retain(|_, times| {
+            expire(times, now);
+            !times.is_empty()
+        });
+
+        if let Some(limit) = self.limits.global {
+            check(&windows.global, hosts.len(), limit, now).map_err(|retry_after| RateLimited {
+                host: None,
+                limit,
+                retry_after,
+            })?;
+        }
+        if let Some(limit) = self.limits.per_host {
+            let none = VecDeque::new();
+            for host in hosts {
+                let times = windows.hosts.get(*host).unwrap_or(&none);
+                let count = hosts.iter().filter(|h| *h == host).count();
+                check(times, count, limit, now).map_err(|retry_after| RateLimited {
+                    host: Some(host.to_string()),
+                    limit,
+                    retry_after,
+                })?;
+            }
+        }
+
+        for host in hosts {
+            windows.global.push_back(now);
+            if self.limits.per_host.is_some() {
+                windows
+                    .hosts
+                    .entry(host.to_string())
+                    .or_default()
+                    .push_back(now);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn expire(times: &mut VecDeque<Instant>, now: Instant) {
+    while times
+        .front()
+        .is_some_and(|start| now.saturating_duration_since(*start) >= RATE_WINDOW)
+    {
+        times.pop_front();
+    }
+}
+
+/// Whether `count` more commands fit next to `times`; if not, how long until
+/// enough of them leave the window.
+fn check(
+    times: &VecDeque<Instant>,
+    count: usize,
+    limit: u32,
+    now: Instant,
+) -> Result<(), Duration> {
+    let excess = (times.len() + count).saturating_sub(limit as usize);
+    if excess == 0 {
+        return Ok(());
+    }
+    Err(match times.get(excess - 1) {
+        Some(start) => (*start + RATE_WINDOW).saturating_duration_since(now),
+        None => RATE_WINDOW,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limits() {
+        let limiter = CommandRateLimiter::new(RateLimits {
+            per_host: Some(2),
+            global: Some(3),
+        });
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert!(limiter.acquire_at(&["web1"], at(0)).is_ok());
+        assert!(limiter.acquire_at(&["web1"], at(10)).is_ok());
+        let limited = limiter.acquire_at(&["web1"], at(20)).unwrap_err();
+        assert_eq!(limited.host.as_deref(), Some("web1"));
+        assert_eq!(limited.retry_after_secs(), 40);
+        assert_eq!(
+            limited.to_string(),
+            "Rate limited: at most 2 commands per minute on 'web1', retry after 40 seconds"
+        );
+
+        // A refused batch counts nothing.
+        let limited = limiter.acquire_at(&["web2", "web3"], at(20)).unwrap_err();
+        assert_eq!(limited.host, None);
+        assert_eq!(limited.retry_after, Duration::from_secs(40));
+        assert!(limiter.acquire_at(&["web2"], at(20)).is_ok());
+
+        // The first command leaves the window after a minute.
+        assert!(limiter.acquire_at(&["web1"], at(60)).is_ok());
+        assert!(limiter.acquire_at(&["web1"], at(61)).is_err());
+
+        let unlimited = CommandRateLimiter::new(RateLimits {
+            per_host: Some(0),
+            global: None,
+        });
+        assert!(!unlimited.is_enabled());
+        assert!(unlimited.acquire(&["web1"; 100]).is_ok());
+    }
+}
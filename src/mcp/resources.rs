@@ -0,0 +1,367 @@
+//! MCP resources: `ssh://<alias>/info` for each connected host,
+//! `ssh://<alias>/outputs/<id>` for its recent command outputs,
+//! `ssh://<alias>/watches/<id>` for the lines its log watches matched,
+//! `ssh://<alias>/schedules/<id>` for the events of its scheduled commands, and
+//! `ssh://<alias>/health` for the results of a host's health probes.
+
+use rmcp::ErrorData as McpError;
+use rmcp::model::{
+    AnnotateAble, RawResource, RawResourceTemplate, ReadResourceResult, Resource, ResourceContents,
+    ResourceTemplate,
+};
+use serde::Serialize;
+
+use super::output::OutputStore;
+use crate::redact::Redactor;
+use crate::ssh::SessionManager;
+use crate::ssh::health::{HealthMonitor, HostHealth};
+use crate::ssh::schedule::ScheduleReport;
+use crate::ssh::watch::WatchInfo;
+
+const JSON_MIME: &str = "application/json";
+
+/// A resource URI this server understands.
+#[derive(Debug, PartialEq)]
+enum ResourceUri<'a> {
+    Info { alias: &'a str },
+    Output { alias: &'a str, id: u64 },
+    Watch { alias: &'a str, id: &'a str },
+    Schedule { alias: &'a str, id: &'a str },
+    Health { alias: &'a str },
+}
+
+impl<'a> ResourceUri<'a> {
+    fn parse(uri: &'a str) -> Option<Self> {
+        let (alias, path) = uri.strip_prefix("ssh://")?.split_once('/')?;
+        if alias.is_empty() {
+            return None;
+        }
+        match path.split_once('/') {
+            None if path == "info" => Some(Self::Info { alias }),
+            None if path == "health" => Some(Self::Health { alias }),
+            Some(("outputs", id)) => Some(Self::Output {
+                alias,
+                id: id.parse().ok()?,
+            }),
+            Some(("watches", id)) if !id.is_empty() => Some(Self::Watch { alias, id }),
+            Some(("schedules", id)) if !id.is_empty() => Some(Self::Schedule { alias, id }),
+            _ => None,
+        }
+    }
+}
+
+fn info_uri(alias: &str) -> String {
+    format!("ssh://{}/info", alias)
+}
+
+fn output_uri(alias: &str, id: u64) -> String {
+    format!("ssh://{}/outputs/{}", alias, id)
+}
+
+fn health_uri(alias: &str) -> String {
+    format!("ssh://{}/health", alias)
+}
+
+pub fn watch_uri(alias: &str, id: &str) -> String {
+    format!("ssh://{}/watches/{}", alias, id)
+}
+
+pub fn schedule_uri(alias: &str, id: &str) -> String {
+    format!("ssh://{}/schedules/{}", alias, id)
+}
+
+/// The watch id behind a subscribable URI.
+pub fn watch_id(uri: &str) -> Option<&str> {
+    match ResourceUri::parse(uri)? {
+        ResourceUri::Watch { id, .. } => Some(id),
+        _ => None,
+    }
+}
+
+/// The schedule id behind a subscribable URI.
+pub fn schedule_id(uri: &str) -> Option<&str> {
+    match ResourceUri::parse(uri)? {
+        ResourceUri::Schedule { id, .. } => Some(id),
+        _ => None,
+    }
+}
+
+/// The caller's sessions, log watches and schedules, the probed hosts, then
+/// the caller's recent outputs, newest first.
+pub async fn list(
+    session_manager: &SessionManager,
+    outputs: &OutputStore,
+    health: &HealthMonitor,
+) -> Vec<Resource> {
+    let mut resources: Vec<Resource> = session_manager
+        .list_connections()
+        .await
+        .into_iter()
+        .filter(|info| info.is_own)
+        .map(|info| {
+            let mut resource = RawResource::new(info_uri(&info.alias), info.alias.clone());
+            resource.title = Some(format!(
+                "{} ({}@{}:{})",
+                info.alias, info.user, info.hostname, info.port
+            ));
+            resource.description = Some("Connection metadata of an open SSH session".to_string());
+            resource.mime_type = Some(JSON_MIME.to_string());
+            resource.no_annotation()
+        })
+        .collect();
+
+    resources.extend(
+        session_manager
+            .list_watches()
+            .await
+            .into_iter()
+            .map(|watch| {
+                let mut resource =
+                    RawResource::new(watch_uri(&watch.host, &watch.id), watch.id.clone());
+                resource.title = Some(format!(
+                    "{}: /{}/ in {}",
+                    watch.host, watch.pattern, watch.path
+                ));
+                resource.description = Some(format!(
+                    "Lines matched by a log watch ({} so far); subscribe for updates",
+                    watch.matched
+                ));
+                resource.mime_type = Some(JSON_MIME.to_string());
+                resource.no_annotation()
+            }),
+    );
+
+    resources.extend(
+        session_manager
+            .list_schedules()
+            .await
+            .into_iter()
+            .map(|schedule| {
+                let mut resource = RawResource::new(
+                    schedule_uri(&schedule.host, &schedule.id),
+                    schedule.id.clone(),
+                );
+                resource.title = Some(format!(
+                    "{}: {} every {}s",
+                    schedule.host, schedule.command, schedule.interval_secs
+                ));
+                resource.description = Some(format!(
+                    "Runs of a scheduled command that changed ({} so far); subscribe for updates",
+                    schedule.events
+                ));
+                resource.mime_type = Some(JSON_MIME.to_string());
+                resource.no_annotation()
+            }),
+    );
+
+    resources.extend(health.hosts().into_iter().map(|host| {
+        let mut resource = RawResource::new(health_uri(&host), format!("{} health", host));
+        resource.description = Some(format!(
+            "Latest results of {}'s health probes, run every {}s",
+            host,
+            health.interval().as_secs()
+        ));
+        resource.mime_type = Some(JSON_MIME.to_string());
+        resource.no_annotation()
+    }));
+
+    resources.extend(
+        outputs
+            .recent(session_manager.namespace())
+            .into_iter()
+            .map(|recent| {
+                let mut resource = RawResource::new(
+                    output_uri(&recent.host, recent.id),
+                    format!("{} #{}", recent.host, recent.id),
+                );
+                resource.title = Some(format!("{}: {}", recent.host, recent.command));
+                resource.description = Some(match recent.output.exit_code {
+                    Some(code) => format!("Command output (exit code {})", code),
+                    None => "Command output (no exit code)".to_string(),
+                });
+                resource.mime_type = Some(JSON_MIME.to_string());
+                resource.size =
+                    u32::try_from(recent.output.stdout.len() + recent.output.stderr.len()).ok();
+                resource.no_annotation()
+            }),
+    );
+    resources
+}
+
+pub fn templates() -> Vec<ResourceTemplate> {
+    [
+        (
+            "ssh://{alias}/info",
+            "session-info",
+            "User, hostname, port, shell and channels of a connected host",
+        ),
+        (
+            "ssh://{alias}/outputs/{id}",
+            "command-output",
+            "A recent command's stdout, stderr and exit code",
+        ),
+        (
+            "ssh://{alias}/watches/{id}",
+            "log-watch",
+            "Lines a log watch (ssh_watch_log) has matched, newest last",
+        ),
+        (
+            "ssh://{alias}/schedules/{id}",
+            "scheduled-command",
+            "Latest output and change events of a scheduled command (ssh_schedule)",
+        ),
+        (
+            "ssh://{alias}/health",
+            "host-health",
+            "Latest results of a host's configured health probes",
+        ),
+    ]
+    .into_iter()
+    .map(|(uri_template, name, description)| {
+        RawResourceTemplate {
+            uri_template: uri_template.to_string(),
+            name: name.to_string(),
+            title: None,
+            description: Some(description.to_string()),
+            mime_type: Some(JSON_MIME.to_string()),
+        }
+        .no_annotation()
+    })
+    .collect()
+}
+
+/// A watch and its matched lines, as tools and resources return them.
+#[derive(Serialize)]
+pub struct WatchMatches {
+    #[serde(flatten)]
+    pub watch: WatchInfo,
+    pub lines: Vec<String>,
+}
+
+impl WatchMatches {
+    pub fn new(redactor: &Redactor, (watch, lines): (WatchInfo, Vec<String>)) -> Self {
+        let lines = lines
+            .iter()
+            .map(|line| redactor.redact(line).into_owned())
+            .collect();
+        Self { watch, lines }
+    }
+}
+
+/// `report` with secrets masked in its output, diffs and matched lines.
+pub fn redacted_report(redactor: &Redactor, mut report: ScheduleReport) -> ScheduleReport {
+    let redact = |text: &str| redactor.redact(text).into_owned();
+    report.last_output = report.last_output.as_deref().map(redact);
+    for event in &mut report.events {
+        event.diff = event.diff.as_deref().map(redact);
+        for line in &mut event.matched {
+            *line = redact(line);
+        }
+    }
+    report
+}
+
+/// `health` with secrets masked in its probes' details.
+pub fn redacted_health(redactor: &Redactor, mut health: HostHealth) -> HostHealth {
+    for probe in &mut health.probes {
+        probe.detail = probe
+            .detail
+            .as_deref()
+            .map(|detail| redactor.redact(detail).into_owned());
+    }
+    health
+}
+
+pub async fn read(
+    session_manager: &SessionManager,
+    outputs: &OutputStore,
+    health: &HealthMonitor,
+    redactor: &Redactor,
+    uri: &str,
+) -> Result<ReadResourceResult, McpError> {
+    let not_found = || McpError::resource_not_found(format!("No resource at {}", uri), None);
+    let contents = match ResourceUri::parse(uri).ok_or_else(not_found)? {
+        ResourceUri::Info { alias } => {
+            let info = session_manager
+                .list_connections()
+                .await
+                .into_iter()
+                .find(|info| info.is_own && info.alias == alias)
+                .ok_or_else(not_found)?;
+            json_contents(uri, &info)?
+        }
+        ResourceUri::Output { alias, id } => {
+            let recent = outputs
+                .recent_output(session_manager.namespace(), alias, id)
+                .ok_or_else(not_found)?;
+            json_contents(uri, &recent)?
+        }
+        ResourceUri::Watch { alias, id } => {
+            let watch = session_manager
+                .watch_lines(id)
+                .await
+                .ok()
+                .filter(|(watch, _)| watch.host == alias)
+                .ok_or_else(not_found)?;
+            json_contents(uri, &WatchMatches::new(redactor, watch))?
+        }
+        ResourceUri::Schedule { alias, id } => {
+            let report = session_manager
+                .schedule_report(id)
+                .await
+                .ok()
+                .filter(|report| report.schedule.host == alias)
+                .ok_or_else(not_found)?;
+            json_contents(uri, &redacted_report(redactor, report))?
+        }
+        ResourceUri::Health { alias } => {
+            let host = health.host(alias).ok_or_else(not_found)?;
+            json_contents(uri, &redacted_health(redactor, host))?
+        }
+    };
+    Ok(ReadResourceResult {
+        contents: vec![contents],
+    })
+}
+
+fn json_contents(uri: &str, value: &impl Serialize) -> Result<ResourceContents, McpError> {
+    let text = serde_json::to_string_pretty(value)
+        .map_err(|e| McpError::internal_error(e.to_string(), None))?;
+    Ok(ResourceContents::TextResourceContents {
+        uri: uri.to_string(),
+        mime_type: Some(JSON_MIME.to_string()),
+        text,
+        meta: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resource_uris() {
+        assert_eq!(
+            ResourceUri::parse("ssh://web-1/info"),
+            Some(ResourceUri::Info { alias: "web-1" })
+        );
+        assert_eq!(
+            ResourceUri::parse(&output_uri("db", 7)),
+            Some(ResourceUri::Output { alias: "db", id: 7 })
+        );
+        assert_eq!(
+            ResourceUri::parse(&health_uri("web-1")),
+            Some(ResourceUri::Health { alias: "web-1" })
+        );
+        assert_eq!(watch_id(&watch_uri("web-1", "watch-3")), Some("watch-3"));
+        assert_eq!(watch_id("ssh://web-1/info"), None);
+        assert_eq!(
+            schedule_id(&schedule_uri("web-1", "schedule-2")),
+            Some("schedule-2")
+        );
+        assert_eq!(schedule_id(&watch_uri("web-1", "watch-3")), None);
+        assert_eq!(ResourceUri::parse("ssh://db/outputs/x"), None);
+        assert_eq!(ResourceUri::parse("ssh:///info"), None);
+        assert_eq!(ResourceUri::parse("file:///etc/hosts"), None);
+    }
+}
@@ -0,0 +1,401 @@
+//! Command templates from `[templates.<name>]` in `config.toml`, each served
+//! as a tool of its own: the caller picks a host and fills in parameters,
+//! which must be in their allowlist or match their pattern and are
+//! shell-quoted into the command. An output parser can turn the command's
+//! stdout into JSON.
+
+use anyhow::Result;
+use regex::{Captures, Regex};
+use rmcp::ErrorData as McpError;
+use rmcp::handler::server::router::tool::ToolRoute;
+use rmcp::handler::server::tool::ToolCallContext;
+use rmcp::model::{CallToolResult, JsonObject, Tool, ToolAnnotations};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+
+use super::SshMcpServer;
+use super::parsers::{OutputParser, with_parsed};
+use crate::config::TemplateConfig;
+use crate::error::SshLiaisonError;
+use crate::inventory::Inventory;
+use crate::ssh::shell::quote;
+
+static PLACEHOLDER_REGEX: OnceLock<Regex> = OnceLock::new();
+
+/// `{name}` where a parameter's value goes.
+fn placeholder() -> &'static Regex {
+    PLACEHOLDER_REGEX.get_or_init(|| {
+        Regex::new(r"\{([A-Za-z_][A-Za-z0-9_]*)\}").expect("Placeholder regex should be valid")
+    })
+}
+
+/// Argument every template tool takes besides its parameters.
+const HOST_ARG: &str = "host";
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct TemplateParam {
+    pub description: Option<String>,
+    /// Values the parameter may take; empty allows any matching `pattern`.
+    pub allowed: Vec<String>,
+    /// Anchored regex every value must match.
+    pub pattern: Option<Regex>,
+    pub default: Option<String>,
+}
+
+impl TemplateParam {
+    fn check(&self, name: &str, value: &str) -> Result<()> {
+        if !self.allowed.is_empty() && !self.allowed.iter().any(|v| v == value) {
+            return Err(invalid(format!(
+                "{} must be one of: {}",
+                name,
+                self.allowed.join(", ")
+            )));
+        }
+        if let Some(ref pattern) = self.pattern
+            && !pattern.is_match(value)
+        {
+            return Err(invalid(format!("{} must match {}", name, pattern.as_str())));
+        }
+        Ok(())
+    }
+
+    fn schema(&self) -> Value {
+        let mut schema = json!({ "type": "string" });
+        if let Some(ref description) = self.description {
+            schema["description"] = json!(description);
+        }
+        if !self.allowed.is_empty() {
+            schema["enum"] = json!(self.allowed);
+        }
+        if let Some(ref pattern) = self.pattern {
+            schema["pattern"] = json!(pattern.as_str());
+        }
+        if let Some(ref default) = self.default {
+            schema["default"] = json!(default);
+        }
+        schema
+    }
+}
+
+/// A named command with parameters, run like `ssh_run_command`.
+#[derive(Debug, Clone)]
+pub struct CommandTemplate {
+    pub name: String,
+    pub description: Option<String>,
+    pub command: String,
+    /// Hosts the template may run on; empty allows any.
+    pub hosts: Vec<String>,
+    pub read_only: bool,
+    pub params: BTreeMap<String, TemplateParam>,
+    pub output: Option<OutputParser>,
+}
+
+impl CommandTemplate {
+    /// Checks `config` and expands its `@group` hosts from `inventory`.
+    pub fn new(name: &str, config: &TemplateConfig, inventory: &Inventory) -> Result<Self> {
+        check_tool_name(name)?;
+        if config.command.trim().is_empty() {
+            return Err(invalid(format!("template '{}' has an empty command", name)));
+        }
+
+        let mut params = BTreeMap::new();
+        for (param, settings) in &config.params {
+            if param == HOST_ARG {
+                return Err(invalid(format!(
+                    "template '{}': '{}' is reserved for the host alias",
+                    name, HOST_ARG
+                )));
+            }
+            let used = placeholder()
+                .captures_iter(&config.command)
+                .any(|c| &c[1] == param);
+            if !used {
+                return Err(invalid(format!(
+                    "template '{}': parameter '{}' has no {{{}}} in the command",
+                    name, param, param
+                )));
+            }
+            let pattern = settings
+                .pattern
+                .as_deref()
+                .map(|p| Regex::new(&format!("^(?:{})$", p)))
+                .transpose()
+                .map_err(|e| {
+                    invalid(format!("template '{}', parameter '{}': {}", name, param, e))
+                })?;
+            let param_settings = TemplateParam {
+                description: settings.description.clone(),
+                allowed: settings.allowed.clone(),
+                pattern,
+                default: settings.default.clone(),
+            };
+            if let Some(ref default) = param_settings.default {
+                param_settings
+                    .check(param, default)
+                    .map_err(|e| invalid(format!("template '{}' default: {}", name, e)))?;
+            }
+            params.insert(param.clone(), param_settings);
+        }
+
+        Ok(Self {
+            name: name.to_string(),
+            description: config.description.clone(),
+            command: config.command.clone(),
+            hosts: inventory.expand_targets(&config.hosts)?,
+            read_only: config.read_only,
+            params,
+            output: config.output.clone(),
+        })
+    }
+
+    /// The host and command for a call's arguments.
+    pub fn render(&self, arguments: &JsonObject) -> Result<(String, String)> {
+        if let Some(unknown) = arguments
+            .keys()
+            .find(|key| *key != HOST_ARG && !self.params.contains_key(*key))
+        {
+            return Err(invalid(format!("unknown parameter '{}'", unknown)));
+        }
+        let host = string_arg(arguments, HOST_ARG)?
+            .ok_or_else(|| invalid(format!("missing '{}'", HOST_ARG)))?;
+        if !self.hosts.is_empty() && !self.hosts.contains(&host) {
+            return Err(invalid(format!(
+                "{} can only run on: {}",
+                self.name,
+                self.hosts.join(", ")
+            )));
+        }
+
+        let mut values = BTreeMap::new();
+        for (name, param) in &self.params {
+            let value = match string_arg(arguments, name)? {
+                Some(value) => value,
+                None => param
+                    .default
+                    .clone()
+                    .ok_or_else(|| invalid(format!("missing '{}'", name)))?,
+            };
+            param.check(name, &value)?;
+            values.insert(name.as_str(), value);
+        }
+        let command =
+            placeholder().replace_all(&self.command, |c: &Captures| match values.get(&c[1]) {
+                Some(value) => quote(value),
+                None => c[0].to_string(),
+            });
+        Ok((host, command.into_owned()))
+    }
+
+    fn tool(&self) -> Tool {
+        let mut properties = serde_json::Map::new();
+        let mut host = json!({
+            "type": "string",
+            "description": "Host alias to run the command on",
+        });
+        if !self.hosts.is_empty() {
+            host["enum"] = json!(self.hosts);
+        }
+        properties.insert(HOST_ARG.to_string(), host);
+        let mut required = vec![HOST_ARG.to_string()];
+        for (name, param) in &self.params {
+            properties.insert(name.clone(), param.schema());
+            if param.default.is_none() {
+                required.push(name.clone());
+            }
+        }
+        let schema = json!({
+            "type": "object",
+            "properties": properties,
+            "required": required,
+            "additionalProperties": false,
+        });
+        let Value::Object(schema) = schema else {
+            unreachable!("schema is an object");
+        };
+
+        let description = match self.description {
+            Some(ref description) => format!("{} Runs `{}`.", description, self.command),
+            None => format!("Runs `{}` on a host.", self.command),
+        };
+        Tool::new(self.name.clone(), description, schema).annotate(
+            ToolAnnotations::new()
+                .read_only(self.read_only)
+                .destructive(!self.read_only)
+                .open_world(true),
+        )
+    }
+
+    /// The template as a tool that runs its command through the server's
+    /// policy, audit and history like `ssh_run_command`.
+    pub fn route(self) -> ToolRoute<SshMcpServer> {
+        let tool = self.tool();
+        let template = Arc::new(self);
+        ToolRoute::new_dyn(tool, move |context| call(Arc::clone(&template), context))
+    }
+}
+
+fn call(
+    template: Arc<CommandTemplate>,
+    context: ToolCallContext<'_, SshMcpServer>,
+) -> Pin<Box<dyn Future<Output = Result<CallToolResult, McpError>> + Send + '_>> {
+    Box::pin(async move {
+        let arguments = context.arguments.unwrap_or_default();
+        let (host, command) = template
+            .render(&arguments)
+            .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+        tracing::debug!(template = %template.name, host = %host, "Running command template");
+        let result = context
+            .service
+            .run_tool_command(&context.request_context, host, command)
+            .await?;
+        Ok(match template.output {
+            Some(ref parser) => with_parsed(result, parser),
+            None => result,
+        })
+    })
+}
+
+/// Rejects names that aren't 1 to 64 letters, digits, `_` or `-`, or that
+/// belong to a built-in tool.
+pub(super) fn check_tool_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if !valid {
+        return Err(invalid(format!(
+            "tool name '{}' must be 1 to {} letters, digits, '_' or '-'",
+            name, MAX_NAME_LEN
+        )));
+    }
+    if SshMcpServer::tool_router().has_route(name) {
+        return Err(invalid(format!(
+            "'{}' is the name of a built-in tool",
+            name
+        )));
+    }
+    Ok(())
+}
+
+fn string_arg(arguments: &JsonObject, name: &str) -> Result<Option<String>> {
+    match arguments.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(value)) => Ok(Some(value.clone())),
+        Some(_) => Err(invalid(format!("'{}' must be a string", name))),
+    }
+}
+
+pub(super) fn invalid(message: String) -> anyhow::Error {
+    SshLiaisonError::Config(message).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TemplateParamConfig;
+
+    fn restart_app() -> TemplateConfig {
+        TemplateConfig {
+            description: Some("Restart an app unit.".to_string()),
+            command: "sudo systemctl restart {unit} && systemctl is-active {unit}".to_string(),
+            hosts: vec!["web1".to_string(), "web2".to_string()],
+            read_only: false,
+            output: None,
+            params: BTreeMap::from([(
+                "unit".to_string(),
+                TemplateParamConfig {
+                    description: None,
+                    allowed: vec!["api.service".to_string(), "worker service".to_string()],
+                    pattern: None,
+                    default: None,
+                },
+            )]),
+        }
+    }
+
+    fn args(value: Value) -> JsonObject {
+        let Value::Object(args) = value else {
+            panic!("not an object")
+        };
+        args
+    }
+
+    #[test]
+    fn test_render_template() {
+        let template =
+            CommandTemplate::new("restart_app", &restart_app(), &Inventory::default()).unwrap();
+        let (host, command) = template
+            .render(&args(json!({ "host": "web1", "unit": "worker service" })))
+            .unwrap();
+        assert_eq!(host, "web1");
+        assert_eq!(
+            command,
+            "sudo systemctl restart 'worker service' && systemctl is-active 'worker service'"
+        );
+
+        let rejected = [
+            json!({ "host": "web1", "unit": "api.service; reboot" }),
+            json!({ "host": "db1", "unit": "api.service" }),
+            json!({ "host": "web1" }),
+            json!({ "host": "web1", "unit": "api.service", "force": "yes" }),
+        ];
+        for arguments in rejected {
+            assert!(template.render(&args(arguments)).is_err());
+        }
+
+        let tool = template.tool();
+        assert_eq!(tool.input_schema["required"], json!(["host", "unit"]));
+        assert_eq!(
+            tool.input_schema["properties"]["unit"]["enum"],
+            json!(["api.service", "worker service"])
+        );
+        assert_eq!(tool.annotations.unwrap().destructive_hint, Some(true));
+    }
+
+    #[test]
+    fn test_template_validation() {
+        let inventory = Inventory::default();
+        let mut config = TemplateConfig {
+            command: "journalctl -u {unit} -n {lines} --no-pager".to_string(),
+            read_only: true,
+            params: BTreeMap::from([
+                (
+                    "unit".to_string(),
+                    TemplateParamConfig {
+                        pattern: Some(r"[a-z-]+\.service".to_string()),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "lines".to_string(),
+                    TemplateParamConfig {
+                        pattern: Some("[0-9]{1,4}".to_string()),
+                        default: Some("100".to_string()),
+                        ..Default::default()
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+        let template = CommandTemplate::new("unit_logs", &config, &inventory).unwrap();
+        let (_, command) = template
+            .render(&args(json!({ "host": "db1", "unit": "nginx.service" })))
+            .unwrap();
+        assert_eq!(command, "journalctl -u nginx.service -n 100 --no-pager");
+        let evil = json!({ "host": "db1", "unit": "x.service -f; id" });
+        assert!(template.render(&args(evil)).is_err());
+
+        assert!(CommandTemplate::new("ssh_run_command", &config, &inventory).is_err());
+        assert!(CommandTemplate::new("bad name", &config, &inventory).is_err());
+        config.command = "journalctl -u {unit} --no-pager".to_string();
+        assert!(
+            CommandTemplate::new("unit_logs", &config, &inventory).is_err(),
+            "unused parameter"
+        );
+    }
+}
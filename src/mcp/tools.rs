@@ -1,22 +1,110 @@
 use anyhow::Result;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
 use rmcp::{
-    ErrorData as McpError,
+    ErrorData as McpError, Peer, RoleServer,
     handler::server::wrapper::Parameters,
-    model::{CallToolResult, Content},
+    model::{
+        CallToolResult, Content, ErrorCode, Meta, ProgressNotificationParam, ResourceContents,
+        ResourceUpdatedNotificationParam,
+    },
     schemars::JsonSchema,
+    service::ElicitationError,
 };
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
 
+use crate::audit::{ApprovalChannel, ApprovalDecision, ApprovalRecord, AuditLog};
+use crate::error::SshLiaisonError;
+use crate::history::CommandHistory;
+use crate::inventory::Inventory;
+use crate::mcp::cache::CacheKey;
+use crate::mcp::output::{DEFAULT_PAGE_BYTES, OutputPage, OutputStore};
+use crate::mcp::queue::{QueueFull, QueueStatus};
+use crate::mcp::rate_limit::{CommandRateLimiter, RateLimited};
+use crate::mcp::resources::{
+    WatchMatches, redacted_health, redacted_report, schedule_uri, watch_uri,
+};
+use crate::policy::{CommandClass, CommandPolicy, PolicyViolation};
+use crate::redact::Redactor;
 use crate::ssh::SessionManager;
+use crate::ssh::ansi::AnsiMode;
+use crate::ssh::binary;
+use crate::ssh::channel::{ChannelTuning, CommandOutput, SudoError};
+use crate::ssh::config::{SshHostConfig, list_ssh_hosts};
+use crate::ssh::disk::{self, DiskUsage, DiskUsageQuery};
+use crate::ssh::docker;
+use crate::ssh::files::{
+    self, ChecksumAlgorithm, DirEntry, EntryKind, FileChecksum, MAX_READ_BYTES, ReadRange, Transfer,
+};
+use crate::ssh::health::{HealthMonitor, HealthStatus};
+use crate::ssh::interactive;
+use crate::ssh::journal::{
+    DEFAULT_JOURNAL_LINES, JournalEntry, JournalQuery, keep_newest, parse_json,
+};
+use crate::ssh::k8s;
+use crate::ssh::keys::{self, KeyType, PublicKey};
+use crate::ssh::known_hosts::{self, KeyStatus, KnownHostEntry};
+use crate::ssh::network::{self, ProbeMethod, ProbeResult};
+use crate::ssh::packages::{self, PackageManager, PackageStatus, PackageUpgrade};
+use crate::ssh::process::{
+    DEFAULT_PROCESS_LIMIT, MAX_PROCESS_LIMIT, PS_COMMAND, ProcessInfo, ProcessSort, parse_ps, top,
+};
+use crate::ssh::schedule::{
+    MAX_SCHEDULE_EVENTS, MIN_SCHEDULE_INTERVAL_SECS, ScheduleInfo, ScheduleSpec,
+};
+use crate::ssh::search::{
+    DEFAULT_FIND_LIMIT, DEFAULT_GREP_MATCHES, DEFAULT_SEARCH_TIMEOUT_SECS, FindQuery, FoundEntry,
+    GrepMatch, GrepQuery, parse_find, parse_grep,
+};
+use crate::ssh::session::SCRATCH_CHANNEL;
+use crate::ssh::shell::{changes_shell_state, needs_encoding, quote};
+use crate::ssh::socks::SocksOptions;
+use crate::ssh::transfer::{
+    self, Pacing, PathFilter, ProgressUpdate, RateLimiter, TransferOptions, TransferSummary,
+};
+use crate::ssh::tunnel::TunnelTarget;
+use crate::ssh::watch::{WatchInfo, tail_command};
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[schemars(description = "SSH connection parameters")]
 pub struct SshConnectParams {
     #[schemars(description = "Host alias from ~/.ssh/config (e.g., 'dev-1', 'prod-server')")]
     pub host_alias: String,
+    #[schemars(description = "Timeouts and buffer sizes for this host's shell channels")]
+    pub tuning: Option<ChannelTuning>,
+    #[schemars(
+        description = "Record this session's shells as asciicast files for later review (default: server setting)"
+    )]
+    pub record: Option<bool>,
+    #[schemars(
+        description = "Tags for the session, e.g. ['prod'], which can select stricter policy rules; hosts may also be tagged in the server config"
+    )]
+    pub tags: Option<Vec<String>>,
 }
 
-#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+/// Stores the connection's channel tuning and recording choice before it connects.
+fn apply_tuning(
+    session_manager: &SessionManager,
+    host_alias: &str,
+    tuning: Option<&ChannelTuning>,
+    record: Option<bool>,
+) {
+    if let Some(tuning) = tuning {
+        let options = tuning.apply(session_manager.channel_options_for(host_alias));
+        tracing::debug!(host = %host_alias, ?options, "Channel tuning");
+        session_manager.set_channel_options(host_alias, options);
+    }
+    if let Some(record) = record {
+        session_manager.set_recording(host_alias, record);
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
 #[schemars(description = "SSH command execution parameters")]
 pub struct SshRunCommandParams {
     #[schemars(description = "Host alias to execute command on (must be connected first)")]
@@ -27,8 +115,444 @@ pub struct SshRunCommandParams {
         description = "Optional sudo password when command requires it. Use with caution; prefer passwordless sudo."
     )]
     pub sudo_password: Option<String>,
+    #[schemars(
+        description = "Remember the sudo password for this session so later sudo commands don't prompt again (default: false)"
+    )]
+    pub cache_sudo_password: Option<bool>,
+    #[schemars(
+        description = "Data piped to the command's standard input, e.g. SQL for psql or file content for tee"
+    )]
+    pub stdin: Option<String>,
+    #[schemars(
+        description = "Send the command base64-encoded so quotes, newlines and comments can't break it. Enabled automatically for risky commands (default: auto)"
+    )]
+    pub base64: Option<bool>,
+    #[schemars(
+        description = "Run in this named channel instead of the default shell, e.g. 'build'. Opened on first use with its own working directory, so it doesn't wait for commands running elsewhere on the host"
+    )]
+    pub channel: Option<String>,
+    #[schemars(
+        description = "Mask secrets (keys, tokens, passwords) in the output (default: true). Set to false only when the raw value is needed"
+    )]
+    pub redact: Option<bool>,
+    #[schemars(description = "Byte offset into stdout to start the returned page at (default: 0)")]
+    pub offset: Option<usize>,
+    #[schemars(
+        description = "Maximum stdout bytes to return (default: 65536). Larger output can be paged with ssh_fetch_output."
+    )]
+    pub max_bytes: Option<usize>,
+    #[schemars(
+        description = "Run the command even if a cached result of it is available (default: false). Only read-only commands are cached, and only when the server has a cache TTL"
+    )]
+    pub no_cache: Option<bool>,
+    #[schemars(
+        description = "Don't run the command; return the exact line that would be sent, the target host, the policy decision and the redactions that would apply (default: false)"
+    )]
+    pub dry_run: Option<bool>,
+    #[schemars(
+        description = "Colors in the output: 'strip' removes them, 'keep' returns the raw ANSI codes, 'convert' turns bold, italic and basic colors into **bold**, *italic* and [red]...[/red] annotations, e.g. to show a colored diff (default: the server's, normally 'strip')"
+    )]
+    pub ansi: Option<AnsiMode>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Remote script execution parameters")]
+pub struct SshRunScriptParams {
+    #[schemars(description = "Host alias to run the script on (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Script body; may span lines and contain heredocs and quotes")]
+    pub script: String,
+    #[schemars(
+        description = "Command the script file is passed to, e.g. 'bash -e', 'python3', 'sudo sh' (default: bash)"
+    )]
+    pub interpreter: Option<String>,
+    #[schemars(
+        description = "Mask secrets (keys, tokens, passwords) in the output (default: true). Set to false only when the raw value is needed"
+    )]
+    pub redact: Option<bool>,
+    #[schemars(description = "Byte offset into stdout to start the returned page at (default: 0)")]
+    pub offset: Option<usize>,
+    #[schemars(
+        description = "Maximum stdout bytes to return (default: 65536). Larger output can be paged with ssh_fetch_output."
+    )]
+    pub max_bytes: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Remote file read parameters")]
+pub struct SshReadFileParams {
+    #[schemars(description = "Host alias (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Path of the remote file")]
+    pub path: String,
+    #[schemars(description = "Byte offset to start reading at (default: 0)")]
+    pub offset: Option<u64>,
+    #[schemars(description = "Maximum bytes to read (default and limit: 1048576)")]
+    pub length: Option<usize>,
+    #[schemars(description = "First line to return, 1-based (counted from offset)")]
+    pub start_line: Option<usize>,
+    #[schemars(description = "Last line to return, inclusive")]
+    pub end_line: Option<usize>,
+    #[schemars(
+        description = "Mask secrets (keys, tokens, passwords) in the output (default: true). Set to false only when the raw value is needed"
+    )]
+    pub redact: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Remote file write parameters")]
+pub struct SshWriteFileParams {
+    #[schemars(description = "Host alias (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Path of the remote file to create or replace")]
+    pub path: String,
+    #[schemars(description = "New file content")]
+    pub content: String,
+    #[schemars(description = "Content is base64-encoded binary data (default: false)")]
+    pub base64: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Host-to-host file copy parameters")]
+pub struct SshCopyBetweenHostsParams {
+    #[schemars(description = "Host alias to copy from (must be connected first)")]
+    pub source_host: String,
+    #[schemars(description = "Path of the file on source_host")]
+    pub source_path: String,
+    #[schemars(description = "Host alias to copy to (must be connected first)")]
+    pub target_host: String,
+    #[schemars(
+        description = "Path on target_host; an existing directory gets the source's file name"
+    )]
+    pub target_path: String,
+    #[schemars(
+        description = "Compare SHA-256 of both ends before replacing the target (default: true)"
+    )]
+    pub verify: Option<bool>,
+    #[schemars(description = "Cap this copy at this many bytes per second")]
+    pub max_bytes_per_sec: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "File or directory transfer parameters")]
+pub struct SshTransferParams {
+    #[schemars(description = "Host alias (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Absolute path on the machine running this server")]
+    pub local_path: String,
+    #[schemars(description = "Path on the remote host")]
+    pub remote_path: String,
+    #[schemars(
+        description = "Globs selecting files in a directory transfer, matched against the relative path or file name, e.g. ['*.conf', 'sites/**'] (default: all files)"
+    )]
+    #[serde(default)]
+    pub include: Vec<String>,
+    #[schemars(
+        description = "Globs for files and directories to leave out, e.g. ['*.log', '.git'] (applied after include)"
+    )]
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[schemars(
+        description = "Continue files whose target already holds a shorter partial copy, then verify SHA-256 on both ends (default: false)"
+    )]
+    pub resume: Option<bool>,
+    #[schemars(description = "Cap this transfer at this many bytes per second")]
+    pub max_bytes_per_sec: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Public key installation parameters")]
+pub struct SshInstallPublicKeyParams {
+    #[schemars(
+        description = "Host alias (must be connected first, e.g. with a password via ssh_connect_direct)"
+    )]
+    pub host: String,
+    #[schemars(
+        description = "Public key line as in a .pub file, e.g. 'ssh-ed25519 AAAA... user@laptop'"
+    )]
+    pub public_key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Keypair generation parameters")]
+pub struct SshGenerateKeypairParams {
+    #[serde(rename = "type")]
+    #[schemars(description = "Key type: 'ed25519' (default) or 'ecdsa' (P-256)")]
+    pub key_type: Option<KeyType>,
+    #[schemars(description = "Comment stored with the public key, e.g. 'deploy@ci'")]
+    pub comment: Option<String>,
+    #[schemars(
+        description = "Absolute path of the private key on the machine running this server (default: ~/.ssh/id_<type>); the public key goes next to it with .pub. Existing files are never overwritten"
+    )]
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "known_hosts listing parameters")]
+pub struct SshKnownHostsListParams {
+    #[schemars(
+        description = "Only entries that apply to this host alias or hostname, hashed and wildcard ones included (default: all entries)"
+    )]
+    pub host: Option<String>,
+    #[schemars(description = "Port, when not the one in ~/.ssh/config (default: 22)")]
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Host key scan parameters")]
+pub struct SshKeyscanParams {
+    #[schemars(description = "Host alias from ~/.ssh/config, or a hostname")]
+    pub host: String,
+    #[schemars(description = "Port, when not the one in ~/.ssh/config (default: 22)")]
+    pub port: Option<u16>,
+    #[schemars(description = "Connection timeout in seconds (default: 5, max: 30)")]
+    pub timeout_secs: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "known_hosts addition parameters")]
+pub struct SshKnownHostsAddParams {
+    #[schemars(description = "Host alias from ~/.ssh/config, or a hostname")]
+    pub host: String,
+    #[schemars(description = "Port, when not the one in ~/.ssh/config (default: 22)")]
+    pub port: Option<u16>,
+    #[schemars(
+        description = "Key lines to trust, e.g. 'ssh-ed25519 AAAA...' from ssh_keyscan. When omitted, every key the host presents now is scanned and added"
+    )]
+    pub public_keys: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "known_hosts removal parameters")]
+pub struct SshKnownHostsRemoveParams {
+    #[schemars(description = "Host alias from ~/.ssh/config, or a hostname")]
+    pub host: String,
+    #[schemars(description = "Port, when not the one in ~/.ssh/config (default: 22)")]
+    pub port: Option<u16>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Working directory parameters")]
+pub struct SshCwdParams {
+    #[schemars(description = "Host alias (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Named channel instead of the default shell")]
+    pub channel: Option<String>,
+    #[schemars(
+        description = "Directory to change to, absolute or relative to the current one. Omit to just get the current directory"
+    )]
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Environment variable parameters")]
+pub struct SshEnvParams {
+    #[schemars(description = "Host alias (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Named channel instead of the default shell")]
+    pub channel: Option<String>,
+    #[schemars(description = "Variables to export, e.g. {\"RAILS_ENV\": \"production\"}")]
+    pub set: Option<BTreeMap<String, String>>,
+    #[schemars(description = "Variables to unset")]
+    pub unset: Option<Vec<String>>,
+    #[schemars(
+        description = "Variables to read from the shell, e.g. [\"PATH\", \"KUBECONFIG\"]. Those set through this tool are always returned without asking the host"
+    )]
+    pub names: Option<Vec<String>>,
+    #[schemars(description = "Mask secrets in the values read (default: true)")]
+    pub redact: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct KnownHostsResult {
+    file: String,
+    entries: Vec<KnownHostEntry>,
+}
+
+#[derive(Serialize)]
+struct ScannedKey {
+    key_type: String,
+    fingerprint: String,
+    public_key: String,
+    /// Compared with the known_hosts entries for the host.
+    status: KeyStatus,
+}
+
+/// Result of `ssh_read_file`.
+#[derive(Debug, Serialize)]
+pub struct FileReadResult {
+    pub path: String,
+    pub content: String,
+    /// `utf-8`, or `base64` for binary data.
+    pub encoding: &'static str,
+    pub bytes: usize,
+    pub truncated: bool,
+    pub via: Transfer,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "File checksum parameters")]
+pub struct SshChecksumParams {
+    #[schemars(description = "Host alias (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Paths of the remote files")]
+    pub paths: Vec<String>,
+    #[schemars(description = "md5, sha1, sha256 or sha512 (default: sha256)")]
+    pub algorithm: Option<ChecksumAlgorithm>,
+}
+
+#[derive(Serialize)]
+struct ChecksumResult {
+    algorithm: ChecksumAlgorithm,
+    files: Vec<FileChecksum>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "File comparison parameters")]
+pub struct SshCompareFilesParams {
+    #[schemars(description = "Host alias of the first file (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Path of the first file")]
+    pub path: String,
+    #[schemars(
+        description = "Host alias of the second file (default: host). Not used with content."
+    )]
+    pub other_host: Option<String>,
+    #[schemars(description = "Path of the second file (default: path). Not used with content.")]
+    pub other_path: Option<String>,
+    #[schemars(description = "Compare against this text instead of a second remote file")]
+    pub content: Option<String>,
+    #[schemars(description = "Mask secrets (keys, tokens, passwords) in the diff (default: true)")]
+    pub redact: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct CompareResult {
+    left: String,
+    right: String,
+    identical: bool,
+    /// At least one side isn't UTF-8, so there is no diff.
+    binary: bool,
+    /// Unified diff from left to right, when the files differ.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff: Option<String>,
+    /// The diff was cut after 256 KiB.
+    truncated: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Remote directory listing parameters")]
+pub struct SshListDirParams {
+    #[schemars(description = "Host alias (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Path of the remote directory")]
+    pub path: String,
+}
+
+/// Result of `ssh_list_dir`.
+#[derive(Debug, Serialize)]
+pub struct DirListing {
+    pub path: String,
+    pub entries: Vec<DirEntry>,
+    pub via: Transfer,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Output paging parameters")]
+pub struct SshFetchOutputParams {
+    #[schemars(description = "Cursor returned as next_cursor by ssh_run_command")]
+    pub cursor: String,
+    #[schemars(
+        description = "Byte offset to read from (default: right after the last page returned)"
+    )]
+    pub offset: Option<usize>,
+    #[schemars(description = "Maximum bytes to return (default: 65536)")]
+    pub max_bytes: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Command cancellation parameters")]
+pub struct SshCancelCommandParams {
+    #[schemars(description = "ID of the running command, as listed by ssh_list_connections")]
+    pub id: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Shell reset parameters")]
+pub struct SshResetSessionParams {
+    #[schemars(description = "Host alias (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Named channel instead of the default shell")]
+    pub channel: Option<String>,
+    #[schemars(
+        description = "Replace the shell with a new one right away instead of trying to resynchronize it first (default: false)"
+    )]
+    pub reopen: Option<bool>,
 }
 
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Multi-host command execution parameters")]
+pub struct SshRunOnHostsParams {
+    #[schemars(
+        description = "Host aliases to run the command on; '@name' selects an inventory group. Aliases that aren't connected yet are connected via ~/.ssh/config."
+    )]
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    #[schemars(description = "Inventory groups to run the command on (see ssh_list_groups)")]
+    #[serde(default)]
+    pub groups: Vec<String>,
+    #[schemars(description = "Command to execute on every host")]
+    pub command: String,
+    #[schemars(description = "Maximum number of hosts to run on at once (default: 8)")]
+    pub max_concurrency: Option<usize>,
+    #[schemars(
+        description = "Mask secrets (keys, tokens, passwords) in the output (default: true). Set to false only when the raw value is needed"
+    )]
+    pub redact: Option<bool>,
+}
+
+/// Outcome of a fan-out command on one host.
+#[derive(Debug, Serialize)]
+pub struct HostCommandResult {
+    pub success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The session was opened for this call.
+    pub auto_connected: bool,
+}
+
+const DEFAULT_MAX_CONCURRENCY: usize = 8;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Sudo password request")]
+pub struct SudoPasswordPrompt {
+    #[schemars(description = "Sudo password for the remote user")]
+    pub password: String,
+    #[schemars(description = "Remember this password for the rest of the session")]
+    #[serde(default)]
+    pub remember: bool,
+}
+
+rmcp::elicit_safe!(SudoPasswordPrompt);
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Destructive command approval")]
+pub struct CommandApprovalPrompt {
+    #[schemars(description = "Run this command")]
+    #[serde(default)]
+    pub approve: bool,
+    #[schemars(description = "Your name (recorded in the audit log)")]
+    pub approver: String,
+    #[schemars(description = "Optional reason or ticket reference")]
+    pub comment: Option<String>,
+}
+
+rmcp::elicit_safe!(CommandApprovalPrompt);
+
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[schemars(description = "Direct SSH connection parameters")]
 pub struct SshConnectDirectParams {
@@ -46,6 +570,78 @@ pub struct SshConnectDirectParams {
     pub password: Option<String>,
     #[schemars(description = "SSH port (default: 22)")]
     pub port: Option<u16>,
+    #[schemars(
+        description = "Passphrase for an encrypted private key (optional, falls back to SSH_LIAISON_KEY_PASSPHRASE or the OS keyring)"
+    )]
+    pub key_passphrase: Option<String>,
+    #[schemars(description = "Timeouts and buffer sizes for this host's shell channels")]
+    pub tuning: Option<ChannelTuning>,
+    #[schemars(
+        description = "Record this session's shells as asciicast files for later review (default: server setting)"
+    )]
+    pub record: Option<bool>,
+    #[schemars(
+        description = "Tags for the session, e.g. ['prod'], which can select stricter policy rules; hosts may also be tagged in the server config"
+    )]
+    pub tags: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Saved session restore parameters")]
+pub struct SshRestoreSessionsParams {
+    #[schemars(description = "Aliases of the saved sessions to act on (default: all)")]
+    pub aliases: Option<Vec<String>>,
+    #[schemars(
+        description = "Remove the saved sessions instead of re-opening them, so later restores skip them (default: false)"
+    )]
+    pub forget: Option<bool>,
+}
+
+const DEFAULT_HISTORY_LIMIT: usize = 20;
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Command history query parameters")]
+pub struct SshHistoryParams {
+    #[schemars(description = "Only commands run on this host alias (default: all hosts)")]
+    pub host: Option<String>,
+    #[schemars(description = "Most entries to return, newest first (default: 20)")]
+    pub limit: Option<usize>,
+    #[schemars(description = "Only commands matching this regex, e.g. 'systemctl|journalctl'")]
+    pub pattern: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Command re-run parameters")]
+pub struct SshRerunParams {
+    #[schemars(
+        description = "History id of the command to run again (from ssh_history or a result's ssh-liaison/historyId)"
+    )]
+    pub id: u64,
+    #[schemars(
+        description = "Mask secrets (keys, tokens, passwords) in the output (default: true). Set to false only when the raw value is needed"
+    )]
+    pub redact: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct RerunResult {
+    /// History id of this run.
+    id: u64,
+    previous_id: u64,
+    host: String,
+    command: String,
+    exit_code: Option<i32>,
+    previous_exit_code: Option<i32>,
+    /// `None` when the previous output is no longer kept.
+    changed: Option<bool>,
+    /// Unified diff from the previous output to this one, when they differ.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    diff: Option<String>,
+    /// The diff was cut after 256 KiB.
+    truncated: bool,
+    /// This run's output, when there is nothing to diff it against.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
@@ -57,46 +653,647 @@ pub struct SshReadLogParams {
     pub file_path: String,
     #[schemars(description = "Number of lines to read from log file")]
     pub lines: i32,
+    #[schemars(
+        description = "Named channel to read in (default: the default shell, or a scratch channel while it is busy)"
+    )]
+    pub channel: Option<String>,
+    #[schemars(
+        description = "Mask secrets (keys, tokens, passwords) in the output (default: true). Set to false only when the raw value is needed"
+    )]
+    pub redact: Option<bool>,
 }
 
-pub async fn ssh_connect_impl(
-    session_manager: &SessionManager,
-    params: Parameters<SshConnectParams>,
-) -> Result<CallToolResult, McpError> {
-    let host_alias = &params.0.host_alias;
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Journal reading parameters")]
+pub struct SshJournalctlParams {
+    #[schemars(description = "Host alias to read the journal on (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Only entries of this systemd unit, e.g. 'nginx.service'")]
+    pub unit: Option<String>,
+    #[schemars(
+        description = "Only entries at this priority or more severe: 0-7 or emerg/alert/crit/err/warning/notice/info/debug, or a range like 'warning..err'"
+    )]
+    pub priority: Option<String>,
+    #[schemars(
+        description = "Start of the time range, in any form journalctl accepts: '-1h', 'today', '2024-05-01 10:00:00'"
+    )]
+    pub since: Option<String>,
+    #[schemars(description = "End of the time range, same forms as since")]
+    pub until: Option<String>,
+    #[schemars(
+        description = "Regular expression the message must match (journalctl --grep; needs journalctl built with PCRE2)"
+    )]
+    pub grep: Option<String>,
+    #[schemars(description = "Number of most recent entries to return (default: 100, max: 1000)")]
+    pub lines: Option<u32>,
+    #[schemars(
+        description = "Return entries as structured JSON (timestamp, unit, identifier, pid, priority, message) instead of plain lines (default: false)"
+    )]
+    pub structured: Option<bool>,
+    #[schemars(
+        description = "Maximum bytes of messages returned (default: 65536); older entries are dropped first"
+    )]
+    pub max_bytes: Option<usize>,
+    #[schemars(
+        description = "Mask secrets (keys, tokens, passwords) in the output (default: true). Set to false only when the raw value is needed"
+    )]
+    pub redact: Option<bool>,
+}
 
-    match session_manager.connect_by_alias(host_alias).await {
-        Ok(()) => Ok(CallToolResult::success(vec![Content::text(format!(
-            "Successfully connected to '{}'",
-            host_alias
-        ))])),
-        Err(e) => {
-            let msg = Box::leak(e.to_string().into_boxed_str());
-            Err(McpError::invalid_params(&*msg, None))
-        }
-    }
+#[derive(Serialize)]
+struct JournalResult {
+    entries: Vec<JournalEntry>,
+    /// Older entries left out to stay under `max_bytes`.
+    dropped: usize,
 }
 
-pub async fn ssh_connect_direct_impl(
-    session_manager: &SessionManager,
-    params: Parameters<SshConnectDirectParams>,
-) -> Result<CallToolResult, McpError> {
-    let p = &params.0;
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Process listing parameters")]
+pub struct SshProcessListParams {
+    #[schemars(description = "Host alias to list processes on (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Order by 'cpu' (default) or 'memory' (resident set size)")]
+    pub sort_by: Option<ProcessSort>,
+    #[schemars(
+        description = "Only processes whose command line contains this text (case-insensitive)"
+    )]
+    pub name: Option<String>,
+    #[schemars(description = "Number of processes to return (default: 15, max: 200)")]
+    pub limit: Option<usize>,
+    #[schemars(
+        description = "Mask secrets (keys, tokens, passwords) in command lines (default: true)"
+    )]
+    pub redact: Option<bool>,
+}
 
-    match session_manager
-        .connect_direct(&p.host_alias, &p.user, &p.hostname, p.port)
-        .await
-    {
-        Ok(()) => {
-            return Ok(CallToolResult::success(vec![Content::text(format!(
-                "Successfully connected to {}@{} using SSH keys",
-                p.user, p.hostname
-            ))]));
-        }
-        Err(e) => {
-            tracing::debug!(error = %e, "SSH key authentication failed, trying password");
-        }
+#[derive(Serialize)]
+struct ProcessListResult {
+    processes: Vec<ProcessInfo>,
+    /// Processes that passed the name filter.
+    matched: usize,
+    total: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Docker container listing parameters")]
+pub struct SshDockerPsParams {
+    #[schemars(description = "Host alias running Docker (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Include stopped containers (default: false)")]
+    pub all: Option<bool>,
+    #[schemars(
+        description = "Run docker through 'sudo -n' when the user isn't in the docker group (default: false)"
+    )]
+    pub sudo: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Docker container log parameters")]
+pub struct SshDockerLogsParams {
+    #[schemars(description = "Host alias running Docker (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Container name or ID")]
+    pub container: String,
+    #[schemars(description = "Number of most recent log lines (default: 100, max: 5000)")]
+    pub lines: Option<u32>,
+    #[schemars(
+        description = "Only lines newer than this: a duration like '10m' or a timestamp like '2024-05-01T10:00:00'"
+    )]
+    pub since: Option<String>,
+    #[schemars(description = "Prefix each line with its timestamp (default: false)")]
+    pub timestamps: Option<bool>,
+    #[schemars(
+        description = "Maximum bytes returned (default: 65536); older lines are dropped first"
+    )]
+    pub max_bytes: Option<usize>,
+    #[schemars(description = "Run docker through 'sudo -n' (default: false)")]
+    pub sudo: Option<bool>,
+    #[schemars(
+        description = "Mask secrets (keys, tokens, passwords) in the output (default: true)"
+    )]
+    pub redact: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Docker container inspection parameters")]
+pub struct SshDockerInspectParams {
+    #[schemars(description = "Host alias running Docker (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Container name or ID")]
+    pub container: String,
+    #[schemars(description = "Run docker through 'sudo -n' (default: false)")]
+    pub sudo: Option<bool>,
+    #[schemars(description = "Mask secrets, e.g. in environment variables (default: true)")]
+    pub redact: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Docker container statistics parameters")]
+pub struct SshDockerStatsParams {
+    #[schemars(description = "Host alias running Docker (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Container names or IDs (default: every running container)")]
+    #[serde(default)]
+    pub containers: Vec<String>,
+    #[schemars(description = "Run docker through 'sudo -n' (default: false)")]
+    pub sudo: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct DockerLogsResult {
+    container: String,
+    lines: Vec<String>,
+    /// Older lines left out to stay under `max_bytes`.
+    dropped: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Kubernetes node triage parameters")]
+pub struct SshK8sNodeTriageParams {
+    #[schemars(description = "Host alias of the Kubernetes node (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "How far back to look for kubelet errors, in minutes (default: 60)")]
+    pub since_minutes: Option<u32>,
+    #[schemars(description = "Most recent kubelet errors to return (default: 20, max: 200)")]
+    pub max_errors: Option<usize>,
+    #[schemars(
+        description = "Mask secrets (keys, tokens, passwords) in the errors (default: true)"
+    )]
+    pub redact: Option<bool>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "File search parameters")]
+pub struct SshFindFilesParams {
+    #[schemars(description = "Host alias to search on (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Directory to search from")]
+    pub path: String,
+    #[schemars(description = "Glob matched against file names, e.g. '*.log'")]
+    pub name: Option<String>,
+    #[schemars(description = "Match name ignoring case (default: false)")]
+    pub ignore_case: Option<bool>,
+    #[schemars(description = "Only entries of this kind: file, directory or symlink")]
+    #[serde(rename = "type")]
+    pub kind: Option<EntryKind>,
+    #[schemars(description = "Maximum directory depth below path")]
+    pub max_depth: Option<u32>,
+    #[schemars(description = "Only entries modified less than this many minutes ago")]
+    pub newer_than_minutes: Option<u32>,
+    #[schemars(description = "Only entries modified more than this many minutes ago")]
+    pub older_than_minutes: Option<u32>,
+    #[schemars(description = "Only files larger than this many KiB")]
+    pub min_size_kb: Option<u64>,
+    #[schemars(
+        description = "Stay on path's filesystem, skipping /proc, /sys and other mounts (default: true)"
+    )]
+    pub same_filesystem: Option<bool>,
+    #[schemars(description = "Maximum entries returned (default: 200, max: 5000)")]
+    pub limit: Option<usize>,
+    #[schemars(description = "Stop searching after this many seconds (default: 10, max: 25)")]
+    pub timeout_secs: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct FindResult {
+    entries: Vec<FoundEntry>,
+    /// More entries matched than `limit`.
+    truncated: bool,
+    /// The search hit `timeout_secs`, so entries may be missing.
+    timed_out: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Content search parameters")]
+pub struct SshGrepParams {
+    #[schemars(description = "Host alias to search on (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Extended regular expression (or literal text with fixed_strings)")]
+    pub pattern: String,
+    #[schemars(description = "Files or directories to search")]
+    pub paths: Vec<String>,
+    #[schemars(description = "Search directories recursively (default: false)")]
+    pub recursive: Option<bool>,
+    #[schemars(description = "Match ignoring case (default: false)")]
+    pub ignore_case: Option<bool>,
+    #[schemars(description = "Treat pattern as literal text, not a regex (default: false)")]
+    pub fixed_strings: Option<bool>,
+    #[schemars(description = "Context lines before each match (default: 0, max: 10)")]
+    pub before: Option<u32>,
+    #[schemars(description = "Context lines after each match (default: 0, max: 10)")]
+    pub after: Option<u32>,
+    #[schemars(description = "Maximum matches returned (default: 100, max: 1000)")]
+    pub max_matches: Option<usize>,
+    #[schemars(description = "Skip binary files (default: true)")]
+    pub skip_binary: Option<bool>,
+    #[schemars(description = "Stop searching after this many seconds (default: 10, max: 25)")]
+    pub timeout_secs: Option<u32>,
+    #[schemars(
+        description = "Mask secrets (keys, tokens, passwords) in matched lines (default: true)"
+    )]
+    pub redact: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct GrepResult {
+    matches: Vec<GrepMatch>,
+    /// More lines matched than `max_matches`.
+    truncated: bool,
+    /// The search hit `timeout_secs`, so matches may be missing.
+    timed_out: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Disk usage parameters")]
+pub struct SshDiskUsageParams {
+    #[schemars(description = "Host alias to inspect (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Directory whose largest subdirectories to list (default: /)")]
+    pub path: Option<String>,
+    #[schemars(description = "Directory depth below path to size (default: 2, max: 6)")]
+    pub depth: Option<u32>,
+    #[schemars(description = "Number of largest directories returned (default: 10, max: 100)")]
+    pub top: Option<usize>,
+    #[schemars(
+        description = "Stop sizing directories after this many seconds (default: 10, max: 25)"
+    )]
+    pub timeout_secs: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct DiskUsageResult {
+    #[serde(flatten)]
+    usage: DiskUsage,
+    /// `du` hit `timeout_secs`, so directory sizes are partial.
+    timed_out: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Network information parameters")]
+pub struct SshNetworkInfoParams {
+    #[schemars(description = "Host alias to inspect (must be connected first)")]
+    pub host: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Port check parameters")]
+pub struct SshPortCheckParams {
+    #[schemars(description = "Host alias to test from (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Host name or IP address to connect to, as seen from host")]
+    pub target_host: String,
+    #[schemars(description = "TCP port to connect to")]
+    pub port: u16,
+    #[schemars(description = "Give up after this many seconds (default: 5, max: 25)")]
+    pub timeout_secs: Option<u32>,
+    #[schemars(
+        description = "Probe with 'nc', 'bash' (/dev/tcp) or 'auto': nc, then bash if nc is missing (default: auto)"
+    )]
+    pub method: Option<ProbeMethod>,
+}
+
+#[derive(Serialize)]
+struct PortCheckResult<'a> {
+    target_host: &'a str,
+    port: u16,
+    method: ProbeMethod,
+    #[serde(flatten)]
+    result: ProbeResult,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Package query parameters")]
+pub struct SshPackageQueryParams {
+    #[schemars(description = "Host alias to query (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Package names to look up, e.g. ['nginx', 'openssl']")]
+    #[serde(default)]
+    pub packages: Vec<String>,
+    #[schemars(
+        description = "Also list upgrades known to the package database, without refreshing it (default: false)"
+    )]
+    pub upgrades: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct PackageQueryResult {
+    manager: PackageManager,
+    packages: Vec<PackageStatus>,
+    /// Only when upgrades were asked for and the host can list them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upgrades: Option<Vec<PackageUpgrade>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Local port forwarding parameters")]
+pub struct SshTunnelOpenParams {
+    #[schemars(
+        description = "Host alias whose session carries the tunnel (must be connected first)"
+    )]
+    pub host: String,
+    #[schemars(
+        description = "Local port to listen on (127.0.0.1 only). Omit or use 0 to pick a free port."
+    )]
+    pub local_port: Option<u16>,
+    #[schemars(
+        description = "Destination host as seen from the SSH server (e.g., 'localhost', 'db.internal')"
+    )]
+    pub remote_host: String,
+    #[schemars(description = "Destination port on the remote side")]
+    pub remote_port: u16,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Dynamic SOCKS5 forwarding parameters")]
+pub struct SshSocksProxyParams {
+    #[schemars(
+        description = "Host alias whose session carries the proxy (must be connected first)"
+    )]
+    pub host: String,
+    #[schemars(
+        description = "Local port for the SOCKS5 listener (127.0.0.1 only). Omit or use 0 to pick a free port."
+    )]
+    pub local_port: Option<u16>,
+    #[schemars(description = "Maximum concurrent proxied connections (default: 64)")]
+    pub max_connections: Option<usize>,
+    #[schemars(
+        description = "Close proxied connections idle for this many seconds (default: 300)"
+    )]
+    pub idle_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Tunnel close parameters")]
+pub struct SshTunnelCloseParams {
+    #[schemars(description = "Tunnel ID returned by ssh_tunnel_open or ssh_tunnel_list")]
+    pub tunnel_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Log watch parameters")]
+pub struct SshWatchLogParams {
+    #[schemars(description = "Host alias to watch on (must be connected first)")]
+    pub host: String,
+    #[schemars(description = "Path to the log file on the remote host")]
+    pub path: String,
+    #[schemars(
+        description = "Regular expression new lines must match to be reported (e.g. 'ERROR|FATAL')"
+    )]
+    pub pattern: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Log watch stop parameters")]
+pub struct SshWatchStopParams {
+    #[schemars(description = "Watch ID returned by ssh_watch_log")]
+    pub watch_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Scheduled command parameters")]
+pub struct SshScheduleParams {
+    #[schemars(description = "Host alias to run the command on (must be connected first)")]
+    pub host: String,
+    #[schemars(
+        description = "Read-only command to run, e.g. 'df -h /' or 'grep -c ERROR /var/log/app.log'"
+    )]
+    pub command: String,
+    #[schemars(description = "Seconds between runs (at least 10); the first run is right away")]
+    pub interval_secs: u64,
+    #[schemars(
+        description = "Report a run whose output differs from the previous run's (default: true)"
+    )]
+    pub notify_on_change: Option<bool>,
+    #[schemars(
+        description = "Regular expression; report a run when the output lines matching it change (e.g. 'ERROR|FATAL')"
+    )]
+    pub pattern: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Scheduled command cancel parameters")]
+pub struct SshScheduleCancelParams {
+    #[schemars(description = "Schedule ID returned by ssh_schedule")]
+    pub schedule_id: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(description = "Health report parameters")]
+pub struct SshHealthReportParams {
+    #[schemars(description = "Only this host's probes (default: every probed host)")]
+    pub host: Option<String>,
+    #[schemars(description = "Only hosts with a probe that is failing or could not run")]
+    pub failing_only: Option<bool>,
+}
+
+pub fn policy_violation_error(violation: &PolicyViolation) -> McpError {
+    McpError::new(
+        ErrorCode::INVALID_REQUEST,
+        violation.to_string(),
+        Some(serde_json::json!({
+            "type": "policy_violation",
+            "rule": violation.rule,
+            "reason": violation.reason,
+            "command": violation.command,
+        })),
+    )
+}
+
+pub const CONNECT_FAILED: ErrorCode = ErrorCode(-32001);
+pub const AUTH_FAILED: ErrorCode = ErrorCode(-32002);
+pub const CHANNEL_ERROR: ErrorCode = ErrorCode(-32003);
+pub const COMMAND_TIMEOUT: ErrorCode = ErrorCode(-32004);
+pub const CHECKSUM_MISMATCH: ErrorCode = ErrorCode(-32005);
+pub const RATE_LIMITED: ErrorCode = ErrorCode(-32006);
+pub const QUEUE_FULL: ErrorCode = ErrorCode(-32007);
+pub const COMMAND_CANCELLED: ErrorCode = ErrorCode(-32008);
+pub const INTERACTIVE_COMMAND: ErrorCode = ErrorCode(-32009);
+
+pub fn queue_full_error(full: &QueueFull) -> McpError {
+    McpError::new(
+        QUEUE_FULL,
+        full.to_string(),
+        Some(serde_json::json!({
+            "type": "queue_full",
+            "host": full.host,
+            "channel": full.channel,
+            "depth": full.depth,
+        })),
+    )
+}
+
+pub fn rate_limited_error(limited: &RateLimited) -> McpError {
+    McpError::new(
+        RATE_LIMITED,
+        limited.to_string(),
+        Some(serde_json::json!({
+            "type": "rate_limited",
+            "host": limited.host,
+            "limit_per_minute": limited.limit,
+            "retry_after_secs": limited.retry_after_secs(),
+        })),
+    )
+}
+
+/// Counts a command on each of `hosts`, or fails with `rate_limited`. Hosts
+/// count by the machine they resolve to, so a new alias for the same box
+/// doesn't start a fresh count.
+pub async fn check_rate_limit(
+    rate_limiter: &CommandRateLimiter,
+    session_manager: &SessionManager,
+    hosts: &[&str],
+) -> Result<(), McpError> {
+    if !rate_limiter.is_enabled() {
+        return Ok(());
+    }
+    let mut resolved = Vec::with_capacity(hosts.len());
+    for host in hosts {
+        resolved.push(session_manager.resolved_host(host).await);
+    }
+    let resolved: Vec<&str> = resolved.iter().map(String::as_str).collect();
+    rate_limiter.acquire(&resolved).map_err(|limited| {
+        tracing::warn!(host = ?limited.host, limit = limited.limit, "Command rate limited");
+        rate_limited_error(&limited)
+    })
+}
+
+/// Maps an SSH-layer failure to an MCP error, with a distinct code per
+/// [`SshLiaisonError`] kind. Untyped errors stay `INVALID_PARAMS`.
+pub fn ssh_error(err: &anyhow::Error) -> McpError {
+    let message = format!("{:#}", err);
+    let Some(typed) = SshLiaisonError::find(err) else {
+        return McpError::invalid_params(message, None);
+    };
+    let code = match typed {
+        SshLiaisonError::Config(_)
+        | SshLiaisonError::HostNotFound(_)
+        | SshLiaisonError::NotConnected(_) => ErrorCode::INVALID_PARAMS,
+        SshLiaisonError::HostNotAllowed(_) | SshLiaisonError::SessionLimit(_) => {
+            ErrorCode::INVALID_REQUEST
+        }
+        SshLiaisonError::Connect { .. } | SshLiaisonError::ConnectionLost { .. } => CONNECT_FAILED,
+        SshLiaisonError::Auth(_) => AUTH_FAILED,
+        SshLiaisonError::Channel(_) => CHANNEL_ERROR,
+        SshLiaisonError::Timeout { .. } => COMMAND_TIMEOUT,
+        SshLiaisonError::Interactive(_) => INTERACTIVE_COMMAND,
+        SshLiaisonError::Cancelled(_) => COMMAND_CANCELLED,
+        SshLiaisonError::ChecksumMismatch { .. } => CHECKSUM_MISMATCH,
+    };
+    let data = match typed {
+        SshLiaisonError::ChecksumMismatch {
+            path,
+            expected,
+            actual,
+        } => serde_json::json!({
+            "type": typed.kind(),
+            "path": path,
+            "expected": expected,
+            "actual": actual,
+        }),
+        SshLiaisonError::Interactive(interactive) => serde_json::json!({
+            "type": typed.kind(),
+            "program": interactive.program,
+            "suggestion": interactive.suggestion,
+        }),
+        _ => serde_json::json!({ "type": typed.kind() }),
+    };
+    McpError::new(code, message, Some(data))
+}
+
+fn approval_denied_error(violation: &PolicyViolation, reason: &str) -> McpError {
+    McpError::new(
+        ErrorCode::INVALID_REQUEST,
+        format!("Command not approved ({}): {}", reason, violation.command),
+        Some(serde_json::json!({
+            "type": "approval_denied",
+            "rule": violation.rule,
+            "reason": reason,
+            "command": violation.command,
+        })),
+    )
+}
+
+pub async fn ssh_connect_impl(
+    session_manager: &SessionManager,
+    params: Parameters<SshConnectParams>,
+) -> Result<CallToolResult, McpError> {
+    let host_alias = &params.0.host_alias;
+    let tags = params.0.tags.as_deref().unwrap_or_default();
+    SessionManager::check_tags(tags).map_err(|e| ssh_error(&e))?;
+    apply_tuning(
+        session_manager,
+        host_alias,
+        params.0.tuning.as_ref(),
+        params.0.record,
+    );
+
+    match session_manager.connect_by_alias(host_alias).await {
+        Ok(()) => {
+            tag_session(session_manager, host_alias, tags).await?;
+            Ok(CallToolResult::success(vec![Content::text(format!(
+                "Successfully connected to '{}'",
+                host_alias
+            ))]))
+        }
+        Err(e) => Err(ssh_error(&e)),
+    }
+}
+
+async fn tag_session(
+    session_manager: &SessionManager,
+    host_alias: &str,
+    tags: &[String],
+) -> Result<(), McpError> {
+    if tags.is_empty() {
+        return Ok(());
     }
+    session_manager
+        .add_tags(host_alias, tags)
+        .await
+        .map_err(|e| ssh_error(&e))
+}
+
+/// The policy with the rules of the host's tags applied.
+async fn session_policy<'a>(
+    policy: &'a CommandPolicy,
+    session_manager: &SessionManager,
+    host: &str,
+) -> Cow<'a, CommandPolicy> {
+    let tags = session_manager.tags(host).await;
+    policy.for_tags(&tags)
+}
+
+pub async fn ssh_connect_direct_impl(
+    session_manager: &SessionManager,
+    params: Parameters<SshConnectDirectParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let tags = p.tags.as_deref().unwrap_or_default();
+    SessionManager::check_tags(tags).map_err(|e| ssh_error(&e))?;
+    apply_tuning(session_manager, &p.host_alias, p.tuning.as_ref(), p.record);
+
+    let key_error = match session_manager
+        .connect_direct(
+            &p.host_alias,
+            &p.user,
+            &p.hostname,
+            p.port,
+            p.key_passphrase.as_deref(),
+        )
+        .await
+    {
+        Ok(()) => {
+            tag_session(session_manager, &p.host_alias, tags).await?;
+            return Ok(CallToolResult::success(vec![Content::text(format!(
+                "Successfully connected to {}@{} using SSH keys",
+                p.user, p.hostname
+            ))]));
+        }
+        Err(e) => {
+            tracing::debug!(error = %e, "SSH key authentication failed, trying password");
+            e
+        }
+    };
 
     if let Some(ref password) = p.password
         && !password.is_empty()
@@ -106,71 +1303,3115 @@ pub async fn ssh_connect_direct_impl(
             .await
         {
             Ok(()) => {
+                tag_session(session_manager, &p.host_alias, tags).await?;
                 return Ok(CallToolResult::success(vec![Content::text(format!(
                     "Successfully connected to {}@{} using password",
                     p.user, p.hostname
                 ))]));
             }
             Err(e) => {
-                let msg = Box::leak(
-                    format!(
-                        "Authentication failed: SSH keys and password both failed. Last error: {}",
-                        e
-                    )
-                    .into_boxed_str(),
-                );
-                return Err(McpError::invalid_params(&*msg, None));
+                return Err(ssh_error(&e.context(
+                    "Authentication failed: SSH keys and password both failed",
+                )));
             }
         }
     }
 
-    let msg = Box::leak(
-        "SSH key authentication failed and no password provided"
-            .to_string()
-            .into_boxed_str(),
-    );
-    Err(McpError::invalid_params(&*msg, None))
+    Err(ssh_error(&key_error.context(
+        "SSH key authentication failed and no password provided",
+    )))
 }
 
 pub async fn ssh_run_command_impl(
     session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    audit: &AuditLog,
+    redactor: &Redactor,
+    outputs: &OutputStore,
+    peer: Peer<RoleServer>,
     params: Parameters<SshRunCommandParams>,
 ) -> Result<CallToolResult, McpError> {
+    if params.0.dry_run.unwrap_or(false) {
+        return dry_run(session_manager, policy, redactor, &params.0).await;
+    }
     let host = &params.0.host;
-    let command = &params.0.command;
+    // Connected first, so tags matched on the hostname apply.
+    let auto_connected = session_manager
+        .auto_connect(host)
+        .await
+        .map_err(|e| ssh_error(&e))?;
+    let policy = &*session_policy(policy, session_manager, host).await;
+    let class = authorize(policy, audit, &peer, host, &params.0.command).await?;
+
+    let command = &if params.0.base64.unwrap_or(false) {
+        session_manager
+            .shell_kind(host)
+            .await
+            .map_err(|e| ssh_error(&e))?
+            .encode(&params.0.command)
+            .ok_or_else(|| {
+                McpError::invalid_params("base64 is not supported by the host's cmd shell", None)
+            })?
+    } else {
+        params.0.command.clone()
+    };
+
     let sudo_password = params.0.sudo_password.as_deref();
+    let mut remember = params.0.cache_sudo_password.unwrap_or(false);
+    let stdin = params.0.stdin.as_deref().map(str::as_bytes);
+    let channel = params.0.channel.as_deref();
 
-    match session_manager
-        .execute_command(host, command, sudo_password)
-        .await
+    let namespace = session_manager.namespace();
+    let logged_command = redactor.redact(&params.0.command);
+    let cache = outputs.cache();
+    if class != CommandClass::ReadOnly {
+        cache.invalidate(namespace, host);
+    }
+    // Cached results are redacted and plain, and a command that changes the shell must run.
+    let cache_key = if cache.is_enabled()
+        && class == CommandClass::ReadOnly
+        && !params.0.no_cache.unwrap_or(false)
+        && params.0.redact.unwrap_or(true)
+        && params.0.ansi.unwrap_or_default() == AnsiMode::Strip
+        && stdin.is_none()
+        && sudo_password.is_none()
+        && !changes_shell_state(&params.0.command)
     {
-        Ok(output) => Ok(CallToolResult::success(vec![Content::text(
-            output.combined_with_stderr_label(),
-        )])),
+        let context = session_manager.shell_context(host, channel).await.ok();
+        context.and_then(|context| {
+            Some(CacheKey {
+                owner: namespace.map(str::to_string),
+                host: host.clone(),
+                channel: channel.map(str::to_string),
+                command: params.0.command.clone(),
+                cwd: context.cwd?,
+                env: context.env,
+            })
+        })
+    } else {
+        None
+    };
+    if let Some(ref key) = cache_key
+        && let Some((output, age)) = cache.get(key)
+    {
+        tracing::debug!(host = %host, command = %params.0.command, age_ms = age.as_millis() as u64, "Serving cached result");
+        let page = outputs.first_page(
+            namespace,
+            output,
+            params.0.offset.unwrap_or(0),
+            params.0.max_bytes.unwrap_or(DEFAULT_PAGE_BYTES),
+        );
+        return page_result(&page).map(|result| {
+            let result = with_cwd(with_command_class(result, class), Some(key.cwd.clone()));
+            with_cache_hit(with_auto_connected(result, auto_connected), Some(age))
+        });
+    }
+    let execute = async |password: Option<&str>| match stdin {
+        Some(data) => {
+            session_manager
+                .execute_command_with_stdin(host, channel, command, data, password)
+                .await
+        }
+        None => {
+            session_manager
+                .execute_command_in(host, channel, command, password)
+                .await
+        }
+    };
+
+    let mut result = execute(sudo_password).await;
+    let mut used_password = sudo_password.map(str::to_string);
+
+    let needs_password = match result
+        .as_ref()
+        .err()
+        .and_then(|e| e.downcast_ref::<SudoError>())
+    {
+        Some(SudoError::PasswordRequired) => true,
+        Some(SudoError::PasswordRejected) => sudo_password.is_none(),
+        None => false,
+    };
+
+    if needs_password {
+        let prompt = elicit_sudo_password(&peer, host, &params.0.command).await?;
+        remember |= prompt.remember;
+        result = execute(Some(&prompt.password)).await;
+        used_password = Some(prompt.password);
+    }
+
+    match result {
+        Ok(mut output) => {
+            let binary = output.binary.take();
+            if remember && let Some(ref password) = used_password {
+                session_manager
+                    .cache_sudo_password(host, password)
+                    .await
+                    .map_err(|e| McpError::internal_error(e.to_string(), None))?;
+            }
+            // A command that timed out may still be running; don't queue a `pwd` behind it.
+            let cwd = if output.exit_code.is_some() {
+                session_manager.current_dir(host, channel).await
+            } else {
+                session_manager
+                    .shell_context(host, channel)
+                    .await
+                    .ok()
+                    .and_then(|c| c.cwd)
+            };
+            let output = params.0.ansi.unwrap_or_default().apply(output);
+            let output = redacted(redactor, params.0.redact, output);
+            outputs.record(namespace, host, &logged_command, &output);
+            let history_id =
+                audit
+                    .history()
+                    .record(namespace, host, channel, &params.0.command, Ok(&output));
+            if let Some(ref key) = cache_key
+                && used_password.is_none()
+                && output.exit_code == Some(0)
+                && !output.truncated
+                && binary.is_none()
+            {
+                cache.insert(key.clone(), output.clone());
+            }
+            let page = outputs.first_page(
+                namespace,
+                output,
+                params.0.offset.unwrap_or(0),
+                params.0.max_bytes.unwrap_or(DEFAULT_PAGE_BYTES),
+            );
+            let result = page_result(&page).map(|result| {
+                let result = with_cwd(with_command_class(result, class), cwd);
+                let result = with_auto_connected(result, auto_connected);
+                let result = with_history_id(result, history_id);
+                match cache_key {
+                    Some(_) => with_cache_hit(result, None),
+                    None => result,
+                }
+            })?;
+            match binary {
+                Some(data) => with_binary(result, host, &data),
+                None => Ok(result),
+            }
+        }
         Err(e) => {
-            let msg = Box::leak(e.to_string().into_boxed_str());
-            Err(McpError::invalid_params(&*msg, None))
+            audit.history().record(
+                namespace,
+                host,
+                channel,
+                &params.0.command,
+                Err(&format!("{:#}", e)),
+            );
+            Err(ssh_error(&e))
         }
     }
 }
 
-pub async fn ssh_read_log_impl(
+/// What `ssh_run_command` would do with `p`. Nothing is connected, approved,
+/// run or recorded.
+async fn dry_run(
     session_manager: &SessionManager,
-    params: Parameters<SshReadLogParams>,
+    policy: &CommandPolicy,
+    redactor: &Redactor,
+    p: &SshRunCommandParams,
 ) -> Result<CallToolResult, McpError> {
-    let host = &params.0.host;
-    let file_path = &params.0.file_path;
-    let lines = params.0.lines;
+    let host = &p.host;
+    let connection = session_manager
+        .list_connections()
+        .await
+        .into_iter()
+        .find(|c| c.is_own && c.alias == *host);
+    let shell = connection.as_ref().map(|c| c.shell).unwrap_or_default();
+    let tags = session_manager.tags(host).await;
+    let policy = &*session_policy(policy, session_manager, host).await;
+    let (decision, class, violation) = match policy.check(&p.command) {
+        Ok(class) => ("allowed", class, None),
+        Err(violation) if violation.approvable => (
+            "approval_required",
+            CommandClass::Destructive,
+            Some(violation),
+        ),
+        Err(violation) => ("denied", policy.classify(&p.command), Some(violation)),
+    };
 
-    let command = format!("tail -n {} {}", lines, file_path);
+    // The same steps as a real run: explicit encoding, the stdin redirect,
+    // then the encoding the session applies to lines that could break.
+    let mut command = p.command.clone();
+    if p.base64.unwrap_or(false) {
+        command = shell.encode(&command).ok_or_else(|| {
+            McpError::invalid_params("base64 is not supported by the host's cmd shell", None)
+        })?;
+    }
+    let stdin_file = p.stdin.as_ref().map(|_| files::upload_path("stdin"));
+    if let Some(ref path) = stdin_file {
+        command = shell.with_stdin_from(&command, path);
+    }
+    let interactive = (!shell.is_windows())
+        .then(|| interactive::detect(&command))
+        .flatten();
+    let encoded = needs_encoding(&command)
+        .then(|| shell.encode(&command))
+        .flatten();
+    let base64 = p.base64.unwrap_or(false) || encoded.is_some();
+    let command = encoded.unwrap_or(command);
 
-    match session_manager.execute_command(host, &command, None).await {
-        Ok(output) => Ok(CallToolResult::success(vec![Content::text(
-            output.combined_with_stderr_label(),
-        )])),
+    let redact_output = p.redact.unwrap_or(true) && redactor.is_enabled();
+    let command_rules = redactor.matching_rules(&p.command);
+    let value = serde_json::json!({
+        "dry_run": true,
+        "host": host,
+        "connected": connection.is_some(),
+        "target": connection.as_ref().map(|c| serde_json::json!({
+            "user": c.user,
+            "hostname": c.hostname,
+            "port": c.port,
+            "shell": c.shell,
+        })),
+        "channel": p.channel,
+        "command": command,
+        "base64": base64,
+        "stdin_file": stdin_file,
+        "interactive": interactive.as_ref().map(|i| serde_json::json!({
+            "program": i.program,
+            "suggestion": i.suggestion,
+        })),
+        "policy": {
+            "decision": decision,
+            "class": class.to_string(),
+            "rule": violation.as_ref().map(|v| &v.rule),
+            "reason": violation.as_ref().map(|v| &v.reason),
+            "tags": tags,
+        },
+        "redaction": {
+            "output": redact_output,
+            "command_rules": command_rules,
+            "logged_command": redactor.redact(&p.command),
+        },
+    });
+
+    let mut text = format!("Dry run on {}, nothing was executed", host);
+    match connection {
+        Some(ref c) => text.push_str(&format!(
+            " ({}@{}:{}, {})",
+            c.user, c.hostname, c.port, c.shell
+        )),
+        None => text.push_str(" (not connected)"),
+    }
+    text.push_str(&format!("\nCommand: {}", command));
+    text.push_str(&format!(
+        "\nPolicy: {} ({})",
+        decision.replace('_', " "),
+        class
+    ));
+    if let Some(ref violation) = violation {
+        text.push_str(&format!(", rule {}: {}", violation.rule, violation.reason));
+    }
+    if let Some(ref interactive) = interactive {
+        text.push_str(&format!("\nRefused: {}", interactive));
+    }
+    text.push_str(if redact_output {
+        "\nOutput: secrets would be masked"
+    } else {
+        "\nOutput: returned unredacted"
+    });
+    if !command_rules.is_empty() {
+        text.push_str(&format!(
+            "\nCommand is logged with {} masked",
+            command_rules.join(", ")
+        ));
+    }
+    tracing::debug!(host = %host, decision, "Dry run");
+    structured_result(&value, text).map(|result| with_command_class(result, class))
+}
+
+pub async fn ssh_run_script_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    audit: &AuditLog,
+    redactor: &Redactor,
+    outputs: &OutputStore,
+    peer: Peer<RoleServer>,
+    params: Parameters<SshRunScriptParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    let interpreter = p.interpreter.as_deref().unwrap_or("bash").trim();
+    if interpreter.is_empty() {
+        return Err(McpError::invalid_params(
+            "interpreter must not be empty",
+            None,
+        ));
+    }
+
+    let class = approve(
+        audit,
+        &peer,
+        &p.host,
+        policy.check_script(interpreter, &p.script),
+    )
+    .await?;
+
+    let history = audit.history();
+    let output = match session_manager
+        .run_script(&p.host, interpreter, &p.script)
+        .await
+    {
+        Ok(output) => output,
         Err(e) => {
-            let msg = Box::leak(e.to_string().into_boxed_str());
-            Err(McpError::invalid_params(&*msg, None))
+            history.record_script(
+                session_manager.namespace(),
+                &p.host,
+                interpreter,
+                &p.script,
+                Err(&format!("{:#}", e)),
+            );
+            return Err(ssh_error(&e));
         }
+    };
+    if class != CommandClass::ReadOnly {
+        outputs
+            .cache()
+            .invalidate(session_manager.namespace(), &p.host);
+    }
+    let output = redacted(redactor, p.redact, output);
+    let history_id = history.record_script(
+        session_manager.namespace(),
+        &p.host,
+        interpreter,
+        &p.script,
+        Ok(&output),
+    );
+    outputs.record(
+        session_manager.namespace(),
+        &p.host,
+        &format!(
+            "{} script ({} lines)",
+            interpreter,
+            p.script.lines().count()
+        ),
+        &output,
+    );
+    let page = outputs.first_page(
+        session_manager.namespace(),
+        output,
+        p.offset.unwrap_or(0),
+        p.max_bytes.unwrap_or(DEFAULT_PAGE_BYTES),
+    );
+    page_result(&page).map(|result| with_history_id(with_command_class(result, class), history_id))
+}
+
+/// `output` with secrets masked, unless the call opted out.
+fn redacted(redactor: &Redactor, redact: Option<bool>, output: CommandOutput) -> CommandOutput {
+    if redact.unwrap_or(true) {
+        redactor.redact_output(output)
+    } else {
+        tracing::debug!("Output redaction skipped on request");
+        output
+    }
+}
+
+/// `page` as structured content, followed by a plain text rendering for
+/// clients that ignore structured results.
+fn page_result(page: &OutputPage) -> Result<CallToolResult, McpError> {
+    let value =
+        serde_json::to_value(page).map_err(|e| McpError::internal_error(e.to_string(), None))?;
+    let mut result = CallToolResult::structured(value);
+    result.content.push(Content::text(page.text()));
+    Ok(result)
+}
+
+/// Adds binary output to `result`: `is_binary`, its size and MIME type in
+/// the structured content, and the bytes as a base64 block, or the path of
+/// a local file holding them when they're larger than [`binary::INLINE_LIMIT`].
+fn with_binary(
+    mut result: CallToolResult,
+    host: &str,
+    data: &[u8],
+) -> Result<CallToolResult, McpError> {
+    let mime_type = binary::mime_type(data);
+    let mut fields = serde_json::json!({
+        "is_binary": true,
+        "binary_bytes": data.len(),
+        "mime_type": mime_type,
+    });
+    if data.len() > binary::INLINE_LIMIT {
+        let path = binary::spill(&binary::spill_dir(), host, data)
+            .map_err(|e| McpError::internal_error(format!("{:#}", e), None))?;
+        result.content.push(Content::text(format!(
+            "Binary output ({} bytes, {}) saved to {}",
+            data.len(),
+            mime_type,
+            path.display()
+        )));
+        fields["path"] = path.display().to_string().into();
+    } else if mime_type.starts_with("image/") {
+        result
+            .content
+            .push(Content::image(BASE64.encode(data), mime_type));
+    } else {
+        result
+            .content
+            .push(Content::resource(ResourceContents::BlobResourceContents {
+                uri: format!("ssh://{}/binary-output", host),
+                mime_type: Some(mime_type.to_string()),
+                blob: BASE64.encode(data),
+                meta: None,
+            }));
+    }
+    if let Some(serde_json::Value::Object(ref mut structured)) = result.structured_content
+        && let serde_json::Value::Object(fields) = fields
+    {
+        structured.extend(fields);
+    }
+    Ok(result)
+}
+
+/// `_meta` key carrying the policy's class of the command a call ran.
+pub const COMMAND_CLASS_META: &str = "ssh-liaison/commandClass";
+
+/// Tags `result` with `class`, so clients can weigh each call on its own
+/// rather than by the tool's static hints.
+fn with_command_class(mut result: CallToolResult, class: CommandClass) -> CallToolResult {
+    result
+        .meta
+        .get_or_insert_with(Meta::default)
+        .0
+        .insert(COMMAND_CLASS_META.to_string(), class.to_string().into());
+    result
+}
+
+/// `_meta` key carrying the shell's working directory after a command.
+pub const CWD_META: &str = "ssh-liaison/cwd";
+
+fn with_cwd(mut result: CallToolResult, cwd: Option<String>) -> CallToolResult {
+    if let Some(cwd) = cwd {
+        result
+            .meta
+            .get_or_insert_with(Meta::default)
+            .0
+            .insert(CWD_META.to_string(), cwd.into());
     }
+    result
+}
+
+/// `_meta` key set when a call connected its host first (see `--auto-connect`).
+pub const AUTO_CONNECTED_META: &str = "ssh-liaison/autoConnected";
+
+fn with_auto_connected(mut result: CallToolResult, auto_connected: bool) -> CallToolResult {
+    if auto_connected {
+        result
+            .meta
+            .get_or_insert_with(Meta::default)
+            .0
+            .insert(AUTO_CONNECTED_META.to_string(), true.into());
+    }
+    result
+}
+
+/// `_meta` key saying whether a cacheable command was answered from the
+/// result cache (see `--cache-ttl`); hits also carry `CACHE_AGE_META`.
+pub const CACHE_HIT_META: &str = "ssh-liaison/cacheHit";
+/// `_meta` key with the age in milliseconds of a cached result.
+pub const CACHE_AGE_META: &str = "ssh-liaison/cacheAgeMs";
+
+fn with_cache_hit(mut result: CallToolResult, age: Option<std::time::Duration>) -> CallToolResult {
+    let meta = &mut result.meta.get_or_insert_with(Meta::default).0;
+    meta.insert(CACHE_HIT_META.to_string(), age.is_some().into());
+    if let Some(age) = age {
+        meta.insert(CACHE_AGE_META.to_string(), (age.as_millis() as u64).into());
+    }
+    result
+}
+
+/// `_meta` key with the `ssh_history` id of the command that ran.
+pub const HISTORY_ID_META: &str = "ssh-liaison/historyId";
+
+/// `_meta` key with how long a command waited behind others on its shell.
+pub const QUEUED_META: &str = "ssh-liaison/queuedMs";
+
+pub fn with_queued(mut result: CallToolResult, waited: std::time::Duration) -> CallToolResult {
+    result
+        .meta
+        .get_or_insert_with(Meta::default)
+        .0
+        .insert(QUEUED_META.to_string(), (waited.as_millis() as u64).into());
+    result
+}
+
+/// Progress notifications about a command's place in its shell's queue, when
+/// the client asked for them with a progress token.
+pub fn queue_progress(
+    peer: &Peer<RoleServer>,
+    meta: &Meta,
+    host: &str,
+) -> impl FnMut(QueueStatus) + use<> {
+    let progress_token = meta.get_progress_token();
+    let peer = peer.clone();
+    let host = host.to_string();
+    let mut first_ahead = None;
+    move |status: QueueStatus| {
+        let Some(ref progress_token) = progress_token else {
+            return;
+        };
+        let total = *first_ahead.get_or_insert(status.ahead);
+        let param = ProgressNotificationParam {
+            progress_token: progress_token.clone(),
+            progress: total.saturating_sub(status.ahead) as f64,
+            total: Some(total as f64),
+            message: Some(status.message(&host)),
+        };
+        let peer = peer.clone();
+        tokio::spawn(async move {
+            if let Err(e) = peer.notify_progress(param).await {
+                tracing::debug!(error = %e, "Failed to send progress notification");
+            }
+        });
+    }
+}
+
+fn with_history_id(mut result: CallToolResult, id: u64) -> CallToolResult {
+    result
+        .meta
+        .get_or_insert_with(Meta::default)
+        .0
+        .insert(HISTORY_ID_META.to_string(), id.into());
+    result
+}
+
+pub async fn ssh_cancel_command_impl(
+    session_manager: &SessionManager,
+    redactor: &Redactor,
+    params: Parameters<SshCancelCommandParams>,
+) -> Result<CallToolResult, McpError> {
+    let (info, output) = session_manager
+        .cancel_command(params.0.id)
+        .await
+        .map_err(|e| McpError::invalid_params(format!("{:#}", e), None))?;
+    let output = redactor.redact_output(output);
+    let mut text = format!(
+        "Command {} on '{}' interrupted after {}s",
+        info.id, info.host, info.elapsed_secs
+    );
+    if output.stdout.is_empty() {
+        text.push_str(", no output");
+    } else {
+        text.push_str(", output so far:\n");
+        text.push_str(&output.stdout);
+    }
+    structured_result(
+        &serde_json::json!({
+            "id": info.id,
+            "host": info.host,
+            "channel": info.channel,
+            "command": info.command,
+            "stdout": output.stdout,
+            "duration_ms": output.duration_ms,
+            "truncated": output.truncated,
+        }),
+        text,
+    )
+}
+
+pub async fn ssh_reset_session_impl(
+    session_manager: &SessionManager,
+    params: Parameters<SshResetSessionParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let channel = p.channel.as_deref();
+    let reset = session_manager
+        .reset_shell(&p.host, channel, p.reopen.unwrap_or(false))
+        .await
+        .map_err(|e| ssh_error(&e))?;
+    let shell = match channel {
+        Some(name) => format!("Channel '{}' on '{}'", name, p.host),
+        None => format!("Shell on '{}'", p.host),
+    };
+    let mut text = if reset.reopened {
+        format!(
+            "{} reopened; its directory and variables are back to the login defaults",
+            shell
+        )
+    } else {
+        format!("{} resynchronized and ready", shell)
+    };
+    if !reset.cancelled.is_empty() {
+        let ids: Vec<String> = reset.cancelled.iter().map(u64::to_string).collect();
+        text.push_str(&format!(" (interrupted command {})", ids.join(", ")));
+    }
+    structured_result(
+        &serde_json::json!({
+            "host": p.host,
+            "channel": channel,
+            "cancelled": reset.cancelled,
+            "reopened": reset.reopened,
+        }),
+        text,
+    )
+}
+
+pub async fn ssh_fetch_output_impl(
+    session_manager: &SessionManager,
+    outputs: &OutputStore,
+    params: Parameters<SshFetchOutputParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let page = outputs
+        .fetch(
+            session_manager.namespace(),
+            &p.cursor,
+            p.offset,
+            p.max_bytes.unwrap_or(DEFAULT_PAGE_BYTES),
+        )
+        .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+    page_result(&page)
+}
+
+pub async fn ssh_run_on_hosts_impl(
+    session_manager: &SessionManager,
+    inventory: &Inventory,
+    policy: &CommandPolicy,
+    audit: &AuditLog,
+    redactor: &Redactor,
+    peer: Peer<RoleServer>,
+    params: Parameters<SshRunOnHostsParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = params.0;
+
+    let targets: Vec<String> = p
+        .hosts
+        .into_iter()
+        .chain(p.groups.iter().map(|g| format!("@{}", g)))
+        .collect();
+    let hosts = inventory
+        .expand_targets(&targets)
+        .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+    if hosts.is_empty() {
+        return Err(McpError::invalid_params("No hosts given", None));
+    }
+
+    // Hosts not connected yet are matched on their configured hostname too,
+    // as they are once `run_on_host` connects them.
+    let mut tags = BTreeSet::new();
+    for host in &hosts {
+        tags.extend(session_manager.tags(host).await);
+    }
+    let class = match policy.for_tags(&tags).check(&p.command) {
+        Ok(class) => class,
+        Err(violation) if violation.approvable => {
+            request_approval(&peer, audit, &hosts.join(","), &violation).await?;
+            CommandClass::Destructive
+        }
+        Err(violation) => return Err(policy_violation_error(&violation)),
+    };
+
+    let max_concurrency = p
+        .max_concurrency
+        .filter(|n| *n > 0)
+        .unwrap_or(DEFAULT_MAX_CONCURRENCY);
+    tracing::debug!(
+        hosts = hosts.len(),
+        max_concurrency,
+        "Running command on hosts"
+    );
+
+    let semaphore = Arc::new(Semaphore::new(max_concurrency));
+    let command = Arc::new(p.command);
+    let redactor = Arc::new(if p.redact.unwrap_or(true) {
+        redactor.clone()
+    } else {
+        Redactor::disabled()
+    });
+    let mut tasks = JoinSet::new();
+    for host in hosts.iter().cloned() {
+        let session_manager = session_manager.clone();
+        let semaphore = Arc::clone(&semaphore);
+        let command = Arc::clone(&command);
+        let redactor = Arc::clone(&redactor);
+        let history = Arc::clone(audit.history());
+        tasks.spawn(async move {
+            let _permit = semaphore.acquire_owned().await;
+            let result = run_on_host(&session_manager, &redactor, &history, &host, &command).await;
+            (host, result)
+        });
+    }
+
+    let mut results = BTreeMap::new();
+    while let Some(joined) = tasks.join_next().await {
+        let (host, result) = joined.map_err(|e| McpError::internal_error(e.to_string(), None))?;
+        results.insert(host, result);
+    }
+
+    Ok(with_command_class(
+        CallToolResult::success(vec![Content::json(results)?]),
+        class,
+    ))
+}
+
+async fn run_on_host(
+    session_manager: &SessionManager,
+    redactor: &Redactor,
+    history: &CommandHistory,
+    host: &str,
+    command: &str,
+) -> HostCommandResult {
+    let failed = |error: String, auto_connected| HostCommandResult {
+        success: false,
+        output: None,
+        exit_code: None,
+        error: Some(error),
+        auto_connected,
+    };
+
+    let auto_connected = !session_manager.is_connected(host).await;
+    if auto_connected && let Err(e) = session_manager.connect_by_alias(host).await {
+        return failed(format!("Failed to connect: {:#}", e), false);
+    }
+
+    let namespace = session_manager.namespace();
+    match session_manager.execute_command(host, command, None).await {
+        Ok(output) => {
+            let output = redactor.redact_output(output);
+            history.record(namespace, host, None, command, Ok(&output));
+            HostCommandResult {
+                success: true,
+                exit_code: output.exit_code,
+                output: Some(output.combined_with_stderr_label()),
+                error: None,
+                auto_connected,
+            }
+        }
+        Err(e) => {
+            let error = format!("{:#}", e);
+            history.record(namespace, host, None, command, Err(&error));
+            failed(error, auto_connected)
+        }
+    }
+}
+
+/// Checks `command` against the policy, asking for approval where the policy allows it.
+async fn authorize(
+    policy: &CommandPolicy,
+    audit: &AuditLog,
+    peer: &Peer<RoleServer>,
+    host: &str,
+    command: &str,
+) -> Result<CommandClass, McpError> {
+    approve(audit, peer, host, policy.check(command)).await
+}
+
+/// The class of a command that passed a policy check, or of one whose
+/// approvable violation a human approved.
+async fn approve(
+    audit: &AuditLog,
+    peer: &Peer<RoleServer>,
+    host: &str,
+    checked: Result<CommandClass, PolicyViolation>,
+) -> Result<CommandClass, McpError> {
+    let class = match checked {
+        Ok(class) => class,
+        Err(violation) if violation.approvable => {
+            request_approval(peer, audit, host, &violation).await?;
+            CommandClass::Destructive
+        }
+        Err(violation) => return Err(policy_violation_error(&violation)),
+    };
+    tracing::debug!(host = %host, class = %class, "Command passed policy");
+    Ok(class)
+}
+
+/// Asks the client's human to approve a command that matched a deny rule and
+/// records the decision. Without elicitation support the command stays blocked.
+async fn request_approval(
+    peer: &Peer<RoleServer>,
+    audit: &AuditLog,
+    host: &str,
+    violation: &PolicyViolation,
+) -> Result<(), McpError> {
+    if !peer.supports_elicitation() {
+        return Err(policy_violation_error(violation));
+    }
+
+    let client = peer.peer_info().map(|info| info.client_info.name.clone());
+    let mut record = ApprovalRecord::new(
+        host,
+        violation,
+        ApprovalDecision::Denied,
+        ApprovalChannel::McpElicitation,
+    );
+    record.client = client;
+
+    tracing::debug!(host = %host, rule = %violation.rule, "Requesting approval from client");
+    let answer = peer
+        .elicit::<CommandApprovalPrompt>(format!(
+            "Approval required on '{}'. The command matches deny rule '{}' ({}):\n\n{}\n\nRun it?",
+            host, violation.rule, violation.reason, violation.command
+        ))
+        .await;
+
+    let result = match answer {
+        Ok(Some(prompt)) => {
+            let approver = prompt.approver.trim().to_string();
+            record.comment = prompt.comment.filter(|c| !c.trim().is_empty());
+            if !approver.is_empty() {
+                record.approver = Some(approver);
+            }
+            if !prompt.approve {
+                Err(approval_denied_error(violation, "rejected by user"))
+            } else if record.approver.is_none() {
+                Err(approval_denied_error(violation, "no approver name given"))
+            } else {
+                record.decision = ApprovalDecision::Approved;
+                Ok(())
+            }
+        }
+        Ok(None) | Err(ElicitationError::NoContent) => {
+            Err(approval_denied_error(violation, "no answer"))
+        }
+        Err(ElicitationError::UserDeclined) | Err(ElicitationError::UserCancelled) => {
+            Err(approval_denied_error(violation, "declined by user"))
+        }
+        Err(e) => {
+            return Err(McpError::internal_error(
+                format!("Approval elicitation failed: {}", e),
+                None,
+            ));
+        }
+    };
+
+    audit.record(&record);
+    result
+}
+
+async fn elicit_sudo_password(
+    peer: &Peer<RoleServer>,
+    host: &str,
+    command: &str,
+) -> Result<SudoPasswordPrompt, McpError> {
+    if !peer.supports_elicitation() {
+        return Err(McpError::invalid_params(
+            SudoError::PasswordRequired.to_string(),
+            None,
+        ));
+    }
+
+    tracing::debug!(host = %host, "Eliciting sudo password from client");
+    match peer
+        .elicit::<SudoPasswordPrompt>(format!(
+            "Sudo password required on '{}' to run: {}",
+            host, command
+        ))
+        .await
+    {
+        Ok(Some(prompt)) if !prompt.password.is_empty() => Ok(prompt),
+        Ok(_) | Err(ElicitationError::NoContent) => {
+            Err(McpError::invalid_params("No sudo password provided", None))
+        }
+        Err(ElicitationError::UserDeclined) | Err(ElicitationError::UserCancelled) => Err(
+            McpError::invalid_params("User declined to provide the sudo password", None),
+        ),
+        Err(e) => Err(McpError::internal_error(
+            format!("Sudo password elicitation failed: {}", e),
+            None,
+        )),
+    }
+}
+
+pub async fn ssh_read_file_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    redactor: &Redactor,
+    params: Parameters<SshReadFileParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    policy
+        .check(&format!("cat {}", quote(&p.path)))
+        .map_err(|v| policy_violation_error(&v))?;
+
+    let range = ReadRange {
+        offset: p.offset.unwrap_or(0),
+        length: p.length,
+        start_line: p.start_line,
+        end_line: p.end_line,
+    };
+    let file = session_manager
+        .read_file(&p.host, &p.path, range)
+        .await
+        .map_err(|e| ssh_error(&e))?;
+
+    let bytes = file.data.len();
+    let (content, encoding) = match String::from_utf8(file.data) {
+        Ok(text) if p.redact.unwrap_or(true) => (redactor.redact(&text).into_owned(), "utf-8"),
+        Ok(text) => (text, "utf-8"),
+        Err(e) => (BASE64.encode(e.into_bytes()), "base64"),
+    };
+    let result = FileReadResult {
+        path: p.path.clone(),
+        content,
+        encoding,
+        bytes,
+        truncated: file.truncated,
+        via: file.via,
+    };
+
+    let mut text = result.content.clone();
+    if result.truncated {
+        text.push_str(&format!(
+            "\n[truncated after {} bytes; use offset/length or line ranges to read more]",
+            bytes
+        ));
+    }
+    let value =
+        serde_json::to_value(&result).map_err(|e| McpError::internal_error(e.to_string(), None))?;
+    let mut call_result = CallToolResult::structured(value);
+    call_result.content.push(Content::text(text));
+    Ok(call_result)
+}
+
+pub async fn ssh_checksum_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    rate_limiter: &CommandRateLimiter,
+    params: Parameters<SshChecksumParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    if p.paths.is_empty() {
+        return Err(McpError::invalid_params("paths must not be empty", None));
+    }
+    let algorithm = p.algorithm.unwrap_or_default();
+    let command = files::checksum_command(algorithm, &p.paths);
+    // Exits 1 when any file is missing; those get an error of their own.
+    let output = run_inspection(session_manager, policy, rate_limiter, &p.host, &command).await?;
+    let sums = files::parse_checksums(algorithm, &output.stdout, &p.paths);
+    if sums.iter().all(|s| s.checksum.is_none()) && output.exit_code != Some(1) {
+        return Err(inspection_failed(&p.host, "Checksum", &output));
+    }
+
+    let text = sums
+        .iter()
+        .map(|s| match (&s.checksum, &s.error) {
+            (Some(sum), _) => format!("{}  {}", sum, s.path),
+            (None, error) => format!("{}: {}", s.path, error.as_deref().unwrap_or("failed")),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    structured_result(
+        &ChecksumResult {
+            algorithm,
+            files: sums,
+        },
+        text,
+    )
+}
+
+/// Whole file for comparing; files past the read limit are refused rather
+/// than compared in part.
+async fn read_for_compare(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    host: &str,
+    path: &str,
+) -> Result<Vec<u8>, McpError> {
+    session_policy(policy, session_manager, host)
+        .await
+        .check(&format!("cat {}", quote(path)))
+        .map_err(|v| policy_violation_error(&v))?;
+    let file = session_manager
+        .read_file(host, path, ReadRange::default())
+        .await
+        .map_err(|e| ssh_error(&e))?;
+    if file.truncated {
+        return Err(McpError::invalid_params(
+            format!(
+                "{}:{} is larger than {} bytes; compare checksums with ssh_checksum instead",
+                host, path, MAX_READ_BYTES
+            ),
+            None,
+        ));
+    }
+    Ok(file.data)
+}
+
+pub async fn ssh_compare_files_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    redactor: &Redactor,
+    params: Parameters<SshCompareFilesParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let left = format!("{}:{}", p.host, p.path);
+    let left_read = read_for_compare(session_manager, policy, &p.host, &p.path);
+
+    let (right, left_data, right_data) = match &p.content {
+        Some(content) => {
+            if p.other_host.is_some() || p.other_path.is_some() {
+                return Err(McpError::invalid_params(
+                    "Give either content or other_host/other_path, not both",
+                    None,
+                ));
+            }
+            (
+                "local".to_string(),
+                left_read.await?,
+                content.as_bytes().to_vec(),
+            )
+        }
+        None => {
+            let other_host = p.other_host.as_deref().unwrap_or(&p.host);
+            let other_path = p.other_path.as_deref().unwrap_or(&p.path);
+            if other_host == p.host && other_path == p.path {
+                return Err(McpError::invalid_params(
+                    "Give other_host, other_path or content to compare with",
+                    None,
+                ));
+            }
+            // Reads on different hosts run in parallel.
+            let (left_data, right_data) = tokio::try_join!(
+                left_read,
+                read_for_compare(session_manager, policy, other_host, other_path)
+            )?;
+            (
+                format!("{}:{}", other_host, other_path),
+                left_data,
+                right_data,
+            )
+        }
+    };
+
+    let identical = left_data == right_data;
+    let (binary, diff, truncated) =
+        match (String::from_utf8(left_data), String::from_utf8(right_data)) {
+            _ if identical => (false, None, false),
+            (Ok(old), Ok(new)) => {
+                let (diff, truncated) = files::unified_diff(&left, &old, &right, &new);
+                let diff = if p.redact.unwrap_or(true) {
+                    redactor.redact(&diff).into_owned()
+                } else {
+                    diff
+                };
+                (false, Some(diff), truncated)
+            }
+            _ => (true, None, false),
+        };
+
+    let text = match &diff {
+        _ if identical => format!("{} and {} are identical", left, right),
+        Some(diff) if truncated => format!("{}\n[diff truncated]", diff),
+        Some(diff) => diff.clone(),
+        None => format!("{} and {} differ (binary)", left, right),
+    };
+    structured_result(
+        &CompareResult {
+            left,
+            right,
+            identical,
+            binary,
+            diff,
+            truncated,
+        },
+        text,
+    )
+}
+
+pub async fn ssh_list_dir_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    params: Parameters<SshListDirParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    policy
+        .check(&format!("ls {}", quote(&p.path)))
+        .map_err(|v| policy_violation_error(&v))?;
+
+    let (entries, via) = session_manager
+        .list_dir(&p.host, &p.path)
+        .await
+        .map_err(|e| ssh_error(&e))?;
+    let listing = DirListing {
+        path: p.path.clone(),
+        entries,
+        via,
+    };
+
+    let mut text = format!("{} ({} entries)\n", listing.path, listing.entries.len());
+    for entry in &listing.entries {
+        let kind = match entry.kind {
+            EntryKind::File => '-',
+            EntryKind::Directory => 'd',
+            EntryKind::Symlink => 'l',
+            EntryKind::Other => '?',
+        };
+        text.push_str(&format!(
+            "{}{} {:>10} {} {}\n",
+            kind,
+            entry.permissions.as_deref().unwrap_or("?????????"),
+            entry
+                .size
+                .map_or_else(|| "-".to_string(), |s| s.to_string()),
+            entry
+                .mtime
+                .map_or_else(|| "-".to_string(), |t| t.to_string()),
+            entry.name
+        ));
+    }
+    let value = serde_json::to_value(&listing)
+        .map_err(|e| McpError::internal_error(e.to_string(), None))?;
+    let mut call_result = CallToolResult::structured(value);
+    call_result.content.push(Content::text(text));
+    Ok(call_result)
+}
+
+pub async fn ssh_write_file_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    audit: &AuditLog,
+    peer: Peer<RoleServer>,
+    params: Parameters<SshWriteFileParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    let class = authorize(
+        policy,
+        audit,
+        &peer,
+        &p.host,
+        &format!("tee {}", quote(&p.path)),
+    )
+    .await?;
+
+    let data = if p.base64.unwrap_or(false) {
+        BASE64
+            .decode(p.content.trim())
+            .map_err(|e| McpError::invalid_params(format!("Invalid base64 content: {}", e), None))?
+    } else {
+        p.content.clone().into_bytes()
+    };
+
+    let via = session_manager
+        .write_file(&p.host, &p.path, &data)
+        .await
+        .map_err(|e| ssh_error(&e))?;
+    let result = CallToolResult::success(vec![Content::text(format!(
+        "Wrote {} bytes to {} on '{}' (via {})",
+        data.len(),
+        p.path,
+        p.host,
+        match via {
+            Transfer::Sftp => "SFTP",
+            Transfer::Shell => "shell",
+        }
+    ))]);
+    Ok(with_command_class(result, class))
+}
+
+pub async fn ssh_copy_between_hosts_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    audit: &AuditLog,
+    peer: Peer<RoleServer>,
+    meta: Meta,
+    params: Parameters<SshCopyBetweenHostsParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let pacing = transfer_pacing(&peer, &meta, p.max_bytes_per_sec)?;
+    session_policy(policy, session_manager, &p.source_host)
+        .await
+        .check(&format!("cat {}", quote(&p.source_path)))
+        .map_err(|v| policy_violation_error(&v))?;
+    let class = authorize(
+        &*session_policy(policy, session_manager, &p.target_host).await,
+        audit,
+        &peer,
+        &p.target_host,
+        &format!("tee {}", quote(&p.target_path)),
+    )
+    .await?;
+
+    let report = session_manager
+        .copy_between_hosts(
+            &p.source_host,
+            &p.source_path,
+            &p.target_host,
+            &p.target_path,
+            p.verify.unwrap_or(true),
+            &pacing,
+        )
+        .await
+        .map_err(|e| ssh_error(&e))?;
+    let text = format!(
+        "Copied {} bytes from {}:{} to {}:{} in {} ms{}",
+        report.bytes,
+        p.source_host,
+        report.source,
+        p.target_host,
+        report.destination,
+        report.duration_ms,
+        match &report.sha256 {
+            Some(sum) => format!(" (sha256 {} verified)", sum),
+            None => String::new(),
+        }
+    );
+    Ok(with_command_class(structured_result(&report, text)?, class))
+}
+
+pub async fn ssh_upload_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    audit: &AuditLog,
+    peer: Peer<RoleServer>,
+    meta: Meta,
+    params: Parameters<SshTransferParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    let options = transfer_options(&peer, &meta, p)?;
+    let local = transfer::local_path(&p.local_path).map_err(|e| ssh_error(&e))?;
+    let filter = PathFilter::new(&p.include, &p.exclude).map_err(|e| ssh_error(&e))?;
+    let class = authorize(
+        policy,
+        audit,
+        &peer,
+        &p.host,
+        &format!("tee {}", quote(&p.remote_path)),
+    )
+    .await?;
+
+    let summary = session_manager
+        .upload(&p.host, &local, &p.remote_path, &filter, &options)
+        .await
+        .map_err(|e| ssh_error(&e))?;
+    let text = transfer_text("Uploaded", &summary, &p.remote_path, &p.host);
+    Ok(with_command_class(
+        structured_result(&summary, text)?,
+        class,
+    ))
+}
+
+pub async fn ssh_download_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    peer: Peer<RoleServer>,
+    meta: Meta,
+    params: Parameters<SshTransferParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    let options = transfer_options(&peer, &meta, p)?;
+    let local = transfer::local_path(&p.local_path).map_err(|e| ssh_error(&e))?;
+    let filter = PathFilter::new(&p.include, &p.exclude).map_err(|e| ssh_error(&e))?;
+    policy
+        .check(&format!("cat {}", quote(&p.remote_path)))
+        .map_err(|v| policy_violation_error(&v))?;
+
+    let summary = session_manager
+        .download(&p.host, &p.remote_path, &local, &filter, &options)
+        .await
+        .map_err(|e| ssh_error(&e))?;
+    let text = transfer_text("Downloaded", &summary, &p.remote_path, &p.host);
+    structured_result(&summary, text)
+}
+
+fn transfer_options(
+    peer: &Peer<RoleServer>,
+    meta: &Meta,
+    p: &SshTransferParams,
+) -> Result<TransferOptions, McpError> {
+    Ok(TransferOptions {
+        resume: p.resume.unwrap_or(false),
+        pacing: transfer_pacing(peer, meta, p.max_bytes_per_sec)?,
+    })
+}
+
+/// A transfer's own rate limit, plus progress notifications when the client
+/// asked for them with a progress token.
+fn transfer_pacing(
+    peer: &Peer<RoleServer>,
+    meta: &Meta,
+    max_bytes_per_sec: Option<u64>,
+) -> Result<Pacing, McpError> {
+    let mut pacing = Pacing::default();
+    if let Some(rate) = max_bytes_per_sec {
+        let limiter = RateLimiter::new(rate).map_err(|e| ssh_error(&e))?;
+        pacing = pacing.limit(Arc::new(limiter));
+    }
+    if let Some(progress_token) = meta.get_progress_token() {
+        let peer = peer.clone();
+        pacing.notify = Some(Arc::new(move |update: &ProgressUpdate| {
+            let peer = peer.clone();
+            let param = ProgressNotificationParam {
+                progress_token: progress_token.clone(),
+                progress: update.bytes as f64,
+                total: update.total.map(|total| total as f64),
+                message: Some(update.message()),
+            };
+            tokio::spawn(async move {
+                if let Err(e) = peer.notify_progress(param).await {
+                    tracing::debug!(error = %e, "Failed to send progress notification");
+                }
+            });
+        }));
+    }
+    Ok(pacing)
+}
+
+fn transfer_text(verb: &str, summary: &TransferSummary, remote: &str, host: &str) -> String {
+    format!(
+        "{} {} file(s), {} bytes in {} ms ({}:{}); {} skipped, {} failed",
+        verb,
+        summary.files_copied,
+        summary.bytes,
+        summary.duration_ms,
+        host,
+        remote,
+        summary.files_skipped,
+        summary.files_failed
+    )
+}
+
+pub async fn ssh_install_public_key_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    audit: &AuditLog,
+    peer: Peer<RoleServer>,
+    params: Parameters<SshInstallPublicKeyParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    let key = PublicKey::parse(&p.public_key).map_err(|e| ssh_error(&e))?;
+    let class = authorize(
+        policy,
+        audit,
+        &peer,
+        &p.host,
+        "tee -a ~/.ssh/authorized_keys",
+    )
+    .await?;
+
+    let added = session_manager
+        .install_public_key(&p.host, &key)
+        .await
+        .map_err(|e| ssh_error(&e))?;
+    let text = if added {
+        format!(
+            "Added {} key to ~/.ssh/authorized_keys on '{}'",
+            key.key_type, p.host
+        )
+    } else {
+        format!(
+            "{} key is already in ~/.ssh/authorized_keys on '{}'",
+            key.key_type, p.host
+        )
+    };
+    let result = CallToolResult::success(vec![Content::text(text)]);
+    Ok(with_command_class(result, class))
+}
+
+pub async fn ssh_generate_keypair_impl(
+    params: Parameters<SshGenerateKeypairParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let keypair = keys::generate_keypair(
+        p.key_type.unwrap_or_default(),
+        p.comment.as_deref(),
+        p.path.as_deref().map(std::path::Path::new),
+    )
+    .await
+    .map_err(|e| ssh_error(&e))?;
+    let text = format!(
+        "Generated {} ({})\n{}",
+        keypair.private_key_path, keypair.fingerprint, keypair.public_key
+    );
+    structured_result(&keypair, text)
+}
+
+pub async fn ssh_cwd_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    rate_limiter: &CommandRateLimiter,
+    params: Parameters<SshCwdParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    let channel = p.channel.as_deref();
+    let cwd = match &p.path {
+        Some(path) => {
+            policy
+                .check(&format!("cd {}", quote(path)))
+                .map_err(|v| policy_violation_error(&v))?;
+            check_rate_limit(rate_limiter, session_manager, &[&p.host]).await?;
+            session_manager.change_dir(&p.host, channel, path).await
+        }
+        None => {
+            check_rate_limit(rate_limiter, session_manager, &[&p.host]).await?;
+            session_manager.working_dir(&p.host, channel).await
+        }
+    }
+    .map_err(|e| ssh_error(&e))?;
+    let value = serde_json::json!({ "host": p.host, "channel": p.channel, "cwd": cwd });
+    structured_result(&value, cwd)
+}
+
+/// `value` as the redactor masks it in a `NAME=value` line, so rules keyed
+/// on the variable name (`*_TOKEN=...`) apply too.
+fn redact_env_value(redactor: &Redactor, name: &str, value: &str) -> String {
+    let line = format!("{}={}", name, value);
+    let redacted = redactor.redact(&line);
+    match redacted.strip_prefix(&format!("{}=", name)) {
+        Some(value) => value.to_string(),
+        None => redacted.into_owned(),
+    }
+}
+
+pub async fn ssh_env_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    rate_limiter: &CommandRateLimiter,
+    audit: &AuditLog,
+    redactor: &Redactor,
+    peer: Peer<RoleServer>,
+    params: Parameters<SshEnvParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    let channel = p.channel.as_deref();
+    let set = p.set.clone().unwrap_or_default();
+    let unset = p.unset.clone().unwrap_or_default();
+    let names = p.names.clone().unwrap_or_default();
+
+    let mut class = None;
+    if !set.is_empty() || !unset.is_empty() {
+        let command: Vec<String> = set
+            .iter()
+            .map(|(name, value)| format!("export {}={}", name, quote(value)))
+            .chain(unset.iter().map(|name| format!("unset {}", name)))
+            .collect();
+        class = Some(authorize(policy, audit, &peer, &p.host, &command.join("; ")).await?);
+        check_rate_limit(rate_limiter, session_manager, &[&p.host]).await?;
+        session_manager
+            .set_env(&p.host, channel, &set, &unset)
+            .await
+            .map_err(|e| ssh_error(&e))?;
+    }
+    if !names.is_empty() {
+        policy
+            .check(&format!("printenv {}", names.join(" ")))
+            .map_err(|v| policy_violation_error(&v))?;
+        check_rate_limit(rate_limiter, session_manager, &[&p.host]).await?;
+    }
+    let mut values = session_manager
+        .env_values(&p.host, channel, &names)
+        .await
+        .map_err(|e| ssh_error(&e))?;
+    let mut tracked = session_manager
+        .shell_context(&p.host, channel)
+        .await
+        .map_err(|e| ssh_error(&e))?
+        .env;
+    if p.redact.unwrap_or(true) {
+        for (name, value) in values.iter_mut().chain(tracked.iter_mut()) {
+            *value = redact_env_value(redactor, name, value);
+        }
+    }
+
+    let mut lines: Vec<String> = tracked
+        .iter()
+        .map(|(name, value)| format!("{}={} (set here)", name, value))
+        .collect();
+    for name in &names {
+        match values.get(name) {
+            Some(value) => lines.push(format!("{}={}", name, value)),
+            None => lines.push(format!("{} is not set", name)),
+        }
+    }
+    let text = if lines.is_empty() {
+        "No variables set through ssh_env; pass names to read some".to_string()
+    } else {
+        lines.join("\n")
+    };
+    let value = serde_json::json!({
+        "host": p.host,
+        "channel": p.channel,
+        "tracked": tracked,
+        "values": values,
+    });
+    let result = structured_result(&value, text)?;
+    Ok(match class {
+        Some(class) => with_command_class(result, class),
+        None => result,
+    })
+}
+
+fn known_host_line(entry: &KnownHostEntry) -> String {
+    let mut line = format!(
+        "line {}: {} {} {}",
+        entry.line,
+        entry.hosts.as_deref().unwrap_or("(hashed)"),
+        entry.key_type,
+        entry.fingerprint
+    );
+    if let Some(marker) = &entry.marker {
+        line = format!("{} @{}", line, marker);
+    }
+    line
+}
+
+pub async fn ssh_known_hosts_list_impl(
+    params: Parameters<SshKnownHostsListParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let file = known_hosts::default_path();
+    let entries = match &p.host {
+        Some(host) => {
+            let (hostname, port) = known_hosts::resolve(host, p.port);
+            known_hosts::entries_for(&file, &known_hosts::host_key_name(&hostname, port)).await
+        }
+        None => match tokio::fs::read_to_string(&file).await {
+            Ok(content) => Ok(known_hosts::parse(&content)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        },
+    }
+    .map_err(|e| ssh_error(&e))?;
+
+    let text = if entries.is_empty() {
+        format!("No matching entries in {}", file.display())
+    } else {
+        let lines: Vec<String> = entries.iter().map(known_host_line).collect();
+        format!("{}:\n{}", file.display(), lines.join("\n"))
+    };
+    let result = KnownHostsResult {
+        file: file.display().to_string(),
+        entries,
+    };
+    structured_result(&result, text)
+}
+
+pub async fn ssh_keyscan_impl(
+    params: Parameters<SshKeyscanParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let (hostname, port) = known_hosts::resolve(&p.host, p.port);
+    let timeout = std::time::Duration::from_secs(p.timeout_secs.unwrap_or(5).clamp(1, 30).into());
+    let keys = known_hosts::keyscan(&hostname, port, timeout)
+        .await
+        .map_err(|e| ssh_error(&e))?;
+    let name = known_hosts::host_key_name(&hostname, port);
+    let recorded = known_hosts::entries_for(&known_hosts::default_path(), &name)
+        .await
+        .map_err(|e| ssh_error(&e))?;
+
+    let scanned: Vec<ScannedKey> = keys
+        .into_iter()
+        .map(|key| ScannedKey {
+            status: known_hosts::key_status(&recorded, &key),
+            fingerprint: key.fingerprint(),
+            public_key: key.to_line(),
+            key_type: key.key_type,
+        })
+        .collect();
+    let lines: Vec<String> = scanned
+        .iter()
+        .map(|k| format!("{} {} ({})", k.key_type, k.fingerprint, k.status))
+        .collect();
+    let text = format!("Host keys of {}:\n{}", name, lines.join("\n"));
+    structured_result(&serde_json::json!({ "host": name, "keys": scanned }), text)
+}
+
+pub async fn ssh_known_hosts_add_impl(
+    params: Parameters<SshKnownHostsAddParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let (hostname, port) = known_hosts::resolve(&p.host, p.port);
+    let keys = match &p.public_keys {
+        Some(lines) if !lines.is_empty() => lines
+            .iter()
+            .map(|line| PublicKey::parse(line))
+            .collect::<Result<Vec<_>>>(),
+        _ => {
+            let timeout = std::time::Duration::from_secs(5);
+            known_hosts::keyscan(&hostname, port, timeout).await
+        }
+    }
+    .map_err(|e| ssh_error(&e))?;
+
+    let name = known_hosts::host_key_name(&hostname, port);
+    let file = known_hosts::default_path();
+    let added = known_hosts::add(&file, &name, &keys)
+        .await
+        .map_err(|e| ssh_error(&e))?;
+    let text = if added.is_empty() {
+        format!("All keys are already in {} for {}", file.display(), name)
+    } else {
+        let lines: Vec<String> = added
+            .iter()
+            .map(|k| format!("{} {}", k.key_type, k.fingerprint()))
+            .collect();
+        format!(
+            "Added to {} for {}:\n{}",
+            file.display(),
+            name,
+            lines.join("\n")
+        )
+    };
+    Ok(CallToolResult::success(vec![Content::text(text)]))
+}
+
+pub async fn ssh_known_hosts_remove_impl(
+    params: Parameters<SshKnownHostsRemoveParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let (hostname, port) = known_hosts::resolve(&p.host, p.port);
+    let name = known_hosts::host_key_name(&hostname, port);
+    let file = known_hosts::default_path();
+    let removed = known_hosts::remove(&file, &name)
+        .await
+        .map_err(|e| ssh_error(&e))?;
+    let text = if removed == 0 {
+        format!("No keys for {} in {}", name, file.display())
+    } else {
+        format!(
+            "Removed {} key(s) for {} from {} (previous contents kept in {}.old)",
+            removed,
+            name,
+            file.display(),
+            file.display()
+        )
+    };
+    Ok(CallToolResult::success(vec![Content::text(text)]))
+}
+
+pub async fn ssh_read_log_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    rate_limiter: &CommandRateLimiter,
+    redactor: &Redactor,
+    params: Parameters<SshReadLogParams>,
+) -> Result<CallToolResult, McpError> {
+    let host = &params.0.host;
+    let policy = &*session_policy(policy, session_manager, host).await;
+    let file_path = &params.0.file_path;
+    let lines = params.0.lines;
+
+    let command = session_manager
+        .shell_kind(host)
+        .await
+        .map_err(|e| ssh_error(&e))?
+        .tail_command(file_path, lines);
+    policy
+        .check(&command)
+        .map_err(|v| policy_violation_error(&v))?;
+    check_rate_limit(rate_limiter, session_manager, &[host]).await?;
+
+    let channel = match params.0.channel.as_deref() {
+        Some(channel) => Some(channel),
+        None => session_manager
+            .is_busy(host)
+            .await
+            .map_err(|e| ssh_error(&e))?
+            .then_some(SCRATCH_CHANNEL),
+    };
+
+    match session_manager
+        .execute_command_in(host, channel, &command, None)
+        .await
+    {
+        Ok(output) => Ok(CallToolResult::success(vec![Content::text(
+            redacted(redactor, params.0.redact, output).combined_with_stderr_label(),
+        )])),
+        Err(e) => Err(ssh_error(&e)),
+    }
+}
+
+pub async fn ssh_journalctl_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    rate_limiter: &CommandRateLimiter,
+    redactor: &Redactor,
+    params: Parameters<SshJournalctlParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    let structured = p.structured.unwrap_or(false);
+    let command = JournalQuery {
+        unit: p.unit.as_deref(),
+        priority: p.priority.as_deref(),
+        since: p.since.as_deref(),
+        until: p.until.as_deref(),
+        grep: p.grep.as_deref(),
+        lines: p.lines.unwrap_or(DEFAULT_JOURNAL_LINES),
+        json: structured,
+    }
+    .command()
+    .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+    let output = run_inspection(session_manager, policy, rate_limiter, &p.host, &command).await?;
+    let output = redacted(redactor, p.redact, output);
+    // `--grep` exits 1 without output when nothing matches.
+    if output.exit_code != Some(0) && !output.combined_with_stderr_label().trim().is_empty() {
+        return Err(inspection_failed(&p.host, "journalctl", &output));
+    }
+
+    let max_bytes = p.max_bytes.unwrap_or(DEFAULT_PAGE_BYTES);
+    if !structured {
+        let lines: Vec<&str> = output.stdout.lines().collect();
+        let (lines, dropped) = keep_newest(lines, max_bytes, |line| line.len() + 1);
+        let mut text = lines.join("\n");
+        if dropped > 0 {
+            text.insert_str(
+                0,
+                &format!(
+                    "[{} older entries dropped to stay under {} bytes]\n",
+                    dropped, max_bytes
+                ),
+            );
+        }
+        if text.is_empty() {
+            text = "No journal entries match".to_string();
+        }
+        return Ok(CallToolResult::success(vec![Content::text(text)]));
+    }
+
+    let (entries, dropped) = keep_newest(parse_json(&output.stdout), max_bytes, |entry| {
+        entry.message.len()
+    });
+    let text = format!(
+        "{} journal entries{}",
+        entries.len(),
+        if dropped > 0 {
+            format!(" ({} older dropped)", dropped)
+        } else {
+            String::new()
+        }
+    );
+    structured_result(&JournalResult { entries, dropped }, text)
+}
+
+/// Runs a read-only helper command of an inspection tool after the policy
+/// check and rate limit, on a scratch channel if the shell is busy.
+async fn run_inspection(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    rate_limiter: &CommandRateLimiter,
+    host: &str,
+    command: &str,
+) -> Result<CommandOutput, McpError> {
+    policy
+        .check(command)
+        .map_err(|v| policy_violation_error(&v))?;
+    check_rate_limit(rate_limiter, session_manager, &[host]).await?;
+    let channel = session_manager
+        .is_busy(host)
+        .await
+        .map_err(|e| ssh_error(&e))?
+        .then_some(SCRATCH_CHANNEL);
+    session_manager
+        .execute_command_in(host, channel, command, None)
+        .await
+        .map_err(|e| ssh_error(&e))
+}
+
+fn inspection_failed(host: &str, what: &str, output: &CommandOutput) -> McpError {
+    McpError::internal_error(
+        format!(
+            "{} failed on '{}' (exit code {}): {}",
+            what,
+            host,
+            output
+                .exit_code
+                .map_or_else(|| "unknown".to_string(), |c| c.to_string()),
+            output.combined_with_stderr_label().trim()
+        ),
+        None,
+    )
+}
+
+/// `value` as structured content, followed by `text` for clients that
+/// ignore structured results.
+fn structured_result(value: &impl Serialize, text: String) -> Result<CallToolResult, McpError> {
+    let value =
+        serde_json::to_value(value).map_err(|e| McpError::internal_error(e.to_string(), None))?;
+    let mut result = CallToolResult::structured(value);
+    result.content.push(Content::text(text));
+    Ok(result)
+}
+
+pub async fn ssh_process_list_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    rate_limiter: &CommandRateLimiter,
+    redactor: &Redactor,
+    params: Parameters<SshProcessListParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    let limit = p.limit.unwrap_or(DEFAULT_PROCESS_LIMIT);
+    if limit == 0 || limit > MAX_PROCESS_LIMIT {
+        return Err(McpError::invalid_params(
+            format!("limit must be between 1 and {}", MAX_PROCESS_LIMIT),
+            None,
+        ));
+    }
+    let sort = p.sort_by.unwrap_or_default();
+
+    let output = run_inspection(session_manager, policy, rate_limiter, &p.host, PS_COMMAND).await?;
+    if output.exit_code != Some(0) {
+        return Err(inspection_failed(&p.host, "ps", &output));
+    }
+    let all = parse_ps(&output.stdout);
+    let total = all.len();
+    let (mut processes, matched) = top(all, sort, p.name.as_deref(), limit);
+    if p.redact.unwrap_or(true) {
+        for process in &mut processes {
+            process.command = redactor.redact(&process.command).into_owned();
+        }
+    }
+
+    let mut text = format!(
+        "{} of {} processes by {}\n{:>7} {:>7} {:<10} {:>5} {:>5} {:>9} COMMAND\n",
+        processes.len(),
+        matched,
+        match sort {
+            ProcessSort::Cpu => "CPU",
+            ProcessSort::Memory => "memory",
+        },
+        "PID",
+        "PPID",
+        "USER",
+        "%CPU",
+        "%MEM",
+        "RSS(KB)"
+    );
+    for process in &processes {
+        text.push_str(&format!(
+            "{:>7} {:>7} {:<10} {:>5.1} {:>5.1} {:>9} {}\n",
+            process.pid,
+            process.ppid,
+            process.user,
+            process.cpu,
+            process.mem,
+            process.rss_kb,
+            process.command
+        ));
+    }
+    structured_result(
+        &ProcessListResult {
+            processes,
+            matched,
+            total,
+        },
+        text,
+    )
+}
+
+/// Runs a `docker` command of the Docker tools, failing with a hint to use
+/// sudo when the daemon socket isn't accessible.
+async fn run_docker(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    rate_limiter: &CommandRateLimiter,
+    host: &str,
+    command: &str,
+) -> Result<CommandOutput, McpError> {
+    let output = run_inspection(session_manager, policy, rate_limiter, host, command).await?;
+    if output.exit_code == Some(0) {
+        return Ok(output);
+    }
+    let mut error = inspection_failed(host, "docker", &output);
+    if !command.starts_with("sudo")
+        && output
+            .combined_with_stderr_label()
+            .contains("permission denied")
+    {
+        error.message = format!("{}. Retry with sudo=true", error.message).into();
+    }
+    Err(error)
+}
+
+pub async fn ssh_docker_ps_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    rate_limiter: &CommandRateLimiter,
+    params: Parameters<SshDockerPsParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    let command = docker::ps_command(p.all.unwrap_or(false), p.sudo.unwrap_or(false));
+    let output = run_docker(session_manager, policy, rate_limiter, &p.host, &command).await?;
+    let containers = docker::parse_ps(&output.stdout);
+
+    let mut text = format!("{} containers\n", containers.len());
+    for c in &containers {
+        text.push_str(&format!(
+            "{} {} ({}) {}{}\n",
+            &c.id[..c.id.len().min(12)],
+            c.name,
+            c.image,
+            c.status,
+            if c.ports.is_empty() {
+                String::new()
+            } else {
+                format!(" [{}]", c.ports)
+            }
+        ));
+    }
+    structured_result(&serde_json::json!({ "containers": containers }), text)
+}
+
+pub async fn ssh_docker_logs_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    rate_limiter: &CommandRateLimiter,
+    redactor: &Redactor,
+    params: Parameters<SshDockerLogsParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    let command = docker::logs_command(
+        &p.container,
+        p.lines.unwrap_or(docker::DEFAULT_LOG_LINES),
+        p.since.as_deref(),
+        p.timestamps.unwrap_or(false),
+        p.sudo.unwrap_or(false),
+    )
+    .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+    let output = run_docker(session_manager, policy, rate_limiter, &p.host, &command).await?;
+    let output = redacted(redactor, p.redact, output);
+
+    let max_bytes = p.max_bytes.unwrap_or(DEFAULT_PAGE_BYTES);
+    let lines: Vec<String> = output.stdout.lines().map(str::to_string).collect();
+    let (lines, dropped) = keep_newest(lines, max_bytes, |line| line.len() + 1);
+    let mut text = lines.join("\n");
+    if dropped > 0 {
+        text.insert_str(
+            0,
+            &format!(
+                "[{} older lines dropped to stay under {} bytes]\n",
+                dropped, max_bytes
+            ),
+        );
+    }
+    structured_result(
+        &DockerLogsResult {
+            container: p.container.clone(),
+            lines,
+            dropped,
+        },
+        text,
+    )
+}
+
+pub async fn ssh_docker_inspect_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    rate_limiter: &CommandRateLimiter,
+    redactor: &Redactor,
+    params: Parameters<SshDockerInspectParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    let command = docker::inspect_command(&p.container, p.sudo.unwrap_or(false))
+        .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+    let output = run_docker(session_manager, policy, rate_limiter, &p.host, &command).await?;
+    let mut inspect = docker::parse_inspect(&output.stdout).map_err(|e| {
+        McpError::internal_error(format!("Unreadable docker inspect output: {}", e), None)
+    })?;
+    if p.redact.unwrap_or(true) {
+        redactor.redact_json(&mut inspect);
+    }
+
+    let text = format!(
+        "{} ({}): {}, restarted {} times",
+        p.container,
+        inspect["Config"]["Image"].as_str().unwrap_or("?"),
+        inspect["State"]["Status"]
+            .as_str()
+            .unwrap_or("unknown state"),
+        inspect["RestartCount"].as_u64().unwrap_or(0)
+    );
+    structured_result(&inspect, text)
+}
+
+pub async fn ssh_docker_stats_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    rate_limiter: &CommandRateLimiter,
+    params: Parameters<SshDockerStatsParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    let command = docker::stats_command(&p.containers, p.sudo.unwrap_or(false))
+        .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+    let output = run_docker(session_manager, policy, rate_limiter, &p.host, &command).await?;
+    let stats = docker::parse_stats(&output.stdout);
+
+    let mut text = format!("{} containers\n", stats.len());
+    for s in &stats {
+        text.push_str(&format!(
+            "{}: CPU {}, memory {} ({}), net {}, block {}\n",
+            s.name,
+            s.cpu_percent
+                .map_or_else(|| "?".to_string(), |c| format!("{:.1}%", c)),
+            s.mem_usage,
+            s.mem_percent
+                .map_or_else(|| "?".to_string(), |m| format!("{:.1}%", m)),
+            s.net_io,
+            s.block_io
+        ));
+    }
+    structured_result(&serde_json::json!({ "containers": stats }), text)
+}
+
+pub async fn ssh_k8s_node_triage_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    rate_limiter: &CommandRateLimiter,
+    redactor: &Redactor,
+    params: Parameters<SshK8sNodeTriageParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    let max_errors = p.max_errors.unwrap_or(k8s::DEFAULT_MAX_ERRORS);
+    if max_errors > k8s::MAX_ERRORS {
+        return Err(McpError::invalid_params(
+            format!("max_errors must be at most {}", k8s::MAX_ERRORS),
+            None,
+        ));
+    }
+    let command = k8s::triage_command(
+        p.since_minutes
+            .unwrap_or(k8s::DEFAULT_ERROR_WINDOW_MINUTES)
+            .max(1),
+        max_errors,
+    );
+    // The last part is a grep, which exits 1 when there are no errors.
+    let output = run_inspection(session_manager, policy, rate_limiter, &p.host, &command).await?;
+    let output = redacted(redactor, p.redact, output);
+    let triage = k8s::parse_triage(&output.stdout);
+    if triage.services.is_empty() && triage.disks.is_empty() {
+        return Err(inspection_failed(&p.host, "Node triage", &output));
+    }
+
+    let mut text = if triage.problems.is_empty() {
+        "Node looks healthy\n".to_string()
+    } else {
+        format!("Problems:\n- {}\n", triage.problems.join("\n- "))
+    };
+    for service in &triage.services {
+        text.push_str(&format!(
+            "{}: {} ({})\n",
+            service.name, service.active_state, service.sub_state
+        ));
+    }
+    for disk in &triage.disks {
+        text.push_str(&format!(
+            "{}: {}% used, {}% inodes\n",
+            disk.mount,
+            disk.used_percent
+                .map_or_else(|| "?".to_string(), |u| u.to_string()),
+            disk.inodes_used_percent
+                .map_or_else(|| "?".to_string(), |u| u.to_string())
+        ));
+    }
+    for error in &triage.kubelet_errors {
+        text.push_str(error);
+        text.push('\n');
+    }
+    structured_result(&triage, text)
+}
+
+pub async fn ssh_find_files_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    rate_limiter: &CommandRateLimiter,
+    params: Parameters<SshFindFilesParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    let limit = p.limit.unwrap_or(DEFAULT_FIND_LIMIT);
+    let timeout_secs = p.timeout_secs.unwrap_or(DEFAULT_SEARCH_TIMEOUT_SECS);
+    let command = FindQuery {
+        path: &p.path,
+        name: p.name.as_deref(),
+        ignore_case: p.ignore_case.unwrap_or(false),
+        kind: p.kind,
+        max_depth: p.max_depth,
+        newer_than_minutes: p.newer_than_minutes,
+        older_than_minutes: p.older_than_minutes,
+        min_size_kb: p.min_size_kb,
+        same_filesystem: p.same_filesystem.unwrap_or(true),
+        limit,
+        timeout_secs,
+    }
+    .command()
+    .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+
+    let output = run_inspection(session_manager, policy, rate_limiter, &p.host, &command).await?;
+    let (entries, truncated) = parse_find(&output.stdout, limit);
+    // The pipe hides timeout's exit status; a search that ran for the whole
+    // budget without filling the limit was cut short.
+    let timed_out = !truncated && output.duration_ms >= u64::from(timeout_secs) * 1000;
+
+    let mut text = format!(
+        "{} entries under {}{}{}\n",
+        entries.len(),
+        p.path,
+        if truncated { " (limit reached)" } else { "" },
+        if timed_out {
+            format!(" (stopped after {}s, results incomplete)", timeout_secs)
+        } else {
+            String::new()
+        }
+    );
+    for entry in &entries {
+        text.push_str(&format!(
+            "{} {:>10} {}\n",
+            match entry.kind {
+                EntryKind::File => '-',
+                EntryKind::Directory => 'd',
+                EntryKind::Symlink => 'l',
+                EntryKind::Other => '?',
+            },
+            entry
+                .size
+                .map_or_else(|| "-".to_string(), |s| s.to_string()),
+            entry.path
+        ));
+    }
+    structured_result(
+        &FindResult {
+            entries,
+            truncated,
+            timed_out,
+        },
+        text,
+    )
+}
+
+pub async fn ssh_grep_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    rate_limiter: &CommandRateLimiter,
+    redactor: &Redactor,
+    params: Parameters<SshGrepParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    let max_matches = p.max_matches.unwrap_or(DEFAULT_GREP_MATCHES);
+    let timeout_secs = p.timeout_secs.unwrap_or(DEFAULT_SEARCH_TIMEOUT_SECS);
+    let (before, after) = (p.before.unwrap_or(0), p.after.unwrap_or(0));
+    let command = GrepQuery {
+        pattern: &p.pattern,
+        paths: &p.paths,
+        recursive: p.recursive.unwrap_or(false),
+        ignore_case: p.ignore_case.unwrap_or(false),
+        fixed_strings: p.fixed_strings.unwrap_or(false),
+        before,
+        after,
+        max_matches,
+        skip_binary: p.skip_binary.unwrap_or(true),
+        timeout_secs,
+    }
+    .command()
+    .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+
+    let output = run_inspection(session_manager, policy, rate_limiter, &p.host, &command).await?;
+    let output = redacted(redactor, p.redact, output);
+    let (matches, truncated) = parse_grep(&output.stdout, before, after, max_matches);
+    // Same as ssh_find_files: the pipe hides timeout's exit status.
+    let timed_out = !truncated && output.duration_ms >= u64::from(timeout_secs) * 1000;
+
+    let mut text = format!(
+        "{} matches{}{}\n",
+        matches.len(),
+        if truncated { " (limit reached)" } else { "" },
+        if timed_out {
+            format!(" (stopped after {}s, results incomplete)", timeout_secs)
+        } else {
+            String::new()
+        }
+    );
+    for m in &matches {
+        for c in &m.before {
+            text.push_str(&format!("{}-{}-{}\n", m.file, c.line, c.text));
+        }
+        text.push_str(&format!("{}:{}:{}\n", m.file, m.line, m.text));
+        for c in &m.after {
+            text.push_str(&format!("{}-{}-{}\n", m.file, c.line, c.text));
+        }
+    }
+    structured_result(
+        &GrepResult {
+            matches,
+            truncated,
+            timed_out,
+        },
+        text,
+    )
+}
+
+pub async fn ssh_disk_usage_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    rate_limiter: &CommandRateLimiter,
+    params: Parameters<SshDiskUsageParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    let path = p.path.as_deref().unwrap_or("/");
+    let top = p.top.unwrap_or(disk::DEFAULT_TOP_DIRS);
+    let timeout_secs = p.timeout_secs.unwrap_or(DEFAULT_SEARCH_TIMEOUT_SECS);
+    let command = DiskUsageQuery {
+        path,
+        depth: p.depth.unwrap_or(disk::DEFAULT_DU_DEPTH),
+        top,
+        timeout_secs,
+    }
+    .command()
+    .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+
+    let output = run_inspection(session_manager, policy, rate_limiter, &p.host, &command).await?;
+    let usage = disk::parse_disk_usage(&output.stdout, path, top);
+    if usage.filesystems.is_empty() {
+        return Err(inspection_failed(&p.host, "Disk usage", &output));
+    }
+    let timed_out = output.duration_ms >= u64::from(timeout_secs) * 1000;
+
+    let mut text = String::new();
+    for fs in &usage.filesystems {
+        text.push_str(&format!(
+            "{} ({}, {}): {}% used, {} KiB free, {}% inodes\n",
+            fs.mount,
+            fs.fs_type,
+            fs.filesystem,
+            fs.used_percent
+                .map_or_else(|| "?".to_string(), |u| u.to_string()),
+            fs.available_kb,
+            fs.inodes_used_percent
+                .map_or_else(|| "?".to_string(), |u| u.to_string())
+        ));
+    }
+    text.push_str(&format!(
+        "\nLargest directories under {}{}{}:\n",
+        path,
+        usage
+            .total_kb
+            .map_or_else(String::new, |t| format!(" ({} KiB total)", t)),
+        if timed_out {
+            format!(", stopped after {}s, sizes incomplete", timeout_secs)
+        } else {
+            String::new()
+        }
+    ));
+    for dir in &usage.directories {
+        text.push_str(&format!("{:>12} KiB  {}\n", dir.size_kb, dir.path));
+    }
+    structured_result(&DiskUsageResult { usage, timed_out }, text)
+}
+
+pub async fn ssh_network_info_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    rate_limiter: &CommandRateLimiter,
+    params: Parameters<SshNetworkInfoParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    let output = run_inspection(
+        session_manager,
+        policy,
+        rate_limiter,
+        &p.host,
+        &network::network_command(),
+    )
+    .await?;
+    let info = network::parse_network(&output.stdout);
+    if info.interfaces.is_empty() && info.routes.is_empty() && info.listening.is_empty() {
+        return Err(inspection_failed(&p.host, "Network info", &output));
+    }
+
+    let mut text = format!("Interfaces (via {}):\n", info.via.join(", "));
+    for interface in &info.interfaces {
+        let addresses: Vec<String> = interface
+            .addresses
+            .iter()
+            .map(|a| match a.prefix_len {
+                Some(prefix) => format!("{}/{}", a.address, prefix),
+                None => a.address.clone(),
+            })
+            .collect();
+        text.push_str(&format!(
+            "  {} {} {}\n",
+            interface.name,
+            interface.state,
+            addresses.join(" ")
+        ));
+    }
+    text.push_str("Routes:\n");
+    for route in &info.routes {
+        text.push_str(&format!(
+            "  {}{}{}\n",
+            route.destination,
+            route
+                .gateway
+                .as_ref()
+                .map_or_else(String::new, |g| format!(" via {}", g)),
+            route
+                .interface
+                .as_ref()
+                .map_or_else(String::new, |i| format!(" dev {}", i))
+        ));
+    }
+    text.push_str("Listening:\n");
+    for socket in &info.listening {
+        text.push_str(&format!(
+            "  {} {}:{} {}\n",
+            socket.protocol,
+            socket.address,
+            socket
+                .port
+                .map_or_else(|| "*".to_string(), |p| p.to_string()),
+            socket.process.as_deref().unwrap_or("-")
+        ));
+    }
+    structured_result(&info, text)
+}
+
+pub async fn ssh_port_check_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    rate_limiter: &CommandRateLimiter,
+    params: Parameters<SshPortCheckParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    let timeout_secs = p
+        .timeout_secs
+        .unwrap_or(network::DEFAULT_PROBE_TIMEOUT_SECS);
+    let methods: &[ProbeMethod] = match p.method.unwrap_or_default() {
+        ProbeMethod::Auto => &[ProbeMethod::Nc, ProbeMethod::Bash],
+        ProbeMethod::Nc => &[ProbeMethod::Nc],
+        ProbeMethod::Bash => &[ProbeMethod::Bash],
+    };
+
+    let mut last_output = None;
+    for &method in methods {
+        let command = network::probe_command(method, &p.target_host, p.port, timeout_secs)
+            .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+        let output =
+            run_inspection(session_manager, policy, rate_limiter, &p.host, &command).await?;
+        let Some(result) = network::parse_probe(&output.stdout) else {
+            tracing::debug!(host = %p.host, ?method, "Port probe unavailable");
+            last_output = Some(output);
+            continue;
+        };
+
+        let text = format!(
+            "{}:{} from {} is {}{} (via {})\n{}",
+            p.target_host,
+            p.port,
+            p.host,
+            result.status,
+            result
+                .latency_ms
+                .map_or_else(String::new, |ms| format!(" after {:.1} ms", ms)),
+            if method == ProbeMethod::Nc {
+                "nc"
+            } else {
+                "bash"
+            },
+            result.detail
+        );
+        return structured_result(
+            &PortCheckResult {
+                target_host: &p.target_host,
+                port: p.port,
+                method,
+                result,
+            },
+            text,
+        );
+    }
+    let output = last_output.expect("at least one probe method ran");
+    Err(inspection_failed(
+        &p.host,
+        "Port check (no usable nc or bash)",
+        &output,
+    ))
+}
+
+pub async fn ssh_package_query_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    rate_limiter: &CommandRateLimiter,
+    params: Parameters<SshPackageQueryParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    let list_upgrades = p.upgrades.unwrap_or(false);
+    if p.packages.is_empty() && !list_upgrades {
+        return Err(McpError::invalid_params(
+            "Give packages to look up, or set upgrades=true",
+            None,
+        ));
+    }
+
+    let output = run_inspection(
+        session_manager,
+        policy,
+        rate_limiter,
+        &p.host,
+        packages::DETECT_COMMAND,
+    )
+    .await?;
+    let Some(detected) = packages::parse_detect(&output.stdout) else {
+        return Err(McpError::internal_error(
+            format!(
+                "No supported package manager (dpkg, rpm, apk, pacman) found on '{}'",
+                p.host
+            ),
+            None,
+        ));
+    };
+
+    let mut statuses = Vec::new();
+    if !p.packages.is_empty() {
+        let command = packages::query_command(detected.manager, &p.packages)
+            .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+        let output =
+            run_inspection(session_manager, policy, rate_limiter, &p.host, &command).await?;
+        statuses = packages::parse_query(detected.manager, &output.stdout, &p.packages);
+    }
+
+    let mut upgrades = None;
+    if list_upgrades {
+        match packages::upgrades_command(detected) {
+            Some(command) => {
+                let output =
+                    run_inspection(session_manager, policy, rate_limiter, &p.host, &command)
+                        .await?;
+                let list = packages::parse_upgrades(detected.manager, &output.stdout);
+                for status in &mut statuses {
+                    status.upgrade = list
+                        .iter()
+                        .find(|u| u.name == status.name)
+                        .map(|u| u.available.clone());
+                }
+                upgrades = Some(list);
+            }
+            None => tracing::debug!(host = %p.host, "No dnf or yum to list rpm upgrades"),
+        }
+    }
+
+    let mut text = format!("Package manager: {}\n", detected.manager);
+    for status in &statuses {
+        text.push_str(&format!(
+            "{}: {}{}\n",
+            status.name,
+            status.version.as_deref().unwrap_or("not installed"),
+            status
+                .upgrade
+                .as_ref()
+                .map_or_else(String::new, |u| format!(" (upgrade to {})", u))
+        ));
+    }
+    match &upgrades {
+        Some(list) => {
+            text.push_str(&format!("{} upgrades available\n", list.len()));
+            for upgrade in list {
+                text.push_str(&format!("  {} {}\n", upgrade.name, upgrade.available));
+            }
+        }
+        None if list_upgrades => text.push_str("Upgrades can't be listed without dnf or yum\n"),
+        None => {}
+    }
+    structured_result(
+        &PackageQueryResult {
+            manager: detected.manager,
+            packages: statuses,
+            upgrades,
+        },
+        text,
+    )
+}
+
+pub async fn ssh_list_hosts_impl() -> Result<CallToolResult, McpError> {
+    let hosts = parse_hosts_blocking().await.map_err(|e| ssh_error(&e))?;
+
+    if hosts.is_empty() {
+        return Ok(CallToolResult::success(vec![Content::text(
+            "No host aliases found in ~/.ssh/config",
+        )]));
+    }
+
+    let lines: Vec<String> = hosts
+        .iter()
+        .map(|h| {
+            let mut line = format!(
+                "{}: {}@{}:{}",
+                h.host,
+                h.user.as_deref().unwrap_or("?"),
+                h.hostname.as_deref().unwrap_or(&h.host),
+                h.port.unwrap_or(22)
+            );
+            if h.proxy_command.is_some() {
+                line.push_str(" (via ProxyCommand)");
+            }
+            line
+        })
+        .collect();
+    Ok(CallToolResult::success(vec![Content::text(
+        lines.join("\n"),
+    )]))
+}
+
+async fn parse_hosts_blocking() -> Result<Vec<SshHostConfig>> {
+    tokio::task::spawn_blocking(list_ssh_hosts).await?
+}
+
+pub async fn ssh_list_groups_impl(inventory: &Inventory) -> Result<CallToolResult, McpError> {
+    if inventory.is_empty() {
+        return Ok(CallToolResult::success(vec![Content::text(
+            "No host groups defined. Add them to ~/.config/ssh-liaison/inventory.toml or pass --inventory.",
+        )]));
+    }
+
+    let mut lines = Vec::new();
+    for (name, group) in &inventory.groups {
+        let hosts = inventory
+            .resolve(name)
+            .map_err(|e| McpError::internal_error(e.to_string(), None))?;
+        let mut line = format!("{}: {}", name, hosts.join(", "));
+        if let Some(ref description) = group.description {
+            line.push_str(&format!(" ({})", description));
+        }
+        lines.push(line);
+    }
+    Ok(CallToolResult::success(vec![Content::text(
+        lines.join("\n"),
+    )]))
+}
+
+pub async fn ssh_list_connections_impl(
+    session_manager: &SessionManager,
+) -> Result<CallToolResult, McpError> {
+    let connections = session_manager.list_connections().await;
+    let text = if connections.is_empty() {
+        "No active connections".to_string()
+    } else {
+        connections
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    structured_result(&serde_json::json!({ "connections": connections }), text)
+}
+
+pub async fn ssh_restore_sessions_impl(
+    session_manager: &SessionManager,
+    params: Parameters<SshRestoreSessionsParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let aliases = p.aliases.as_deref().unwrap_or_default();
+
+    if p.forget.unwrap_or(false) {
+        let store = session_manager.session_store().ok_or_else(|| {
+            McpError::invalid_params("Session persistence is disabled on this server", None)
+        })?;
+        let forgotten = store.forget(aliases).map_err(|e| ssh_error(&e))?;
+        let text = if forgotten.is_empty() {
+            "No saved sessions matched".to_string()
+        } else {
+            format!("Forgot saved session(s): {}", forgotten.join(", "))
+        };
+        return structured_result(&serde_json::json!({ "forgotten": forgotten }), text);
+    }
+
+    let restored = session_manager
+        .restore_sessions(aliases)
+        .await
+        .map_err(|e| ssh_error(&e))?;
+    let text = if restored.is_empty() {
+        "No saved sessions to restore".to_string()
+    } else {
+        restored
+            .iter()
+            .map(|r| r.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    structured_result(&serde_json::json!({ "sessions": restored }), text)
+}
+
+pub async fn ssh_history_impl(
+    session_manager: &SessionManager,
+    audit: &AuditLog,
+    params: Parameters<SshHistoryParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let pattern = p
+        .pattern
+        .as_deref()
+        .map(regex::Regex::new)
+        .transpose()
+        .map_err(|e| McpError::invalid_params(format!("Invalid pattern: {}", e), None))?;
+    let entries = audit.history().query(
+        session_manager.namespace(),
+        p.host.as_deref(),
+        pattern.as_ref(),
+        p.limit.unwrap_or(DEFAULT_HISTORY_LIMIT),
+    );
+    let text = if entries.is_empty() {
+        "No commands recorded".to_string()
+    } else {
+        entries
+            .iter()
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    structured_result(&serde_json::json!({ "entries": entries }), text)
+}
+
+pub async fn ssh_rerun_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    audit: &AuditLog,
+    redactor: &Redactor,
+    outputs: &OutputStore,
+    peer: Peer<RoleServer>,
+    params: Parameters<SshRerunParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let namespace = session_manager.namespace();
+    let history = audit.history();
+    let previous = history.get(namespace, p.id).ok_or_else(|| {
+        McpError::invalid_params(format!("No command with id {} in the history", p.id), None)
+    })?;
+    let command = match previous.command {
+        Some(command) => command,
+        None if previous.entry.script => {
+            return Err(McpError::invalid_params(
+                "Scripts can't be re-run because their body isn't kept; use ssh_run_script",
+                None,
+            ));
+        }
+        None => {
+            return Err(McpError::invalid_params(
+                "The command was saved with secrets masked and can't be re-run; use ssh_run_command",
+                None,
+            ));
+        }
+    };
+    let host = &previous.entry.host;
+    let channel = previous.entry.channel.as_deref();
+
+    let policy = &*session_policy(policy, session_manager, host).await;
+    let class = authorize(policy, audit, &peer, host, &command).await?;
+    if class != CommandClass::ReadOnly {
+        outputs.cache().invalidate(namespace, host);
+    }
+    let output = match session_manager
+        .execute_command_in(host, channel, &command, None)
+        .await
+    {
+        Ok(output) => redacted(redactor, p.redact, output),
+        Err(e) => {
+            history.record(namespace, host, channel, &command, Err(&format!("{:#}", e)));
+            return Err(ssh_error(&e));
+        }
+    };
+    let id = history.record(namespace, host, channel, &command, Ok(&output));
+    outputs.record(namespace, host, &redactor.redact(&command), &output);
+
+    let new_text = output.combined_with_stderr_label();
+    let (changed, diff, truncated) = match previous.output {
+        Some(ref old) => {
+            let old_text = old.combined_with_stderr_label();
+            if old_text == new_text {
+                (Some(false), None, false)
+            } else {
+                let (diff, truncated) = files::unified_diff(
+                    &format!("#{}", p.id),
+                    &old_text,
+                    &format!("#{}", id),
+                    &new_text,
+                );
+                (Some(true), Some(diff), truncated)
+            }
+        }
+        None => (None, None, false),
+    };
+
+    let exit = |code: Option<i32>| code.map_or("none".to_string(), |c| c.to_string());
+    let mut text = format!(
+        "Re-ran #{} as #{} on '{}': exit {} (was {})\n",
+        p.id,
+        id,
+        host,
+        exit(output.exit_code),
+        exit(previous.entry.exit_code)
+    );
+    match (&diff, changed) {
+        (Some(diff), _) => {
+            text.push_str(diff);
+            if truncated {
+                text.push_str("\n[diff truncated]");
+            }
+        }
+        (None, Some(_)) => text.push_str("Output unchanged"),
+        (None, None) => {
+            text.push_str("The previous output is no longer kept; this run's output:\n");
+            text.push_str(&new_text);
+        }
+    }
+    let result = structured_result(
+        &RerunResult {
+            id,
+            previous_id: p.id,
+            host: host.clone(),
+            command: previous.entry.command,
+            exit_code: output.exit_code,
+            previous_exit_code: previous.entry.exit_code,
+            changed,
+            diff,
+            truncated,
+            output: changed.is_none().then_some(new_text),
+        },
+        text,
+    )?;
+    Ok(with_history_id(with_command_class(result, class), id))
+}
+
+pub async fn ssh_tunnel_open_impl(
+    session_manager: &SessionManager,
+    params: Parameters<SshTunnelOpenParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+
+    match session_manager
+        .open_tunnel(
+            &p.host,
+            p.local_port.unwrap_or(0),
+            &p.remote_host,
+            p.remote_port,
+        )
+        .await
+    {
+        Ok(info) => Ok(CallToolResult::success(vec![Content::text(format!(
+            "Tunnel {} open: {} -> {} via '{}'",
+            info.id, info.local_addr, info.target, info.host
+        ))])),
+        Err(e) => Err(ssh_error(&e)),
+    }
+}
+
+pub async fn ssh_socks_proxy_impl(
+    session_manager: &SessionManager,
+    params: Parameters<SshSocksProxyParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let defaults = SocksOptions::default();
+    let options = SocksOptions {
+        max_connections: p
+            .max_connections
+            .filter(|n| *n > 0)
+            .unwrap_or(defaults.max_connections),
+        idle_timeout: p
+            .idle_timeout_secs
+            .map(std::time::Duration::from_secs)
+            .unwrap_or(defaults.idle_timeout),
+    };
+
+    match session_manager
+        .open_socks_proxy(&p.host, p.local_port.unwrap_or(0), options)
+        .await
+    {
+        Ok(info) => {
+            let max = match info.target {
+                TunnelTarget::Socks { max_connections } => max_connections,
+                TunnelTarget::Remote { .. } => options.max_connections,
+            };
+            Ok(CallToolResult::success(vec![Content::text(format!(
+                "SOCKS5 proxy {} listening on {} via '{}' (max {} connections, idle timeout {}s). Use socks5h://{} to resolve names on the remote side.",
+                info.id,
+                info.local_addr,
+                info.host,
+                max,
+                options.idle_timeout.as_secs(),
+                info.local_addr
+            ))]))
+        }
+        Err(e) => Err(ssh_error(&e)),
+    }
+}
+
+pub async fn ssh_tunnel_list_impl(
+    session_manager: &SessionManager,
+) -> Result<CallToolResult, McpError> {
+    let tunnels = session_manager.list_tunnels().await;
+    let text = if tunnels.is_empty() {
+        "No open tunnels".to_string()
+    } else {
+        tunnels
+            .iter()
+            .map(|t| t.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    Ok(CallToolResult::success(vec![Content::text(text)]))
+}
+
+pub async fn ssh_tunnel_close_impl(
+    session_manager: &SessionManager,
+    params: Parameters<SshTunnelCloseParams>,
+) -> Result<CallToolResult, McpError> {
+    match session_manager.close_tunnel(&params.0.tunnel_id).await {
+        Ok(info) => Ok(CallToolResult::success(vec![Content::text(format!(
+            "Tunnel {} closed ({})",
+            info.id, info.local_addr
+        ))])),
+        Err(e) => Err(ssh_error(&e)),
+    }
+}
+
+pub async fn ssh_watch_log_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    peer: Peer<RoleServer>,
+    params: Parameters<SshWatchLogParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    policy
+        .check(&tail_command(&p.path))
+        .map_err(|v| policy_violation_error(&v))?;
+    let pattern = regex::Regex::new(&p.pattern)
+        .map_err(|e| McpError::invalid_params(format!("Invalid pattern: {}", e), None))?;
+
+    let notify_peer = peer.clone();
+    let notify = Arc::new(move |watch: &WatchInfo| {
+        let peer = notify_peer.clone();
+        let uri = watch_uri(&watch.host, &watch.id);
+        tokio::spawn(async move {
+            if let Err(e) = peer
+                .notify_resource_updated(ResourceUpdatedNotificationParam { uri })
+                .await
+            {
+                tracing::debug!(error = %e, "Failed to send resource update");
+            }
+        });
+    });
+
+    let info = session_manager
+        .watch_log(&p.host, &p.path, pattern, notify)
+        .await
+        .map_err(|e| ssh_error(&e))?;
+    if let Err(e) = peer.notify_resource_list_changed().await {
+        tracing::debug!(error = %e, "Failed to send resource list change");
+    }
+    Ok(CallToolResult::success(vec![Content::text(format!(
+        "Watch {} started: lines of {} on '{}' matching /{}/ are kept (last {}). Read or subscribe to {} for them; stop with ssh_watch_stop.",
+        info.id,
+        info.path,
+        info.host,
+        info.pattern,
+        crate::ssh::watch::MAX_WATCH_LINES,
+        watch_uri(&info.host, &info.id)
+    ))]))
+}
+
+pub async fn ssh_watch_stop_impl(
+    session_manager: &SessionManager,
+    redactor: &Redactor,
+    peer: Peer<RoleServer>,
+    params: Parameters<SshWatchStopParams>,
+) -> Result<CallToolResult, McpError> {
+    let watch = session_manager
+        .close_watch(&params.0.watch_id)
+        .await
+        .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+    if let Err(e) = peer.notify_resource_list_changed().await {
+        tracing::debug!(error = %e, "Failed to send resource list change");
+    }
+    let matches = WatchMatches::new(redactor, watch);
+    let value = serde_json::to_value(&matches)
+        .map_err(|e| McpError::internal_error(e.to_string(), None))?;
+    let mut result = CallToolResult::structured(value);
+    let mut text = format!("Watch {} stopped", matches.watch);
+    for line in &matches.lines {
+        text.push('\n');
+        text.push_str(line);
+    }
+    result.content.push(Content::text(text));
+    Ok(result)
+}
+
+pub async fn ssh_schedule_impl(
+    session_manager: &SessionManager,
+    policy: &CommandPolicy,
+    peer: Peer<RoleServer>,
+    params: Parameters<SshScheduleParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    let policy = &*session_policy(policy, session_manager, &p.host).await;
+    // Scheduled runs happen unattended, so nothing that would need approval.
+    let class = policy
+        .check(&p.command)
+        .map_err(|v| policy_violation_error(&v))?;
+    if class != CommandClass::ReadOnly {
+        return Err(McpError::invalid_params(
+            format!(
+                "Only read-only commands can be scheduled; '{}' is {}",
+                p.command, class
+            ),
+            None,
+        ));
+    }
+    if p.interval_secs < MIN_SCHEDULE_INTERVAL_SECS {
+        return Err(McpError::invalid_params(
+            format!(
+                "interval_secs must be at least {}",
+                MIN_SCHEDULE_INTERVAL_SECS
+            ),
+            None,
+        ));
+    }
+    let pattern = p
+        .pattern
+        .as_deref()
+        .map(regex::Regex::new)
+        .transpose()
+        .map_err(|e| McpError::invalid_params(format!("Invalid pattern: {}", e), None))?;
+    let notify_on_change = p.notify_on_change.unwrap_or(true);
+    if !notify_on_change && pattern.is_none() {
+        return Err(McpError::invalid_params(
+            "Nothing to report: give a pattern or leave notify_on_change on",
+            None,
+        ));
+    }
+
+    let notify_peer = peer.clone();
+    let notify = Arc::new(move |schedule: &ScheduleInfo| {
+        let peer = notify_peer.clone();
+        let uri = schedule_uri(&schedule.host, &schedule.id);
+        tokio::spawn(async move {
+            if let Err(e) = peer
+                .notify_resource_updated(ResourceUpdatedNotificationParam { uri })
+                .await
+            {
+                tracing::debug!(error = %e, "Failed to send resource update");
+            }
+        });
+    });
+
+    let spec = ScheduleSpec {
+        host: p.host.clone(),
+        command: p.command.clone(),
+        interval: std::time::Duration::from_secs(p.interval_secs),
+        notify_on_change,
+        pattern,
+    };
+    let info = session_manager
+        .schedule_command(spec, notify)
+        .await
+        .map_err(|e| ssh_error(&e))?;
+    if let Err(e) = peer.notify_resource_list_changed().await {
+        tracing::debug!(error = %e, "Failed to send resource list change");
+    }
+    Ok(CallToolResult::success(vec![Content::text(format!(
+        "Schedule {} started: `{}` runs on '{}' every {}s. Runs whose output changed are kept (last {}); read or subscribe to {} for them, and cancel with ssh_schedule_cancel.",
+        info.id,
+        info.command,
+        info.host,
+        info.interval_secs,
+        MAX_SCHEDULE_EVENTS,
+        schedule_uri(&info.host, &info.id)
+    ))]))
+}
+
+pub async fn ssh_schedule_list_impl(
+    session_manager: &SessionManager,
+) -> Result<CallToolResult, McpError> {
+    let schedules = session_manager.list_schedules().await;
+    let text = if schedules.is_empty() {
+        "No scheduled commands".to_string()
+    } else {
+        schedules
+            .iter()
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    structured_result(&serde_json::json!({ "schedules": schedules }), text)
+}
+
+pub async fn ssh_schedule_cancel_impl(
+    session_manager: &SessionManager,
+    redactor: &Redactor,
+    peer: Peer<RoleServer>,
+    params: Parameters<SshScheduleCancelParams>,
+) -> Result<CallToolResult, McpError> {
+    let report = session_manager
+        .cancel_schedule(&params.0.schedule_id)
+        .await
+        .map_err(|e| McpError::invalid_params(e.to_string(), None))?;
+    if let Err(e) = peer.notify_resource_list_changed().await {
+        tracing::debug!(error = %e, "Failed to send resource list change");
+    }
+    let report = redacted_report(redactor, report);
+    let text = format!("Schedule {} cancelled", report.schedule);
+    structured_result(&report, text)
+}
+
+pub fn ssh_health_report_impl(
+    health: &HealthMonitor,
+    redactor: &Redactor,
+    params: Parameters<SshHealthReportParams>,
+) -> Result<CallToolResult, McpError> {
+    let p = &params.0;
+    if health.is_empty() {
+        return Err(McpError::invalid_params(
+            "No health probes are configured; add [[health.probes]] to config.toml",
+            None,
+        ));
+    }
+    if let Some(ref host) = p.host
+        && health.host(host).is_none()
+    {
+        return Err(McpError::invalid_params(
+            format!("No health probes for host '{}'", host),
+            None,
+        ));
+    }
+    let mut report = health.report(p.host.as_deref());
+    if p.failing_only.unwrap_or(false) {
+        report
+            .hosts
+            .retain(|host| host.status > HealthStatus::Pending);
+    }
+    report.hosts = report
+        .hosts
+        .into_iter()
+        .map(|host| redacted_health(redactor, host))
+        .collect();
+
+    let text = if report.hosts.is_empty() {
+        "Every probed host is healthy".to_string()
+    } else {
+        report
+            .hosts
+            .iter()
+            .map(|host| {
+                let probes: Vec<String> = host
+                    .probes
+                    .iter()
+                    .map(|probe| format!("  {}", probe))
+                    .collect();
+                format!("{}: {}\n{}", host.host, host.status, probes.join("\n"))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    structured_result(&report, text)
 }
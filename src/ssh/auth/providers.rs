@@ -0,0 +1,395 @@
+//! The built-in providers: SSH agent, key files, password and keyboard-interactive.
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use tracing::Instrument;
+
+use super::{AuthContext, AuthFuture, AuthMethod, AuthProvider};
+use crate::error::SshLiaisonError;
+use crate::ssh::keys;
+use crate::ssh::passphrase::key_is_encrypted;
+
+/// Every identity of the SSH agent, unless `IdentitiesOnly` is set.
+pub struct Agent;
+
+impl AuthProvider for Agent {
+    fn method(&self) -> AuthMethod {
+        AuthMethod::Agent
+    }
+
+    fn authenticate<'a>(&'a self, ctx: &'a AuthContext<'a>) -> AuthFuture<'a> {
+        Box::pin(async move {
+            if ctx.config.identities_only {
+                tracing::debug!("IdentitiesOnly is set, skipping SSH agent");
+                return Ok(false);
+            }
+            if ctx.agent_sockets().is_empty() {
+                tracing::debug!("No SSH agent socket found");
+                return Ok(false);
+            }
+            for socket in ctx.agent_sockets() {
+                tracing::debug!(socket = %socket.display(), "Attempting SSH agent authentication");
+                let attempt = ctx
+                    .session
+                    .auth_agent(ctx.user, socket, None)
+                    .instrument(tracing::info_span!("ssh.auth", method = "agent"));
+                match attempt.await {
+                    Ok(true) => {
+                        tracing::debug!("SSH agent authentication successful");
+                        return Ok(true);
+                    }
+                    Ok(false) => {
+                        tracing::debug!(
+                            "SSH agent key accepted, the server wants another method too"
+                        );
+                    }
+                    Err(e) => {
+                        tracing::debug!(error = %e, "SSH agent authentication failed");
+                    }
+                }
+            }
+            Ok(false)
+        })
+    }
+}
+
+/// The host's `IdentityFile`s in order; a FIDO2 key goes through the agent.
+pub struct IdentityFile;
+
+impl AuthProvider for IdentityFile {
+    fn method(&self) -> AuthMethod {
+        AuthMethod::IdentityFile
+    }
+
+    fn authenticate<'a>(&'a self, ctx: &'a AuthContext<'a>) -> AuthFuture<'a> {
+        Box::pin(async move {
+            let mut failures = Vec::new();
+            for identity_file in &ctx.config.identity_files {
+                match identity_file_auth(ctx, identity_file).await {
+                    Ok(true) => return Ok(true),
+                    Ok(false) => {}
+                    Err(e) => failures.push(e.to_string()),
+                }
+            }
+            if failures.is_empty() {
+                return Ok(false);
+            }
+            Err(SshLiaisonError::Auth(failures.join(" ")).into())
+        })
+    }
+}
+
+async fn identity_file_auth(ctx: &AuthContext<'_>, identity_file: &Path) -> Result<bool> {
+    tracing::debug!(path = %identity_file.display(), "Trying identity file");
+    if !identity_file.exists() {
+        return Err(anyhow::anyhow!(
+            "Identity file not found: {}. Check that the file exists and path is correct.",
+            identity_file.display()
+        ));
+    }
+
+    #[cfg(unix)]
+    {
+        if let Ok(metadata) = std::fs::metadata(identity_file) {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = metadata.permissions().mode();
+            if mode & 0o077 != 0 {
+                tracing::warn!(
+                    path = %identity_file.display(),
+                    mode = format!("{:o}", mode & 0o777),
+                    "Identity file has insecure permissions, should be 600"
+                );
+            }
+        }
+    }
+
+    if let Some(key_type) = keys::security_key_type(identity_file) {
+        // Also with IdentitiesOnly: like OpenSSH, the agent may
+        // sign with a listed identity.
+        tracing::debug!(key_type = %key_type, "Identity file is a security key, using the agent");
+        let result = agent_key(ctx, identity_file).await;
+        if !matches!(result, Ok(true)) {
+            let reason = match result {
+                Ok(_) => "no SSH agent holds it".to_string(),
+                Err(e) => e.to_string(),
+            };
+            return Err(anyhow::anyhow!(
+                "Identity file {} is a FIDO2 security key ({}), which can only be used through the SSH agent, and {}. Load it with `ssh-add {}` and confirm presence on the key when it asks.",
+                identity_file.display(),
+                key_type,
+                reason,
+                identity_file.display()
+            ));
+        }
+        tracing::debug!("Security key authentication successful");
+        return Ok(true);
+    }
+
+    match key_file(ctx, identity_file, &ctx.config.certificate_files).await {
+        Ok(true) => {
+            tracing::debug!("Identity file authentication successful");
+            Ok(true)
+        }
+        Ok(false) => {
+            tracing::debug!("Identity file auth returned OK but not authenticated");
+            Ok(false)
+        }
+        Err(e) => {
+            tracing::debug!(error = %e, "Identity file authentication failed");
+            Err(anyhow::anyhow!(
+                "Authentication failed with identity file {}. Error: {}. Make sure the key is added to authorized_keys on the remote host.",
+                identity_file.display(),
+                e
+            ))
+        }
+    }
+}
+
+/// `~/.ssh/id_*` for hosts without an `IdentityFile` or `IdentitiesOnly`.
+pub struct DefaultKeys;
+
+impl AuthProvider for DefaultKeys {
+    fn method(&self) -> AuthMethod {
+        AuthMethod::DefaultKeys
+    }
+
+    fn authenticate<'a>(&'a self, ctx: &'a AuthContext<'a>) -> AuthFuture<'a> {
+        Box::pin(async move {
+            if !ctx.config.identity_files.is_empty() {
+                return Ok(false);
+            }
+            if ctx.config.identities_only {
+                tracing::debug!("IdentitiesOnly set but no IdentityFile specified");
+                return Ok(false);
+            }
+            tracing::debug!("Trying common SSH key files");
+            let mut skipped_security_keys: Vec<PathBuf> = Vec::new();
+            for path in keys::default_identity_paths() {
+                if !path.exists() {
+                    tracing::trace!(path = %path.display(), "Key file not found");
+                } else if let Some(key_type) = keys::security_key_type(&path) {
+                    // The agent is the only way to use these.
+                    tracing::debug!(path = %path.display(), key_type = %key_type, "Skipping security key, only usable through the SSH agent");
+                    skipped_security_keys.push(path);
+                } else {
+                    tracing::trace!(path = %path.display(), "Trying key file");
+                    match key_file(ctx, &path, &ctx.config.certificate_files).await {
+                        Ok(true) => {
+                            tracing::debug!(path = %path.display(), "Key file authentication successful");
+                            return Ok(true);
+                        }
+                        Ok(false) => {
+                            tracing::trace!(path = %path.display(), "Key returned OK but not authenticated");
+                        }
+                        Err(e) => {
+                            tracing::trace!(path = %path.display(), error = %e, "Key file auth failed");
+                        }
+                    }
+                }
+            }
+            if !skipped_security_keys.is_empty() {
+                let paths: Vec<String> = skipped_security_keys
+                    .iter()
+                    .map(|p| p.display().to_string())
+                    .collect();
+                ctx.note(format!(
+                    "FIDO2 security key(s) {} were skipped: they only work through the SSH agent, so load them with `ssh-add`.",
+                    paths.join(", ")
+                ));
+            }
+            Ok(false)
+        })
+    }
+}
+
+/// The password given with the connect request.
+pub struct Password;
+
+impl AuthProvider for Password {
+    fn method(&self) -> AuthMethod {
+        AuthMethod::Password
+    }
+
+    fn authenticate<'a>(&'a self, ctx: &'a AuthContext<'a>) -> AuthFuture<'a> {
+        Box::pin(async move {
+            let Some(password) = ctx.password else {
+                return Ok(false);
+            };
+            match ctx
+                .session
+                .auth_password(ctx.user, password)
+                .instrument(tracing::info_span!("ssh.auth", method = "password"))
+                .await
+            {
+                Ok(authenticated) => Ok(authenticated),
+                Err(e) => {
+                    tracing::debug!(error = %e, "Password authentication failed");
+                    ctx.note(format!("password: {}", e));
+                    Ok(false)
+                }
+            }
+        })
+    }
+}
+
+/// The password again, as the answer to every keyboard-interactive prompt,
+/// for servers that only take passwords through PAM.
+pub struct KeyboardInteractive;
+
+impl AuthProvider for KeyboardInteractive {
+    fn method(&self) -> AuthMethod {
+        AuthMethod::KeyboardInteractive
+    }
+
+    fn authenticate<'a>(&'a self, ctx: &'a AuthContext<'a>) -> AuthFuture<'a> {
+        Box::pin(async move {
+            let Some(password) = ctx.password else {
+                return Ok(false);
+            };
+            match ctx
+                .session
+                .auth_keyboard_interactive(ctx.user, password)
+                .instrument(tracing::info_span!(
+                    "ssh.auth",
+                    method = "keyboard-interactive"
+                ))
+                .await
+            {
+                Ok(authenticated) => Ok(authenticated),
+                Err(e) => {
+                    tracing::debug!(error = %e, "Keyboard-interactive authentication failed");
+                    ctx.note(format!("keyboard-interactive: {}", e));
+                    Ok(false)
+                }
+            }
+        })
+    }
+}
+
+/// Public key auth with a key file, asking for a passphrase if the key is
+/// encrypted, and presenting its certificate if it has one.
+///
+/// The context's passphrase takes precedence over the configured provider.
+#[tracing::instrument(
+    name = "ssh.auth",
+    skip_all,
+    fields(method = "publickey", key = %key_path.display())
+)]
+pub async fn key_file(
+    ctx: &AuthContext<'_>,
+    key_path: &Path,
+    certificate_files: &[PathBuf],
+) -> Result<bool> {
+    let encrypted = key_is_encrypted(key_path);
+    let passphrase = match ctx.passphrase {
+        Some(p) => Some(p.to_string()),
+        None if encrypted => Some(ctx.passphrase_provider.passphrase(key_path).ok_or_else(
+            || {
+                anyhow::anyhow!(
+                    "Key {} is encrypted and no passphrase is available. Pass a passphrase, set SSH_LIAISON_KEY_PASSPHRASE, or store it in the OS keyring.",
+                    key_path.display()
+                )
+            },
+        )?),
+        None => None,
+    };
+
+    let certificate = keys::certificate_for(key_path, certificate_files);
+    let cert_note = match certificate {
+        Some(ref cert_path) => certificate_note(cert_path, ctx.user),
+        None => None,
+    };
+    if let Some(ref cert_path) = certificate {
+        tracing::debug!(certificate = %cert_path.display(), "Presenting certificate");
+    }
+
+    match ctx
+        .session
+        .auth_key_file(
+            ctx.user,
+            key_path,
+            certificate.as_deref(),
+            passphrase.as_deref(),
+        )
+        .await
+    {
+        Ok(authenticated) => Ok(authenticated),
+        Err(e) if let Some(note) = cert_note => Err(anyhow::anyhow!("{} ({})", e, note)),
+        Err(e) if encrypted => Err(anyhow::anyhow!(
+            "Failed to authenticate with encrypted key {} (wrong passphrase?): {}",
+            key_path.display(),
+            e
+        )),
+        Err(e) => Err(e),
+    }
+}
+
+/// Authenticates with the agent identity matching `key_path`'s public key,
+/// the only way to use a FIDO2 key. `Ok(false)` when no agent holds it.
+#[tracing::instrument(
+    name = "ssh.auth",
+    skip_all,
+    fields(method = "agent", key = %key_path.display())
+)]
+pub async fn agent_key(ctx: &AuthContext<'_>, key_path: &Path) -> Result<bool> {
+    let blob = keys::public_key_blob(key_path).ok_or_else(|| {
+        anyhow::anyhow!("Could not read the public key of {}", key_path.display())
+    })?;
+    if ctx.agent_sockets().is_empty() {
+        anyhow::bail!("no SSH agent is available");
+    }
+    for socket in ctx.agent_sockets() {
+        match ctx.session.auth_agent(ctx.user, socket, Some(&blob)).await {
+            Ok(true) => return Ok(true),
+            Ok(false) => {
+                tracing::debug!(socket = %socket.display(), "Agent doesn't hold the key")
+            }
+            Err(e) => {
+                tracing::debug!(socket = %socket.display(), error = %e, "Agent attempt failed")
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Why a certificate may be refused: outside its validity window, or not
+/// listing `user` as a principal. `None` when nothing stands out.
+fn certificate_note(cert_path: &Path, user: &str) -> Option<String> {
+    let Some(cert) = keys::Certificate::load(cert_path) else {
+        return Some(format!(
+            "{} is not a readable OpenSSH certificate",
+            cert_path.display()
+        ));
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let note = if now >= cert.valid_before {
+        format!(
+            "certificate {} ('{}') expired {} min ago; request a new one from your CA",
+            cert_path.display(),
+            cert.key_id,
+            (now - cert.valid_before) / 60
+        )
+    } else if now < cert.valid_after {
+        format!(
+            "certificate {} ('{}') is not valid for another {} min",
+            cert_path.display(),
+            cert.key_id,
+            (cert.valid_after - now).div_ceil(60)
+        )
+    } else if !cert.principals.is_empty() && !cert.principals.iter().any(|p| p == user) {
+        format!(
+            "certificate {} ('{}') is for {}, not '{}'",
+            cert_path.display(),
+            cert.key_id,
+            cert.principals.join(", "),
+            user
+        )
+    } else {
+        return None;
+    };
+    tracing::warn!("{}", note);
+    Some(note)
+}
@@ -0,0 +1,300 @@
+//! Signed certificates and one-time passwords from HashiCorp Vault's SSH
+//! secrets engine, fetched with the `vault` CLI (so its token helper and
+//! `VAULT_TOKEN` work as usual).
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use super::providers::key_file;
+use super::{AuthContext, AuthFuture, AuthMethod, AuthProvider};
+use crate::error::SshLiaisonError;
+use crate::ssh::keys::{Certificate, key_pair, public_key_blob};
+use crate::ssh::recording::file_safe;
+
+/// Mount of the SSH secrets engine when none is configured.
+pub const DEFAULT_MOUNT: &str = "ssh";
+
+/// Certificates expiring sooner than this are fetched again.
+const MIN_REMAINING_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VaultMode {
+    /// Have the CA role sign the identity's public key.
+    #[default]
+    Sign,
+    /// Log in with a one-time password (needs vault-ssh-helper on the host).
+    Otp,
+}
+
+/// `[vault]` in the server config: defaults, and the role of each host alias.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VaultConfig {
+    /// Vault server (default: `$VAULT_ADDR`).
+    pub address: Option<String>,
+    /// SSH secrets engine mount (default `ssh`).
+    pub mount: Option<String>,
+    #[serde(default)]
+    pub hosts: BTreeMap<String, VaultHost>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VaultHost {
+    pub role: String,
+    #[serde(default)]
+    pub mode: VaultMode,
+    pub address: Option<String>,
+    pub mount: Option<String>,
+}
+
+impl VaultConfig {
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    /// Where `host_alias` gets its credentials, if it is configured.
+    pub fn target(&self, host_alias: &str) -> Option<VaultTarget> {
+        let host = self.hosts.get(host_alias)?;
+        Some(VaultTarget {
+            address: host.address.clone().or_else(|| self.address.clone()),
+            mount: host
+                .mount
+                .clone()
+                .or_else(|| self.mount.clone())
+                .unwrap_or_else(|| DEFAULT_MOUNT.to_string()),
+            role: host.role.clone(),
+            mode: host.mode,
+        })
+    }
+}
+
+/// A Vault role resolved for one host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VaultTarget {
+    pub address: Option<String>,
+    pub mount: String,
+    pub role: String,
+    pub mode: VaultMode,
+}
+
+impl VaultTarget {
+    fn sign_args(&self, public_key: &Path, principal: &str) -> Vec<String> {
+        vec![
+            "write".to_string(),
+            "-field=signed_key".to_string(),
+            format!("{}/sign/{}", self.mount, self.role),
+            format!("public_key=@{}", public_key.display()),
+            format!("valid_principals={}", principal),
+            "cert_type=user".to_string(),
+        ]
+    }
+
+    fn otp_args(&self, ip: IpAddr) -> Vec<String> {
+        vec![
+            "write".to_string(),
+            "-field=key".to_string(),
+            format!("{}/creds/{}", self.mount, self.role),
+            format!("ip={}", ip),
+        ]
+    }
+
+    async fn vault(&self, args: &[String]) -> Result<String> {
+        let mut command = tokio::process::Command::new("vault");
+        command.args(args).kill_on_drop(true);
+        if let Some(ref address) = self.address {
+            command.env("VAULT_ADDR", address);
+        }
+        let output = command
+            .output()
+            .await
+            .context("Failed to run the vault CLI; is it installed and on PATH?")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "vault {} failed: {}",
+                args[..3].join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    }
+
+    /// A certificate for `key_path` valid for `principal`, signed by Vault
+    /// unless one fetched earlier still has a while to go. It is kept in
+    /// `cache_dir`, named after the host alias.
+    pub async fn signed_certificate(
+        &self,
+        host_alias: &str,
+        key_path: &Path,
+        principal: &str,
+        cache_dir: &Path,
+    ) -> Result<PathBuf> {
+        let mut public_key = key_path.as_os_str().to_owned();
+        public_key.push(".pub");
+        let public_key = PathBuf::from(public_key);
+        if !public_key.exists() {
+            anyhow::bail!(
+                "Vault signing needs the public key {}, which doesn't exist",
+                public_key.display()
+            );
+        }
+
+        let cert_path = cache_dir.join(format!("{}-cert.pub", file_safe(host_alias)));
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        if let (Some(cert), Some(blob)) = (Certificate::load(&cert_path), public_key_blob(key_path))
+            && cert.certifies(&blob)
+            && cert.is_valid_at(now + MIN_REMAINING_SECS)
+            && cert.principals.iter().any(|p| p == principal)
+        {
+            tracing::debug!(path = %cert_path.display(), "Reusing Vault-signed certificate");
+            return Ok(cert_path);
+        }
+
+        let signed = self.vault(&self.sign_args(&public_key, principal)).await?;
+        std::fs::create_dir_all(cache_dir)
+            .with_context(|| format!("Failed to create {}", cache_dir.display()))?;
+        std::fs::write(&cert_path, format!("{}\n", signed))
+            .with_context(|| format!("Failed to write {}", cert_path.display()))?;
+        tracing::info!(host = %host_alias, role = %self.role, "Vault signed an SSH certificate");
+        Ok(cert_path)
+    }
+
+    /// A one-time password for the host at `ip`.
+    pub async fn otp(&self, ip: IpAddr) -> Result<String> {
+        let otp = self.vault(&self.otp_args(ip)).await?;
+        if otp.is_empty() {
+            anyhow::bail!("Vault returned an empty OTP for role '{}'", self.role);
+        }
+        Ok(otp)
+    }
+}
+
+/// Authenticates the hosts under `[vault.hosts]`, as an external provider.
+pub struct VaultAuth(pub VaultConfig);
+
+impl AuthProvider for VaultAuth {
+    fn method(&self) -> AuthMethod {
+        AuthMethod::External
+    }
+
+    fn authenticate<'a>(&'a self, ctx: &'a AuthContext<'a>) -> AuthFuture<'a> {
+        Box::pin(async move {
+            let Some(target) = self.0.target(ctx.host_alias) else {
+                return Ok(false);
+            };
+            match target.mode {
+                VaultMode::Sign => {
+                    let key_path =
+                        key_pair(ctx.config.identity_files.first().map(PathBuf::as_path))?;
+                    let cert_path = target
+                        .signed_certificate(ctx.host_alias, &key_path, ctx.user, &cache_dir())
+                        .await
+                        .with_context(|| {
+                            format!(
+                                "Failed to get a certificate for '{}' from Vault",
+                                ctx.host_alias
+                            )
+                        })?;
+                    key_file(ctx, &key_path, std::slice::from_ref(&cert_path))
+                        .await
+                        .map_err(|e| {
+                            SshLiaisonError::Auth(format!(
+                                "Authentication with the Vault-signed certificate {} failed: {}",
+                                cert_path.display(),
+                                e
+                            ))
+                            .into()
+                        })
+                }
+                VaultMode::Otp => {
+                    authenticate_with_otp(ctx, &target).await?;
+                    Ok(true)
+                }
+            }
+        })
+    }
+}
+
+/// Where Vault-signed certificates are kept between connects.
+fn cache_dir() -> PathBuf {
+    crate::config::config_dir()
+        .map(|dir| dir.join("vault"))
+        .unwrap_or_else(|| PathBuf::from("vault"))
+}
+
+#[tracing::instrument(name = "ssh.auth", skip_all, fields(method = "vault-otp", role = %target.role))]
+async fn authenticate_with_otp(ctx: &AuthContext<'_>, target: &VaultTarget) -> Result<()> {
+    let (user, hostname) = (ctx.user, ctx.hostname);
+    // Vault issues OTPs per target IP.
+    let ip = tokio::net::lookup_host((hostname, ctx.port))
+        .await
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .map(|addr| addr.ip())
+        .ok_or_else(|| anyhow::anyhow!("Failed to resolve {} for a Vault OTP", hostname))?;
+    let otp = target.otp(ip).await?;
+    match ctx.session.auth_password(user, &otp).await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(SshLiaisonError::Auth(format!(
+            "Vault OTP was not accepted for {}@{}",
+            user, hostname
+        ))
+        .into()),
+        Err(e) => Err(SshLiaisonError::Auth(format!(
+            "Vault OTP login failed for {}@{} (is vault-ssh-helper set up there?): {}",
+            user, hostname, e
+        ))
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vault_targets() {
+        let config: VaultConfig = toml::from_str(
+            r#"
+address = "https://vault.corp:8200"
+
+[hosts.prod-web]
+role = "ops"
+
+[hosts.legacy]
+role = "otp-role"
+mode = "otp"
+mount = "ssh-otp"
+"#,
+        )
+        .unwrap();
+
+        let web = config.target("prod-web").unwrap();
+        assert_eq!(web.address.as_deref(), Some("https://vault.corp:8200"));
+        assert_eq!(web.mode, VaultMode::Sign);
+        assert_eq!(
+            web.sign_args(Path::new("/k/id.pub"), "deploy")[2..],
+            [
+                "ssh/sign/ops",
+                "public_key=@/k/id.pub",
+                "valid_principals=deploy",
+                "cert_type=user"
+            ]
+        );
+
+        let legacy = config.target("legacy").unwrap();
+        assert_eq!(legacy.mode, VaultMode::Otp);
+        assert_eq!(
+            legacy.otp_args("10.0.0.5".parse().unwrap())[2..],
+            ["ssh-otp/creds/otp-role", "ip=10.0.0.5"]
+        );
+        assert!(config.target("other").is_none());
+    }
+}
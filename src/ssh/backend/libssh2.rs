@@ -0,0 +1,314 @@
+//! libssh2 through `async-ssh2-lite`, the default backend.
+
+use anyhow::Result;
+use async_ssh2_lite::ssh2::{FileStat, OpenFlags, OpenType, RenameFlags};
+use async_ssh2_lite::{AsyncChannel, AsyncSession, AsyncSftp, TokioTcpStream};
+use std::net::SocketAddr;
+use std::path::Path;
+use tokio::net::TcpStream;
+
+use super::{Backend, BackendFuture, FileAttrs, OpenMode, Sftp, SftpFile, SshChannel, SshSession};
+use crate::error::SshLiaisonError;
+use crate::ssh::known_hosts::{self, HostKeyChecking};
+use crate::ssh::{agent, keys};
+
+pub struct Libssh2Session(AsyncSession<TokioTcpStream>);
+
+pub async fn handshake(
+    stream: TcpStream,
+    host: &str,
+    port: u16,
+    checking: HostKeyChecking,
+) -> Result<Libssh2Session> {
+    let mut session = AsyncSession::new(stream, None)?;
+    session.handshake().await?;
+    let (blob, _) = session
+        .host_key()
+        .ok_or_else(|| anyhow::anyhow!("the server sent no host key"))?;
+    let key = keys::PublicKey::from_blob(blob)?;
+    known_hosts::verify_host_key(host, port, &key, checking).await?;
+    Ok(Libssh2Session(session))
+}
+
+fn channel_error(what: &str, e: async_ssh2_lite::Error) -> SshLiaisonError {
+    SshLiaisonError::Channel(format!("Failed to {}: {}", what, e))
+}
+
+impl SshChannel for AsyncChannel<TokioTcpStream> {
+    fn is_eof(&self) -> bool {
+        self.eof()
+    }
+
+    fn resize(&mut self, cols: u32, rows: u32) -> BackendFuture<'_, ()> {
+        Box::pin(async move {
+            self.request_pty_size(cols, rows, None, None).await?;
+            Ok(())
+        })
+    }
+
+    fn close(&mut self) -> BackendFuture<'_, ()> {
+        Box::pin(async move {
+            AsyncChannel::close(self).await?;
+            Ok(())
+        })
+    }
+
+    fn exit_status(&self) -> Option<u32> {
+        // libssh2 reports 0 until the status arrives, which is after EOF.
+        if !self.eof() {
+            return None;
+        }
+        AsyncChannel::exit_status(self)
+            .ok()
+            .map(|status| status as u32)
+    }
+}
+
+impl SshSession for Libssh2Session {
+    fn backend(&self) -> Backend {
+        Backend::Libssh2
+    }
+
+    fn is_authenticated(&self) -> bool {
+        self.0.authenticated()
+    }
+
+    fn auth_password<'a>(&'a self, user: &'a str, password: &'a str) -> BackendFuture<'a, bool> {
+        Box::pin(async move {
+            self.0.userauth_password(user, password).await?;
+            Ok(self.0.authenticated())
+        })
+    }
+
+    fn auth_keyboard_interactive<'a>(
+        &'a self,
+        user: &'a str,
+        answer: &'a str,
+    ) -> BackendFuture<'a, bool> {
+        Box::pin(async move {
+            let mut prompt = AnswerPrompt(answer);
+            self.0
+                .userauth_keyboard_interactive(user, &mut prompt)
+                .await?;
+            Ok(self.0.authenticated())
+        })
+    }
+
+    fn auth_key_file<'a>(
+        &'a self,
+        user: &'a str,
+        key: &'a Path,
+        certificate: Option<&'a Path>,
+        passphrase: Option<&'a str>,
+    ) -> BackendFuture<'a, bool> {
+        // libssh2 presents whatever public key it is given, a certificate included.
+        Box::pin(async move {
+            self.0
+                .userauth_pubkey_file(user, certificate, key, passphrase)
+                .await?;
+            Ok(self.0.authenticated())
+        })
+    }
+
+    fn auth_agent<'a>(
+        &'a self,
+        user: &'a str,
+        socket: &'a Path,
+        key: Option<&'a [u8]>,
+    ) -> BackendFuture<'a, bool> {
+        Box::pin(async move {
+            let Some(blob) = key else {
+                agent::with_socket(socket, self.0.userauth_agent(user)).await?;
+                return Ok(self.0.authenticated());
+            };
+            let attempt = async {
+                let mut agent = self.0.agent().await?;
+                agent.connect().await?;
+                agent.list_identities().await?;
+                let identity = agent.identities()?.into_iter().find(|id| id.blob() == blob);
+                let Some(identity) = identity else {
+                    return Ok::<_, anyhow::Error>(false);
+                };
+                let result = agent.userauth(user, &identity).await;
+                let _ = agent.disconnect().await;
+                result?;
+                Ok(true)
+            };
+            Ok(agent::with_socket(socket, attempt).await? && self.0.authenticated())
+        })
+    }
+
+    fn open_shell<'a>(
+        &'a self,
+        term: &'a str,
+        cols: u32,
+        rows: u32,
+    ) -> BackendFuture<'a, Box<dyn SshChannel>> {
+        Box::pin(async move {
+            let mut channel = self
+                .0
+                .channel_session()
+                .await
+                .map_err(|e| channel_error("open channel", e))?;
+            channel
+                .request_pty(term, None, Some((cols, rows, 0, 0)))
+                .await
+                .map_err(|e| channel_error("request PTY", e))?;
+            channel
+                .shell()
+                .await
+                .map_err(|e| channel_error("open shell", e))?;
+            Ok(Box::new(channel) as Box<dyn SshChannel>)
+        })
+    }
+
+    fn exec<'a>(&'a self, command: &'a str) -> BackendFuture<'a, Box<dyn SshChannel>> {
+        Box::pin(async move {
+            let mut channel = self.0.channel_session().await?;
+            channel.exec(command).await?;
+            Ok(Box::new(channel) as Box<dyn SshChannel>)
+        })
+    }
+
+    fn direct_tcpip<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+        origin: SocketAddr,
+    ) -> BackendFuture<'a, Box<dyn SshChannel>> {
+        Box::pin(async move {
+            let origin_ip = origin.ip().to_string();
+            let channel = self
+                .0
+                .channel_direct_tcpip(host, port, Some((&origin_ip, origin.port())))
+                .await?;
+            Ok(Box::new(channel) as Box<dyn SshChannel>)
+        })
+    }
+
+    fn sftp(&self) -> BackendFuture<'_, Box<dyn Sftp>> {
+        Box::pin(async move { Ok(Box::new(Libssh2Sftp(self.0.sftp().await?)) as Box<dyn Sftp>) })
+    }
+
+    fn set_keepalive(&self, secs: u32) {
+        self.0.set_keepalive(true, secs);
+    }
+
+    fn keepalive(&self) -> BackendFuture<'_, ()> {
+        Box::pin(async move {
+            self.0.keepalive_send().await?;
+            Ok(())
+        })
+    }
+
+    fn disconnect(&self) -> BackendFuture<'_, ()> {
+        Box::pin(async move {
+            self.0.disconnect(None, "Goodbye", None).await?;
+            Ok(())
+        })
+    }
+}
+
+struct Libssh2Sftp(AsyncSftp<TokioTcpStream>);
+
+impl From<FileStat> for FileAttrs {
+    fn from(stat: FileStat) -> Self {
+        Self {
+            size: stat.size,
+            uid: stat.uid,
+            gid: stat.gid,
+            perm: stat.perm,
+            atime: stat.atime,
+            mtime: stat.mtime,
+        }
+    }
+}
+
+impl From<FileAttrs> for FileStat {
+    fn from(attrs: FileAttrs) -> Self {
+        Self {
+            size: attrs.size,
+            uid: attrs.uid,
+            gid: attrs.gid,
+            perm: attrs.perm,
+            atime: attrs.atime,
+            mtime: attrs.mtime,
+        }
+    }
+}
+
+impl Sftp for Libssh2Sftp {
+    fn open<'a>(
+        &'a self,
+        path: &'a str,
+        mode: OpenMode,
+        perm: u32,
+    ) -> BackendFuture<'a, Box<dyn SftpFile>> {
+        Box::pin(async move {
+            let flags = match mode {
+                OpenMode::Read => OpenFlags::READ,
+                OpenMode::CreateNew => OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::EXCLUSIVE,
+                OpenMode::Truncate => OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
+                OpenMode::Append => OpenFlags::WRITE | OpenFlags::APPEND,
+            };
+            let file = self
+                .0
+                .open_mode(Path::new(path), flags, perm as i32, OpenType::File)
+                .await?;
+            Ok(Box::new(file) as Box<dyn SftpFile>)
+        })
+    }
+
+    fn stat<'a>(&'a self, path: &'a str) -> BackendFuture<'a, FileAttrs> {
+        Box::pin(async move { Ok(self.0.stat(Path::new(path)).await?.into()) })
+    }
+
+    fn setstat<'a>(&'a self, path: &'a str, attrs: FileAttrs) -> BackendFuture<'a, ()> {
+        Box::pin(async move { Ok(self.0.setstat(Path::new(path), attrs.into()).await?) })
+    }
+
+    fn mkdir<'a>(&'a self, path: &'a str, perm: u32) -> BackendFuture<'a, ()> {
+        Box::pin(async move { Ok(self.0.mkdir(Path::new(path), perm as i32).await?) })
+    }
+
+    fn unlink<'a>(&'a self, path: &'a str) -> BackendFuture<'a, ()> {
+        Box::pin(async move { Ok(self.0.unlink(Path::new(path)).await?) })
+    }
+
+    fn rename<'a>(&'a self, from: &'a str, to: &'a str) -> BackendFuture<'a, ()> {
+        Box::pin(async move {
+            let flags = RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE;
+            Ok(self
+                .0
+                .rename(Path::new(from), Path::new(to), Some(flags))
+                .await?)
+        })
+    }
+
+    fn readdir<'a>(&'a self, path: &'a str) -> BackendFuture<'a, Vec<(String, FileAttrs)>> {
+        Box::pin(async move {
+            let entries = self.0.readdir(Path::new(path)).await?;
+            Ok(entries
+                .into_iter()
+                .filter_map(|(entry, stat)| {
+                    let name = entry.file_name()?.to_string_lossy().into_owned();
+                    Some((name, stat.into()))
+                })
+                .collect())
+        })
+    }
+}
+
+/// Answers every keyboard-interactive prompt with the same text.
+struct AnswerPrompt<'a>(&'a str);
+
+impl async_ssh2_lite::ssh2::KeyboardInteractivePrompt for AnswerPrompt<'_> {
+    fn prompt<'b>(
+        &mut self,
+        _username: &str,
+        _instructions: &str,
+        prompts: &[async_ssh2_lite::ssh2::Prompt<'b>],
+    ) -> Vec<String> {
+        prompts.iter().map(|_| self.0.to_string()).collect()
+    }
+}
@@ -0,0 +1,270 @@
+//! The SSH library under a session. libssh2 (the `libssh2` feature, on by
+//! default) is used unless the pure-Rust russh client (the `russh` feature)
+//! is picked with `backend = "russh"`. Shells, exec channels, tunnels and
+//! SFTP only go through [`SshSession`], [`SshChannel`] and [`Sftp`], so the
+//! marker protocol and the session manager work the same on either.
+
+#[cfg(not(any(feature = "libssh2", feature = "russh")))]
+compile_error!("build with the `libssh2` or the `russh` feature, or both");
+
+#[cfg(feature = "libssh2")]
+pub mod libssh2;
+#[cfg(feature = "russh")]
+pub mod russh;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+use super::known_hosts::HostKeyChecking;
+
+pub type BackendFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T>> + Send + 'a>>;
+
+/// Which SSH implementation new sessions use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// libssh2, through `async-ssh2-lite`; needs the `libssh2` feature.
+    Libssh2,
+    /// russh, without C dependencies; needs the `russh` feature.
+    Russh,
+}
+
+/// libssh2 when the build has it, russh otherwise.
+impl Default for Backend {
+    fn default() -> Self {
+        if cfg!(feature = "libssh2") {
+            Self::Libssh2
+        } else {
+            Self::Russh
+        }
+    }
+}
+
+impl Backend {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Libssh2 => "libssh2",
+            Self::Russh => "russh",
+        }
+    }
+
+    /// Whether this build can open sessions with the backend.
+    pub fn is_available(self) -> bool {
+        match self {
+            Self::Libssh2 => cfg!(feature = "libssh2"),
+            Self::Russh => cfg!(feature = "russh"),
+        }
+    }
+}
+
+impl std::fmt::Display for Backend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// An open channel: a shell, an exec'd command, or a forwarded connection.
+/// Reads return the remote side's stdout and 0 once it sent EOF.
+pub trait SshChannel: AsyncRead + AsyncWrite + Send + Unpin {
+    /// Whether the remote side sent EOF.
+    fn is_eof(&self) -> bool;
+
+    /// Tells the channel's PTY its new size.
+    fn resize(&mut self, cols: u32, rows: u32) -> BackendFuture<'_, ()>;
+
+    fn close(&mut self) -> BackendFuture<'_, ()>;
+
+    /// The command's exit status, once the remote side reported it.
+    fn exit_status(&self) -> Option<u32>;
+}
+
+/// What a remote file is opened for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    Read,
+    /// Writes a new file; fails if it exists.
+    CreateNew,
+    /// Writes the file from the start, creating or emptying it.
+    Truncate,
+    /// Writes at the end of an existing file.
+    Append,
+}
+
+/// Size, owner, mode and times (Unix seconds) of a remote file. `setstat`
+/// leaves the fields that are `None` alone.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileAttrs {
+    pub size: Option<u64>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub perm: Option<u32>,
+    pub atime: Option<u64>,
+    pub mtime: Option<u64>,
+}
+
+impl FileAttrs {
+    const TYPE_MASK: u32 = 0o170000;
+
+    pub fn is_dir(&self) -> bool {
+        self.perm
+            .is_some_and(|perm| perm & Self::TYPE_MASK == 0o040000)
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.perm
+            .is_some_and(|perm| perm & Self::TYPE_MASK == 0o100000)
+    }
+}
+
+/// An open remote file.
+pub trait SftpFile: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> SftpFile for T {}
+
+/// The SFTP subsystem of a session. Paths are the server's.
+pub trait Sftp: Send + Sync {
+    /// Opens `path`; files it creates get the permission bits `perm`.
+    fn open<'a>(
+        &'a self,
+        path: &'a str,
+        mode: OpenMode,
+        perm: u32,
+    ) -> BackendFuture<'a, Box<dyn SftpFile>>;
+
+    fn stat<'a>(&'a self, path: &'a str) -> BackendFuture<'a, FileAttrs>;
+
+    fn setstat<'a>(&'a self, path: &'a str, attrs: FileAttrs) -> BackendFuture<'a, ()>;
+
+    fn mkdir<'a>(&'a self, path: &'a str, perm: u32) -> BackendFuture<'a, ()>;
+
+    fn unlink<'a>(&'a self, path: &'a str) -> BackendFuture<'a, ()>;
+
+    /// Renames `from` over `to`. Servers that won't replace an existing file
+    /// (OpenSSH without the POSIX rename extension) fail.
+    fn rename<'a>(&'a self, from: &'a str, to: &'a str) -> BackendFuture<'a, ()>;
+
+    /// Names and attributes of a directory's entries, `.` and `..` included
+    /// when the server lists them.
+    fn readdir<'a>(&'a self, path: &'a str) -> BackendFuture<'a, Vec<(String, FileAttrs)>>;
+}
+
+/// A connected session, authenticated by the `auth_*` calls.
+///
+/// Auth calls return `Ok(true)` once the session is authenticated and
+/// `Ok(false)` when the server wants more (partial success). A refusal is an
+/// error, so providers can report why.
+pub trait SshSession: Send + Sync {
+    fn backend(&self) -> Backend;
+
+    /// Whether one of the auth calls completed authentication.
+    fn is_authenticated(&self) -> bool;
+
+    fn auth_password<'a>(&'a self, user: &'a str, password: &'a str) -> BackendFuture<'a, bool>;
+
+    /// Keyboard-interactive auth, answering every prompt with `answer`.
+    fn auth_keyboard_interactive<'a>(
+        &'a self,
+        user: &'a str,
+        answer: &'a str,
+    ) -> BackendFuture<'a, bool>;
+
+    /// Public key auth with a key file, presenting `certificate` if given.
+    fn auth_key_file<'a>(
+        &'a self,
+        user: &'a str,
+        key: &'a Path,
+        certificate: Option<&'a Path>,
+        passphrase: Option<&'a str>,
+    ) -> BackendFuture<'a, bool>;
+
+    /// Public key auth with the identities of the agent at `socket`, or only
+    /// the one whose public key blob is `key`; `Ok(false)` also when the agent
+    /// doesn't hold that key.
+    fn auth_agent<'a>(
+        &'a self,
+        user: &'a str,
+        socket: &'a Path,
+        key: Option<&'a [u8]>,
+    ) -> BackendFuture<'a, bool>;
+
+    /// A shell on a PTY of `cols`x`rows`.
+    fn open_shell<'a>(
+        &'a self,
+        term: &'a str,
+        cols: u32,
+        rows: u32,
+    ) -> BackendFuture<'a, Box<dyn SshChannel>>;
+
+    /// `command` on a channel without a PTY.
+    fn exec<'a>(&'a self, command: &'a str) -> BackendFuture<'a, Box<dyn SshChannel>>;
+
+    /// A `direct-tcpip` channel to `host:port` on behalf of `origin`.
+    fn direct_tcpip<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+        origin: SocketAddr,
+    ) -> BackendFuture<'a, Box<dyn SshChannel>>;
+
+    /// The SFTP subsystem; fails when the server has none, and file reads,
+    /// writes and listings go through the shell instead.
+    fn sftp(&self) -> BackendFuture<'_, Box<dyn Sftp>>;
+
+    /// Has the library send keepalives on its own every `secs`, where it can.
+    fn set_keepalive(&self, secs: u32);
+
+    /// Sends a keepalive; fails once the connection is gone.
+    fn keepalive(&self) -> BackendFuture<'_, ()>;
+
+    fn disconnect(&self) -> BackendFuture<'_, ()>;
+}
+
+/// Completes the SSH handshake over `stream` with `backend`, checking the
+/// server's key for `host`:`port` against `known_hosts` under `checking`.
+pub async fn handshake(
+    backend: Backend,
+    stream: TcpStream,
+    host: &str,
+    port: u16,
+    checking: HostKeyChecking,
+) -> Result<Box<dyn SshSession>> {
+    match backend {
+        #[cfg(feature = "libssh2")]
+        Backend::Libssh2 => Ok(Box::new(
+            libssh2::handshake(stream, host, port, checking).await?,
+        )),
+        #[cfg(feature = "russh")]
+        Backend::Russh => Ok(Box::new(
+            russh::handshake(stream, host, port, checking).await?,
+        )),
+        #[allow(unreachable_patterns)]
+        backend => {
+            let _ = (stream, host, port, checking);
+            anyhow::bail!(
+                "this build has no {} backend; rebuild with `--features {}`",
+                backend,
+                backend
+            )
+        }
+    }
+}
+
+/// Whether `err` came from the SSH library rather than from this crate.
+pub fn is_protocol_error(err: &(dyn std::error::Error + 'static)) -> bool {
+    #[cfg(feature = "libssh2")]
+    if err.is::<async_ssh2_lite::Error>() {
+        return true;
+    }
+    #[cfg(feature = "russh")]
+    if err.is::<::russh::Error>() {
+        return true;
+    }
+    let _ = err;
+    false
+}
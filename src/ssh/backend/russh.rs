@@ -0,0 +1,600 @@
+//! russh, a pure-Rust SSH client (`--features russh`), with SFTP through
+//! russh-sftp.
+
+use anyhow::Result;
+use russh::client::{self, AuthResult, Handle, KeyboardInteractiveAuthResponse};
+use russh::keys::{self as ssh_keys, PrivateKeyWithHashAlg};
+use russh::{ChannelMsg, ChannelReadHalf, ChannelWriteHalf, Disconnect, MethodSet};
+use russh_sftp::client::SftpSession;
+use russh_sftp::protocol::{FileAttributes, OpenFlags};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::sync::RwLock;
+
+use super::{Backend, BackendFuture, FileAttrs, OpenMode, Sftp, SftpFile, SshChannel, SshSession};
+use crate::error::SshLiaisonError;
+use crate::ssh::keys;
+use crate::ssh::known_hosts::{self, HostKeyChecking};
+
+/// Bytes a channel's reader may fall behind before the server is held back.
+const CHANNEL_PIPE_BYTES: usize = 64 * 1024;
+
+/// Keyboard-interactive rounds answered before giving up on a server that
+/// keeps asking.
+const MAX_PROMPT_ROUNDS: usize = 8;
+
+pub struct RusshSession {
+    /// Auth needs `&mut`; channels are opened concurrently under the read lock.
+    handle: RwLock<Handle<Client>>,
+    authenticated: AtomicBool,
+}
+
+pub struct Client {
+    host: String,
+    port: u16,
+    checking: HostKeyChecking,
+}
+
+impl client::Handler for Client {
+    type Error = russh::Error;
+
+    async fn check_server_key(
+        &mut self,
+        server_public_key: &ssh_keys::PublicKey,
+    ) -> Result<bool, Self::Error> {
+        Ok(host_key_allowed(&self.host, self.port, server_public_key, self.checking).await)
+    }
+}
+
+/// Whether the server's key passes [`known_hosts::verify_host_key`]; the
+/// reason it didn't is logged, as russh only reports `UnknownKey`.
+async fn host_key_allowed(
+    host: &str,
+    port: u16,
+    key: &ssh_keys::PublicKey,
+    checking: HostKeyChecking,
+) -> bool {
+    let name = known_hosts::host_key_name(host, port);
+    let Some(key) = key
+        .to_openssh()
+        .ok()
+        .and_then(|line| keys::PublicKey::parse(&line).ok())
+    else {
+        tracing::warn!(host = %name, "Unreadable host key, refused");
+        return false;
+    };
+    match known_hosts::verify_host_key(host, port, &key, checking).await {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::warn!(host = %name, error = %format!("{:#}", e), "Host key refused");
+            false
+        }
+    }
+}
+
+pub async fn handshake(
+    stream: TcpStream,
+    host: &str,
+    port: u16,
+    checking: HostKeyChecking,
+) -> Result<RusshSession> {
+    let handler = Client {
+        host: host.to_string(),
+        port,
+        checking,
+    };
+    let handle = client::connect_stream(Arc::new(client::Config::default()), stream, handler)
+        .await
+        .map_err(|e| match e {
+            russh::Error::UnknownKey => anyhow::anyhow!(
+                "the host key was refused: it is not in known_hosts, differs from the one \
+                 there, or is revoked"
+            ),
+            e => e.into(),
+        })?;
+    Ok(RusshSession {
+        handle: RwLock::new(handle),
+        authenticated: AtomicBool::new(false),
+    })
+}
+
+/// `Ok(true)` on success, `Ok(false)` when the server wants another method.
+fn auth_outcome(result: AuthResult) -> Result<bool> {
+    match result {
+        AuthResult::Success => Ok(true),
+        AuthResult::Failure {
+            partial_success: true,
+            ..
+        } => Ok(false),
+        AuthResult::Failure {
+            remaining_methods, ..
+        } => Err(refused(&remaining_methods)),
+    }
+}
+
+fn refused(remaining: &MethodSet) -> anyhow::Error {
+    let methods: Vec<&str> = remaining.iter().map(<&str>::from).collect();
+    anyhow::anyhow!("refused by the server (it takes: {})", methods.join(", "))
+}
+
+fn channel_error(what: &str, e: russh::Error) -> SshLiaisonError {
+    SshLiaisonError::Channel(format!("Failed to {}: {}", what, e))
+}
+
+impl RusshSession {
+    /// Remembers a successful auth for `is_authenticated`.
+    fn settle(&self, outcome: Result<bool>) -> Result<bool> {
+        if let Ok(true) = outcome {
+            self.authenticated.store(true, Ordering::Relaxed);
+        }
+        outcome
+    }
+
+    async fn keyboard_interactive(&self, user: &str, answer: &str) -> Result<bool> {
+        let mut handle = self.handle.write().await;
+        let mut response = handle
+            .authenticate_keyboard_interactive_start(user, None::<String>)
+            .await?;
+        for _ in 0..MAX_PROMPT_ROUNDS {
+            match response {
+                KeyboardInteractiveAuthResponse::Success => return Ok(true),
+                KeyboardInteractiveAuthResponse::Failure {
+                    partial_success: true,
+                    ..
+                } => return Ok(false),
+                KeyboardInteractiveAuthResponse::Failure {
+                    remaining_methods, ..
+                } => return Err(refused(&remaining_methods)),
+                KeyboardInteractiveAuthResponse::InfoRequest { prompts, .. } => {
+                    let answers = prompts.iter().map(|_| answer.to_string()).collect();
+                    response = handle
+                        .authenticate_keyboard_interactive_respond(answers)
+                        .await?;
+                }
+            }
+        }
+        anyhow::bail!("the server kept prompting")
+    }
+
+    /// Offers the agent's identities, or only the one whose blob is `key`.
+    #[cfg(unix)]
+    async fn agent(&self, user: &str, socket: &Path, key: Option<&[u8]>) -> Result<bool> {
+        let mut agent = ssh_keys::agent::client::AgentClient::connect_uds(socket).await?;
+        let identities = agent.request_identities().await?;
+        let mut handle = self.handle.write().await;
+        let mut offered = false;
+        for identity in identities {
+            if let Some(blob) = key
+                && identity.to_bytes().ok().as_deref() != Some(blob)
+            {
+                continue;
+            }
+            offered = true;
+            let hash = if identity.algorithm().is_rsa() {
+                handle.best_supported_rsa_hash().await?.flatten()
+            } else {
+                None
+            };
+            match handle
+                .authenticate_publickey_with(user, identity, hash, &mut agent)
+                .await?
+            {
+                AuthResult::Success => return Ok(true),
+                AuthResult::Failure {
+                    partial_success: true,
+                    ..
+                } => return Ok(false),
+                AuthResult::Failure { .. } => {}
+            }
+        }
+        match (offered, key) {
+            (false, Some(_)) => Ok(false),
+            (false, None) => anyhow::bail!("the agent holds no keys"),
+            (true, _) => anyhow::bail!("the server accepted none of the agent's keys"),
+        }
+    }
+}
+
+impl SshSession for RusshSession {
+    fn backend(&self) -> Backend {
+        Backend::Russh
+    }
+
+    fn is_authenticated(&self) -> bool {
+        self.authenticated.load(Ordering::Relaxed)
+    }
+
+    fn auth_password<'a>(&'a self, user: &'a str, password: &'a str) -> BackendFuture<'a, bool> {
+        Box::pin(async move {
+            let mut handle = self.handle.write().await;
+            self.settle(auth_outcome(
+                handle.authenticate_password(user, password).await?,
+            ))
+        })
+    }
+
+    fn auth_keyboard_interactive<'a>(
+        &'a self,
+        user: &'a str,
+        answer: &'a str,
+    ) -> BackendFuture<'a, bool> {
+        Box::pin(async move { self.settle(self.keyboard_interactive(user, answer).await) })
+    }
+
+    fn auth_key_file<'a>(
+        &'a self,
+        user: &'a str,
+        key: &'a Path,
+        certificate: Option<&'a Path>,
+        passphrase: Option<&'a str>,
+    ) -> BackendFuture<'a, bool> {
+        Box::pin(async move {
+            let key = Arc::new(ssh_keys::load_secret_key(key, passphrase)?);
+            let mut handle = self.handle.write().await;
+            let result = match certificate {
+                Some(certificate) => {
+                    let certificate = ssh_keys::load_openssh_certificate(certificate)?;
+                    handle
+                        .authenticate_openssh_cert(user, key, certificate)
+                        .await?
+                }
+                None => {
+                    let hash = if key.algorithm().is_rsa() {
+                        handle.best_supported_rsa_hash().await?.flatten()
+                    } else {
+                        None
+                    };
+                    handle
+                        .authenticate_publickey(user, PrivateKeyWithHashAlg::new(key, hash))
+                        .await?
+                }
+            };
+            self.settle(auth_outcome(result))
+        })
+    }
+
+    #[cfg(unix)]
+    fn auth_agent<'a>(
+        &'a self,
+        user: &'a str,
+        socket: &'a Path,
+        key: Option<&'a [u8]>,
+    ) -> BackendFuture<'a, bool> {
+        Box::pin(async move { self.settle(self.agent(user, socket, key).await) })
+    }
+
+    #[cfg(not(unix))]
+    fn auth_agent<'a>(
+        &'a self,
+        _user: &'a str,
+        _socket: &'a Path,
+        _key: Option<&'a [u8]>,
+    ) -> BackendFuture<'a, bool> {
+        Box::pin(async { anyhow::bail!("the russh backend only talks to agents on Unix sockets") })
+    }
+
+    fn open_shell<'a>(
+        &'a self,
+        term: &'a str,
+        cols: u32,
+        rows: u32,
+    ) -> BackendFuture<'a, Box<dyn SshChannel>> {
+        Box::pin(async move {
+            let channel = self
+                .handle
+                .read()
+                .await
+                .channel_open_session()
+                .await
+                .map_err(|e| channel_error("open channel", e))?;
+            channel
+                .request_pty(true, term, cols, rows, 0, 0, &[])
+                .await
+                .map_err(|e| channel_error("request PTY", e))?;
+            channel
+                .request_shell(true)
+                .await
+                .map_err(|e| channel_error("open shell", e))?;
+            Ok(Box::new(RusshChannel::new(channel)) as Box<dyn SshChannel>)
+        })
+    }
+
+    fn exec<'a>(&'a self, command: &'a str) -> BackendFuture<'a, Box<dyn SshChannel>> {
+        Box::pin(async move {
+            let channel = self.handle.read().await.channel_open_session().await?;
+            channel.exec(true, command).await?;
+            Ok(Box::new(RusshChannel::new(channel)) as Box<dyn SshChannel>)
+        })
+    }
+
+    fn direct_tcpip<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+        origin: SocketAddr,
+    ) -> BackendFuture<'a, Box<dyn SshChannel>> {
+        Box::pin(async move {
+            let channel = self
+                .handle
+                .read()
+                .await
+                .channel_open_direct_tcpip(
+                    host,
+                    port.into(),
+                    origin.ip().to_string(),
+                    origin.port().into(),
+                )
+                .await?;
+            Ok(Box::new(RusshChannel::new(channel)) as Box<dyn SshChannel>)
+        })
+    }
+
+    fn sftp(&self) -> BackendFuture<'_, Box<dyn Sftp>> {
+        Box::pin(async move {
+            let channel = self.handle.read().await.channel_open_session().await?;
+            channel.request_subsystem(true, "sftp").await?;
+            let session = SftpSession::new(channel.into_stream()).await?;
+            Ok(Box::new(RusshSftp(session)) as Box<dyn Sftp>)
+        })
+    }
+
+    fn set_keepalive(&self, _secs: u32) {
+        // russh only sends keepalives of its own when configured at connect;
+        // `keepalive` is called on the interval instead.
+    }
+
+    fn keepalive(&self) -> BackendFuture<'_, ()> {
+        Box::pin(async move {
+            let handle = self.handle.read().await;
+            if handle.is_closed() {
+                anyhow::bail!("the connection is closed");
+            }
+            handle.send_keepalive(true).await?;
+            Ok(())
+        })
+    }
+
+    fn disconnect(&self) -> BackendFuture<'_, ()> {
+        Box::pin(async move {
+            self.handle
+                .read()
+                .await
+                .disconnect(Disconnect::ByApplication, "Goodbye", "")
+                .await?;
+            Ok(())
+        })
+    }
+}
+
+/// A russh channel as a byte stream. What the server sends, stderr
+/// included, is pumped into a pipe, which keeps the write half free for
+/// resizing and closing.
+struct RusshChannel {
+    reader: DuplexStream,
+    writer: Pin<Box<dyn AsyncWrite + Send>>,
+    /// Taken when the channel is dropped, to close it.
+    control: Option<ChannelWriteHalf<client::Msg>>,
+    exit_status: Arc<OnceLock<u32>>,
+    eof: bool,
+}
+
+impl RusshChannel {
+    fn new(channel: russh::Channel<client::Msg>) -> Self {
+        let (read_half, write_half) = channel.split();
+        let (reader, pipe) = tokio::io::duplex(CHANNEL_PIPE_BYTES);
+        let exit_status = Arc::new(OnceLock::new());
+        tokio::spawn(pump(read_half, pipe, Arc::clone(&exit_status)));
+        Self {
+            reader,
+            writer: Box::pin(write_half.make_writer()),
+            control: Some(write_half),
+            exit_status,
+            eof: false,
+        }
+    }
+}
+
+/// Copies the channel's data and stderr into `pipe` and records the exit
+/// status, until the server closes the channel. The pipe ends at EOF; the
+/// status usually comes after it.
+async fn pump(mut read_half: ChannelReadHalf, mut pipe: DuplexStream, status: Arc<OnceLock<u32>>) {
+    let mut open = true;
+    while let Some(msg) = read_half.wait().await {
+        let data = match msg {
+            ChannelMsg::Data { data } | ChannelMsg::ExtendedData { data, .. } => data,
+            ChannelMsg::ExitStatus { exit_status } => {
+                let _ = status.set(exit_status);
+                continue;
+            }
+            ChannelMsg::Eof => {
+                open = false;
+                let _ = pipe.shutdown().await;
+                continue;
+            }
+            ChannelMsg::Close => break,
+            _ => continue,
+        };
+        if open && pipe.write_all(&data).await.is_err() {
+            open = false;
+        }
+    }
+    if open {
+        let _ = pipe.shutdown().await;
+    }
+}
+
+impl AsyncRead for RusshChannel {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.reader).poll_read(cx, buf);
+        if matches!(poll, Poll::Ready(Ok(())))
+            && buf.filled().len() == filled
+            && buf.remaining() > 0
+        {
+            self.eof = true;
+        }
+        poll
+    }
+}
+
+impl AsyncWrite for RusshChannel {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.writer.as_mut().poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.writer.as_mut().poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.writer.as_mut().poll_shutdown(cx)
+    }
+}
+
+impl SshChannel for RusshChannel {
+    fn is_eof(&self) -> bool {
+        self.eof
+    }
+
+    fn resize(&mut self, cols: u32, rows: u32) -> BackendFuture<'_, ()> {
+        Box::pin(async move {
+            if let Some(ref control) = self.control {
+                control.window_change(cols, rows, 0, 0).await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn close(&mut self) -> BackendFuture<'_, ()> {
+        Box::pin(async move {
+            if let Some(control) = self.control.take() {
+                control.close().await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn exit_status(&self) -> Option<u32> {
+        self.exit_status.get().copied()
+    }
+}
+
+impl Drop for RusshChannel {
+    fn drop(&mut self) {
+        if let Some(control) = self.control.take()
+            && let Ok(runtime) = tokio::runtime::Handle::try_current()
+        {
+            runtime.spawn(async move {
+                let _ = control.close().await;
+            });
+        }
+    }
+}
+
+struct RusshSftp(SftpSession);
+
+impl From<FileAttributes> for FileAttrs {
+    fn from(attrs: FileAttributes) -> Self {
+        Self {
+            size: attrs.size,
+            uid: attrs.uid,
+            gid: attrs.gid,
+            perm: attrs.permissions,
+            atime: attrs.atime.map(u64::from),
+            mtime: attrs.mtime.map(u64::from),
+        }
+    }
+}
+
+impl From<FileAttrs> for FileAttributes {
+    fn from(attrs: FileAttrs) -> Self {
+        // SFTP v3 carries 32-bit times.
+        let time = |secs: Option<u64>| secs.and_then(|secs| u32::try_from(secs).ok());
+        Self {
+            size: attrs.size,
+            uid: attrs.uid,
+            gid: attrs.gid,
+            permissions: attrs.perm,
+            atime: time(attrs.atime),
+            mtime: time(attrs.mtime),
+            ..Self::empty()
+        }
+    }
+}
+
+impl Sftp for RusshSftp {
+    fn open<'a>(
+        &'a self,
+        path: &'a str,
+        mode: OpenMode,
+        perm: u32,
+    ) -> BackendFuture<'a, Box<dyn SftpFile>> {
+        Box::pin(async move {
+            let flags = match mode {
+                OpenMode::Read => OpenFlags::READ,
+                OpenMode::CreateNew => OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::EXCLUDE,
+                OpenMode::Truncate => OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
+                OpenMode::Append => OpenFlags::WRITE | OpenFlags::APPEND,
+            };
+            let attrs = FileAttributes {
+                permissions: (mode != OpenMode::Read).then_some(perm),
+                ..FileAttributes::empty()
+            };
+            let file = self
+                .0
+                .open_with_flags_and_attributes(path, flags, attrs)
+                .await?;
+            Ok(Box::new(file) as Box<dyn SftpFile>)
+        })
+    }
+
+    fn stat<'a>(&'a self, path: &'a str) -> BackendFuture<'a, FileAttrs> {
+        Box::pin(async move { Ok(self.0.metadata(path).await?.into()) })
+    }
+
+    fn setstat<'a>(&'a self, path: &'a str, attrs: FileAttrs) -> BackendFuture<'a, ()> {
+        Box::pin(async move { Ok(self.0.set_metadata(path, attrs.into()).await?) })
+    }
+
+    fn mkdir<'a>(&'a self, path: &'a str, perm: u32) -> BackendFuture<'a, ()> {
+        Box::pin(async move {
+            self.0.create_dir(path).await?;
+            let attrs = FileAttrs {
+                perm: Some(perm),
+                ..FileAttrs::default()
+            };
+            Ok(self.0.set_metadata(path, attrs.into()).await?)
+        })
+    }
+
+    fn unlink<'a>(&'a self, path: &'a str) -> BackendFuture<'a, ()> {
+        Box::pin(async move { Ok(self.0.remove_file(path).await?) })
+    }
+
+    fn rename<'a>(&'a self, from: &'a str, to: &'a str) -> BackendFuture<'a, ()> {
+        Box::pin(async move { Ok(self.0.rename(from, to).await?) })
+    }
+
+    fn readdir<'a>(&'a self, path: &'a str) -> BackendFuture<'a, Vec<(String, FileAttrs)>> {
+        Box::pin(async move {
+            let entries = self.0.read_dir(path).await?;
+            Ok(entries
+                .map(|entry| (entry.file_name(), entry.metadata().into()))
+                .collect())
+        })
+    }
+}
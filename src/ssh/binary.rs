@@ -0,0 +1,159 @@
+//! Binary command output (`cat image.png`, `tar cz`). It's taken from the
+//! bytes the shell printed rather than from decoded text, which would turn
+//! it into replacement characters.
+
+use anyhow::{Context, Result};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::recording::file_safe;
+
+/// Binary output up to this size is returned inline, larger output is
+/// written to a local file.
+pub const INLINE_LIMIT: usize = 64 * 1024;
+
+/// Whether `data` is binary rather than text: it has a NUL byte, or more
+/// than one in ten bytes are control characters text doesn't use.
+pub fn looks_binary(data: &[u8]) -> bool {
+    if data.contains(&0) {
+        return true;
+    }
+    let controls = data
+        .iter()
+        .filter(|&&b| b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x07 | 0x08 | 0x0c | 0x1b))
+        .count();
+    controls * 10 > data.len()
+}
+
+/// What a command printed, out of everything `raw` read from the shell:
+/// from the line after the echoed command line to the completion marker, or
+/// to the end when it was never read. The echo carries `marker:$?`, the
+/// completion `marker:<status>`.
+pub fn payload(raw: &[u8], marker: &str) -> Vec<u8> {
+    let marker = marker.as_bytes();
+    let mut echo = None;
+    let mut end = raw.len();
+    let occurrences = raw
+        .windows(marker.len())
+        .enumerate()
+        .filter(|(_, w)| *w == marker);
+    for (pos, _) in occurrences {
+        match raw.get(pos + marker.len() + 1) {
+            Some(b'0'..=b'9' | b'-') => end = pos,
+            _ if echo.is_none() => echo = Some(pos),
+            _ => {}
+        }
+    }
+    let echo = echo.filter(|&pos| pos < end).unwrap_or(0);
+    let start = raw[echo..end]
+        .iter()
+        .position(|&b| b == b'\n')
+        .map_or(echo, |pos| echo + pos + 1);
+    undo_onlcr(&raw[start..end])
+}
+
+/// Undoes the terminal's `\n` to `\r\n` translation. Every newline got a
+/// carriage return in front, so dropping one before each newline restores
+/// the bytes exactly.
+pub fn undo_onlcr(data: &[u8]) -> Vec<u8> {
+    let mut restored = Vec::with_capacity(data.len());
+    for (i, &b) in data.iter().enumerate() {
+        if b == b'\r' && data.get(i + 1) == Some(&b'\n') {
+            continue;
+        }
+        restored.push(b);
+    }
+    restored
+}
+
+/// MIME type from the magic number, `application/octet-stream` otherwise.
+pub fn mime_type(data: &[u8]) -> &'static str {
+    const MAGIC: [(&[u8], &str); 8] = [
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF8", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"BZh", "application/x-bzip2"),
+        (b"\xfd7zXZ\x00", "application/x-xz"),
+    ];
+    if data.len() > 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        return "image/webp";
+    }
+    MAGIC
+        .iter()
+        .find(|(magic, _)| data.starts_with(magic))
+        .map_or("application/octet-stream", |(_, mime)| mime)
+}
+
+/// Where binary output too large to return inline is written.
+pub fn spill_dir() -> PathBuf {
+    std::env::temp_dir().join("ssh-liaison")
+}
+
+/// Writes `data` to `<dir>/<host>-<unix time in ms>.bin`, readable only by the user.
+pub fn spill(dir: &Path, host_alias: &str, data: &[u8]) -> Result<PathBuf> {
+    std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let path = dir.join(format!("{}-{}.bin", file_safe(host_alias), now.as_millis()));
+    let mut options = OpenOptions::new();
+    options.create_new(true).write(true);
+    // The output may hold anything, and isn't redacted.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(&path)
+        .and_then(|mut file| file.write_all(data))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    tracing::info!(host = %host_alias, path = %path.display(), bytes = data.len(), "Binary output saved");
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binary_payload() {
+        let png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR\r\n";
+        assert!(looks_binary(png));
+        assert!(!looks_binary(b"total 4\r\n\x1b[01;34mdir\x1b[0m\r\n"));
+        assert_eq!(mime_type(png), "image/png");
+        assert_eq!(mime_type(b"\x00\x01"), "application/octet-stream");
+
+        // The PTY turned each `\n` into `\r\n`, and nothing ends the last line.
+        let marker = "__M__";
+        let mut raw = b"$ cat a.png; echo __M__:$?\r\n".to_vec();
+        raw.extend_from_slice(b"\x89PNG\r\r\n\x1a\r\n\x00\x00\x00\rIHDR\r\r\n");
+        raw.extend_from_slice(b"__M__:0\r\n$ ");
+        assert_eq!(payload(&raw, marker), png);
+
+        // Cut off before the marker: everything after the echo.
+        assert_eq!(
+            payload(b"$ tar cz .; echo __M__:$?\r\n\x1f\x8b\x08\x00", marker),
+            b"\x1f\x8b\x08\x00"
+        );
+    }
+
+    #[test]
+    fn test_spill_binary_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = spill(&dir.path().join("out"), "web/1", b"\x00\x01\x02").unwrap();
+        assert!(
+            path.file_name()
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .starts_with("web_1-")
+        );
+        assert_eq!(std::fs::read(&path).unwrap(), b"\x00\x01\x02");
+    }
+}
@@ -1,22 +1,186 @@
 use anyhow::Result;
-use async_ssh2_lite::AsyncChannel;
-use async_ssh2_lite::TokioTcpStream;
+use encoding_rs::{Decoder, Encoding, UTF_8};
 use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::sync::OnceLock;
 use std::time::{Duration, Instant};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
 
-const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+use super::ansi;
+use super::backend::SshChannel;
+use super::binary;
+use super::charset::{self, Charset};
+use super::interactive::{self, InteractiveCommand};
+use super::recording::Recorder;
+use super::shell::ShellKind;
+use crate::error::SshLiaisonError;
+use crate::redact;
+
+const COMMAND_TIMEOUT_SECS: u64 = 30;
 const READ_BUFFER_SIZE: usize = 4096;
 const READ_TIMEOUT_MS: u64 = 100;
-const NO_DATA_THRESHOLD: u32 = 10;
-const IDLE_TIMEOUT_MS: u64 = 500;
-const CONTINUE_READ_ATTEMPTS: u32 = 10;
-const CONTINUE_READ_TIMEOUT_MS: u64 = 100;
-const CONTINUE_READ_MAX_FAILURES: u32 = 3;
 const SLEEP_ON_EOF_MS: u64 = 50;
 const SLEEP_ON_ERROR_MS: u64 = 10;
+const INTERRUPT_SETTLE_MS: u64 = 100;
+const RESYNC_TIMEOUT_MS: u64 = 5000;
+const SUDO_PROMPTS: [&str; 2] = ["[sudo] password", "Password:"];
+const SCRUBBED: &str = "********";
+/// Default cap on the output kept from one command.
+pub const DEFAULT_MAX_OUTPUT_BYTES: usize = 1024 * 1024;
+/// Output end kept past the cap so the completion marker is still seen.
+const CAPTURE_TAIL_BYTES: usize = 2 * READ_BUFFER_SIZE;
+const TRUNCATION_NOTICE: &str = "\n[... output truncated ...]\n";
+
+/// Timeouts and buffer sizes of a shell channel, for tuning to slow or
+/// high-latency links (satellite, mobile) without recompiling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelOptions {
+    /// Longest a command may run before it fails with a timeout.
+    pub command_timeout_secs: u64,
+    /// How long the shell gets to answer the startup probe or resync after Ctrl-C.
+    pub resync_timeout_ms: u64,
+    /// Wait for each read while draining output during a resync.
+    pub read_timeout_ms: u64,
+    /// Bytes read from the channel at a time.
+    pub read_buffer_bytes: usize,
+    /// Output kept per command; beyond it the middle is dropped, keeping the end.
+    pub max_output_bytes: usize,
+    /// Charset output is decoded from and commands are encoded to.
+    pub charset: Charset,
+    /// Exports `LC_ALL=C.UTF-8` and `LANG=C.UTF-8` when the shell opens.
+    pub utf8_locale: bool,
+}
+
+impl Default for ChannelOptions {
+    fn default() -> Self {
+        Self {
+            command_timeout_secs: COMMAND_TIMEOUT_SECS,
+            resync_timeout_ms: RESYNC_TIMEOUT_MS,
+            read_timeout_ms: READ_TIMEOUT_MS,
+            read_buffer_bytes: READ_BUFFER_SIZE,
+            max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
+            charset: Charset::default(),
+            utf8_locale: false,
+        }
+    }
+}
+
+impl ChannelOptions {
+    /// Environment variables overriding the matching field.
+    pub const ENV_VARS: [&str; 7] = [
+        "SSH_LIAISON_COMMAND_TIMEOUT_SECS",
+        "SSH_LIAISON_RESYNC_TIMEOUT_MS",
+        "SSH_LIAISON_READ_TIMEOUT_MS",
+        "SSH_LIAISON_READ_BUFFER_BYTES",
+        "SSH_LIAISON_MAX_OUTPUT_BYTES",
+        "SSH_LIAISON_CHARSET",
+        "SSH_LIAISON_UTF8_LOCALE",
+    ];
+
+    /// `self` with the fields set in [`Self::ENV_VARS`] overridden.
+    pub fn with_env(self) -> Result<Self> {
+        self.with_vars(|name| std::env::var(name).ok())
+    }
+
+    fn with_vars(mut self, var: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        fn parse<T: std::str::FromStr>(
+            name: &str,
+            value: Option<String>,
+            field: &mut T,
+        ) -> Result<()>
+        where
+            T::Err: std::fmt::Display,
+        {
+            if let Some(value) = value {
+                *field = value
+                    .trim()
+                    .parse()
+                    .map_err(|e| anyhow::anyhow!("Invalid {}={:?}: {}", name, value, e))?;
+            }
+            Ok(())
+        }
+        let [command, resync, read, buffer, output, charset, utf8_locale] = Self::ENV_VARS;
+        parse(command, var(command), &mut self.command_timeout_secs)?;
+        parse(resync, var(resync), &mut self.resync_timeout_ms)?;
+        parse(read, var(read), &mut self.read_timeout_ms)?;
+        parse(buffer, var(buffer), &mut self.read_buffer_bytes)?;
+        parse(output, var(output), &mut self.max_output_bytes)?;
+        parse(charset, var(charset), &mut self.charset)?;
+        parse(utf8_locale, var(utf8_locale), &mut self.utf8_locale)?;
+        if self.read_buffer_bytes == 0 {
+            anyhow::bail!("{} must be greater than 0", buffer);
+        }
+        Ok(self)
+    }
+
+    pub fn command_timeout(&self) -> Duration {
+        Duration::from_secs(self.command_timeout_secs)
+    }
+
+    pub fn resync_timeout(&self) -> Duration {
+        Duration::from_millis(self.resync_timeout_ms)
+    }
+
+    pub fn read_timeout(&self) -> Duration {
+        Duration::from_millis(self.read_timeout_ms)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+#[schemars(
+    description = "Channel tuning for slow or high-latency links; unset fields keep the server defaults"
+)]
+pub struct ChannelTuning {
+    #[schemars(description = "Longest a command may run, in seconds (default: 30)")]
+    pub command_timeout_secs: Option<u64>,
+    #[schemars(
+        description = "Time the shell gets to answer the startup probe or resync after an interrupt, in ms (default: 5000)"
+    )]
+    pub resync_timeout_ms: Option<u64>,
+    #[schemars(description = "Wait per read while draining output, in ms (default: 100)")]
+    pub read_timeout_ms: Option<u64>,
+    #[schemars(description = "Bytes read from the channel at a time (default: 4096)")]
+    pub read_buffer_bytes: Option<usize>,
+    #[schemars(description = "Output kept per command (default: 1 MiB)")]
+    pub max_output_bytes: Option<usize>,
+    #[schemars(
+        with = "Option<String>",
+        description = "Charset of the host's output, e.g. 'koi8-r', 'latin1' or 'shift_jis', or 'auto' to ask the host's locale when the shell opens (default: utf-8)"
+    )]
+    pub charset: Option<Charset>,
+    #[schemars(
+        description = "Export LC_ALL=C.UTF-8 and LANG=C.UTF-8 when the shell opens, so programs print UTF-8 whatever the host's locale (default: false)"
+    )]
+    pub utf8_locale: Option<bool>,
+}
+
+impl ChannelTuning {
+    /// `base` with the fields set here replaced.
+    pub fn apply(&self, base: ChannelOptions) -> ChannelOptions {
+        ChannelOptions {
+            command_timeout_secs: self
+                .command_timeout_secs
+                .unwrap_or(base.command_timeout_secs),
+            resync_timeout_ms: self.resync_timeout_ms.unwrap_or(base.resync_timeout_ms),
+            read_timeout_ms: self.read_timeout_ms.unwrap_or(base.read_timeout_ms),
+            read_buffer_bytes: self
+                .read_buffer_bytes
+                .filter(|&n| n > 0)
+                .unwrap_or(base.read_buffer_bytes),
+            max_output_bytes: self.max_output_bytes.unwrap_or(base.max_output_bytes),
+            charset: self.charset.unwrap_or(base.charset),
+            utf8_locale: self.utf8_locale.unwrap_or(base.utf8_locale),
+        }
+    }
+}
+
+/// Start of every completion marker.
+pub const MARKER_PREFIX: &str = "__SSH_CMD_DONE_";
 
 fn generate_marker() -> String {
     use std::time::{SystemTime, UNIX_EPOCH};
@@ -24,13 +188,52 @@ fn generate_marker() -> String {
         .duration_since(UNIX_EPOCH)
         .unwrap()
         .as_nanos();
-    format!("__SSH_CMD_DONE_{}__", timestamp)
+    format!("{}{}__", MARKER_PREFIX, timestamp)
+}
+
+/// Sudo prompt outcomes the caller can act on (e.g. ask the user and retry).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SudoError {
+    /// Sudo asked for a password and none was supplied. The command was interrupted.
+    PasswordRequired,
+    /// Sudo asked again after the supplied password was sent. The command was interrupted.
+    PasswordRejected,
+}
+
+impl std::fmt::Display for SudoError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SudoError::PasswordRequired => write!(
+                f,
+                "Command requires a sudo password. Provide sudo_password or configure passwordless sudo."
+            ),
+            SudoError::PasswordRejected => write!(f, "Sudo rejected the supplied password"),
+        }
+    }
+}
+
+impl std::error::Error for SudoError {}
+
+fn count_sudo_prompts(output: &str) -> usize {
+    SUDO_PROMPTS.iter().map(|p| output.matches(p).count()).sum()
 }
 
-#[derive(Debug, Clone, Default)]
+/// Output of one command, split by stream when the shell allows it.
+#[derive(Debug, Clone, Default, Serialize)]
 pub struct CommandOutput {
     pub stdout: String,
     pub stderr: String,
+    /// Exit status reported by the shell, `None` if the marker line was never seen.
+    pub exit_code: Option<i32>,
+    pub duration_ms: u64,
+    /// Whether `stdout` or `stderr` were cut short.
+    pub truncated: bool,
+    /// `stdout` with its colors kept, when it had any; see [`ansi::AnsiMode`].
+    #[serde(skip)]
+    pub styled: Option<String>,
+    /// What the command printed when it was binary; `stdout` only says how much.
+    #[serde(skip)]
+    pub binary: Option<Vec<u8>>,
 }
 
 impl CommandOutput {
@@ -50,8 +253,47 @@ impl CommandOutput {
     }
 }
 
+/// Interactive shell on a PTY; commands are delimited with unique markers.
 pub struct ShellChannel {
-    channel: AsyncChannel<TokioTcpStream>,
+    channel: Box<dyn SshChannel>,
+    options: ChannelOptions,
+    shell: ShellKind,
+    recorder: Option<Recorder>,
+    /// An interrupt didn't bring the prompt back; output can't be trusted.
+    desynced: bool,
+    encoding: &'static Encoding,
+    /// Keeps a character split across reads until its last byte arrives.
+    decoder: Decoder,
+}
+
+/// Largest char boundary in `text` at or below `index`.
+fn floor_char_boundary(text: &str, index: usize) -> usize {
+    let mut index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// Keeps the first `limit` bytes and the last [`CAPTURE_TAIL_BYTES`] of `buf`,
+/// with [`TRUNCATION_NOTICE`] in between. `kept` remembers where the notice
+/// ends across calls. Returns the number of bytes dropped.
+fn cap_output(buf: &mut String, limit: usize, kept: &mut Option<usize>) -> usize {
+    let head_end = match *kept {
+        Some(head_end) => head_end,
+        None if buf.len() <= limit + CAPTURE_TAIL_BYTES => return 0,
+        None => {
+            let head = floor_char_boundary(buf, limit);
+            buf.insert_str(head, TRUNCATION_NOTICE);
+            head + TRUNCATION_NOTICE.len()
+        }
+    };
+    *kept = Some(head_end);
+
+    let tail_start =
+        floor_char_boundary(buf, buf.len().saturating_sub(CAPTURE_TAIL_BYTES)).max(head_end);
+    buf.replace_range(head_end..tail_start, "");
+    tail_start - head_end
 }
 
 /// Marker on own line (preceded by \n or at start) — ignores echoed command, truncates at real marker.
@@ -70,208 +312,600 @@ fn find_last_marker_on_own_line(output: &str, marker: &str) -> Option<usize> {
     last_pos
 }
 
-fn remove_command_echo(output: &mut String, command: &str, marker: &str) {
-    let full_cmd = format!("{}; echo {}", command, marker);
-    while let Some(cmd_pos) = output.find(&full_cmd) {
-        output.replace_range(cmd_pos..cmd_pos + full_cmd.len(), "");
+/// Start of the `<marker>:<status>` line and the status, once the whole line
+/// arrived. The echoed command carries `<marker>:$?` instead, so it never
+/// matches even when a wrapped echo puts the marker at a line start. Without
+/// `own_line` the marker may follow other output on its line, as it does
+/// after binary output that doesn't end in a newline.
+fn find_completion(output: &str, marker: &str, own_line: bool) -> Option<(usize, i32)> {
+    let pos = if own_line {
+        find_last_marker_on_own_line(output, marker)?
+    } else {
+        output.rfind(marker)?
+    };
+    let line = &output[pos + marker.len()..];
+    let end = line.find(['\r', '\n'])?;
+    let code = line[..end].strip_prefix(':')?.trim().parse().ok()?;
+    Some((pos, code))
+}
+
+/// Length of the terminal noise at the start of `text` that may sit inside an
+/// echoed command: CR/LF from line wrapping, the space bash prints before a
+/// wrap (" \r"), and escape sequences.
+fn echo_noise_len(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    match bytes.first() {
+        Some(b'\r' | b'\n') => 1,
+        Some(b' ') if bytes.get(1) == Some(&b'\r') => 1,
+        Some(0x1b) if bytes.get(1) == Some(&b'[') => bytes[2..]
+            .iter()
+            .position(|b| b.is_ascii_alphabetic())
+            .map_or(0, |end| end + 3),
+        _ => 0,
+    }
+}
+
+/// Byte range of `echo` in `output` starting at `start`, skipping wrap noise.
+fn match_echo(output: &str, start: usize, echo: &str) -> Option<usize> {
+    let mut pos = start;
+    for expected in echo.chars() {
+        loop {
+            let rest = &output[pos..];
+            if rest.starts_with(expected) {
+                pos += expected.len_utf8();
+                break;
+            }
+            match echo_noise_len(rest) {
+                0 => return None,
+                noise => pos += noise,
+            }
+        }
+    }
+    Some(pos)
+}
+
+/// Removes the PTY's echo of the command line `full_cmd`, even when the
+/// terminal wrapped it, together with any prompt text before it and the line
+/// break after it.
+fn remove_command_echo(output: &mut String, full_cmd: &str) {
+    let Some(first) = full_cmd.chars().next() else {
+        return;
+    };
+
+    let mut search = 0;
+    while let Some(found) = output[search..].find(first) {
+        let start = search + found;
+        let Some(mut end) = match_echo(output, start, full_cmd) else {
+            search = start + first.len_utf8();
+            continue;
+        };
+        let line_start = output[..start].rfind('\n').map_or(0, |i| i + 1);
+        if output[end..].starts_with("\r\n") {
+            end += 2;
+        } else if output[end..].starts_with(['\r', '\n']) {
+            end += 1;
+        }
+        output.replace_range(line_start..end, "");
+        search = line_start;
+    }
+}
+
+/// `data` in `encoding`; bytes that aren't UTF-8 go as they are.
+fn encode<'a>(encoding: &'static Encoding, data: &'a [u8]) -> Cow<'a, [u8]> {
+    match std::str::from_utf8(data) {
+        Ok(text) if encoding != UTF_8 => encoding.encode(text).0,
+        _ => data.into(),
     }
 }
 
 impl ShellChannel {
-    pub fn new(channel: AsyncChannel<TokioTcpStream>) -> Self {
-        Self { channel }
+    pub fn new(channel: Box<dyn SshChannel>) -> Self {
+        Self {
+            channel,
+            options: ChannelOptions::default(),
+            shell: ShellKind::default(),
+            recorder: None,
+            desynced: false,
+            encoding: UTF_8,
+            decoder: UTF_8.new_decoder_without_bom_handling(),
+        }
+    }
+
+    pub fn shell(&self) -> ShellKind {
+        self.shell
+    }
+
+    /// Asks the remote shell what it is, so commands are wrapped in its syntax.
+    /// Also drains the login banner. Falls back to POSIX `sh` if there's no answer.
+    pub async fn detect_shell(&mut self) -> Result<ShellKind> {
+        let marker = generate_marker();
+        let probe = format!("{}\n", ShellKind::probe_command(&marker));
+        self.send(probe.as_bytes()).await?;
+
+        let answer = self
+            .read_until(|drained| match marker_line(drained, &marker, true) {
+                Some(after) => Some(Some(after)),
+                None => looks_like_windows_prompt(drained).then_some(None),
+            })
+            .await;
+        self.shell = match answer {
+            Some(Some(after)) => ShellKind::from_probe(&after),
+            Some(None) => self.detect_windows_shell().await?,
+            None => {
+                tracing::warn!("Shell did not answer the probe, assuming POSIX sh");
+                ShellKind::Sh
+            }
+        };
+        Ok(self.shell)
+    }
+
+    async fn detect_windows_shell(&mut self) -> Result<ShellKind> {
+        let marker = generate_marker();
+        let probe = format!("{}\r", ShellKind::windows_probe_command(&marker));
+        self.send(probe.as_bytes()).await?;
+
+        let kind = match self.read_until_marker(&marker, true).await {
+            Some(after) => ShellKind::from_windows_probe(&after),
+            None => {
+                tracing::warn!("Windows shell did not answer the probe, assuming cmd");
+                ShellKind::Cmd
+            }
+        };
+
+        // The other shell's probe line errors after the answer; drain it.
+        let marker = generate_marker();
+        self.send(format!("echo {}\r", marker).as_bytes()).await?;
+        if self.read_until_marker(&marker, true).await.is_none() {
+            tracing::warn!("Shell did not resynchronize after the Windows probe");
+        }
+        Ok(kind)
+    }
+
+    /// Records everything sent and read from now on.
+    pub fn set_recorder(&mut self, recorder: Recorder) {
+        self.recorder = Some(recorder);
+    }
+
+    /// Writes `data` to the shell, in its charset, and flushes it.
+    async fn send(&mut self, data: &[u8]) -> std::io::Result<()> {
+        let data = encode(self.encoding, data);
+        if let Some(ref mut recorder) = self.recorder {
+            recorder.input(&data);
+        }
+        self.channel.write_all(&data).await?;
+        self.channel.flush().await
+    }
+
+    /// `bytes` read from the shell as text; a character cut off at the end
+    /// is completed by the next call.
+    fn decode(&mut self, bytes: &[u8]) -> String {
+        let capacity = self
+            .decoder
+            .max_utf8_buffer_length(bytes.len())
+            .unwrap_or(bytes.len() * 3);
+        let mut text = String::with_capacity(capacity);
+        let _ = self.decoder.decode_to_string(bytes, &mut text, false);
+        text
+    }
+
+    fn set_encoding(&mut self, encoding: &'static Encoding) {
+        if encoding != self.encoding {
+            self.encoding = encoding;
+            self.decoder = encoding.new_decoder_without_bom_handling();
+        }
+    }
+
+    pub fn encoding(&self) -> &'static Encoding {
+        self.encoding
+    }
+
+    /// Applies the charset options once the shell is known: exports a UTF-8
+    /// locale, or asks the host for its charmap when the charset is `auto`.
+    /// Windows shells keep the configured charset.
+    pub async fn setup_charset(&mut self) -> Result<&'static Encoding> {
+        if self.shell.is_windows() {
+            return Ok(self.encoding);
+        }
+        if self.options.utf8_locale {
+            let marker = generate_marker();
+            let separator = self.shell.separator();
+            let line = format!(
+                "{}{} {}{} echo {}\n",
+                self.shell.set_env_command("LC_ALL", "C.UTF-8"),
+                separator,
+                self.shell.set_env_command("LANG", "C.UTF-8"),
+                separator,
+                marker
+            );
+            self.send(line.as_bytes()).await?;
+            if self.read_until_marker(&marker, true).await.is_some() {
+                self.set_encoding(UTF_8);
+            } else {
+                tracing::warn!("Shell did not answer after setting a UTF-8 locale");
+            }
+        } else if self.options.charset == Charset::Auto {
+            let marker = generate_marker();
+            let line = format!("{}\n", charset::charmap_command(self.shell, &marker));
+            self.send(line.as_bytes()).await?;
+            let charmap = self.read_until_marker(&marker, true).await;
+            match charmap
+                .as_deref()
+                .and_then(|after| after.strip_prefix(':'))
+                .map(str::trim)
+            {
+                Some(charmap) => match charset::from_label(charmap) {
+                    Some(encoding) => self.set_encoding(encoding),
+                    None => tracing::warn!(charmap, "Unknown charmap, decoding output as UTF-8"),
+                },
+                None => {
+                    tracing::warn!("Shell did not report its charmap, decoding output as UTF-8")
+                }
+            }
+        }
+        Ok(self.encoding)
+    }
+
+    async fn recv(&mut self, buffer: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.channel.read(buffer).await?;
+        if n > 0
+            && let Some(ref mut recorder) = self.recorder
+        {
+            recorder.output(&buffer[..n]);
+        }
+        Ok(n)
+    }
+
+    pub fn set_options(&mut self, options: ChannelOptions) {
+        self.options = options;
+        if let Charset::Fixed(encoding) = options.charset {
+            self.set_encoding(encoding);
+        }
+    }
+
+    /// Whether the remote side closed the shell.
+    pub fn is_eof(&self) -> bool {
+        self.channel.is_eof()
     }
 
+    /// Whether the shell stopped answering after an interrupt and has to be
+    /// replaced before the next command.
+    pub fn is_desynced(&self) -> bool {
+        self.desynced
+    }
+
+    /// Runs `command` and returns its output. When `cancel` fires the command
+    /// is interrupted and what it printed so far is returned without an exit code.
     pub async fn execute_command(
         &mut self,
         command: &str,
         sudo_password: Option<&str>,
+        cancel: &CancellationToken,
     ) -> Result<CommandOutput> {
         let marker = generate_marker();
-        let full_command = format!("{}; echo {}\n", command, marker);
+        let full_line = self.shell.with_marker(command, &marker);
+        let full_command = format!("{}{}", full_line, self.shell.line_ending());
 
-        tracing::debug!(command = %command, "Executing command");
-        tracing::trace!(full_command = %full_command.trim(), "Full command with marker");
+        tracing::debug!(command = %redact::for_logs(command), "Executing command");
+        tracing::trace!(
+            full_command = %redact::for_logs(full_command.trim()),
+            "Full command with marker"
+        );
 
-        self.channel.write_all(full_command.as_bytes()).await?;
-        self.channel.flush().await?;
+        if !self.discard_stale_output().await? {
+            return Err(SshLiaisonError::Channel(
+                "Shell is out of sync and didn't recover; it's reopened before the next command"
+                    .to_string(),
+            )
+            .into());
+        }
+        self.send(full_command.as_bytes()).await?;
 
         tracing::trace!("Command sent, starting to read");
 
         let mut stdout = String::new();
-        let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+        let mut buffer = vec![0u8; self.options.read_buffer_bytes];
         let start = Instant::now();
-        let mut marker_found = false;
-        let mut last_read_time = Instant::now();
-        let mut no_data_count = 0;
-        let mut sudo_password_sent = false;
+        let timeout = self.options.command_timeout();
+        let deadline = tokio::time::Instant::now() + timeout;
+        let mut kept_head = None;
+        let mut dropped = 0;
+        let mut sudo_prompts_answered = 0;
+        // Kept to return binary output as it was printed.
+        let mut raw = Vec::new();
+        let mut raw_truncated = false;
+        let mut binary = false;
 
-        loop {
-            if start.elapsed() > COMMAND_TIMEOUT {
-                tracing::warn!(elapsed = ?start.elapsed(), "Command timeout");
-                anyhow::bail!("Command timeout after {:?}", COMMAND_TIMEOUT);
+        let exit_code = loop {
+            let read = tokio::select! {
+                biased;
+                () = cancel.cancelled() => None,
+                read = tokio::time::timeout_at(deadline, self.recv(&mut buffer)) => Some(read),
+            };
+            let Some(read) = read else {
+                tracing::debug!(elapsed = ?start.elapsed(), "Command cancelled, interrupting");
+                self.interrupt().await?;
+                remove_command_echo(&mut stdout, &full_line);
+                break None;
+            };
+            let n = match read {
+                Err(_) => {
+                    tracing::warn!(elapsed = ?start.elapsed(), "Command timeout, interrupting");
+                    let recovered = self.interrupt().await?;
+                    return Err(SshLiaisonError::Timeout {
+                        after: timeout,
+                        recovered,
+                    }
+                    .into());
+                }
+                Ok(Ok(0)) if self.channel.is_eof() => {
+                    return Err(SshLiaisonError::Channel("Shell closed".to_string()).into());
+                }
+                // Not EOF, the channel just had nothing after all.
+                Ok(Ok(0)) => {
+                    sleep(Duration::from_millis(SLEEP_ON_EOF_MS)).await;
+                    continue;
+                }
+                Ok(Ok(n)) => n,
+                Ok(Err(e)) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Ok(Err(e)) => return Err(e.into()),
+            };
+
+            if !self.shell.is_windows() {
+                binary |= binary::looks_binary(&buffer[..n]);
+                if raw.len() < self.options.max_output_bytes {
+                    raw.extend_from_slice(&buffer[..n]);
+                } else {
+                    raw_truncated = true;
+                }
             }
+            let chunk = self.decode(&buffer[..n]);
+            tracing::trace!(bytes = n, "Read data");
+            stdout.push_str(&chunk);
+            // A sequence split across reads is still found.
+            let recent = floor_char_boundary(&stdout, stdout.len().saturating_sub(chunk.len() + 8));
+            if interactive::enters_alternate_screen(&stdout[recent..]) {
+                tracing::warn!("Command switched to the alternate screen, interrupting");
+                self.interrupt().await?;
+                return Err(SshLiaisonError::Interactive(InteractiveCommand {
+                    program: interactive::program(command),
+                    suggestion:
+                        "full-screen programs can't run here; use a batch mode or plain output"
+                            .to_string(),
+                })
+                .into());
+            }
+            dropped += cap_output(&mut stdout, self.options.max_output_bytes, &mut kept_head);
 
-            let read_future = self.channel.read(&mut buffer);
-            let timeout_future = sleep(Duration::from_millis(READ_TIMEOUT_MS));
-
-            tokio::select! {
-                result = read_future => {
-                    match result {
-                        Ok(0) => {
-                            if marker_found {
-                                break;
-                            }
-                            no_data_count += 1;
-                            if no_data_count > NO_DATA_THRESHOLD && last_read_time.elapsed() > Duration::from_millis(IDLE_TIMEOUT_MS) {
-                                tracing::trace!(idle_ms = IDLE_TIMEOUT_MS, "No data, assuming command completed");
-                                break;
-                            }
-                            sleep(Duration::from_millis(SLEEP_ON_EOF_MS)).await;
-                        }
-                        Ok(n) => {
-                            last_read_time = Instant::now();
-                            no_data_count = 0;
-                            let chunk = String::from_utf8_lossy(&buffer[..n]);
-                            tracing::trace!(bytes = n, "Read data");
-                            stdout.push_str(&chunk);
-
-                            let sudo_prompt = stdout.contains("[sudo] password")
-                                || stdout.contains("Password:");
-                            if sudo_prompt && !sudo_password_sent {
-                                if let Some(pass) = sudo_password {
-                                    tracing::trace!("Sudo password prompt detected, sending response");
-                                    self.channel
-                                        .write_all(format!("{}\n", pass).as_bytes())
-                                        .await?;
-                                    self.channel.flush().await?;
-                                    sudo_password_sent = true;
-                                } else {
-                                    anyhow::bail!(
-                                        "Command requires sudo password. Elicitation support coming soon. \
-                                        Please ensure the user has passwordless sudo configured or handle manually."
-                                    );
-                                }
-                            }
-
-                            if let Some(marker_pos) = find_last_marker_on_own_line(&stdout, &marker) {
-                                tracing::trace!(position = marker_pos, "Marker found");
-
-                                let mut continue_reading = true;
-                                let mut read_attempts = 0;
-
-                                while continue_reading && read_attempts < CONTINUE_READ_ATTEMPTS {
-                                    match tokio::time::timeout(Duration::from_millis(CONTINUE_READ_TIMEOUT_MS), self.channel.read(&mut buffer)).await {
-                                        Ok(Ok(0)) => {
-                                            read_attempts += 1;
-                                            sleep(Duration::from_millis(SLEEP_ON_EOF_MS)).await;
-                                        }
-                                        Ok(Ok(n)) => {
-                                            let chunk = String::from_utf8_lossy(&buffer[..n]);
-                                            stdout.push_str(&chunk);
-                                            read_attempts = 0;
-
-                                            if chunk.contains(&marker) {
-                                                continue_reading = false;
-                                            }
-                                        }
-                                        _ => {
-                                            read_attempts += 1;
-                                            if read_attempts > CONTINUE_READ_MAX_FAILURES {
-                                                continue_reading = false;
-                                            }
-                                        }
-                                    }
-                                }
-
-                                if let Some(pos) = find_last_marker_on_own_line(&stdout, &marker) {
-                                    tracing::trace!(position = pos, total_len = stdout.len(), "Using marker on own line");
-                                    stdout.truncate(pos);
-                                    remove_command_echo(&mut stdout, command, &marker);
-                                    marker_found = true;
-                                    break;
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            tracing::trace!(error = %e, "Read error");
-                            sleep(Duration::from_millis(SLEEP_ON_ERROR_MS)).await;
-                        }
+            let sudo_prompts = count_sudo_prompts(&stdout);
+            if sudo_prompts > sudo_prompts_answered {
+                match sudo_password {
+                    Some(pass) if sudo_prompts_answered == 0 => {
+                        tracing::trace!("Sudo password prompt detected, sending response");
+                        // Sent past the recorder; the PTY doesn't echo it either.
+                        let answer = format!("{}\n", pass);
+                        let answer = encode(self.encoding, answer.as_bytes()).into_owned();
+                        self.channel.write_all(&answer).await?;
+                        self.channel.flush().await?;
+                        sudo_prompts_answered = sudo_prompts;
                     }
-                }
-                _ = timeout_future => {
-                    if marker_found {
-                        break;
+                    Some(_) => {
+                        tracing::debug!("Sudo prompted again, password rejected");
+                        self.interrupt().await?;
+                        return Err(SudoError::PasswordRejected.into());
                     }
-                    if last_read_time.elapsed() > Duration::from_millis(IDLE_TIMEOUT_MS) && no_data_count > 5 {
-                        tracing::trace!(idle_ms = IDLE_TIMEOUT_MS, "No data, breaking");
-                        break;
+                    None => {
+                        tracing::debug!("Sudo password required but not provided");
+                        self.interrupt().await?;
+                        return Err(SudoError::PasswordRequired.into());
                     }
                 }
             }
-        }
+
+            if let Some((pos, code)) = find_completion(&stdout, &marker, !binary) {
+                tracing::trace!(
+                    position = pos,
+                    total_len = stdout.len(),
+                    "Completion line found"
+                );
+                stdout.truncate(pos);
+                remove_command_echo(&mut stdout, &full_line);
+                break Some(code);
+            }
+        };
 
         tracing::trace!(
             stdout_len = stdout.len(),
-            marker_found = marker_found,
-            "Loop finished"
+            elapsed = ?start.elapsed(),
+            "Command completed"
         );
 
-        let cleaned = clean_ansi_sequences(&stdout);
+        if binary {
+            let data = binary::payload(&raw, &marker);
+            tracing::debug!(bytes = data.len(), "Command printed binary output");
+            return Ok(CommandOutput {
+                stdout: format!("[binary output, {} bytes]", data.len()),
+                exit_code,
+                duration_ms: start.elapsed().as_millis() as u64,
+                truncated: raw_truncated,
+                binary: Some(data),
+                ..Default::default()
+            });
+        }
+        if dropped > 0 {
+            tracing::debug!(dropped, "Command output truncated");
+        }
+        let windows = self.shell.is_windows();
+        let finish = |mut cleaned: String| {
+            if windows {
+                cleaned = cleaned.replace("\r\n", "\n");
+            }
+            if let Some(pass) = sudo_password.filter(|p| !p.is_empty()) {
+                cleaned = cleaned.replace(pass, SCRUBBED);
+            }
+            if dropped > 0 {
+                cleaned = cleaned.replacen(
+                    TRUNCATION_NOTICE,
+                    &format!("\n[... {} bytes truncated ...]\n", dropped),
+                    1,
+                );
+            }
+            cleaned.trim_end().to_string()
+        };
 
         Ok(CommandOutput {
-            stdout: cleaned.trim_end().to_string(),
+            stdout: finish(ansi::strip(&stdout)),
             stderr: String::new(),
+            exit_code,
+            duration_ms: start.elapsed().as_millis() as u64,
+            truncated: dropped > 0,
+            styled: ansi::has_colors(&stdout).then(|| finish(ansi::keep_colors(&stdout))),
+            binary: None,
         })
     }
 }
 
-static ANSI_REGEX: OnceLock<Regex> = OnceLock::new();
-static OSC_REGEX: OnceLock<Regex> = OnceLock::new();
-static OTHER_ESCAPE_REGEX: OnceLock<Regex> = OnceLock::new();
+impl ShellChannel {
+    /// Sends Ctrl-C, then resynchronizes. Returns whether the shell answered.
+    pub async fn interrupt(&mut self) -> Result<bool> {
+        self.send(b"\x03").await?;
+        sleep(Duration::from_millis(INTERRUPT_SETTLE_MS)).await;
+        self.resync().await
+    }
+
+    /// Sends a newline and drains output until the shell echoes a fresh
+    /// marker. Returns whether it did; if not, the channel is marked desynced.
+    pub async fn resync(&mut self) -> Result<bool> {
+        let marker = generate_marker();
+        let line_ending = self.shell.line_ending();
+        self.send(format!("{}echo {}{}", line_ending, marker, line_ending).as_bytes())
+            .await?;
+
+        let resynced = self.read_until_marker(&marker, false).await.is_some();
+        if resynced {
+            tracing::trace!("Shell resynchronized");
+        } else {
+            tracing::warn!("Shell did not resynchronize");
+        }
+        self.desynced = !resynced;
+        Ok(resynced)
+    }
+
+    /// Output already waiting before a command is sent belongs to an earlier
+    /// one (something printed after its marker, or a program that took over
+    /// the shell) and would end up in the new command's output. A bare prompt
+    /// is harmless; whole lines mean the shell is out of step, so it is
+    /// resynchronized. Returns whether the shell is usable.
+    async fn discard_stale_output(&mut self) -> Result<bool> {
+        let mut buffer = vec![0u8; self.options.read_buffer_bytes];
+        let mut stale = String::new();
+        // A zero timeout polls the read once, taking only what already arrived.
+        while let Ok(Ok(n)) = tokio::time::timeout(Duration::ZERO, self.recv(&mut buffer)).await
+            && n > 0
+        {
+            stale.push_str(&self.decode(&buffer[..n]));
+        }
+        if !has_stale_lines(&stale) {
+            return Ok(true);
+        }
+        tracing::debug!(
+            bytes = stale.len(),
+            "Stale output before command, resynchronizing"
+        );
+        self.resync().await
+    }
+
+    /// Drains output until `marker` starts a line, returning what follows it
+    /// (once `whole_line` is set, only after the rest of that line arrived).
+    /// `None` after the resync timeout.
+    async fn read_until_marker(&mut self, marker: &str, whole_line: bool) -> Option<String> {
+        self.read_until(|drained| marker_line(drained, marker, whole_line))
+            .await
+    }
+
+    /// Drains output until `done` accepts what was read so far.
+    /// `None` after the resync timeout.
+    async fn read_until<T>(&mut self, mut done: impl FnMut(&str) -> Option<T>) -> Option<T> {
+        let mut drained = String::new();
+        let mut buffer = vec![0u8; self.options.read_buffer_bytes];
+        let start = Instant::now();
+        while start.elapsed() < self.options.resync_timeout() {
+            match tokio::time::timeout(self.options.read_timeout(), self.recv(&mut buffer)).await {
+                Ok(Ok(0)) => sleep(Duration::from_millis(SLEEP_ON_EOF_MS)).await,
+                Ok(Ok(n)) => {
+                    drained.push_str(&self.decode(&buffer[..n]));
+                    if let Some(found) = done(&drained) {
+                        return Some(found);
+                    }
+                }
+                Ok(Err(_)) => sleep(Duration::from_millis(SLEEP_ON_ERROR_MS)).await,
+                Err(_) => {}
+            }
+        }
+        None
+    }
+}
 
-fn clean_ansi_sequences(text: &str) -> String {
-    let ansi_re = ANSI_REGEX
-        .get_or_init(|| Regex::new(r"\x1b\[[0-9;]*[a-zA-Z]").expect("ANSI regex should be valid"));
-    let mut cleaned = ansi_re.replace_all(text, "").to_string();
+/// Whether output left in the shell holds whole lines, rather than just the
+/// end of the last marker line and a prompt.
+fn has_stale_lines(left: &str) -> bool {
+    left.trim_start_matches(['\r', '\n']).contains('\n')
+}
 
-    let osc_re = OSC_REGEX
-        .get_or_init(|| Regex::new(r"\x1b\][^\x07]*\x07").expect("OSC regex should be valid"));
-    cleaned = osc_re.replace_all(&cleaned, "").to_string();
+/// What follows `marker` once it starts a line in `drained` (with
+/// `whole_line`, only once the rest of that line arrived).
+fn marker_line(drained: &str, marker: &str, whole_line: bool) -> Option<String> {
+    let pos = find_last_marker_on_own_line(drained, marker)?;
+    let after = &drained[pos + marker.len()..];
+    (!whole_line || after.contains('\n')).then(|| after.to_string())
+}
 
-    let other_re = OTHER_ESCAPE_REGEX.get_or_init(|| {
-        Regex::new(r"\x1b[P^_].*?\x1b\\").expect("Other escape regex should be valid")
-    });
-    cleaned = other_re.replace_all(&cleaned, "").to_string();
+static WINDOWS_PROMPT_REGEX: OnceLock<Regex> = OnceLock::new();
 
-    cleaned
+/// A PowerShell (`PS C:\Users\me>`) or cmd (`me@HOST C:\Users\me>`) prompt.
+fn looks_like_windows_prompt(output: &str) -> bool {
+    WINDOWS_PROMPT_REGEX
+        .get_or_init(|| {
+            Regex::new(r"(?m)(^|\s)(PS )?[A-Za-z]:\\[^\r\n<>]*> ?\r?$")
+                .expect("Windows prompt regex should be valid")
+        })
+        .is_match(&ansi::strip(output))
 }
 
 impl ShellChannel {
-    #[allow(dead_code)]
     pub async fn execute_command_streaming(&mut self, command: &str) -> Result<String> {
         let marker = generate_marker();
-        let full_command = format!("{}; echo {}\n", command, marker);
+        let full_command = format!(
+            "{}{} echo {}{}",
+            command,
+            self.shell.separator(),
+            marker,
+            self.shell.line_ending()
+        );
 
-        self.channel.write_all(full_command.as_bytes()).await?;
-        self.channel.flush().await?;
+        self.send(full_command.as_bytes()).await?;
 
-        let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+        let mut buffer = vec![0u8; self.options.read_buffer_bytes];
         let mut stdout_accumulated = String::new();
         let start = Instant::now();
+        let timeout = self.options.command_timeout();
 
         loop {
-            if start.elapsed() > COMMAND_TIMEOUT {
-                anyhow::bail!("Command timeout after {:?}", COMMAND_TIMEOUT);
+            if start.elapsed() > timeout {
+                let recovered = self.interrupt().await?;
+                return Err(SshLiaisonError::Timeout {
+                    after: timeout,
+                    recovered,
+                }
+                .into());
             }
 
-            match self.channel.read(&mut buffer).await {
+            match self.recv(&mut buffer).await {
                 Ok(0) => {
                     sleep(Duration::from_millis(SLEEP_ON_EOF_MS)).await;
                     continue;
                 }
                 Ok(n) => {
-                    let chunk = String::from_utf8_lossy(&buffer[..n]);
+                    let chunk = self.decode(&buffer[..n]);
                     stdout_accumulated.push_str(&chunk);
 
                     if let Some(pos) = stdout_accumulated.find(&marker) {
@@ -294,15 +928,146 @@ impl ShellChannel {
         Ok(stdout_accumulated)
     }
 
-    #[allow(dead_code)]
     pub async fn write(&mut self, data: &[u8]) -> Result<()> {
-        self.channel.write_all(data).await?;
-        self.channel.flush().await?;
+        self.send(data).await?;
         Ok(())
     }
 
-    pub async fn close(mut self) -> Result<()> {
+    pub async fn close(&mut self) -> Result<()> {
         self.channel.close().await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exit_code_follows_marker() {
+        let marker = "__MARKER_1__";
+        let output = format!("ls; echo {m}:$?\nfile\n{m}:2\r\n", m = marker);
+        assert_eq!(find_completion(&output, marker, true), Some((30, 2)));
+
+        // The status isn't complete until its line ends.
+        let partial = format!("file\n{}:12", marker);
+        assert_eq!(find_completion(&partial, marker, true), None);
+
+        // A wrapped echo can put the marker at a line start, with `:$?` after it.
+        let wrapped = format!("ls; echo\n{}:$?\r\nfile\n", marker);
+        assert_eq!(find_completion(&wrapped, marker, true), None);
+
+        let windows = format!("{}:-1073741819\r\n", marker);
+        assert_eq!(
+            find_completion(&windows, marker, true),
+            Some((0, -1073741819))
+        );
+
+        let legacy = format!("file\n{}\n", marker);
+        assert_eq!(find_completion(&legacy, marker, true), None);
+
+        let binary = format!("cat a.gz; echo {m}:$?\r\n\x1f\u{fffd}{m}:0\r\n", m = marker);
+        assert_eq!(find_completion(&binary, marker, true), None);
+        assert_eq!(find_completion(&binary, marker, false), Some((36, 0)));
+    }
+
+    #[test]
+    fn test_encode_for_shell_charset() {
+        let password = "пароль\n".as_bytes();
+        assert_eq!(encode(UTF_8, password), password);
+        assert_eq!(
+            encode(encoding_rs::WINDOWS_1251, password),
+            &b"\xef\xe0\xf0\xee\xeb\xfc\n"[..]
+        );
+        assert_eq!(encode(encoding_rs::WINDOWS_1251, b"\xff"), &b"\xff"[..]);
+    }
+
+    #[test]
+    fn test_stale_lines() {
+        assert!(!has_stale_lines(""));
+        assert!(!has_stale_lines("\nuser@web1:~$ "));
+        assert!(!has_stale_lines("\r\nC:\\Users\\me>"));
+        assert!(has_stale_lines("tick 41\ntick 42\n$ "));
+    }
+
+    #[test]
+    fn test_channel_options_from_env() {
+        let vars = std::collections::HashMap::from([
+            ("SSH_LIAISON_COMMAND_TIMEOUT_SECS", "120"),
+            ("SSH_LIAISON_RESYNC_TIMEOUT_MS", " 15000 "),
+            ("SSH_LIAISON_CHARSET", "auto"),
+        ]);
+        let options = ChannelOptions::default()
+            .with_vars(|name| vars.get(name).map(|v| v.to_string()))
+            .unwrap();
+        assert_eq!(options.command_timeout(), Duration::from_secs(120));
+        assert_eq!(options.resync_timeout(), Duration::from_secs(15));
+        assert_eq!(options.read_buffer_bytes, READ_BUFFER_SIZE);
+        assert_eq!(options.charset, Charset::Auto);
+
+        let bad = ChannelOptions::default().with_vars(|name| {
+            (name == "SSH_LIAISON_READ_BUFFER_BYTES").then(|| "lots".to_string())
+        });
+        assert!(bad.is_err());
+    }
+
+    #[test]
+    fn test_windows_prompt() {
+        assert!(looks_like_windows_prompt(
+            "Microsoft Windows [Version 10.0.20348]\r\n\r\nadmin@WIN C:\\Users\\admin>"
+        ));
+        assert!(looks_like_windows_prompt(
+            "\x1b[?25lPS C:\\Users\\admin> \x1b[?25h"
+        ));
+        assert!(!looks_like_windows_prompt("user@web:~$ "));
+    }
+
+    #[test]
+    fn test_remove_wrapped_command_echo() {
+        let marker = "__M__";
+        let mut output = "user@web:~$ ls -la /var/lo \rg; echo __M__:$?\r\nsyslog\r\n".to_string();
+        remove_command_echo(
+            &mut output,
+            &ShellKind::Bash.with_marker("ls -la /var/log", marker),
+        );
+        assert_eq!(output, "syslog\r\n");
+
+        let mut output = "ls -la /var/l\r\nog; ec\x1b[Kho __M__:$?\nsyslog\n".to_string();
+        remove_command_echo(
+            &mut output,
+            &ShellKind::Bash.with_marker("ls -la /var/log", marker),
+        );
+        assert_eq!(output, "syslog\n");
+
+        let mut output = "grep ls notes\n".to_string();
+        remove_command_echo(&mut output, &ShellKind::Bash.with_marker("ls", marker));
+        assert_eq!(output, "grep ls notes\n");
+    }
+
+    #[test]
+    fn test_cap_output_keeps_head_and_tail() {
+        let mut kept = None;
+        let mut buf = "a".repeat(100);
+        assert_eq!(cap_output(&mut buf, 100, &mut kept), 0);
+        assert!(kept.is_none());
+
+        let mut dropped = 0;
+        for _ in 0..4 {
+            buf.push_str(&"b".repeat(CAPTURE_TAIL_BYTES));
+            dropped += cap_output(&mut buf, 100, &mut kept);
+        }
+        buf.push_str("\n__DONE__");
+        dropped += cap_output(&mut buf, 100, &mut kept);
+
+        assert_eq!(
+            dropped,
+            4 * CAPTURE_TAIL_BYTES + "\n__DONE__".len() - CAPTURE_TAIL_BYTES
+        );
+        assert!(buf.starts_with(&format!("{}{}", "a".repeat(100), TRUNCATION_NOTICE)));
+        assert!(buf.ends_with("\n__DONE__"));
+        assert_eq!(
+            buf.len(),
+            100 + TRUNCATION_NOTICE.len() + CAPTURE_TAIL_BYTES
+        );
+    }
+}
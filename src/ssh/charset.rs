@@ -0,0 +1,105 @@
+//! Character sets of remote shells. Output is decoded from the host's
+//! charset instead of assumed to be UTF-8, and commands are encoded to it.
+
+use encoding_rs::{Encoding, UTF_8};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::shell::ShellKind;
+
+/// How the bytes a shell prints are decoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    /// Asks the host for its locale's charmap when the shell opens.
+    Auto,
+    Fixed(&'static Encoding),
+}
+
+impl Default for Charset {
+    fn default() -> Self {
+        Charset::Fixed(UTF_8)
+    }
+}
+
+impl std::fmt::Display for Charset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Charset::Auto => write!(f, "auto"),
+            Charset::Fixed(encoding) => write!(f, "{}", encoding.name()),
+        }
+    }
+}
+
+impl std::str::FromStr for Charset {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().eq_ignore_ascii_case("auto") {
+            return Ok(Charset::Auto);
+        }
+        from_label(s)
+            .map(Charset::Fixed)
+            .ok_or_else(|| anyhow::anyhow!("Unknown charset '{}'", s))
+    }
+}
+
+impl Serialize for Charset {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Charset {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// The encoding of a charset name as `locale charmap` or a user prints it
+/// (`KOI8-R`, `latin1`, `Shift_JIS`, `cp1251`). ASCII is read as UTF-8, a
+/// superset that also keeps UTF-8 file names intact in the C locale.
+pub fn from_label(label: &str) -> Option<&'static Encoding> {
+    let label = label.trim();
+    let ascii = ["ANSI_X3.4-1968", "ASCII", "US-ASCII", "646"];
+    if ascii.iter().any(|a| label.eq_ignore_ascii_case(a)) {
+        return Some(UTF_8);
+    }
+    Encoding::for_label(label.as_bytes())
+}
+
+/// Shell line asking for the locale's charmap, printed after `marker:`.
+pub fn charmap_command(shell: ShellKind, marker: &str) -> String {
+    match shell {
+        ShellKind::Fish => format!("echo {}:(locale charmap 2>/dev/null)", marker),
+        _ => format!("echo {}:$(locale charmap 2>/dev/null)", marker),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_charset_labels() {
+        assert_eq!("auto".parse::<Charset>().unwrap(), Charset::Auto);
+        let koi8: Charset = "koi8-r".parse().unwrap();
+        assert_eq!(koi8, Charset::Fixed(encoding_rs::KOI8_R));
+        assert_eq!(koi8.to_string(), "KOI8-R");
+        assert_eq!(
+            "Shift_JIS".parse::<Charset>().unwrap(),
+            Charset::Fixed(encoding_rs::SHIFT_JIS)
+        );
+        assert_eq!(from_label("latin1"), Some(encoding_rs::WINDOWS_1252));
+        assert_eq!(from_label("ANSI_X3.4-1968\r\n"), Some(UTF_8));
+        assert!("klingon".parse::<Charset>().is_err());
+
+        let tuning: std::collections::HashMap<String, Charset> =
+            serde_json::from_str(r#"{"charset": "cp1251"}"#).unwrap();
+        assert_eq!(tuning["charset"], Charset::Fixed(encoding_rs::WINDOWS_1251));
+        assert_eq!(
+            serde_json::to_string(&tuning["charset"]).unwrap(),
+            "\"windows-1251\""
+        );
+    }
+}
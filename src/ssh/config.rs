@@ -1,20 +1,32 @@
 use anyhow::{Context, Result};
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-#[derive(Debug, Clone)]
+use super::known_hosts::HostKeyChecking;
+use crate::error::SshLiaisonError;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SshHostConfig {
-    #[allow(dead_code)]
     pub host: String,
     pub hostname: Option<String>,
     pub user: Option<String>,
     pub port: Option<u16>,
-    pub identity_file: Option<PathBuf>,
+    /// Every `IdentityFile` of the matching blocks, tried in order.
+    pub identity_files: Vec<PathBuf>,
+    /// `CertificateFile`s, each presented with the identity it certifies.
+    pub certificate_files: Vec<PathBuf>,
     pub proxy_command: Option<String>,
     pub proxy_use_fdpass: bool,
     pub identities_only: bool,
+    /// `ServerAliveInterval` in seconds; 0 turns keepalives off.
+    pub server_alive_interval: Option<u32>,
+    /// `IdentityAgent`: a socket path, `none`, or `SSH_AUTH_SOCK`/`$VAR`
+    /// naming an environment variable.
+    pub identity_agent: Option<String>,
+    /// `StrictHostKeyChecking`; unset means `accept-new`.
+    pub strict_host_key_checking: Option<HostKeyChecking>,
 }
 
 fn expand_path(path_str: &str, home: &str) -> PathBuf {
@@ -27,29 +39,57 @@ fn expand_path(path_str: &str, home: &str) -> PathBuf {
     }
 }
 
-fn parse_include_directive(line: &str, home: &str) -> Vec<PathBuf> {
-    if !line.starts_with("Include ") {
+const SYSTEM_CONFIG_PATH: &str = "/etc/ssh/ssh_config";
+
+/// Paths named by an `Include` line, with `~` and glob patterns expanded.
+///
+/// Relative paths are resolved against `base_dir` (`~/.ssh` for the user config,
+/// `/etc/ssh` for the system one). Glob matches are returned in lexical order.
+fn parse_include_directive(line: &str, home: &str, base_dir: &Path) -> Vec<PathBuf> {
+    let Some((key, value)) = split_option(line) else {
+        return Vec::new();
+    };
+    if key != "include" {
         return Vec::new();
     }
 
-    let include_paths = line[8..].trim();
     let mut paths = Vec::new();
 
-    for path_str in include_paths.split_whitespace() {
-        let expanded = expand_path(path_str, home);
-        if expanded.exists() {
-            paths.push(expanded);
+    for path_str in value.split_whitespace() {
+        let expanded = expand_path(strip_quotes(path_str), home);
+        let expanded = if expanded.is_absolute() {
+            expanded
+        } else {
+            base_dir.join(expanded)
+        };
+
+        let pattern = expanded.to_string_lossy();
+        match glob::glob(&pattern) {
+            Ok(matches) => {
+                let mut matched: Vec<PathBuf> = matches.filter_map(|m| m.ok()).collect();
+                matched.sort();
+                paths.extend(matched.into_iter().filter(|p| p.is_file()));
+            }
+            Err(e) => {
+                tracing::debug!(pattern = %pattern, error = %e, "Invalid Include pattern");
+            }
         }
     }
 
     paths
 }
 
-fn read_config_file(path: &PathBuf, home: &str, visited: &mut HashSet<PathBuf>) -> Result<String> {
+/// Reads a config file with its `Include`s spliced in where they appear.
+fn read_config_file(
+    path: &Path,
+    home: &str,
+    base_dir: &Path,
+    visited: &mut HashSet<PathBuf>,
+) -> Result<String> {
     if visited.contains(path) {
         return Ok(String::new());
     }
-    visited.insert(path.clone());
+    visited.insert(path.to_path_buf());
 
     if !path.exists() {
         return Ok(String::new());
@@ -59,22 +99,21 @@ fn read_config_file(path: &PathBuf, home: &str, visited: &mut HashSet<PathBuf>)
         .with_context(|| format!("Failed to read SSH config from {}", path.display()))?;
 
     let mut result = String::new();
-    let mut includes = Vec::new();
 
     for line in content.lines() {
         let trimmed = line.trim();
 
-        if trimmed.is_empty() || trimmed.starts_with('#') {
-            result.push_str(line);
-            result.push('\n');
-            continue;
-        }
-
-        if trimmed.starts_with("Include ") {
-            let include_paths = parse_include_directive(trimmed, home);
-            for include_path in include_paths {
-                if let Ok(included_content) = read_config_file(&include_path, home, visited) {
-                    includes.push(included_content);
+        if !trimmed.is_empty()
+            && !trimmed.starts_with('#')
+            && let Some((key, _)) = split_option(trimmed)
+            && key == "include"
+        {
+            for include_path in parse_include_directive(trimmed, home, base_dir) {
+                match read_config_file(&include_path, home, base_dir, visited) {
+                    Ok(included) => result.push_str(&included),
+                    Err(e) => {
+                        tracing::debug!(path = %include_path.display(), error = %e, "Skipping Include");
+                    }
                 }
             }
             continue;
@@ -84,137 +123,538 @@ fn read_config_file(path: &PathBuf, home: &str, visited: &mut HashSet<PathBuf>)
         result.push('\n');
     }
 
-    let mut final_content = String::new();
-    for included in includes {
-        final_content.push_str(&included);
-    }
-    final_content.push_str(&result);
+    Ok(result)
+}
 
-    Ok(final_content)
+#[derive(Debug, Clone)]
+enum MatchCriterion {
+    All,
+    Host(String),
+    OriginalHost(String),
+    User(String),
+    LocalUser(String),
+    /// Criteria we can't evaluate (`exec`, `canonical`, ...) never match.
+    Unsupported(String),
 }
 
-pub fn parse_ssh_config(host_alias: &str) -> Result<SshHostConfig> {
-    let home = std::env::var("HOME").context("HOME environment variable not set")?;
-    let config_path = PathBuf::from(&home).join(".ssh").join("config");
+#[derive(Debug, Clone)]
+enum BlockCondition {
+    /// Options before the first `Host`/`Match` line apply to every host.
+    Global,
+    Host(Vec<String>),
+    /// All criteria must hold; each may be negated with a leading `!`.
+    Match(Vec<(bool, MatchCriterion)>),
+}
 
-    if !config_path.exists() {
-        anyhow::bail!("SSH config file not found at {}", config_path.display());
-    }
+#[derive(Debug, Clone)]
+struct ConfigBlock {
+    condition: BlockCondition,
+    options: Vec<(String, String)>,
+}
 
-    let mut visited = HashSet::new();
-    let content = read_config_file(&config_path, &home, &mut visited)
-        .with_context(|| format!("Failed to read SSH config from {}", config_path.display()))?;
+fn parse_match_criteria(value: &str) -> Vec<(bool, MatchCriterion)> {
+    let mut criteria = Vec::new();
+    let mut tokens = value.split_whitespace();
+
+    while let Some(token) = tokens.next() {
+        let (negated, keyword) = match token.strip_prefix('!') {
+            Some(k) => (true, k),
+            None => (false, token),
+        };
+        let keyword = keyword.to_lowercase();
+        let criterion = match keyword.as_str() {
+            "all" => MatchCriterion::All,
+            "host" | "originalhost" | "user" | "localuser" => {
+                let arg = tokens.next().unwrap_or_default().to_string();
+                match keyword.as_str() {
+                    "host" => MatchCriterion::Host(arg),
+                    "originalhost" => MatchCriterion::OriginalHost(arg),
+                    "user" => MatchCriterion::User(arg),
+                    _ => MatchCriterion::LocalUser(arg),
+                }
+            }
+            "canonical" | "final" => MatchCriterion::Unsupported(keyword),
+            _ => {
+                tokens.next();
+                MatchCriterion::Unsupported(keyword)
+            }
+        };
+        criteria.push((negated, criterion));
+    }
 
-    tracing::trace!(config_length = content.len(), "Parsed SSH config");
+    criteria
+}
 
-    let mut current_host: Option<String> = None;
-    let mut hosts: HashMap<String, SshHostConfig> = HashMap::new();
+/// Splits `Keyword value` or `Keyword=value` into a lowercase keyword and its value.
+fn split_option(line: &str) -> Option<(String, String)> {
+    let line = line.trim();
+    let key_end = line.find(|c: char| c.is_whitespace() || c == '=')?;
+    let (key, rest) = line.split_at(key_end);
+    let value = rest.trim_start();
+    let value = value.strip_prefix('=').unwrap_or(value).trim();
+    if value.is_empty() {
+        return None;
+    }
+    Some((key.to_lowercase(), value.to_string()))
+}
 
-    tracing::trace!(lines = content.lines().count(), "Starting config parsing");
+fn parse_blocks(content: &str) -> Vec<ConfigBlock> {
+    let mut blocks = vec![ConfigBlock {
+        condition: BlockCondition::Global,
+        options: Vec::new(),
+    }];
 
     for line in content.lines() {
         let line = line.trim();
-
         if line.is_empty() || line.starts_with('#') {
             continue;
         }
-
-        if line.starts_with("Include ") {
+        let Some((key, value)) = split_option(line) else {
             continue;
+        };
+
+        match key.as_str() {
+            "include" => {}
+            "host" => blocks.push(ConfigBlock {
+                condition: BlockCondition::Host(
+                    value.split_whitespace().map(str::to_string).collect(),
+                ),
+                options: Vec::new(),
+            }),
+            "match" => blocks.push(ConfigBlock {
+                condition: BlockCondition::Match(parse_match_criteria(&value)),
+                options: Vec::new(),
+            }),
+            _ => {
+                if let Some(block) = blocks.last_mut() {
+                    block.options.push((key, value));
+                }
+            }
         }
+    }
+
+    blocks
+}
 
-        if line.to_lowercase().starts_with("host ") {
-            let host = line[5..].trim();
-            if !host.is_empty() {
-                current_host = Some(host.to_string());
-                if !hosts.contains_key(host) {
-                    hosts.insert(
-                        host.to_string(),
-                        SshHostConfig {
-                            host: host.to_string(),
-                            hostname: None,
-                            user: None,
-                            port: None,
-                            identity_file: None,
-                            proxy_command: None,
-                            proxy_use_fdpass: false,
-                            identities_only: false,
-                        },
-                    );
+/// OpenSSH wildcard match: `*` matches any sequence, `?` exactly one character.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    backtrack = Some((star_p, star_t + 1));
                 }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches a host against a pattern list (`Host` line or `Match host` argument).
+///
+/// Patterns are separated by whitespace or commas; a leading `!` negates. A matching
+/// negated pattern rejects the host even if other patterns match, and a list of only
+/// negated patterns never matches. Comparison is case-insensitive like OpenSSH.
+pub fn host_matches<S: AsRef<str>>(patterns: &[S], host: &str) -> bool {
+    let host = host.to_lowercase();
+    let mut matched = false;
+
+    for pattern in split_patterns(patterns) {
+        match pattern.strip_prefix('!') {
+            Some(negated) => {
+                if wildcard_match(negated, &host) {
+                    return false;
+                }
+            }
+            None => matched |= wildcard_match(&pattern, &host),
+        }
+    }
+
+    matched
+}
+
+/// Whether a negated pattern in `patterns` rejects `host`.
+pub fn host_negated<S: AsRef<str>>(patterns: &[S], host: &str) -> bool {
+    let host = host.to_lowercase();
+    split_patterns(patterns).any(|pattern| {
+        pattern
+            .strip_prefix('!')
+            .is_some_and(|p| wildcard_match(p, &host))
+    })
+}
+
+/// The lowercased patterns of a list, split on whitespace and commas.
+fn split_patterns<S: AsRef<str>>(patterns: &[S]) -> impl Iterator<Item = String> + '_ {
+    patterns
+        .iter()
+        .flat_map(|p| p.as_ref().split(|c: char| c == ',' || c.is_whitespace()))
+        .filter(|p| !p.is_empty())
+        .map(str::to_lowercase)
+}
+
+fn local_user() -> Option<String> {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .ok()
+        .filter(|u| !u.is_empty())
+}
+
+/// Evaluates `Match` criteria against the options obtained so far, like OpenSSH:
+/// `host` sees the HostName set by earlier blocks, `user` the remote user.
+fn match_criteria_hold(
+    criteria: &[(bool, MatchCriterion)],
+    config: &SshHostConfig,
+    host_alias: &str,
+) -> bool {
+    let local = local_user();
+    criteria.iter().all(|(negated, criterion)| {
+        let result = match criterion {
+            MatchCriterion::All => true,
+            MatchCriterion::Host(patterns) => {
+                let host = config.hostname.as_deref().unwrap_or(host_alias);
+                host_matches(&[patterns], host)
+            }
+            MatchCriterion::OriginalHost(patterns) => host_matches(&[patterns], host_alias),
+            MatchCriterion::User(patterns) => config
+                .user
+                .as_deref()
+                .or(local.as_deref())
+                .is_some_and(|u| host_matches(&[patterns], u)),
+            MatchCriterion::LocalUser(patterns) => local
+                .as_deref()
+                .is_some_and(|u| host_matches(&[patterns], u)),
+            MatchCriterion::Unsupported(keyword) => {
+                tracing::debug!(criterion = %keyword, "Unsupported Match criterion, block skipped");
+                false
             }
+        };
+        result != *negated
+    })
+}
+
+/// Substitutes OpenSSH percent tokens: `%h` host, `%p` port, `%r` remote user,
+/// `%n` original alias, `%u` local user, `%d` home directory, `%%` a literal `%`.
+/// Unknown tokens are left untouched.
+fn expand_tokens(value: &str, config: &SshHostConfig, home: &str) -> String {
+    if !value.contains('%') {
+        return value.to_string();
+    }
+
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
             continue;
         }
+        match chars.next() {
+            Some('%') => out.push('%'),
+            Some('h') => out.push_str(config.hostname.as_deref().unwrap_or(&config.host)),
+            Some('p') => out.push_str(&config.port.unwrap_or(22).to_string()),
+            Some('r') => out.push_str(config.user.as_deref().unwrap_or_default()),
+            Some('n') => out.push_str(&config.host),
+            Some('u') => out.push_str(&local_user().unwrap_or_default()),
+            Some('d') => out.push_str(home),
+            Some(other) => {
+                out.push('%');
+                out.push(other);
+            }
+            None => out.push('%'),
+        }
+    }
+    out
+}
 
-        if let Some(ref host) = current_host
-            && let Some(config) = hosts.get_mut(host)
-        {
-            let line_lower = line.to_lowercase();
-            if line_lower.starts_with("hostname ") {
-                let hostname = line[9..].trim().to_string();
-                tracing::trace!(host = %host, hostname = %hostname, "Setting hostname");
-                config.hostname = Some(hostname);
-            } else if line_lower.starts_with("user ") {
-                config.user = Some(line[5..].trim().to_string());
-            } else if line_lower.starts_with("port ") {
-                if let Ok(port) = line[5..].trim().parse::<u16>() {
-                    config.port = Some(port);
+fn parse_bool(value: &str) -> bool {
+    let value = value.to_lowercase();
+    value == "yes" || value == "true" || value == "1"
+}
+
+fn strip_quotes(value: &str) -> &str {
+    if value.len() >= 2
+        && ((value.starts_with('"') && value.ends_with('"'))
+            || (value.starts_with('\'') && value.ends_with('\'')))
+    {
+        &value[1..value.len() - 1]
+    } else {
+        value
+    }
+}
+
+/// Sets an option unless an earlier matching block already did (OpenSSH "first obtained value wins").
+/// `IdentityFile` and `CertificateFile` accumulate over all matching blocks instead.
+fn apply_option(
+    config: &mut SshHostConfig,
+    seen: &mut HashSet<String>,
+    key: &str,
+    value: &str,
+    home: &str,
+) {
+    let files = match key {
+        "identityfile" => Some(&mut config.identity_files),
+        "certificatefile" => Some(&mut config.certificate_files),
+        _ => None,
+    };
+    if let Some(files) = files {
+        let path = expand_path(strip_quotes(value), home);
+        if !files.contains(&path) {
+            files.push(path);
+        }
+        return;
+    }
+
+    if seen.contains(key) {
+        return;
+    }
+
+    let applied = match key {
+        "hostname" => {
+            config.hostname = Some(value.to_string());
+            true
+        }
+        "user" => {
+            config.user = Some(value.to_string());
+            true
+        }
+        "port" => value.parse::<u16>().map(|p| config.port = Some(p)).is_ok(),
+        "proxycommand" => {
+            config.proxy_command = Some(strip_quotes(value).to_string());
+            true
+        }
+        "proxyusefdpass" => {
+            config.proxy_use_fdpass = parse_bool(value);
+            true
+        }
+        "identitiesonly" => {
+            config.identities_only = parse_bool(value);
+            true
+        }
+        "identityagent" => {
+            config.identity_agent = Some(strip_quotes(value).to_string());
+            true
+        }
+        "serveraliveinterval" => value
+            .parse::<u32>()
+            .map(|secs| config.server_alive_interval = Some(secs))
+            .is_ok(),
+        "stricthostkeychecking" => HostKeyChecking::parse(value)
+            .map(|checking| config.strict_host_key_checking = Some(checking))
+            .is_some(),
+        _ => false,
+    };
+
+    if applied {
+        seen.insert(key.to_string());
+    }
+}
+
+/// Resolves the options for `host_alias` like `ssh -G`: blocks are evaluated in
+/// file order (user config, then system config) and the first value obtained for
+/// each option wins. Returns `None` unless a `Host` block from the user config
+/// matches the alias (`Match` blocks alone don't define a host).
+fn resolve_blocks(
+    user: &[ConfigBlock],
+    system: &[ConfigBlock],
+    host_alias: &str,
+    home: &str,
+) -> Option<SshHostConfig> {
+    let mut config = SshHostConfig {
+        host: host_alias.to_string(),
+        hostname: None,
+        user: None,
+        port: None,
+        identity_files: Vec::new(),
+        certificate_files: Vec::new(),
+        proxy_command: None,
+        proxy_use_fdpass: false,
+        identities_only: false,
+        server_alive_interval: None,
+        identity_agent: None,
+        strict_host_key_checking: None,
+    };
+    let mut seen = HashSet::new();
+    let mut matched = false;
+
+    let blocks = user
+        .iter()
+        .map(|b| (b, true))
+        .chain(system.iter().map(|b| (b, false)));
+    for (block, from_user) in blocks {
+        match block.condition {
+            BlockCondition::Global => {}
+            BlockCondition::Host(ref patterns) => {
+                if !host_matches(patterns, host_alias) {
+                    continue;
+                }
+                matched |= from_user;
+            }
+            BlockCondition::Match(ref criteria) => {
+                if !match_criteria_hold(criteria, &config, host_alias) {
+                    continue;
                 }
-            } else if line_lower.starts_with("identityfile ") {
-                let path_str = line[13..].trim();
-                let expanded_path = expand_path(path_str, &home);
-                config.identity_file = Some(expanded_path);
-            } else if line_lower.starts_with("proxycommand ") {
-                let cmd = line[13..].trim();
-                let cmd = if (cmd.starts_with('"') && cmd.ends_with('"'))
-                    || (cmd.starts_with('\'') && cmd.ends_with('\''))
-                {
-                    &cmd[1..cmd.len() - 1]
-                } else {
-                    cmd
-                };
-                config.proxy_command = Some(cmd.to_string());
-            } else if line_lower.starts_with("proxyusefdpass ") {
-                let value = line[15..].trim().to_lowercase();
-                config.proxy_use_fdpass = value == "yes" || value == "true" || value == "1";
-            } else if line_lower.starts_with("identitiesonly ") {
-                let value = line[15..].trim().to_lowercase();
-                config.identities_only = value == "yes" || value == "true" || value == "1";
             }
         }
+        for (key, value) in &block.options {
+            apply_option(&mut config, &mut seen, key, value, home);
+        }
     }
 
-    tracing::debug!(hosts_count = hosts.len(), "Found hosts in config");
+    if !matched {
+        return None;
+    }
 
-    if let Some(config) = hosts.get(host_alias) {
-        tracing::debug!(
-            host = %host_alias,
-            hostname = ?config.hostname,
-            user = ?config.user,
-            port = ?config.port,
-            "Found exact match"
-        );
-        return Ok(config.clone());
+    if config.hostname.is_none() {
+        config.hostname = Some(host_alias.to_string());
+    }
+    if config.user.is_none() {
+        config.user = local_user();
+    }
+
+    // HostName itself may only refer to the alias; the rest see the final values.
+    if let Some(hostname) = config.hostname.take() {
+        let alias_only = SshHostConfig {
+            hostname: None,
+            ..config.clone()
+        };
+        config.hostname = Some(expand_tokens(&hostname, &alias_only, home));
+    }
+    let expand_files = |files: &[PathBuf]| -> Vec<PathBuf> {
+        files
+            .iter()
+            .map(|file| expand_path(&expand_tokens(&file.to_string_lossy(), &config, home), home))
+            .collect()
+    };
+    let identity_files = expand_files(&config.identity_files);
+    let certificate_files = expand_files(&config.certificate_files);
+    config.identity_files = identity_files;
+    config.certificate_files = certificate_files;
+    if let Some(ref proxy_command) = config.proxy_command {
+        config.proxy_command = Some(expand_tokens(proxy_command, &config, home));
+    }
+    if let Some(ref identity_agent) = config.identity_agent {
+        let expanded = expand_tokens(identity_agent, &config, home);
+        config.identity_agent = Some(expand_path(&expanded, home).to_string_lossy().into_owned());
     }
 
-    for (host_pattern, config) in &hosts {
-        if host_pattern.contains('*') {
-            let pattern = host_pattern.replace("*", ".*");
-            if let Ok(re) = regex::Regex::new(&format!("^{}$", pattern))
-                && re.is_match(host_alias)
-            {
-                return Ok(config.clone());
+    Some(config)
+}
+
+/// User and system config, with includes spliced in, split into blocks.
+struct LoadedConfig {
+    home: String,
+    user: Vec<ConfigBlock>,
+    system: Vec<ConfigBlock>,
+}
+
+fn load_config() -> Result<LoadedConfig> {
+    let home = std::env::var("HOME").context("HOME environment variable not set")?;
+    let user_dir = PathBuf::from(&home).join(".ssh");
+    let config_path = user_dir.join("config");
+
+    if !config_path.exists() {
+        return Err(SshLiaisonError::Config(format!(
+            "SSH config file not found at {}",
+            config_path.display()
+        ))
+        .into());
+    }
+
+    let mut visited = HashSet::new();
+    let content = read_config_file(&config_path, &home, &user_dir, &mut visited)
+        .with_context(|| format!("Failed to read SSH config from {}", config_path.display()))?;
+
+    let system_path = Path::new(SYSTEM_CONFIG_PATH);
+    let system_dir = system_path.parent().unwrap_or(Path::new("/"));
+    let system_content = read_config_file(system_path, &home, system_dir, &mut visited)
+        .unwrap_or_else(|e| {
+            tracing::debug!(error = %e, "Ignoring system SSH config");
+            String::new()
+        });
+
+    tracing::trace!(
+        config_length = content.len(),
+        system_config_length = system_content.len(),
+        "Parsed SSH config"
+    );
+
+    Ok(LoadedConfig {
+        user: parse_blocks(&content),
+        system: parse_blocks(&system_content),
+        home,
+    })
+}
+
+/// Concrete aliases named on `Host` lines, in file order, skipping wildcard
+/// and negated patterns.
+fn concrete_aliases(blocks: &[ConfigBlock]) -> Vec<String> {
+    let mut aliases: Vec<String> = Vec::new();
+    for block in blocks {
+        let BlockCondition::Host(ref patterns) = block.condition else {
+            continue;
+        };
+        for pattern in patterns.iter().flat_map(|p| p.split(',')) {
+            if pattern.is_empty() || pattern.contains(['*', '?', '!']) {
+                continue;
+            }
+            if !aliases.iter().any(|a| a == pattern) {
+                aliases.push(pattern.to_string());
             }
         }
     }
+    aliases
+}
+
+pub fn parse_ssh_config(host_alias: &str) -> Result<SshHostConfig> {
+    let loaded = load_config()?;
+
+    let config = resolve_blocks(&loaded.user, &loaded.system, host_alias, &loaded.home)
+        .ok_or_else(|| SshLiaisonError::HostNotFound(host_alias.to_string()))?;
+
+    tracing::debug!(
+        host = %host_alias,
+        hostname = ?config.hostname,
+        user = ?config.user,
+        port = ?config.port,
+        "Resolved host config"
+    );
+
+    Ok(config)
+}
 
-    anyhow::bail!("Host '{}' not found in SSH config", host_alias)
+/// Every concrete `Host` alias in the user config, resolved.
+pub fn list_ssh_hosts() -> Result<Vec<SshHostConfig>> {
+    let loaded = load_config()?;
+    Ok(concrete_aliases(&loaded.user)
+        .iter()
+        .filter_map(|alias| resolve_blocks(&loaded.user, &loaded.system, alias, &loaded.home))
+        .collect())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn resolve_host_config(content: &str, host_alias: &str, home: &str) -> Option<SshHostConfig> {
+        resolve_blocks(&parse_blocks(content), &[], host_alias, home)
+    }
+
     #[test]
     fn test_expand_path() {
         let home = "/home/user";
@@ -233,10 +673,11 @@ mod tests {
     #[test]
     fn test_parse_include_directive_helper() {
         let home = "/home/user";
-        let result = parse_include_directive("Include /nonexistent/path", home);
+        let base = Path::new("/home/user/.ssh");
+        let result = parse_include_directive("Include /nonexistent/path", home, base);
         assert!(result.is_empty());
 
-        let result = parse_include_directive("NotInclude something", home);
+        let result = parse_include_directive("NotInclude something", home, base);
         assert!(result.is_empty());
     }
 
@@ -297,4 +738,231 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("web-?", "web-1"));
+        assert!(!wildcard_match("web-?", "web-10"));
+        assert!(wildcard_match("*.internal", "db.internal"));
+        assert!(!wildcard_match("*.internal", "db.internal.example"));
+        assert!(wildcard_match("a*b*c", "aXXbYYc"));
+        assert!(!wildcard_match("a*b*c", "aXXbYY"));
+        assert!(wildcard_match("*", ""));
+        assert!(!wildcard_match("db.internal", "dbxinternal"));
+    }
+
+    #[test]
+    fn test_host_pattern_lists() {
+        assert!(host_matches(&["web-1 web-2"], "web-2"));
+        assert!(host_matches(&["db,web-?"], "web-3"));
+        assert!(host_matches(&["* !prod-*"], "staging-1"));
+        assert!(!host_matches(&["* !prod-*"], "prod-db"));
+        assert!(!host_matches(&["!prod-*"], "staging-1"));
+        assert!(host_matches(&["WEB-*"], "web-1"));
+        assert!(!host_matches::<&str>(&[], "web-1"));
+    }
+
+    #[test]
+    fn test_first_obtained_value_wins() {
+        let content = "\
+Host web1
+    HostName 10.0.0.1
+    User deploy
+
+Host web*
+    User admin
+    Port 2222
+
+Host *
+    Port 22
+    IdentityFile ~/.ssh/id_ed25519
+";
+        let config = resolve_host_config(content, "web1", "/home/user").unwrap();
+        assert_eq!(config.hostname.as_deref(), Some("10.0.0.1"));
+        assert_eq!(config.user.as_deref(), Some("deploy"));
+        assert_eq!(config.port, Some(2222));
+        assert_eq!(
+            config.identity_files,
+            [PathBuf::from("/home/user/.ssh/id_ed25519")]
+        );
+
+        let config = resolve_host_config(content, "web2", "/home/user").unwrap();
+        assert_eq!(config.hostname.as_deref(), Some("web2"));
+        assert_eq!(config.user.as_deref(), Some("admin"));
+    }
+
+    #[test]
+    fn test_identity_files_accumulate() {
+        let content = "\
+Host web*
+    IdentityFile ~/.ssh/web_key
+    CertificateFile ~/.ssh/web_key-cert.pub
+
+Host *
+    IdentityFile ~/.ssh/id_ed25519
+    IdentityFile ~/.ssh/web_key
+";
+        let config = resolve_host_config(content, "web1", "/home/user").unwrap();
+        assert_eq!(
+            config.identity_files,
+            [
+                PathBuf::from("/home/user/.ssh/web_key"),
+                PathBuf::from("/home/user/.ssh/id_ed25519")
+            ]
+        );
+        assert_eq!(
+            config.certificate_files,
+            [PathBuf::from("/home/user/.ssh/web_key-cert.pub")]
+        );
+    }
+
+    #[test]
+    fn test_global_options_and_equals_syntax() {
+        let content = "\
+IdentitiesOnly=yes
+ServerAliveInterval 15
+StrictHostKeyChecking accept-new
+Host db
+    HostName=db.internal
+    Port = 5022
+    ServerAliveInterval=60
+    StrictHostKeyChecking=yes
+Host db
+    HostName ignored.example
+";
+        let config = resolve_host_config(content, "db", "/home/user").unwrap();
+        assert!(config.identities_only);
+        assert_eq!(config.server_alive_interval, Some(15));
+        assert_eq!(
+            config.strict_host_key_checking,
+            Some(HostKeyChecking::AcceptNew)
+        );
+        assert_eq!(config.hostname.as_deref(), Some("db.internal"));
+        assert_eq!(config.port, Some(5022));
+
+        assert!(resolve_host_config(content, "other", "/home/user").is_none());
+    }
+
+    #[test]
+    fn test_match_blocks() {
+        let content = "\
+Host app
+    HostName app.internal
+Host db
+    HostName db.example.com
+    User deploy
+Match host *.internal
+    Port 2200
+Match originalhost db user deploy
+    IdentityFile ~/.ssh/deploy_key
+Match !host *.internal all
+    ProxyCommand ssh -W %h:%p bastion
+Match exec \"true\"
+    User nobody
+";
+        let app = resolve_host_config(content, "app", "/home/user").unwrap();
+        assert_eq!(app.port, Some(2200));
+        assert!(app.identity_files.is_empty());
+        assert!(app.proxy_command.is_none());
+        assert_ne!(app.user.as_deref(), Some("nobody"));
+
+        let db = resolve_host_config(content, "db", "/home/user").unwrap();
+        assert_eq!(db.port, None);
+        assert_eq!(
+            db.identity_files,
+            [PathBuf::from("/home/user/.ssh/deploy_key")]
+        );
+        assert_eq!(
+            db.proxy_command.as_deref(),
+            Some("ssh -W db.example.com:22 bastion")
+        );
+        assert_eq!(db.user.as_deref(), Some("deploy"));
+    }
+
+    #[test]
+    fn test_percent_token_expansion() {
+        let content = "\
+Host jump-*
+    HostName %h.corp.example
+    User ops
+    Port 2022
+    IdentityFile ~/.ssh/%r_key
+    CertificateFile ~/.ssh/%r_key-cert.pub
+    IdentityAgent ~/.agents/%h.sock
+    ProxyCommand ssh -W %h:%p -l %r bastion # 100%%
+";
+        let config = resolve_host_config(content, "jump-1", "/home/user").unwrap();
+        assert_eq!(config.hostname.as_deref(), Some("jump-1.corp.example"));
+        assert_eq!(
+            config.identity_files,
+            [PathBuf::from("/home/user/.ssh/ops_key")]
+        );
+        assert_eq!(
+            config.certificate_files,
+            [PathBuf::from("/home/user/.ssh/ops_key-cert.pub")]
+        );
+        assert_eq!(
+            config.identity_agent.as_deref(),
+            Some("/home/user/.agents/jump-1.corp.example.sock")
+        );
+        assert_eq!(
+            config.proxy_command.as_deref(),
+            Some("ssh -W jump-1.corp.example:2022 -l ops bastion # 100%")
+        );
+    }
+
+    #[test]
+    fn test_include_globs_are_spliced_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path();
+        fs::create_dir(base.join("config.d")).unwrap();
+        fs::write(
+            base.join("config.d/b.conf"),
+            "Host b\n    HostName b.example\n",
+        )
+        .unwrap();
+        fs::write(
+            base.join("config.d/a.conf"),
+            "Host a\n    HostName a.example\n",
+        )
+        .unwrap();
+        fs::write(base.join("config.d/ignored.txt"), "Host c\n").unwrap();
+        fs::write(
+            base.join("config"),
+            "Host first\n    User one\nInclude config.d/*.conf\nHost last\n",
+        )
+        .unwrap();
+
+        let mut visited = HashSet::new();
+        let content =
+            read_config_file(&base.join("config"), "/home/user", base, &mut visited).unwrap();
+        let hosts: Vec<&str> = content
+            .lines()
+            .filter_map(|l| l.strip_prefix("Host "))
+            .collect();
+        assert_eq!(hosts, vec!["first", "a", "b", "last"]);
+    }
+
+    #[test]
+    fn test_system_config_fills_unset_options() {
+        let user = parse_blocks("Host web\n    Port 2222\n");
+        let system = parse_blocks("Host *\n    Port 22\n    User sysuser\n");
+
+        let config = resolve_blocks(&user, &system, "web", "/home/user").unwrap();
+        assert_eq!(config.port, Some(2222));
+        assert_eq!(config.user.as_deref(), Some("sysuser"));
+
+        assert!(resolve_blocks(&user, &system, "unknown", "/home/user").is_none());
+    }
+
+    #[test]
+    fn test_concrete_aliases() {
+        let blocks = parse_blocks(
+            "Host *\n    User x\nHost web1 web2,db1\nHost web-? !prod\nMatch all\nHost db1 cache\n",
+        );
+        assert_eq!(
+            concrete_aliases(&blocks),
+            vec!["web1", "web2", "db1", "cache"]
+        );
+    }
 }
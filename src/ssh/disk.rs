@@ -0,0 +1,233 @@
+//! "Why is the disk full": filesystem usage from `df` plus the largest
+//! directories under a path from a bounded `du`, in one round trip.
+
+use anyhow::Result;
+use serde::Serialize;
+
+use super::search::MAX_SEARCH_TIMEOUT_SECS;
+use super::shell::quote;
+use crate::error::SshLiaisonError;
+
+pub const DEFAULT_DU_DEPTH: u32 = 2;
+pub const MAX_DU_DEPTH: u32 = 6;
+pub const DEFAULT_TOP_DIRS: usize = 10;
+pub const MAX_TOP_DIRS: usize = 100;
+
+/// Pseudo and in-memory filesystems that only add noise to the usage table.
+const IGNORED_FS_TYPES: &[&str] = &["tmpfs", "devtmpfs", "squashfs", "overlay", "efivarfs"];
+
+const SECTION: &str = "@@disk";
+
+#[derive(Debug)]
+pub struct DiskUsageQuery<'a> {
+    pub path: &'a str,
+    pub depth: u32,
+    pub top: usize,
+    pub timeout_secs: u32,
+}
+
+impl DiskUsageQuery<'_> {
+    /// Read-only `df` of every real filesystem, then `du` of `path` on its
+    /// own filesystem under `timeout`, largest first. A `du` that times out
+    /// still prints what it summed so far.
+    pub fn command(&self) -> Result<String> {
+        let invalid = |message: String| Err(SshLiaisonError::Config(message).into());
+        if self.path.is_empty() {
+            return invalid("path must not be empty".to_string());
+        }
+        if self.depth == 0 || self.depth > MAX_DU_DEPTH {
+            return invalid(format!("depth must be between 1 and {}", MAX_DU_DEPTH));
+        }
+        if self.top == 0 || self.top > MAX_TOP_DIRS {
+            return invalid(format!("top must be between 1 and {}", MAX_TOP_DIRS));
+        }
+        if self.timeout_secs == 0 || self.timeout_secs > MAX_SEARCH_TIMEOUT_SECS {
+            return invalid(format!(
+                "timeout_secs must be between 1 and {}",
+                MAX_SEARCH_TIMEOUT_SECS
+            ));
+        }
+
+        let exclude: String = IGNORED_FS_TYPES
+            .iter()
+            .map(|t| format!(" -x {}", t))
+            .collect();
+        // Sorted, the path's own total comes first; one more line than `top`
+        // keeps it next to the largest subdirectories.
+        Ok([
+            format!("echo '{} df'", SECTION),
+            format!("LC_ALL=C df -P -k -T{} 2>/dev/null", exclude),
+            format!("echo '{} inodes'", SECTION),
+            format!("LC_ALL=C df -P -i{} 2>/dev/null", exclude),
+            format!("echo '{} du'", SECTION),
+            format!(
+                "LC_ALL=C timeout {} du -x -k --max-depth={} {} 2>/dev/null | sort -rn | head -n {}",
+                self.timeout_secs,
+                self.depth,
+                quote(self.path),
+                self.top + 1
+            ),
+        ]
+        .join("; "))
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FilesystemUsage {
+    pub filesystem: String,
+    #[serde(rename = "type")]
+    pub fs_type: String,
+    pub mount: String,
+    pub size_kb: u64,
+    pub used_kb: u64,
+    pub available_kb: u64,
+    pub used_percent: Option<u8>,
+    /// Missing on filesystems without a fixed inode table, e.g. btrfs.
+    pub inodes_used_percent: Option<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DirectoryUsage {
+    pub path: String,
+    pub size_kb: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DiskUsage {
+    /// Fullest first.
+    pub filesystems: Vec<FilesystemUsage>,
+    /// Size of the whole path, when `du` got to print it.
+    pub total_kb: Option<u64>,
+    /// Largest first, excluding the path itself.
+    pub directories: Vec<DirectoryUsage>,
+}
+
+/// Parses [`DiskUsageQuery::command`] output, keeping `top` directories.
+pub fn parse_disk_usage(output: &str, path: &str, top: usize) -> DiskUsage {
+    let mut usage = DiskUsage::default();
+    let mut section = "";
+    let root = path.trim_end_matches('/');
+
+    for line in output.lines() {
+        if let Some(header) = line.strip_prefix(SECTION) {
+            section = match header.trim() {
+                "df" => "df",
+                "inodes" => "inodes",
+                "du" => "du",
+                _ => "",
+            };
+            continue;
+        }
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match section {
+            // Filesystem Type 1024-blocks Used Available Capacity Mounted-on
+            "df" if fields.len() >= 7 && fields[0] != "Filesystem" => {
+                usage.filesystems.push(FilesystemUsage {
+                    filesystem: fields[0].to_string(),
+                    fs_type: fields[1].to_string(),
+                    size_kb: fields[2].parse().unwrap_or(0),
+                    used_kb: fields[3].parse().unwrap_or(0),
+                    available_kb: fields[4].parse().unwrap_or(0),
+                    used_percent: percent(fields[5]),
+                    mount: fields[6..].join(" "),
+                    inodes_used_percent: None,
+                });
+            }
+            // Filesystem Inodes IUsed IFree IUse% Mounted-on
+            "inodes" if fields.len() >= 6 && fields[0] != "Filesystem" => {
+                let mount = fields[5..].join(" ");
+                if let Some(fs) = usage.filesystems.iter_mut().find(|f| f.mount == mount) {
+                    fs.inodes_used_percent = percent(fields[4]);
+                }
+            }
+            "du" => {
+                let Some((size, dir)) = line.split_once('\t') else {
+                    continue;
+                };
+                let Ok(size_kb) = size.trim().parse() else {
+                    continue;
+                };
+                if dir.trim_end_matches('/') == root {
+                    usage.total_kb = Some(size_kb);
+                } else {
+                    usage.directories.push(DirectoryUsage {
+                        path: dir.to_string(),
+                        size_kb,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    usage
+        .filesystems
+        .sort_by_key(|f| std::cmp::Reverse(f.used_percent));
+    usage.directories.truncate(top);
+    usage
+}
+
+/// `df` prints `-` for inode use on filesystems that don't track it.
+fn percent(field: &str) -> Option<u8> {
+    field.trim_end_matches('%').parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disk_usage_command() {
+        let query = DiskUsageQuery {
+            path: "/var",
+            depth: 2,
+            top: 5,
+            timeout_secs: 10,
+        };
+        let command = query.command().unwrap();
+        assert!(command.contains("timeout 10 du -x -k --max-depth=2 /var"));
+        assert!(command.ends_with("| sort -rn | head -n 6"));
+        assert!(crate::policy::is_read_only_command(&command));
+
+        let deep = DiskUsageQuery { depth: 20, ..query };
+        assert!(deep.command().is_err());
+    }
+
+    #[test]
+    fn test_parse_disk_usage() {
+        let output = "\
+@@disk df
+Filesystem     Type 1024-blocks     Used Available Capacity Mounted on
+/dev/sda1      ext4    41152736 37037462   4115274      91% /
+/dev/sdb1      xfs    104857600 10485760  94371840      10% /data disk
+@@disk inodes
+Filesystem      Inodes  IUsed   IFree IUse% Mounted on
+/dev/sda1      2621440 262144 2359296   10% /
+/dev/sdb1            0      0       0     - /data disk
+@@disk du
+20971520\t/var/
+15728640\t/var/lib
+10485760\t/var/lib/docker
+4194304\t/var/log
+";
+        let usage = parse_disk_usage(output, "/var/", 2);
+        assert_eq!(usage.filesystems.len(), 2);
+        assert_eq!(usage.filesystems[0].mount, "/");
+        assert_eq!(usage.filesystems[0].inodes_used_percent, Some(10));
+        assert_eq!(usage.filesystems[1].mount, "/data disk");
+        assert_eq!(usage.filesystems[1].inodes_used_percent, None);
+        assert_eq!(usage.total_kb, Some(20971520));
+        assert_eq!(
+            usage.directories,
+            [
+                DirectoryUsage {
+                    path: "/var/lib".to_string(),
+                    size_kb: 15728640
+                },
+                DirectoryUsage {
+                    path: "/var/lib/docker".to_string(),
+                    size_kb: 10485760
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,244 @@
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::shell::quote;
+use crate::error::SshLiaisonError;
+
+pub const DEFAULT_LOG_LINES: u32 = 100;
+pub const MAX_LOG_LINES: u32 = 5000;
+
+/// One JSON object per line; the same template works for `ps` and `stats`.
+const JSON_FORMAT: &str = "--format '{{json .}}'";
+
+/// `docker`, or `sudo -n docker` for hosts where the user isn't in the
+/// `docker` group; `-n` fails instead of hanging on a password prompt.
+fn docker(sudo: bool) -> &'static str {
+    if sudo { "sudo -n docker" } else { "docker" }
+}
+
+/// Accepts container names and IDs only, so nothing else reaches the shell.
+fn check_container(container: &str) -> Result<()> {
+    let valid = container.len() <= 128
+        && container
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphanumeric())
+        && container
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '-'));
+    if !valid {
+        return Err(SshLiaisonError::Config(format!(
+            "'{}' is not a container name or ID",
+            container
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+pub fn ps_command(all: bool, sudo: bool) -> String {
+    format!(
+        "{} ps --no-trunc {}{}",
+        docker(sudo),
+        JSON_FORMAT,
+        if all { " --all" } else { "" }
+    )
+}
+
+/// `docker logs`, with the container's stderr folded into stdout in order.
+pub fn logs_command(
+    container: &str,
+    lines: u32,
+    since: Option<&str>,
+    timestamps: bool,
+    sudo: bool,
+) -> Result<String> {
+    check_container(container)?;
+    if lines == 0 || lines > MAX_LOG_LINES {
+        return Err(SshLiaisonError::Config(format!(
+            "lines must be between 1 and {}",
+            MAX_LOG_LINES
+        ))
+        .into());
+    }
+    let mut command = format!("{} logs --tail {}", docker(sudo), lines);
+    if let Some(since) = since {
+        command.push_str(&format!(" --since {}", quote(since)));
+    }
+    if timestamps {
+        command.push_str(" --timestamps");
+    }
+    command.push_str(&format!(" {} 2>&1", container));
+    Ok(command)
+}
+
+pub fn inspect_command(container: &str, sudo: bool) -> Result<String> {
+    check_container(container)?;
+    Ok(format!(
+        "{} inspect --type container {}",
+        docker(sudo),
+        container
+    ))
+}
+
+/// A single sample of every running container, or of `containers`.
+pub fn stats_command(containers: &[String], sudo: bool) -> Result<String> {
+    let mut command = format!(
+        "{} stats --no-stream --no-trunc {}",
+        docker(sudo),
+        JSON_FORMAT
+    );
+    for container in containers {
+        check_container(container)?;
+        command.push(' ');
+        command.push_str(container);
+    }
+    Ok(command)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContainerSummary {
+    #[serde(rename(deserialize = "ID"))]
+    pub id: String,
+    #[serde(rename(deserialize = "Names"))]
+    pub name: String,
+    #[serde(rename(deserialize = "Image"))]
+    pub image: String,
+    #[serde(rename(deserialize = "Command"), default)]
+    pub command: String,
+    /// `running`, `exited`, ...; missing from Docker before 20.10.
+    #[serde(rename(deserialize = "State"), default)]
+    pub state: Option<String>,
+    /// Human status such as `Up 3 hours (healthy)`.
+    #[serde(rename(deserialize = "Status"), default)]
+    pub status: String,
+    #[serde(rename(deserialize = "Ports"), default)]
+    pub ports: String,
+    #[serde(rename(deserialize = "CreatedAt"), default)]
+    pub created_at: String,
+}
+
+#[derive(Deserialize)]
+struct RawStats {
+    #[serde(rename = "ID", default)]
+    id: String,
+    #[serde(rename = "Name", default)]
+    name: String,
+    #[serde(rename = "CPUPerc", default)]
+    cpu: String,
+    #[serde(rename = "MemUsage", default)]
+    mem_usage: String,
+    #[serde(rename = "MemPerc", default)]
+    mem: String,
+    #[serde(rename = "NetIO", default)]
+    net_io: String,
+    #[serde(rename = "BlockIO", default)]
+    block_io: String,
+    #[serde(rename = "PIDs", default)]
+    pids: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ContainerStats {
+    pub id: String,
+    pub name: String,
+    /// Percent of one CPU; above 100 on multi-core hosts.
+    pub cpu_percent: Option<f64>,
+    pub mem_percent: Option<f64>,
+    /// `used / limit`, e.g. `512MiB / 1.9GiB`.
+    pub mem_usage: String,
+    pub net_io: String,
+    pub block_io: String,
+    pub pids: Option<u32>,
+}
+
+impl From<RawStats> for ContainerStats {
+    fn from(raw: RawStats) -> Self {
+        let percent = |value: &str| value.trim().trim_end_matches('%').parse().ok();
+        Self {
+            cpu_percent: percent(&raw.cpu),
+            mem_percent: percent(&raw.mem),
+            pids: raw.pids.trim().parse().ok(),
+            id: raw.id,
+            name: raw.name,
+            mem_usage: raw.mem_usage,
+            net_io: raw.net_io,
+            block_io: raw.block_io,
+        }
+    }
+}
+
+/// Objects printed by `--format '{{json .}}'`; other lines are skipped.
+fn json_lines<T: DeserializeOwned>(output: &str) -> Vec<T> {
+    output
+        .lines()
+        .filter_map(|line| serde_json::from_str(line.trim()).ok())
+        .collect()
+}
+
+pub fn parse_ps(output: &str) -> Vec<ContainerSummary> {
+    json_lines(output)
+}
+
+pub fn parse_stats(output: &str) -> Vec<ContainerStats> {
+    json_lines::<RawStats>(output)
+        .into_iter()
+        .map(ContainerStats::from)
+        .collect()
+}
+
+/// The single object of `docker inspect` output.
+pub fn parse_inspect(output: &str) -> Result<Value> {
+    let mut objects: Vec<Value> = serde_json::from_str(output.trim())?;
+    if objects.is_empty() {
+        anyhow::bail!("docker inspect returned no container");
+    }
+    Ok(objects.swap_remove(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_docker_commands() {
+        assert_eq!(
+            ps_command(true, false),
+            "docker ps --no-trunc --format '{{json .}}' --all"
+        );
+        assert_eq!(
+            logs_command("web-1", 50, Some("10m"), true, true).unwrap(),
+            "sudo -n docker logs --tail 50 --since 10m --timestamps web-1 2>&1"
+        );
+        assert!(logs_command("web; reboot", 50, None, false, false).is_err());
+        assert!(logs_command("web", 0, None, false, false).is_err());
+        assert!(inspect_command("-f", false).is_err());
+        assert_eq!(
+            stats_command(&["db".to_string()], false).unwrap(),
+            "docker stats --no-stream --no-trunc --format '{{json .}}' db"
+        );
+    }
+
+    #[test]
+    fn test_parse_docker_output() {
+        let ps = r#"{"Command":"\"nginx -g\"","CreatedAt":"2024-05-01 10:00:00 +0000 UTC","ID":"3f2a","Image":"nginx:1.25","Names":"web","Ports":"0.0.0.0:80->80/tcp","State":"running","Status":"Up 3 hours"}
+permission denied
+"#;
+        let containers = parse_ps(ps);
+        assert_eq!(containers.len(), 1);
+        assert_eq!(containers[0].name, "web");
+        assert_eq!(containers[0].state.as_deref(), Some("running"));
+
+        let stats = r#"{"BlockIO":"1MB / 0B","CPUPerc":"153.20%","ID":"3f2a","MemPerc":"12.50%","MemUsage":"256MiB / 2GiB","Name":"web","NetIO":"1kB / 2kB","PIDs":"9"}"#;
+        let stats = parse_stats(stats);
+        assert_eq!(stats[0].cpu_percent, Some(153.2));
+        assert_eq!(stats[0].mem_percent, Some(12.5));
+        assert_eq!(stats[0].pids, Some(9));
+
+        let inspect = parse_inspect(r#"[{"Id":"3f2a","State":{"Status":"running"}}]"#).unwrap();
+        assert_eq!(inspect["State"]["Status"], "running");
+        assert!(parse_inspect("[]").is_err());
+    }
+}
@@ -0,0 +1,582 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::backend::{OpenMode, Sftp};
+use super::shell::quote;
+use super::transfer;
+
+/// Most bytes returned by one read.
+pub const MAX_READ_BYTES: usize = 1024 * 1024;
+/// Base64 grows data by a third; stay under the shell output cap.
+const SHELL_READ_BYTES: usize = 512 * 1024;
+/// Base64 per shell line, well under the PTY's 4096-byte line limit.
+const SHELL_WRITE_CHUNK: usize = 2048;
+/// Permissions of files `write_file` creates.
+pub const NEW_FILE_MODE: u32 = 0o644;
+
+/// How file contents were transferred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transfer {
+    Sftp,
+    Shell,
+}
+
+/// Part of a file to read. Lines are 1-based and inclusive and are counted
+/// within the byte window.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReadRange {
+    pub offset: u64,
+    pub length: Option<usize>,
+    pub start_line: Option<usize>,
+    pub end_line: Option<usize>,
+}
+
+impl ReadRange {
+    pub fn limit(&self) -> usize {
+        self.length.unwrap_or(MAX_READ_BYTES).min(MAX_READ_BYTES)
+    }
+
+    fn has_lines(&self) -> bool {
+        self.start_line.is_some() || self.end_line.is_some()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FileContent {
+    pub data: Vec<u8>,
+    /// More data follows the returned window.
+    pub truncated: bool,
+    pub via: Transfer,
+}
+
+/// Lines `start..=end` (1-based) of `data`, keeping their line endings.
+pub fn select_lines(data: &[u8], start: usize, end: Option<usize>) -> &[u8] {
+    let start = start.max(1);
+    if end.is_some_and(|end| end < start) {
+        return &[];
+    }
+    let mut begin = (start == 1).then_some(0);
+    let mut line = 1;
+    for (i, byte) in data.iter().enumerate() {
+        if *byte != b'\n' {
+            continue;
+        }
+        if Some(line) == end {
+            return begin.map_or(&[], |b| &data[b..=i]);
+        }
+        line += 1;
+        if line == start {
+            begin = Some(i + 1);
+        }
+    }
+    begin.map_or(&[], |b| &data[b..])
+}
+
+/// Applies the line part of `range` to a window read from `range.offset`.
+pub fn apply_lines(mut content: FileContent, range: &ReadRange) -> FileContent {
+    if range.has_lines() {
+        let selected = select_lines(&content.data, range.start_line.unwrap_or(1), range.end_line);
+        let ends_early = range.end_line.is_some() && selected.len() < content.data.len();
+        content.truncated = content.truncated && !ends_early;
+        content.data = selected.to_vec();
+    }
+    content
+}
+
+pub async fn sftp_read(
+    sftp: &dyn Sftp,
+    path: &str,
+    offset: u64,
+    limit: usize,
+) -> Result<(Vec<u8>, bool)> {
+    let mut file = sftp
+        .open(path, OpenMode::Read, 0)
+        .await
+        .with_context(|| format!("Failed to open {}", path))?;
+
+    if transfer::skip_stream(&mut file, offset).await? < offset {
+        return Ok((Vec::new(), false));
+    }
+
+    let mut data = Vec::new();
+    (&mut file)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut data)
+        .await?;
+    let truncated = data.len() > limit;
+    data.truncate(limit);
+    Ok((data, truncated))
+}
+
+fn unique_suffix() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+}
+
+/// Hidden temp file next to `path`, so the final rename stays on one filesystem.
+pub fn temp_path(path: &str) -> String {
+    let nanos = unique_suffix();
+    let (dir, name) = match path.rsplit_once('/') {
+        Some((dir, name)) => (format!("{}/", dir), name),
+        None => (String::new(), path),
+    };
+    format!("{}.{}.ssh-liaison-{}.tmp", dir, name, nanos)
+}
+
+/// Remote temp file for uploaded scripts and stdin data, e.g. `/tmp/ssh-liaison-script-…`.
+pub fn upload_path(kind: &str) -> String {
+    format!("/tmp/ssh-liaison-{}-{}", kind, unique_suffix())
+}
+
+/// Writes `data` to `tmp` with the target's permissions (`new_mode` for new files),
+/// then renames it over `path`. Returns `false` when the server refused the
+/// rename (OpenSSH won't overwrite), leaving `tmp` for the caller to move.
+pub async fn sftp_write(
+    sftp: &dyn Sftp,
+    path: &str,
+    tmp: &str,
+    data: &[u8],
+    new_mode: u32,
+) -> Result<bool> {
+    let mode = match sftp.stat(path).await {
+        Ok(stat) => stat.perm.map_or(new_mode, |perm| perm & 0o7777),
+        Err(_) => new_mode,
+    };
+
+    let mut file = sftp
+        .open(tmp, OpenMode::CreateNew, mode)
+        .await
+        .with_context(|| format!("Failed to create {}", tmp))?;
+    let written = async {
+        file.write_all(data).await?;
+        file.flush().await?;
+        file.shutdown().await
+    }
+    .await;
+    drop(file);
+    if let Err(e) = written {
+        let _ = sftp.unlink(tmp).await;
+        return Err(anyhow::Error::from(e).context(format!("Failed to write {}", tmp)));
+    }
+
+    Ok(sftp_rename_over(sftp, tmp, path).await)
+}
+
+/// Renames `tmp` over `path`; `false` when the server refused (OpenSSH
+/// won't overwrite), leaving `tmp` for the caller to move.
+pub async fn sftp_rename_over(sftp: &dyn Sftp, tmp: &str, path: &str) -> bool {
+    match sftp.rename(tmp, path).await {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::debug!(error = %e, "SFTP rename refused, moving via shell");
+            false
+        }
+    }
+}
+
+/// Shell command printing the window as base64; fails if the file can't be read.
+pub fn shell_read_command(path: &str, offset: u64, limit: usize) -> String {
+    let path = quote(path);
+    format!(
+        "if [ -f {p} ] && [ -r {p} ]; then tail -c +{} {p} | head -c {} | base64; else echo \"Cannot read {p}\"; false; fi",
+        offset + 1,
+        limit + 1,
+        p = path
+    )
+}
+
+pub fn shell_read_limit(limit: usize) -> usize {
+    limit.min(SHELL_READ_BYTES)
+}
+
+pub fn decode_base64_output(output: &str) -> Result<Vec<u8>> {
+    let compact: String = output.chars().filter(|c| !c.is_whitespace()).collect();
+    BASE64
+        .decode(compact)
+        .context("Remote output is not valid base64")
+}
+
+/// Shell commands that upload `data` as base64 in PTY-sized lines, decode it into
+/// `tmp` and move it over `path`, keeping an existing file's permissions.
+pub fn shell_write_commands(path: &str, tmp: &str, data: &[u8], new_mode: u32) -> Vec<String> {
+    let encoded = BASE64.encode(data);
+    let staging = quote(&format!("{}.b64", tmp));
+    let (path, tmp) = (quote(path), quote(tmp));
+
+    let mut commands = vec![format!(": > {}", staging)];
+    commands.extend(encoded.as_bytes().chunks(SHELL_WRITE_CHUNK).map(|chunk| {
+        format!(
+            "printf '%s' {} >> {}",
+            String::from_utf8_lossy(chunk),
+            staging
+        )
+    }));
+    commands.push(format!(
+        "base64 -d {s} > {t} && rm -f {s} && {{ if [ -e {p} ]; then chmod \"$(stat -c %a {p} 2>/dev/null || stat -f %Lp {p})\" {t}; else chmod {m:o} {t}; fi; }} && mv -f {t} {p}",
+        s = staging,
+        t = tmp,
+        p = path,
+        m = new_mode
+    ));
+    commands
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryKind {
+    File,
+    Directory,
+    Symlink,
+    Other,
+}
+
+/// One directory entry; metadata the server didn't report is `None`.
+#[derive(Debug, Clone, Serialize)]
+pub struct DirEntry {
+    pub name: String,
+    pub kind: EntryKind,
+    pub size: Option<u64>,
+    /// Modification time, seconds since the Unix epoch.
+    pub mtime: Option<u64>,
+    /// Octal permission bits, e.g. `0644`.
+    pub mode: Option<String>,
+    /// `ls`-style permissions, e.g. `rw-r--r--`.
+    pub permissions: Option<String>,
+}
+
+impl DirEntry {
+    fn new(name: String, mode: Option<u32>, size: Option<u64>, mtime: Option<u64>) -> Self {
+        Self {
+            name,
+            kind: mode.map_or(EntryKind::Other, entry_kind),
+            size,
+            mtime,
+            mode: mode.map(|m| format!("{:04o}", m & 0o7777)),
+            permissions: mode.map(permission_string),
+        }
+    }
+}
+
+pub fn entry_kind(mode: u32) -> EntryKind {
+    match mode & 0o170000 {
+        0o100000 => EntryKind::File,
+        0o040000 => EntryKind::Directory,
+        0o120000 => EntryKind::Symlink,
+        _ => EntryKind::Other,
+    }
+}
+
+pub fn permission_string(mode: u32) -> String {
+    let mut text = String::with_capacity(9);
+    for shift in [6, 3, 0] {
+        let bits = (mode >> shift) & 0o7;
+        text.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        text.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        text.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+    text
+}
+
+pub async fn sftp_list_dir(sftp: &dyn Sftp, path: &str) -> Result<Vec<DirEntry>> {
+    let entries = sftp
+        .readdir(path)
+        .await
+        .with_context(|| format!("Failed to list {}", path))?;
+    let mut listing: Vec<DirEntry> = entries
+        .into_iter()
+        .map(|(name, stat)| DirEntry::new(name, stat.perm, stat.size, stat.mtime))
+        .filter(|e| e.name != "." && e.name != "..")
+        .collect();
+    listing.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(listing)
+}
+
+/// GNU `stat` listing with the raw mode in hex: `mode size mtime name`, tab-separated.
+pub fn shell_list_dir_command(path: &str) -> String {
+    format!(
+        "LC_ALL=C find {} -mindepth 1 -maxdepth 1 -exec stat --printf '%f\\t%s\\t%Y\\t%n\\n' {{}} +",
+        quote(path)
+    )
+}
+
+pub fn parse_shell_listing(output: &str) -> Vec<DirEntry> {
+    let mut listing: Vec<DirEntry> = output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.trim_end_matches('\r').splitn(4, '\t');
+            let mode = u32::from_str_radix(fields.next()?, 16).ok()?;
+            let size = fields.next()?.parse().ok();
+            let mtime = fields.next()?.parse().ok();
+            let path = fields.next()?;
+            let name = path.rsplit('/').next().unwrap_or(path).to_string();
+            Some(DirEntry::new(name, Some(mode), size, mtime))
+        })
+        .collect();
+    listing.sort_by(|a, b| a.name.cmp(&b.name));
+    listing
+}
+
+/// Largest unified diff returned; the rest is cut.
+pub const MAX_DIFF_BYTES: usize = 256 * 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ChecksumAlgorithm {
+    Md5,
+    Sha1,
+    #[default]
+    Sha256,
+    Sha512,
+}
+
+impl ChecksumAlgorithm {
+    fn program(self) -> &'static str {
+        match self {
+            ChecksumAlgorithm::Md5 => "md5sum",
+            ChecksumAlgorithm::Sha1 => "sha1sum",
+            ChecksumAlgorithm::Sha256 => "sha256sum",
+            ChecksumAlgorithm::Sha512 => "sha512sum",
+        }
+    }
+
+    fn hex_len(self) -> usize {
+        match self {
+            ChecksumAlgorithm::Md5 => 32,
+            ChecksumAlgorithm::Sha1 => 40,
+            ChecksumAlgorithm::Sha256 => 64,
+            ChecksumAlgorithm::Sha512 => 128,
+        }
+    }
+}
+
+/// `sha256sum` and friends over `paths`, with their errors in the output.
+pub fn checksum_command(algorithm: ChecksumAlgorithm, paths: &[String]) -> String {
+    let paths: Vec<String> = paths.iter().map(|p| quote(p)).collect();
+    format!(
+        "LC_ALL=C {} -- {} 2>&1",
+        algorithm.program(),
+        paths.join(" ")
+    )
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileChecksum {
+    pub path: String,
+    /// Lowercase hex.
+    pub checksum: Option<String>,
+    /// Why there is no checksum, e.g. `No such file or directory`.
+    pub error: Option<String>,
+}
+
+/// Parses [`checksum_command`] output into one entry per path, in order.
+pub fn parse_checksums(
+    algorithm: ChecksumAlgorithm,
+    output: &str,
+    paths: &[String],
+) -> Vec<FileChecksum> {
+    let mut sums: Vec<(String, String)> = Vec::new();
+    let mut errors: Vec<&str> = Vec::new();
+    for line in output.lines() {
+        let line = line.trim_end();
+        // Names with a backslash or newline are escaped and the line starts with `\`.
+        let (escaped, rest) = match line.strip_prefix('\\') {
+            Some(rest) => (true, rest),
+            None => (false, line),
+        };
+        let hex_len = algorithm.hex_len();
+        if rest.len() > hex_len + 2
+            && rest.is_char_boundary(hex_len)
+            && rest[..hex_len].bytes().all(|b| b.is_ascii_hexdigit())
+            && matches!(&rest[hex_len..hex_len + 2], "  " | " *")
+        {
+            let path = &rest[hex_len + 2..];
+            let path = if escaped {
+                unescape_checksum_path(path)
+            } else {
+                path.to_string()
+            };
+            sums.push((path, rest[..hex_len].to_lowercase()));
+        } else if let Some(error) = line.strip_prefix(&format!("{}: ", algorithm.program())) {
+            errors.push(error);
+        }
+    }
+
+    paths
+        .iter()
+        .map(|path| {
+            let checksum = sums
+                .iter()
+                .find(|(p, _)| p == path)
+                .map(|(_, sum)| sum.clone());
+            let error = checksum.is_none().then(|| {
+                errors
+                    .iter()
+                    .find_map(|e| e.strip_prefix(path.as_str())?.strip_prefix(": "))
+                    .unwrap_or("no checksum printed")
+                    .to_string()
+            });
+            FileChecksum {
+                path: path.clone(),
+                checksum,
+                error,
+            }
+        })
+        .collect()
+}
+
+/// Undoes coreutils' `\\` and `\n` escaping of file names.
+fn unescape_checksum_path(path: &str) -> String {
+    let mut unescaped = String::with_capacity(path.len());
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                chars.next();
+                unescaped.push('\n');
+            }
+            ('\\', Some('\\')) => {
+                chars.next();
+                unescaped.push('\\');
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// Unified diff with three lines of context, cut at [`MAX_DIFF_BYTES`];
+/// the flag says whether it was cut.
+pub fn unified_diff(old_label: &str, old: &str, new_label: &str, new: &str) -> (String, bool) {
+    let mut diff = similar::TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(3)
+        .header(old_label, new_label)
+        .to_string();
+    if diff.len() <= MAX_DIFF_BYTES {
+        return (diff, false);
+    }
+    let mut end = MAX_DIFF_BYTES;
+    while !diff.is_char_boundary(end) {
+        end -= 1;
+    }
+    diff.truncate(end);
+    (diff, true)
+}
+
+/// Removes leftovers of a failed shell upload.
+pub fn shell_cleanup_command(tmp: &str) -> String {
+    format!("rm -f {} {}", quote(tmp), quote(&format!("{}.b64", tmp)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksums() {
+        let paths = vec![
+            "/etc/hosts".to_string(),
+            "/etc/missing".to_string(),
+            "/tmp/a\\b".to_string(),
+        ];
+        let command = checksum_command(ChecksumAlgorithm::Sha256, &paths);
+        assert!(command.starts_with("LC_ALL=C sha256sum -- /etc/hosts /etc/missing"));
+        assert!(crate::policy::is_read_only_command(&command));
+
+        let output = "\
+b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c  /etc/hosts
+sha256sum: /etc/missing: No such file or directory
+\\7d865e959b2466918c9863afca942d0fb89d7c9ac0c99bafc3749504ded97730  /tmp/a\\\\b
+";
+        let sums = parse_checksums(ChecksumAlgorithm::Sha256, output, &paths);
+        assert_eq!(
+            sums[0].checksum.as_deref(),
+            Some("b5bb9d8014a0f9b1d61e21e796d78dccdf1352f23cd32812f4850b878ae4944c")
+        );
+        assert_eq!(sums[1].checksum, None);
+        assert_eq!(sums[1].error.as_deref(), Some("No such file or directory"));
+        assert!(sums[2].checksum.is_some());
+    }
+
+    #[test]
+    fn test_unified_diff() {
+        let (diff, cut) = unified_diff(
+            "web1:/etc/app.conf",
+            "port=80\nworkers=4\n",
+            "web2:/etc/app.conf",
+            "port=80\nworkers=8\n",
+        );
+        assert!(!cut);
+        assert_eq!(
+            diff,
+            "--- web1:/etc/app.conf\n+++ web2:/etc/app.conf\n@@ -1,2 +1,2 @@\n port=80\n-workers=4\n+workers=8\n"
+        );
+    }
+
+    #[test]
+    fn test_select_lines() {
+        let data = b"one\ntwo\nthree\nfour";
+        assert_eq!(select_lines(data, 1, Some(1)), b"one\n");
+        assert_eq!(select_lines(data, 2, Some(3)), b"two\nthree\n");
+        assert_eq!(select_lines(data, 3, None), b"three\nfour");
+        assert_eq!(select_lines(data, 4, Some(10)), b"four");
+        assert_eq!(select_lines(data, 9, None), b"");
+        assert_eq!(select_lines(data, 3, Some(2)), b"");
+    }
+
+    #[test]
+    fn test_shell_write_round_trip() {
+        let data = vec![b'x'; SHELL_WRITE_CHUNK * 2];
+        let commands = shell_write_commands(
+            "/etc/my app.conf",
+            "/etc/.my app.conf.tmp",
+            &data,
+            NEW_FILE_MODE,
+        );
+        assert_eq!(commands[0], ": > '/etc/.my app.conf.tmp.b64'");
+        assert!(
+            commands
+                .last()
+                .unwrap()
+                .ends_with("mv -f '/etc/.my app.conf.tmp' '/etc/my app.conf'")
+        );
+
+        let uploaded: String = commands[1..commands.len() - 1]
+            .iter()
+            .map(|c| {
+                assert!(c.len() < 4096);
+                c.split(' ').nth(2).unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(decode_base64_output(&uploaded).unwrap(), data);
+    }
+
+    #[test]
+    fn test_parse_shell_listing() {
+        let output =
+            "41ed\t4096\t1700000000\t/etc/ssh\r\n81a4\t12\t1700000100\t/etc/host name\nbogus\n";
+        let listing = parse_shell_listing(output);
+        assert_eq!(listing.len(), 2);
+        assert_eq!(listing[0].name, "host name");
+        assert_eq!(listing[0].kind, EntryKind::File);
+        assert_eq!(listing[0].mode.as_deref(), Some("0644"));
+        assert_eq!(listing[0].permissions.as_deref(), Some("rw-r--r--"));
+        assert_eq!(listing[1].kind, EntryKind::Directory);
+        assert_eq!(listing[1].mtime, Some(1_700_000_000));
+        assert_eq!(entry_kind(0o120777), EntryKind::Symlink);
+    }
+
+    #[test]
+    fn test_temp_path_stays_in_directory() {
+        assert!(temp_path("/etc/hosts").starts_with("/etc/.hosts.ssh-liaison-"));
+        assert!(temp_path("notes.txt").starts_with(".notes.txt.ssh-liaison-"));
+    }
+}
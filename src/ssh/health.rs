@@ -0,0 +1,462 @@
+//! Health probes run on an interval against inventory hosts: a TCP connect
+//! from this machine, a command's exit code, or how full a filesystem is.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::net::TcpStream;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+use super::channel::CommandOutput;
+use super::config::parse_ssh_config;
+use super::session::{HEALTH_CHANNEL, SessionManager};
+use super::shell::quote;
+use crate::error::SshLiaisonError;
+
+pub const DEFAULT_HEALTH_INTERVAL_SECS: u64 = 60;
+/// Shortest interval probes can run at.
+pub const MIN_HEALTH_INTERVAL_SECS: u64 = 10;
+const TCP_PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Characters of command output kept in a probe's detail.
+const MAX_DETAIL_CHARS: usize = 200;
+
+/// What a probe checks.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum ProbeCheck {
+    /// A TCP connect from this machine to the host's `HostName`.
+    Tcp { port: u16 },
+    /// A command run on the host, passing when it exits with `expect_exit`.
+    Command {
+        command: String,
+        #[serde(default)]
+        expect_exit: i32,
+    },
+    /// A filesystem on the host, passing while at most `max_used_percent` full.
+    Disk { path: String, max_used_percent: u8 },
+}
+
+impl ProbeCheck {
+    fn default_name(&self) -> String {
+        match self {
+            Self::Tcp { port } => format!("tcp:{}", port),
+            Self::Command { command, .. } => command.clone(),
+            Self::Disk { path, .. } => format!("disk:{}", path),
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        let invalid = |message: &str| Err(SshLiaisonError::Config(message.to_string()).into());
+        match self {
+            Self::Tcp { port: 0 } => invalid("tcp probe port must not be 0"),
+            Self::Command { command, .. } if command.trim().is_empty() => {
+                invalid("command probe command must not be empty")
+            }
+            Self::Disk { path, .. } if path.is_empty() => {
+                invalid("disk probe path must not be empty")
+            }
+            Self::Disk {
+                max_used_percent, ..
+            } if *max_used_percent == 0 || *max_used_percent > 100 => {
+                invalid("disk probe max_used_percent must be between 1 and 100")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// One probe of one host.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthProbe {
+    pub host: String,
+    pub name: String,
+    pub check: ProbeCheck,
+}
+
+impl HealthProbe {
+    /// A probe named after its check unless `name` is given.
+    pub fn new(host: String, name: Option<String>, check: ProbeCheck) -> Result<Self> {
+        check.validate()?;
+        let name = name.unwrap_or_else(|| check.default_name());
+        Ok(Self { host, name, check })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Ok,
+    /// Not run yet.
+    Pending,
+    /// The check ran and did not pass.
+    Failing,
+    /// The check could not run, e.g. the host could not be reached over SSH.
+    Error,
+}
+
+impl std::fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Ok => "ok",
+            Self::Pending => "pending",
+            Self::Failing => "failing",
+            Self::Error => "error",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProbeResult {
+    pub probe: String,
+    #[serde(flatten)]
+    pub check: ProbeCheck,
+    pub status: HealthStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// Unix milliseconds of the last run.
+    pub checked_at_ms: Option<u64>,
+    pub duration_ms: Option<u64>,
+    /// Unix milliseconds the probe entered its status.
+    pub since_ms: Option<u64>,
+}
+
+impl std::fmt::Display for ProbeResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.status, self.probe)?;
+        if let Some(ref detail) = self.detail {
+            write!(f, ": {}", detail)?;
+        }
+        Ok(())
+    }
+}
+
+/// A host's probes; its status is the worst of theirs.
+#[derive(Debug, Clone, Serialize)]
+pub struct HostHealth {
+    pub host: String,
+    pub status: HealthStatus,
+    pub probes: Vec<ProbeResult>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub interval_secs: u64,
+    pub hosts: Vec<HostHealth>,
+}
+
+/// Runs the configured probes every `interval` and keeps their latest results.
+#[derive(Clone, Default)]
+pub struct HealthMonitor {
+    interval: Duration,
+    probes: Arc<Vec<HealthProbe>>,
+    /// Latest result of each probe, in `probes` order.
+    results: Arc<Mutex<Vec<ProbeResult>>>,
+}
+
+impl HealthMonitor {
+    pub fn new(interval: Duration, probes: Vec<HealthProbe>) -> Result<Self> {
+        if interval < Duration::from_secs(MIN_HEALTH_INTERVAL_SECS) {
+            return Err(SshLiaisonError::Config(format!(
+                "health interval must be at least {}s",
+                MIN_HEALTH_INTERVAL_SECS
+            ))
+            .into());
+        }
+        let results = probes
+            .iter()
+            .map(|probe| ProbeResult {
+                probe: probe.name.clone(),
+                check: probe.check.clone(),
+                status: HealthStatus::Pending,
+                detail: None,
+                checked_at_ms: None,
+                duration_ms: None,
+                since_ms: None,
+            })
+            .collect();
+        Ok(Self {
+            interval,
+            probes: Arc::new(probes),
+            results: Arc::new(Mutex::new(results)),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.probes.is_empty()
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Hosts with at least one probe, sorted.
+    pub fn hosts(&self) -> Vec<String> {
+        let mut hosts: Vec<String> = self.probes.iter().map(|p| p.host.clone()).collect();
+        hosts.sort();
+        hosts.dedup();
+        hosts
+    }
+
+    /// Latest results of every host's probes, or only `host`'s.
+    pub fn report(&self, host: Option<&str>) -> HealthReport {
+        let results = self.results.lock().unwrap_or_else(|e| e.into_inner());
+        let mut hosts: BTreeMap<&str, Vec<ProbeResult>> = BTreeMap::new();
+        for (probe, result) in self.probes.iter().zip(results.iter()) {
+            if host.is_none_or(|host| host == probe.host) {
+                hosts
+                    .entry(probe.host.as_str())
+                    .or_default()
+                    .push(result.clone());
+            }
+        }
+        HealthReport {
+            interval_secs: self.interval.as_secs(),
+            hosts: hosts
+                .into_iter()
+                .map(|(host, probes)| HostHealth {
+                    host: host.to_string(),
+                    status: probes
+                        .iter()
+                        .map(|p| p.status)
+                        .max()
+                        .unwrap_or(HealthStatus::Ok),
+                    probes,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn host(&self, host: &str) -> Option<HostHealth> {
+        self.report(Some(host)).hosts.pop()
+    }
+
+    /// Starts running the probes through `sessions`, the first time right
+    /// away. Hosts are probed in parallel, each host's probes one after
+    /// another. `None` when there are no probes.
+    pub fn start(&self, sessions: SessionManager) -> Option<JoinHandle<()>> {
+        if self.is_empty() {
+            return None;
+        }
+        let monitor = self.clone();
+        Some(tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(monitor.interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                let mut hosts = tokio::task::JoinSet::new();
+                for host in monitor.hosts() {
+                    let monitor = monitor.clone();
+                    let sessions = sessions.clone();
+                    hosts.spawn(async move { monitor.probe_host(&sessions, &host).await });
+                }
+                while hosts.join_next().await.is_some() {}
+            }
+        }))
+    }
+
+    async fn probe_host(&self, sessions: &SessionManager, host: &str) {
+        for (index, probe) in self.probes.iter().enumerate() {
+            if probe.host != host {
+                continue;
+            }
+            let started = Instant::now();
+            let (status, detail) = match run_probe(sessions, probe).await {
+                Ok((true, detail)) => (HealthStatus::Ok, detail),
+                Ok((false, detail)) => (HealthStatus::Failing, detail),
+                Err(e) => (HealthStatus::Error, format!("{:#}", e)),
+            };
+            self.record(index, status, detail, started.elapsed().as_millis() as u64);
+        }
+    }
+
+    fn record(&self, index: usize, status: HealthStatus, detail: String, duration_ms: u64) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        let mut results = self.results.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(result) = results.get_mut(index) else {
+            return;
+        };
+        if result.status != status {
+            let host = &self.probes[index].host;
+            match status {
+                HealthStatus::Ok => {
+                    tracing::info!(host = %host, probe = %result.probe, "Health probe passing")
+                }
+                _ => tracing::warn!(
+                    host = %host,
+                    probe = %result.probe,
+                    status = %status,
+                    detail = %detail,
+                    "Health probe not passing"
+                ),
+            }
+            result.status = status;
+            result.since_ms = Some(now);
+        }
+        result.detail = Some(detail);
+        result.checked_at_ms = Some(now);
+        result.duration_ms = Some(duration_ms);
+    }
+}
+
+/// Whether the probe passed, and what it saw.
+async fn run_probe(sessions: &SessionManager, probe: &HealthProbe) -> Result<(bool, String)> {
+    match probe.check {
+        ProbeCheck::Tcp { port } => {
+            let hostname = parse_ssh_config(&probe.host)
+                .ok()
+                .and_then(|config| config.hostname)
+                .unwrap_or_else(|| probe.host.clone());
+            let target = format!("{}:{}", hostname, port);
+            Ok(
+                match tokio::time::timeout(TCP_PROBE_TIMEOUT, TcpStream::connect(&target)).await {
+                    Ok(Ok(_)) => (true, format!("{} accepted the connection", target)),
+                    Ok(Err(e)) => (false, format!("{}: {}", target, e)),
+                    Err(_) => (
+                        false,
+                        format!(
+                            "{}: no answer within {}s",
+                            target,
+                            TCP_PROBE_TIMEOUT.as_secs()
+                        ),
+                    ),
+                },
+            )
+        }
+        ProbeCheck::Command {
+            ref command,
+            expect_exit,
+        } => {
+            let output = run_command(sessions, &probe.host, command).await?;
+            let passed = output.exit_code == Some(expect_exit);
+            let code = output.exit_code.map_or("no exit code".to_string(), |code| {
+                format!("exit code {}", code)
+            });
+            let first_line = output
+                .combined_with_stderr_label()
+                .lines()
+                .find(|line| !line.trim().is_empty())
+                .map(|line| {
+                    line.trim()
+                        .chars()
+                        .take(MAX_DETAIL_CHARS)
+                        .collect::<String>()
+                });
+            Ok((
+                passed,
+                match first_line {
+                    Some(line) => format!("{}: {}", code, line),
+                    None => code,
+                },
+            ))
+        }
+        ProbeCheck::Disk {
+            ref path,
+            max_used_percent,
+        } => {
+            let command = format!("LC_ALL=C df -P -k {}", quote(path));
+            let output = run_command(sessions, &probe.host, &command).await?;
+            let used = parse_used_percent(&output.stdout)
+                .with_context(|| format!("df printed no usage for {}", path))?;
+            Ok((
+                used <= max_used_percent,
+                format!("{} is {}% full (limit {}%)", path, used, max_used_percent),
+            ))
+        }
+    }
+}
+
+/// Runs `command` in the host's health channel, connecting first if needed.
+async fn run_command(
+    sessions: &SessionManager,
+    host: &str,
+    command: &str,
+) -> Result<CommandOutput> {
+    if !sessions.is_connected(host).await {
+        tracing::debug!(host = %host, "Connecting for health probes");
+        sessions.connect_by_alias(host).await?;
+    }
+    sessions
+        .execute_command_in(host, Some(HEALTH_CHANNEL), command, None)
+        .await
+}
+
+/// The `Capacity` column of the last line of `df -P` output.
+fn parse_used_percent(output: &str) -> Option<u8> {
+    let line = output.lines().rev().find(|line| !line.trim().is_empty())?;
+    line.split_whitespace()
+        .nth(4)?
+        .strip_suffix('%')?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_used_percent() {
+        let output = "Filesystem 1024-blocks Used Available Capacity Mounted on\n\
+                      /dev/sda1 41152736 35000000 6152736 86% /\n";
+        assert_eq!(parse_used_percent(output), Some(86));
+        assert_eq!(
+            parse_used_percent("df: /nope: No such file or directory\n"),
+            None
+        );
+        assert_eq!(parse_used_percent(""), None);
+    }
+
+    #[test]
+    fn test_health_report() {
+        let probe = |host: &str, check| HealthProbe::new(host.to_string(), None, check).unwrap();
+        let monitor = HealthMonitor::new(
+            Duration::from_secs(30),
+            vec![
+                probe("web", ProbeCheck::Tcp { port: 443 }),
+                probe(
+                    "web",
+                    ProbeCheck::Disk {
+                        path: "/".to_string(),
+                        max_used_percent: 90,
+                    },
+                ),
+                probe(
+                    "db",
+                    ProbeCheck::Command {
+                        command: "pg_isready".to_string(),
+                        expect_exit: 0,
+                    },
+                ),
+            ],
+        )
+        .unwrap();
+        assert_eq!(monitor.hosts(), ["db", "web"]);
+        assert_eq!(monitor.host("web").unwrap().status, HealthStatus::Pending);
+
+        monitor.record(0, HealthStatus::Ok, "ok".to_string(), 3);
+        monitor.record(1, HealthStatus::Failing, "/ is 95% full".to_string(), 40);
+        let web = monitor.host("web").unwrap();
+        assert_eq!(web.status, HealthStatus::Failing);
+        assert_eq!(web.probes[0].probe, "tcp:443");
+        assert_eq!(web.probes[1].probe, "disk:/");
+        assert!(web.probes[1].since_ms.is_some());
+
+        let report = monitor.report(None);
+        assert_eq!(report.interval_secs, 30);
+        assert_eq!(report.hosts.len(), 2);
+        assert!(monitor.host("cache").is_none());
+
+        assert!(HealthMonitor::new(Duration::from_secs(1), Vec::new()).is_err());
+        let full = ProbeCheck::Disk {
+            path: "/".to_string(),
+            max_used_percent: 0,
+        };
+        assert!(HealthProbe::new("web".to_string(), None, full).is_err());
+    }
+}
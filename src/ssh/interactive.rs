@@ -0,0 +1,339 @@
+//! Commands that take over the terminal (editors, pagers, monitors, REPLs) or
+//! never end on their own. In the shared shell they would hang until the
+//! command times out, so they are refused up front with an alternative.
+
+use crate::policy::{command_words, split_pipelines};
+
+/// Sequences a program sends to switch to the terminal's alternate screen,
+/// as full-screen programs do.
+const ALTERNATE_SCREEN: [&str; 3] = ["\x1b[?1049h", "\x1b[?1047h", "\x1b[?47h"];
+
+/// Full-screen programs without a batch mode worth detecting.
+const FULL_SCREEN: &[&str] = &[
+    "htop",
+    "btop",
+    "glances",
+    "nmon",
+    "iftop",
+    "nethogs",
+    "ncdu",
+    "mc",
+    "ranger",
+    "nnn",
+    "tig",
+    "lazygit",
+    "k9s",
+    "ctop",
+    "mutt",
+    "lynx",
+    "w3m",
+    "alsamixer",
+    "cfdisk",
+    "nmtui",
+];
+
+const EDITORS: &[&str] = &[
+    "vi", "vim", "nvim", "view", "nano", "pico", "emacs", "joe", "mcedit", "micro",
+];
+
+const EDIT_FILES: &str = "edit files with ssh_read_file and ssh_write_file, or sed -i";
+
+/// A command that would hang the shell, and what to run instead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InteractiveCommand {
+    pub program: String,
+    pub suggestion: String,
+}
+
+impl std::fmt::Display for InteractiveCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "'{}' is interactive and would hang the shell; {}",
+            self.program, self.suggestion
+        )
+    }
+}
+
+/// The first part of `command` that would wait for a terminal or run forever.
+pub fn detect(command: &str) -> Option<InteractiveCommand> {
+    split_pipelines(command).into_iter().find_map(|pipeline| {
+        let last = pipeline.len() - 1;
+        pipeline.iter().enumerate().find_map(|(i, segment)| {
+            let stdin_piped = i > 0 || segment.contains('<');
+            let stdout_piped = i < last || segment.contains('>');
+            check_segment(segment, stdin_piped, stdout_piped)
+        })
+    })
+}
+
+/// The program `command` starts with, for messages.
+pub fn program(command: &str) -> String {
+    split_pipelines(command)
+        .first()
+        .and_then(|pipeline| pipeline.first())
+        .and_then(|segment| command_words(segment).first().copied())
+        .map_or_else(
+            || command.to_string(),
+            |p| p.rsplit('/').next().unwrap_or(p).to_string(),
+        )
+}
+
+/// Whether `output` switches to the alternate screen, as full-screen programs do.
+pub fn enters_alternate_screen(output: &str) -> bool {
+    ALTERNATE_SCREEN.iter().any(|s| output.contains(s))
+}
+
+fn check_segment(
+    segment: &str,
+    stdin_piped: bool,
+    stdout_piped: bool,
+) -> Option<InteractiveCommand> {
+    let words = command_words(segment);
+    let program = words.first()?;
+    let program = program.rsplit('/').next().unwrap_or(program);
+    let args = &words[1..];
+    let has = |flags: &[&str]| args.iter().any(|a| flags.contains(a));
+    // A short flag, alone or combined with others (`-bn1`).
+    let has_short = |letter: char| {
+        args.iter()
+            .any(|a| a.starts_with('-') && !a.starts_with("--") && a[1..].contains(letter))
+    };
+    let pager_off = stdout_piped || has(&["--no-pager"]) || segment.contains("PAGER=");
+
+    let suggestion = match program {
+        _ if FULL_SCREEN.contains(&program) => match program {
+            "htop" | "btop" | "glances" | "nmon" => "use top -bn1 or ps aux --sort=-%cpu | head",
+            "iftop" | "nethogs" => "use ss -tunap or ssh_network_info",
+            "ncdu" => "use du -xh -d1 or ssh_disk_usage",
+            "tig" | "lazygit" => "use git --no-pager log",
+            _ => "run a non-interactive command instead",
+        },
+        _ if EDITORS.contains(&program) => {
+            let batch = match program {
+                "emacs" => has(&["--batch", "-batch"]),
+                "vi" | "vim" | "nvim" | "view" => has(&["-e", "-es", "-E", "-Es", "--headless"]),
+                _ => false,
+            };
+            (!batch).then_some(EDIT_FILES)?
+        }
+        "less" | "more" | "most" | "pg" => {
+            (!stdout_piped).then_some("use cat, head -n or tail -n instead of a pager")?
+        }
+        "man" => (!pager_off && !has(&["-P"])).then_some("use man -P cat <page>")?,
+        "top" => (!has_short('b') && !has_short('l')).then_some("use top -bn1")?,
+        "iotop" => (!has_short('b') && !has(&["--batch"])).then_some("use iotop -bon1")?,
+        "watch" => "run the command once, or use ssh_schedule to repeat it",
+        "tail" => {
+            (has_short('f') || has_short('F') || args.iter().any(|a| a.starts_with("--follow")))
+                .then_some("use tail -n 100, or ssh_watch_log to follow a file")?
+        }
+        "journalctl" => {
+            if has_short('f') || has(&["--follow"]) {
+                "use journalctl -n 100 --no-pager, or ssh_watch_log to follow a log"
+            } else {
+                (!pager_off).then_some("add --no-pager")?
+            }
+        }
+        "systemctl" => {
+            let paged = args
+                .iter()
+                .find(|a| !a.starts_with('-'))
+                .is_some_and(|sub| {
+                    matches!(*sub, "status" | "cat" | "show" | "help") || sub.starts_with("list-")
+                });
+            if args.first() == Some(&"edit") {
+                "write a drop-in file with ssh_write_file, then systemctl daemon-reload"
+            } else {
+                (paged && !pager_off).then_some("add --no-pager")?
+            }
+        }
+        "git" => git_suggestion(args, pager_off)?,
+        "crontab" => has(&["-e"]).then_some("use crontab -l, and pipe a new table to crontab -")?,
+        "visudo" | "vipw" | "vigr" => EDIT_FILES,
+        "passwd" => (!stdin_piped).then_some("use chpasswd with the password on stdin")?,
+        "mysql" | "mariadb" => (!stdin_piped
+            && !has(&["-e", "--execute"])
+            && !args.iter().any(|a| a.starts_with("--execute=")))
+        .then_some("pass the query with -e, or on stdin")?,
+        "psql" => (!stdin_piped
+            && !has(&["-c", "--command", "-f", "--file", "-l", "--list"])
+            && !args
+                .iter()
+                .any(|a| a.starts_with("--command=") || a.starts_with("--file=")))
+        .then_some("pass the query with -c, or on stdin")?,
+        "sqlite3" => (!stdin_piped && positional(args, &[]) < 2)
+            .then_some("pass the SQL after the database: sqlite3 app.db 'SELECT ...'")?,
+        "redis-cli" => (!stdin_piped
+            && positional(args, &["-h", "-p", "-a", "-n", "-u", "--user", "--pass"]) == 0)
+            .then_some("pass the command as arguments: redis-cli INFO")?,
+        "mongo" | "mongosh" => (!stdin_piped && !has(&["--eval"]) && positional(args, &[]) < 2)
+            .then_some("pass the script with --eval")?,
+        "python" | "python3" | "node" | "irb" | "ipython" | "php" | "lua" => {
+            let repl = match program {
+                "php" => has(&["-a"]),
+                _ => args.is_empty() || has(&["-i"]),
+            };
+            (repl && !stdin_piped).then_some("pass the code with -c / -e, or a script file")?
+        }
+        "ssh" => (!stdin_piped && positional(args, SSH_VALUE_FLAGS) == 1).then_some(
+            "connect it with ssh_connect and run commands there, or give ssh a command",
+        )?,
+        "sftp" | "ftp" | "telnet" => (!stdin_piped && !has(&["-b"])).then_some(
+            "use ssh_upload / ssh_download for files, or ssh_port_check for connectivity",
+        )?,
+        "ping" | "ping6" => {
+            (!has_short('c') && !has_short('w')).then_some("use ping -c 4, or ssh_port_check")?
+        }
+        "tcpdump" => (!has_short('c')).then_some("add -c <packets> so it stops")?,
+        "vmstat" | "iostat" | "mpstat" | "pidstat" | "sar" => {
+            let counts = args.iter().filter(|a| a.parse::<u64>().is_ok()).count();
+            (counts == 1).then_some("add a count after the interval, e.g. vmstat 1 5")?
+        }
+        "docker" | "podman" | "kubectl" => container_suggestion(args)?,
+        "tmux" | "screen" => {
+            let attach = args.is_empty()
+                || args.first().is_some_and(|a| {
+                    matches!(*a, "attach" | "attach-session" | "a" | "at" | "-r" | "-x")
+                });
+            attach.then_some("use tmux new -d / send-keys / capture-pane to drive a session")?
+        }
+        _ => return None,
+    };
+    Some(InteractiveCommand {
+        program: program.to_string(),
+        suggestion: suggestion.to_string(),
+    })
+}
+
+/// `ssh` flags that take a value.
+const SSH_VALUE_FLAGS: &[&str] = &[
+    "-b", "-c", "-D", "-E", "-e", "-F", "-I", "-i", "-J", "-L", "-l", "-m", "-O", "-o", "-p", "-Q",
+    "-R", "-S", "-W", "-w", "-B",
+];
+
+/// Arguments that aren't flags or the values of `value_flags`.
+fn positional(args: &[&str], value_flags: &[&str]) -> usize {
+    let mut count = 0;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if value_flags.contains(arg) {
+            args.next();
+        } else if !arg.starts_with('-') {
+            count += 1;
+        }
+    }
+    count
+}
+
+fn git_suggestion(args: &[&str], pager_off: bool) -> Option<&'static str> {
+    let sub = *args.iter().find(|a| !a.starts_with('-'))?;
+    let has = |flags: &[&str]| args.iter().any(|a| flags.contains(a));
+    let has_prefix = |prefix: &str| args.iter().any(|a| a.starts_with(prefix));
+    let has_short = |letter: char| {
+        args.iter()
+            .any(|a| a.starts_with('-') && !a.starts_with("--") && a[1..].contains(letter))
+    };
+    match sub {
+        "log" | "diff" | "show" | "blame" | "reflog" | "shortlog" | "branch" | "tag" | "grep" => {
+            (!pager_off && !has(&["-P"]) && !has_prefix("core.pager"))
+                .then_some("use git --no-pager")
+        }
+        "commit" => (!has_short('m')
+            && !has_short('F')
+            && !has_short('C')
+            && !has(&["--no-edit", "--file", "--reuse-message"])
+            && !has_prefix("--message")
+            && !has_prefix("--fixup"))
+        .then_some("pass the message with -m, or --no-edit"),
+        "rebase" => has(&["-i", "--interactive"]).then_some("run a non-interactive rebase"),
+        "add" => {
+            has(&["-p", "-i", "--patch", "--interactive"]).then_some("add whole files or paths")
+        }
+        _ => None,
+    }
+}
+
+fn container_suggestion(args: &[&str]) -> Option<&'static str> {
+    let sub = *args.iter().find(|a| !a.starts_with('-'))?;
+    let follows = args
+        .iter()
+        .any(|a| matches!(*a, "-f" | "--follow" | "-w" | "--watch"));
+    match sub {
+        "logs" if follows => Some("drop -f and use --tail 100, or ssh_docker_logs"),
+        "get" if follows => Some("drop --watch and run it again later"),
+        "stats" if !args.contains(&"--no-stream") => Some("add --no-stream"),
+        "attach" => Some("use logs, or exec a command that exits"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refused(command: &str) -> Option<String> {
+        detect(command).map(|c| c.program)
+    }
+
+    #[test]
+    fn test_detect_interactive() {
+        for (command, program) in [
+            ("vim /etc/hosts", "vim"),
+            ("sudo top", "top"),
+            ("cd /var/log && less syslog", "less"),
+            ("cat app.log | less", "less"),
+            ("git log", "git"),
+            ("systemctl status nginx", "systemctl"),
+            ("tail -fn 100 app.log | grep ERROR", "tail"),
+            ("mysql -u root app", "mysql"),
+            ("ping example.com", "ping"),
+            ("ssh -p 2222 bastion", "ssh"),
+            ("git commit -a", "git"),
+            ("sftp backup@nas", "sftp"),
+            ("vmstat 1", "vmstat"),
+            ("docker logs -f web", "docker"),
+        ] {
+            assert_eq!(refused(command).as_deref(), Some(program), "{}", command);
+        }
+
+        for command in [
+            "top -bn1",
+            "less file | cat",
+            "man -P cat ls",
+            "git --no-pager log -5",
+            "git log | head",
+            "systemctl status nginx --no-pager",
+            "SYSTEMD_PAGER= systemctl status nginx",
+            "journalctl -u nginx -n 50 --no-pager",
+            "tail -n 100 app.log",
+            "mysql -e 'SELECT 1' app",
+            "mysql app < dump.sql",
+            "echo 'SELECT 1' | psql app",
+            "python3 -c 'print(1)'",
+            "python3 script.py",
+            "ssh bastion uptime",
+            "ping -c 4 example.com",
+            "git commit -m 'fix'",
+            "git commit -am 'fix'",
+            "vmstat 1 5",
+            "vim -es -c 'wq' file",
+            "docker stats --no-stream",
+            "grep -c vim notes.txt",
+        ] {
+            assert_eq!(refused(command), None, "{}", command);
+        }
+
+        let top = detect("top").unwrap();
+        assert_eq!(
+            top.to_string(),
+            "'top' is interactive and would hang the shell; use top -bn1"
+        );
+    }
+
+    #[test]
+    fn test_alternate_screen() {
+        assert!(enters_alternate_screen("\x1b[?1049h\x1b[22;0;0t"));
+        assert!(!enters_alternate_screen("\x1b[?2004hplain"));
+    }
+}
@@ -0,0 +1,212 @@
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::Value;
+
+use super::shell::quote;
+use crate::error::SshLiaisonError;
+
+pub const DEFAULT_JOURNAL_LINES: u32 = 100;
+pub const MAX_JOURNAL_LINES: u32 = 1000;
+
+const PRIORITIES: &[&str] = &[
+    "emerg", "alert", "crit", "err", "warning", "notice", "info", "debug",
+];
+
+/// Fields requested in JSON mode; the rest of each record is noise here.
+const JSON_FIELDS: &str =
+    "__REALTIME_TIMESTAMP,_SYSTEMD_UNIT,SYSLOG_IDENTIFIER,_PID,PRIORITY,MESSAGE";
+
+/// Filters for a `journalctl` read.
+#[derive(Debug, Default)]
+pub struct JournalQuery<'a> {
+    pub unit: Option<&'a str>,
+    /// A level (`err`, `3`) or a range (`warning..err`).
+    pub priority: Option<&'a str>,
+    /// Anything `journalctl --since` accepts: `-1h`, `today`, `2024-05-01 10:00`.
+    pub since: Option<&'a str>,
+    pub until: Option<&'a str>,
+    /// Regular expression matched against the message (`journalctl --grep`).
+    pub grep: Option<&'a str>,
+    pub lines: u32,
+    pub json: bool,
+}
+
+impl JournalQuery<'_> {
+    /// The `journalctl` command line, newest `lines` entries last.
+    pub fn command(&self) -> Result<String> {
+        if self.lines == 0 || self.lines > MAX_JOURNAL_LINES {
+            return Err(SshLiaisonError::Config(format!(
+                "lines must be between 1 and {}",
+                MAX_JOURNAL_LINES
+            ))
+            .into());
+        }
+        let mut command = format!("journalctl --no-pager --quiet -n {}", self.lines);
+        if let Some(unit) = self.unit {
+            command.push_str(&format!(" -u {}", quote(unit)));
+        }
+        if let Some(priority) = self.priority {
+            if !priority.split("..").all(is_priority) {
+                return Err(SshLiaisonError::Config(format!(
+                    "Invalid priority '{}': use 0-7, {} or a range like warning..err",
+                    priority,
+                    PRIORITIES.join("/")
+                ))
+                .into());
+            }
+            command.push_str(&format!(" -p {}", priority));
+        }
+        if let Some(since) = self.since {
+            command.push_str(&format!(" --since {}", quote(since)));
+        }
+        if let Some(until) = self.until {
+            command.push_str(&format!(" --until {}", quote(until)));
+        }
+        if let Some(grep) = self.grep {
+            command.push_str(&format!(" --grep {}", quote(grep)));
+        }
+        if self.json {
+            command.push_str(&format!(" -o json --output-fields={}", JSON_FIELDS));
+        } else {
+            command.push_str(" -o short-iso");
+        }
+        Ok(command)
+    }
+}
+
+fn is_priority(value: &str) -> bool {
+    PRIORITIES.contains(&value) || matches!(value.parse::<u8>(), Ok(0..=7))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JournalEntry {
+    /// Unix seconds.
+    pub timestamp: Option<u64>,
+    pub unit: Option<String>,
+    pub identifier: Option<String>,
+    pub pid: Option<u32>,
+    /// 0 (emerg) to 7 (debug).
+    pub priority: Option<u8>,
+    pub message: String,
+}
+
+/// Parses `journalctl -o json` output, one record per line. Lines that aren't
+/// records (a truncation marker, stray stderr) are skipped.
+pub fn parse_json(output: &str) -> Vec<JournalEntry> {
+    output
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line.trim()).ok())
+        .filter_map(|record| {
+            let field = |name: &str| record.get(name).and_then(field_text);
+            Some(JournalEntry {
+                timestamp: field("__REALTIME_TIMESTAMP")
+                    .and_then(|us| us.parse::<u64>().ok())
+                    .map(|us| us / 1_000_000),
+                unit: field("_SYSTEMD_UNIT"),
+                identifier: field("SYSLOG_IDENTIFIER"),
+                pid: field("_PID").and_then(|pid| pid.parse().ok()),
+                priority: field("PRIORITY").and_then(|p| p.parse().ok()),
+                message: field("MESSAGE")?,
+            })
+        })
+        .collect()
+}
+
+/// A field's text; journald sends non-UTF-8 values as byte arrays.
+fn field_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Array(bytes) => {
+            let bytes: Vec<u8> = bytes
+                .iter()
+                .filter_map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+                .collect();
+            Some(String::from_utf8_lossy(&bytes).into_owned())
+        }
+        _ => None,
+    }
+}
+
+/// The newest of `items` whose sizes add up to at most `max_bytes`, and how
+/// many older ones were dropped. The newest item is kept whatever its size.
+pub fn keep_newest<T>(
+    mut items: Vec<T>,
+    max_bytes: usize,
+    size: impl Fn(&T) -> usize,
+) -> (Vec<T>, usize) {
+    let mut total = 0;
+    let mut keep = 0;
+    for item in items.iter().rev() {
+        total += size(item);
+        if keep > 0 && total > max_bytes {
+            break;
+        }
+        keep += 1;
+    }
+    let dropped = items.len() - keep;
+    items.drain(..dropped);
+    (items, dropped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal_command() {
+        let query = JournalQuery {
+            unit: Some("nginx.service"),
+            priority: Some("warning..err"),
+            since: Some("-1h"),
+            grep: Some("upstream timed out"),
+            lines: 50,
+            ..Default::default()
+        };
+        assert_eq!(
+            query.command().unwrap(),
+            "journalctl --no-pager --quiet -n 50 -u nginx.service -p warning..err --since -1h --grep 'upstream timed out' -o short-iso"
+        );
+
+        let bad = JournalQuery {
+            priority: Some("err; reboot"),
+            lines: 10,
+            ..Default::default()
+        };
+        assert!(bad.command().is_err());
+        let too_many = JournalQuery {
+            lines: MAX_JOURNAL_LINES + 1,
+            ..Default::default()
+        };
+        assert!(too_many.command().is_err());
+    }
+
+    #[test]
+    fn test_parse_json_entries() {
+        let output = concat!(
+            r#"{"__REALTIME_TIMESTAMP":"1714557600123456","_SYSTEMD_UNIT":"nginx.service","SYSLOG_IDENTIFIER":"nginx","_PID":"812","PRIORITY":"3","MESSAGE":"bind() failed"}"#,
+            "\n[... 10 bytes truncated ...]\n",
+            r#"{"PRIORITY":"6","MESSAGE":[104,105,255]}"#,
+            "\n"
+        );
+        let entries = parse_json(output);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].timestamp, Some(1714557600));
+        assert_eq!(entries[0].pid, Some(812));
+        assert_eq!(entries[0].priority, Some(3));
+        assert_eq!(entries[1].message, "hi\u{fffd}");
+    }
+
+    #[test]
+    fn test_keep_newest() {
+        let lines = vec!["aaaa", "bb", "cc", "dddddd"];
+        assert_eq!(
+            keep_newest(lines.clone(), 8, |l| l.len()),
+            (vec!["cc", "dddddd"], 2)
+        );
+        assert_eq!(
+            keep_newest(lines.clone(), 10, |l| l.len()),
+            (vec!["bb", "cc", "dddddd"], 1)
+        );
+        assert_eq!(keep_newest(lines, 2, |l| l.len()).0, vec!["dddddd"]);
+    }
+}
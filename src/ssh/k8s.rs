@@ -0,0 +1,309 @@
+//! One-call health check of a Kubernetes node: kubelet and container runtime
+//! services, disk pressure on the paths the kubelet evicts for, and recent
+//! kubelet errors.
+
+use serde::Serialize;
+
+/// kubelet first; only the runtimes that are installed show up as loaded.
+const SERVICES: &[&str] = &["kubelet", "containerd", "docker", "crio"];
+const RUNTIMES: &[&str] = &["containerd", "docker", "crio"];
+
+/// The kubelet's nodefs and imagefs, plus logs that often fill them.
+const PRESSURE_PATHS: &[&str] = &[
+    "/",
+    "/var/lib/kubelet",
+    "/var/lib/containerd",
+    "/var/lib/docker",
+    "/var/lib/containers",
+    "/var/log",
+];
+
+/// The kubelet's default hard eviction thresholds are 10% free space on
+/// nodefs, 15% on imagefs and 5% free inodes; warn a little before.
+const DISK_WARN_PERCENT: u8 = 85;
+const INODE_WARN_PERCENT: u8 = 90;
+
+pub const DEFAULT_ERROR_WINDOW_MINUTES: u32 = 60;
+pub const DEFAULT_MAX_ERRORS: usize = 20;
+pub const MAX_ERRORS: usize = 200;
+const MAX_ERROR_CHARS: usize = 500;
+
+const SECTION: &str = "@@triage";
+
+/// A single line of read-only commands, so the triage also runs in
+/// read-only mode. Each part is introduced by a `@@triage <part>` line.
+pub fn triage_command(window_minutes: u32, max_errors: usize) -> String {
+    let mut parts = vec![
+        format!("echo '{} services'", SECTION),
+        format!(
+            "systemctl show --no-pager -p Id,LoadState,ActiveState,SubState,NRestarts,ActiveEnterTimestamp {}",
+            SERVICES.join(" ")
+        ),
+    ];
+    for path in PRESSURE_PATHS {
+        parts.push(format!("echo '{} disk {}'", SECTION, path));
+        parts.push(format!("df -P -k {} 2>/dev/null", path));
+        parts.push(format!("df -P -i {} 2>/dev/null", path));
+    }
+    parts.push(format!("echo '{} errors'", SECTION));
+    // klog writes errors as `E0501 10:00:00.123456 ...` at journald's
+    // default priority, so `-p err` would miss them.
+    parts.push(format!(
+        "journalctl -u kubelet --since '-{}min' --no-pager -q -o cat 2>/dev/null | grep -E '^E[0-9]{{4}} ' | tail -n {}",
+        window_minutes, max_errors
+    ));
+    parts.join("; ")
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ServiceStatus {
+    pub name: String,
+    /// `loaded`, or `not-found` when the unit isn't installed.
+    pub load_state: String,
+    pub active_state: String,
+    pub sub_state: String,
+    pub restarts: Option<u32>,
+    pub active_since: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PathUsage {
+    pub path: String,
+    pub mount: String,
+    pub used_percent: Option<u8>,
+    pub available_kb: Option<u64>,
+    pub inodes_used_percent: Option<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct NodeTriage {
+    /// Installed services only.
+    pub services: Vec<ServiceStatus>,
+    /// Paths that exist, one per filesystem.
+    pub disks: Vec<PathUsage>,
+    pub kubelet_errors: Vec<String>,
+    /// What looks wrong, most severe first; empty when the node looks healthy.
+    pub problems: Vec<String>,
+}
+
+/// Parses [`triage_command`] output and lists the problems it shows.
+pub fn parse_triage(output: &str) -> NodeTriage {
+    let mut triage = NodeTriage::default();
+    let mut section = "";
+    let mut disk: Option<PathUsage> = None;
+    let mut service = ServiceStatus::default();
+
+    for line in output.lines() {
+        if let Some(header) = line.strip_prefix(SECTION) {
+            flush_disk(&mut triage, disk.take());
+            let mut words = header.split_whitespace();
+            section = match words.next() {
+                Some("services") => "services",
+                Some("disk") => {
+                    disk = words.next().map(|path| PathUsage {
+                        path: path.to_string(),
+                        ..Default::default()
+                    });
+                    "disk"
+                }
+                Some("errors") => "errors",
+                _ => "",
+            };
+            continue;
+        }
+        match section {
+            "services" => {
+                if line.trim().is_empty() {
+                    flush_service(&mut triage, std::mem::take(&mut service));
+                } else if let Some((key, value)) = line.split_once('=') {
+                    let value = value.trim().to_string();
+                    match key {
+                        "Id" => service.name = value.trim_end_matches(".service").to_string(),
+                        "LoadState" => service.load_state = value,
+                        "ActiveState" => service.active_state = value,
+                        "SubState" => service.sub_state = value,
+                        "NRestarts" => service.restarts = value.parse().ok(),
+                        "ActiveEnterTimestamp" => {
+                            service.active_since = Some(value).filter(|v| !v.is_empty())
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            "disk" => {
+                if let Some(ref mut usage) = disk {
+                    parse_df_line(line, usage);
+                }
+            }
+            "errors" if !line.trim().is_empty() => {
+                triage
+                    .kubelet_errors
+                    .push(line.chars().take(MAX_ERROR_CHARS).collect());
+            }
+            _ => {}
+        }
+    }
+    flush_service(&mut triage, service);
+    flush_disk(&mut triage, disk);
+    triage.problems = problems(&triage);
+    triage
+}
+
+fn flush_service(triage: &mut NodeTriage, service: ServiceStatus) {
+    if !service.name.is_empty() && service.load_state != "not-found" {
+        triage.services.push(service);
+    }
+}
+
+fn flush_disk(triage: &mut NodeTriage, usage: Option<PathUsage>) {
+    let Some(usage) = usage.filter(|u| !u.mount.is_empty()) else {
+        return;
+    };
+    // `/var/lib/kubelet` is usually on `/`; report each filesystem once.
+    if !triage.disks.iter().any(|d| d.mount == usage.mount) {
+        triage.disks.push(usage);
+    }
+}
+
+/// A `df -P` data line, `-k` (`used avail capacity%`) or `-i`
+/// (`iused ifree iuse%`); both have six columns, the last being the mount.
+fn parse_df_line(line: &str, usage: &mut PathUsage) {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 6 || fields[0] == "Filesystem" {
+        return;
+    }
+    let percent = fields[4].trim_end_matches('%').parse().ok();
+    usage.mount = fields[5..].join(" ");
+    if usage.used_percent.is_none() && usage.available_kb.is_none() {
+        usage.used_percent = percent;
+        usage.available_kb = fields[3].parse().ok();
+    } else {
+        usage.inodes_used_percent = percent;
+    }
+}
+
+fn problems(triage: &NodeTriage) -> Vec<String> {
+    let mut problems = Vec::new();
+    let active = |name: &str| {
+        triage
+            .services
+            .iter()
+            .any(|s| s.name == name && s.active_state == "active")
+    };
+
+    match triage.services.iter().find(|s| s.name == "kubelet") {
+        None => problems.push("kubelet is not installed".to_string()),
+        Some(kubelet) if kubelet.active_state != "active" => problems.push(format!(
+            "kubelet is {} ({})",
+            kubelet.active_state, kubelet.sub_state
+        )),
+        Some(_) => {}
+    }
+    if !RUNTIMES.iter().any(|r| active(r)) {
+        problems.push("No container runtime (containerd, docker, crio) is active".to_string());
+    }
+    for service in &triage.services {
+        if let Some(restarts) = service.restarts.filter(|r| *r > 0) {
+            problems.push(format!("{} restarted {} times", service.name, restarts));
+        }
+    }
+    for disk in &triage.disks {
+        if let Some(used) = disk.used_percent.filter(|u| *u >= DISK_WARN_PERCENT) {
+            problems.push(format!("{} ({}) is {}% full", disk.mount, disk.path, used));
+        }
+        if let Some(used) = disk
+            .inodes_used_percent
+            .filter(|u| *u >= INODE_WARN_PERCENT)
+        {
+            problems.push(format!(
+                "{} ({}) has {}% of inodes used",
+                disk.mount, disk.path, used
+            ));
+        }
+    }
+    if !triage.kubelet_errors.is_empty() {
+        problems.push(format!(
+            "{} recent kubelet errors",
+            triage.kubelet_errors.len()
+        ));
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_triage_command_is_read_only() {
+        let command = triage_command(30, 10);
+        assert!(command.contains("--since '-30min'"));
+        assert!(command.contains("tail -n 10"));
+        assert!(crate::policy::is_read_only_command(&command));
+    }
+
+    #[test]
+    fn test_parse_triage() {
+        let output = "\
+@@triage services
+Id=kubelet.service
+LoadState=loaded
+ActiveState=activating
+SubState=auto-restart
+NRestarts=14
+ActiveEnterTimestamp=
+
+Id=containerd.service
+LoadState=loaded
+ActiveState=active
+SubState=running
+NRestarts=0
+ActiveEnterTimestamp=Wed 2024-05-01 10:00:00 UTC
+
+Id=docker.service
+LoadState=not-found
+ActiveState=inactive
+SubState=dead
+NRestarts=0
+ActiveEnterTimestamp=
+@@triage disk /
+Filesystem     1024-blocks     Used Available Capacity Mounted on
+/dev/sda1         41152736 37037462   4115274      91% /
+Filesystem      Inodes  IUsed   IFree IUse% Mounted on
+/dev/sda1      2621440 262144 2359296   10% /
+@@triage disk /var/lib/kubelet
+Filesystem     1024-blocks     Used Available Capacity Mounted on
+/dev/sda1         41152736 37037462   4115274      91% /
+Filesystem      Inodes  IUsed   IFree IUse% Mounted on
+/dev/sda1      2621440 262144 2359296   10% /
+@@triage disk /var/lib/docker
+@@triage errors
+E0501 10:00:00.000000    812 kubelet.go:2855] \"Container runtime network not ready\"
+";
+        let triage = parse_triage(output);
+        assert_eq!(
+            triage
+                .services
+                .iter()
+                .map(|s| s.name.as_str())
+                .collect::<Vec<_>>(),
+            ["kubelet", "containerd"]
+        );
+        assert_eq!(triage.services[0].restarts, Some(14));
+        assert_eq!(triage.services[0].active_since, None);
+        assert_eq!(triage.disks.len(), 1);
+        assert_eq!(triage.disks[0].used_percent, Some(91));
+        assert_eq!(triage.disks[0].available_kb, Some(4115274));
+        assert_eq!(triage.disks[0].inodes_used_percent, Some(10));
+        assert_eq!(triage.kubelet_errors.len(), 1);
+        assert_eq!(
+            triage.problems,
+            [
+                "kubelet is activating (auto-restart)",
+                "kubelet restarted 14 times",
+                "/ (/) is 91% full",
+                "1 recent kubelet errors",
+            ]
+        );
+    }
+}
@@ -0,0 +1,447 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::config::parse_ssh_config;
+use super::keys::PublicKey;
+
+/// `~/.ssh/known_hosts`.
+pub fn default_path() -> PathBuf {
+    let home = std::env::var("HOME").unwrap_or_else(|_| "~".to_string());
+    PathBuf::from(home).join(".ssh").join("known_hosts")
+}
+
+/// Hostname and port `host` is reached at: the `HostName`/`Port` of a
+/// `~/.ssh/config` alias, else `host` itself on 22. `port` wins when given.
+pub fn resolve(host: &str, port: Option<u16>) -> (String, u16) {
+    let config = parse_ssh_config(host).ok();
+    let hostname = config
+        .as_ref()
+        .and_then(|c| c.hostname.clone())
+        .unwrap_or_else(|| host.to_string());
+    let port = port.or(config.and_then(|c| c.port)).unwrap_or(22);
+    (hostname, port)
+}
+
+/// How OpenSSH names a host in known_hosts: `host`, or `[host]:port` off 22.
+pub fn host_key_name(hostname: &str, port: u16) -> String {
+    if port == 22 {
+        hostname.to_string()
+    } else {
+        format!("[{}]:{}", hostname, port)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct KnownHostEntry {
+    /// 1-based line in the file.
+    pub line: usize,
+    /// `cert-authority` or `revoked`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub marker: Option<String>,
+    /// Host patterns; `None` for hashed entries (`HashKnownHosts`).
+    pub hosts: Option<String>,
+    pub key_type: String,
+    pub fingerprint: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(skip)]
+    blob: String,
+}
+
+/// Entries of a known_hosts file; comments and lines that don't parse are skipped.
+pub fn parse(content: &str) -> Vec<KnownHostEntry> {
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                return None;
+            }
+            let (marker, line) = match line.strip_prefix('@') {
+                Some(rest) => {
+                    let (marker, rest) = rest.split_once(char::is_whitespace)?;
+                    (Some(marker.to_string()), rest.trim_start())
+                }
+                None => (None, line),
+            };
+            let (hosts, key) = line.split_once(char::is_whitespace)?;
+            let key = PublicKey::parse(key).ok()?;
+            Some(KnownHostEntry {
+                line: i + 1,
+                marker,
+                hosts: (!hosts.starts_with("|1|")).then(|| hosts.to_string()),
+                fingerprint: key.fingerprint(),
+                key_type: key.key_type,
+                comment: key.comment,
+                blob: key.blob,
+            })
+        })
+        .collect()
+}
+
+/// Line numbers `ssh-keygen -F` / `-R` report as matching.
+pub fn parse_found_lines(output: &str) -> Vec<usize> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let rest = line.strip_prefix("# Host ")?;
+            let (_, found) = rest.split_once(" found: line ")?;
+            found.split_whitespace().next()?.parse().ok()
+        })
+        .collect()
+}
+
+async fn ssh_keygen(args: &[&str], file: &Path) -> Result<String> {
+    let output = tokio::process::Command::new("ssh-keygen")
+        .args(args)
+        .arg("-f")
+        .arg(file)
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .context("Failed to run ssh-keygen; is OpenSSH installed and on PATH?")?;
+    if !output.status.success() {
+        anyhow::bail!(
+            "ssh-keygen {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Entries in `file` that apply to `name` (see [`host_key_name`]), hashed
+/// and wildcard ones included, as `ssh-keygen -F` finds them.
+pub async fn entries_for(file: &Path, name: &str) -> Result<Vec<KnownHostEntry>> {
+    if !file.exists() {
+        return Ok(Vec::new());
+    }
+    let found = parse_found_lines(&ssh_keygen(&["-F", name], file).await?);
+    let content = tokio::fs::read_to_string(file).await?;
+    Ok(parse(&content)
+        .into_iter()
+        .filter(|entry| found.contains(&entry.line))
+        .collect())
+}
+
+/// Appends `keys` for `name` unless they are already known for it, creating
+/// `~/.ssh` (700) and the file as needed. Returns the keys added.
+pub async fn add(file: &Path, name: &str, keys: &[PublicKey]) -> Result<Vec<PublicKey>> {
+    let existing = entries_for(file, name).await?;
+    let new: Vec<PublicKey> = keys
+        .iter()
+        .filter(|key| {
+            !existing
+                .iter()
+                .any(|e| e.marker.is_none() && e.blob == key.blob)
+        })
+        .cloned()
+        .collect();
+    if new.is_empty() {
+        return Ok(new);
+    }
+
+    if let Some(parent) = file.parent()
+        && !parent.exists()
+    {
+        std::fs::create_dir_all(parent)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(parent, std::fs::Permissions::from_mode(0o700))?;
+        }
+    }
+    let needs_newline = std::fs::read(file)
+        .map(|content| !content.is_empty() && !content.ends_with(b"\n"))
+        .unwrap_or(false);
+    let mut lines = String::new();
+    if needs_newline {
+        lines.push('\n');
+    }
+    for key in &new {
+        lines.push_str(&format!("{} {} {}\n", name, key.key_type, key.blob));
+    }
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file)
+        .and_then(|mut f| f.write_all(lines.as_bytes()))
+        .with_context(|| format!("Failed to write {}", file.display()))?;
+    for key in &new {
+        tracing::info!(
+            "Added {} {} key {} to {}",
+            name,
+            key.key_type,
+            key.fingerprint(),
+            file.display()
+        );
+    }
+    Ok(new)
+}
+
+/// Removes every key recorded for `name` with `ssh-keygen -R`, which keeps
+/// the previous file as `<file>.old`. `@cert-authority` and `@revoked` lines
+/// stay. Returns how many entries went.
+pub async fn remove(file: &Path, name: &str) -> Result<usize> {
+    if !file.exists() {
+        return Ok(0);
+    }
+    let removed = parse_found_lines(&ssh_keygen(&["-R", name], file).await?).len();
+    if removed > 0 {
+        tracing::warn!(
+            "Removed {} known_hosts entries for {} from {}",
+            removed,
+            name,
+            file.display()
+        );
+    }
+    Ok(removed)
+}
+
+/// Host keys `ssh-keyscan` collects from `hostname:port`, one per type.
+pub async fn keyscan(hostname: &str, port: u16, timeout: Duration) -> Result<Vec<PublicKey>> {
+    let output = tokio::process::Command::new("ssh-keyscan")
+        .args(["-T", &timeout.as_secs().max(1).to_string()])
+        .args(["-p", &port.to_string()])
+        .arg(hostname)
+        .stdin(std::process::Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .context("Failed to run ssh-keyscan; is OpenSSH installed and on PATH?")?;
+    let keys = parse_keyscan(&String::from_utf8_lossy(&output.stdout));
+    if keys.is_empty() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = stderr
+            .lines()
+            .filter(|l| !l.starts_with('#'))
+            .collect::<Vec<_>>()
+            .join("; ");
+        anyhow::bail!(
+            "No host keys received from {}:{}{}",
+            hostname,
+            port,
+            if reason.is_empty() {
+                String::new()
+            } else {
+                format!(": {}", reason)
+            }
+        );
+    }
+    Ok(keys)
+}
+
+/// Keys in `ssh-keyscan` output (`<host> <type> <base64>` lines).
+pub fn parse_keyscan(output: &str) -> Vec<PublicKey> {
+    output
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .filter_map(|(_, key)| PublicKey::parse(key).ok())
+        .collect()
+}
+
+/// How a scanned key compares with what known_hosts has for the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyStatus {
+    /// Recorded for the host.
+    Known,
+    /// The host has a different key of this type recorded: it was
+    /// reinstalled, or something is in the middle.
+    Changed,
+    /// Nothing of this type is recorded.
+    New,
+    /// Listed under `@revoked`.
+    Revoked,
+}
+
+impl std::fmt::Display for KeyStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyStatus::Known => write!(f, "known"),
+            KeyStatus::Changed => write!(f, "changed"),
+            KeyStatus::New => write!(f, "new"),
+            KeyStatus::Revoked => write!(f, "revoked"),
+        }
+    }
+}
+
+pub fn key_status(entries: &[KnownHostEntry], key: &PublicKey) -> KeyStatus {
+    if entries
+        .iter()
+        .any(|e| e.blob == key.blob && e.marker.as_deref() == Some("revoked"))
+    {
+        KeyStatus::Revoked
+    } else if entries
+        .iter()
+        .any(|e| e.marker.is_none() && e.blob == key.blob)
+    {
+        KeyStatus::Known
+    } else if entries
+        .iter()
+        .any(|e| e.marker.is_none() && e.key_type == key.key_type)
+    {
+        KeyStatus::Changed
+    } else {
+        KeyStatus::New
+    }
+}
+
+/// What to do with a host key known_hosts doesn't vouch for, as
+/// `StrictHostKeyChecking` says.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostKeyChecking {
+    /// `yes`/`ask`: only keys already in known_hosts.
+    Strict,
+    /// `accept-new`: record unknown keys, refuse changed ones.
+    #[default]
+    AcceptNew,
+    /// `no`/`off`: accept any key.
+    Off,
+}
+
+impl HostKeyChecking {
+    /// The `StrictHostKeyChecking` value in `~/.ssh/config`.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "yes" | "ask" => Some(Self::Strict),
+            "accept-new" => Some(Self::AcceptNew),
+            "no" | "off" => Some(Self::Off),
+            _ => None,
+        }
+    }
+}
+
+/// Checks the key `hostname:port` presented against `~/.ssh/known_hosts`.
+/// Changed and revoked keys are always refused, and so is every key when
+/// the file can't be read; unknown ones as `checking` says.
+pub async fn verify_host_key(
+    hostname: &str,
+    port: u16,
+    key: &PublicKey,
+    checking: HostKeyChecking,
+) -> Result<()> {
+    if checking == HostKeyChecking::Off {
+        return Ok(());
+    }
+    let file = default_path();
+    let name = host_key_name(hostname, port);
+    let entries = entries_for(&file, &name).await.with_context(|| {
+        format!(
+            "Could not check the host key of {} against known_hosts",
+            name
+        )
+    })?;
+    match key_status(&entries, key) {
+        KeyStatus::Known => Ok(()),
+        KeyStatus::New if checking == HostKeyChecking::AcceptNew => {
+            if let Err(e) = add(&file, &name, std::slice::from_ref(key)).await {
+                tracing::warn!(host = %name, error = %e, "Could not record the new host key");
+            }
+            Ok(())
+        }
+        KeyStatus::New => anyhow::bail!(
+            "{} host key {} of {} is not in known_hosts (StrictHostKeyChecking); \
+             check it and add it with ssh_known_hosts_add",
+            key.key_type,
+            key.fingerprint(),
+            name
+        ),
+        status => anyhow::bail!(
+            "{} host key {} of {} is {}; known_hosts has another one or revoked it",
+            key.key_type,
+            key.fingerprint(),
+            name,
+            status
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ED25519: &str =
+        "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIH4Ko7L+FwyJy2+IrOryQ1rLgMcQCf16zuv5xdcPVRx6";
+
+    #[test]
+    fn test_parse_known_hosts() {
+        let content = format!(
+            "# comment\n\nweb1,10.0.0.1 {ED25519} old laptop\n\
+             |1|A37pijEk4ZVOWsOAqu/WFPnCDE4=|4pScLM69cdE/Et8VBruuQ35iXQ4= {ED25519}\n\
+             @revoked * {ED25519}\ngarbage\n"
+        );
+        let entries = parse(&content);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].line, 3);
+        assert_eq!(entries[0].hosts.as_deref(), Some("web1,10.0.0.1"));
+        assert_eq!(entries[0].comment.as_deref(), Some("old laptop"));
+        assert_eq!(
+            entries[0].fingerprint,
+            "SHA256:57RicpQ1a/7GqJUD3rFb8pl18XOjX+kJOldXBQz0nA4"
+        );
+        assert_eq!(entries[1].hosts, None);
+        assert_eq!(entries[2].marker.as_deref(), Some("revoked"));
+        assert_eq!(entries[2].hosts.as_deref(), Some("*"));
+    }
+
+    #[test]
+    fn test_parse_keygen_and_keyscan_output() {
+        let found = "# Host web1 found: line 1 \n|1|x|y ssh-ed25519 AAAA\n\
+                     # Host web1 found: line 6 REVOKED\n@revoked * ssh-ed25519 AAAA\n";
+        assert_eq!(parse_found_lines(found), [1, 6]);
+        assert!(parse_found_lines("Host nope not found in kh\n").is_empty());
+
+        let scanned = parse_keyscan(&format!(
+            "# web1:22 SSH-2.0-OpenSSH_9.6\nweb1 {ED25519}\nweb1 ssh-rsa bogus\n"
+        ));
+        assert_eq!(scanned.len(), 1);
+        assert_eq!(scanned[0].key_type, "ssh-ed25519");
+        assert_eq!(host_key_name("web1", 22), "web1");
+        assert_eq!(host_key_name("10.0.0.5", 2222), "[10.0.0.5]:2222");
+    }
+
+    #[test]
+    fn test_key_status() {
+        let scanned = PublicKey::parse(ED25519).unwrap();
+        let entries = parse(&format!("web1 {ED25519}\n"));
+        assert_eq!(key_status(&entries, &scanned), KeyStatus::Known);
+        assert_eq!(key_status(&[], &scanned), KeyStatus::New);
+
+        let other =
+            "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIAcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcH";
+        let entries = parse(&format!("web1 {other}\n"));
+        assert_eq!(key_status(&entries, &scanned), KeyStatus::Changed);
+        let entries = parse(&format!("web1 {other}\n@revoked * {ED25519}\n"));
+        assert_eq!(key_status(&entries, &scanned), KeyStatus::Revoked);
+
+        let blob =
+            base64::Engine::decode(&base64::engine::general_purpose::STANDARD, &scanned.blob)
+                .unwrap();
+        let presented = PublicKey::from_blob(&blob).unwrap();
+        assert_eq!(presented.key_type, "ssh-ed25519");
+        assert_eq!(presented.fingerprint(), scanned.fingerprint());
+        assert!(PublicKey::from_blob(b"junk").is_err());
+    }
+
+    #[test]
+    fn test_host_key_checking() {
+        assert_eq!(HostKeyChecking::parse("yes"), Some(HostKeyChecking::Strict));
+        assert_eq!(HostKeyChecking::parse("ask"), Some(HostKeyChecking::Strict));
+        assert_eq!(
+            HostKeyChecking::parse("Accept-New"),
+            Some(HostKeyChecking::AcceptNew)
+        );
+        assert_eq!(HostKeyChecking::parse("no"), Some(HostKeyChecking::Off));
+        assert_eq!(HostKeyChecking::parse("off"), Some(HostKeyChecking::Off));
+        assert_eq!(HostKeyChecking::parse("maybe"), None);
+        assert_eq!(HostKeyChecking::default(), HostKeyChecking::AcceptNew);
+    }
+}
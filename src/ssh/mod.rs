@@ -1,5 +1,36 @@
+pub mod agent;
+pub mod ansi;
+pub mod auth;
+pub mod backend;
+pub mod binary;
 pub mod channel;
+pub mod charset;
 pub mod config;
+pub mod disk;
+pub mod docker;
+pub mod files;
+pub mod health;
+pub mod interactive;
+pub mod journal;
+pub mod k8s;
+pub mod keys;
+pub mod known_hosts;
+pub mod network;
+pub mod packages;
+pub mod passphrase;
+pub mod process;
+pub mod reconnect;
+pub mod recording;
+pub mod running;
+pub mod schedule;
+pub mod search;
 pub mod session;
+pub mod shell;
+pub mod socks;
+pub mod store;
+pub mod transfer;
+pub mod transport;
+pub mod tunnel;
+pub mod watch;
 
 pub use session::SessionManager;
@@ -0,0 +1,645 @@
+//! Interfaces, routes and listening sockets of a host. Uses `ip -j` and
+//! `ss` where available and falls back to `ifconfig` and `netstat` on boxes
+//! without iproute2 (or with one too old for JSON output). Also TCP probes
+//! from the host to somewhere else.
+
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::net::Ipv4Addr;
+
+use crate::error::SshLiaisonError;
+
+const SECTION: &str = "@@net";
+
+/// net-tools live in /sbin, which isn't on a regular user's PATH everywhere.
+const SBIN_PATH: &str = "PATH=$PATH:/sbin:/usr/sbin";
+
+/// A single line of read-only commands; each part is introduced by a
+/// `@@net <part>` line and tries iproute2 first.
+pub fn network_command() -> String {
+    [
+        format!("echo '{} addr'", SECTION),
+        format!(
+            "ip -j addr show 2>/dev/null || {} ifconfig -a 2>/dev/null",
+            SBIN_PATH
+        ),
+        format!("echo '{} route'", SECTION),
+        format!(
+            "ip -j route show 2>/dev/null || {} netstat -rn 2>/dev/null",
+            SBIN_PATH
+        ),
+        format!("echo '{} listen'", SECTION),
+        format!(
+            "ss -tulnp 2>/dev/null || {} netstat -tulnp 2>/dev/null",
+            SBIN_PATH
+        ),
+    ]
+    .join("; ")
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InterfaceAddress {
+    /// `inet` or `inet6`.
+    pub family: String,
+    pub address: String,
+    pub prefix_len: Option<u8>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct NetworkInterface {
+    pub name: String,
+    /// `up`, `down` or `unknown` (loopback and some virtual devices).
+    pub state: String,
+    pub mac: Option<String>,
+    pub mtu: Option<u32>,
+    pub addresses: Vec<InterfaceAddress>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Route {
+    /// `default` or a CIDR block.
+    pub destination: String,
+    pub gateway: Option<String>,
+    pub interface: Option<String>,
+    pub metric: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ListeningSocket {
+    /// `tcp` or `udp`.
+    pub protocol: String,
+    pub address: String,
+    pub port: Option<u16>,
+    /// Only known for the user's own processes unless connected as root.
+    pub process: Option<String>,
+    pub pid: Option<u32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct NetworkInfo {
+    pub interfaces: Vec<NetworkInterface>,
+    pub routes: Vec<Route>,
+    pub listening: Vec<ListeningSocket>,
+    /// Tools that produced the data, e.g. `["ip", "ss"]`.
+    pub via: Vec<&'static str>,
+}
+
+/// Parses [`network_command`] output, whichever tools answered.
+pub fn parse_network(output: &str) -> NetworkInfo {
+    let mut sections: Vec<(&str, String)> = Vec::new();
+    for line in output.lines() {
+        if let Some(header) = line.strip_prefix(SECTION) {
+            sections.push((header.trim(), String::new()));
+        } else if let Some((_, body)) = sections.last_mut() {
+            body.push_str(line);
+            body.push('\n');
+        }
+    }
+
+    let mut info = NetworkInfo::default();
+    for (section, body) in &sections {
+        if body.trim().is_empty() {
+            continue;
+        }
+        let json = body.trim_start().starts_with('[');
+        match *section {
+            "addr" if json => {
+                info.interfaces = parse_ip_addr(body);
+                info.via.push("ip");
+            }
+            "addr" => {
+                info.interfaces = parse_ifconfig(body);
+                info.via.push("ifconfig");
+            }
+            "route" if json => {
+                info.routes = parse_ip_route(body);
+                if !info.via.contains(&"ip") {
+                    info.via.push("ip");
+                }
+            }
+            "route" => {
+                info.routes = parse_netstat_routes(body);
+                info.via.push("netstat");
+            }
+            "listen" => {
+                let (sockets, via) = parse_listening(body);
+                info.listening = sockets;
+                if !info.via.contains(&via) {
+                    info.via.push(via);
+                }
+            }
+            _ => {}
+        }
+    }
+    info
+}
+
+#[derive(Deserialize)]
+struct IpLink {
+    ifname: String,
+    #[serde(default)]
+    operstate: Option<String>,
+    #[serde(default)]
+    mtu: Option<u32>,
+    #[serde(default)]
+    address: Option<String>,
+    #[serde(default)]
+    addr_info: Vec<IpAddrInfo>,
+}
+
+#[derive(Deserialize)]
+struct IpAddrInfo {
+    family: String,
+    local: String,
+    #[serde(default)]
+    prefixlen: Option<u8>,
+}
+
+fn parse_ip_addr(body: &str) -> Vec<NetworkInterface> {
+    let links: Vec<IpLink> = serde_json::from_str(body.trim()).unwrap_or_default();
+    links
+        .into_iter()
+        .map(|link| NetworkInterface {
+            state: link
+                .operstate
+                .map_or_else(|| "unknown".to_string(), |s| s.to_lowercase()),
+            mac: link.address.filter(|mac| mac != "00:00:00:00:00:00"),
+            mtu: link.mtu,
+            addresses: link
+                .addr_info
+                .into_iter()
+                .map(|a| InterfaceAddress {
+                    family: a.family,
+                    address: a.local,
+                    prefix_len: a.prefixlen,
+                })
+                .collect(),
+            name: link.ifname,
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+struct IpRoute {
+    dst: String,
+    #[serde(default)]
+    gateway: Option<String>,
+    #[serde(default)]
+    dev: Option<String>,
+    #[serde(default)]
+    metric: Option<u32>,
+}
+
+fn parse_ip_route(body: &str) -> Vec<Route> {
+    let routes: Vec<IpRoute> = serde_json::from_str(body.trim()).unwrap_or_default();
+    routes
+        .into_iter()
+        .map(|r| Route {
+            destination: r.dst,
+            gateway: r.gateway,
+            interface: r.dev,
+            metric: r.metric,
+        })
+        .collect()
+}
+
+/// Both the net-tools 2.10 layout (`eth0: flags=4163<UP,...>  mtu 1500`,
+/// `inet 10.0.0.5  netmask 255.255.255.0`) and the older one
+/// (`eth0  Link encap:Ethernet  HWaddr ...`, `inet addr:10.0.0.5  Mask:...`).
+fn parse_ifconfig(body: &str) -> Vec<NetworkInterface> {
+    let mut interfaces: Vec<NetworkInterface> = Vec::new();
+    for line in body.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        if !line.starts_with(char::is_whitespace) {
+            interfaces.push(NetworkInterface {
+                name: words[0].trim_end_matches(':').to_string(),
+                state: "down".to_string(),
+                ..Default::default()
+            });
+        }
+        let Some(interface) = interfaces.last_mut() else {
+            continue;
+        };
+        let after = |key: &str| {
+            words
+                .iter()
+                .position(|w| *w == key)
+                .and_then(|i| words.get(i + 1))
+                .copied()
+        };
+        let prefixed = |key: &str| words.iter().find_map(|w| w.strip_prefix(key));
+
+        if words.contains(&"UP") || line.contains("<UP") {
+            interface.state = "up".to_string();
+        }
+        if let Some(mtu) = after("mtu").or_else(|| prefixed("MTU:")) {
+            interface.mtu = mtu.parse().ok();
+        }
+        if let Some(mac) = after("ether").or_else(|| after("HWaddr")) {
+            interface.mac = Some(mac.to_string());
+        }
+        match words.first() {
+            Some(&"inet") => {
+                let address = prefixed("addr:").or(words.get(1).copied());
+                let mask = after("netmask").or_else(|| prefixed("Mask:"));
+                if let Some(address) = address {
+                    interface.addresses.push(InterfaceAddress {
+                        family: "inet".to_string(),
+                        address: address.to_string(),
+                        prefix_len: mask.and_then(netmask_prefix),
+                    });
+                }
+            }
+            Some(&"inet6") => {
+                // `inet6 fe80::1  prefixlen 64` or `inet6 addr: fe80::1/64 Scope:Link`
+                let value = if words.get(1) == Some(&"addr:") {
+                    words.get(2)
+                } else {
+                    words.get(1)
+                };
+                if let Some(value) = value {
+                    let (address, prefix) = match value.split_once('/') {
+                        Some((address, prefix)) => (address, prefix.parse().ok()),
+                        None => (*value, after("prefixlen").and_then(|p| p.parse().ok())),
+                    };
+                    interface.addresses.push(InterfaceAddress {
+                        family: "inet6".to_string(),
+                        address: address.to_string(),
+                        prefix_len: prefix,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    interfaces
+}
+
+/// `255.255.255.0` -> 24, or `0xffffff00` as printed by BSD ifconfig.
+fn netmask_prefix(mask: &str) -> Option<u8> {
+    let bits = match mask.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => u32::from(mask.parse::<Ipv4Addr>().ok()?),
+    };
+    Some(bits.count_ones() as u8)
+}
+
+/// `netstat -rn`: `Destination Gateway Genmask Flags MSS Window irtt Iface`.
+fn parse_netstat_routes(body: &str) -> Vec<Route> {
+    body.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 8 || fields[0].parse::<Ipv4Addr>().is_err() {
+                return None;
+            }
+            let prefix = netmask_prefix(fields[2])?;
+            Some(Route {
+                destination: if prefix == 0 {
+                    "default".to_string()
+                } else {
+                    format!("{}/{}", fields[0], prefix)
+                },
+                gateway: Some(fields[1].to_string()).filter(|g| g != "0.0.0.0"),
+                interface: fields.last().map(|i| i.to_string()),
+                metric: None,
+            })
+        })
+        .collect()
+}
+
+/// `ss -tulnp` (`Netid State Recv-Q Send-Q Local Peer Process`) or
+/// `netstat -tulnp` (`Proto Recv-Q Send-Q Local Foreign [State] PID/Program`),
+/// told apart by the second column being a state or a queue size.
+fn parse_listening(body: &str) -> (Vec<ListeningSocket>, &'static str) {
+    let mut via = "ss";
+    let mut sockets = Vec::new();
+    for line in body.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 5 || !(fields[0].starts_with("tcp") || fields[0].starts_with("udp")) {
+            continue;
+        }
+        let protocol = fields[0].trim_end_matches('6').to_string();
+        let (local, process_field) = if fields[1].parse::<u64>().is_ok() {
+            via = "netstat";
+            let process_at = if protocol == "tcp" { 6 } else { 5 };
+            (fields[3], fields.get(process_at..).map(|p| p.join(" ")))
+        } else {
+            (fields[4], fields.get(6..).map(|p| p.join(" ")))
+        };
+        let (address, port) = match local.rsplit_once(':') {
+            Some((address, port)) => (address, port.parse().ok()),
+            None => (local, None),
+        };
+        let (process, pid) = process_field
+            .filter(|p| !p.is_empty() && p != "-")
+            .map_or((None, None), |p| parse_process(&p));
+        sockets.push(ListeningSocket {
+            protocol,
+            address: address
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_string(),
+            port,
+            process,
+            pid,
+        });
+    }
+    (sockets, via)
+}
+
+/// `users:(("sshd",pid=812,fd=3))` from ss, or `812/sshd` from netstat.
+fn parse_process(field: &str) -> (Option<String>, Option<u32>) {
+    if let Some(users) = field.strip_prefix("users:((") {
+        let name = users.split('"').nth(1).map(str::to_string);
+        let pid = users
+            .split(',')
+            .find_map(|part| part.strip_prefix("pid="))
+            .and_then(|pid| pid.parse().ok());
+        return (name, pid);
+    }
+    match field.split_once('/') {
+        Some((pid, name)) => (Some(name.to_string()), pid.parse().ok()),
+        None => (Some(field.to_string()), None),
+    }
+}
+
+pub const DEFAULT_PROBE_TIMEOUT_SECS: u32 = 5;
+/// Below the default 30s command timeout, so `timeout` fires first.
+pub const MAX_PROBE_TIMEOUT_SECS: u32 = 25;
+
+const PROBE_EXIT: &str = "@@probe exit";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeMethod {
+    /// `nc`, then bash's `/dev/tcp` if there is no usable `nc`.
+    #[default]
+    Auto,
+    Nc,
+    /// bash's `/dev/tcp`; runs `bash -c`, so read-only mode refuses it.
+    Bash,
+}
+
+/// Host names and IP addresses only, so nothing else reaches the shell.
+fn check_target(host: &str, port: u16) -> Result<()> {
+    let valid = !host.is_empty()
+        && host.len() <= 253
+        && !host.starts_with('-')
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | ':'));
+    if !valid {
+        return Err(SshLiaisonError::Config(format!(
+            "'{}' is not a host name or IP address",
+            host
+        ))
+        .into());
+    }
+    if port == 0 {
+        return Err(SshLiaisonError::Config("port must be between 1 and 65535".to_string()).into());
+    }
+    Ok(())
+}
+
+/// Connects to `host:port` from the remote host with `method` (`Nc` or
+/// `Bash`), under `timeout`, between two nanosecond timestamps.
+pub fn probe_command(
+    method: ProbeMethod,
+    host: &str,
+    port: u16,
+    timeout_secs: u32,
+) -> Result<String> {
+    check_target(host, port)?;
+    if timeout_secs == 0 || timeout_secs > MAX_PROBE_TIMEOUT_SECS {
+        return Err(SshLiaisonError::Config(format!(
+            "timeout_secs must be between 1 and {}",
+            MAX_PROBE_TIMEOUT_SECS
+        ))
+        .into());
+    }
+    let connect = match method {
+        ProbeMethod::Bash => format!(
+            "timeout {} bash -c 'exec 3<>/dev/tcp/{}/{}'",
+            timeout_secs, host, port
+        ),
+        ProbeMethod::Nc | ProbeMethod::Auto => format!(
+            "timeout {} nc -z -v -w {} {} {}",
+            timeout_secs, timeout_secs, host, port
+        ),
+    };
+    Ok(format!(
+        "date +%s%N; LC_ALL=C {} 2>&1; echo \"{} $?\"; date +%s%N",
+        connect, PROBE_EXIT
+    ))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProbeStatus {
+    Open,
+    /// Actively refused: the host is up but nothing listens.
+    Closed,
+    /// No answer within the timeout, typically a firewall dropping packets.
+    Timeout,
+    /// No route to the host or network.
+    Unreachable,
+    /// Name resolution or anything else that isn't about the port.
+    Error,
+}
+
+impl std::fmt::Display for ProbeStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProbeStatus::Open => write!(f, "open"),
+            ProbeStatus::Closed => write!(f, "closed"),
+            ProbeStatus::Timeout => write!(f, "timeout"),
+            ProbeStatus::Unreachable => write!(f, "unreachable"),
+            ProbeStatus::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProbeResult {
+    pub status: ProbeStatus,
+    /// Time to connect (or fail), measured on the remote host.
+    pub latency_ms: Option<f64>,
+    /// What the probe printed, e.g. the connect error.
+    pub detail: String,
+}
+
+/// Parses [`probe_command`] output; `None` when `nc` is missing or doesn't
+/// know `-z`, so the caller can fall back to bash.
+pub fn parse_probe(output: &str) -> Option<ProbeResult> {
+    let mut stamps = Vec::new();
+    let mut exit_code = None;
+    let mut detail = Vec::new();
+    for line in output.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        if let Some(code) = line.strip_prefix(PROBE_EXIT) {
+            exit_code = code.trim().parse::<i32>().ok();
+        } else if line.len() >= 18 && line.bytes().all(|b| b.is_ascii_digit()) {
+            stamps.push(line.parse::<u128>().ok()?);
+        } else {
+            detail.push(line);
+        }
+    }
+    let exit_code = exit_code?;
+    let detail = detail.join("\n");
+    let lower = detail.to_lowercase();
+    if exit_code == 127
+        || [
+            "invalid option",
+            "illegal option",
+            "unrecognized option",
+            "usage:",
+        ]
+        .iter()
+        .any(|m| lower.contains(m))
+    {
+        return None;
+    }
+
+    let status = if exit_code == 0 {
+        ProbeStatus::Open
+    } else if exit_code == 124 || lower.contains("timed out") {
+        ProbeStatus::Timeout
+    } else if lower.contains("refused") {
+        ProbeStatus::Closed
+    } else if lower.contains("no route") || lower.contains("unreachable") {
+        ProbeStatus::Unreachable
+    } else {
+        ProbeStatus::Error
+    };
+    let latency_ms = match stamps[..] {
+        [start, end] if end >= start => Some((end - start) as f64 / 1_000_000.0),
+        _ => None,
+    };
+    Some(ProbeResult {
+        status,
+        latency_ms,
+        detail,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_network_command_is_read_only() {
+        assert!(crate::policy::is_read_only_command(&network_command()));
+    }
+
+    #[test]
+    fn test_probe_command() {
+        let command = probe_command(ProbeMethod::Auto, "db.internal", 5432, 5).unwrap();
+        assert_eq!(
+            command,
+            "date +%s%N; LC_ALL=C timeout 5 nc -z -v -w 5 db.internal 5432 2>&1; echo \"@@probe exit $?\"; date +%s%N"
+        );
+        assert!(crate::policy::is_read_only_command(&command));
+        assert!(probe_command(ProbeMethod::Bash, "10.0.0.5", 22, 5).is_ok());
+        assert!(probe_command(ProbeMethod::Nc, "db;reboot", 5432, 5).is_err());
+        assert!(probe_command(ProbeMethod::Nc, "-e", 5432, 5).is_err());
+        assert!(probe_command(ProbeMethod::Nc, "db", 0, 5).is_err());
+    }
+
+    #[test]
+    fn test_parse_probe() {
+        let open = "1714557600000000000\nConnection to db 5432 port [tcp/postgresql] succeeded!\n@@probe exit 0\n1714557600002500000\n";
+        let result = parse_probe(open).unwrap();
+        assert_eq!(result.status, ProbeStatus::Open);
+        assert_eq!(result.latency_ms, Some(2.5));
+
+        let refused = "1714557600000000000\nbash: connect: Connection refused\nbash: line 1: /dev/tcp/db/5432: Connection refused\n@@probe exit 1\n1714557600001000000\n";
+        assert_eq!(parse_probe(refused).unwrap().status, ProbeStatus::Closed);
+
+        let filtered = "1714557600000000000\n@@probe exit 124\n1714557605000000000\n";
+        let result = parse_probe(filtered).unwrap();
+        assert_eq!(result.status, ProbeStatus::Timeout);
+        assert_eq!(result.latency_ms, Some(5000.0));
+
+        let unresolved = "1714557600000000000\nnc: getaddrinfo for host \"nope\" port 1: Name or service not known\n@@probe exit 1\n1714557600000000000\n";
+        assert_eq!(parse_probe(unresolved).unwrap().status, ProbeStatus::Error);
+
+        let busybox = "1714557600N\nbash: nc: command not found\n@@probe exit 127\n1714557600N\n";
+        assert_eq!(parse_probe(busybox), None);
+    }
+
+    #[test]
+    fn test_parse_iproute2() {
+        let output = r#"@@net addr
+[{"ifindex":1,"ifname":"lo","flags":["LOOPBACK","UP"],"mtu":65536,"operstate":"UNKNOWN","address":"00:00:00:00:00:00","addr_info":[{"family":"inet","local":"127.0.0.1","prefixlen":8}]},{"ifindex":2,"ifname":"eth0","mtu":1500,"operstate":"UP","address":"52:54:00:12:34:56","addr_info":[{"family":"inet","local":"10.0.0.5","prefixlen":24},{"family":"inet6","local":"fe80::1","prefixlen":64}]}]
+@@net route
+[{"dst":"default","gateway":"10.0.0.1","dev":"eth0","protocol":"dhcp","metric":100,"flags":[]},{"dst":"10.0.0.0/24","dev":"eth0","flags":[]}]
+@@net listen
+Netid State  Recv-Q Send-Q Local Address:Port  Peer Address:Port Process
+udp   UNCONN 0      0      127.0.0.53%lo:53         0.0.0.0:*     users:(("systemd-resolve",pid=652,fd=13))
+tcp   LISTEN 0      128          0.0.0.0:22         0.0.0.0:*     users:(("sshd",pid=812,fd=3))
+tcp   LISTEN 0      511             [::]:80            [::]:*
+"#;
+        let info = parse_network(output);
+        assert_eq!(info.via, ["ip", "ss"]);
+        assert_eq!(info.interfaces[0].state, "unknown");
+        assert_eq!(info.interfaces[0].mac, None);
+        assert_eq!(info.interfaces[1].addresses[1].family, "inet6");
+        assert_eq!(info.routes[0].gateway.as_deref(), Some("10.0.0.1"));
+        assert_eq!(info.routes[0].metric, Some(100));
+        assert_eq!(info.listening.len(), 3);
+        assert_eq!(info.listening[0].address, "127.0.0.53%lo");
+        assert_eq!(info.listening[1].process.as_deref(), Some("sshd"));
+        assert_eq!(info.listening[1].pid, Some(812));
+        assert_eq!(info.listening[2].address, "::");
+        assert_eq!(info.listening[2].port, Some(80));
+        assert_eq!(info.listening[2].process, None);
+    }
+
+    #[test]
+    fn test_parse_net_tools() {
+        let output = "@@net addr
+eth0      Link encap:Ethernet  HWaddr 52:54:00:12:34:56
+          inet addr:10.0.0.5  Bcast:10.0.0.255  Mask:255.255.255.0
+          inet6 addr: fe80::1/64 Scope:Link
+          UP BROADCAST RUNNING MULTICAST  MTU:1500  Metric:1
+
+wlan0: flags=4098<BROADCAST,MULTICAST>  mtu 1500
+        inet 192.168.1.7  netmask 255.255.0.0  broadcast 192.168.255.255
+        ether 3c:22:fb:00:00:01  txqueuelen 1000  (Ethernet)
+@@net route
+Kernel IP routing table
+Destination     Gateway         Genmask         Flags   MSS Window  irtt Iface
+0.0.0.0         10.0.0.1        0.0.0.0         UG        0 0          0 eth0
+10.0.0.0        0.0.0.0         255.255.255.0   U         0 0          0 eth0
+@@net listen
+Active Internet connections (only servers)
+Proto Recv-Q Send-Q Local Address           Foreign Address         State       PID/Program name
+tcp        0      0 0.0.0.0:22              0.0.0.0:*               LISTEN      812/sshd
+tcp6       0      0 :::80                   :::*                    LISTEN      -
+udp        0      0 0.0.0.0:68              0.0.0.0:*                           650/dhclient
+";
+        let info = parse_network(output);
+        assert_eq!(info.via, ["ifconfig", "netstat"]);
+        let eth0 = &info.interfaces[0];
+        assert_eq!(eth0.state, "up");
+        assert_eq!(eth0.mtu, Some(1500));
+        assert_eq!(eth0.mac.as_deref(), Some("52:54:00:12:34:56"));
+        assert_eq!(eth0.addresses[0].address, "10.0.0.5");
+        assert_eq!(eth0.addresses[0].prefix_len, Some(24));
+        assert_eq!(eth0.addresses[1].prefix_len, Some(64));
+        let wlan0 = &info.interfaces[1];
+        assert_eq!(wlan0.state, "down");
+        assert_eq!(wlan0.addresses[0].prefix_len, Some(16));
+        assert_eq!(info.routes[0].destination, "default");
+        assert_eq!(info.routes[1].destination, "10.0.0.0/24");
+        assert_eq!(info.routes[1].gateway, None);
+        assert_eq!(info.listening[0].pid, Some(812));
+        assert_eq!(info.listening[1].protocol, "tcp");
+        assert_eq!(info.listening[1].address, "::");
+        assert_eq!(info.listening[1].process, None);
+        assert_eq!(info.listening[2].process.as_deref(), Some("dhclient"));
+    }
+}
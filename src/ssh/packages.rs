@@ -0,0 +1,323 @@
+//! Installed package versions and pending upgrades from whichever package
+//! manager the host has: dpkg, rpm, apk or pacman. Everything here only
+//! reads the package database and never refreshes repository indexes.
+
+use anyhow::Result;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::shell::quote;
+use crate::error::SshLiaisonError;
+
+/// Prints the path of every tool present; `dnf` and `yum` are only used to
+/// list upgrades on rpm systems.
+pub const DETECT_COMMAND: &str = "command -v dpkg-query rpm apk pacman dnf yum";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum PackageManager {
+    Dpkg,
+    Rpm,
+    Apk,
+    Pacman,
+}
+
+impl std::fmt::Display for PackageManager {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PackageManager::Dpkg => write!(f, "dpkg"),
+            PackageManager::Rpm => write!(f, "rpm"),
+            PackageManager::Apk => write!(f, "apk"),
+            PackageManager::Pacman => write!(f, "pacman"),
+        }
+    }
+}
+
+/// The package manager and, for rpm, the tool that knows about upgrades.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Detected {
+    pub manager: PackageManager,
+    pub rpm_frontend: Option<&'static str>,
+}
+
+/// Parses [`DETECT_COMMAND`] output. dpkg wins over rpm, since Debian hosts
+/// sometimes have `rpm` installed for building packages.
+pub fn parse_detect(output: &str) -> Option<Detected> {
+    let found: Vec<&str> = output
+        .lines()
+        .filter_map(|line| line.trim().rsplit('/').next())
+        .collect();
+    let manager = [
+        ("dpkg-query", PackageManager::Dpkg),
+        ("rpm", PackageManager::Rpm),
+        ("apk", PackageManager::Apk),
+        ("pacman", PackageManager::Pacman),
+    ]
+    .into_iter()
+    .find(|(tool, _)| found.contains(tool))
+    .map(|(_, manager)| manager)?;
+    let rpm_frontend = ["dnf", "yum"].into_iter().find(|tool| found.contains(tool));
+    Some(Detected {
+        manager,
+        rpm_frontend,
+    })
+}
+
+fn check_package(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name.len() <= 128
+        && !name.starts_with('-')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '+' | ':' | '@'));
+    if !valid {
+        return Err(SshLiaisonError::Config(format!("'{}' is not a package name", name)).into());
+    }
+    Ok(())
+}
+
+/// Lists the installed ones of `packages`; missing packages are left out.
+pub fn query_command(manager: PackageManager, packages: &[String]) -> Result<String> {
+    for package in packages {
+        check_package(package)?;
+    }
+    let names: Vec<String> = packages.iter().map(|p| quote(p)).collect();
+    let names = names.join(" ");
+    Ok(match manager {
+        PackageManager::Dpkg => format!(
+            "dpkg-query -W -f='${{Package}}\\t${{Version}}\\t${{db:Status-Abbrev}}\\n' {} 2>/dev/null",
+            names
+        ),
+        PackageManager::Rpm => format!(
+            "rpm -q --qf '%{{NAME}}\\t%{{VERSION}}-%{{RELEASE}}.%{{ARCH}}\\n' {} 2>/dev/null",
+            names
+        ),
+        PackageManager::Apk => format!("apk list --installed {} 2>/dev/null", names),
+        PackageManager::Pacman => format!("pacman -Q {} 2>/dev/null", names),
+    })
+}
+
+/// Upgrades known to the local package database, as of its last refresh.
+pub fn upgrades_command(detected: Detected) -> Option<String> {
+    Some(match detected.manager {
+        PackageManager::Dpkg => "apt list --upgradable 2>/dev/null".to_string(),
+        // `-C` keeps dnf/yum from refreshing metadata over the network.
+        PackageManager::Rpm => format!("{} check-update -q -C 2>/dev/null", detected.rpm_frontend?),
+        PackageManager::Apk => "apk list --upgradable 2>/dev/null".to_string(),
+        PackageManager::Pacman => "pacman -Qu 2>/dev/null".to_string(),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PackageStatus {
+    pub name: String,
+    pub installed: bool,
+    pub version: Option<String>,
+    /// Newer version the package database knows of, when upgrades were listed.
+    pub upgrade: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PackageUpgrade {
+    pub name: String,
+    /// Not every manager prints it.
+    pub current: Option<String>,
+    pub available: String,
+}
+
+/// `name-1.2.3-r0` as printed by apk, split into name and version.
+fn split_apk(package: &str) -> Option<(&str, &str)> {
+    let (rest, release) = package.rsplit_once('-')?;
+    if !release.starts_with('r') {
+        return None;
+    }
+    let (name, version) = rest.rsplit_once('-')?;
+    if !version.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    Some((name, &package[name.len() + 1..]))
+}
+
+/// Parses [`query_command`] output into one entry per queried package.
+pub fn parse_query(
+    manager: PackageManager,
+    output: &str,
+    packages: &[String],
+) -> Vec<PackageStatus> {
+    let mut installed: Vec<(String, String)> = Vec::new();
+    for line in output.lines() {
+        let fields: Vec<&str> = match manager {
+            PackageManager::Dpkg | PackageManager::Rpm => line.split('\t').collect(),
+            PackageManager::Apk | PackageManager::Pacman => line.split_whitespace().collect(),
+        };
+        let entry = match manager {
+            // `rc` (removed, config files left) and the like aren't installed.
+            PackageManager::Dpkg if fields.len() >= 3 && fields[2].starts_with("ii") => {
+                Some((fields[0], fields[1]))
+            }
+            PackageManager::Rpm if fields.len() == 2 => Some((fields[0], fields[1])),
+            PackageManager::Apk if line.contains("[installed]") => {
+                fields.first().and_then(|p| split_apk(p))
+            }
+            PackageManager::Pacman if fields.len() == 2 => Some((fields[0], fields[1])),
+            _ => None,
+        };
+        if let Some((name, version)) = entry {
+            installed.push((name.to_string(), version.to_string()));
+        }
+    }
+
+    packages
+        .iter()
+        .map(|package| {
+            // dpkg prints `libc6` for a query of `libc6:amd64`.
+            let base = package.split(':').next().unwrap_or(package);
+            let version = installed
+                .iter()
+                .find(|(name, _)| name == package || name == base)
+                .map(|(_, version)| version.clone());
+            PackageStatus {
+                name: package.clone(),
+                installed: version.is_some(),
+                version,
+                upgrade: None,
+            }
+        })
+        .collect()
+}
+
+/// Parses [`upgrades_command`] output.
+pub fn parse_upgrades(manager: PackageManager, output: &str) -> Vec<PackageUpgrade> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match manager {
+                // `curl/jammy-updates 7.81.0-1ubuntu1.16 amd64 [upgradable from: 7.81.0-1ubuntu1.15]`
+                PackageManager::Dpkg => {
+                    let (name, _) = fields.first()?.split_once('/')?;
+                    Some(PackageUpgrade {
+                        name: name.to_string(),
+                        current: line
+                            .split_once("upgradable from: ")
+                            .map(|(_, from)| from.trim_end_matches(']').to_string()),
+                        available: fields.get(1)?.to_string(),
+                    })
+                }
+                // `curl.x86_64   7.76.1-29.el9   baseos`
+                PackageManager::Rpm if fields.len() == 3 => {
+                    let (name, _) = fields[0].rsplit_once('.')?;
+                    Some(PackageUpgrade {
+                        name: name.to_string(),
+                        current: None,
+                        available: fields[1].to_string(),
+                    })
+                }
+                // `curl-8.5.0-r1 x86_64 {curl} (curl) [upgradable from: curl-8.5.0-r0]`
+                PackageManager::Apk => {
+                    let (name, available) = split_apk(fields.first()?)?;
+                    let current = line
+                        .split_once("upgradable from: ")
+                        .and_then(|(_, from)| split_apk(from.trim_end_matches(']')))
+                        .map(|(_, version)| version.to_string());
+                    Some(PackageUpgrade {
+                        name: name.to_string(),
+                        current,
+                        available: available.to_string(),
+                    })
+                }
+                // `curl 8.5.0-1 -> 8.6.0-1`
+                PackageManager::Pacman if fields.len() == 4 && fields[2] == "->" => {
+                    Some(PackageUpgrade {
+                        name: fields[0].to_string(),
+                        current: Some(fields[1].to_string()),
+                        available: fields[3].to_string(),
+                    })
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_and_commands() {
+        let detected = parse_detect("/usr/bin/rpm\n/usr/bin/dnf\n").unwrap();
+        assert_eq!(detected.manager, PackageManager::Rpm);
+        assert_eq!(detected.rpm_frontend, Some("dnf"));
+        assert_eq!(
+            parse_detect("/usr/bin/dpkg-query\n/usr/bin/rpm\n")
+                .unwrap()
+                .manager,
+            PackageManager::Dpkg
+        );
+        assert_eq!(parse_detect(""), None);
+
+        let packages = vec!["nginx".to_string(), "libc6:amd64".to_string()];
+        for manager in [
+            PackageManager::Dpkg,
+            PackageManager::Rpm,
+            PackageManager::Apk,
+            PackageManager::Pacman,
+        ] {
+            let command = query_command(manager, &packages).unwrap();
+            assert!(crate::policy::is_read_only_command(&command), "{}", command);
+            let upgrades = upgrades_command(Detected {
+                manager,
+                rpm_frontend: Some("yum"),
+            })
+            .unwrap();
+            assert!(
+                crate::policy::is_read_only_command(&upgrades),
+                "{}",
+                upgrades
+            );
+        }
+        assert!(crate::policy::is_read_only_command(DETECT_COMMAND));
+        assert!(query_command(PackageManager::Apk, &["x; reboot".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_parse_packages() {
+        let packages = vec![
+            "nginx".to_string(),
+            "libc6:amd64".to_string(),
+            "apache2".to_string(),
+        ];
+        let dpkg = "nginx\t1.18.0-6ubuntu14.4\tii \nlibc6\t2.35-0ubuntu3.6\tii \napache2\t2.4.52-1ubuntu4\trc \n";
+        let status = parse_query(PackageManager::Dpkg, dpkg, &packages);
+        assert_eq!(status[0].version.as_deref(), Some("1.18.0-6ubuntu14.4"));
+        assert!(status[1].installed);
+        assert!(!status[2].installed);
+
+        let apk = "nginx-1.24.0-r15 x86_64 {nginx} (BSD-2-Clause) [installed]\n";
+        let status = parse_query(PackageManager::Apk, apk, &packages[..1]);
+        assert_eq!(status[0].version.as_deref(), Some("1.24.0-r15"));
+
+        let rpm = "package nginx is not installed\n";
+        assert!(!parse_query(PackageManager::Rpm, rpm, &packages[..1])[0].installed);
+
+        let apt = "Listing...\ncurl/jammy-updates 7.81.0-1ubuntu1.16 amd64 [upgradable from: 7.81.0-1ubuntu1.15]\n";
+        let upgrades = parse_upgrades(PackageManager::Dpkg, apt);
+        assert_eq!(upgrades.len(), 1);
+        assert_eq!(upgrades[0].current.as_deref(), Some("7.81.0-1ubuntu1.15"));
+
+        let dnf = "\ncurl.x86_64   7.76.1-29.el9   baseos\n";
+        assert_eq!(parse_upgrades(PackageManager::Rpm, dnf)[0].name, "curl");
+
+        let apk = "libcrypto3-3.1.4-r5 x86_64 {openssl} (Apache-2.0) [upgradable from: libcrypto3-3.1.4-r4]\n";
+        let upgrades = parse_upgrades(PackageManager::Apk, apk);
+        assert_eq!(upgrades[0].name, "libcrypto3");
+        assert_eq!(upgrades[0].current.as_deref(), Some("3.1.4-r4"));
+
+        let pacman = "linux 6.7.4.arch1-1 -> 6.7.5.arch1-1\n";
+        assert_eq!(
+            parse_upgrades(PackageManager::Pacman, pacman)[0].available,
+            "6.7.5.arch1-1"
+        );
+    }
+}
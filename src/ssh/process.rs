@@ -0,0 +1,149 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Every process, one per line without headers, in the C locale so `%cpu`
+/// uses a decimal point. Only POSIX `-o` fields, so it also runs on BSD and
+/// macOS `ps`; sorting happens here rather than with GNU `--sort`.
+pub const PS_COMMAND: &str =
+    "LC_ALL=C ps -A -o pid= -o ppid= -o user= -o pcpu= -o pmem= -o rss= -o etime= -o args=";
+
+pub const DEFAULT_PROCESS_LIMIT: usize = 15;
+pub const MAX_PROCESS_LIMIT: usize = 200;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ProcessSort {
+    #[default]
+    Cpu,
+    Memory,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub ppid: u32,
+    pub user: String,
+    /// Percent of one CPU, averaged over the process lifetime on Linux.
+    pub cpu: f32,
+    pub mem: f32,
+    pub rss_kb: u64,
+    pub elapsed_secs: u64,
+    /// Executable name from the command line, `[kthreadd]` style for kernel threads.
+    pub name: String,
+    pub command: String,
+}
+
+/// Parses [`PS_COMMAND`] output; lines that don't fit are skipped.
+pub fn parse_ps(output: &str) -> Vec<ProcessInfo> {
+    output.lines().filter_map(parse_line).collect()
+}
+
+fn parse_line(line: &str) -> Option<ProcessInfo> {
+    let mut rest = line.trim_start();
+    let mut fields = [""; 7];
+    for field in &mut fields {
+        let end = rest.find(char::is_whitespace)?;
+        *field = &rest[..end];
+        rest = rest[end..].trim_start();
+    }
+    let command = rest.trim_end().to_string();
+    let [pid, ppid, user, cpu, mem, rss, etime] = fields;
+    Some(ProcessInfo {
+        pid: pid.parse().ok()?,
+        ppid: ppid.parse().ok()?,
+        user: user.to_string(),
+        cpu: cpu.parse().ok()?,
+        mem: mem.parse().ok()?,
+        rss_kb: rss.parse().ok()?,
+        elapsed_secs: parse_etime(etime)?,
+        name: process_name(&command),
+        command,
+    })
+}
+
+/// `[[dd-]hh:]mm:ss` as seconds.
+fn parse_etime(etime: &str) -> Option<u64> {
+    let (days, clock) = match etime.split_once('-') {
+        Some((days, clock)) => (days.parse::<u64>().ok()?, clock),
+        None => (0, etime),
+    };
+    let mut secs = 0;
+    for part in clock.split(':') {
+        secs = secs * 60 + part.parse::<u64>().ok()?;
+    }
+    Some(days * 86_400 + secs)
+}
+
+fn process_name(command: &str) -> String {
+    if command.starts_with('[') {
+        return command.to_string();
+    }
+    let program = command.split_whitespace().next().unwrap_or_default();
+    program.rsplit('/').next().unwrap_or(program).to_string()
+}
+
+/// The `limit` heaviest processes by `sort`, optionally only those whose
+/// command line contains `filter` (ignoring case), and how many matched.
+pub fn top(
+    mut processes: Vec<ProcessInfo>,
+    sort: ProcessSort,
+    filter: Option<&str>,
+    limit: usize,
+) -> (Vec<ProcessInfo>, usize) {
+    if let Some(filter) = filter.map(str::to_lowercase) {
+        processes.retain(|p| p.command.to_lowercase().contains(&filter));
+    }
+    match sort {
+        ProcessSort::Cpu => processes.sort_by(|a, b| b.cpu.total_cmp(&a.cpu)),
+        ProcessSort::Memory => processes.sort_by_key(|p| std::cmp::Reverse(p.rss_kb)),
+    }
+    let matched = processes.len();
+    processes.truncate(limit);
+    (processes, matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PS_OUTPUT: &str = "    1     0 root      0.0  0.1 12345 10-02:03:04 /sbin/init splash
+    2     0 root      0.0  0.0     0    10:00 [kthreadd]
+  812     1 www-data 42.5  3.2 262144    01:05 nginx: worker process
+  913     1 postgres  7.0 12.4 1048576 1-00:00:00 /usr/lib/postgresql/16/bin/postgres -D /var/lib/postgresql
+garbage line
+";
+
+    #[test]
+    fn test_parse_ps() {
+        let processes = parse_ps(PS_OUTPUT);
+        assert_eq!(processes.len(), 4);
+        assert_eq!(
+            processes[0].elapsed_secs,
+            10 * 86_400 + 2 * 3600 + 3 * 60 + 4
+        );
+        assert_eq!(processes[0].name, "init");
+        assert_eq!(processes[0].command, "/sbin/init splash");
+        assert_eq!(processes[1].name, "[kthreadd]");
+        assert_eq!(processes[2].name, "nginx:");
+        assert_eq!(processes[3].elapsed_secs, 86_400);
+        assert_eq!(processes[3].name, "postgres");
+    }
+
+    #[test]
+    fn test_top_processes() {
+        let processes = parse_ps(PS_OUTPUT);
+        let (top_cpu, matched) = top(processes.clone(), ProcessSort::Cpu, None, 2);
+        assert_eq!(matched, 4);
+        assert_eq!(
+            top_cpu.iter().map(|p| p.pid).collect::<Vec<_>>(),
+            [812, 913]
+        );
+
+        let (top_mem, _) = top(processes.clone(), ProcessSort::Memory, None, 1);
+        assert_eq!(top_mem[0].pid, 913);
+
+        let (filtered, matched) = top(processes, ProcessSort::Cpu, Some("NGINX"), 10);
+        assert_eq!(matched, 1);
+        assert_eq!(filtered[0].pid, 812);
+    }
+}
@@ -0,0 +1,161 @@
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Size of the PTY requested for shell channels (the libssh2 default).
+pub const PTY_COLS: u32 = 80;
+pub const PTY_ROWS: u32 = 24;
+
+/// Where sessions are recorded, and whether they are by default.
+#[derive(Debug, Clone)]
+pub struct RecordingOptions {
+    pub dir: PathBuf,
+    /// Record every session unless a connect turns it off.
+    pub all_sessions: bool,
+}
+
+impl Default for RecordingOptions {
+    fn default() -> Self {
+        Self {
+            dir: crate::config::config_dir()
+                .map(|dir| dir.join("recordings"))
+                .unwrap_or_else(|| PathBuf::from("recordings")),
+            all_sessions: false,
+        }
+    }
+}
+
+/// Writes one shell channel's traffic as an asciicast v2 file, replayable
+/// with `asciinema play`. Each event is written through immediately, so a
+/// crash leaves a usable file.
+pub struct Recorder {
+    file: File,
+    path: PathBuf,
+    start: Instant,
+    /// Bytes of an output UTF-8 sequence split across reads.
+    pending: Vec<u8>,
+}
+
+impl Recorder {
+    /// Creates `<dir>/<host>[-<channel>]-<unix time in ms>.cast` and writes the header.
+    pub fn create(dir: &Path, host_alias: &str, channel: Option<&str>) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create recording directory {}", dir.display()))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let name = match channel {
+            Some(channel) => format!("{}-{}", host_alias, channel),
+            None => host_alias.to_string(),
+        };
+        let path = dir.join(format!("{}-{}.cast", file_safe(&name), now.as_millis()));
+        let mut options = OpenOptions::new();
+        options.create_new(true).append(true);
+        // Recordings hold raw output, secrets included.
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut file = options
+            .open(&path)
+            .with_context(|| format!("Failed to create recording {}", path.display()))?;
+
+        let header = serde_json::json!({
+            "version": 2,
+            "width": PTY_COLS,
+            "height": PTY_ROWS,
+            "timestamp": now.as_secs(),
+            "title": name,
+            "env": { "TERM": "xterm" },
+        });
+        writeln!(file, "{}", header)
+            .with_context(|| format!("Failed to write recording {}", path.display()))?;
+        tracing::info!(host = %host_alias, path = %path.display(), "Recording session");
+
+        Ok(Self {
+            file,
+            path,
+            start: Instant::now(),
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Data sent to the shell.
+    pub fn input(&mut self, data: &[u8]) {
+        self.event("i", &String::from_utf8_lossy(data));
+    }
+
+    /// Data the shell printed.
+    pub fn output(&mut self, data: &[u8]) {
+        self.pending.extend_from_slice(data);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            // An incomplete sequence at the end waits for the next read.
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => self.pending.len(),
+        };
+        let chunk: Vec<u8> = self.pending.drain(..valid).collect();
+        if !chunk.is_empty() {
+            self.event("o", &String::from_utf8_lossy(&chunk));
+        }
+    }
+
+    fn event(&mut self, kind: &str, data: &str) {
+        let event = serde_json::json!([self.start.elapsed().as_secs_f64(), kind, data]);
+        if let Err(e) = writeln!(self.file, "{}", event) {
+            tracing::warn!(path = %self.path.display(), error = %e, "Failed to write recording");
+        }
+    }
+}
+
+/// `name` with anything but alphanumerics, `-`, `_` and `.` replaced.
+pub(crate) fn file_safe(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_asciicast() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut recorder = Recorder::create(dir.path(), "web/1", Some("build")).unwrap();
+        recorder.input(b"ls\n");
+        recorder.output("caf\u{e9}".as_bytes().split_at(4).0);
+        recorder.output(&"caf\u{e9}".as_bytes()[4..]);
+
+        let path = recorder.path().to_path_buf();
+        assert!(
+            path.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("web_1-build-")
+        );
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines[0]["version"], 2);
+        assert_eq!(lines[1][1], "i");
+        assert_eq!(lines[1][2], "ls\n");
+        assert_eq!(lines[2][2], "caf");
+        assert_eq!(lines[3][2], "\u{e9}");
+    }
+}
@@ -0,0 +1,219 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+use super::channel::CommandOutput;
+use crate::redact;
+
+/// How long a cancel waits for the interrupted command to return its output.
+pub const CANCEL_WAIT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunningInfo {
+    pub id: u64,
+    /// Client namespace of the session the command runs in.
+    #[serde(skip)]
+    pub owner: Option<String>,
+    pub host: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    /// The command with secrets masked, as in the logs.
+    pub command: String,
+    /// Unix seconds.
+    pub started_at: u64,
+    pub elapsed_secs: u64,
+}
+
+impl std::fmt::Display for RunningInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {} ({}s", self.id, self.command, self.elapsed_secs)?;
+        if let Some(ref channel) = self.channel {
+            write!(f, ", channel '{}'", channel)?;
+        }
+        write!(f, ")")
+    }
+}
+
+struct Running {
+    info: RunningInfo,
+    started: Instant,
+    cancel: CancellationToken,
+    output: watch::Receiver<Option<CommandOutput>>,
+}
+
+impl Running {
+    fn info(&self) -> RunningInfo {
+        RunningInfo {
+            elapsed_secs: self.started.elapsed().as_secs(),
+            ..self.info.clone()
+        }
+    }
+}
+
+/// Commands running in session shells, by id, so they can be interrupted.
+#[derive(Clone, Default)]
+pub struct RunningCommands {
+    commands: Arc<std::sync::Mutex<HashMap<u64, Running>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl RunningCommands {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn commands(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Running>> {
+        self.commands.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Registers a command; it's listed until the returned guard is dropped.
+    pub fn start(
+        &self,
+        owner: Option<&str>,
+        host: &str,
+        channel: Option<&str>,
+        command: &str,
+    ) -> RunningGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let started_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let cancel = CancellationToken::new();
+        let (output, receiver) = watch::channel(None);
+        self.commands().insert(
+            id,
+            Running {
+                info: RunningInfo {
+                    id,
+                    owner: owner.map(str::to_string),
+                    host: host.to_string(),
+                    channel: channel.map(str::to_string),
+                    command: redact::for_logs(command).into_owned(),
+                    started_at,
+                    elapsed_secs: 0,
+                },
+                started: Instant::now(),
+                cancel: cancel.clone(),
+                output: receiver,
+            },
+        );
+        RunningGuard {
+            id,
+            cancel,
+            output,
+            commands: self.clone(),
+        }
+    }
+
+    /// Commands owned by `owner`, oldest first.
+    pub fn list(&self, owner: Option<&str>) -> Vec<RunningInfo> {
+        let mut list: Vec<RunningInfo> = self
+            .commands()
+            .values()
+            .filter(|r| r.info.owner.as_deref() == owner)
+            .map(Running::info)
+            .collect();
+        list.sort_by_key(|r| r.id);
+        list
+    }
+
+    /// Interrupts a command and waits for what it printed before that.
+    pub async fn cancel(
+        &self,
+        owner: Option<&str>,
+        id: u64,
+    ) -> Result<(RunningInfo, CommandOutput)> {
+        let (info, mut output) = {
+            let commands = self.commands();
+            match commands.get(&id) {
+                Some(running) if running.info.owner.as_deref() == owner => {
+                    running.cancel.cancel();
+                    (running.info(), running.output.clone())
+                }
+                _ => anyhow::bail!("No running command with id {}", id),
+            }
+        };
+        tracing::info!(id, host = %info.host, "Cancelling command");
+
+        let finished = tokio::time::timeout(CANCEL_WAIT, output.wait_for(Option::is_some)).await;
+        match finished {
+            Ok(Ok(output)) => Ok((info, output.clone().unwrap_or_default())),
+            Ok(Err(_)) => anyhow::bail!("Command {} ended without output", id),
+            Err(_) => anyhow::bail!(
+                "Command {} did not stop within {}s",
+                id,
+                CANCEL_WAIT.as_secs()
+            ),
+        }
+    }
+}
+
+/// A registered command; dropping it takes the command off the list.
+pub struct RunningGuard {
+    id: u64,
+    cancel: CancellationToken,
+    output: watch::Sender<Option<CommandOutput>>,
+    commands: RunningCommands,
+}
+
+impl RunningGuard {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Fires when the command is cancelled.
+    pub fn token(&self) -> &CancellationToken {
+        &self.cancel
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// Hands the command's output to a waiting cancel.
+    pub fn finish(&self, output: &CommandOutput) {
+        self.output.send_replace(Some(output.clone()));
+    }
+}
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        self.commands.commands().remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_cancel_running_command() {
+        let running = RunningCommands::new();
+        let guard = running.start(Some("a"), "web1", None, "sleep 100");
+        assert_eq!(running.list(Some("a")).len(), 1);
+        assert!(running.list(Some("b")).is_empty());
+        assert!(running.cancel(Some("b"), guard.id()).await.is_err());
+
+        let id = guard.id();
+        let command = tokio::spawn(async move {
+            guard.token().cancelled().await;
+            guard.finish(&CommandOutput {
+                stdout: "partial".to_string(),
+                ..Default::default()
+            });
+        });
+        let (info, output) = running.cancel(Some("a"), id).await.unwrap();
+        assert_eq!(info.command, "sleep 100");
+        assert_eq!(output.stdout, "partial");
+        assert_eq!(output.exit_code, None);
+
+        command.await.unwrap();
+        assert!(running.list(Some("a")).is_empty());
+        assert!(running.cancel(Some("a"), id).await.is_err());
+    }
+}
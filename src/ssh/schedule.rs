@@ -0,0 +1,447 @@
+use anyhow::Result;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+use super::channel::CommandOutput;
+use super::files::unified_diff;
+
+/// Shortest interval a command can be scheduled at.
+pub const MIN_SCHEDULE_INTERVAL_SECS: u64 = 10;
+/// Schedules one client can have at once.
+pub const MAX_SCHEDULES: usize = 16;
+/// Events kept per schedule; older ones are dropped.
+pub const MAX_SCHEDULE_EVENTS: usize = 50;
+/// Matching lines kept per event.
+const MAX_MATCHED_LINES: usize = 50;
+
+/// Runs the scheduled command once.
+pub type ScheduleRun =
+    Arc<dyn Fn() -> Pin<Box<dyn Future<Output = Result<CommandOutput>> + Send>> + Send + Sync>;
+
+/// Called with each event of a subscribed schedule.
+pub type ScheduleNotify = Arc<dyn Fn(&ScheduleInfo) + Send + Sync>;
+
+/// What to run, where, and what counts as an event.
+#[derive(Debug, Clone)]
+pub struct ScheduleSpec {
+    pub host: String,
+    pub command: String,
+    pub interval: Duration,
+    /// A run whose output differs from the previous run's is an event.
+    pub notify_on_change: bool,
+    /// A run whose lines matching this differ from the previous run's is an event.
+    pub pattern: Option<Regex>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduleInfo {
+    pub id: String,
+    /// Client namespace that created the schedule.
+    #[serde(skip)]
+    pub owner: Option<String>,
+    pub host: String,
+    pub command: String,
+    pub interval_secs: u64,
+    pub notify_on_change: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    pub runs: u64,
+    /// Events since the schedule started, dropped ones included.
+    pub events: u64,
+    /// Unix milliseconds of the last run.
+    pub last_run_ms: Option<u64>,
+    pub last_exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    pub active: bool,
+}
+
+impl std::fmt::Display for ScheduleInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: `{}` on '{}' every {}s ({} runs, {} events{})",
+            self.id,
+            self.command,
+            self.host,
+            self.interval_secs,
+            self.runs,
+            self.events,
+            if self.active { "" } else { ", stopped" }
+        )?;
+        if let Some(ref error) = self.last_error {
+            write!(f, ", last run failed: {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// A run whose output changed or had new matching lines.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduleEvent {
+    pub run: u64,
+    pub timestamp_ms: u64,
+    pub exit_code: Option<i32>,
+    /// Unified diff from the previous run's output.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+    /// Lines matching the pattern, when they differ from the previous run's.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub matched: Vec<String>,
+}
+
+/// A schedule with its latest output and events, oldest first.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScheduleReport {
+    #[serde(flatten)]
+    pub schedule: ScheduleInfo,
+    pub last_output: Option<String>,
+    pub events: Vec<ScheduleEvent>,
+}
+
+/// What a schedule has seen, shared with its task.
+#[derive(Default)]
+struct ScheduleState {
+    runs: u64,
+    events_total: u64,
+    last_run_ms: Option<u64>,
+    last_exit_code: Option<i32>,
+    last_error: Option<String>,
+    last_output: Option<String>,
+    last_matched: Vec<String>,
+    events: VecDeque<ScheduleEvent>,
+    active: bool,
+}
+
+impl ScheduleState {
+    /// Takes the result of the next run; returns whether it was an event.
+    /// The first run only sets the baseline for changes.
+    fn observe(
+        &mut self,
+        result: std::result::Result<CommandOutput, String>,
+        notify_on_change: bool,
+        pattern: Option<&Regex>,
+    ) -> bool {
+        self.runs += 1;
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        self.last_run_ms = Some(timestamp_ms);
+        let output = match result {
+            Ok(output) => output,
+            Err(error) => {
+                self.last_exit_code = None;
+                self.last_error = Some(error);
+                return false;
+            }
+        };
+        self.last_error = None;
+        self.last_exit_code = output.exit_code;
+
+        let text = output.combined_with_stderr_label();
+        let diff = match self.last_output {
+            Some(ref previous) if notify_on_change && *previous != text => {
+                let (diff, _) = unified_diff(
+                    &format!("run {}", self.runs - 1),
+                    previous,
+                    &format!("run {}", self.runs),
+                    &text,
+                );
+                Some(diff)
+            }
+            _ => None,
+        };
+        let matched: Vec<String> = pattern
+            .map(|pattern| {
+                text.lines()
+                    .filter(|line| pattern.is_match(line))
+                    .take(MAX_MATCHED_LINES)
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let new_matches = !matched.is_empty() && matched != self.last_matched;
+        self.last_output = Some(text);
+        self.last_matched = matched.clone();
+
+        if diff.is_none() && !new_matches {
+            return false;
+        }
+        if self.events.len() >= MAX_SCHEDULE_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(ScheduleEvent {
+            run: self.runs,
+            timestamp_ms,
+            exit_code: output.exit_code,
+            diff,
+            matched: if new_matches { matched } else { Vec::new() },
+        });
+        self.events_total += 1;
+        true
+    }
+}
+
+struct Schedule {
+    info: ScheduleInfo,
+    state: Arc<std::sync::Mutex<ScheduleState>>,
+    subscribed: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl Schedule {
+    fn snapshot(&self) -> ScheduleInfo {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        ScheduleInfo {
+            runs: state.runs,
+            events: state.events_total,
+            last_run_ms: state.last_run_ms,
+            last_exit_code: state.last_exit_code,
+            last_error: state.last_error.clone(),
+            active: state.active,
+            ..self.info.clone()
+        }
+    }
+
+    fn report(&self) -> ScheduleReport {
+        let schedule = self.snapshot();
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        ScheduleReport {
+            schedule,
+            last_output: state.last_output.clone(),
+            events: state.events.iter().cloned().collect(),
+        }
+    }
+}
+
+/// Commands run on an interval, keeping the runs whose output changed.
+#[derive(Clone, Default)]
+pub struct ScheduleManager {
+    schedules: Arc<Mutex<HashMap<String, Schedule>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl ScheduleManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts calling `run` every `spec.interval`, the first time right away;
+    /// `notify` is called for every event once the schedule is subscribed.
+    pub async fn open(
+        &self,
+        owner: Option<&str>,
+        spec: ScheduleSpec,
+        run: ScheduleRun,
+        notify: ScheduleNotify,
+    ) -> Result<ScheduleInfo> {
+        let mut schedules = self.schedules.lock().await;
+        let owned = schedules
+            .values()
+            .filter(|s| s.info.owner.as_deref() == owner)
+            .count();
+        if owned >= MAX_SCHEDULES {
+            anyhow::bail!(
+                "At most {} schedules can run at once; cancel one with ssh_schedule_cancel",
+                MAX_SCHEDULES
+            );
+        }
+
+        let id = format!(
+            "schedule-{}",
+            self.next_id.fetch_add(1, Ordering::Relaxed) + 1
+        );
+        let info = ScheduleInfo {
+            id: id.clone(),
+            owner: owner.map(str::to_string),
+            host: spec.host.clone(),
+            command: spec.command.clone(),
+            interval_secs: spec.interval.as_secs(),
+            notify_on_change: spec.notify_on_change,
+            pattern: spec.pattern.as_ref().map(|p| p.as_str().to_string()),
+            runs: 0,
+            events: 0,
+            last_run_ms: None,
+            last_exit_code: None,
+            last_error: None,
+            active: true,
+        };
+        let state = Arc::new(std::sync::Mutex::new(ScheduleState {
+            active: true,
+            ..Default::default()
+        }));
+        let subscribed = Arc::new(AtomicBool::new(false));
+
+        let task_state = Arc::clone(&state);
+        let task_subscribed = Arc::clone(&subscribed);
+        let task_info = info.clone();
+        let handle = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(spec.interval);
+            ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                ticker.tick().await;
+                let result = run().await.map_err(|e| format!("{:#}", e));
+                if let Err(ref e) = result {
+                    tracing::debug!(id = %task_info.id, error = %e, "Scheduled run failed");
+                }
+                let event = task_state
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .observe(result, spec.notify_on_change, spec.pattern.as_ref());
+                if event && task_subscribed.load(Ordering::Relaxed) {
+                    notify(&task_info);
+                }
+            }
+        });
+
+        tracing::info!(id = %id, host = %info.host, interval_secs = info.interval_secs, "Scheduled command");
+        schedules.insert(
+            id,
+            Schedule {
+                info: info.clone(),
+                state,
+                subscribed,
+                handle,
+            },
+        );
+        Ok(info)
+    }
+
+    /// Schedules owned by `owner`.
+    pub async fn list(&self, owner: Option<&str>) -> Vec<ScheduleInfo> {
+        let schedules = self.schedules.lock().await;
+        let mut list: Vec<ScheduleInfo> = schedules
+            .values()
+            .filter(|s| s.info.owner.as_deref() == owner)
+            .map(Schedule::snapshot)
+            .collect();
+        list.sort_by(|a, b| a.id.cmp(&b.id));
+        list
+    }
+
+    pub async fn report(&self, owner: Option<&str>, id: &str) -> Result<ScheduleReport> {
+        let schedules = self.schedules.lock().await;
+        match schedules.get(id) {
+            Some(schedule) if schedule.info.owner.as_deref() == owner => Ok(schedule.report()),
+            _ => anyhow::bail!("Schedule '{}' not found", id),
+        }
+    }
+
+    /// Turns notifications for a schedule on or off; `false` if it isn't `owner`'s.
+    pub async fn set_subscribed(&self, owner: Option<&str>, id: &str, subscribed: bool) -> bool {
+        let schedules = self.schedules.lock().await;
+        match schedules.get(id) {
+            Some(schedule) if schedule.info.owner.as_deref() == owner => {
+                schedule.subscribed.store(subscribed, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Stops a schedule, returning what it had kept.
+    pub async fn close(&self, owner: Option<&str>, id: &str) -> Result<ScheduleReport> {
+        let mut schedules = self.schedules.lock().await;
+        let owned = schedules
+            .get(id)
+            .is_some_and(|s| s.info.owner.as_deref() == owner);
+        let Some(schedule) = owned.then(|| schedules.remove(id)).flatten() else {
+            anyhow::bail!("Schedule '{}' not found", id);
+        };
+        schedule.handle.abort();
+        tracing::info!(id = %id, "Schedule cancelled");
+        let mut report = schedule.report();
+        report.schedule.active = false;
+        Ok(report)
+    }
+
+    /// Stops every schedule, whoever owns it.
+    pub async fn close_all(&self) {
+        let mut schedules = self.schedules.lock().await;
+        for (_, schedule) in schedules.drain() {
+            schedule.handle.abort();
+        }
+    }
+
+    /// Stops every schedule of `owner`.
+    pub async fn close_owner(&self, owner: Option<&str>) {
+        let mut schedules = self.schedules.lock().await;
+        schedules.retain(|_, schedule| {
+            let closing = schedule.info.owner.as_deref() == owner;
+            if closing {
+                schedule.handle.abort();
+            }
+            !closing
+        });
+    }
+
+    /// Whether a schedule runs on `host`'s session.
+    pub async fn has_host(&self, owner: Option<&str>, host: &str) -> bool {
+        let schedules = self.schedules.lock().await;
+        schedules
+            .values()
+            .any(|s| s.info.host == host && s.info.owner.as_deref() == owner)
+    }
+
+    /// Stops every schedule that runs on `host`'s session.
+    pub async fn close_for_host(&self, owner: Option<&str>, host: &str) {
+        let mut schedules = self.schedules.lock().await;
+        schedules.retain(|_, schedule| {
+            let closing = schedule.info.host == host && schedule.info.owner.as_deref() == owner;
+            if closing {
+                schedule.handle.abort();
+            }
+            !closing
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(stdout: &str) -> std::result::Result<CommandOutput, String> {
+        Ok(CommandOutput {
+            stdout: stdout.to_string(),
+            exit_code: Some(0),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_schedule_events() {
+        let pattern = Regex::new("ERROR").unwrap();
+        let mut state = ScheduleState::default();
+        assert!(
+            !state.observe(output("errors: 1\n"), true, None),
+            "baseline"
+        );
+        assert!(!state.observe(output("errors: 1\n"), true, None));
+        assert!(state.observe(output("errors: 2\n"), true, None));
+        assert!(!state.observe(Err("timed out".into()), true, None));
+        assert_eq!(state.last_error.as_deref(), Some("timed out"));
+
+        let event = state.events.back().unwrap();
+        assert_eq!(event.run, 3);
+        let diff = event.diff.as_deref().unwrap();
+        assert!(diff.contains("-errors: 1") && diff.contains("+errors: 2"));
+
+        let mut state = ScheduleState::default();
+        assert!(state.observe(output("ok\nERROR a\n"), false, Some(&pattern)));
+        assert!(!state.observe(output("ok 2\nERROR a\n"), false, Some(&pattern)));
+        assert!(state.observe(output("ERROR a\nERROR b\n"), false, Some(&pattern)));
+        assert_eq!(state.events.back().unwrap().matched, ["ERROR a", "ERROR b"]);
+        assert!(state.events.iter().all(|e| e.diff.is_none()));
+        assert_eq!((state.runs, state.events_total), (3, 2));
+    }
+}
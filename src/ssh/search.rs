@@ -0,0 +1,403 @@
+//! Bounded `find` and `grep` over the shell: every search runs under
+//! `timeout` and its output is cut after a fixed number of results.
+
+use anyhow::Result;
+use serde::Serialize;
+
+use super::files::EntryKind;
+use super::shell::quote;
+use crate::error::SshLiaisonError;
+
+pub const DEFAULT_FIND_LIMIT: usize = 200;
+pub const MAX_FIND_LIMIT: usize = 5000;
+pub const DEFAULT_SEARCH_TIMEOUT_SECS: u32 = 10;
+/// Below the default 30s command timeout, so `timeout` fires first.
+pub const MAX_SEARCH_TIMEOUT_SECS: u32 = 25;
+
+#[derive(Debug, Default)]
+pub struct FindQuery<'a> {
+    pub path: &'a str,
+    /// Glob matched against the file name, like `-name`.
+    pub name: Option<&'a str>,
+    pub ignore_case: bool,
+    pub kind: Option<EntryKind>,
+    pub max_depth: Option<u32>,
+    /// Modified less than this many minutes ago.
+    pub newer_than_minutes: Option<u32>,
+    /// Modified more than this many minutes ago.
+    pub older_than_minutes: Option<u32>,
+    pub min_size_kb: Option<u64>,
+    /// Stay on the starting filesystem (`-xdev`), skipping /proc and mounts.
+    pub same_filesystem: bool,
+    pub limit: usize,
+    pub timeout_secs: u32,
+}
+
+impl FindQuery<'_> {
+    /// GNU `find` printing `kind size mtime path` per match, stopped after
+    /// `timeout_secs` and cut one line past `limit` to tell if there is more.
+    pub fn command(&self) -> Result<String> {
+        let invalid = |message: String| Err(SshLiaisonError::Config(message).into());
+        if self.path.is_empty() {
+            return invalid("path must not be empty".to_string());
+        }
+        if self.limit == 0 || self.limit > MAX_FIND_LIMIT {
+            return invalid(format!("limit must be between 1 and {}", MAX_FIND_LIMIT));
+        }
+        if self.timeout_secs == 0 || self.timeout_secs > MAX_SEARCH_TIMEOUT_SECS {
+            return invalid(format!(
+                "timeout_secs must be between 1 and {}",
+                MAX_SEARCH_TIMEOUT_SECS
+            ));
+        }
+
+        let mut command = format!(
+            "LC_ALL=C timeout {} find {}",
+            self.timeout_secs,
+            quote(self.path)
+        );
+        // Global options go before the tests.
+        if let Some(depth) = self.max_depth {
+            command.push_str(&format!(" -maxdepth {}", depth));
+        }
+        if self.same_filesystem {
+            command.push_str(" -xdev");
+        }
+        if let Some(name) = self.name {
+            let test = if self.ignore_case { "-iname" } else { "-name" };
+            command.push_str(&format!(" {} {}", test, quote(name)));
+        }
+        match self.kind {
+            Some(EntryKind::File) => command.push_str(" -type f"),
+            Some(EntryKind::Directory) => command.push_str(" -type d"),
+            Some(EntryKind::Symlink) => command.push_str(" -type l"),
+            Some(EntryKind::Other) => {
+                return invalid("type must be file, directory or symlink".to_string());
+            }
+            None => {}
+        }
+        if let Some(minutes) = self.newer_than_minutes {
+            command.push_str(&format!(" -mmin -{}", minutes));
+        }
+        if let Some(minutes) = self.older_than_minutes {
+            command.push_str(&format!(" -mmin +{}", minutes));
+        }
+        if let Some(kb) = self.min_size_kb {
+            command.push_str(&format!(" -size +{}k", kb));
+        }
+        command.push_str(&format!(
+            " -printf '%y\\t%s\\t%T@\\t%p\\n' 2>/dev/null | head -n {}",
+            self.limit + 1
+        ));
+        Ok(command)
+    }
+}
+
+pub const DEFAULT_GREP_MATCHES: usize = 100;
+pub const MAX_GREP_MATCHES: usize = 1000;
+pub const MAX_CONTEXT_LINES: u32 = 10;
+/// Longer lines (minified JS, one-line JSON) are cut to keep results small.
+const MAX_LINE_CHARS: usize = 500;
+
+#[derive(Debug, Default)]
+pub struct GrepQuery<'a> {
+    pub pattern: &'a str,
+    pub paths: &'a [String],
+    pub recursive: bool,
+    pub ignore_case: bool,
+    /// Match `pattern` literally instead of as an extended regex.
+    pub fixed_strings: bool,
+    pub before: u32,
+    pub after: u32,
+    pub max_matches: usize,
+    pub skip_binary: bool,
+    pub timeout_secs: u32,
+}
+
+impl GrepQuery<'_> {
+    /// GNU `grep` with file names ended by NUL, so `:` and `-` in them can't
+    /// be confused with the line number separators. `-m` only limits matches
+    /// per file, so the output is also cut after enough lines for one match
+    /// more than `max_matches`.
+    pub fn command(&self) -> Result<String> {
+        let invalid = |message: String| Err(SshLiaisonError::Config(message).into());
+        if self.pattern.is_empty() {
+            return invalid("pattern must not be empty".to_string());
+        }
+        if self.paths.is_empty() || self.paths.iter().any(|p| p.is_empty()) {
+            return invalid("paths must name at least one file or directory".to_string());
+        }
+        if self.max_matches == 0 || self.max_matches > MAX_GREP_MATCHES {
+            return invalid(format!(
+                "max_matches must be between 1 and {}",
+                MAX_GREP_MATCHES
+            ));
+        }
+        if self.before > MAX_CONTEXT_LINES || self.after > MAX_CONTEXT_LINES {
+            return invalid(format!(
+                "context must be at most {} lines",
+                MAX_CONTEXT_LINES
+            ));
+        }
+        if self.timeout_secs == 0 || self.timeout_secs > MAX_SEARCH_TIMEOUT_SECS {
+            return invalid(format!(
+                "timeout_secs must be between 1 and {}",
+                MAX_SEARCH_TIMEOUT_SECS
+            ));
+        }
+
+        let mut command = format!(
+            "LC_ALL=C timeout {} grep -HnsZ -m {}",
+            self.timeout_secs,
+            self.max_matches + 1
+        );
+        if self.recursive {
+            command.push_str(" -r");
+        }
+        if self.ignore_case {
+            command.push_str(" -i");
+        }
+        command.push_str(if self.fixed_strings { " -F" } else { " -E" });
+        command.push_str(if self.skip_binary { " -I" } else { " -a" });
+        if self.before > 0 {
+            command.push_str(&format!(" -B {}", self.before));
+        }
+        if self.after > 0 {
+            command.push_str(&format!(" -A {}", self.after));
+        }
+        command.push_str(&format!(" -e {} --", quote(self.pattern)));
+        for path in self.paths {
+            command.push(' ');
+            command.push_str(&quote(path));
+        }
+        // Each match takes its line, its context and a `--` separator.
+        let lines_per_match = (self.before + self.after) as usize + 2;
+        command.push_str(&format!(
+            " | head -n {}",
+            (self.max_matches + 1) * lines_per_match
+        ));
+        Ok(command)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ContextLine {
+    pub line: u64,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GrepMatch {
+    pub file: String,
+    pub line: u64,
+    pub text: String,
+    /// Context lines that aren't matches themselves; neighbouring matches
+    /// are records of their own.
+    pub before: Vec<ContextLine>,
+    pub after: Vec<ContextLine>,
+}
+
+struct GrepLine<'a> {
+    file: &'a str,
+    line: u64,
+    is_match: bool,
+    text: &'a str,
+}
+
+/// Parses [`GrepQuery::command`] output into at most `limit` matches with
+/// up to `before` / `after` lines of context; the flag says whether grep
+/// found more.
+pub fn parse_grep(output: &str, before: u32, after: u32, limit: usize) -> (Vec<GrepMatch>, bool) {
+    let lines: Vec<GrepLine> = output
+        .lines()
+        .filter_map(|line| {
+            let (file, rest) = line.split_once('\0')?;
+            let split = rest.find([':', '-'])?;
+            Some(GrepLine {
+                file,
+                line: rest[..split].parse().ok()?,
+                is_match: rest.as_bytes()[split] == b':',
+                text: &rest[split + 1..],
+            })
+        })
+        .collect();
+    let context = |l: &GrepLine| ContextLine {
+        line: l.line,
+        text: l.text.chars().take(MAX_LINE_CHARS).collect(),
+    };
+
+    let mut matches = Vec::new();
+    for (i, hit) in lines.iter().enumerate().filter(|(_, l)| l.is_match) {
+        let near = |l: &&GrepLine| l.file == hit.file && !l.is_match;
+        let mut before_lines: Vec<ContextLine> = lines[..i]
+            .iter()
+            .rev()
+            .take_while(|l| l.file == hit.file && l.line + u64::from(before) >= hit.line)
+            .filter(near)
+            .map(context)
+            .collect();
+        before_lines.reverse();
+        let after_lines = lines[i + 1..]
+            .iter()
+            .take_while(|l| l.file == hit.file && l.line <= hit.line + u64::from(after))
+            .filter(near)
+            .map(context)
+            .collect();
+        matches.push(GrepMatch {
+            file: hit.file.to_string(),
+            line: hit.line,
+            text: hit.text.chars().take(MAX_LINE_CHARS).collect(),
+            before: before_lines,
+            after: after_lines,
+        });
+    }
+    let more = matches.len() > limit;
+    matches.truncate(limit);
+    (matches, more)
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FoundEntry {
+    pub path: String,
+    pub kind: EntryKind,
+    pub size: Option<u64>,
+    /// Unix seconds.
+    pub mtime: Option<i64>,
+}
+
+/// Parses [`FindQuery::command`] output, keeping at most `limit` entries;
+/// the flag says whether find printed more.
+pub fn parse_find(output: &str, limit: usize) -> (Vec<FoundEntry>, bool) {
+    let mut entries: Vec<FoundEntry> = output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(4, '\t');
+            let kind = match fields.next()? {
+                "f" => EntryKind::File,
+                "d" => EntryKind::Directory,
+                "l" => EntryKind::Symlink,
+                _ => EntryKind::Other,
+            };
+            let size = fields.next()?.parse().ok();
+            let mtime = fields
+                .next()?
+                .split('.')
+                .next()
+                .and_then(|secs| secs.parse().ok());
+            Some(FoundEntry {
+                path: fields.next()?.to_string(),
+                kind,
+                size,
+                mtime,
+            })
+        })
+        .collect();
+    let more = entries.len() > limit;
+    entries.truncate(limit);
+    (entries, more)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_command() {
+        let query = FindQuery {
+            path: "/var/log",
+            name: Some("*.log"),
+            kind: Some(EntryKind::File),
+            max_depth: Some(3),
+            newer_than_minutes: Some(60),
+            min_size_kb: Some(1024),
+            same_filesystem: true,
+            limit: 100,
+            timeout_secs: 10,
+            ..Default::default()
+        };
+        let command = query.command().unwrap();
+        assert_eq!(
+            command,
+            "LC_ALL=C timeout 10 find /var/log -maxdepth 3 -xdev -name '*.log' -type f -mmin -60 -size +1024k -printf '%y\\t%s\\t%T@\\t%p\\n' 2>/dev/null | head -n 101"
+        );
+        assert!(crate::policy::is_read_only_command(&command));
+
+        let unbounded = FindQuery {
+            path: "/",
+            limit: MAX_FIND_LIMIT + 1,
+            timeout_secs: 10,
+            ..Default::default()
+        };
+        assert!(unbounded.command().is_err());
+    }
+
+    #[test]
+    fn test_grep_command() {
+        let paths = vec!["/etc/nginx".to_string(), "-weird".to_string()];
+        let query = GrepQuery {
+            pattern: "listen|server_name",
+            paths: &paths,
+            recursive: true,
+            before: 1,
+            after: 2,
+            max_matches: 10,
+            skip_binary: true,
+            timeout_secs: 10,
+            ..Default::default()
+        };
+        let command = query.command().unwrap();
+        assert_eq!(
+            command,
+            "LC_ALL=C timeout 10 grep -HnsZ -m 11 -r -E -I -B 1 -A 2 -e 'listen|server_name' -- /etc/nginx -weird | head -n 55"
+        );
+        assert!(crate::policy::is_read_only_command(&command));
+
+        let no_paths = GrepQuery {
+            pattern: "x",
+            max_matches: 10,
+            timeout_secs: 10,
+            ..Default::default()
+        };
+        assert!(no_paths.command().is_err());
+    }
+
+    #[test]
+    fn test_parse_grep() {
+        let output = "/etc/a.conf\x0011-# ports\n\
+                      /etc/a.conf\x0012:listen 80;\n\
+                      /etc/a.conf\x0013:listen 443;\n\
+                      /etc/a.conf\x0014-root /srv;\n\
+                      --\n\
+                      /etc/b:c.conf\x003:listen 8080-8090;\n";
+        let (matches, more) = parse_grep(output, 1, 1, 10);
+        assert!(!more);
+        assert_eq!(matches.len(), 3);
+        assert_eq!(matches[0].line, 12);
+        assert_eq!(matches[0].before[0].text, "# ports");
+        assert!(matches[0].after.is_empty());
+        assert_eq!(matches[1].after[0].line, 14);
+        assert_eq!(matches[2].file, "/etc/b:c.conf");
+        assert_eq!(matches[2].text, "listen 8080-8090;");
+
+        let (matches, more) = parse_grep(output, 1, 1, 2);
+        assert!(more);
+        assert_eq!(matches.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_find() {
+        let output = "f\t1048576\t1714557600.1234567890\t/var/log/app.log\n\
+                      d\t4096\t1714557000.0000000000\t/var/log/nginx\n\
+                      f\t10\t1714557000.0\t/var/log/with\ttab.log\n";
+        let (entries, more) = parse_find(output, 2);
+        assert!(more);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].size, Some(1048576));
+        assert_eq!(entries[0].mtime, Some(1714557600));
+        assert_eq!(entries[1].kind, EntryKind::Directory);
+
+        let (entries, more) = parse_find(output, 10);
+        assert!(!more);
+        assert_eq!(entries[2].path, "/var/log/with\ttab.log");
+    }
+}
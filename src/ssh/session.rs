@@ -1,20 +1,308 @@
 use anyhow::{Context, Result};
-use async_ssh2_lite::{AsyncSession, TokioTcpStream};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Mutex, watch};
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
 
-use super::channel::ShellChannel;
-use super::config::{SshHostConfig, parse_ssh_config};
+use super::auth::vault::{VaultAuth, VaultConfig};
+use super::auth::{self, AuthContext, AuthMethod, AuthProvider, DEFAULT_AUTH_ORDER};
+use super::backend::{self, Backend, OpenMode, Sftp, SshChannel, SshSession};
+use super::channel::{ChannelOptions, ShellChannel, SudoError};
+use super::config::{SshHostConfig, host_matches, host_negated, parse_ssh_config};
+use super::files::{self, DirEntry, FileContent, ReadRange, Transfer};
+use super::interactive;
+use super::keys;
+use super::known_hosts::HostKeyChecking;
+use super::passphrase::{ChainPassphraseProvider, PassphraseProvider};
+use super::reconnect::ReconnectPolicy;
+use super::recording::{PTY_COLS, PTY_ROWS, Recorder, RecordingOptions};
+use super::running::{RunningCommands, RunningInfo};
+use super::schedule::{
+    ScheduleInfo, ScheduleManager, ScheduleNotify, ScheduleReport, ScheduleRun, ScheduleSpec,
+};
+use super::shell::{self, ShellContext, ShellKind, quote};
+use super::socks::SocksOptions;
+use super::store::{RestoreStatus, RestoredSession, SavedSession, SessionStore};
+use super::transfer::{
+    self, CopyReport, Pacing, PathFilter, Progress, RateLimiter, TransferOptions, TransferSummary,
+};
+use super::transport::{Transport, open_session};
+use super::tunnel::{TunnelInfo, TunnelManager};
+use super::watch::{WatchInfo, WatchManager, WatchNotify};
+use crate::error::SshLiaisonError;
 
-pub struct SessionState {
-    session: AsyncSession<TokioTcpStream>,
-    channel: ShellChannel,
+/// Remote files checksummed per command when verifying a transfer.
+const VERIFY_BATCH: usize = 64;
+
+/// Keepalive interval for hosts without `ServerAliveInterval`, in seconds.
+pub const DEFAULT_KEEPALIVE_SECS: u32 = 30;
+
+/// Most shell channels kept open on one session, the default one included.
+/// OpenSSH allows 10 per connection (`MaxSessions`); SFTP and tunnels need some too.
+pub const MAX_CHANNELS_PER_SESSION: usize = 6;
+
+/// Sessions open at once, across all clients, unless configured otherwise.
+pub const DEFAULT_MAX_SESSIONS: usize = 16;
+
+/// Channel `ssh_read_log` falls back to while the default shell is busy.
+pub const SCRATCH_CHANNEL: &str = "scratch";
+
+/// Channel scheduled commands run in, so they don't wait for the default shell.
+pub const SCHEDULE_CHANNEL: &str = "schedule";
+
+/// Channel health probes run their commands in.
+pub const HEALTH_CHANNEL: &str = "health";
+
+/// Sessions are keyed by the owning client namespace and the host alias.
+type SessionKey = (Option<String>, String);
+
+/// A shell channel that commands lock for their duration, so the session
+/// state itself isn't held while they run.
+type SharedChannel = Arc<Mutex<ShellChannel>>;
+
+/// One host's state behind its own lock, so hosts never wait on each other.
+type SharedState = Arc<Mutex<SessionState>>;
+
+/// How a session was opened, kept so a dropped session can be re-established.
+#[derive(Clone)]
+enum ConnectOrigin {
+    Config {
+        config: SshHostConfig,
+        passphrase: Option<String>,
+        /// From `connect_direct`, whose alias is the caller's choice.
+        direct: bool,
+    },
+    Password {
+        user: String,
+        host: String,
+        password: String,
+        port: u16,
+    },
+}
+
+impl ConnectOrigin {
+    /// User, hostname and port the session went to.
+    fn endpoint(&self, host_alias: &str) -> (String, String, u16) {
+        match self {
+            Self::Config { config, .. } => (
+                config.user.clone().unwrap_or_default(),
+                config
+                    .hostname
+                    .clone()
+                    .unwrap_or_else(|| host_alias.to_string()),
+                config.port.unwrap_or(22),
+            ),
+            Self::Password {
+                user, host, port, ..
+            } => (user.clone(), host.clone(), *port),
+        }
+    }
+}
+
+struct SessionState {
+    session: Arc<dyn SshSession>,
+    /// The default shell, used when a command names no channel.
+    channel: SharedChannel,
+    /// Extra shells opened on demand, each with its own cwd and environment.
+    named_channels: HashMap<String, SharedChannel>,
+    /// Tracked cwd and variables of the default shell (`None`) and named channels.
+    contexts: HashMap<Option<String>, ShellContext>,
+    shell: ShellKind,
+    /// Opt-in cached sudo password, reused when a command doesn't supply one.
+    sudo_password: Option<String>,
+    origin: ConnectOrigin,
+    connected_at: SystemTime,
+    /// Last time a shell, SFTP or tunnel was taken from the session.
+    last_used: Instant,
+    auth_method: AuthMethod,
+    /// What the remote OS reported at connect, if it could be read.
+    remote_os: Option<String>,
+    /// Labels the client gave the session, kept across reconnects.
+    tags: BTreeSet<String>,
+    _keepalive: Option<KeepaliveTask>,
+}
+
+impl SessionState {
+    /// The descriptor `restore_sessions` re-opens this session from.
+    fn saved(&self, host_alias: &str) -> SavedSession {
+        let (user, hostname, port) = self.origin.endpoint(host_alias);
+        let (config, direct) = match &self.origin {
+            ConnectOrigin::Config { config, direct, .. } => (Some(config.clone()), *direct),
+            ConnectOrigin::Password { .. } => (None, true),
+        };
+        SavedSession {
+            alias: host_alias.to_string(),
+            user,
+            hostname,
+            port,
+            auth_method: self.auth_method,
+            config,
+            direct,
+            tags: self.tags.clone(),
+            saved_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+}
+
+/// Background keepalive sender, stopped when the session is dropped or replaced.
+struct KeepaliveTask(JoinHandle<()>);
+
+impl KeepaliveTask {
+    fn spawn(host_alias: &str, session: Arc<dyn SshSession>, secs: u32) -> Self {
+        session.set_keepalive(secs);
+        let host_alias = host_alias.to_string();
+        let handle = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(secs.into()));
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                if let Err(e) = session.keepalive().await {
+                    tracing::warn!(host = %host_alias, error = %e, "Keepalive failed, stopping");
+                    break;
+                }
+                tracing::trace!(host = %host_alias, "Keepalive sent");
+            }
+        });
+        Self(handle)
+    }
+}
+
+impl Drop for KeepaliveTask {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionInfo {
+    pub alias: String,
+    pub user: String,
+    pub hostname: String,
+    pub port: u16,
+    /// When the session was (re)established, in Unix seconds.
+    pub connected_at: u64,
+    /// Namespace of the client that opened the session (`None` when unscoped).
+    pub owner: Option<String>,
+    /// Name the owning MCP client reported at initialization.
+    pub client: Option<String>,
+    /// Whether the session belongs to the manager it was listed from.
+    pub is_own: bool,
+    pub shell: ShellKind,
+    /// Named channels open besides the default shell.
+    pub channels: Vec<String>,
+    pub auth_method: AuthMethod,
+    pub remote_os: Option<String>,
+    /// Tags from the config and the client, which select extra policy rules.
+    pub tags: Vec<String>,
+    /// Commands running in the session's shells; only listed for own sessions.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub running: Vec<RunningInfo>,
+}
+
+impl std::fmt::Display for ConnectionInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.alias, self.shell)?;
+        if !self.channels.is_empty() {
+            write!(f, " [channels: {}]", self.channels.join(", "))?;
+        }
+        if !self.tags.is_empty() {
+            write!(f, " [tags: {}]", self.tags.join(", "))?;
+        }
+        if !self.running.is_empty() {
+            let ids: Vec<String> = self.running.iter().map(|r| r.id.to_string()).collect();
+            write!(f, " [running: {}]", ids.join(", "))?;
+        }
+        match (&self.owner, &self.client) {
+            (Some(owner), Some(client)) => write!(f, " [owner: {} ({})]", owner, client)?,
+            (Some(owner), None) => write!(f, " [owner: {}]", owner)?,
+            (None, _) => {}
+        }
+        if self.is_own && self.owner.is_some() {
+            write!(f, " (yours)")?;
+        }
+        Ok(())
+    }
+}
+
+/// What [`SessionManager::reset_shell`] did.
+#[derive(Debug, Clone, Serialize)]
+pub struct ShellReset {
+    /// Commands that were running in the shell and got interrupted.
+    pub cancelled: Vec<u64>,
+    /// Whether a new shell replaced the old one, losing its directory and variables.
+    pub reopened: bool,
 }
 
+/// SSH sessions, optionally scoped to one client's namespace.
+///
+/// Scoped managers share the underlying session table, but each one only sees
+/// and operates on the aliases it created, so clients can't clash on aliases.
 pub struct SessionManager {
-    sessions: Arc<Mutex<HashMap<String, SessionState>>>,
+    /// Only locked to look up or swap entries, never across an await.
+    sessions: Arc<std::sync::Mutex<HashMap<SessionKey, SharedState>>>,
+    passphrase_provider: Arc<dyn PassphraseProvider>,
+    tunnels: TunnelManager,
+    watches: WatchManager,
+    running: RunningCommands,
+    schedules: ScheduleManager,
+    namespace: Option<String>,
+    client_names: Arc<std::sync::Mutex<HashMap<String, String>>>,
+    reconnect: ReconnectPolicy,
+    keepalive_secs: u32,
+    /// SSH library new sessions are opened with.
+    backend: Backend,
+    /// `Host`-style patterns a connection must match; `None` allows any host.
+    allowed_hosts: Option<Arc<Vec<String>>>,
+    channel_options: ChannelOptions,
+    /// Options configured for host aliases, in every namespace.
+    host_channel_options: Arc<HashMap<String, ChannelOptions>>,
+    /// Per-alias replacements for `channel_options`, kept across reconnects.
+    channel_overrides: Arc<std::sync::Mutex<HashMap<SessionKey, ChannelOptions>>>,
+    recording: Arc<RecordingOptions>,
+    /// Per-alias choice to record or not, over `recording.all_sessions`.
+    record_overrides: Arc<std::sync::Mutex<HashMap<SessionKey, bool>>>,
+    /// Built-in providers first, then those added with `with_auth_provider`.
+    auth_providers: Arc<Vec<Arc<dyn AuthProvider>>>,
+    auth_order: Arc<Vec<AuthMethod>>,
+    /// Transports configured for host aliases, over their `ProxyCommand`.
+    transports: Arc<HashMap<String, Transport>>,
+    /// Shared by every SFTP transfer of the server.
+    transfer_limit: Option<Arc<RateLimiter>>,
+    /// Tags given to hosts by the config, as tag -> `Host`-style patterns.
+    host_tags: Arc<BTreeMap<String, Vec<String>>>,
+    /// Where sessions are saved for `restore_sessions`; `None` keeps nothing.
+    store: Option<Arc<SessionStore>>,
+    /// Cap on the sessions in the shared table; 0 is unlimited.
+    max_sessions: usize,
+    /// Sessions still connecting, counted against `max_sessions` when new;
+    /// a second connect of the alias waits until the receiver closes.
+    connecting: Arc<std::sync::Mutex<HashMap<SessionKey, watch::Receiver<()>>>>,
+    /// Connect `~/.ssh/config` aliases on their first command.
+    auto_connect: bool,
+}
+
+/// A session being opened, holding its place under the cap until dropped.
+#[derive(Debug)]
+struct PendingSlot {
+    connecting: Arc<std::sync::Mutex<HashMap<SessionKey, watch::Receiver<()>>>>,
+    key: SessionKey,
+    /// Dropped with the slot, waking the connects waiting on this alias.
+    _done: watch::Sender<()>,
+}
+
+impl Drop for PendingSlot {
+    fn drop(&mut self) {
+        let mut connecting = self.connecting.lock().unwrap_or_else(|e| e.into_inner());
+        connecting.remove(&self.key);
+    }
 }
 
 impl Default for SessionManager {
@@ -24,236 +312,425 @@ impl Default for SessionManager {
 }
 
 impl SessionManager {
+    /// Manager whose encrypted keys are unlocked from the env var or OS keyring.
     pub fn new() -> Self {
+        Self::with_passphrase_provider(Arc::new(ChainPassphraseProvider::non_interactive()))
+    }
+
+    pub fn with_passphrase_provider(passphrase_provider: Arc<dyn PassphraseProvider>) -> Self {
         Self {
-            sessions: Arc::new(Mutex::new(HashMap::new())),
+            sessions: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            passphrase_provider,
+            tunnels: TunnelManager::new(),
+            watches: WatchManager::new(),
+            running: RunningCommands::new(),
+            schedules: ScheduleManager::new(),
+            namespace: None,
+            client_names: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            reconnect: ReconnectPolicy::default(),
+            keepalive_secs: DEFAULT_KEEPALIVE_SECS,
+            backend: Backend::default(),
+            allowed_hosts: None,
+            channel_options: ChannelOptions::default(),
+            host_channel_options: Arc::new(HashMap::new()),
+            channel_overrides: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            recording: Arc::new(RecordingOptions::default()),
+            record_overrides: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            auth_providers: Arc::new(auth::builtin_providers()),
+            auth_order: Arc::new(DEFAULT_AUTH_ORDER.to_vec()),
+            transports: Arc::new(HashMap::new()),
+            transfer_limit: None,
+            host_tags: Arc::new(BTreeMap::new()),
+            store: None,
+            max_sessions: DEFAULT_MAX_SESSIONS,
+            connecting: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            auto_connect: false,
         }
     }
 
-    pub async fn connect_by_alias(&self, host_alias: &str) -> Result<()> {
-        let config = parse_ssh_config(host_alias)?;
-        self.connect_with_config(host_alias, &config).await
+    /// Cap on the output kept per command; see [`ChannelOptions::max_output_bytes`].
+    pub fn with_max_output_bytes(mut self, max_output_bytes: usize) -> Self {
+        self.channel_options.max_output_bytes = max_output_bytes;
+        self
     }
 
-    pub async fn connect_with_config(
-        &self,
-        host_alias: &str,
-        config: &SshHostConfig,
-    ) -> Result<()> {
-        let hostname = config
-            .hostname
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("Hostname not specified for host '{}'", host_alias))?;
-        let port = config.port.unwrap_or(22);
-        let user = config
-            .user
-            .as_ref()
-            .ok_or_else(|| anyhow::anyhow!("User not specified for host '{}'", host_alias))?;
+    pub fn with_channel_options(mut self, options: ChannelOptions) -> Self {
+        self.channel_options = options;
+        self
+    }
 
-        if let Some(ref proxy_cmd) = config.proxy_command {
-            tracing::debug!(proxy_command = %proxy_cmd, "ProxyCommand specified");
-            tracing::debug!(hostname = %hostname, port = %port, "Attempting direct connection");
-        }
+    /// Options for the channels of these host aliases, e.g. from the server config.
+    pub fn with_host_channel_options(mut self, options: HashMap<String, ChannelOptions>) -> Self {
+        self.host_channel_options = Arc::new(options);
+        self
+    }
 
-        let addr = tokio::net::lookup_host(format!("{}:{}", hostname, port))
-            .await
-            .context("Failed to resolve hostname")?
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("No address found for {}", hostname))?;
+    /// Options for channels of hosts without their own.
+    pub fn channel_options(&self) -> ChannelOptions {
+        self.channel_options
+    }
 
-        let mut session = AsyncSession::<TokioTcpStream>::connect(addr, None)
-            .await
-            .context("Failed to connect")?;
+    /// Options for the alias's channels, applied from its next connect on
+    /// (and to channels opened later on a live session).
+    pub fn set_channel_options(&self, host_alias: &str, options: ChannelOptions) {
+        let mut overrides = self
+            .channel_overrides
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        overrides.insert(self.key(host_alias), options);
+    }
 
-        session.handshake().await.context("SSH handshake failed")?;
+    /// Options the alias's channels are opened with.
+    pub fn channel_options_for(&self, host_alias: &str) -> ChannelOptions {
+        let overrides = self
+            .channel_overrides
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        overrides
+            .get(&self.key(host_alias))
+            .or_else(|| self.host_channel_options.get(host_alias))
+            .copied()
+            .unwrap_or(self.channel_options)
+    }
 
-        let mut authenticated = false;
+    pub fn with_recording(mut self, recording: RecordingOptions) -> Self {
+        self.recording = Arc::new(recording);
+        self
+    }
 
-        if !config.identities_only {
-            tracing::debug!("Attempting SSH agent authentication");
-            match session.userauth_agent(user).await {
-                Ok(_) => {
-                    if session.authenticated() {
-                        authenticated = true;
-                        tracing::debug!("SSH agent authentication successful");
-                    } else {
-                        tracing::debug!("SSH agent returned OK but session not authenticated");
-                    }
-                }
-                Err(e) => {
-                    tracing::debug!(error = %e, "SSH agent authentication failed");
-                }
-            }
-        } else {
-            tracing::debug!("IdentitiesOnly is set, skipping SSH agent");
+    /// Whether the alias's shells are recorded, from its next connect on
+    /// (and for channels opened later on a live session).
+    pub fn set_recording(&self, host_alias: &str, record: bool) {
+        let mut overrides = self
+            .record_overrides
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        overrides.insert(self.key(host_alias), record);
+    }
+
+    fn is_recorded(&self, host_alias: &str) -> bool {
+        let overrides = self
+            .record_overrides
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        overrides
+            .get(&self.key(host_alias))
+            .copied()
+            .unwrap_or(self.recording.all_sessions)
+    }
+
+    /// A recorder for a new shell on the alias, if it is recorded.
+    fn recorder(&self, host_alias: &str, channel: Option<&str>) -> Result<Option<Recorder>> {
+        if !self.is_recorded(host_alias) {
+            return Ok(None);
         }
+        Recorder::create(&self.recording.dir, host_alias, channel).map(Some)
+    }
 
-        if !authenticated {
-            if let Some(ref identity_file) = config.identity_file {
-                tracing::debug!(path = %identity_file.display(), "Trying identity file");
-                if !identity_file.exists() {
-                    anyhow::bail!(
-                        "Identity file not found: {}. Check that the file exists and path is correct.",
-                        identity_file.display()
-                    );
-                }
+    /// Fetch certificates or one-time passwords from Vault for the configured aliases.
+    /// Adds a provider, tried when its method comes up in the auth order.
+    pub fn with_auth_provider(mut self, provider: Arc<dyn AuthProvider>) -> Self {
+        Arc::make_mut(&mut self.auth_providers).push(provider);
+        self
+    }
 
-                #[cfg(unix)]
-                {
-                    if let Ok(metadata) = std::fs::metadata(identity_file) {
-                        use std::os::unix::fs::PermissionsExt;
-                        let mode = metadata.permissions().mode();
-                        if mode & 0o077 != 0 {
-                            tracing::warn!(
-                                path = %identity_file.display(),
-                                mode = format!("{:o}", mode & 0o777),
-                                "Identity file has insecure permissions, should be 600"
-                            );
-                        }
-                    }
-                }
+    /// Order in which auth methods are tried; see [`DEFAULT_AUTH_ORDER`].
+    pub fn with_auth_order(mut self, order: Vec<AuthMethod>) -> Self {
+        self.auth_order = Arc::new(order);
+        self
+    }
 
-                match session
-                    .userauth_pubkey_file(user, None, identity_file, None)
-                    .await
-                {
-                    Ok(_) => {
-                        if session.authenticated() {
-                            authenticated = true;
-                            tracing::debug!("Identity file authentication successful");
-                        } else {
-                            tracing::debug!("Identity file auth returned OK but not authenticated");
-                        }
-                    }
-                    Err(e) => {
-                        tracing::debug!(error = %e, "Identity file authentication failed");
-                        anyhow::bail!(
-                            "Authentication failed with identity file {}. Error: {}. Make sure the key is added to authorized_keys on the remote host.",
-                            identity_file.display(),
-                            e
-                        );
-                    }
-                }
-            } else if !config.identities_only {
-                let home = std::env::var("HOME").unwrap_or_else(|_| "~".to_string());
-                let key_paths = vec![
-                    format!("{}/.ssh/id_ed25519", home),
-                    format!("{}/.ssh/id_rsa", home),
-                    format!("{}/.ssh/id_ecdsa", home),
-                    format!("{}/.ssh/id_dsa", home),
-                ];
-
-                tracing::debug!("Trying common SSH key files");
-                for key_path in key_paths {
-                    let path = PathBuf::from(&key_path);
-                    if path.exists() {
-                        tracing::trace!(path = %path.display(), "Trying key file");
-                        match session.userauth_pubkey_file(user, None, &path, None).await {
-                            Ok(_) => {
-                                if session.authenticated() {
-                                    authenticated = true;
-                                    tracing::debug!(path = %path.display(), "Key file authentication successful");
-                                    break;
-                                } else {
-                                    tracing::trace!(path = %path.display(), "Key returned OK but not authenticated");
-                                }
-                            }
-                            Err(e) => {
-                                tracing::trace!(path = %path.display(), error = %e, "Key file auth failed");
-                            }
-                        }
-                    } else {
-                        tracing::trace!(path = %path.display(), "Key file not found");
-                    }
-                }
-            } else {
-                tracing::debug!("IdentitiesOnly set but no IdentityFile specified");
-            }
+    /// Hosts under `[vault.hosts]` authenticate with Vault credentials.
+    pub fn with_vault(self, vault: VaultConfig) -> Self {
+        if vault.is_empty() {
+            return self;
         }
+        self.with_auth_provider(Arc::new(VaultAuth(vault)))
+    }
 
-        if !authenticated {
-            let mut error_msg = String::from("SSH key authentication failed.");
+    /// Reach these host aliases over SSM, EC2 Instance Connect, or plain TCP.
+    pub fn with_host_transports(mut self, transports: HashMap<String, Transport>) -> Self {
+        self.transports = Arc::new(transports);
+        self
+    }
 
-            if config.identities_only {
-                if config.identity_file.is_some() {
-                    error_msg.push_str(
-                        " IdentitiesOnly is set but the specified identity file failed authentication.",
-                    );
-                } else {
-                    error_msg.push_str(" IdentitiesOnly is set but no IdentityFile was specified.");
-                }
-            } else {
-                error_msg.push_str(" No valid keys found or agent not available.");
-            }
+    /// Caps the combined rate of all file transfers, on top of any per-transfer limit.
+    pub fn with_transfer_rate_limit(mut self, limiter: RateLimiter) -> Self {
+        self.transfer_limit = Some(Arc::new(limiter));
+        self
+    }
 
-            if config.proxy_command.is_some() {
-                error_msg.push_str(" ProxyCommand was specified but connection failed.");
-            }
+    /// `pacing` plus the server-wide transfer limit.
+    fn paced(&self, pacing: &Pacing) -> Pacing {
+        match &self.transfer_limit {
+            Some(limiter) => pacing.clone().limit(Arc::clone(limiter)),
+            None => pacing.clone(),
+        }
+    }
 
-            error_msg.push_str(" Check that:");
-            if !config.identities_only {
-                error_msg.push_str(" SSH agent is running,");
-            }
-            if config.identity_file.is_some() {
-                error_msg.push_str(" the identity file exists and has correct permissions (600),");
-            } else if !config.identities_only {
-                error_msg.push_str(" keys exist in ~/.ssh/,");
-            }
-            error_msg
-                .push_str(" and the public key is added to authorized_keys on the remote host.");
+    /// Keepalive interval for hosts whose config doesn't set one; 0 disables it.
+    pub fn with_keepalive_interval(mut self, secs: u32) -> Self {
+        self.keepalive_secs = secs;
+        self
+    }
+
+    /// SSH library for new sessions; open ones keep theirs until they reconnect.
+    pub fn with_backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Only connect to aliases or hostnames matching one of `patterns`
+    /// (`*`/`?` wildcards, `!` negates, as in `~/.ssh/config` `Host` lines).
+    pub fn with_allowed_hosts(mut self, patterns: Vec<String>) -> Self {
+        self.allowed_hosts = Some(Arc::new(patterns));
+        self
+    }
+
+    pub fn with_reconnect_policy(mut self, reconnect: ReconnectPolicy) -> Self {
+        self.reconnect = reconnect;
+        self
+    }
+
+    /// Tags hosts whose alias or hostname matches the patterns of each tag.
+    pub fn with_host_tags(mut self, host_tags: BTreeMap<String, Vec<String>>) -> Self {
+        self.host_tags = Arc::new(host_tags);
+        self
+    }
+
+    /// Saves every session opened to `store` until it is disconnected.
+    pub fn with_session_store(mut self, store: SessionStore) -> Self {
+        self.store = Some(Arc::new(store));
+        self
+    }
+
+    /// Most sessions open at once across namespaces; at the cap, a new one
+    /// closes the least recently used idle session, its own namespace's
+    /// first. 0 removes the cap.
+    pub fn with_max_sessions(mut self, max_sessions: usize) -> Self {
+        self.max_sessions = max_sessions;
+        self
+    }
+
+    /// Lets [`auto_connect`](Self::auto_connect) open sessions for aliases
+    /// that aren't connected yet.
+    pub fn with_auto_connect(mut self, auto_connect: bool) -> Self {
+        self.auto_connect = auto_connect;
+        self
+    }
+
+    pub fn session_store(&self) -> Option<&SessionStore> {
+        self.store.as_deref()
+    }
 
-            anyhow::bail!("{}", error_msg);
+    /// A view of the same session table restricted to `namespace`.
+    pub fn scoped(&self, namespace: impl Into<String>) -> Self {
+        Self {
+            namespace: Some(namespace.into()),
+            ..self.clone()
         }
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
 
-        if !session.authenticated() {
-            anyhow::bail!("Authentication failed for {}@{}", user, hostname);
+    /// Records the client name shown next to this namespace in `list_connections`.
+    pub fn set_client_name(&self, name: &str) {
+        if let Some(ref namespace) = self.namespace {
+            let mut names = self.client_names.lock().unwrap_or_else(|e| e.into_inner());
+            names.insert(namespace.clone(), name.to_string());
         }
+    }
 
-        let mut channel = session
-            .channel_session()
-            .await
-            .context("Failed to open channel")?;
+    fn key(&self, host_alias: &str) -> SessionKey {
+        (self.namespace.clone(), host_alias.to_string())
+    }
 
-        channel
-            .request_pty("xterm", None, None)
-            .await
-            .context("Failed to request PTY")?;
+    fn table(&self) -> std::sync::MutexGuard<'_, HashMap<SessionKey, SharedState>> {
+        self.sessions.lock().unwrap_or_else(|e| e.into_inner())
+    }
 
-        channel.shell().await.context("Failed to open shell")?;
+    /// The alias's session state.
+    fn state(&self, host_alias: &str) -> Result<SharedState> {
+        self.table()
+            .get(&self.key(host_alias))
+            .cloned()
+            .ok_or_else(|| SshLiaisonError::NotConnected(host_alias.to_string()).into())
+    }
 
-        let shell_channel = ShellChannel::new(channel);
+    /// The machine `host_alias` reaches, as `hostname` or `hostname:port`
+    /// off port 22: from its session, else from `~/.ssh/config`. Aliases that
+    /// resolve to nothing are returned as they are.
+    pub async fn resolved_host(&self, host_alias: &str) -> String {
+        let (hostname, port) = match self.state(host_alias) {
+            Ok(state) => {
+                let (_, hostname, port) = state.lock().await.origin.endpoint(host_alias);
+                (hostname, port)
+            }
+            Err(_) => match parse_ssh_config(host_alias) {
+                Ok(config) => (
+                    config.hostname.unwrap_or_else(|| host_alias.to_string()),
+                    config.port.unwrap_or(22),
+                ),
+                Err(_) => return host_alias.to_string(),
+            },
+        };
+        let hostname = hostname.to_ascii_lowercase();
+        if port == 22 {
+            hostname
+        } else {
+            format!("{}:{}", hostname, port)
+        }
+    }
 
-        let state = SessionState {
-            session,
-            channel: shell_channel,
+    /// Connects using the options `~/.ssh/config` resolves for `host_alias`.
+    pub async fn connect_by_alias(&self, host_alias: &str) -> Result<()> {
+        let config = parse_ssh_config(host_alias)?;
+        self.connect_with_config(host_alias, &config, None).await
+    }
+
+    /// Connects with agent/key auth and opens the persistent shell for `host_alias`.
+    /// An existing session under the same alias is replaced.
+    pub async fn connect_with_config(
+        &self,
+        host_alias: &str,
+        config: &SshHostConfig,
+        passphrase: Option<&str>,
+    ) -> Result<()> {
+        let hostname = config.hostname.as_deref().unwrap_or(host_alias);
+        self.check_host_allowed(&[host_alias, hostname])?;
+        let origin = ConnectOrigin::Config {
+            config: config.clone(),
+            passphrase: passphrase.map(str::to_string),
+            direct: false,
         };
+        self.connect_origin(host_alias, origin, None, BTreeSet::new())
+            .await
+    }
 
-        let mut sessions = self.sessions.lock().await;
-        sessions.insert(host_alias.to_string(), state);
+    /// Whether the host allowlist, if there is one, lets the host named
+    /// `names` (alias, hostname) through: a negated pattern matching any of
+    /// them rejects it, else one of them has to match.
+    pub fn is_host_allowed(&self, names: &[&str]) -> bool {
+        match self.allowed_hosts {
+            Some(ref patterns) => {
+                !names.iter().any(|name| host_negated(patterns, name))
+                    && names.iter().any(|name| host_matches(patterns, name))
+            }
+            None => true,
+        }
+    }
 
-        Ok(())
+    /// Rejects a connection the host allowlist doesn't let through.
+    fn check_host_allowed(&self, names: &[&str]) -> Result<()> {
+        if self.is_host_allowed(names) {
+            return Ok(());
+        }
+        tracing::warn!(host = ?names, "Connection refused by host allowlist");
+        Err(SshLiaisonError::HostNotAllowed(names.join(" / ")).into())
+    }
+
+    async fn establish_with_config(
+        &self,
+        host_alias: &str,
+        config: &SshHostConfig,
+        passphrase: Option<&str>,
+    ) -> Result<(Box<dyn SshSession>, ShellChannel, AuthMethod)> {
+        let hostname = config.hostname.as_ref().ok_or_else(|| {
+            SshLiaisonError::Config(format!("Hostname not specified for host '{}'", host_alias))
+        })?;
+        let port = config.port.unwrap_or(22);
+        let user = config.user.as_ref().ok_or_else(|| {
+            SshLiaisonError::Config(format!("User not specified for host '{}'", host_alias))
+        })?;
+
+        let transport = self.transports.get(host_alias);
+        let proxy_command = Transport::proxy_command(transport, config);
+        match proxy_command {
+            Some(ref command) => {
+                tracing::debug!(proxy_command = %command, "Connecting through proxy command");
+                if config.proxy_use_fdpass {
+                    tracing::warn!("ProxyUseFdpass is not supported, using the command's stdio");
+                }
+            }
+            None => {
+                tracing::debug!(hostname = %hostname, port = %port, "Attempting direct connection")
+            }
+        }
+        if let Some(transport) = transport
+            && matches!(transport, Transport::InstanceConnect(_))
+        {
+            let mut public_key =
+                keys::key_pair(config.identity_files.first().map(PathBuf::as_path))?
+                    .into_os_string();
+            public_key.push(".pub");
+            transport
+                .prepare(config, user, Path::new(&public_key))
+                .await?;
+        }
+
+        let session = open_session(
+            hostname,
+            port,
+            proxy_command.as_deref(),
+            self.backend,
+            config.strict_host_key_checking.unwrap_or_default(),
+        )
+        .await
+        .map_err(|e| match transport.and_then(Transport::hint) {
+            Some(hint) => e.context(format!("Connecting to '{}' failed; {}", host_alias, hint)),
+            None => e,
+        })?;
+
+        let mut ctx = AuthContext::new(&*session, host_alias, config, &*self.passphrase_provider);
+        ctx.passphrase = passphrase;
+        let method = auth::authenticate(&self.auth_providers, &self.auth_order, &ctx).await?;
+
+        let shell_channel = open_shell(
+            &*session,
+            self.channel_options_for(host_alias),
+            self.recorder(host_alias, None)?,
+        )
+        .await?;
+        Ok((session, shell_channel, method))
     }
 
+    /// Connects to `user@host:port` with agent/key auth, without reading ssh config.
     pub async fn connect_direct(
         &self,
         host_alias: &str,
         user: &str,
         host: &str,
         port: Option<u16>,
+        passphrase: Option<&str>,
     ) -> Result<()> {
         let config = SshHostConfig {
             host: host_alias.to_string(),
             hostname: Some(host.to_string()),
             user: Some(user.to_string()),
             port,
-            identity_file: None,
+            identity_files: Vec::new(),
+            certificate_files: Vec::new(),
             proxy_command: None,
             proxy_use_fdpass: false,
             identities_only: false,
+            server_alive_interval: None,
+            identity_agent: None,
+            strict_host_key_checking: None,
+        };
+        // The alias is the caller's choice here, so only the address counts.
+        self.check_host_allowed(&[host])?;
+        let origin = ConnectOrigin::Config {
+            config,
+            passphrase: passphrase.map(str::to_string),
+            direct: true,
         };
-        self.connect_with_config(host_alias, &config).await
+        self.connect_origin(host_alias, origin, None, BTreeSet::new())
+            .await
     }
 
+    /// Connects to `user@host:port` with password auth.
     pub async fn connect_with_password(
         &self,
         host_alias: &str,
@@ -262,107 +739,2119 @@ impl SessionManager {
         password: &str,
         port: Option<u16>,
     ) -> Result<()> {
-        let port = port.unwrap_or(22);
-        let addr = tokio::net::lookup_host(format!("{}:{}", host, port))
-            .await
-            .context("Failed to resolve hostname")?
-            .next()
-            .ok_or_else(|| anyhow::anyhow!("No address found for {}", host))?;
-
-        let mut session = AsyncSession::<TokioTcpStream>::connect(addr, None)
-            .await
-            .context("Failed to connect")?;
-
-        session.handshake().await.context("SSH handshake failed")?;
-
-        session
-            .userauth_password(user, password)
+        self.check_host_allowed(&[host])?;
+        let origin = ConnectOrigin::Password {
+            user: user.to_string(),
+            host: host.to_string(),
+            password: password.to_string(),
+            port: port.unwrap_or(22),
+        };
+        self.connect_origin(host_alias, origin, None, BTreeSet::new())
             .await
-            .context("Password authentication failed")?;
+    }
 
-        if !session.authenticated() {
-            anyhow::bail!("Authentication failed for {}@{}", user, host);
+    #[tracing::instrument(name = "ssh.connect", skip_all, fields(host = %host_alias))]
+    async fn establish(
+        &self,
+        host_alias: &str,
+        origin: &ConnectOrigin,
+    ) -> Result<(Box<dyn SshSession>, ShellChannel, AuthMethod)> {
+        match origin {
+            ConnectOrigin::Config {
+                config, passphrase, ..
+            } => {
+                self.establish_with_config(host_alias, config, passphrase.as_deref())
+                    .await
+            }
+            ConnectOrigin::Password {
+                user,
+                host,
+                password,
+                port,
+            } => {
+                self.establish_with_password(host_alias, user, host, password, *port)
+                    .await
+            }
         }
+    }
 
-        let mut channel = session
-            .channel_session()
-            .await
-            .context("Failed to open channel")?;
+    /// Password auth, and keyboard-interactive with the same password, in the
+    /// configured order.
+    async fn establish_with_password(
+        &self,
+        host_alias: &str,
+        user: &str,
+        host: &str,
+        password: &str,
+        port: u16,
+    ) -> Result<(Box<dyn SshSession>, ShellChannel, AuthMethod)> {
+        let session =
+            open_session(host, port, None, self.backend, HostKeyChecking::default()).await?;
 
-        channel
-            .request_pty("xterm", None, None)
-            .await
-            .context("Failed to request PTY")?;
+        let config = SshHostConfig {
+            host: host_alias.to_string(),
+            hostname: Some(host.to_string()),
+            user: Some(user.to_string()),
+            port: Some(port),
+            identity_files: Vec::new(),
+            certificate_files: Vec::new(),
+            proxy_command: None,
+            proxy_use_fdpass: false,
+            identities_only: true,
+            server_alive_interval: None,
+            identity_agent: None,
+            strict_host_key_checking: None,
+        };
+        let mut ctx = AuthContext::new(&*session, host_alias, &config, &*self.passphrase_provider);
+        ctx.password = Some(password);
+        let order: Vec<AuthMethod> = self
+            .auth_order
+            .iter()
+            .copied()
+            .filter(|m| matches!(m, AuthMethod::Password | AuthMethod::KeyboardInteractive))
+            .collect();
+        let method = auth::authenticate(&self.auth_providers, &order, &ctx).await?;
 
-        channel.shell().await.context("Failed to open shell")?;
+        let shell_channel = open_shell(
+            &*session,
+            self.channel_options_for(host_alias),
+            self.recorder(host_alias, None)?,
+        )
+        .await?;
+        Ok((session, shell_channel, method))
+    }
+
+    async fn connect_origin(
+        &self,
+        host_alias: &str,
+        origin: ConnectOrigin,
+        sudo_password: Option<String>,
+        tags: BTreeSet<String>,
+    ) -> Result<()> {
+        let _slot = self.reserve_slot(host_alias).await?;
+        let (session, channel, auth_method) = self.establish(host_alias, &origin).await?;
+        let session: Arc<dyn SshSession> = Arc::from(session);
 
-        let shell_channel = ShellChannel::new(channel);
+        let keepalive_secs = match origin {
+            ConnectOrigin::Config { ref config, .. } => config.server_alive_interval,
+            ConnectOrigin::Password { .. } => None,
+        }
+        .unwrap_or(self.keepalive_secs);
+        let keepalive = (keepalive_secs > 0)
+            .then(|| KeepaliveTask::spawn(host_alias, Arc::clone(&session), keepalive_secs));
 
         let state = SessionState {
             session,
-            channel: shell_channel,
+            shell: channel.shell(),
+            channel: Arc::new(Mutex::new(channel)),
+            named_channels: HashMap::new(),
+            contexts: HashMap::new(),
+            sudo_password,
+            origin,
+            connected_at: SystemTime::now(),
+            last_used: Instant::now(),
+            auth_method,
+            remote_os: None,
+            tags,
+            _keepalive: keepalive,
         };
 
-        let mut sessions = self.sessions.lock().await;
-        sessions.insert(host_alias.to_string(), state);
+        let state = Arc::new(Mutex::new(state));
+        self.install(host_alias, Arc::clone(&state)).await;
+
+        let shell = state.lock().await.shell;
+        match self
+            .execute_in_shell(host_alias, None, shell.os_command(), None)
+            .await
+        {
+            Ok(output) if output.exit_code == Some(0) => {
+                state.lock().await.remote_os = printed_dir(&output);
+            }
+            Ok(_) => tracing::debug!(host = %host_alias, "Remote OS not reported"),
+            Err(e) => tracing::debug!(host = %host_alias, error = %e, "Failed to read remote OS"),
+        }
 
+        self.remember(host_alias).await;
         Ok(())
     }
 
-    #[allow(dead_code)]
-    pub async fn is_connected(&self, host_alias: &str) -> bool {
-        let sessions = self.sessions.lock().await;
-        sessions.contains_key(host_alias)
+    /// Puts `state` in the table as the alias's session, closing the one it
+    /// replaces.
+    async fn install(&self, host_alias: &str, state: SharedState) {
+        let replaced = self.table().insert(self.key(host_alias), state);
+        if let Some(replaced) = replaced
+            && let Err(e) = close_state(&replaced).await
+        {
+            tracing::warn!(host = %host_alias, error = %e, "Failed to close replaced session");
+        }
     }
 
-    pub async fn execute_command(
-        &self,
-        host_alias: &str,
-        command: &str,
-        sudo_password: Option<&str>,
-    ) -> Result<crate::ssh::channel::CommandOutput> {
-        let mut sessions = self.sessions.lock().await;
-        let state = sessions
-            .get_mut(host_alias)
-            .ok_or_else(|| anyhow::anyhow!("Not connected to host '{}'", host_alias))?;
-
-        state.channel.execute_command(command, sudo_password).await
+    /// Holds the alias's place under the cap while it connects, closing idle
+    /// sessions to make room; replacing a session needs none. Waits for a
+    /// connect of the same alias already under way to finish first.
+    async fn reserve_slot(&self, host_alias: &str) -> Result<PendingSlot> {
+        let key = self.key(host_alias);
+        loop {
+            let under_way = {
+                let table = self.table();
+                let mut connecting = self.connecting.lock().unwrap_or_else(|e| e.into_inner());
+                match connecting.get(&key) {
+                    Some(done) => Some(done.clone()),
+                    None => {
+                        let new = connecting
+                            .keys()
+                            .filter(|k| !table.contains_key(*k))
+                            .count();
+                        if self.max_sessions == 0
+                            || table.contains_key(&key)
+                            || table.len() + new < self.max_sessions
+                        {
+                            let (done, waiter) = watch::channel(());
+                            connecting.insert(key.clone(), waiter);
+                            return Ok(PendingSlot {
+                                connecting: Arc::clone(&self.connecting),
+                                key,
+                                _done: done,
+                            });
+                        }
+                        None
+                    }
+                }
+            };
+            if let Some(mut done) = under_way {
+                // Errs once the other connect drops its slot.
+                let _ = done.changed().await;
+                continue;
+            }
+            if !self.evict_idle().await {
+                tracing::warn!(host = %host_alias, max = self.max_sessions, "Session limit reached");
+                return Err(SshLiaisonError::SessionLimit(self.max_sessions).into());
+            }
+        }
     }
 
-    #[allow(dead_code)]
-    pub async fn execute_command_streaming(
-        &self,
-        host_alias: &str,
-        command: &str,
-    ) -> Result<String> {
-        let mut sessions = self.sessions.lock().await;
-        let state = sessions
-            .get_mut(host_alias)
-            .ok_or_else(|| anyhow::anyhow!("Not connected to host '{}'", host_alias))?;
+    /// Closes the least recently used session that runs no command, tunnel,
+    /// watch or schedule, taking one of this namespace before other clients'
+    /// since the cap is shared. Returns whether there was one.
+    async fn evict_idle(&self) -> bool {
+        let sessions: Vec<(SessionKey, SharedState)> = self
+            .table()
+            .iter()
+            .map(|(key, state)| (key.clone(), Arc::clone(state)))
+            .collect();
 
-        state.channel.execute_command_streaming(command).await
-    }
+        let mut idle = Vec::new();
+        for ((owner, alias), state) in sessions {
+            let last_used = {
+                let Ok(state) = state.try_lock() else {
+                    continue;
+                };
+                let busy = std::iter::once(&state.channel)
+                    .chain(state.named_channels.values())
+                    .any(|channel| channel.try_lock().is_err());
+                if busy {
+                    continue;
+                }
+                state.last_used
+            };
+            let namespace = owner.as_deref();
+            if self.tunnels.has_host(namespace, &alias).await
+                || self.watches.has_host(namespace, &alias).await
+                || self.schedules.has_host(namespace, &alias).await
+            {
+                continue;
+            }
+            idle.push((owner != self.namespace, last_used, owner, alias));
+        }
 
-    pub async fn disconnect(&self, host_alias: &str) -> Result<()> {
-        let mut sessions = self.sessions.lock().await;
-        if let Some(state) = sessions.remove(host_alias) {
-            state.channel.close().await?;
-            state.session.disconnect(None, "Goodbye", None).await?;
+        let Some((_, last_used, owner, alias)) = idle.into_iter().min() else {
+            return false;
+        };
+        tracing::info!(
+            host = %alias,
+            namespace = ?owner,
+            idle_secs = last_used.elapsed().as_secs(),
+            "Closing least recently used session to stay under the session limit"
+        );
+        let removed = self.table().remove(&(owner, alias.clone()));
+        if let Some(store) = &self.store
+            && let Err(e) = store.forget(std::slice::from_ref(&alias))
+        {
+            tracing::warn!(host = %alias, error = %e, "Failed to forget saved session");
         }
-        Ok(())
+        if let Some(state) = removed
+            && let Err(e) = close_state(&state).await
+        {
+            tracing::warn!(host = %alias, error = %e, "Failed to close session");
+        }
+        true
     }
 
-    #[allow(dead_code)]
-    pub async fn list_connections(&self) -> Vec<String> {
-        let sessions = self.sessions.lock().await;
-        sessions.keys().cloned().collect()
+    /// Writes the session's descriptor to the store, if there is one.
+    async fn remember(&self, host_alias: &str) {
+        let (Some(store), Ok(state)) = (&self.store, self.state(host_alias)) else {
+            return;
+        };
+        let saved = state.lock().await.saved(host_alias);
+        if let Err(e) = store.save(saved) {
+            tracing::warn!(host = %host_alias, error = %e, "Failed to save session");
+        }
     }
-}
 
-impl Clone for SessionManager {
-    fn clone(&self) -> Self {
-        Self {
-            sessions: Arc::clone(&self.sessions),
+    /// Re-opens the saved sessions named in `aliases` (all when empty) in this
+    /// manager's namespace. Sessions that are already open are left alone.
+    pub async fn restore_sessions(&self, aliases: &[String]) -> Result<Vec<RestoredSession>> {
+        let store = self.store.as_ref().ok_or_else(|| {
+            SshLiaisonError::Config("Session persistence is disabled".to_string())
+        })?;
+        let saved = store
+            .load()?
+            .into_iter()
+            .filter(|s| aliases.is_empty() || aliases.contains(&s.alias));
+
+        let mut restoring = tokio::task::JoinSet::new();
+        for session in saved {
+            let manager = self.clone();
+            restoring.spawn(async move { manager.restore_session(session).await });
         }
+        let mut restored = Vec::new();
+        while let Some(joined) = restoring.join_next().await {
+            restored.push(joined?);
+        }
+        restored.sort_by(|a, b| a.alias.cmp(&b.alias));
+        Ok(restored)
+    }
+
+    async fn restore_session(&self, saved: SavedSession) -> RestoredSession {
+        let result = match saved.config {
+            _ if self.state(&saved.alias).is_ok() => Ok(RestoreStatus::Connected),
+            None => Ok(RestoreStatus::NeedsPassword),
+            Some(ref config) => {
+                let names: &[&str] = if saved.direct {
+                    &[&saved.hostname]
+                } else {
+                    &[&saved.alias, &saved.hostname]
+                };
+                let origin = ConnectOrigin::Config {
+                    config: config.clone(),
+                    passphrase: None,
+                    direct: saved.direct,
+                };
+                match self.check_host_allowed(names) {
+                    Ok(()) => self
+                        .connect_origin(&saved.alias, origin, None, saved.tags.clone())
+                        .await
+                        .map(|()| RestoreStatus::Restored),
+                    Err(e) => Err(e),
+                }
+            }
+        };
+        match result {
+            Ok(status) => tracing::info!(host = %saved.alias, %status, "Restoring session"),
+            Err(ref e) => {
+                tracing::warn!(host = %saved.alias, error = %e, "Failed to restore session")
+            }
+        }
+        RestoredSession {
+            status: match result {
+                Ok(status) => status,
+                Err(_) => RestoreStatus::Failed,
+            },
+            error: result.err().map(|e| format!("{:#}", e)),
+            alias: saved.alias,
+            user: saved.user,
+            hostname: saved.hostname,
+            port: saved.port,
+        }
+    }
+
+    /// Re-opens a session the way it was first opened, keeping its cached sudo
+    /// password. Retries with backoff; auth and config errors are not retried.
+    async fn reconnect(&self, host_alias: &str) -> Result<()> {
+        let (origin, sudo_password, tags) = {
+            let state = self.state(host_alias)?;
+            let state = state.lock().await;
+            (
+                state.origin.clone(),
+                state.sudo_password.clone(),
+                state.tags.clone(),
+            )
+        };
+
+        let mut last_error = None;
+        for attempt in 1..=self.reconnect.max_attempts {
+            sleep(self.reconnect.backoff(attempt)).await;
+            tracing::info!(host = %host_alias, attempt, "Reconnecting");
+            match self
+                .connect_origin(
+                    host_alias,
+                    origin.clone(),
+                    sudo_password.clone(),
+                    tags.clone(),
+                )
+                .await
+            {
+                Ok(()) => {
+                    tracing::info!(host = %host_alias, attempt, "Reconnected");
+                    return Ok(());
+                }
+                Err(e) => {
+                    tracing::warn!(host = %host_alias, attempt, error = %e, "Reconnect attempt failed");
+                    let permanent = matches!(
+                        SshLiaisonError::find(&e),
+                        Some(
+                            SshLiaisonError::Auth(_)
+                                | SshLiaisonError::Config(_)
+                                | SshLiaisonError::HostNotFound(_)
+                        )
+                    );
+                    last_error = Some(e);
+                    if permanent {
+                        break;
+                    }
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| anyhow::anyhow!("Reconnecting is disabled")))
+    }
+
+    /// Checks the shell and transport before use, reconnecting a dead session
+    /// when the reconnect policy allows it.
+    async fn ensure_alive(&self, host_alias: &str) -> Result<()> {
+        let (channel, session) = {
+            let state = self.state(host_alias)?;
+            let state = state.lock().await;
+            (Arc::clone(&state.channel), Arc::clone(&state.session))
+        };
+        // A busy default shell is running a command, so it isn't closed.
+        let closed = channel.try_lock().is_ok_and(|c| c.is_eof());
+        let alive = !closed && session.keepalive().await.is_ok();
+        if alive {
+            return Ok(());
+        }
+
+        tracing::warn!(host = %host_alias, "Session is no longer alive");
+        let lost = SshLiaisonError::ConnectionLost {
+            host: host_alias.to_string(),
+            reconnected: false,
+        };
+        if !self.reconnect.is_enabled() {
+            return Err(lost.into());
+        }
+        self.reconnect(host_alias).await.context(lost)
+    }
+
+    pub async fn is_connected(&self, host_alias: &str) -> bool {
+        self.table().contains_key(&self.key(host_alias))
+    }
+
+    /// Connects an alias without a session from `~/.ssh/config`, if
+    /// auto-connect is on. Returns whether it connected.
+    pub async fn auto_connect(&self, host_alias: &str) -> Result<bool> {
+        if !self.auto_connect || self.is_connected(host_alias).await {
+            return Ok(false);
+        }
+        tracing::info!(host = %host_alias, "Connecting on first command");
+        self.connect_by_alias(host_alias).await?;
+        Ok(true)
+    }
+
+    /// Runs `command` in the alias's persistent shell and returns its output.
+    ///
+    /// `sudo_password` (or the cached one) answers a sudo prompt; without one a
+    /// prompt fails with [`SudoError::PasswordRequired`].
+    pub async fn execute_command(
+        &self,
+        host_alias: &str,
+        command: &str,
+        sudo_password: Option<&str>,
+    ) -> Result<crate::ssh::channel::CommandOutput> {
+        self.execute_command_in(host_alias, None, command, sudo_password)
+            .await
+    }
+
+    /// [`Self::execute_command`] in the named channel (opened on first use),
+    /// so it neither waits for nor disturbs the default shell.
+    #[tracing::instrument(
+        name = "ssh.command",
+        skip_all,
+        fields(
+            host = %host_alias,
+            channel = channel.unwrap_or("default"),
+            exit_code = tracing::field::Empty,
+        )
+    )]
+    pub async fn execute_command_in(
+        &self,
+        host_alias: &str,
+        channel: Option<&str>,
+        command: &str,
+        sudo_password: Option<&str>,
+    ) -> Result<crate::ssh::channel::CommandOutput> {
+        self.ensure_alive(host_alias).await?;
+        let result = self
+            .execute_in_shell(host_alias, channel, command, sudo_password)
+            .await;
+
+        match result {
+            Err(e) if is_transport_error(&e) => {
+                tracing::warn!(host = %host_alias, error = %e, "Connection lost while running command");
+                let reconnected = self.reconnect.is_enabled()
+                    && match self.reconnect(host_alias).await {
+                        Ok(()) => true,
+                        Err(re) => {
+                            tracing::warn!(host = %host_alias, error = %re, "Reconnect failed");
+                            false
+                        }
+                    };
+                Err(e.context(SshLiaisonError::ConnectionLost {
+                    host: host_alias.to_string(),
+                    reconnected,
+                }))
+            }
+            Ok(output) => {
+                if let Some(code) = output.exit_code {
+                    tracing::Span::current().record("exit_code", code);
+                }
+                let succeeded = output.exit_code == Some(0);
+                // The session may have been closed meanwhile; nothing to track then.
+                let _ = self
+                    .update_context(host_alias, channel, |c| c.observe(command, succeeded))
+                    .await;
+                Ok(output)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Runs `command` with `stdin` as its standard input. The data is uploaded
+    /// to a private temp file first, since input typed into the PTY would be
+    /// echoed and can't be ended with EOF without ending the shell.
+    pub async fn execute_command_with_stdin(
+        &self,
+        host_alias: &str,
+        channel: Option<&str>,
+        command: &str,
+        stdin: &[u8],
+        sudo_password: Option<&str>,
+    ) -> Result<crate::ssh::channel::CommandOutput> {
+        self.require_posix_shell(host_alias, "stdin").await?;
+        let path = files::upload_path("stdin");
+        self.write_file_mode(host_alias, &path, stdin, 0o600)
+            .await
+            .context("Failed to upload stdin")?;
+
+        let redirected = self
+            .shell_kind(host_alias)
+            .await?
+            .with_stdin_from(command, &path);
+        let result = self
+            .execute_command_in(host_alias, channel, &redirected, sudo_password)
+            .await;
+
+        let cleanup = format!("rm -f {}", quote(&path));
+        if let Err(e) = self
+            .execute_command_in(host_alias, channel, &cleanup, None)
+            .await
+        {
+            tracing::warn!(host = %host_alias, path = %path, error = %e, "Failed to remove stdin file");
+        }
+        result
+    }
+
+    /// The shell detected on the session when it was opened.
+    pub async fn shell_kind(&self, host_alias: &str) -> Result<ShellKind> {
+        let state = self.state(host_alias)?;
+        let state = state.lock().await;
+        Ok(state.shell)
+    }
+
+    /// What is tracked about the shell's working directory and variables.
+    pub async fn shell_context(
+        &self,
+        host_alias: &str,
+        channel: Option<&str>,
+    ) -> Result<ShellContext> {
+        let state = self.state(host_alias)?;
+        let state = state.lock().await;
+        Ok(state
+            .contexts
+            .get(&channel.map(str::to_string))
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn update_context(
+        &self,
+        host_alias: &str,
+        channel: Option<&str>,
+        update: impl FnOnce(&mut ShellContext),
+    ) -> Result<()> {
+        let state = self.state(host_alias)?;
+        let mut state = state.lock().await;
+        update(
+            state
+                .contexts
+                .entry(channel.map(str::to_string))
+                .or_default(),
+        );
+        Ok(())
+    }
+
+    /// The shell's working directory: the tracked one, else asked with `pwd`.
+    pub async fn working_dir(&self, host_alias: &str, channel: Option<&str>) -> Result<String> {
+        if let Some(cwd) = self.shell_context(host_alias, channel).await?.cwd {
+            return Ok(cwd);
+        }
+        self.check_dir(host_alias, channel).await
+    }
+
+    /// The working directory to report after a command: the tracked one,
+    /// confirmed with `pwd` when it is unknown or hasn't been for
+    /// [`shell::CWD_CHECK_EVERY`] commands. `None` if that fails.
+    pub async fn current_dir(&self, host_alias: &str, channel: Option<&str>) -> Option<String> {
+        let context = self.shell_context(host_alias, channel).await.ok()?;
+        if let Some(cwd) = context.fresh_cwd() {
+            return Some(cwd.to_string());
+        }
+        match self.check_dir(host_alias, channel).await {
+            Ok(cwd) => Some(cwd),
+            Err(e) => {
+                tracing::debug!(host = %host_alias, error = %e, "Failed to check the working directory");
+                context.cwd
+            }
+        }
+    }
+
+    async fn check_dir(&self, host_alias: &str, channel: Option<&str>) -> Result<String> {
+        let kind = self.shell_kind(host_alias).await?;
+        let output = self
+            .execute_command_in(host_alias, channel, kind.pwd_command(), None)
+            .await?;
+        let cwd = printed_dir(&output).context("pwd printed nothing")?;
+        self.update_context(host_alias, channel, |c| {
+            c.cwd = Some(cwd.clone());
+            c.commands_since_check = 0;
+        })
+        .await?;
+        Ok(cwd)
+    }
+
+    /// `cd` to `path` in the shell; returns the directory it ended up in.
+    pub async fn change_dir(
+        &self,
+        host_alias: &str,
+        channel: Option<&str>,
+        path: &str,
+    ) -> Result<String> {
+        let kind = self.shell_kind(host_alias).await?;
+        let output = self
+            .execute_command_in(host_alias, channel, &kind.cd_command(path), None)
+            .await?;
+        let cwd = printed_dir(&output).filter(|_| output.exit_code == Some(0));
+        let Some(cwd) = cwd else {
+            anyhow::bail!(
+                "Cannot change to {}: {}",
+                path,
+                output.combined_with_stderr_label().trim()
+            );
+        };
+        self.update_context(host_alias, channel, |c| {
+            c.cwd = Some(cwd.clone());
+            c.commands_since_check = 0;
+        })
+        .await?;
+        Ok(cwd)
+    }
+
+    /// Exports `set` and unsets `unset` in the shell, tracking both.
+    pub async fn set_env(
+        &self,
+        host_alias: &str,
+        channel: Option<&str>,
+        set: &BTreeMap<String, String>,
+        unset: &[String],
+    ) -> Result<()> {
+        check_env_names(set.keys().chain(unset))?;
+        let kind = self.shell_kind(host_alias).await?;
+        let commands: Vec<String> = set
+            .iter()
+            .map(|(name, value)| kind.set_env_command(name, value))
+            .chain(unset.iter().map(|name| kind.unset_env_command(name)))
+            .collect();
+        if commands.is_empty() {
+            return Ok(());
+        }
+        let command = commands.join(&format!("{} ", kind.separator()));
+        let output = self
+            .execute_command_in(host_alias, channel, &command, None)
+            .await?;
+        if output.exit_code != Some(0) {
+            anyhow::bail!(
+                "Setting variables failed: {}",
+                output.combined_with_stderr_label().trim()
+            );
+        }
+        self.update_context(host_alias, channel, |c| {
+            c.env
+                .extend(set.iter().map(|(k, v)| (k.clone(), v.clone())));
+            for name in unset {
+                c.env.remove(name);
+            }
+        })
+        .await
+    }
+
+    /// Current values of `names` in the shell; those that aren't set are absent.
+    pub async fn env_values(
+        &self,
+        host_alias: &str,
+        channel: Option<&str>,
+        names: &[String],
+    ) -> Result<BTreeMap<String, String>> {
+        check_env_names(names)?;
+        if names.is_empty() {
+            return Ok(BTreeMap::new());
+        }
+        let kind = self.shell_kind(host_alias).await?;
+        let output = self
+            .execute_command_in(host_alias, channel, &kind.print_env_command(names), None)
+            .await?;
+        Ok(shell::parse_env_output(&output.stdout, names))
+    }
+
+    /// Whether the default shell is running a command right now.
+    pub async fn is_busy(&self, host_alias: &str) -> Result<bool> {
+        let state = self.state(host_alias)?;
+        let state = state.lock().await;
+        Ok(state.channel.try_lock().is_err())
+    }
+
+    /// The default shell, or the named channel.
+    async fn channel(&self, host_alias: &str, name: Option<&str>) -> Result<SharedChannel> {
+        let Some(name) = name else {
+            return self.default_channel(host_alias).await;
+        };
+        self.named_channel(host_alias, name).await
+    }
+
+    /// The default shell, replaced first if an interrupt left it out of sync.
+    async fn default_channel(&self, host_alias: &str) -> Result<SharedChannel> {
+        let (channel, session) = {
+            let state = self.state(host_alias)?;
+            let mut state = state.lock().await;
+            state.last_used = Instant::now();
+            (Arc::clone(&state.channel), Arc::clone(&state.session))
+        };
+        if !channel.try_lock().is_ok_and(|c| c.is_desynced()) {
+            return Ok(channel);
+        }
+        tracing::warn!(host = %host_alias, "Shell out of sync, reopening");
+        self.reopen_default_channel(host_alias, &channel, session)
+            .await
+    }
+
+    /// Replaces the default shell `old` with a new one.
+    async fn reopen_default_channel(
+        &self,
+        host_alias: &str,
+        old: &SharedChannel,
+        session: Arc<dyn SshSession>,
+    ) -> Result<SharedChannel> {
+        let fresh = open_shell(
+            &*session,
+            self.channel_options_for(host_alias),
+            self.recorder(host_alias, None)?,
+        )
+        .await?;
+        let state = self.state(host_alias)?;
+        let mut state = state.lock().await;
+        // Another command may have replaced it meanwhile.
+        if Arc::ptr_eq(&state.channel, old) {
+            state.channel = Arc::new(Mutex::new(fresh));
+            state.contexts.remove(&None);
+        }
+        Ok(Arc::clone(&state.channel))
+    }
+
+    /// Opens the named channel on first use, and again if the remote side
+    /// closed it or it fell out of sync.
+    async fn named_channel(&self, host_alias: &str, name: &str) -> Result<SharedChannel> {
+        let session = {
+            let state = self.state(host_alias)?;
+            let mut state = state.lock().await;
+            state.last_used = Instant::now();
+            if let Some(channel) = state.named_channels.get(name) {
+                if !channel
+                    .try_lock()
+                    .is_ok_and(|c| c.is_eof() || c.is_desynced())
+                {
+                    return Ok(Arc::clone(channel));
+                }
+                tracing::debug!(host = %host_alias, channel = %name, "Channel closed or out of sync, reopening");
+                state.named_channels.remove(name);
+                state.contexts.remove(&Some(name.to_string()));
+            }
+            if state.named_channels.len() + 1 >= MAX_CHANNELS_PER_SESSION {
+                return Err(SshLiaisonError::Channel(format!(
+                    "{} already has {} channels open",
+                    host_alias, MAX_CHANNELS_PER_SESSION
+                ))
+                .into());
+            }
+            Arc::clone(&state.session)
+        };
+
+        let channel = open_shell(
+            &*session,
+            self.channel_options_for(host_alias),
+            self.recorder(host_alias, Some(name))?,
+        )
+        .await?;
+        tracing::debug!(host = %host_alias, channel = %name, "Opened channel");
+
+        let state = self.state(host_alias)?;
+        let mut state = state.lock().await;
+        let channel = state
+            .named_channels
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(Mutex::new(channel)));
+        Ok(Arc::clone(channel))
+    }
+
+    /// Uploads go to `/tmp` and are cleaned up with `rm`, which a Windows host doesn't have.
+    async fn require_posix_shell(&self, host_alias: &str, what: &str) -> Result<()> {
+        let kind = self.shell_kind(host_alias).await?;
+        if kind.is_windows() {
+            anyhow::bail!("Sending {} is not supported with a {} shell", what, kind);
+        }
+        Ok(())
+    }
+
+    async fn execute_in_shell(
+        &self,
+        host_alias: &str,
+        channel_name: Option<&str>,
+        command: &str,
+        sudo_password: Option<&str>,
+    ) -> Result<crate::ssh::channel::CommandOutput> {
+        let channel = self.channel(host_alias, channel_name).await?;
+        let (kind, cached) = {
+            let state = self.state(host_alias)?;
+            let state = state.lock().await;
+            (state.shell, state.sudo_password.clone())
+        };
+
+        let original = command;
+        if !kind.is_windows()
+            && let Some(interactive) = interactive::detect(command)
+        {
+            tracing::info!(host = %host_alias, program = %interactive.program, "Refusing interactive command");
+            return Err(SshLiaisonError::Interactive(interactive).into());
+        }
+        let encoded;
+        let command = match shell::needs_encoding(command)
+            .then(|| kind.encode(command))
+            .flatten()
+        {
+            Some(line) => {
+                tracing::debug!(host = %host_alias, "Sending command base64-encoded");
+                encoded = line;
+                &encoded
+            }
+            None => command,
+        };
+
+        let password = sudo_password.or(cached.as_deref());
+        let mut shell = channel.lock().await;
+        // Registered once the shell is ours, so a cancel can't hit the previous command.
+        let running = self
+            .running
+            .start(self.namespace(), host_alias, channel_name, original);
+        let result = shell
+            .execute_command(command, password, running.token())
+            .await;
+        drop(shell);
+        let result = match result {
+            Ok(output) => {
+                running.finish(&output);
+                if running.is_cancelled() && output.exit_code.is_none() {
+                    Err(SshLiaisonError::Cancelled(running.id()).into())
+                } else {
+                    Ok(output)
+                }
+            }
+            // Name the program the client sent, not the base64 wrapper.
+            Err(mut e) => {
+                if let Some(SshLiaisonError::Interactive(found)) = e.downcast_mut() {
+                    found.program = interactive::program(original);
+                }
+                Err(e)
+            }
+        };
+
+        if sudo_password.is_none()
+            && cached.is_some()
+            && matches!(
+                result
+                    .as_ref()
+                    .err()
+                    .and_then(|e| e.downcast_ref::<SudoError>()),
+                Some(SudoError::PasswordRejected)
+            )
+        {
+            tracing::debug!(host = %host_alias, "Cached sudo password rejected, clearing");
+            if let Ok(state) = self.state(host_alias) {
+                state.lock().await.sudo_password = None;
+            }
+        }
+
+        result
+    }
+
+    /// Remembers a sudo password for later commands on this session.
+    pub async fn cache_sudo_password(&self, host_alias: &str, password: &str) -> Result<()> {
+        let state = self.state(host_alias)?;
+        let mut state = state.lock().await;
+        state.sudo_password = Some(password.to_string());
+        Ok(())
+    }
+
+    /// Brings a shell back to a clean prompt: interrupts what runs in it and
+    /// resynchronizes, or opens a new shell if that fails or `reopen` is set.
+    pub async fn reset_shell(
+        &self,
+        host_alias: &str,
+        channel_name: Option<&str>,
+        reopen: bool,
+    ) -> Result<ShellReset> {
+        self.ensure_alive(host_alias).await?;
+        let mut cancelled = Vec::new();
+        let running = self
+            .running_commands()
+            .into_iter()
+            .filter(|r| r.host == host_alias && r.channel.as_deref() == channel_name);
+        for command in running {
+            match self.running.cancel(self.namespace(), command.id).await {
+                Ok(_) => cancelled.push(command.id),
+                Err(e) => {
+                    tracing::debug!(id = command.id, error = %e, "Command ended before reset")
+                }
+            }
+        }
+
+        let channel = self.channel(host_alias, channel_name).await?;
+        let resynced = !reopen && {
+            let mut shell = channel.lock().await;
+            shell.interrupt().await.unwrap_or_else(|e| {
+                tracing::warn!(host = %host_alias, error = %e, "Interrupt failed");
+                false
+            })
+        };
+        if !resynced {
+            match channel_name {
+                None => {
+                    let session = self.shared_session(host_alias).await?;
+                    self.reopen_default_channel(host_alias, &channel, session)
+                        .await?;
+                }
+                Some(name) => {
+                    {
+                        let state = self.state(host_alias)?;
+                        let mut state = state.lock().await;
+                        if state
+                            .named_channels
+                            .get(name)
+                            .is_some_and(|c| Arc::ptr_eq(c, &channel))
+                        {
+                            state.named_channels.remove(name);
+                            state.contexts.remove(&Some(name.to_string()));
+                        }
+                    }
+                    self.named_channel(host_alias, name).await?;
+                }
+            }
+        }
+        tracing::info!(
+            host = %host_alias,
+            channel = channel_name.unwrap_or("default"),
+            reopened = !resynced,
+            "Shell reset"
+        );
+        Ok(ShellReset {
+            cancelled,
+            reopened: !resynced,
+        })
+    }
+
+    pub async fn execute_command_streaming(
+        &self,
+        host_alias: &str,
+        command: &str,
+    ) -> Result<String> {
+        self.ensure_alive(host_alias).await?;
+        let channel = self.channel(host_alias, None).await?;
+        let mut channel = channel.lock().await;
+        channel.execute_command_streaming(command).await
+    }
+
+    /// Reads part of a remote file over SFTP, or base64 over the shell when the
+    /// server has no SFTP subsystem.
+    pub async fn read_file(
+        &self,
+        host_alias: &str,
+        path: &str,
+        range: ReadRange,
+    ) -> Result<FileContent> {
+        self.ensure_alive(host_alias).await?;
+        let session = self.shared_session(host_alias).await?;
+
+        let content = match session.sftp().await {
+            Ok(sftp) => {
+                let (data, truncated) =
+                    files::sftp_read(&*sftp, path, range.offset, range.limit()).await?;
+                FileContent {
+                    data,
+                    truncated,
+                    via: Transfer::Sftp,
+                }
+            }
+            Err(e) => {
+                tracing::debug!(host = %host_alias, error = %e, "SFTP unavailable, reading via shell");
+                let limit = files::shell_read_limit(range.limit());
+                let command = files::shell_read_command(path, range.offset, limit);
+                let output = self.execute_command(host_alias, &command, None).await?;
+                if output.exit_code != Some(0) {
+                    anyhow::bail!("Failed to read {}: {}", path, output.stdout.trim());
+                }
+                let mut data = files::decode_base64_output(&output.stdout)?;
+                let truncated = data.len() > limit;
+                data.truncate(limit);
+                FileContent {
+                    data,
+                    truncated,
+                    via: Transfer::Shell,
+                }
+            }
+        };
+        Ok(files::apply_lines(content, &range))
+    }
+
+    /// Replaces a remote file atomically: the data goes to a temp file in the
+    /// same directory, which is then renamed over `path`.
+    pub async fn write_file(&self, host_alias: &str, path: &str, data: &[u8]) -> Result<Transfer> {
+        self.write_file_mode(host_alias, path, data, files::NEW_FILE_MODE)
+            .await
+    }
+
+    async fn write_file_mode(
+        &self,
+        host_alias: &str,
+        path: &str,
+        data: &[u8],
+        new_mode: u32,
+    ) -> Result<Transfer> {
+        self.ensure_alive(host_alias).await?;
+        let session = self.shared_session(host_alias).await?;
+        let tmp = files::temp_path(path);
+
+        match session.sftp().await {
+            Ok(sftp) => {
+                if !files::sftp_write(&*sftp, path, &tmp, data, new_mode).await? {
+                    self.move_over(host_alias, &*sftp, &tmp, path).await?;
+                }
+                Ok(Transfer::Sftp)
+            }
+            Err(e) => {
+                tracing::debug!(host = %host_alias, error = %e, "SFTP unavailable, writing via shell");
+                for command in files::shell_write_commands(path, &tmp, data, new_mode) {
+                    let output = self.execute_command(host_alias, &command, None).await?;
+                    if output.exit_code != Some(0) {
+                        let cleanup = files::shell_cleanup_command(&tmp);
+                        let _ = self.execute_command(host_alias, &cleanup, None).await;
+                        anyhow::bail!("Failed to write {}: {}", path, output.stdout.trim());
+                    }
+                }
+                Ok(Transfer::Shell)
+            }
+        }
+    }
+
+    /// `mv` over the shell for a temp file the SFTP server wouldn't rename
+    /// over `path`; the temp file is removed if that fails too.
+    async fn move_over(
+        &self,
+        host_alias: &str,
+        sftp: &dyn Sftp,
+        tmp: &str,
+        path: &str,
+    ) -> Result<()> {
+        let command = format!("mv -f {} {}", quote(tmp), quote(path));
+        let output = self.execute_command(host_alias, &command, None).await?;
+        if output.exit_code != Some(0) {
+            let _ = sftp.unlink(tmp).await;
+            anyhow::bail!("Failed to replace {}: {}", path, output.stdout.trim());
+        }
+        Ok(())
+    }
+
+    async fn sftp_for(&self, host_alias: &str) -> Result<Box<dyn Sftp>> {
+        self.ensure_alive(host_alias).await?;
+        let session = self.shared_session(host_alias).await?;
+        session
+            .sftp()
+            .await
+            .with_context(|| format!("SFTP is not available on '{}'", host_alias))
+    }
+
+    /// Streams a file from one session to another through this process over
+    /// SFTP, so the hosts need no access to each other. The data lands in a
+    /// temp file next to the target, is checked against the source's SHA-256
+    /// when `verify` is set, and only then replaces the target. A target that
+    /// is a directory gets the source's file name.
+    pub async fn copy_between_hosts(
+        &self,
+        src_host: &str,
+        src_path: &str,
+        dst_host: &str,
+        dst_path: &str,
+        verify: bool,
+        pacing: &Pacing,
+    ) -> Result<CopyReport> {
+        let src_sftp = self.sftp_for(src_host).await?;
+        let dst_sftp = self.sftp_for(dst_host).await?;
+
+        let source = src_sftp
+            .stat(src_path)
+            .await
+            .with_context(|| format!("Failed to stat {} on '{}'", src_path, src_host))?;
+        if !source.is_file() {
+            anyhow::bail!("{} on '{}' is not a regular file", src_path, src_host);
+        }
+        let destination = match dst_sftp.stat(dst_path).await {
+            Ok(stat) if stat.is_dir() => {
+                transfer::join_remote(dst_path, transfer::remote_file_name(src_path))
+            }
+            _ => dst_path.to_string(),
+        };
+        // Replacing a file keeps its permissions; a new one gets the source's.
+        let mode = match dst_sftp.stat(&destination).await {
+            Ok(stat) => stat.perm,
+            Err(_) => source.perm,
+        }
+        .map_or(files::NEW_FILE_MODE, |perm| perm & 0o7777);
+
+        let tmp = files::temp_path(&destination);
+        let mut reader = src_sftp
+            .open(src_path, OpenMode::Read, 0)
+            .await
+            .with_context(|| format!("Failed to open {} on '{}'", src_path, src_host))?;
+        let mut writer = dst_sftp
+            .open(&tmp, OpenMode::CreateNew, mode)
+            .await
+            .with_context(|| format!("Failed to create {} on '{}'", tmp, dst_host))?;
+
+        let mut progress = Progress::new(
+            format!("{}:{} -> {}:{}", src_host, src_path, dst_host, destination),
+            source.size,
+            &match source.size {
+                Some(size) => self.paced(pacing).with_total(size),
+                None => self.paced(pacing),
+            },
+        );
+        let copied = async {
+            transfer::copy_stream(&mut reader, &mut writer, &mut progress).await?;
+            writer.shutdown().await
+        }
+        .await;
+        drop(writer);
+        if let Err(e) = copied {
+            let _ = dst_sftp.unlink(&tmp).await;
+            return Err(anyhow::Error::from(e).context(format!(
+                "Failed to copy {} from '{}' to '{}'",
+                src_path, src_host, dst_host
+            )));
+        }
+
+        let sha256 = if verify {
+            match self.verify_copy(src_host, src_path, dst_host, &tmp).await {
+                Ok(sum) => Some(sum),
+                Err(e) => {
+                    let _ = dst_sftp.unlink(&tmp).await;
+                    return Err(e);
+                }
+            }
+        } else {
+            None
+        };
+        if !files::sftp_rename_over(&*dst_sftp, &tmp, &destination).await {
+            self.move_over(dst_host, &*dst_sftp, &tmp, &destination)
+                .await?;
+        }
+
+        tracing::info!(
+            from = %src_host,
+            to = %dst_host,
+            bytes = progress.bytes(),
+            elapsed_ms = progress.elapsed_ms(),
+            "Copied file between hosts"
+        );
+        Ok(CopyReport {
+            source: src_path.to_string(),
+            destination,
+            bytes: progress.bytes(),
+            duration_ms: progress.elapsed_ms(),
+            sha256,
+        })
+    }
+
+    /// Copies a local file or directory tree to the host over SFTP. Resumed
+    /// transfers are checked against SHA-256 on both ends.
+    pub async fn upload(
+        &self,
+        host_alias: &str,
+        local: &Path,
+        remote: &str,
+        filter: &PathFilter,
+        options: &TransferOptions,
+    ) -> Result<TransferSummary> {
+        let sftp = self.sftp_for(host_alias).await?;
+        let options = TransferOptions {
+            pacing: self.paced(&options.pacing),
+            ..options.clone()
+        };
+        let mut summary = transfer::upload(&*sftp, local, remote, filter, &options).await?;
+        if options.resume {
+            self.verify_transfer(host_alias, &mut summary, true).await?;
+        }
+        tracing::info!(
+            host = %host_alias,
+            files = summary.files_copied,
+            failed = summary.files_failed,
+            bytes = summary.bytes,
+            "Upload finished"
+        );
+        Ok(summary)
+    }
+
+    /// Copies a remote file or directory tree from the host over SFTP, with
+    /// the same checks as [`Self::upload`].
+    pub async fn download(
+        &self,
+        host_alias: &str,
+        remote: &str,
+        local: &Path,
+        filter: &PathFilter,
+        options: &TransferOptions,
+    ) -> Result<TransferSummary> {
+        let sftp = self.sftp_for(host_alias).await?;
+        let options = TransferOptions {
+            pacing: self.paced(&options.pacing),
+            ..options.clone()
+        };
+        let mut summary = transfer::download(&*sftp, remote, local, filter, &options).await?;
+        if options.resume {
+            self.verify_transfer(host_alias, &mut summary, false)
+                .await?;
+        }
+        tracing::info!(
+            host = %host_alias,
+            files = summary.files_copied,
+            failed = summary.files_failed,
+            bytes = summary.bytes,
+            "Download finished"
+        );
+        Ok(summary)
+    }
+
+    /// Compares the SHA-256 of every copied file on both ends. In a directory
+    /// transfer mismatches are marked in the summary; for a single file the
+    /// mismatch is the error.
+    async fn verify_transfer(
+        &self,
+        host_alias: &str,
+        summary: &mut TransferSummary,
+        upload: bool,
+    ) -> Result<()> {
+        let copied = std::mem::take(&mut summary.copied);
+        for batch in copied.chunks(VERIFY_BATCH) {
+            let paths: Vec<String> = batch.iter().map(|f| f.remote.clone()).collect();
+            let remote_sums = self.sha256_files(host_alias, &paths).await?;
+            for (file, remote_sum) in batch.iter().zip(remote_sums) {
+                let remote_sum = remote_sum.checksum.ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Failed to checksum {}: {}",
+                        file.remote,
+                        remote_sum.error.unwrap_or_default()
+                    )
+                });
+                let local_sum = transfer::local_sha256(&file.local).await;
+                if summary.single_file
+                    && let (Ok(local_sum), Ok(remote_sum)) = (&local_sum, &remote_sum)
+                    && local_sum != remote_sum
+                {
+                    let (expected, actual) = if upload {
+                        (local_sum, remote_sum)
+                    } else {
+                        (remote_sum, local_sum)
+                    };
+                    return Err(SshLiaisonError::ChecksumMismatch {
+                        path: file.relative.clone(),
+                        expected: expected.clone(),
+                        actual: actual.clone(),
+                    }
+                    .into());
+                }
+                let (source, copy) = if upload {
+                    (local_sum, remote_sum)
+                } else {
+                    (remote_sum, local_sum)
+                };
+                summary.verified(&file.relative, source, copy);
+            }
+        }
+        summary.copied = copied;
+        Ok(())
+    }
+
+    /// SHA-256 of `src_path` on one host and `dst_path` on the other, which
+    /// must match.
+    async fn verify_copy(
+        &self,
+        src_host: &str,
+        src_path: &str,
+        dst_host: &str,
+        dst_path: &str,
+    ) -> Result<String> {
+        let (source, copy) = tokio::try_join!(
+            self.sha256(src_host, src_path),
+            self.sha256(dst_host, dst_path)
+        )?;
+        if source != copy {
+            return Err(SshLiaisonError::ChecksumMismatch {
+                path: format!("{}:{} -> {}:{}", src_host, src_path, dst_host, dst_path),
+                expected: source,
+                actual: copy,
+            }
+            .into());
+        }
+        Ok(source)
+    }
+
+    async fn sha256_files(
+        &self,
+        host_alias: &str,
+        paths: &[String],
+    ) -> Result<Vec<files::FileChecksum>> {
+        let command = files::checksum_command(files::ChecksumAlgorithm::Sha256, paths);
+        let output = self.execute_command(host_alias, &command, None).await?;
+        Ok(files::parse_checksums(
+            files::ChecksumAlgorithm::Sha256,
+            &output.stdout,
+            paths,
+        ))
+    }
+
+    async fn sha256(&self, host_alias: &str, path: &str) -> Result<String> {
+        let sum = self
+            .sha256_files(host_alias, &[path.to_string()])
+            .await?
+            .remove(0);
+        sum.checksum.ok_or_else(|| {
+            anyhow::anyhow!(
+                "Failed to checksum {} on '{}': {}",
+                path,
+                host_alias,
+                sum.error.unwrap_or_default()
+            )
+        })
+    }
+
+    /// Appends `key` to the login user's `authorized_keys` unless it is
+    /// already there, fixing up permissions like `ssh-copy-id`. Returns
+    /// whether the key was added.
+    pub async fn install_public_key(
+        &self,
+        host_alias: &str,
+        key: &keys::PublicKey,
+    ) -> Result<bool> {
+        self.require_posix_shell(host_alias, "public keys").await?;
+        let output = self
+            .execute_command(host_alias, &keys::install_key_command(key), None)
+            .await?;
+        let added = keys::parse_install_output(&output.stdout).with_context(|| {
+            format!(
+                "Failed to update ~/.ssh/authorized_keys on '{}': {}",
+                host_alias,
+                output.stdout.trim()
+            )
+        })?;
+        tracing::info!(
+            host = %host_alias,
+            key_type = %key.key_type,
+            added,
+            "Installed public key"
+        );
+        Ok(added)
+    }
+
+    /// Uploads `script` to a private temp file, runs it with `interpreter` and
+    /// removes it again. Unlike `execute_command`, the script may contain
+    /// heredocs, quotes and any number of lines.
+    pub async fn run_script(
+        &self,
+        host_alias: &str,
+        interpreter: &str,
+        script: &str,
+    ) -> Result<crate::ssh::channel::CommandOutput> {
+        self.require_posix_shell(host_alias, "scripts").await?;
+        let path = files::upload_path("script");
+        self.write_file_mode(host_alias, &path, script.as_bytes(), 0o700)
+            .await
+            .context("Failed to upload script")?;
+
+        let command = format!("{} {}", interpreter, quote(&path));
+        let result = self.execute_command(host_alias, &command, None).await;
+
+        let cleanup = format!("rm -f {}", quote(&path));
+        if let Err(e) = self.execute_command(host_alias, &cleanup, None).await {
+            tracing::warn!(host = %host_alias, path = %path, error = %e, "Failed to remove script");
+        }
+        result
+    }
+
+    /// Lists a remote directory over SFTP, or with GNU `find`/`stat` when the
+    /// server has no SFTP subsystem.
+    pub async fn list_dir(
+        &self,
+        host_alias: &str,
+        path: &str,
+    ) -> Result<(Vec<DirEntry>, Transfer)> {
+        self.ensure_alive(host_alias).await?;
+        let session = self.shared_session(host_alias).await?;
+
+        match session.sftp().await {
+            Ok(sftp) => Ok((files::sftp_list_dir(&*sftp, path).await?, Transfer::Sftp)),
+            Err(e) => {
+                tracing::debug!(host = %host_alias, error = %e, "SFTP unavailable, listing via shell");
+                let command = files::shell_list_dir_command(path);
+                let output = self.execute_command(host_alias, &command, None).await?;
+                if output.exit_code != Some(0) {
+                    anyhow::bail!("Failed to list {}: {}", path, output.stdout.trim());
+                }
+                Ok((files::parse_shell_listing(&output.stdout), Transfer::Shell))
+            }
+        }
+    }
+
+    /// Opens a second interactive shell on the session with a PTY of `cols`x`rows`,
+    /// for passing a local terminal through. The command shell is left untouched.
+    pub async fn open_pty_shell(
+        &self,
+        host_alias: &str,
+        term: &str,
+        cols: u32,
+        rows: u32,
+    ) -> Result<Box<dyn SshChannel>> {
+        self.ensure_alive(host_alias).await?;
+        let session = self.shared_session(host_alias).await?;
+        session.open_shell(term, cols, rows).await
+    }
+
+    async fn shared_session(&self, host_alias: &str) -> Result<Arc<dyn SshSession>> {
+        let state = self.state(host_alias)?;
+        let mut state = state.lock().await;
+        state.last_used = Instant::now();
+        Ok(Arc::clone(&state.session))
+    }
+
+    /// Forwards `127.0.0.1:local_port` to `remote_host:remote_port` through the session.
+    pub async fn open_tunnel(
+        &self,
+        host_alias: &str,
+        local_port: u16,
+        remote_host: &str,
+        remote_port: u16,
+    ) -> Result<TunnelInfo> {
+        let session = self.shared_session(host_alias).await?;
+        self.tunnels
+            .open(
+                self.namespace(),
+                host_alias,
+                session,
+                local_port,
+                remote_host,
+                remote_port,
+            )
+            .await
+    }
+
+    /// Opens a SOCKS5 proxy on `127.0.0.1:local_port` through the session.
+    pub async fn open_socks_proxy(
+        &self,
+        host_alias: &str,
+        local_port: u16,
+        options: SocksOptions,
+    ) -> Result<TunnelInfo> {
+        let session = self.shared_session(host_alias).await?;
+        self.tunnels
+            .open_socks(self.namespace(), host_alias, session, local_port, options)
+            .await
+    }
+
+    pub async fn list_tunnels(&self) -> Vec<TunnelInfo> {
+        self.tunnels.list(self.namespace()).await
+    }
+
+    pub async fn close_tunnel(&self, tunnel_id: &str) -> Result<TunnelInfo> {
+        self.tunnels.close(self.namespace(), tunnel_id).await
+    }
+
+    /// Follows `path` with `tail -F`, keeping the lines matching `pattern`.
+    pub async fn watch_log(
+        &self,
+        host_alias: &str,
+        path: &str,
+        pattern: regex::Regex,
+        notify: WatchNotify,
+    ) -> Result<WatchInfo> {
+        let kind = self.shell_kind(host_alias).await?;
+        if kind.is_windows() {
+            anyhow::bail!("Watching logs needs tail, which a {} host lacks", kind);
+        }
+        let session = self.shared_session(host_alias).await?;
+        self.watches
+            .open(
+                self.namespace(),
+                host_alias,
+                &*session,
+                path,
+                pattern,
+                notify,
+            )
+            .await
+    }
+
+    pub async fn list_watches(&self) -> Vec<WatchInfo> {
+        self.watches.list(self.namespace()).await
+    }
+
+    pub async fn watch_lines(&self, watch_id: &str) -> Result<(WatchInfo, Vec<String>)> {
+        self.watches.lines(self.namespace(), watch_id).await
+    }
+
+    pub async fn set_watch_subscribed(&self, watch_id: &str, subscribed: bool) -> bool {
+        self.watches
+            .set_subscribed(self.namespace(), watch_id, subscribed)
+            .await
+    }
+
+    /// Commands running in this client's sessions.
+    pub fn running_commands(&self) -> Vec<RunningInfo> {
+        self.running.list(self.namespace())
+    }
+
+    /// Sends Ctrl-C to a running command and returns what it printed so far.
+    pub async fn cancel_command(
+        &self,
+        id: u64,
+    ) -> Result<(RunningInfo, crate::ssh::channel::CommandOutput)> {
+        self.running.cancel(self.namespace(), id).await
+    }
+
+    pub async fn close_watch(&self, watch_id: &str) -> Result<(WatchInfo, Vec<String>)> {
+        self.watches.close(self.namespace(), watch_id).await
+    }
+
+    /// Runs `spec.command` on its host every `spec.interval`, in the
+    /// schedule channel.
+    pub async fn schedule_command(
+        &self,
+        spec: ScheduleSpec,
+        notify: ScheduleNotify,
+    ) -> Result<ScheduleInfo> {
+        self.state(&spec.host)?;
+        let manager = self.clone();
+        let host = spec.host.clone();
+        let command = spec.command.clone();
+        let run: ScheduleRun = Arc::new(move || {
+            let manager = manager.clone();
+            let host = host.clone();
+            let command = command.clone();
+            Box::pin(async move {
+                manager
+                    .execute_command_in(&host, Some(SCHEDULE_CHANNEL), &command, None)
+                    .await
+            })
+        });
+        self.schedules
+            .open(self.namespace(), spec, run, notify)
+            .await
+    }
+
+    pub async fn list_schedules(&self) -> Vec<ScheduleInfo> {
+        self.schedules.list(self.namespace()).await
+    }
+
+    pub async fn schedule_report(&self, schedule_id: &str) -> Result<ScheduleReport> {
+        self.schedules.report(self.namespace(), schedule_id).await
+    }
+
+    pub async fn set_schedule_subscribed(&self, schedule_id: &str, subscribed: bool) -> bool {
+        self.schedules
+            .set_subscribed(self.namespace(), schedule_id, subscribed)
+            .await
+    }
+
+    pub async fn cancel_schedule(&self, schedule_id: &str) -> Result<ScheduleReport> {
+        self.schedules.close(self.namespace(), schedule_id).await
+    }
+
+    /// Closes the session's tunnels, watches, schedules, shell, and
+    /// connection. Unknown aliases are a no-op.
+    pub async fn disconnect(&self, host_alias: &str) -> Result<()> {
+        self.tunnels
+            .close_for_host(self.namespace(), host_alias)
+            .await;
+        self.watches
+            .close_for_host(self.namespace(), host_alias)
+            .await;
+        self.schedules
+            .close_for_host(self.namespace(), host_alias)
+            .await;
+        if let Some(store) = &self.store
+            && let Err(e) = store.forget(&[host_alias.to_string()])
+        {
+            tracing::warn!(host = %host_alias, error = %e, "Failed to forget saved session");
+        }
+        let removed = self.table().remove(&self.key(host_alias));
+        if let Some(state) = removed {
+            close_state(&state).await?;
+        }
+        Ok(())
+    }
+
+    /// Closes this namespace's sessions with their tunnels, watches and
+    /// schedules, and forgets its client name. Used when an HTTP client's MCP
+    /// session ends; saved sessions are kept for `restore_sessions`.
+    pub async fn close_namespace(&self) -> usize {
+        let namespace = self.namespace();
+        self.tunnels.close_owner(namespace).await;
+        self.watches.close_owner(namespace).await;
+        self.schedules.close_owner(namespace).await;
+        let removed: Vec<(String, SharedState)> = {
+            let mut table = self.table();
+            let keys: Vec<SessionKey> = table
+                .keys()
+                .filter(|(owner, _)| *owner == self.namespace)
+                .cloned()
+                .collect();
+            keys.into_iter()
+                .filter_map(|key| Some((key.1.clone(), table.remove(&key)?)))
+                .collect()
+        };
+        let owned = |(owner, _): &SessionKey| *owner == self.namespace;
+        self.channel_overrides
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|key, _| !owned(key));
+        self.record_overrides
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .retain(|key, _| !owned(key));
+        if let Some(namespace) = namespace {
+            self.client_names
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .remove(namespace);
+        }
+
+        let count = removed.len();
+        for (alias, state) in removed {
+            if let Err(e) = close_state(&state).await {
+                tracing::warn!(host = %alias, error = %e, "Failed to close session");
+            }
+        }
+        count
+    }
+
+    /// Closes every session in the shared table, all namespaces included, and
+    /// returns how many there were. Used when the server shuts down.
+    pub async fn close_all(&self) -> usize {
+        self.tunnels.close_all().await;
+        self.watches.close_all().await;
+        self.schedules.close_all().await;
+        let removed: Vec<(SessionKey, SharedState)> = self.table().drain().collect();
+        let count = removed.len();
+
+        let mut closing = tokio::task::JoinSet::new();
+        for ((_, alias), state) in removed {
+            closing.spawn(async move {
+                match close_state(&state).await {
+                    Ok(()) => tracing::debug!(host = %alias, "Session closed"),
+                    Err(e) => tracing::warn!(host = %alias, error = %e, "Failed to close session"),
+                }
+            });
+        }
+        while closing.join_next().await.is_some() {}
+        count
+    }
+
+    /// Rejects tags other than letters, digits, `-`, `_` and `.`.
+    pub fn check_tags(tags: &[String]) -> Result<()> {
+        let valid = |tag: &String| {
+            !tag.is_empty()
+                && tag
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        };
+        match tags.iter().find(|tag| !valid(tag)) {
+            Some(tag) => Err(SshLiaisonError::Config(format!("Invalid tag: '{}'", tag)).into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Adds client-supplied tags to a connected session.
+    pub async fn add_tags(&self, host_alias: &str, tags: &[String]) -> Result<()> {
+        Self::check_tags(tags)?;
+        let state = self.state(host_alias)?;
+        state.lock().await.tags.extend(tags.iter().cloned());
+        tracing::debug!(host = %host_alias, ?tags, "Tagged session");
+        self.remember(host_alias).await;
+        Ok(())
+    }
+
+    /// Config and session tags of `host_alias`; only the config ones, matched
+    /// on the alias and the hostname `~/.ssh/config` gives it, when it isn't
+    /// connected.
+    pub async fn tags(&self, host_alias: &str) -> BTreeSet<String> {
+        match self.state(host_alias) {
+            Ok(state) => self.tags_of(host_alias, &*state.lock().await),
+            Err(_) => {
+                let hostname = parse_ssh_config(host_alias)
+                    .ok()
+                    .and_then(|config| config.hostname);
+                self.unconnected_tags(host_alias, hostname.as_deref())
+            }
+        }
+    }
+
+    /// Tags of an alias not connected yet, matched on the `hostname` its
+    /// config gives too, as they will be once it connects.
+    fn unconnected_tags(&self, host_alias: &str, hostname: Option<&str>) -> BTreeSet<String> {
+        match hostname {
+            Some(hostname) => self.config_tags(&[host_alias, hostname]),
+            None => self.config_tags(&[host_alias]),
+        }
+    }
+
+    fn tags_of(&self, host_alias: &str, state: &SessionState) -> BTreeSet<String> {
+        let (_, hostname, _) = state.origin.endpoint(host_alias);
+        let mut tags = self.config_tags(&[host_alias, &hostname]);
+        tags.extend(state.tags.iter().cloned());
+        tags
+    }
+
+    fn config_tags(&self, names: &[&str]) -> BTreeSet<String> {
+        self.host_tags
+            .iter()
+            .filter(|(_, patterns)| names.iter().any(|name| host_matches(patterns, name)))
+            .map(|(tag, _)| tag.clone())
+            .collect()
+    }
+
+    /// All sessions in the shared table, including other clients' ones.
+    pub async fn list_connections(&self) -> Vec<ConnectionInfo> {
+        let entries: Vec<(SessionKey, SharedState)> = self
+            .table()
+            .iter()
+            .map(|(key, state)| (key.clone(), Arc::clone(state)))
+            .collect();
+
+        let mut list = Vec::with_capacity(entries.len());
+        let all_running = self.running_commands();
+        for ((owner, alias), state) in entries {
+            let state = state.lock().await;
+            let mut channels: Vec<String> = state.named_channels.keys().cloned().collect();
+            channels.sort();
+            let (user, hostname, port) = state.origin.endpoint(&alias);
+            let tags = self.tags_of(&alias, &state).into_iter().collect();
+            let running = all_running
+                .iter()
+                .filter(|r| owner == self.namespace && r.host == alias)
+                .cloned()
+                .collect();
+            list.push(ConnectionInfo {
+                user,
+                hostname,
+                port,
+                connected_at: state
+                    .connected_at
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+                alias,
+                is_own: owner == self.namespace,
+                owner,
+                client: None,
+                shell: state.shell,
+                channels,
+                auth_method: state.auth_method,
+                remote_os: state.remote_os.clone(),
+                tags,
+                running,
+            });
+        }
+        let names = self.client_names.lock().unwrap_or_else(|e| e.into_inner());
+        for info in &mut list {
+            info.client = info.owner.as_ref().and_then(|o| names.get(o).cloned());
+        }
+        list.sort_by(|a, b| (!a.is_own, &a.owner, &a.alias).cmp(&(!b.is_own, &b.owner, &b.alias)));
+        list
+    }
+}
+
+/// Closes the state's shells and sends the SSH disconnect message.
+async fn close_state(state: &SharedState) -> Result<()> {
+    let state = state.lock().await;
+    // Busy channels go down with the session.
+    for channel in std::iter::once(&state.channel).chain(state.named_channels.values()) {
+        if let Ok(mut channel) = channel.try_lock() {
+            channel.close().await?;
+        }
+    }
+    state.session.disconnect().await?;
+    Ok(())
+}
+
+/// The last non-empty line a command printed, e.g. the directory of `pwd`.
+fn printed_dir(output: &crate::ssh::channel::CommandOutput) -> Option<String> {
+    output
+        .stdout
+        .lines()
+        .map(str::trim)
+        .rfind(|line| !line.is_empty())
+        .map(str::to_string)
+}
+
+fn check_env_names<'a>(names: impl IntoIterator<Item = &'a String>) -> Result<()> {
+    match names.into_iter().find(|name| !shell::is_env_name(name)) {
+        Some(name) => Err(SshLiaisonError::Config(format!(
+            "Invalid environment variable name: '{}'",
+            name
+        ))
+        .into()),
+        None => Ok(()),
+    }
+}
+
+/// I/O or SSH library failures, as opposed to timeouts, sudo or policy errors.
+fn is_transport_error(err: &anyhow::Error) -> bool {
+    err.chain()
+        .any(|e| e.is::<std::io::Error>() || backend::is_protocol_error(e))
+}
+
+/// Opens the persistent interactive shell on a PTY and detects which shell it is.
+async fn open_shell(
+    session: &dyn SshSession,
+    options: ChannelOptions,
+    recorder: Option<Recorder>,
+) -> Result<ShellChannel> {
+    let channel = session.open_shell("xterm", PTY_COLS, PTY_ROWS).await?;
+    let mut shell_channel = ShellChannel::new(channel);
+    shell_channel.set_options(options);
+    if let Some(recorder) = recorder {
+        shell_channel.set_recorder(recorder);
+    }
+    let kind = shell_channel.detect_shell().await?;
+    tracing::debug!(shell = %kind, "Detected remote shell");
+    let encoding = shell_channel.setup_charset().await?;
+    tracing::debug!(charset = encoding.name(), "Decoding shell output");
+    Ok(shell_channel)
+}
+
+impl Clone for SessionManager {
+    fn clone(&self) -> Self {
+        Self {
+            sessions: Arc::clone(&self.sessions),
+            passphrase_provider: Arc::clone(&self.passphrase_provider),
+            tunnels: self.tunnels.clone(),
+            watches: self.watches.clone(),
+            running: self.running.clone(),
+            schedules: self.schedules.clone(),
+            namespace: self.namespace.clone(),
+            client_names: Arc::clone(&self.client_names),
+            reconnect: self.reconnect,
+            keepalive_secs: self.keepalive_secs,
+            backend: self.backend,
+            allowed_hosts: self.allowed_hosts.clone(),
+            channel_options: self.channel_options,
+            host_channel_options: Arc::clone(&self.host_channel_options),
+            channel_overrides: Arc::clone(&self.channel_overrides),
+            recording: Arc::clone(&self.recording),
+            record_overrides: Arc::clone(&self.record_overrides),
+            auth_providers: Arc::clone(&self.auth_providers),
+            auth_order: Arc::clone(&self.auth_order),
+            transports: Arc::clone(&self.transports),
+            transfer_limit: self.transfer_limit.clone(),
+            host_tags: Arc::clone(&self.host_tags),
+            store: self.store.clone(),
+            max_sessions: self.max_sessions,
+            connecting: Arc::clone(&self.connecting),
+            auto_connect: self.auto_connect,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh::backend::BackendFuture;
+
+    #[test]
+    fn test_host_allowlist() {
+        let manager = SessionManager::new();
+        assert!(manager.check_host_allowed(&["prod-db"]).is_ok());
+
+        let manager = manager.with_allowed_hosts(vec!["staging-*".into(), "10.0.1.*".into()]);
+        assert!(manager.check_host_allowed(&["staging-web"]).is_ok());
+        assert!(manager.check_host_allowed(&["web", "10.0.1.7"]).is_ok());
+        let err = manager.check_host_allowed(&["prod-db"]).unwrap_err();
+        assert_eq!(
+            SshLiaisonError::find(&err).unwrap().kind(),
+            "host_not_allowed"
+        );
+
+        // A negation on either name wins over an allow pattern on the other.
+        let manager = SessionManager::new().with_allowed_hosts(vec![
+            "*".into(),
+            "!*.prod.internal".into(),
+            "!10.9.*".into(),
+        ]);
+        assert!(manager.check_host_allowed(&["web", "10.0.1.7"]).is_ok());
+        assert!(
+            manager
+                .check_host_allowed(&["staging-web", "db1.prod.internal"])
+                .is_err()
+        );
+        assert!(
+            manager
+                .check_host_allowed(&["db1.prod.internal", "10.0.1.7"])
+                .is_err()
+        );
+        assert!(manager.check_host_allowed(&["web", "10.9.0.1"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_session_limit_slots() {
+        let manager = SessionManager::new().with_max_sessions(1);
+        let slot = manager.reserve_slot("web").await.unwrap();
+        // A second connect of the alias waits for the first.
+        let second = tokio::time::timeout(Duration::from_millis(50), manager.reserve_slot("web"));
+        assert!(second.await.is_err());
+        let err = manager.reserve_slot("db").await.unwrap_err();
+        assert_eq!(SshLiaisonError::find(&err).unwrap().kind(), "session_limit");
+
+        let waiting = manager.reserve_slot("web");
+        drop(slot);
+        drop(waiting.await.unwrap());
+        assert!(manager.reserve_slot("db").await.is_ok());
+        let unlimited = manager.with_max_sessions(0);
+        let mut slots = Vec::new();
+        for alias in ["a", "b", "c"] {
+            slots.push(unlimited.reserve_slot(alias).await.unwrap());
+        }
+    }
+
+    /// A session that only records being disconnected.
+    #[derive(Default)]
+    struct StubSession {
+        disconnected: std::sync::atomic::AtomicBool,
+    }
+
+    fn refused<'a, T: 'a>() -> BackendFuture<'a, T> {
+        Box::pin(async { anyhow::bail!("stub session") })
+    }
+
+    impl SshSession for StubSession {
+        fn backend(&self) -> Backend {
+            Backend::default()
+        }
+        fn is_authenticated(&self) -> bool {
+            true
+        }
+        fn auth_password<'a>(&'a self, _: &'a str, _: &'a str) -> BackendFuture<'a, bool> {
+            refused()
+        }
+        fn auth_keyboard_interactive<'a>(
+            &'a self,
+            _: &'a str,
+            _: &'a str,
+        ) -> BackendFuture<'a, bool> {
+            refused()
+        }
+        fn auth_key_file<'a>(
+            &'a self,
+            _: &'a str,
+            _: &'a Path,
+            _: Option<&'a Path>,
+            _: Option<&'a str>,
+        ) -> BackendFuture<'a, bool> {
+            refused()
+        }
+        fn auth_agent<'a>(
+            &'a self,
+            _: &'a str,
+            _: &'a Path,
+            _: Option<&'a [u8]>,
+        ) -> BackendFuture<'a, bool> {
+            refused()
+        }
+        fn open_shell<'a>(
+            &'a self,
+            _: &'a str,
+            _: u32,
+            _: u32,
+        ) -> BackendFuture<'a, Box<dyn SshChannel>> {
+            refused()
+        }
+        fn exec<'a>(&'a self, _: &'a str) -> BackendFuture<'a, Box<dyn SshChannel>> {
+            refused()
+        }
+        fn direct_tcpip<'a>(
+            &'a self,
+            _: &'a str,
+            _: u16,
+            _: std::net::SocketAddr,
+        ) -> BackendFuture<'a, Box<dyn SshChannel>> {
+            refused()
+        }
+        fn sftp(&self) -> BackendFuture<'_, Box<dyn Sftp>> {
+            refused()
+        }
+        fn set_keepalive(&self, _: u32) {}
+        fn keepalive(&self) -> BackendFuture<'_, ()> {
+            refused()
+        }
+        fn disconnect(&self) -> BackendFuture<'_, ()> {
+            self.disconnected
+                .store(true, std::sync::atomic::Ordering::SeqCst);
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    impl SshChannel for tokio::io::DuplexStream {
+        fn is_eof(&self) -> bool {
+            false
+        }
+        fn resize(&mut self, _: u32, _: u32) -> BackendFuture<'_, ()> {
+            Box::pin(async { Ok(()) })
+        }
+        fn close(&mut self) -> BackendFuture<'_, ()> {
+            Box::pin(async { Ok(()) })
+        }
+        fn exit_status(&self) -> Option<u32> {
+            None
+        }
+    }
+
+    fn stub_state(session: &Arc<StubSession>) -> SharedState {
+        let (channel, _) = tokio::io::duplex(64);
+        Arc::new(Mutex::new(SessionState {
+            session: Arc::clone(session) as Arc<dyn SshSession>,
+            channel: Arc::new(Mutex::new(ShellChannel::new(Box::new(channel)))),
+            named_channels: HashMap::new(),
+            contexts: HashMap::new(),
+            shell: ShellKind::default(),
+            sudo_password: None,
+            origin: ConnectOrigin::Password {
+                user: "deploy".into(),
+                host: "web.example.com".into(),
+                password: String::new(),
+                port: 22,
+            },
+            connected_at: SystemTime::now(),
+            last_used: Instant::now(),
+            auth_method: AuthMethod::Password,
+            remote_os: None,
+            tags: BTreeSet::new(),
+            _keepalive: None,
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_replaced_session_is_closed() {
+        let dir = tempfile::tempdir().unwrap();
+        let manager = SessionManager::new()
+            .with_max_sessions(1)
+            .with_session_store(SessionStore::new(dir.path().join("sessions.json")));
+        let first = Arc::new(StubSession::default());
+        manager.install("web", stub_state(&first)).await;
+
+        // Reconnecting the alias needs no room under the cap.
+        let slot = manager.reserve_slot("web").await.unwrap();
+        let second = Arc::new(StubSession::default());
+        manager.install("web", stub_state(&second)).await;
+        drop(slot);
+
+        assert!(first.disconnected.load(std::sync::atomic::Ordering::SeqCst));
+        assert!(
+            !second
+                .disconnected
+                .load(std::sync::atomic::Ordering::SeqCst)
+        );
+        assert_eq!(manager.table().len(), 1);
+
+        // Evicting it to make room forgets it too.
+        manager.remember("web").await;
+        assert_eq!(manager.session_store().unwrap().load().unwrap().len(), 1);
+        let _slot = manager.reserve_slot("db").await.unwrap();
+        assert!(
+            second
+                .disconnected
+                .load(std::sync::atomic::Ordering::SeqCst)
+        );
+        assert!(manager.session_store().unwrap().load().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_close_namespace() {
+        let manager = SessionManager::new();
+        let first = manager.scoped("client-1");
+        let second = manager.scoped("client-2");
+        first.set_client_name("claude-desktop");
+        second.set_client_name("cursor");
+
+        assert_eq!(first.close_namespace().await, 0);
+        let names = manager.client_names.lock().unwrap().clone();
+        assert_eq!(
+            names,
+            HashMap::from([("client-2".to_string(), "cursor".to_string())])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_config_tags() {
+        let manager = SessionManager::new().with_host_tags(BTreeMap::from([
+            ("prod".to_string(), vec!["prod-*".to_string()]),
+            (
+                "db".to_string(),
+                vec!["*-db".to_string(), "10.0.2.*".to_string()],
+            ),
+        ]));
+        let tags: Vec<String> = manager.tags("prod-db").await.into_iter().collect();
+        assert_eq!(tags, ["db", "prod"]);
+        assert!(manager.tags("web").await.is_empty());
+        assert_eq!(manager.config_tags(&["web", "10.0.2.5"]).len(), 1);
+        // Before it connects, an alias is tagged by its configured hostname too.
+        let tags: Vec<String> = manager
+            .unconnected_tags("replica", Some("10.0.2.5"))
+            .into_iter()
+            .collect();
+        assert_eq!(tags, ["db"]);
+
+        assert!(SessionManager::check_tags(&["prod".into(), "eu-west.1".into()]).is_ok());
+        assert!(SessionManager::check_tags(&["".into()]).is_err());
+        assert!(SessionManager::check_tags(&["a b".into()]).is_err());
     }
 }
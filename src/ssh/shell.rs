@@ -0,0 +1,654 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+use super::channel::MARKER_PREFIX;
+use crate::policy::split_segments;
+
+/// Shell running in a session's PTY, detected when the session starts.
+///
+/// Everything but fish and the Windows shells is driven with POSIX syntax.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShellKind {
+    /// A POSIX `sh` we couldn't identify further (dash, ksh, ...).
+    #[default]
+    Sh,
+    Bash,
+    Zsh,
+    Busybox,
+    Fish,
+    /// Windows PowerShell or PowerShell 7, the usual Windows OpenSSH shell.
+    #[serde(rename = "powershell")]
+    PowerShell,
+    /// `cmd.exe`, the Windows OpenSSH default.
+    Cmd,
+}
+
+impl std::fmt::Display for ShellKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ShellKind::Sh => "sh",
+            ShellKind::Bash => "bash",
+            ShellKind::Zsh => "zsh",
+            ShellKind::Busybox => "busybox",
+            ShellKind::Fish => "fish",
+            ShellKind::PowerShell => "powershell",
+            ShellKind::Cmd => "cmd",
+        };
+        f.write_str(name)
+    }
+}
+
+impl ShellKind {
+    /// A line that every supported shell runs as `echo`, printing `marker`
+    /// followed by the version variables only one family sets. The marker is
+    /// split in two quoted halves so the PTY's echo of the line doesn't match.
+    pub fn probe_command(marker: &str) -> String {
+        let (head, tail) = marker.split_at(marker.len() / 2);
+        format!(
+            "echo \"{}\"\"{}=$BASH_VERSION=$ZSH_VERSION=$FISH_VERSION=$BB_ASH_VERSION=\"",
+            head, tail
+        )
+    }
+
+    /// Reads the probe output that follows the marker.
+    pub fn from_probe(fields: &str) -> Self {
+        let line = fields.lines().next().unwrap_or("");
+        let set: Vec<bool> = line
+            .strip_prefix('=')
+            .unwrap_or(line)
+            .split('=')
+            .map(|v| !v.trim().is_empty())
+            .collect();
+        match set.as_slice() {
+            [true, ..] => ShellKind::Bash,
+            [_, true, ..] => ShellKind::Zsh,
+            [_, _, true, ..] => ShellKind::Fish,
+            [_, _, _, true, ..] => ShellKind::Busybox,
+            _ => ShellKind::Sh,
+        }
+    }
+
+    /// Probe lines for a shell that didn't answer [`Self::probe_command`]
+    /// but printed a Windows prompt: cmd joins the marker around `^`,
+    /// PowerShell concatenates the quoted halves. Each shell errors on the other line.
+    pub fn windows_probe_command(marker: &str) -> String {
+        let (head, tail) = marker.split_at(marker.len() / 2);
+        format!(
+            "echo {}^{}=cmd\rWrite-Output ('{}'+'{}=powershell')",
+            head, tail, head, tail
+        )
+    }
+
+    /// Reads the Windows probe output that follows the marker.
+    pub fn from_windows_probe(fields: &str) -> Self {
+        let line = fields.lines().next().unwrap_or("");
+        if line.trim().eq_ignore_ascii_case("=powershell") {
+            ShellKind::PowerShell
+        } else {
+            ShellKind::Cmd
+        }
+    }
+
+    pub fn is_windows(self) -> bool {
+        matches!(self, ShellKind::PowerShell | ShellKind::Cmd)
+    }
+
+    /// What "pressing Enter" sends: the Windows console wants a carriage return.
+    pub fn line_ending(self) -> &'static str {
+        if self.is_windows() { "\r" } else { "\n" }
+    }
+
+    /// Runs the next command after the previous one regardless of its status.
+    pub fn separator(self) -> &'static str {
+        match self {
+            ShellKind::Cmd => " &",
+            _ => ";",
+        }
+    }
+
+    fn status_var(self) -> &'static str {
+        match self {
+            ShellKind::Fish => "$status",
+            // `$?` is only a success flag; native programs set `$LASTEXITCODE`.
+            ShellKind::PowerShell => {
+                "$(if ($?) {0} elseif ($LASTEXITCODE) {$LASTEXITCODE} else {1})"
+            }
+            // `call` re-expands the variable after the command ran.
+            ShellKind::Cmd => "%^ERRORLEVEL%",
+            _ => "$?",
+        }
+    }
+
+    /// `command` followed by the completion marker and its exit status.
+    pub fn with_marker(self, command: &str, marker: &str) -> String {
+        match self {
+            ShellKind::PowerShell => {
+                format!("{}; echo \"{}:{}\"", command, marker, self.status_var())
+            }
+            ShellKind::Cmd => format!("{} & call echo {}:{}", command, marker, self.status_var()),
+            _ => format!("{}; echo {}:{}", command, marker, self.status_var()),
+        }
+    }
+
+    /// `command` base64-encoded and evaluated by the current shell, so any
+    /// content is safe on the command line and shell state (cwd, variables)
+    /// still persists. `None` for cmd, which has no way to decode it.
+    pub fn encode(self, command: &str) -> Option<String> {
+        let encoded = BASE64.encode(command);
+        match self {
+            ShellKind::Fish => Some(format!("printf '%s' {} | base64 -d | source", encoded)),
+            ShellKind::PowerShell => Some(format!(
+                "Invoke-Expression ([Text.Encoding]::UTF8.GetString([Convert]::FromBase64String('{}')))",
+                encoded
+            )),
+            ShellKind::Cmd => None,
+            _ => Some(format!("eval \"$(printf '%s' {} | base64 -d)\"", encoded)),
+        }
+    }
+
+    /// Command printing the last `lines` lines of `path`.
+    pub fn tail_command(self, path: &str, lines: i32) -> String {
+        let get_content = format!(
+            "Get-Content -Tail {} -LiteralPath '{}'",
+            lines,
+            path.replace('\'', "''")
+        );
+        match self {
+            ShellKind::PowerShell => get_content,
+            ShellKind::Cmd => format!("powershell -NoProfile -Command \"{}\"", get_content),
+            _ => format!("tail -n {} {}", lines, path),
+        }
+    }
+
+    /// Changes to `path` and prints the new working directory.
+    pub fn cd_command(self, path: &str) -> String {
+        match self {
+            ShellKind::Fish => format!("cd {}; and pwd", quote(path)),
+            ShellKind::PowerShell => format!(
+                "if (Set-Location -LiteralPath {} -PassThru -ErrorAction SilentlyContinue) {{ (Get-Location).Path }} else {{ Write-Error 'No such directory' }}",
+                powershell_quote(path)
+            ),
+            ShellKind::Cmd => format!("cd /d \"{}\" && cd", path),
+            _ => format!("cd -- {} && pwd", quote(path)),
+        }
+    }
+
+    /// Prints the working directory.
+    pub fn pwd_command(self) -> &'static str {
+        match self {
+            ShellKind::PowerShell => "(Get-Location).Path",
+            ShellKind::Cmd => "cd",
+            _ => "pwd",
+        }
+    }
+
+    /// Prints a one-line description of the remote OS.
+    pub fn os_command(self) -> &'static str {
+        match self {
+            ShellKind::PowerShell => "[Environment]::OSVersion.VersionString",
+            ShellKind::Cmd => "ver",
+            ShellKind::Fish => "uname -sr",
+            _ => {
+                "(. /etc/os-release && [ -n \"$PRETTY_NAME\" ] && echo \"$PRETTY_NAME\") 2>/dev/null || uname -sr"
+            }
+        }
+    }
+
+    /// Sets and exports `name` (a valid variable name) for later commands.
+    pub fn set_env_command(self, name: &str, value: &str) -> String {
+        match self {
+            ShellKind::Fish => format!("set -gx {} {}", name, quote(value)),
+            ShellKind::PowerShell => format!("$env:{} = {}", name, powershell_quote(value)),
+            ShellKind::Cmd => format!("set \"{}={}\"", name, value),
+            _ => format!("export {}={}", name, quote(value)),
+        }
+    }
+
+    pub fn unset_env_command(self, name: &str) -> String {
+        match self {
+            ShellKind::Fish => format!("set -e {}", name),
+            ShellKind::PowerShell => {
+                format!("Remove-Item -ErrorAction SilentlyContinue Env:{}", name)
+            }
+            ShellKind::Cmd => format!("set \"{}=\"", name),
+            _ => format!("unset {}", name),
+        }
+    }
+
+    /// Prints `NAME=value` for each of `names` that is set; read back with
+    /// [`parse_env_output`].
+    pub fn print_env_command(self, names: &[String]) -> String {
+        let lines: Vec<String> = names
+            .iter()
+            .map(|name| match self {
+                ShellKind::Fish => {
+                    format!("set -q {0}; and printf '%s=%s\\n' {0} \"${0}\"", name)
+                }
+                ShellKind::PowerShell => {
+                    format!("if (Test-Path Env:{0}) {{ '{0}=' + $env:{0} }}", name)
+                }
+                ShellKind::Cmd => format!("(if defined {0} echo {0}=%{0}%)", name),
+                _ => format!(
+                    "[ -n \"${{{0}+x}}\" ] && printf '%s=%s\\n' {0} \"${0}\"",
+                    name
+                ),
+            })
+            .collect();
+        let status = match self {
+            ShellKind::PowerShell => "$null",
+            ShellKind::Cmd => "ver >nul",
+            _ => "true",
+        };
+        format!(
+            "{}{} {}",
+            lines.join(&format!("{} ", self.separator())),
+            self.separator(),
+            status
+        )
+    }
+
+    /// `command` with its stdin redirected from `path`; the exit status stays the command's.
+    pub fn with_stdin_from(self, command: &str, path: &str) -> String {
+        let command = command.trim_end().trim_end_matches(';');
+        match self {
+            ShellKind::Fish => format!("begin; {}; end < {}", command, quote(path)),
+            _ => format!("{{ {}; }} < {}", command, quote(path)),
+        }
+    }
+}
+
+/// Whether `command` could break the `<command>; echo <marker>` line: a
+/// newline or heredoc splits it, a `#` may comment the marker out, a trailing
+/// `\` or `&` swallows the `;`, and a literal marker fakes completion.
+pub fn needs_encoding(command: &str) -> bool {
+    let trimmed = command.trim_end();
+    command.contains(['\n', '\r', '#'])
+        || command.contains("<<")
+        || command.contains(MARKER_PREFIX)
+        || trimmed.ends_with('\\')
+        || trimmed.ends_with('&')
+}
+
+/// Commands after which a tracked working directory is checked with `pwd` anyway.
+pub const CWD_CHECK_EVERY: u32 = 20;
+
+/// What is known about a shell's state without asking the host.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ShellContext {
+    /// Last working directory seen; `None` until one is, or after a
+    /// directory change that couldn't be followed.
+    pub cwd: Option<String>,
+    /// Variables exported through `ssh_env` or by commands that ran.
+    pub env: BTreeMap<String, String>,
+    /// Commands run since `cwd` was last confirmed by the host.
+    #[serde(skip)]
+    pub commands_since_check: u32,
+}
+
+impl ShellContext {
+    /// Follows `cd`, `export` and `unset` in a command the shell ran, as far
+    /// as that works without running anything. A directory change that
+    /// can't be resolved (`cd ~`, `cd $DIR`, a failed command) forgets `cwd`.
+    pub fn observe(&mut self, command: &str, succeeded: bool) {
+        self.commands_since_check += 1;
+        for segment in split_segments(command) {
+            let mut words = shell_words(&segment);
+            // `{ ...; }` runs in the current shell; `( ... )` doesn't.
+            if words.first().is_some_and(|w| w == "{") {
+                words.remove(0);
+            }
+            let Some((program, args)) = words.split_first() else {
+                continue;
+            };
+            match program.as_str() {
+                "cd" => {
+                    let target = args
+                        .iter()
+                        .find(|a| *a == "-" || !a.starts_with('-'))
+                        .map(String::as_str);
+                    self.cwd = match target {
+                        Some(target) if succeeded => resolve_dir(self.cwd.as_deref(), target),
+                        _ => None,
+                    };
+                }
+                "pushd" | "popd" | "eval" | "source" | "." => self.cwd = None,
+                "export" => {
+                    for arg in args {
+                        let Some((name, value)) = arg.split_once('=') else {
+                            continue;
+                        };
+                        if !is_env_name(name) {
+                            continue;
+                        }
+                        if value.contains(['$', '`']) {
+                            self.env.remove(name);
+                        } else {
+                            self.env.insert(name.to_string(), value.to_string());
+                        }
+                    }
+                }
+                "unset" => {
+                    for name in args.iter().filter(|a| !a.starts_with('-')) {
+                        self.env.remove(name);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The tracked directory, unless it's time to confirm it with `pwd`.
+    pub fn fresh_cwd(&self) -> Option<&str> {
+        self.cwd
+            .as_deref()
+            .filter(|_| self.commands_since_check < CWD_CHECK_EVERY)
+    }
+}
+
+/// Whether `command` changes the state of the shell it runs in (directory,
+/// variables, options), so its effect would be lost if it weren't run.
+pub fn changes_shell_state(command: &str) -> bool {
+    split_segments(command).iter().any(|segment| {
+        let words = shell_words(segment);
+        let words: Vec<_> = words.iter().skip_while(|w| *w == "{").collect();
+        let Some(program) = words.first() else {
+            return false;
+        };
+        // `NAME=value` alone sets a shell variable; before a command it doesn't.
+        let is_assignment =
+            |w: &&String| w.split_once('=').is_some_and(|(name, _)| is_env_name(name));
+        words.iter().all(is_assignment)
+            || matches!(
+                program.as_str(),
+                "cd" | "pushd"
+                    | "popd"
+                    | "eval"
+                    | "source"
+                    | "."
+                    | "export"
+                    | "unset"
+                    | "set"
+                    | "shopt"
+                    | "alias"
+                    | "unalias"
+                    | "umask"
+                    | "ulimit"
+                    | "declare"
+                    | "typeset"
+                    | "readonly"
+                    | "trap"
+                    | "exec"
+            )
+    })
+}
+
+/// Words of a command segment with quotes and backslashes removed.
+/// Expansions (`$X`, `` `...` ``) are left as they are.
+fn shell_words(segment: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current: Option<String> = None;
+    let mut quote: Option<char> = None;
+    let mut chars = segment.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') => {
+                let word = current.get_or_insert_default();
+                word.extend(chars.next());
+            }
+            (Some(_), c) => current.get_or_insert_default().push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                current.get_or_insert_default();
+            }
+            (None, '\\') => {
+                let word = current.get_or_insert_default();
+                word.extend(chars.next());
+            }
+            (None, c) if c.is_whitespace() => words.extend(current.take()),
+            (None, c) => current.get_or_insert_default().push(c),
+        }
+    }
+    words.extend(current);
+    words
+}
+
+/// Where `cd target` leads from `cwd`, resolving `.` and `..` the way
+/// `cd` does by default (without following symlinks).
+fn resolve_dir(cwd: Option<&str>, target: &str) -> Option<String> {
+    if target == "-" || target.starts_with('~') || target.contains(['$', '`', '*', '?', '[']) {
+        return None;
+    }
+    let base = if target.starts_with('/') {
+        ""
+    } else {
+        cwd.filter(|cwd| cwd.starts_with('/'))?
+    };
+    let mut parts: Vec<&str> = base.split('/').filter(|p| !p.is_empty()).collect();
+    for part in target.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    Some(format!("/{}", parts.join("/")))
+}
+
+/// Whether `name` can be set as an environment variable by every shell.
+pub fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Values printed by [`ShellKind::print_env_command`]; names that aren't
+/// set are absent. Lines that don't start a variable continue the previous value.
+pub fn parse_env_output(output: &str, names: &[String]) -> BTreeMap<String, String> {
+    let mut values = BTreeMap::new();
+    let mut current: Option<String> = None;
+    for line in output.lines() {
+        let line = line.trim_end_matches('\r');
+        match line.split_once('=') {
+            Some((name, value)) if names.iter().any(|n| n == name) => {
+                values.insert(name.to_string(), value.to_string());
+                current = Some(name.to_string());
+            }
+            _ => {
+                if let Some(value) = current.as_ref().and_then(|name| values.get_mut(name)) {
+                    value.push('\n');
+                    value.push_str(line);
+                }
+            }
+        }
+    }
+    values
+}
+
+fn powershell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// Quotes `value` as a single POSIX shell word.
+pub fn quote(value: &str) -> String {
+    if !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%".contains(c))
+    {
+        return value.to_string();
+    }
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("/var/log/syslog"), "/var/log/syslog");
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("my file.txt"), "'my file.txt'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+        assert_eq!(quote("$(rm -rf /)"), "'$(rm -rf /)'");
+    }
+
+    #[test]
+    fn test_encoding_for_risky_commands() {
+        assert!(!needs_encoding("cd /var/log; ls -la | grep syslog"));
+        assert!(needs_encoding("cat <<EOF > x\nhello\nEOF"));
+        assert!(needs_encoding("ls # list"));
+        assert!(needs_encoding("sleep 10 &"));
+        assert!(needs_encoding("echo __SSH_CMD_DONE_1__:0"));
+
+        let encoded = ShellKind::Bash.encode("echo 'a;b'\nfalse").unwrap();
+        assert!(!needs_encoding(&encoded));
+        let b64 = encoded.split(' ').nth(3).unwrap();
+        assert_eq!(BASE64.decode(b64).unwrap(), b"echo 'a;b'\nfalse");
+        assert!(
+            ShellKind::Fish
+                .encode("ls")
+                .unwrap()
+                .ends_with("| base64 -d | source")
+        );
+        assert_eq!(ShellKind::Cmd.encode("dir"), None);
+    }
+
+    #[test]
+    fn test_shell_kind_from_probe() {
+        assert_eq!(
+            ShellKind::from_probe("=5.2.15(1)-release====\r\n"),
+            ShellKind::Bash
+        );
+        assert_eq!(ShellKind::from_probe("==5.9===\n"), ShellKind::Zsh);
+        assert_eq!(ShellKind::from_probe("===3.7.0==\n"), ShellKind::Fish);
+        assert_eq!(ShellKind::from_probe("====1.36.1=\n"), ShellKind::Busybox);
+        assert_eq!(ShellKind::from_probe("=====\n"), ShellKind::Sh);
+
+        let marker = "__SSH_CMD_DONE_42__";
+        assert!(!ShellKind::probe_command(marker).contains(marker));
+        assert_eq!(
+            ShellKind::Fish.with_marker("ls", marker),
+            "ls; echo __SSH_CMD_DONE_42__:$status"
+        );
+    }
+
+    #[test]
+    fn test_windows_shells() {
+        let marker = "__SSH_CMD_DONE_42__";
+        let probe = ShellKind::windows_probe_command(marker);
+        assert!(!probe.contains(marker));
+        assert!(probe.contains("__SSH_CMD^_DONE_42__=cmd\r"));
+        assert_eq!(
+            ShellKind::from_windows_probe("=powershell\r\n"),
+            ShellKind::PowerShell
+        );
+        assert_eq!(ShellKind::from_windows_probe("=cmd\r\n"), ShellKind::Cmd);
+
+        assert_eq!(
+            ShellKind::Cmd.with_marker("dir", marker),
+            "dir & call echo __SSH_CMD_DONE_42__:%^ERRORLEVEL%"
+        );
+        assert!(
+            ShellKind::PowerShell
+                .with_marker("Get-Process", marker)
+                .starts_with("Get-Process; echo \"__SSH_CMD_DONE_42__:$(if ($?)")
+        );
+        assert_eq!(
+            ShellKind::PowerShell.tail_command("C:\\logs\\it's.log", 20),
+            "Get-Content -Tail 20 -LiteralPath 'C:\\logs\\it''s.log'"
+        );
+        assert_eq!(
+            ShellKind::Bash.tail_command("/var/log/syslog", 20),
+            "tail -n 20 /var/log/syslog"
+        );
+    }
+
+    #[test]
+    fn test_cwd_and_env_commands() {
+        assert_eq!(
+            ShellKind::Bash.cd_command("/srv/my app"),
+            "cd -- '/srv/my app' && pwd"
+        );
+        assert_eq!(ShellKind::Cmd.pwd_command(), "cd");
+        assert_eq!(
+            ShellKind::Zsh.set_env_command("A", "it's"),
+            r"export A='it'\''s'"
+        );
+        assert_eq!(ShellKind::Fish.set_env_command("A", "1"), "set -gx A 1");
+        assert_eq!(
+            ShellKind::PowerShell.set_env_command("A", "it's"),
+            "$env:A = 'it''s'"
+        );
+        assert_eq!(ShellKind::Fish.unset_env_command("A"), "set -e A");
+
+        let names = ["PATH".to_string(), "EMPTY".to_string(), "GONE".to_string()];
+        let command = ShellKind::Bash.print_env_command(&names);
+        assert!(command.starts_with(r#"[ -n "${PATH+x}" ] && printf '%s=%s\n' PATH "$PATH"; "#));
+        assert!(command.ends_with("; true"));
+        assert!(!needs_encoding(&command));
+
+        let values = parse_env_output("PATH=/usr/bin\r\nEMPTY=\nmore\n", &names);
+        assert_eq!(values["PATH"], "/usr/bin");
+        assert_eq!(values["EMPTY"], "\nmore");
+        assert!(!values.contains_key("GONE"));
+        assert!(is_env_name("_A1") && !is_env_name("1A") && !is_env_name("A-B"));
+    }
+
+    #[test]
+    fn test_shell_context_follows_commands() {
+        let mut context = ShellContext::default();
+        context.observe("cd /var/log && ls", true);
+        assert_eq!(context.cwd.as_deref(), Some("/var/log"));
+        context.observe("cd ../lib/'my app'/./data", true);
+        assert_eq!(context.cwd.as_deref(), Some("/var/lib/my app/data"));
+        context.observe("(cd /tmp && make); cd -- ..", true);
+        assert_eq!(context.cwd.as_deref(), Some("/var/lib/my app"));
+        context.observe("{ cd /srv; } < /tmp/in", true);
+        assert_eq!(context.cwd.as_deref(), Some("/srv"));
+        context.observe("cd /nonexistent", false);
+        assert_eq!(context.cwd, None);
+        context.observe("cd /etc; cd ~/src", true);
+        assert_eq!(context.cwd, None);
+        context.observe("cd docs", true);
+        assert_eq!(context.cwd, None);
+
+        context.observe("export RAILS_ENV=production \"GREETING=hi there\"", true);
+        context.observe("export PATH=$PATH:/opt/bin; unset GREETING", true);
+        assert_eq!(
+            context.env.iter().collect::<Vec<_>>(),
+            [(&"RAILS_ENV".to_string(), &"production".to_string())]
+        );
+
+        context.observe("cd /", true);
+        assert_eq!(context.fresh_cwd(), Some("/"));
+        context.commands_since_check = CWD_CHECK_EVERY;
+        assert_eq!(context.fresh_cwd(), None);
+
+        assert!(changes_shell_state("ls; cd /tmp"));
+        assert!(changes_shell_state("{ export A=1; }"));
+        assert!(changes_shell_state("LANG=C"));
+        assert!(!changes_shell_state("LANG=C sort file"));
+        assert!(!changes_shell_state("cat /etc/os-release | grep -i cd"));
+    }
+
+    #[test]
+    fn test_with_stdin_from() {
+        assert_eq!(
+            ShellKind::Bash.with_stdin_from("psql -d app;", "/tmp/in file"),
+            "{ psql -d app; } < '/tmp/in file'"
+        );
+        assert_eq!(
+            ShellKind::Fish.with_stdin_from("psql", "/tmp/in"),
+            "begin; psql; end < /tmp/in"
+        );
+    }
+}
@@ -0,0 +1,357 @@
+use anyhow::{Context, Result};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+use super::backend::SshSession;
+
+const SOCKS_VERSION: u8 = 0x05;
+const AUTH_NONE: u8 = 0x00;
+const AUTH_NO_ACCEPTABLE: u8 = 0xff;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_GENERAL_FAILURE: u8 = 0x01;
+const REPLY_HOST_UNREACHABLE: u8 = 0x04;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const REPLY_ADDRESS_NOT_SUPPORTED: u8 = 0x08;
+
+const DEFAULT_MAX_CONNECTIONS: usize = 64;
+const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const COPY_BUFFER_SIZE: usize = 8192;
+
+#[derive(Debug, Clone, Copy)]
+pub struct SocksOptions {
+    /// Connections above this limit are refused with a SOCKS failure reply.
+    pub max_connections: usize,
+    /// Connections with no traffic in either direction for this long are closed.
+    pub idle_timeout: Duration,
+}
+
+impl Default for SocksOptions {
+    fn default() -> Self {
+        Self {
+            max_connections: DEFAULT_MAX_CONNECTIONS,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum SocksError {
+    Protocol(String),
+    Reply(u8, String),
+}
+
+pub(crate) async fn serve(
+    listener: TcpListener,
+    session: Arc<dyn SshSession>,
+    options: SocksOptions,
+    active: Arc<AtomicUsize>,
+) {
+    let limit = Arc::new(Semaphore::new(options.max_connections));
+    let mut connections = JoinSet::new();
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, peer) = match accepted {
+                    Ok(v) => v,
+                    Err(e) => {
+                        tracing::warn!(error = %e, "SOCKS accept failed");
+                        continue;
+                    }
+                };
+
+                let permit = Arc::clone(&limit).try_acquire_owned().ok();
+                let session = Arc::clone(&session);
+                let active = Arc::clone(&active);
+                connections.spawn(async move {
+                    let Some(_permit) = permit else {
+                        tracing::warn!(peer = %peer, "SOCKS connection limit reached, refusing");
+                        let mut stream = stream;
+                        let _ = refuse(&mut stream).await;
+                        return;
+                    };
+                    active.fetch_add(1, Ordering::Relaxed);
+                    if let Err(e) = handle_connection(stream, peer, &*session, options.idle_timeout).await {
+                        tracing::debug!(peer = %peer, error = %e, "SOCKS connection ended with error");
+                    }
+                    active.fetch_sub(1, Ordering::Relaxed);
+                });
+            }
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+        }
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    session: &dyn SshSession,
+    idle_timeout: Duration,
+) -> Result<()> {
+    let (host, port) = match tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake(&mut stream)).await {
+        Ok(Ok(target)) => target,
+        Ok(Err(SocksError::Protocol(msg))) => anyhow::bail!("SOCKS handshake failed: {}", msg),
+        Ok(Err(SocksError::Reply(code, msg))) => {
+            write_reply(&mut stream, code).await?;
+            anyhow::bail!("SOCKS request rejected: {}", msg);
+        }
+        Err(_) => anyhow::bail!("SOCKS handshake timed out"),
+    };
+
+    tracing::debug!(peer = %peer, target = %format!("{}:{}", host, port), "SOCKS connect");
+
+    let mut channel = match session.direct_tcpip(&host, port, peer).await {
+        Ok(c) => c,
+        Err(e) => {
+            write_reply(&mut stream, REPLY_HOST_UNREACHABLE).await?;
+            return Err(e).with_context(|| format!("Failed to open channel to {}:{}", host, port));
+        }
+    };
+
+    write_reply(&mut stream, REPLY_SUCCEEDED).await?;
+
+    let result = copy_with_idle_timeout(&mut stream, &mut channel, idle_timeout).await;
+    let _ = channel.close().await;
+    result
+}
+
+async fn refuse(stream: &mut TcpStream) -> Result<()> {
+    tokio::time::timeout(HANDSHAKE_TIMEOUT, async {
+        let _ = handshake(stream).await;
+        write_reply(stream, REPLY_GENERAL_FAILURE).await
+    })
+    .await
+    .context("SOCKS refusal timed out")?
+}
+
+/// Negotiates "no auth" and reads a CONNECT request, returning its destination.
+async fn handshake<S>(stream: &mut S) -> std::result::Result<(String, u16), SocksError>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let io = |e: std::io::Error| SocksError::Protocol(e.to_string());
+
+    let mut header = [0u8; 2];
+    stream.read_exact(&mut header).await.map_err(io)?;
+    if header[0] != SOCKS_VERSION {
+        return Err(SocksError::Protocol(format!(
+            "unsupported SOCKS version {}",
+            header[0]
+        )));
+    }
+    let mut methods = vec![0u8; header[1] as usize];
+    stream.read_exact(&mut methods).await.map_err(io)?;
+
+    if !methods.contains(&AUTH_NONE) {
+        stream
+            .write_all(&[SOCKS_VERSION, AUTH_NO_ACCEPTABLE])
+            .await
+            .map_err(io)?;
+        return Err(SocksError::Protocol(
+            "client offered no supported auth method".to_string(),
+        ));
+    }
+    stream
+        .write_all(&[SOCKS_VERSION, AUTH_NONE])
+        .await
+        .map_err(io)?;
+
+    let mut request = [0u8; 4];
+    stream.read_exact(&mut request).await.map_err(io)?;
+    if request[1] != CMD_CONNECT {
+        return Err(SocksError::Reply(
+            REPLY_COMMAND_NOT_SUPPORTED,
+            format!("unsupported command {}", request[1]),
+        ));
+    }
+
+    let host = match request[3] {
+        ATYP_IPV4 => {
+            let mut addr = [0u8; 4];
+            stream.read_exact(&mut addr).await.map_err(io)?;
+            Ipv4Addr::from(addr).to_string()
+        }
+        ATYP_IPV6 => {
+            let mut addr = [0u8; 16];
+            stream.read_exact(&mut addr).await.map_err(io)?;
+            Ipv6Addr::from(addr).to_string()
+        }
+        ATYP_DOMAIN => {
+            let len = stream.read_u8().await.map_err(io)?;
+            let mut name = vec![0u8; len as usize];
+            stream.read_exact(&mut name).await.map_err(io)?;
+            String::from_utf8(name)
+                .map_err(|_| SocksError::Protocol("domain name is not UTF-8".to_string()))?
+        }
+        other => {
+            return Err(SocksError::Reply(
+                REPLY_ADDRESS_NOT_SUPPORTED,
+                format!("unsupported address type {}", other),
+            ));
+        }
+    };
+    let port = stream.read_u16().await.map_err(io)?;
+
+    Ok((host, port))
+}
+
+async fn write_reply<S: AsyncWrite + Unpin>(stream: &mut S, code: u8) -> Result<()> {
+    // The bound address is not meaningful for an SSH-forwarded connection.
+    stream
+        .write_all(&[SOCKS_VERSION, code, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0])
+        .await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Bidirectional copy that gives up after `idle_timeout` without traffic either way.
+async fn copy_with_idle_timeout<A, B>(a: &mut A, b: &mut B, idle_timeout: Duration) -> Result<()>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf_a = vec![0u8; COPY_BUFFER_SIZE];
+    let mut buf_b = vec![0u8; COPY_BUFFER_SIZE];
+    let mut a_open = true;
+    let mut b_open = true;
+
+    while a_open || b_open {
+        tokio::select! {
+            n = a.read(&mut buf_a), if a_open => {
+                match n? {
+                    0 => {
+                        a_open = false;
+                        let _ = b.shutdown().await;
+                    }
+                    n => {
+                        b.write_all(&buf_a[..n]).await?;
+                        b.flush().await?;
+                    }
+                }
+            }
+            n = b.read(&mut buf_b), if b_open => {
+                match n? {
+                    0 => {
+                        b_open = false;
+                        let _ = a.shutdown().await;
+                    }
+                    n => {
+                        a.write_all(&buf_b[..n]).await?;
+                        a.flush().await?;
+                    }
+                }
+            }
+            _ = tokio::time::sleep(idle_timeout) => {
+                tracing::debug!(idle = ?idle_timeout, "Closing idle SOCKS connection");
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_handshake_domain_connect() {
+        let (mut client, mut server) = tokio::io::duplex(256);
+
+        let client_task = tokio::spawn(async move {
+            client.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+            let mut auth = [0u8; 2];
+            client.read_exact(&mut auth).await.unwrap();
+            assert_eq!(auth, [0x05, 0x00]);
+
+            let mut req = vec![0x05, 0x01, 0x00, 0x03, 11];
+            req.extend_from_slice(b"db.internal");
+            req.extend_from_slice(&5432u16.to_be_bytes());
+            client.write_all(&req).await.unwrap();
+        });
+
+        let target = handshake(&mut server).await.unwrap();
+        client_task.await.unwrap();
+        assert_eq!(target, ("db.internal".to_string(), 5432));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_ipv4_connect() {
+        let (mut client, mut server) = tokio::io::duplex(256);
+
+        let client_task = tokio::spawn(async move {
+            client.write_all(&[0x05, 0x02, 0x02, 0x00]).await.unwrap();
+            let mut auth = [0u8; 2];
+            client.read_exact(&mut auth).await.unwrap();
+            client
+                .write_all(&[0x05, 0x01, 0x00, 0x01, 10, 0, 0, 5, 0x1f, 0x90])
+                .await
+                .unwrap();
+        });
+
+        let target = handshake(&mut server).await.unwrap();
+        client_task.await.unwrap();
+        assert_eq!(target, ("10.0.0.5".to_string(), 8080));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_rejects_bind_command() {
+        let (mut client, mut server) = tokio::io::duplex(256);
+
+        let client_task = tokio::spawn(async move {
+            client.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+            let mut auth = [0u8; 2];
+            client.read_exact(&mut auth).await.unwrap();
+            client
+                .write_all(&[0x05, 0x02, 0x00, 0x01, 127, 0, 0, 1, 0, 80])
+                .await
+                .unwrap();
+        });
+
+        let err = handshake(&mut server).await.unwrap_err();
+        client_task.await.unwrap();
+        assert!(matches!(
+            err,
+            SocksError::Reply(REPLY_COMMAND_NOT_SUPPORTED, _)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_handshake_requires_no_auth_method() {
+        let (mut client, mut server) = tokio::io::duplex(256);
+
+        let client_task = tokio::spawn(async move {
+            client.write_all(&[0x05, 0x01, 0x02]).await.unwrap();
+            let mut auth = [0u8; 2];
+            client.read_exact(&mut auth).await.unwrap();
+            auth
+        });
+
+        assert!(matches!(
+            handshake(&mut server).await,
+            Err(SocksError::Protocol(_))
+        ));
+        assert_eq!(client_task.await.unwrap(), [0x05, AUTH_NO_ACCEPTABLE]);
+    }
+
+    #[tokio::test]
+    async fn test_copy_closes_on_idle() {
+        let (mut a, _a_peer) = tokio::io::duplex(64);
+        let (mut b, _b_peer) = tokio::io::duplex(64);
+
+        let result = copy_with_idle_timeout(&mut a, &mut b, Duration::from_millis(20)).await;
+        assert!(result.is_ok());
+    }
+}
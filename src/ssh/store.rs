@@ -0,0 +1,215 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+use super::auth::AuthMethod;
+use super::config::SshHostConfig;
+
+/// How to re-open a session after a server restart. Passwords and key
+/// passphrases are never part of it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedSession {
+    pub alias: String,
+    pub user: String,
+    pub hostname: String,
+    pub port: u16,
+    pub auth_method: AuthMethod,
+    /// The resolved host options; `None` for sessions opened with a password.
+    pub config: Option<SshHostConfig>,
+    /// Opened with `ssh_connect_direct`, so the alias doesn't count for the allowlist.
+    #[serde(default)]
+    pub direct: bool,
+    /// Tags the client gave the session.
+    #[serde(default)]
+    pub tags: BTreeSet<String>,
+    /// When the session was saved, in Unix seconds.
+    pub saved_at: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestoreStatus {
+    Restored,
+    /// The alias already had an open session.
+    Connected,
+    /// Opened with a password, which isn't saved; connect again to restore it.
+    NeedsPassword,
+    Failed,
+}
+
+impl std::fmt::Display for RestoreStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RestoreStatus::Restored => write!(f, "restored"),
+            RestoreStatus::Connected => write!(f, "already connected"),
+            RestoreStatus::NeedsPassword => write!(f, "needs password"),
+            RestoreStatus::Failed => write!(f, "failed"),
+        }
+    }
+}
+
+/// Outcome of re-opening one saved session.
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoredSession {
+    pub alias: String,
+    pub user: String,
+    pub hostname: String,
+    pub port: u16,
+    pub status: RestoreStatus,
+    pub error: Option<String>,
+}
+
+impl std::fmt::Display for RestoredSession {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({}@{}:{}): {}",
+            self.alias, self.user, self.hostname, self.port, self.status
+        )?;
+        if let Some(ref error) = self.error {
+            write!(f, ": {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// JSON file of the sessions to re-open, one per alias.
+#[derive(Debug)]
+pub struct SessionStore {
+    path: PathBuf,
+    /// Serializes read-modify-write cycles within the server.
+    lock: std::sync::Mutex<()>,
+}
+
+impl SessionStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: std::sync::Mutex::new(()),
+        }
+    }
+
+    /// `sessions.json` under the config directory.
+    pub fn default_path() -> PathBuf {
+        crate::config::config_dir()
+            .map(|dir| dir.join("sessions.json"))
+            .unwrap_or_else(|| PathBuf::from("sessions.json"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The saved sessions; none when the file doesn't exist yet.
+    pub fn load(&self) -> Result<Vec<SavedSession>> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.read()
+    }
+
+    /// Saves `session`, replacing one with the same alias.
+    pub fn save(&self, session: SavedSession) -> Result<()> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut sessions = self.read()?;
+        sessions.retain(|s| s.alias != session.alias);
+        sessions.push(session);
+        sessions.sort_by(|a, b| a.alias.cmp(&b.alias));
+        self.write(&sessions)
+    }
+
+    /// Forgets the sessions of `aliases` (all when empty) and returns the
+    /// aliases that were saved.
+    pub fn forget(&self, aliases: &[String]) -> Result<Vec<String>> {
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let (forgotten, kept): (Vec<_>, Vec<_>) = self
+            .read()?
+            .into_iter()
+            .partition(|s| aliases.is_empty() || aliases.contains(&s.alias));
+        if !forgotten.is_empty() {
+            self.write(&kept)?;
+        }
+        Ok(forgotten.into_iter().map(|s| s.alias).collect())
+    }
+
+    fn read(&self) -> Result<Vec<SavedSession>> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(content) => serde_json::from_str(&content)
+                .with_context(|| format!("Invalid session file {}", self.path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e)
+                .with_context(|| format!("Failed to read session file {}", self.path.display())),
+        }
+    }
+
+    /// Replaces the file through a temporary one, readable by the owner only.
+    fn write(&self, sessions: &[SavedSession]) -> Result<()> {
+        if let Some(parent) = self.path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let file = options
+            .open(&tmp)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        serde_json::to_writer_pretty(file, sessions)?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("Failed to replace {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn saved(alias: &str) -> SavedSession {
+        SavedSession {
+            alias: alias.to_string(),
+            user: "deploy".to_string(),
+            hostname: "10.0.0.5".to_string(),
+            port: 22,
+            auth_method: AuthMethod::Agent,
+            config: None,
+            direct: false,
+            tags: BTreeSet::from(["prod".to_string()]),
+            saved_at: 0,
+        }
+    }
+
+    #[test]
+    fn test_session_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SessionStore::new(dir.path().join("state/sessions.json"));
+        assert!(store.load().unwrap().is_empty());
+
+        store.save(saved("web")).unwrap();
+        store.save(saved("db")).unwrap();
+        let mut web = saved("web");
+        web.port = 2222;
+        store.save(web).unwrap();
+        let sessions = store.load().unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].alias, "db");
+        assert_eq!(sessions[1].port, 2222);
+
+        assert_eq!(
+            store.forget(&["db".into(), "missing".into()]).unwrap(),
+            ["db"]
+        );
+        assert_eq!(store.load().unwrap(), [sessions[1].clone()]);
+        assert_eq!(store.forget(&[]).unwrap(), ["web"]);
+        assert!(store.load().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,1047 @@
+//! Streaming file transfers: data is copied in chunks through the server,
+//! so files of any size move without being held in memory.
+
+use anyhow::{Context, Result};
+use glob::Pattern;
+use serde::Serialize;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+
+use super::backend::{FileAttrs, OpenMode, Sftp};
+use super::files::{EntryKind, NEW_FILE_MODE, entry_kind};
+use crate::error::SshLiaisonError;
+
+pub const COPY_CHUNK: usize = 64 * 1024;
+/// Log progress about this often on long transfers.
+const PROGRESS_LOG_BYTES: u64 = 16 * 1024 * 1024;
+/// Minimum gap between two progress notifications of a transfer.
+const NOTIFY_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Token bucket capping bytes per second. One limiter can be shared by many
+/// transfers, which then split the rate between them.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_sec: u64,
+    bucket: std::sync::Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Negative when callers took more than was available and are waiting.
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Takes `bytes` at `now`; returns how long the taker has to wait before
+    /// sending them. Up to one second's worth builds up while idle.
+    fn take(&mut self, rate: f64, bytes: u64, now: Instant) -> Duration {
+        let idle = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + idle * rate).min(rate);
+        self.updated = now;
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / rate)
+        }
+    }
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Result<Self> {
+        if bytes_per_sec == 0 {
+            return Err(SshLiaisonError::Config(
+                "Transfer rate limit must be at least 1 byte per second".to_string(),
+            )
+            .into());
+        }
+        Ok(Self {
+            bytes_per_sec,
+            bucket: std::sync::Mutex::new(Bucket {
+                tokens: bytes_per_sec as f64,
+                updated: Instant::now(),
+            }),
+        })
+    }
+
+    pub fn bytes_per_sec(&self) -> u64 {
+        self.bytes_per_sec
+    }
+
+    /// Waits until `bytes` may be sent.
+    pub async fn acquire(&self, bytes: u64) {
+        let wait = self.bucket.lock().unwrap_or_else(|e| e.into_inner()).take(
+            self.bytes_per_sec as f64,
+            bytes,
+            Instant::now(),
+        );
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+/// Snapshot of a running transfer, for progress notifications.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressUpdate {
+    /// File being copied.
+    pub file: String,
+    /// Bytes of the whole transfer done, including resumed prefixes.
+    pub bytes: u64,
+    /// Size of the whole transfer, when known up front (single files).
+    pub total: Option<u64>,
+    pub bytes_per_sec: u64,
+    /// Seconds left at the current rate, when the total is known.
+    pub eta_secs: Option<u64>,
+}
+
+impl ProgressUpdate {
+    pub fn percent(&self) -> Option<f64> {
+        self.total
+            .filter(|&total| total > 0)
+            .map(|total| self.bytes as f64 * 100.0 / total as f64)
+    }
+
+    /// One line for humans, e.g. `db.sql.gz: 12.0 MiB of 96.0 MiB (12%), 2.0 MiB/s, ETA 42s`.
+    pub fn message(&self) -> String {
+        let mut text = format!("{}: {}", self.file, format_bytes(self.bytes));
+        if let (Some(total), Some(percent)) = (self.total, self.percent()) {
+            text.push_str(&format!(" of {} ({:.0}%)", format_bytes(total), percent));
+        }
+        text.push_str(&format!(", {}/s", format_bytes(self.bytes_per_sec)));
+        if let Some(eta) = self.eta_secs {
+            text.push_str(&format!(", ETA {}s", eta));
+        }
+        text
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64;
+    let mut unit = "B";
+    for next in UNITS {
+        if value < 1024.0 {
+            break;
+        }
+        value /= 1024.0;
+        unit = next;
+    }
+    format!("{:.1} {}", value, unit)
+}
+
+/// Called with progress of a transfer, at most every [`NOTIFY_INTERVAL`].
+pub type ProgressNotify = Arc<dyn Fn(&ProgressUpdate) + Send + Sync>;
+
+/// Rate limits and progress reporting of one transfer, shared by its files.
+#[derive(Clone)]
+pub struct Pacing {
+    /// Every limiter is waited on for each chunk, e.g. a per-transfer and a
+    /// server-wide one.
+    pub limits: Vec<Arc<RateLimiter>>,
+    pub notify: Option<ProgressNotify>,
+    total: Option<u64>,
+    started: Instant,
+    /// Bytes sent by all files so far.
+    sent: Arc<AtomicU64>,
+    /// Bytes resumed files already had, counted as done but not as sent.
+    resumed: Arc<AtomicU64>,
+}
+
+impl Default for Pacing {
+    fn default() -> Self {
+        Self {
+            limits: Vec::new(),
+            notify: None,
+            total: None,
+            started: Instant::now(),
+            sent: Arc::new(AtomicU64::new(0)),
+            resumed: Arc::new(AtomicU64::new(0)),
+        }
+    }
+}
+
+impl std::fmt::Debug for Pacing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pacing")
+            .field("limits", &self.limits)
+            .field("notify", &self.notify.is_some())
+            .field("total", &self.total)
+            .finish()
+    }
+}
+
+impl Pacing {
+    pub fn limit(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.limits.push(limiter);
+        self
+    }
+
+    /// Same pacing for a transfer of a known size.
+    pub fn with_total(&self, total: u64) -> Self {
+        Self {
+            total: Some(total),
+            ..self.clone()
+        }
+    }
+
+    fn update(&self, file: &str) -> ProgressUpdate {
+        let sent = self.sent.load(Ordering::Relaxed);
+        let bytes = sent + self.resumed.load(Ordering::Relaxed);
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let bytes_per_sec = if elapsed > 0.0 {
+            (sent as f64 / elapsed) as u64
+        } else {
+            0
+        };
+        let eta_secs = self
+            .total
+            .filter(|_| bytes_per_sec > 0)
+            .map(|total| total.saturating_sub(bytes) / bytes_per_sec);
+        ProgressUpdate {
+            file: file.to_string(),
+            bytes,
+            total: self.total,
+            bytes_per_sec,
+            eta_secs,
+        }
+    }
+}
+
+/// Bytes moved so far by one file.
+#[derive(Debug)]
+pub struct Progress {
+    label: String,
+    total: Option<u64>,
+    done: u64,
+    logged: u64,
+    started: Instant,
+    pacing: Pacing,
+    notified: Option<Instant>,
+}
+
+impl Progress {
+    pub fn new(label: impl Into<String>, total: Option<u64>, pacing: &Pacing) -> Self {
+        Self {
+            label: label.into(),
+            total,
+            done: 0,
+            logged: 0,
+            started: Instant::now(),
+            pacing: pacing.clone(),
+            notified: None,
+        }
+    }
+
+    /// Counts the `bytes` a resumed file already had as done.
+    fn resumed(&mut self, bytes: u64) {
+        self.pacing.resumed.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    async fn advance(&mut self, bytes: u64) {
+        for limiter in &self.pacing.limits {
+            limiter.acquire(bytes).await;
+        }
+        self.done += bytes;
+        self.pacing.sent.fetch_add(bytes, Ordering::Relaxed);
+        if self.done - self.logged >= PROGRESS_LOG_BYTES {
+            self.logged = self.done;
+            tracing::debug!(
+                transfer = %self.label,
+                bytes = self.done,
+                total = ?self.total,
+                "Transfer progress"
+            );
+        }
+        if let Some(notify) = &self.pacing.notify
+            && (self
+                .notified
+                .is_none_or(|at| at.elapsed() >= NOTIFY_INTERVAL)
+                || Some(self.done) == self.total)
+        {
+            self.notified = Some(Instant::now());
+            notify(&self.pacing.update(&self.label));
+        }
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.done
+    }
+
+    pub fn elapsed_ms(&self) -> u64 {
+        self.started.elapsed().as_millis() as u64
+    }
+}
+
+/// Copies `reader` to `writer` until EOF and flushes it; returns the bytes
+/// copied.
+pub async fn copy_stream<R, W>(
+    reader: &mut R,
+    writer: &mut W,
+    progress: &mut Progress,
+) -> std::io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut buffer = vec![0u8; COPY_CHUNK];
+    let mut copied = 0u64;
+    loop {
+        let n = reader.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..n]).await?;
+        copied += n as u64;
+        progress.advance(n as u64).await;
+    }
+    writer.flush().await?;
+    Ok(copied)
+}
+
+/// Reads and drops up to `bytes` from `reader`, for streams that can't seek;
+/// returns how many were skipped, fewer at EOF.
+pub async fn skip_stream<R>(reader: &mut R, bytes: u64) -> std::io::Result<u64>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut buffer = vec![0u8; COPY_CHUNK];
+    let mut skipped = 0u64;
+    while skipped < bytes {
+        let want = (bytes - skipped).min(buffer.len() as u64) as usize;
+        let n = reader.read(&mut buffer[..want]).await?;
+        if n == 0 {
+            break;
+        }
+        skipped += n as u64;
+    }
+    Ok(skipped)
+}
+
+/// SHA-256 of a local file, hex-encoded like `sha256sum` prints it.
+pub async fn local_sha256(path: &Path) -> Result<String> {
+    let path = path.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        use sha2::{Digest, Sha256};
+        let mut file = std::fs::File::open(&path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher)?;
+        Ok(format!("{:x}", hasher.finalize()))
+    })
+    .await?
+}
+
+/// `name` inside `dir`, for copies whose target is an existing directory.
+pub fn join_remote(dir: &str, name: &str) -> String {
+    format!("{}/{}", dir.trim_end_matches('/'), name)
+}
+
+/// Last component of a remote path.
+pub fn remote_file_name(path: &str) -> &str {
+    path.trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or(path)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CopyReport {
+    pub source: String,
+    /// Final path; a directory target gets the source's file name appended.
+    pub destination: String,
+    pub bytes: u64,
+    pub duration_ms: u64,
+    /// SHA-256 both ends agreed on; `None` when not verified.
+    pub sha256: Option<String>,
+}
+
+/// Per-file entries kept in a [`TransferSummary`]; the counts cover the rest.
+pub const MAX_REPORTED_FILES: usize = 500;
+
+#[derive(Debug, Clone, Default)]
+pub struct TransferOptions {
+    /// Continue files whose target is a shorter prefix of them instead of
+    /// starting over.
+    pub resume: bool,
+    pub pacing: Pacing,
+}
+
+/// Include/exclude globs for directory transfers, matched against the path
+/// relative to the transfer root and against the bare file name, so `*.log`
+/// and `logs/**` both work. Excluded directories are not descended into;
+/// includes only select files.
+#[derive(Debug, Default)]
+pub struct PathFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl PathFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self> {
+        let compile = |patterns: &[String]| -> Result<Vec<Pattern>> {
+            patterns
+                .iter()
+                .map(|p| {
+                    Pattern::new(p).map_err(|e| {
+                        SshLiaisonError::Config(format!("Invalid glob '{}': {}", p, e)).into()
+                    })
+                })
+                .collect()
+        };
+        Ok(Self {
+            include: compile(include)?,
+            exclude: compile(exclude)?,
+        })
+    }
+
+    fn matches(patterns: &[Pattern], relative: &str) -> bool {
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        patterns
+            .iter()
+            .any(|p| p.matches(relative) || p.matches(name))
+    }
+
+    pub fn excludes(&self, relative: &str) -> bool {
+        Self::matches(&self.exclude, relative)
+    }
+
+    pub fn selects_file(&self, relative: &str) -> bool {
+        !self.excludes(relative)
+            && (self.include.is_empty() || Self::matches(&self.include, relative))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileOutcome {
+    Copied,
+    Failed,
+    /// Copied, but the SHA-256 of the copy differs from the source's.
+    Mismatch,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FileTransfer {
+    /// Relative to the transfer root.
+    pub path: String,
+    pub outcome: FileOutcome,
+    /// Bytes sent in this run; a resumed file's existing prefix isn't counted.
+    pub bytes: u64,
+    /// Offset a resumed file continued from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resumed_from: Option<u64>,
+    /// SHA-256 both ends agreed on, when verified.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Bytes written for one file.
+#[derive(Debug)]
+struct Copied {
+    bytes: u64,
+    resumed_from: Option<u64>,
+}
+
+/// Both ends of a copied file, for checksumming afterwards.
+#[derive(Debug, Clone)]
+pub struct CopiedFile {
+    pub relative: String,
+    pub remote: String,
+    pub local: PathBuf,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct TransferSummary {
+    pub files_copied: usize,
+    /// Filtered out, or neither a regular file nor a directory (e.g. symlinks).
+    pub files_skipped: usize,
+    pub files_failed: usize,
+    pub bytes: u64,
+    pub duration_ms: u64,
+    /// Copied and failed files, the first [`MAX_REPORTED_FILES`] of them.
+    pub files: Vec<FileTransfer>,
+    /// Every copied file, reported or not.
+    #[serde(skip)]
+    pub copied: Vec<CopiedFile>,
+    /// The source was a single file rather than a directory.
+    #[serde(skip)]
+    pub single_file: bool,
+}
+
+impl TransferSummary {
+    fn record(&mut self, file: CopiedFile, result: Result<Copied>) {
+        let entry = match result {
+            Ok(copied) => {
+                self.files_copied += 1;
+                self.bytes += copied.bytes;
+                tracing::info!(
+                    file = %file.relative,
+                    bytes = copied.bytes,
+                    resumed_from = ?copied.resumed_from,
+                    "Transferred file"
+                );
+                FileTransfer {
+                    path: file.relative.clone(),
+                    outcome: FileOutcome::Copied,
+                    bytes: copied.bytes,
+                    resumed_from: copied.resumed_from,
+                    sha256: None,
+                    error: None,
+                }
+            }
+            Err(e) => {
+                self.files_failed += 1;
+                tracing::warn!(file = %file.relative, error = %e, "File transfer failed");
+                FileTransfer {
+                    path: file.relative.clone(),
+                    outcome: FileOutcome::Failed,
+                    bytes: 0,
+                    resumed_from: None,
+                    sha256: None,
+                    error: Some(format!("{:#}", e)),
+                }
+            }
+        };
+        if entry.outcome == FileOutcome::Copied {
+            self.copied.push(file);
+        }
+        if self.files.len() < MAX_REPORTED_FILES {
+            self.files.push(entry);
+        }
+    }
+
+    /// Records the checksums of a copied file's source and copy; a mismatch
+    /// turns it into a failure.
+    pub fn verified(&mut self, relative: &str, source: Result<String>, copy: Result<String>) {
+        let entry = self.files.iter_mut().find(|f| f.path == relative);
+        let error = match (source, copy) {
+            (Ok(expected), Ok(actual)) if expected == actual => {
+                if let Some(entry) = entry {
+                    entry.sha256 = Some(expected);
+                }
+                return;
+            }
+            (Ok(expected), Ok(actual)) => {
+                tracing::warn!(file = %relative, %expected, %actual, "Checksum mismatch");
+                if let Some(entry) = entry {
+                    entry.outcome = FileOutcome::Mismatch;
+                    entry.error = Some(
+                        SshLiaisonError::ChecksumMismatch {
+                            path: relative.to_string(),
+                            expected,
+                            actual,
+                        }
+                        .to_string(),
+                    );
+                }
+                self.files_copied -= 1;
+                self.files_failed += 1;
+                return;
+            }
+            (Err(e), _) | (_, Err(e)) => e,
+        };
+        // Copied, but not confirmed: keep it as copied and say why.
+        if let Some(entry) = entry {
+            entry.error = Some(format!("Not verified: {:#}", error));
+        }
+    }
+}
+
+fn relative_join(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
+
+fn unix_time(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs())
+}
+
+#[cfg(unix)]
+fn local_mode(meta: &std::fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn local_mode(meta: &std::fs::Metadata) -> u32 {
+    if meta.is_dir() { 0o755 } else { NEW_FILE_MODE }
+}
+
+/// Mode and times of a local file, for `setstat` on the remote copy.
+fn remote_attributes(meta: &std::fs::Metadata) -> FileAttrs {
+    FileAttrs {
+        size: None,
+        uid: None,
+        gid: None,
+        perm: Some(local_mode(meta)),
+        atime: meta.accessed().ok().and_then(unix_time),
+        mtime: meta.modified().ok().and_then(unix_time),
+    }
+}
+
+/// Applies a remote file's mode and times to its local copy.
+fn apply_local_attributes(path: &Path, stat: &FileAttrs) -> std::io::Result<()> {
+    #[cfg(unix)]
+    if let Some(perm) = stat.perm {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(perm & 0o7777))?;
+    }
+    if let Some(mtime) = stat.mtime {
+        let mtime = UNIX_EPOCH + Duration::from_secs(mtime);
+        let atime = stat
+            .atime
+            .map_or(mtime, |atime| UNIX_EPOCH + Duration::from_secs(atime));
+        let times = std::fs::FileTimes::new()
+            .set_accessed(atime)
+            .set_modified(mtime);
+        std::fs::File::options()
+            .write(true)
+            .open(path)?
+            .set_times(times)?;
+    }
+    Ok(())
+}
+
+/// Copies a local file or directory tree to `remote`, keeping modes and
+/// mtimes. A directory's contents go into `remote`, which is created if
+/// missing; a file sent to an existing remote directory keeps its name.
+/// Files are written in place. A single file that fails is an error; in a
+/// directory, failed files are reported and the others still copied.
+pub async fn upload(
+    sftp: &dyn Sftp,
+    local: &Path,
+    remote: &str,
+    filter: &PathFilter,
+    options: &TransferOptions,
+) -> Result<TransferSummary> {
+    let started = Instant::now();
+    let mut summary = TransferSummary::default();
+    let meta = tokio::fs::metadata(local)
+        .await
+        .with_context(|| format!("Failed to stat {}", local.display()))?;
+
+    if !meta.is_dir() {
+        let name = local
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let target = match sftp.stat(remote).await {
+            Ok(stat) if stat.is_dir() => join_remote(remote, &name),
+            _ => remote.to_string(),
+        };
+        let pacing = options.pacing.with_total(meta.len());
+        let copied = upload_file(sftp, local, &target, &meta, options.resume, &pacing).await?;
+        let file = CopiedFile {
+            relative: name,
+            remote: target,
+            local: local.to_path_buf(),
+        };
+        summary.record(file, Ok(copied));
+        summary.single_file = true;
+        summary.duration_ms = started.elapsed().as_millis() as u64;
+        return Ok(summary);
+    }
+
+    ensure_remote_dir(sftp, remote, local_mode(&meta)).await?;
+    // Directory times are set last, after their contents stopped changing.
+    let mut directories = vec![(remote.to_string(), meta)];
+    let mut pending = vec![(local.to_path_buf(), remote.to_string(), String::new())];
+    while let Some((local_dir, remote_dir, relative)) = pending.pop() {
+        let mut entries = Vec::new();
+        let mut reader = tokio::fs::read_dir(&local_dir)
+            .await
+            .with_context(|| format!("Failed to list {}", local_dir.display()))?;
+        while let Some(entry) = reader.next_entry().await? {
+            entries.push(entry);
+        }
+        entries.sort_by_key(|e| e.file_name());
+
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let file = CopiedFile {
+                relative: relative_join(&relative, &name),
+                remote: join_remote(&remote_dir, &name),
+                local: entry.path(),
+            };
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                if filter.excludes(&file.relative) {
+                    continue;
+                }
+                let meta = entry.metadata().await?;
+                if let Err(e) = ensure_remote_dir(sftp, &file.remote, local_mode(&meta)).await {
+                    summary.record(file, Err(e));
+                    continue;
+                }
+                directories.push((file.remote.clone(), meta));
+                pending.push((file.local, file.remote, file.relative));
+            } else if file_type.is_file() && filter.selects_file(&file.relative) {
+                let result = match entry.metadata().await {
+                    Ok(meta) => {
+                        let (resume, pacing) = (options.resume, &options.pacing);
+                        upload_file(sftp, &file.local, &file.remote, &meta, resume, pacing).await
+                    }
+                    Err(e) => Err(e.into()),
+                };
+                summary.record(file, result);
+            } else {
+                summary.files_skipped += 1;
+            }
+        }
+    }
+    for (dir, meta) in directories.iter().rev() {
+        let _ = sftp.setstat(dir, remote_attributes(meta)).await;
+    }
+
+    summary.duration_ms = started.elapsed().as_millis() as u64;
+    Ok(summary)
+}
+
+async fn ensure_remote_dir(sftp: &dyn Sftp, path: &str, mode: u32) -> Result<()> {
+    match sftp.stat(path).await {
+        Ok(stat) if stat.is_dir() => Ok(()),
+        Ok(_) => anyhow::bail!("{} exists and is not a directory", path),
+        Err(_) => sftp
+            .mkdir(path, mode)
+            .await
+            .with_context(|| format!("Failed to create directory {}", path)),
+    }
+}
+
+async fn upload_file(
+    sftp: &dyn Sftp,
+    local: &Path,
+    remote: &str,
+    meta: &std::fs::Metadata,
+    resume: bool,
+    pacing: &Pacing,
+) -> Result<Copied> {
+    let attributes = remote_attributes(meta);
+    // A remote file no longer than the local one is taken as a partial copy.
+    let offset = match sftp.stat(remote).await {
+        Ok(stat) if resume && stat.is_file() => stat.size.filter(|&size| size <= meta.len()),
+        _ => None,
+    };
+    let mut reader = tokio::fs::File::open(local)
+        .await
+        .with_context(|| format!("Failed to open {}", local.display()))?;
+    let mode = match offset {
+        Some(offset) => {
+            reader.seek(SeekFrom::Start(offset)).await?;
+            OpenMode::Append
+        }
+        None => OpenMode::Truncate,
+    };
+    let mut writer = sftp
+        .open(remote, mode, attributes.perm.unwrap_or(NEW_FILE_MODE))
+        .await
+        .with_context(|| format!("Failed to create {}", remote))?;
+    let mut progress = Progress::new(
+        remote_file_name(remote),
+        Some(meta.len() - offset.unwrap_or(0)),
+        pacing,
+    );
+    progress.resumed(offset.unwrap_or(0));
+    copy_stream(&mut reader, &mut writer, &mut progress).await?;
+    writer.shutdown().await?;
+    drop(writer);
+    sftp.setstat(remote, attributes)
+        .await
+        .with_context(|| format!("Failed to set mode and times of {}", remote))?;
+    Ok(Copied {
+        bytes: progress.bytes(),
+        resumed_from: offset.filter(|&offset| offset > 0),
+    })
+}
+
+/// Copies a remote file or directory tree to `local`, keeping modes and
+/// mtimes; the mirror image of [`upload`].
+pub async fn download(
+    sftp: &dyn Sftp,
+    remote: &str,
+    local: &Path,
+    filter: &PathFilter,
+    options: &TransferOptions,
+) -> Result<TransferSummary> {
+    let started = Instant::now();
+    let mut summary = TransferSummary::default();
+    let stat = sftp
+        .stat(remote)
+        .await
+        .with_context(|| format!("Failed to stat {}", remote))?;
+
+    if !stat.is_dir() {
+        let name = remote_file_name(remote).to_string();
+        let target = if local.is_dir() {
+            local.join(&name)
+        } else {
+            local.to_path_buf()
+        };
+        let pacing = match stat.size {
+            Some(size) => options.pacing.with_total(size),
+            None => options.pacing.clone(),
+        };
+        let copied = download_file(sftp, remote, &target, &stat, options.resume, &pacing).await?;
+        let file = CopiedFile {
+            relative: name,
+            remote: remote.to_string(),
+            local: target,
+        };
+        summary.record(file, Ok(copied));
+        summary.single_file = true;
+        summary.duration_ms = started.elapsed().as_millis() as u64;
+        return Ok(summary);
+    }
+
+    tokio::fs::create_dir_all(local)
+        .await
+        .with_context(|| format!("Failed to create {}", local.display()))?;
+    let mut directories = vec![(local.to_path_buf(), stat)];
+    let mut pending = vec![(remote.to_string(), local.to_path_buf(), String::new())];
+    while let Some((remote_dir, local_dir, relative)) = pending.pop() {
+        let mut entries = sftp
+            .readdir(&remote_dir)
+            .await
+            .with_context(|| format!("Failed to list {}", remote_dir))?;
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (name, stat) in entries {
+            if name == "." || name == ".." {
+                continue;
+            }
+            let file = CopiedFile {
+                relative: relative_join(&relative, &name),
+                remote: join_remote(&remote_dir, &name),
+                local: local_dir.join(&name),
+            };
+            match stat.perm.map(entry_kind) {
+                Some(EntryKind::Directory) => {
+                    if filter.excludes(&file.relative) {
+                        continue;
+                    }
+                    if let Err(e) = tokio::fs::create_dir_all(&file.local).await {
+                        summary.record(file, Err(e.into()));
+                        continue;
+                    }
+                    directories.push((file.local.clone(), stat));
+                    pending.push((file.remote, file.local, file.relative));
+                }
+                Some(EntryKind::File) if filter.selects_file(&file.relative) => {
+                    let (resume, pacing) = (options.resume, &options.pacing);
+                    let result =
+                        download_file(sftp, &file.remote, &file.local, &stat, resume, pacing).await;
+                    summary.record(file, result);
+                }
+                _ => summary.files_skipped += 1,
+            }
+        }
+    }
+    for (dir, stat) in directories.iter().rev() {
+        let _ = apply_local_attributes(dir, stat);
+    }
+
+    summary.duration_ms = started.elapsed().as_millis() as u64;
+    Ok(summary)
+}
+
+async fn download_file(
+    sftp: &dyn Sftp,
+    remote: &str,
+    local: &Path,
+    stat: &FileAttrs,
+    resume: bool,
+    pacing: &Pacing,
+) -> Result<Copied> {
+    let offset = match tokio::fs::metadata(local).await {
+        Ok(meta) if resume && meta.is_file() => {
+            Some(meta.len()).filter(|&len| stat.size.is_some_and(|size| len <= size))
+        }
+        _ => None,
+    };
+    let mut reader = sftp
+        .open(remote, OpenMode::Read, 0)
+        .await
+        .with_context(|| format!("Failed to open {}", remote))?;
+    // SFTP files don't seek here: the prefix is read again but not rewritten.
+    let mut writer = match offset {
+        Some(offset) => {
+            if skip_stream(&mut reader, offset).await? < offset {
+                anyhow::bail!("{} shrank while resuming", remote);
+            }
+            tokio::fs::OpenOptions::new().append(true).open(local).await
+        }
+        None => tokio::fs::File::create(local).await,
+    }
+    .with_context(|| format!("Failed to create {}", local.display()))?;
+    let mut progress = Progress::new(
+        remote_file_name(remote),
+        stat.size.map(|size| size - offset.unwrap_or(0)),
+        pacing,
+    );
+    progress.resumed(offset.unwrap_or(0));
+    copy_stream(&mut reader, &mut writer, &mut progress).await?;
+    drop(writer);
+    apply_local_attributes(local, stat)
+        .with_context(|| format!("Failed to set mode and times of {}", local.display()))?;
+    Ok(Copied {
+        bytes: progress.bytes(),
+        resumed_from: offset.filter(|&offset| offset > 0),
+    })
+}
+
+/// Local paths must be absolute: the server's working directory is not
+/// something the client knows.
+pub fn local_path(path: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(path);
+    if !path.is_absolute() {
+        return Err(SshLiaisonError::Config(format!(
+            "Local path must be absolute: {}",
+            path.display()
+        ))
+        .into());
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_copy_stream() {
+        let data: Vec<u8> = (0..COPY_CHUNK * 2 + 17).map(|i| i as u8).collect();
+        let mut reader = data.as_slice();
+        let mut writer = Vec::new();
+        let mut progress = Progress::new("test", Some(data.len() as u64), &Pacing::default());
+        let copied = copy_stream(&mut reader, &mut writer, &mut progress)
+            .await
+            .unwrap();
+        assert_eq!(copied, data.len() as u64);
+        assert_eq!(progress.bytes(), copied);
+        assert_eq!(writer, data);
+
+        let mut rest = &data[..];
+        assert_eq!(skip_stream(&mut rest, 100).await.unwrap(), 100);
+        assert_eq!(rest.len(), data.len() - 100);
+        assert_eq!(
+            skip_stream(&mut rest, u64::MAX).await.unwrap(),
+            data.len() as u64 - 100
+        );
+
+        assert_eq!(join_remote("/srv/", "app.tar"), "/srv/app.tar");
+        assert_eq!(remote_file_name("/var/backups/db.sql.gz"), "db.sql.gz");
+    }
+
+    #[test]
+    fn test_rate_limiter_bucket() {
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 1000.0,
+            updated: start,
+        };
+        // The first second's worth goes out at once, then callers queue up.
+        assert_eq!(bucket.take(1000.0, 1000, start), Duration::ZERO);
+        assert_eq!(bucket.take(1000.0, 500, start), Duration::from_millis(500));
+        assert_eq!(bucket.take(1000.0, 500, start), Duration::from_secs(1));
+        // Idle time refills it, but never beyond one second.
+        let later = start + Duration::from_secs(10);
+        assert_eq!(bucket.take(1000.0, 1000, later), Duration::ZERO);
+        assert!(RateLimiter::new(0).is_err());
+    }
+
+    #[test]
+    fn test_progress_update_message() {
+        let update = ProgressUpdate {
+            file: "db.sql.gz".to_string(),
+            bytes: 24 * 1024 * 1024,
+            total: Some(96 * 1024 * 1024),
+            bytes_per_sec: 2 * 1024 * 1024,
+            eta_secs: Some(42),
+        };
+        assert_eq!(update.percent(), Some(25.0));
+        assert_eq!(
+            update.message(),
+            "db.sql.gz: 24.0 MiB of 96.0 MiB (25%), 2.0 MiB/s, ETA 42s"
+        );
+        let unknown = ProgressUpdate {
+            total: None,
+            eta_secs: None,
+            bytes: 100,
+            ..update
+        };
+        assert_eq!(unknown.message(), "db.sql.gz: 100 B, 2.0 MiB/s");
+    }
+
+    #[test]
+    fn test_path_filter() {
+        let filter = PathFilter::new(
+            &["*.conf".to_string(), "sites/**".to_string()],
+            &["*.bak".to_string(), ".git".to_string()],
+        )
+        .unwrap();
+        assert!(filter.selects_file("nginx.conf"));
+        assert!(filter.selects_file("conf.d/default.conf"));
+        assert!(filter.selects_file("sites/app/index.html"));
+        assert!(!filter.selects_file("mime.types"));
+        assert!(!filter.selects_file("old.conf.bak"));
+        assert!(filter.excludes(".git"));
+        assert!(filter.excludes("vendor/.git"));
+        assert!(!filter.excludes("conf.d"));
+
+        let all = PathFilter::default();
+        assert!(all.selects_file("any/file"));
+        assert!(PathFilter::new(&["[".to_string()], &[]).is_err());
+    }
+
+    #[test]
+    fn test_verified() {
+        let mut summary = TransferSummary::default();
+        for name in ["a", "b", "c"] {
+            let file = CopiedFile {
+                relative: name.to_string(),
+                remote: format!("/srv/{}", name),
+                local: PathBuf::from(format!("/tmp/{}", name)),
+            };
+            let copied = Copied {
+                bytes: 10,
+                resumed_from: None,
+            };
+            summary.record(file, Ok(copied));
+        }
+        assert_eq!(summary.copied.len(), 3);
+
+        summary.verified("a", Ok("aa".to_string()), Ok("aa".to_string()));
+        summary.verified("b", Ok("bb".to_string()), Ok("b0".to_string()));
+        summary.verified(
+            "c",
+            Ok("cc".to_string()),
+            Err(anyhow::anyhow!("no sha256sum")),
+        );
+        assert_eq!(summary.files_copied, 2);
+        assert_eq!(summary.files_failed, 1);
+        assert_eq!(summary.files[0].sha256.as_deref(), Some("aa"));
+        assert_eq!(summary.files[1].outcome, FileOutcome::Mismatch);
+        assert_eq!(summary.files[2].outcome, FileOutcome::Copied);
+        assert!(
+            summary.files[2]
+                .error
+                .as_ref()
+                .unwrap()
+                .contains("no sha256sum")
+        );
+    }
+}
@@ -0,0 +1,383 @@
+//! How the byte stream under an SSH session is obtained: a TCP connection, or
+//! a proxy command (OpenSSH `ProxyCommand`, AWS SSM, EC2 Instance Connect,
+//! Teleport) whose stdin/stdout carry the SSH protocol.
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::process::{Child, Command};
+
+use super::backend::{self, Backend, SshSession};
+use super::config::SshHostConfig;
+use super::known_hosts::HostKeyChecking;
+use super::shell::quote;
+use crate::error::SshLiaisonError;
+
+/// How long the loopback end of a proxy command bridge waits for its peer.
+const BRIDGE_ACCEPT_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransportKind {
+    /// TCP to `HostName:Port`, ignoring any `ProxyCommand`.
+    Direct,
+    /// AWS Systems Manager Session Manager (`aws ssm start-session`).
+    Ssm,
+    /// EC2 Instance Connect Endpoint (`aws ec2-instance-connect open-tunnel`).
+    InstanceConnect,
+    /// Teleport node, through `tsh proxy ssh` with the current `tsh login`.
+    Teleport,
+}
+
+/// AWS instance reached through SSM or EC2 Instance Connect.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct AwsTarget {
+    /// Instance ID (default: the host's `HostName`, e.g. `i-0abc…`).
+    pub instance_id: Option<String>,
+    pub region: Option<String>,
+    pub profile: Option<String>,
+}
+
+impl AwsTarget {
+    fn instance_id<'a>(&'a self, config: &'a SshHostConfig) -> &'a str {
+        self.instance_id
+            .as_deref()
+            .or(config.hostname.as_deref())
+            .unwrap_or(&config.host)
+    }
+
+    fn aws_args(&self) -> String {
+        let mut args = String::new();
+        if let Some(ref region) = self.region {
+            args.push_str(&format!(" --region {}", quote(region)));
+        }
+        if let Some(ref profile) = self.profile {
+            args.push_str(&format!(" --profile {}", quote(profile)));
+        }
+        args
+    }
+}
+
+/// Teleport proxy and cluster; unset ones come from the current `tsh` profile.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct TeleportTarget {
+    #[serde(rename = "teleport_proxy")]
+    pub proxy: Option<String>,
+    #[serde(rename = "teleport_cluster")]
+    pub cluster: Option<String>,
+}
+
+/// Transport configured for a host alias in the server config.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transport {
+    Direct,
+    Ssm(AwsTarget),
+    InstanceConnect(AwsTarget),
+    Teleport(TeleportTarget),
+}
+
+impl Transport {
+    pub fn new(kind: TransportKind, aws: AwsTarget, teleport: TeleportTarget) -> Self {
+        match kind {
+            TransportKind::Direct => Self::Direct,
+            TransportKind::Ssm => Self::Ssm(aws),
+            TransportKind::InstanceConnect => Self::InstanceConnect(aws),
+            TransportKind::Teleport => Self::Teleport(teleport),
+        }
+    }
+
+    /// The command carrying the SSH stream to `config`'s host, if any. With
+    /// no transport configured this is the host's `ProxyCommand`.
+    pub fn proxy_command(transport: Option<&Self>, config: &SshHostConfig) -> Option<String> {
+        let port = config.port.unwrap_or(22);
+        match transport {
+            None => config.proxy_command.clone(),
+            Some(Self::Direct) => None,
+            Some(Self::Ssm(aws)) => Some(format!(
+                "aws ssm start-session --target {} --document-name AWS-StartSSHSession --parameters portNumber={}{}",
+                quote(aws.instance_id(config)),
+                port,
+                aws.aws_args()
+            )),
+            Some(Self::InstanceConnect(aws)) => Some(format!(
+                "aws ec2-instance-connect open-tunnel --instance-id {} --remote-port {}{}",
+                quote(aws.instance_id(config)),
+                port,
+                aws.aws_args()
+            )),
+            Some(Self::Teleport(teleport)) => {
+                let mut command = String::from("tsh proxy ssh");
+                if let Some(ref proxy) = teleport.proxy {
+                    command.push_str(&format!(" --proxy={}", quote(proxy)));
+                }
+                if let Some(ref cluster) = teleport.cluster {
+                    command.push_str(&format!(" --cluster={}", quote(cluster)));
+                }
+                // Teleport addresses nodes by name; the alias stands in for a missing HostName.
+                let node = config.hostname.as_deref().unwrap_or(&config.host);
+                let target = match config.user {
+                    Some(ref user) => format!("{}@{}:{}", user, node, port),
+                    None => format!("{}:{}", node, port),
+                };
+                command.push(' ');
+                command.push_str(&quote(&target));
+                Some(command)
+            }
+        }
+    }
+
+    /// What to check when connecting over this transport fails.
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            Self::Direct => None,
+            Self::Ssm(_) => Some(
+                "check the AWS credentials, the SSM agent on the instance, and the Session Manager plugin",
+            ),
+            Self::InstanceConnect(_) => {
+                Some("check the AWS credentials and the instance's EC2 Instance Connect Endpoint")
+            }
+            Self::Teleport(_) => {
+                Some("check that `tsh status` shows a current login, or run `tsh login`")
+            }
+        }
+    }
+
+    /// EC2 Instance Connect only accepts keys pushed to the instance shortly
+    /// before logging in; this pushes `public_key` for `user`.
+    pub async fn prepare(
+        &self,
+        config: &SshHostConfig,
+        user: &str,
+        public_key: &std::path::Path,
+    ) -> Result<()> {
+        let Self::InstanceConnect(aws) = self else {
+            return Ok(());
+        };
+        let mut command = Command::new("aws");
+        command
+            .args(["ec2-instance-connect", "send-ssh-public-key"])
+            .args(["--instance-id", aws.instance_id(config)])
+            .args(["--instance-os-user", user])
+            .arg("--ssh-public-key")
+            .arg(format!("file://{}", public_key.display()));
+        if let Some(ref region) = aws.region {
+            command.args(["--region", region]);
+        }
+        if let Some(ref profile) = aws.profile {
+            command.args(["--profile", profile]);
+        }
+        let output = command
+            .kill_on_drop(true)
+            .output()
+            .await
+            .context("Failed to run the aws CLI; is it installed and on PATH?")?;
+        if !output.status.success() {
+            anyhow::bail!(
+                "aws ec2-instance-connect send-ssh-public-key failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        tracing::debug!(user = %user, "Pushed public key through EC2 Instance Connect");
+        Ok(())
+    }
+}
+
+/// Resolves, connects (directly or through `proxy_command`), and completes
+/// the SSH handshake with `backend`, checking the host key as `checking` says.
+pub async fn open_session(
+    host: &str,
+    port: u16,
+    proxy_command: Option<&str>,
+    backend: Backend,
+    checking: HostKeyChecking,
+) -> Result<Box<dyn SshSession>> {
+    let connect_error = |reason: String| SshLiaisonError::Connect {
+        host: format!("{}:{}", host, port),
+        reason,
+    };
+
+    let stream = match proxy_command {
+        Some(command) => proxy_stream(command)
+            .await
+            .map_err(|e| connect_error(format!("ProxyCommand failed: {:#}", e)))?,
+        None => {
+            let addr = tokio::net::lookup_host(format!("{}:{}", host, port))
+                .await
+                .map_err(|e| connect_error(format!("failed to resolve hostname: {}", e)))?
+                .next()
+                .ok_or_else(|| connect_error("no address found".to_string()))?;
+            TcpStream::connect(addr)
+                .await
+                .map_err(|e| connect_error(e.to_string()))?
+        }
+    };
+
+    backend::handshake(backend, stream, host, port, checking)
+        .await
+        .map_err(|e| {
+            let hint = if proxy_command.is_some() {
+                " (check the proxy command's output with -vv)"
+            } else {
+                ""
+            };
+            connect_error(format!("SSH handshake failed: {:#}{}", e, hint)).into()
+        })
+}
+
+/// A TCP stream whose other end is wired to `command`'s stdin and stdout.
+///
+/// Sessions here run over TCP streams, so the command is bridged
+/// through a loopback connection. The command is killed once the session
+/// closes its end.
+pub async fn proxy_stream(command: &str) -> Result<TcpStream> {
+    let mut child = spawn_shell(command)?;
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+    let client = TcpStream::connect(listener.local_addr()?).await?;
+    let expected_peer = client.local_addr()?;
+
+    // Only our own connection may become the bridge.
+    let server = tokio::time::timeout(BRIDGE_ACCEPT_TIMEOUT, async {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            if peer == expected_peer {
+                return Ok::<_, std::io::Error>(stream);
+            }
+            tracing::warn!(peer = %peer, "Unexpected connection to proxy bridge, dropped");
+        }
+    })
+    .await
+    .context("Proxy bridge connection timed out")??;
+
+    let mut stdin = child.stdin.take().context("proxy command has no stdin")?;
+    let mut stdout = child.stdout.take().context("proxy command has no stdout")?;
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                tracing::debug!(target: "ssh_liaison_mcp::ssh::proxy", "{}", line);
+            }
+        });
+    }
+
+    let command = command.to_string();
+    tokio::spawn(async move {
+        let (mut read_half, mut write_half) = server.into_split();
+        tokio::select! {
+            _ = tokio::io::copy(&mut read_half, &mut stdin) => {}
+            _ = tokio::io::copy(&mut stdout, &mut write_half) => {}
+        }
+        match child.try_wait() {
+            Ok(Some(status)) if !status.success() => {
+                tracing::warn!(command = %command, status = %status, "Proxy command exited with an error")
+            }
+            _ => tracing::debug!(command = %command, "Proxy command finished"),
+        }
+        // `child` is killed on drop if it is still running.
+    });
+
+    Ok(client)
+}
+
+fn spawn_shell(command: &str) -> Result<Child> {
+    #[cfg(unix)]
+    let mut cmd = {
+        let mut cmd = Command::new("/bin/sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    };
+    #[cfg(not(unix))]
+    let mut cmd = {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    };
+    cmd.stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .with_context(|| format!("Failed to start proxy command '{}'", command))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn host(proxy_command: Option<&str>) -> SshHostConfig {
+        SshHostConfig {
+            host: "db".to_string(),
+            hostname: Some("i-0abc123".to_string()),
+            user: Some("ec2-user".to_string()),
+            port: None,
+            identity_files: Vec::new(),
+            certificate_files: Vec::new(),
+            proxy_command: proxy_command.map(str::to_string),
+            proxy_use_fdpass: false,
+            identities_only: false,
+            server_alive_interval: None,
+            identity_agent: None,
+            strict_host_key_checking: None,
+        }
+    }
+
+    #[test]
+    fn test_proxy_commands() {
+        let config = host(Some("ssh -W %h:%p bastion"));
+        assert_eq!(
+            Transport::proxy_command(None, &config).as_deref(),
+            Some("ssh -W %h:%p bastion")
+        );
+        assert_eq!(
+            Transport::proxy_command(Some(&Transport::Direct), &config),
+            None
+        );
+
+        let ssm = Transport::Ssm(AwsTarget {
+            region: Some("eu-west-1".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(
+            Transport::proxy_command(Some(&ssm), &host(None)).as_deref(),
+            Some(
+                "aws ssm start-session --target i-0abc123 --document-name AWS-StartSSHSession --parameters portNumber=22 --region eu-west-1"
+            )
+        );
+
+        let eic = Transport::InstanceConnect(AwsTarget {
+            instance_id: Some("i-0def".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(
+            Transport::proxy_command(Some(&eic), &host(None)).as_deref(),
+            Some("aws ec2-instance-connect open-tunnel --instance-id i-0def --remote-port 22")
+        );
+    }
+
+    #[test]
+    fn test_teleport_proxy_command() {
+        let teleport = Transport::Teleport(TeleportTarget {
+            proxy: Some("teleport.corp:443".to_string()),
+            cluster: Some("prod".to_string()),
+        });
+        let mut config = host(None);
+        config.hostname = None;
+        assert_eq!(
+            Transport::proxy_command(Some(&teleport), &config).as_deref(),
+            Some("tsh proxy ssh --proxy=teleport.corp:443 --cluster=prod ec2-user@db:22")
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_proxy_stream_bridges_command_io() {
+        let mut stream = proxy_stream("cat").await.unwrap();
+        stream.write_all(b"SSH-2.0-test\r\n").await.unwrap();
+        let mut buf = [0u8; 14];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"SSH-2.0-test\r\n");
+    }
+}
@@ -0,0 +1,315 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Mutex;
+use tokio::task::{JoinHandle, JoinSet};
+
+use super::backend::SshSession;
+use super::socks::{self, SocksOptions};
+
+const TUNNEL_BIND_ADDR: &str = "127.0.0.1";
+
+#[derive(Debug, Clone)]
+pub enum TunnelTarget {
+    /// Fixed destination (`ssh -L`).
+    Remote { host: String, port: u16 },
+    /// Destination chosen per connection by the SOCKS5 client (`ssh -D`).
+    Socks { max_connections: usize },
+}
+
+impl std::fmt::Display for TunnelTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TunnelTarget::Remote { host, port } => write!(f, "{}:{}", host, port),
+            TunnelTarget::Socks { max_connections } => {
+                write!(f, "SOCKS5 (max {} connections)", max_connections)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TunnelInfo {
+    pub id: String,
+    /// Client namespace of the session the tunnel runs over.
+    pub owner: Option<String>,
+    pub host: String,
+    pub local_addr: SocketAddr,
+    pub target: TunnelTarget,
+    pub active_connections: usize,
+}
+
+impl std::fmt::Display for TunnelInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} -> {} via '{}' ({} active)",
+            self.id, self.local_addr, self.target, self.host, self.active_connections
+        )
+    }
+}
+
+struct Tunnel {
+    info: TunnelInfo,
+    active: Arc<AtomicUsize>,
+    handle: JoinHandle<()>,
+}
+
+impl Tunnel {
+    fn snapshot(&self) -> TunnelInfo {
+        TunnelInfo {
+            active_connections: self.active.load(Ordering::Relaxed),
+            ..self.info.clone()
+        }
+    }
+}
+
+/// Local (`ssh -L` style) port forwards over established sessions.
+#[derive(Clone, Default)]
+pub struct TunnelManager {
+    tunnels: Arc<Mutex<HashMap<String, Tunnel>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl TunnelManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Binds `127.0.0.1:local_port` (0 picks a free port) and forwards each accepted
+    /// connection to `remote_host:remote_port` as seen from the SSH server.
+    pub async fn open(
+        &self,
+        owner: Option<&str>,
+        host: &str,
+        session: Arc<dyn SshSession>,
+        local_port: u16,
+        remote_host: &str,
+        remote_port: u16,
+    ) -> Result<TunnelInfo> {
+        let listener = bind_local(local_port).await?;
+        let local_addr = listener.local_addr()?;
+        let active = Arc::new(AtomicUsize::new(0));
+        let handle = tokio::spawn(accept_loop(
+            listener,
+            session,
+            remote_host.to_string(),
+            remote_port,
+            Arc::clone(&active),
+        ));
+        let target = TunnelTarget::Remote {
+            host: remote_host.to_string(),
+            port: remote_port,
+        };
+        self.register(owner, host, local_addr, target, active, handle)
+            .await
+    }
+
+    /// Binds `127.0.0.1:local_port` as a SOCKS5 proxy whose connections are opened
+    /// from the SSH server (`ssh -D`).
+    pub async fn open_socks(
+        &self,
+        owner: Option<&str>,
+        host: &str,
+        session: Arc<dyn SshSession>,
+        local_port: u16,
+        options: SocksOptions,
+    ) -> Result<TunnelInfo> {
+        let listener = bind_local(local_port).await?;
+        let local_addr = listener.local_addr()?;
+        let active = Arc::new(AtomicUsize::new(0));
+        let target = TunnelTarget::Socks {
+            max_connections: options.max_connections,
+        };
+        let handle = tokio::spawn(socks::serve(
+            listener,
+            session,
+            options,
+            Arc::clone(&active),
+        ));
+        self.register(owner, host, local_addr, target, active, handle)
+            .await
+    }
+
+    async fn register(
+        &self,
+        owner: Option<&str>,
+        host: &str,
+        local_addr: SocketAddr,
+        target: TunnelTarget,
+        active: Arc<AtomicUsize>,
+        handle: JoinHandle<()>,
+    ) -> Result<TunnelInfo> {
+        let id = format!(
+            "tunnel-{}",
+            self.next_id.fetch_add(1, Ordering::Relaxed) + 1
+        );
+
+        tracing::info!(
+            id = %id,
+            host = %host,
+            local = %local_addr,
+            target = %target,
+            "Opening tunnel"
+        );
+
+        let info = TunnelInfo {
+            id: id.clone(),
+            owner: owner.map(str::to_string),
+            host: host.to_string(),
+            local_addr,
+            target,
+            active_connections: 0,
+        };
+
+        let mut tunnels = self.tunnels.lock().await;
+        tunnels.insert(
+            id,
+            Tunnel {
+                info: info.clone(),
+                active,
+                handle,
+            },
+        );
+
+        Ok(info)
+    }
+
+    /// Tunnels owned by `owner`.
+    pub async fn list(&self, owner: Option<&str>) -> Vec<TunnelInfo> {
+        let tunnels = self.tunnels.lock().await;
+        let mut list: Vec<TunnelInfo> = tunnels
+            .values()
+            .filter(|t| t.info.owner.as_deref() == owner)
+            .map(Tunnel::snapshot)
+            .collect();
+        list.sort_by(|a, b| a.id.cmp(&b.id));
+        list
+    }
+
+    pub async fn close(&self, owner: Option<&str>, id: &str) -> Result<TunnelInfo> {
+        let mut tunnels = self.tunnels.lock().await;
+        let owned = tunnels
+            .get(id)
+            .is_some_and(|t| t.info.owner.as_deref() == owner);
+        let Some(tunnel) = owned.then(|| tunnels.remove(id)).flatten() else {
+            anyhow::bail!("Tunnel '{}' not found", id);
+        };
+        tunnel.handle.abort();
+        tracing::info!(id = %id, "Tunnel closed");
+        Ok(tunnel.snapshot())
+    }
+
+    /// Closes every tunnel, whoever owns it.
+    pub async fn close_all(&self) {
+        let mut tunnels = self.tunnels.lock().await;
+        for (_, tunnel) in tunnels.drain() {
+            tunnel.handle.abort();
+        }
+    }
+
+    /// Closes every tunnel of `owner`.
+    pub async fn close_owner(&self, owner: Option<&str>) {
+        let mut tunnels = self.tunnels.lock().await;
+        tunnels.retain(|_, tunnel| {
+            let closing = tunnel.info.owner.as_deref() == owner;
+            if closing {
+                tunnel.handle.abort();
+            }
+            !closing
+        });
+    }
+
+    /// Whether a tunnel runs over `host`'s session.
+    pub async fn has_host(&self, owner: Option<&str>, host: &str) -> bool {
+        let tunnels = self.tunnels.lock().await;
+        tunnels
+            .values()
+            .any(|t| t.info.host == host && t.info.owner.as_deref() == owner)
+    }
+
+    /// Closes every tunnel that runs over `host`'s session.
+    pub async fn close_for_host(&self, owner: Option<&str>, host: &str) {
+        let mut tunnels = self.tunnels.lock().await;
+        tunnels.retain(|id, tunnel| {
+            if tunnel.info.host == host && tunnel.info.owner.as_deref() == owner {
+                tunnel.handle.abort();
+                tracing::debug!(id = %id, host = %host, "Tunnel closed with session");
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+async fn bind_local(local_port: u16) -> Result<TcpListener> {
+    TcpListener::bind((TUNNEL_BIND_ADDR, local_port))
+        .await
+        .with_context(|| format!("Failed to bind {}:{}", TUNNEL_BIND_ADDR, local_port))
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    session: Arc<dyn SshSession>,
+    remote_host: String,
+    remote_port: u16,
+    active: Arc<AtomicUsize>,
+) {
+    // Dropping the JoinSet (when this task is aborted) aborts all forwarded connections.
+    let mut connections = JoinSet::new();
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, peer) = match accepted {
+                    Ok(v) => v,
+                    Err(e) => {
+                        tracing::warn!(error = %e, "Tunnel accept failed");
+                        continue;
+                    }
+                };
+                tracing::debug!(peer = %peer, "Tunnel connection accepted");
+
+                let session = Arc::clone(&session);
+                let remote_host = remote_host.clone();
+                let active = Arc::clone(&active);
+                connections.spawn(async move {
+                    active.fetch_add(1, Ordering::Relaxed);
+                    if let Err(e) = forward_connection(stream, peer, &*session, &remote_host, remote_port).await {
+                        tracing::debug!(peer = %peer, error = %e, "Tunnel connection ended with error");
+                    }
+                    active.fetch_sub(1, Ordering::Relaxed);
+                });
+            }
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+        }
+    }
+}
+
+async fn forward_connection(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    session: &dyn SshSession,
+    remote_host: &str,
+    remote_port: u16,
+) -> Result<()> {
+    let mut channel = session
+        .direct_tcpip(remote_host, remote_port, peer)
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to open direct-tcpip channel to {}:{}",
+                remote_host, remote_port
+            )
+        })?;
+
+    let (sent, received) = tokio::io::copy_bidirectional(&mut stream, &mut channel).await?;
+    tracing::trace!(peer = %peer, sent, received, "Tunnel connection finished");
+
+    let _ = channel.close().await;
+    Ok(())
+}
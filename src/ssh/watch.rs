@@ -0,0 +1,278 @@
+use anyhow::Result;
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use super::backend::SshSession;
+use super::shell::quote;
+
+/// Matching lines kept per watch; older ones are dropped.
+pub const MAX_WATCH_LINES: usize = 200;
+
+/// Called with each matching line of a subscribed watch.
+pub type WatchNotify = Arc<dyn Fn(&WatchInfo) + Send + Sync>;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WatchInfo {
+    pub id: String,
+    /// Client namespace of the session the watch runs over.
+    #[serde(skip)]
+    pub owner: Option<String>,
+    pub host: String,
+    pub path: String,
+    pub pattern: String,
+    /// Lines that matched since the watch started, dropped ones included.
+    pub matched: u64,
+    /// Whether `tail` is still running; it stops when the session drops.
+    pub active: bool,
+}
+
+impl std::fmt::Display for WatchInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: /{}/ in {} on '{}' ({} matched{})",
+            self.id,
+            self.pattern,
+            self.path,
+            self.host,
+            self.matched,
+            if self.active { "" } else { ", stopped" }
+        )
+    }
+}
+
+/// What a watch has seen, shared with its reader task.
+#[derive(Default)]
+struct WatchState {
+    lines: VecDeque<String>,
+    matched: u64,
+    active: bool,
+}
+
+struct Watch {
+    info: WatchInfo,
+    state: Arc<std::sync::Mutex<WatchState>>,
+    subscribed: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl Watch {
+    fn snapshot(&self) -> (WatchInfo, Vec<String>) {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let info = WatchInfo {
+            matched: state.matched,
+            active: state.active,
+            ..self.info.clone()
+        };
+        (info, state.lines.iter().cloned().collect())
+    }
+}
+
+/// Remote `tail -F` watchers keeping the lines that match a pattern.
+#[derive(Clone, Default)]
+pub struct WatchManager {
+    watches: Arc<Mutex<HashMap<String, Watch>>>,
+    next_id: Arc<AtomicU64>,
+}
+
+/// The command a watch runs: new lines only, following rotation.
+pub fn tail_command(path: &str) -> String {
+    format!("tail -n 0 -F {}", quote(path))
+}
+
+impl WatchManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts following `path` on an exec channel of `session`; `notify` is
+    /// called for every matching line once the watch is subscribed.
+    pub async fn open(
+        &self,
+        owner: Option<&str>,
+        host: &str,
+        session: &dyn SshSession,
+        path: &str,
+        pattern: Regex,
+        notify: WatchNotify,
+    ) -> Result<WatchInfo> {
+        let channel = session.exec(&tail_command(path)).await?;
+
+        let id = format!("watch-{}", self.next_id.fetch_add(1, Ordering::Relaxed) + 1);
+        let info = WatchInfo {
+            id: id.clone(),
+            owner: owner.map(str::to_string),
+            host: host.to_string(),
+            path: path.to_string(),
+            pattern: pattern.as_str().to_string(),
+            matched: 0,
+            active: true,
+        };
+        let state = Arc::new(std::sync::Mutex::new(WatchState {
+            active: true,
+            ..Default::default()
+        }));
+        let subscribed = Arc::new(AtomicBool::new(false));
+
+        let task_state = Arc::clone(&state);
+        let task_subscribed = Arc::clone(&subscribed);
+        let task_info = info.clone();
+        let handle = tokio::spawn(async move {
+            let mut lines = BufReader::new(channel).lines();
+            loop {
+                match lines.next_line().await {
+                    Ok(Some(line)) if pattern.is_match(&line) => {
+                        let matched = {
+                            let mut state = task_state.lock().unwrap_or_else(|e| e.into_inner());
+                            if state.lines.len() >= MAX_WATCH_LINES {
+                                state.lines.pop_front();
+                            }
+                            state.lines.push_back(line);
+                            state.matched += 1;
+                            state.matched
+                        };
+                        if task_subscribed.load(Ordering::Relaxed) {
+                            notify(&WatchInfo {
+                                matched,
+                                ..task_info.clone()
+                            });
+                        }
+                    }
+                    Ok(Some(_)) => {}
+                    Ok(None) => break,
+                    Err(e) => {
+                        tracing::debug!(id = %task_info.id, error = %e, "Watch read failed");
+                        break;
+                    }
+                }
+            }
+            let exit_status = lines.into_inner().into_inner().exit_status();
+            tracing::info!(id = %task_info.id, exit_status = ?exit_status, "Log watch stopped");
+            task_state.lock().unwrap_or_else(|e| e.into_inner()).active = false;
+        });
+
+        tracing::info!(id = %id, host = %host, path = %path, "Watching log");
+        self.watches.lock().await.insert(
+            id,
+            Watch {
+                info: info.clone(),
+                state,
+                subscribed,
+                handle,
+            },
+        );
+        Ok(info)
+    }
+
+    /// Watches owned by `owner`.
+    pub async fn list(&self, owner: Option<&str>) -> Vec<WatchInfo> {
+        let watches = self.watches.lock().await;
+        let mut list: Vec<WatchInfo> = watches
+            .values()
+            .filter(|w| w.info.owner.as_deref() == owner)
+            .map(|w| w.snapshot().0)
+            .collect();
+        list.sort_by(|a, b| a.id.cmp(&b.id));
+        list
+    }
+
+    /// The watch and its kept lines, oldest first.
+    pub async fn lines(&self, owner: Option<&str>, id: &str) -> Result<(WatchInfo, Vec<String>)> {
+        let watches = self.watches.lock().await;
+        match watches.get(id) {
+            Some(watch) if watch.info.owner.as_deref() == owner => Ok(watch.snapshot()),
+            _ => anyhow::bail!("Watch '{}' not found", id),
+        }
+    }
+
+    /// Turns notifications for a watch on or off; `false` if it isn't `owner`'s.
+    pub async fn set_subscribed(&self, owner: Option<&str>, id: &str, subscribed: bool) -> bool {
+        let watches = self.watches.lock().await;
+        match watches.get(id) {
+            Some(watch) if watch.info.owner.as_deref() == owner => {
+                watch.subscribed.store(subscribed, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Stops a watch, returning what it had kept.
+    pub async fn close(&self, owner: Option<&str>, id: &str) -> Result<(WatchInfo, Vec<String>)> {
+        let mut watches = self.watches.lock().await;
+        let owned = watches
+            .get(id)
+            .is_some_and(|w| w.info.owner.as_deref() == owner);
+        let Some(watch) = owned.then(|| watches.remove(id)).flatten() else {
+            anyhow::bail!("Watch '{}' not found", id);
+        };
+        watch.handle.abort();
+        tracing::info!(id = %id, "Log watch closed");
+        let (mut info, lines) = watch.snapshot();
+        info.active = false;
+        Ok((info, lines))
+    }
+
+    /// Stops every watch, whoever owns it.
+    pub async fn close_all(&self) {
+        let mut watches = self.watches.lock().await;
+        for (_, watch) in watches.drain() {
+            watch.handle.abort();
+        }
+    }
+
+    /// Stops every watch of `owner`.
+    pub async fn close_owner(&self, owner: Option<&str>) {
+        let mut watches = self.watches.lock().await;
+        watches.retain(|_, watch| {
+            let closing = watch.info.owner.as_deref() == owner;
+            if closing {
+                watch.handle.abort();
+            }
+            !closing
+        });
+    }
+
+    /// Whether a watch runs over `host`'s session.
+    pub async fn has_host(&self, owner: Option<&str>, host: &str) -> bool {
+        let watches = self.watches.lock().await;
+        watches
+            .values()
+            .any(|w| w.info.host == host && w.info.owner.as_deref() == owner)
+    }
+
+    /// Stops every watch that runs over `host`'s session.
+    pub async fn close_for_host(&self, owner: Option<&str>, host: &str) {
+        let mut watches = self.watches.lock().await;
+        watches.retain(|_, watch| {
+            let closing = watch.info.host == host && watch.info.owner.as_deref() == owner;
+            if closing {
+                watch.handle.abort();
+            }
+            !closing
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tail_command_quotes_path() {
+        assert_eq!(
+            tail_command("/var/log/app.log"),
+            "tail -n 0 -F /var/log/app.log"
+        );
+        assert_eq!(
+            tail_command("/tmp/my app.log"),
+            "tail -n 0 -F '/tmp/my app.log'"
+        );
+    }
+}
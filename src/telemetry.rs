@@ -0,0 +1,73 @@
+//! OTLP export of the tracing spans (`tool_call`, `ssh.connect`, `ssh.auth`,
+//! `ssh.command`), built with the `otel` feature.
+
+use anyhow::{Context, Result};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::trace::{SdkTracer, SdkTracerProvider};
+use tracing::Level;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::registry::LookupSpan;
+
+/// Standard variable naming the collector when `--otlp-endpoint` isn't given.
+pub const ENDPOINT_VAR: &str = "OTEL_EXPORTER_OTLP_ENDPOINT";
+
+/// Flushes and stops the exporter when dropped, so spans of a short-lived
+/// command still reach the collector.
+pub struct OtlpGuard {
+    provider: SdkTracerProvider,
+}
+
+impl Drop for OtlpGuard {
+    fn drop(&mut self) {
+        if let Err(e) = self.provider.shutdown() {
+            eprintln!("Failed to flush OTLP spans: {}", e);
+        }
+    }
+}
+
+/// Layer exporting spans, with the filter it should get and its guard.
+pub struct OtlpLayer<S> {
+    pub layer: OpenTelemetryLayer<S, SdkTracer>,
+    /// Independent of `-v`: spans are exported even when stderr only shows warnings.
+    pub filter: Targets,
+    pub guard: OtlpGuard,
+}
+
+/// A layer exporting this crate's spans over OTLP/gRPC to `endpoint` (or
+/// [`ENDPOINT_VAR`]); `None` when neither is set.
+pub fn otlp_layer<S>(endpoint: Option<&str>) -> Result<Option<OtlpLayer<S>>>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+{
+    let endpoint = match endpoint {
+        Some(endpoint) => endpoint.to_string(),
+        None => match std::env::var(ENDPOINT_VAR) {
+            Ok(endpoint) if !endpoint.is_empty() => endpoint,
+            _ => return Ok(None),
+        },
+    };
+
+    let exporter = SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(&endpoint)
+        .build()
+        .with_context(|| format!("Failed to create OTLP exporter for {}", endpoint))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(
+            Resource::builder()
+                .with_service_name(env!("CARGO_PKG_NAME"))
+                .build(),
+        )
+        .build();
+    let tracer = provider.tracer(env!("CARGO_PKG_NAME"));
+
+    Ok(Some(OtlpLayer {
+        layer: tracing_opentelemetry::layer().with_tracer(tracer),
+        filter: Targets::new().with_target(env!("CARGO_CRATE_NAME"), Level::INFO),
+        guard: OtlpGuard { provider },
+    }))
+}
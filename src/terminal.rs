@@ -0,0 +1,157 @@
+//! Local terminal handling for the CLI's `shell` passthrough.
+
+/// Terminal size as (columns, rows), if stdout is a terminal.
+#[cfg(unix)]
+pub fn size() -> Option<(u32, u32)> {
+    // SAFETY: TIOCGWINSZ only writes into the winsize we pass.
+    let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+    let rc = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut ws) };
+    (rc == 0 && ws.ws_col > 0 && ws.ws_row > 0).then(|| (ws.ws_col.into(), ws.ws_row.into()))
+}
+
+#[cfg(not(unix))]
+pub fn size() -> Option<(u32, u32)> {
+    None
+}
+
+#[cfg(unix)]
+pub use unix::{RawTerminal, Resizes};
+
+#[cfg(unix)]
+mod unix {
+    use anyhow::{Context, Result};
+    use std::io;
+    use std::os::fd::{AsRawFd, RawFd};
+    use tokio::io::unix::AsyncFd;
+
+    struct Stdin;
+
+    impl AsRawFd for Stdin {
+        fn as_raw_fd(&self) -> RawFd {
+            libc::STDIN_FILENO
+        }
+    }
+
+    fn restore(saved: &libc::termios, saved_flags: libc::c_int) {
+        // SAFETY: puts back the state read in `RawTerminal::enable`.
+        unsafe {
+            libc::fcntl(libc::STDIN_FILENO, libc::F_SETFL, saved_flags);
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, saved);
+        }
+    }
+
+    /// Puts stdin in raw, non-blocking mode until dropped.
+    ///
+    /// Reads go through the reactor instead of `tokio::io::stdin`, whose
+    /// blocking reader thread would swallow the first keystroke typed after
+    /// the passthrough ends.
+    pub struct RawTerminal {
+        stdin: AsyncFd<Stdin>,
+        saved: libc::termios,
+        saved_flags: libc::c_int,
+    }
+
+    impl RawTerminal {
+        pub fn enable() -> Result<Self> {
+            let fd = libc::STDIN_FILENO;
+            // SAFETY: termios/fcntl calls on stdin with pointers to locals.
+            let (saved, saved_flags) = unsafe {
+                let mut saved: libc::termios = std::mem::zeroed();
+                if libc::tcgetattr(fd, &mut saved) != 0 {
+                    return Err(io::Error::last_os_error()).context("stdin is not a terminal");
+                }
+                let mut raw = saved;
+                libc::cfmakeraw(&mut raw);
+                if libc::tcsetattr(fd, libc::TCSANOW, &raw) != 0 {
+                    return Err(io::Error::last_os_error()).context("Failed to enable raw mode");
+                }
+                let saved_flags = libc::fcntl(fd, libc::F_GETFL);
+                libc::fcntl(fd, libc::F_SETFL, saved_flags | libc::O_NONBLOCK);
+                (saved, saved_flags)
+            };
+
+            match AsyncFd::new(Stdin) {
+                Ok(stdin) => Ok(Self {
+                    stdin,
+                    saved,
+                    saved_flags,
+                }),
+                Err(e) => {
+                    restore(&saved, saved_flags);
+                    Err(e).context("Failed to watch stdin")
+                }
+            }
+        }
+
+        /// Reads whatever input is available; 0 means stdin was closed.
+        pub async fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+            loop {
+                let mut guard = self.stdin.readable().await?;
+                // SAFETY: reads at most buf.len() bytes into buf.
+                let result = guard.try_io(|inner| {
+                    let n = unsafe {
+                        libc::read(inner.as_raw_fd(), buf.as_mut_ptr().cast(), buf.len())
+                    };
+                    if n < 0 {
+                        Err(io::Error::last_os_error())
+                    } else {
+                        Ok(n as usize)
+                    }
+                });
+                if let Ok(result) = result {
+                    return result;
+                }
+            }
+        }
+    }
+
+    impl Drop for RawTerminal {
+        fn drop(&mut self) {
+            restore(&self.saved, self.saved_flags);
+        }
+    }
+
+    /// SIGWINCH notifications.
+    pub struct Resizes(tokio::signal::unix::Signal);
+
+    impl Resizes {
+        pub fn new() -> Result<Self> {
+            let kind = tokio::signal::unix::SignalKind::window_change();
+            Ok(Self(tokio::signal::unix::signal(kind)?))
+        }
+
+        pub async fn recv(&mut self) {
+            self.0.recv().await;
+        }
+    }
+}
+
+/// Stand-in for platforms without termios.
+#[cfg(not(unix))]
+pub struct RawTerminal;
+
+#[cfg(not(unix))]
+impl RawTerminal {
+    pub fn enable() -> anyhow::Result<Self> {
+        anyhow::bail!("Terminal passthrough is only supported on Unix")
+    }
+
+    pub async fn read(&self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok(0)
+    }
+}
+
+#[cfg(not(unix))]
+pub struct Resizes;
+
+#[cfg(not(unix))]
+impl Resizes {
+    pub fn new() -> anyhow::Result<Self> {
+        Ok(Self)
+    }
+
+    /// Never fires; there is no resize signal to listen for.
+    pub async fn recv(&mut self) {
+        std::future::pending::<()>().await
+    }
+}